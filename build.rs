@@ -1,6 +1,8 @@
 extern crate pkg_config;
 
 fn main() {
-    pkg_config::Config::new().probe("libdvdcss").unwrap();
+    if std::env::var_os("CARGO_FEATURE_DVDCSS").is_some() && std::env::var_os("CARGO_FEATURE_DLOPEN").is_none() {
+        pkg_config::Config::new().probe("libdvdcss").unwrap();
+    }
     println!("cargo::rerun-if-changed=build.rs");
 }
@@ -0,0 +1,12 @@
+#![no_main]
+
+use dvdromcopy::udf::FileEntry;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file_entry) = FileEntry::read(data) {
+        let _ = file_entry.icb_tag.allocation_descriptor_type();
+        let _ = file_entry.icb_tag.file_type();
+        let _ = file_entry.modification_time.iso8601();
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use dvdromcopy::udf::FileIdentifierDescriptor;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file_identifier) = FileIdentifierDescriptor::read(data) {
+        let _ = file_identifier.file_identifier.to_string();
+        let _ = file_identifier.icb.extent_type();
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use dvdromcopy::udf_parser::{is_safe_file_identifier, parse_file_identifiers};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(file_identifiers) = parse_file_identifiers(data) {
+        for file_identifier in file_identifiers {
+            is_safe_file_identifier(&file_identifier.file_identifier.to_string());
+        }
+    }
+});
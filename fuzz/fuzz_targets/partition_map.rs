@@ -0,0 +1,11 @@
+#![no_main]
+
+use dvdromcopy::udf::PartitionMap;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(map) = PartitionMap::read(data) {
+        let mut bytes = vec![0u8; map.get_length() as usize];
+        map.write(&mut bytes).unwrap();
+    }
+});
@@ -0,0 +1,161 @@
+//! The permissions, times, owner and hidden flag of copied files. UDF records Unix-style
+//! permissions, times and owners in each File Entry (ECMA-167 4/14.9) and whether a file is
+//! hidden in the File Identifier Descriptor that names it (4/14.4.3); the copies get the same
+//! ones where the host has them. The directory of a copy gets the time its disc was recorded.
+
+use std::{io, path::Path, time::SystemTime};
+
+/// Taken away from the permissions of the disc, as the usual umask, so that the copies are not
+/// writable by other users even on discs whose files are
+pub const UMASK: u32 = 0o022;
+
+/// The Unix mode of a copy of a file with the permissions of its File Entry. The owner can
+/// always read and write the copy, so that it can be replaced or resumed.
+pub fn unix_mode(permissions: u32) -> u32 {
+    let mut mode = 0;
+    // ECMA-167 4/14.9.5: five bits each for others, the group and the owner, of which the low
+    // three are execute, write and read as in a Unix mode
+    for (udf_shift, unix_shift) in [(0, 0), (5, 3), (10, 6)] {
+        mode |= ((permissions >> udf_shift) & 0o7) << unix_shift;
+    }
+    (mode & !UMASK) | 0o600
+}
+
+/// Give the file at path the mode, if any, and the hidden attribute if hidden. Hosts without
+/// such a mode or attribute (Windows and Linux respectively) ignore it.
+pub fn set_attributes(path: &Path, mode: Option<u32>, hidden: bool) -> io::Result<()> {
+    if let Some(mode) = mode {
+        set_mode(path, mode)?;
+    }
+    if hidden {
+        set_hidden(path)?;
+    }
+    Ok(())
+}
+
+/// Give the file at path the modification and access times that are known
+pub fn set_times(path: &Path, modified: Option<SystemTime>, accessed: Option<SystemTime>) -> io::Result<()> {
+    let mut times = std::fs::FileTimes::new();
+    if let Some(modified) = modified {
+        times = times.set_modified(modified);
+    }
+    if let Some(accessed) = accessed {
+        times = times.set_accessed(accessed);
+    }
+    // Windows needs write access to change the times; the file is not truncated
+    std::fs::File::options().write(true).open(path)?.set_times(times)
+}
+
+/// Give the file at path the numeric owner and group that are known. Only root can give files
+/// to other users, and hosts without Unix owners ignore it.
+#[cfg(unix)]
+pub fn set_owner(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    std::os::unix::fs::chown(path, uid, gid)
+}
+
+#[cfg(not(unix))]
+pub fn set_owner(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Set the modification time of a directory, e.g. to when its disc was mastered
+pub fn set_directory_modified(dir: &Path, time: SystemTime) -> io::Result<()> {
+    let mut options = std::fs::File::options();
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        /// which opening a directory needs
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        options.access_mode(FILE_WRITE_ATTRIBUTES).custom_flags(FILE_FLAG_BACKUP_SEMANTICS);
+    }
+    options.open(dir)?.set_modified(time)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(windows)]
+fn set_hidden(path: &Path) -> io::Result<()> {
+    use std::os::windows::{ffi::OsStrExt, fs::MetadataExt};
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetFileAttributesW(file_name: *const u16, file_attributes: u32) -> i32;
+    }
+    let attributes = std::fs::metadata(path)?.file_attributes();
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    // SAFETY: wide is a NUL-terminated path that outlives the call
+    if unsafe { SetFileAttributesW(wide.as_ptr(), attributes | FILE_ATTRIBUTE_HIDDEN) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn set_hidden(path: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+    /// sys/stat.h: hidden in the Finder
+    const UF_HIDDEN: u32 = 0x8000;
+    extern "C" {
+        fn chflags(path: *const std::ffi::c_char, flags: u32) -> i32;
+    }
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: path is NUL-terminated and outlives the call
+    if unsafe { chflags(path.as_ptr(), UF_HIDDEN) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Other Unixes only hide names that start with a dot, which would be a different name
+#[cfg(not(any(windows, target_os = "macos")))]
+fn set_hidden(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_mode() {
+        // what makeiso records for files and directories: read, and execute for directories
+        assert_eq!(unix_mode(0x1084), 0o644);
+        assert_eq!(unix_mode(0x14A5), 0o755);
+        // writable by everyone, but not the copy
+        assert_eq!(unix_mode(0x7FFF), 0o755);
+        assert_eq!(unix_mode(0), 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_set_attributes() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("dvdromcopy-attributes-test-{}", std::process::id()));
+        std::fs::write(&path, b"IFO").unwrap();
+        set_attributes(&path, Some(unix_mode(0x14A5)), false).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o755);
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_050_085_500);
+        set_times(&path, Some(modified), None).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        assert_eq!(std::fs::read(&path).unwrap(), b"IFO");
+        std::fs::remove_file(&path).unwrap();
+
+        let dir = path.with_extension("d");
+        std::fs::create_dir(&dir).unwrap();
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_050_085_500);
+        set_directory_modified(&dir, time).unwrap();
+        assert_eq!(std::fs::metadata(&dir).unwrap().modified().unwrap(), time);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -1,41 +1,68 @@
-use std::{collections::BTreeMap, io::{Read, Seek}, mem, num::NonZero};
+use std::{borrow::BorrowMut, collections::BTreeMap, io::{self, Read, Seek, SeekFrom}, mem, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
 
-use log::debug;
+use log::{debug, warn};
 use lru::LruCache;
 
-use crate::{dvdcss_sys::DVDCSS_BLOCK_SIZE, udf::{LogicalVolumeDescriptor, LongAd, PartitionDescriptor}, udf_parser::Result};
+use crate::{dvdcss_sys::DVDCSS_BLOCK_SIZE, logical_block_reader::{spared_sector, translate_virtual_sector, SparablePartition, VirtualPartition}, sparse_image::SectorRanges, udf::{LogicalVolumeDescriptor, LongAd, PartitionDescriptor}, udf_parser::{DescriptorStats, Result, UdfError}};
 
+/// What a Cache has done, readable from other threads (e.g. for the metrics of serve)
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    /// blocks returned from the cache
+    pub hits: AtomicU64,
+    /// blocks read from the reader
+    pub misses: AtomicU64,
+    /// blocks that could not be read
+    pub read_errors: AtomicU64,
+    /// reads of blocks that were tried again after failing, as ReadErrorPolicy::retries allows
+    pub retries: AtomicU64,
+    /// the descriptors that the parser of the disc validated
+    pub descriptors: DescriptorStats,
+}
 
-pub struct Cache<R: Read + Seek, const BYTE_SIZE: usize> {
-    /// The cache data.
-    data: [u8; BYTE_SIZE],
-    lru_cache: lru::LruCache<u32, u32>,
-    empty_blocks: Vec<u32>,
-    reader: R,
+/// What a Cache does when a block cannot be read (--on-read-error)
+#[derive(Debug, Clone, Default)]
+pub struct ReadErrorPolicy {
+    /// how many more times a block that could not be read is sought to and read again
+    pub retries: u32,
+    /// a block that still cannot be read is read as zeros and its sector added to skipped,
+    /// rather than failing the read
+    pub skip: bool,
+    /// the sectors read as zeros, shared by the caches of the same disc
+    pub skipped: Arc<Mutex<SectorRanges>>,
 }
-impl<R: Read + Seek, const BYTE_SIZE: usize> Cache<R, BYTE_SIZE>   {
-    /// Create a new cache.
-    pub fn new(reader:R) -> Cache<R, BYTE_SIZE> {
-        let mut empty_blocks = Vec::with_capacity(BYTE_SIZE / DVDCSS_BLOCK_SIZE);
-        for i in 0..BYTE_SIZE / DVDCSS_BLOCK_SIZE {
-            empty_blocks.push(i as u32);
-        }
-        Cache {
-            data: [0; BYTE_SIZE],
-            lru_cache: LruCache::new(NonZero::new(empty_blocks.len()).unwrap()),
-            empty_blocks,
-            reader
-        }
-    }
-    fn ensure_empty_block(&mut self) -> u32 {
-        if let Some(index) = self.empty_blocks.pop() {
-            index
-        } else {
-            let (_old_block, index) = self.lru_cache.pop_lru().unwrap();
-            index
-        }
+impl ReadErrorPolicy {
+    /// The number of sectors that were read as zeros so far
+    pub fn skipped_count(&self) -> u64 {
+        self.skipped.lock().unwrap().sector_count()
     }
-    pub fn read_exact(&mut self, pos: usize, buf: &mut [u8]) -> Result<()> {
+}
+
+/// The most blocks that a Cache reads ahead of sequential reads in one read (128 KiB), which
+/// keeps an optical drive streaming rather than stopping for every block
+pub const MAX_READAHEAD_BLOCKS: u32 = 64;
+
+/// Which blocks a Cache reads along with a block that it does not have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Readahead {
+    /// the blocks after it when the reads are sequential, twice as many with each read up to
+    /// MAX_READAHEAD_BLOCKS
+    #[default]
+    Sequential,
+    /// this many blocks after it, whether or not the reads are sequential. With 0 only the
+    /// block is read
+    Blocks(u32),
+}
+
+/// The blocks that Cache::new keeps
+pub const DEFAULT_CACHE_BLOCKS: usize = 1;
+
+/// A disc read a 2048-byte block at a time, which the volume structures, directories and files
+/// are all read through
+pub trait BlockDevice {
+    fn read_block(&mut self, block: u32) -> Result<&[u8]>;
+    /// Read buf from byte pos of the blocks
+    fn read_exact_at(&mut self, pos: usize, buf: &mut [u8]) -> Result<()> {
         let end_pos = pos + buf.len();
         let mut read = 0;
         while read < buf.len() {
@@ -48,34 +75,499 @@ impl<R: Read + Seek, const BYTE_SIZE: usize> Cache<R, BYTE_SIZE>   {
             buf[read..read + len].copy_from_slice(&data[offset..offset + len]);
             read += len;
         }
-        // debug!("read_exact: pos={}, len={} read {:?}", pos, buf.len(), buf);
         Ok(())
     }
-    pub fn read_block(&mut self, block: u32) -> Result<&[u8]> {
-        let existing = self.lru_cache.get(&block);
-        if let Some(&index) = existing {
-            let start = index as usize * DVDCSS_BLOCK_SIZE as usize;
-            Ok(&self.data[start..start + DVDCSS_BLOCK_SIZE as usize])
+    fn has_sparable_partitions(&self) -> bool {
+        false
+    }
+    /// The sector that sector of a Sparable Partition is recorded in
+    fn spared_sector(&self, sector: u32) -> u32 {
+        sector
+    }
+    fn stats(&self) -> Arc<CacheStats>;
+}
+
+/// The cached blocks of a disc, kept by sector. A UdfParser keeps one for all of its caches,
+/// so that a block read once by any of them is not read again.
+#[derive(Debug)]
+pub struct BlockStore {
+    /// The cache data, which grows a block at a time up to capacity blocks
+    data: Vec<u8>,
+    capacity: usize,
+    /// which block of data holds each sector
+    lru_cache: lru::LruCache<u32, u32>,
+    /// the blocks of data that were freed by a failed read
+    empty_blocks: Vec<u32>,
+    stats: Arc<CacheStats>,
+}
+impl BlockStore {
+    /// Keep up to capacity blocks (at least 1). Their memory is only allocated as blocks are
+    /// read, so a large store costs nothing until it is used.
+    pub fn with_capacity(capacity: usize) -> Self {
+        BlockStore {
+            data: vec![],
+            capacity: capacity.max(1),
+            // unbounded, as it never has more entries than data has blocks
+            lru_cache: LruCache::unbounded(),
+            empty_blocks: vec![],
+            stats: Arc::default(),
+        }
+    }
+    pub fn stats(&self) -> &Arc<CacheStats> {
+        &self.stats
+    }
+    /// Forget the cached blocks, e.g. when the reader will read them differently
+    pub fn clear(&mut self) {
+        self.lru_cache.clear();
+        self.empty_blocks.clear();
+        self.data.clear();
+    }
+    fn block(&self, index: u32) -> &[u8] {
+        let start = index as usize * DVDCSS_BLOCK_SIZE;
+        &self.data[start..start + DVDCSS_BLOCK_SIZE]
+    }
+    fn ensure_empty_block(&mut self) -> u32 {
+        if let Some(index) = self.empty_blocks.pop() {
+            index
+        } else if self.data.len() < self.capacity * DVDCSS_BLOCK_SIZE {
+            self.data.resize(self.data.len() + DVDCSS_BLOCK_SIZE, 0);
+            (self.data.len() / DVDCSS_BLOCK_SIZE - 1) as u32
         } else {
-            let index = self.ensure_empty_block();
-            let buf = &mut self.data[index as usize * DVDCSS_BLOCK_SIZE..
-                (index + 1) as usize * DVDCSS_BLOCK_SIZE];
-            buf.fill(0);
-            match (|| -> Result<()> {
-                self.reader.seek(std::io::SeekFrom::Start(block as u64 * DVDCSS_BLOCK_SIZE as u64))?;
-                self.reader.read_exact(buf)?;
-                Ok(())
-            })() {
+            let (_old_sector, index) = self.lru_cache.pop_lru().unwrap();
+            index
+        }
+    }
+}
+impl Default for BlockStore {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CACHE_BLOCKS)
+    }
+}
+
+/// The Cache of a UdfParser, which shares the parser's BlockStore
+pub type SharedCache<'a, R> = Cache<&'a mut R, &'a mut BlockStore>;
+
+/// Reads the blocks of a reader through a BlockStore, which it owns or borrows from a UdfParser
+pub struct Cache<R: Read + Seek, S: BorrowMut<BlockStore> = BlockStore> {
+    blocks: S,
+    reader: R,
+    /// the virtual partitions whose sectors are read from the blocks their VATs map them to
+    virtual_partitions: BTreeMap<u16, VirtualPartition>,
+    /// the Sparable Partitions whose moved packets read_exact_from_partition reads from their spares
+    sparable_partitions: Vec<SparablePartition>,
+    read_error_policy: ReadErrorPolicy,
+    /// the sectors from readahead_start read ahead of a sequential reader, which are not in
+    /// the LRU cache so that they do not evict its blocks
+    readahead: Vec<u8>,
+    readahead_start: u32,
+    /// the sector after the last one read, to tell sequential reads
+    next_sector: Option<u32>,
+    readahead_policy: Readahead,
+    /// how many blocks the next read reads: with Readahead::Sequential doubled while the
+    /// reads are sequential, up to MAX_READAHEAD_BLOCKS
+    readahead_blocks: u32,
+    /// the byte that Read reads next
+    position: u64,
+    /// where the 2048 bytes of each sector are in the reader, e.g. of raw 2352-byte CD sectors
+    sector_size: u32,
+    data_offset: u32,
+}
+impl<R: Read + Seek> Cache<R> {
+    /// Create a new cache of DEFAULT_CACHE_BLOCKS blocks.
+    pub fn new(reader: R) -> Cache<R> {
+        Self::with_capacity(reader, DEFAULT_CACHE_BLOCKS)
+    }
+    /// Create a new cache that keeps up to capacity blocks (at least 1)
+    pub fn with_capacity(reader: R, capacity: usize) -> Cache<R> {
+        Cache::with_blocks(reader, BlockStore::with_capacity(capacity))
+    }
+}
+impl<R: Read + Seek, S: BorrowMut<BlockStore>> Cache<R, S> {
+    /// Create a cache that keeps its blocks in blocks
+    pub fn with_blocks(reader: R, blocks: S) -> Self {
+        Cache {
+            blocks,
+            reader,
+            virtual_partitions: BTreeMap::new(),
+            sparable_partitions: vec![],
+            read_error_policy: ReadErrorPolicy::default(),
+            readahead: vec![],
+            readahead_start: 0,
+            next_sector: None,
+            readahead_policy: Readahead::default(),
+            readahead_blocks: 1,
+            position: 0,
+            sector_size: DVDCSS_BLOCK_SIZE as u32,
+            data_offset: 0,
+        }
+    }
+    /// Read the sectors of virtual_partitions through their Virtual Allocation Tables
+    pub fn with_virtual_partitions(self, virtual_partitions: BTreeMap<u16, VirtualPartition>) -> Self {
+        Self { virtual_partitions, ..self }
+    }
+    /// Read the moved packets of sparable_partitions from where their Sparing Tables say
+    pub fn with_sparable_partitions(self, sparable_partitions: Vec<SparablePartition>) -> Self {
+        Self { sparable_partitions, ..self }
+    }
+    /// Retry or skip the blocks that cannot be read as read_error_policy says
+    pub fn with_read_error_policy(self, read_error_policy: ReadErrorPolicy) -> Self {
+        Self { read_error_policy, ..self }
+    }
+    /// Read the blocks that readahead says along with each block that is not cached, in one
+    /// read of the reader
+    pub fn with_readahead(self, readahead_policy: Readahead) -> Self {
+        Self { readahead_policy, ..self }
+    }
+    /// Read each block from data_offset bytes into a sector of sector_size bytes of the reader,
+    /// e.g. of an image of raw CD sectors. Such blocks are not read ahead.
+    pub fn with_sector_layout(self, sector_size: u32, data_offset: u32) -> Self {
+        Self { sector_size, data_offset, ..self }
+    }
+}
+
+impl<R: Read + Seek, S: BorrowMut<BlockStore>> BlockDevice for Cache<R, S> {
+    fn read_block(&mut self, block: u32) -> Result<&[u8]> {
+        let blocks = self.blocks.borrow_mut();
+        let sector = match translate_virtual_sector(&self.virtual_partitions, block) {
+            Ok(sector) => sector,
+            Err(e) => {
+                blocks.stats.read_errors.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+        // by sector rather than block, as the caches that share a BlockStore may read
+        // different virtual partitions
+        if let Some(&index) = blocks.lru_cache.get(&sector) {
+            blocks.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(blocks.block(index));
+        }
+        let position = sector as u64 * self.sector_size as u64 + self.data_offset as u64;
+        let index = blocks.ensure_empty_block();
+        let buf = &mut blocks.data[index as usize * DVDCSS_BLOCK_SIZE..(index + 1) as usize * DVDCSS_BLOCK_SIZE];
+        buf.fill(0);
+        let sequential = self.next_sector == Some(sector);
+        self.next_sector = sector.checked_add(1);
+        if let Some(offset) = (sector.checked_sub(self.readahead_start))
+            .map(|i| i as usize * DVDCSS_BLOCK_SIZE)
+            .filter(|&offset| offset < self.readahead.len())
+        {
+            blocks.stats.hits.fetch_add(1, Ordering::Relaxed);
+            buf.copy_from_slice(&self.readahead[offset..offset + DVDCSS_BLOCK_SIZE]);
+            blocks.lru_cache.put(sector, index);
+            return Ok(buf);
+        }
+        self.readahead_blocks = match (self.readahead_policy, sequential) {
+            // the blocks of the sectors are not next to each other
+            _ if self.sector_size != DVDCSS_BLOCK_SIZE as u32 => 1,
+            (Readahead::Sequential, true) => (self.readahead_blocks * 2).min(MAX_READAHEAD_BLOCKS),
+            (Readahead::Sequential, false) => 1,
+            (Readahead::Blocks(count), _) => count.saturating_add(1),
+        };
+        if self.readahead_blocks > 1 {
+            let len = self.readahead_blocks as usize * DVDCSS_BLOCK_SIZE;
+            self.readahead.resize(len, 0);
+            let read = (self.reader.seek(std::io::SeekFrom::Start(position)))
+                .and_then(|_| self.reader.read_exact(&mut self.readahead));
+            match read {
                 Ok(()) => {
-                    self.lru_cache.put(block, index);
-                    Ok(buf)
+                    self.readahead_start = sector;
+                    blocks.stats.misses.fetch_add(self.readahead_blocks as u64, Ordering::Relaxed);
+                    buf.copy_from_slice(&self.readahead[..DVDCSS_BLOCK_SIZE]);
+                    blocks.lru_cache.put(sector, index);
+                    return Ok(buf);
                 }
+                // e.g. past the end of the disc or a bad sector, which the read of the
+                // block alone finds out
                 Err(e) => {
-                    self.empty_blocks.push(index);
-                    Err(e)
+                    debug!("read_block: {} sectors from {}: {}; reading one", self.readahead_blocks, sector, e);
+                    self.readahead.clear();
+                    self.readahead_blocks = 1;
                 }
             }
         }
+        let mut tries = 0;
+        let read = loop {
+            let read = (self.reader.seek(std::io::SeekFrom::Start(position)))
+                .and_then(|_| self.reader.read_exact(buf));
+            match read {
+                Err(e) if tries < self.read_error_policy.retries && e.kind() != io::ErrorKind::Interrupted => {
+                    tries += 1;
+                    blocks.stats.retries.fetch_add(1, Ordering::Relaxed);
+                    debug!("read_block: sector {}: {}; try {} of {}", sector, e, tries + 1, self.read_error_policy.retries + 1);
+                }
+                Err(e) if self.read_error_policy.skip && e.kind() != io::ErrorKind::Interrupted => {
+                    warn!("Could not read sector {}; reading it as zeros: {}", sector, e);
+                    blocks.stats.read_errors.fetch_add(1, Ordering::Relaxed);
+                    self.read_error_policy.skipped.lock().unwrap().insert(sector, sector + 1);
+                    buf.fill(0);
+                    break Ok(());
+                }
+                read => break read,
+            }
+        };
+        match read {
+            Ok(()) => {
+                blocks.stats.misses.fetch_add(1, Ordering::Relaxed);
+                blocks.lru_cache.put(sector, index);
+                Ok(buf)
+            }
+            Err(e) => {
+                blocks.stats.read_errors.fetch_add(1, Ordering::Relaxed);
+                blocks.empty_blocks.push(index);
+                Err(e.into())
+            }
+        }
+    }
+    fn has_sparable_partitions(&self) -> bool {
+        !self.sparable_partitions.is_empty()
+    }
+    fn spared_sector(&self, sector: u32) -> u32 {
+        spared_sector(&self.sparable_partitions, sector)
+    }
+    fn stats(&self) -> Arc<CacheStats> {
+        Arc::clone(&self.blocks.borrow().stats)
+    }
+}
+
+impl<B: BlockDevice + ?Sized> BlockDevice for &mut B {
+    fn read_block(&mut self, block: u32) -> Result<&[u8]> {
+        (**self).read_block(block)
+    }
+    fn read_exact_at(&mut self, pos: usize, buf: &mut [u8]) -> Result<()> {
+        (**self).read_exact_at(pos, buf)
+    }
+    fn has_sparable_partitions(&self) -> bool {
+        (**self).has_sparable_partitions()
+    }
+    fn spared_sector(&self, sector: u32) -> u32 {
+        (**self).spared_sector(sector)
+    }
+    fn stats(&self) -> Arc<CacheStats> {
+        (**self).stats()
+    }
+}
+
+/// Reads the blocks from the position that seek set, so that a Cache can be the reader of e.g.
+/// a UdfParser
+impl<R: Read + Seek, S: BorrowMut<BlockStore>> Read for Cache<R, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let block = u32::try_from(self.position / DVDCSS_BLOCK_SIZE as u64)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "position past the last block"))?;
+        let offset = (self.position % DVDCSS_BLOCK_SIZE as u64) as usize;
+        let data = match self.read_block(block) {
+            Ok(data) => data,
+            // the end of the reader
+            Err(UdfError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+            Err(UdfError::Io(e)) => return Err(e),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let len = buf.len().min(DVDCSS_BLOCK_SIZE - offset);
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        self.position += len as u64;
+        Ok(len)
     }
 }
 
+impl<R: Read + Seek, S: BorrowMut<BlockStore>> Seek for Cache<R, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => {
+                let mut end = self.reader.seek(SeekFrom::End(0))?;
+                if self.sector_size != DVDCSS_BLOCK_SIZE as u32 {
+                    end = end.saturating_sub(self.data_offset as u64) / self.sector_size as u64 * DVDCSS_BLOCK_SIZE as u64;
+                }
+                end.checked_add_signed(offset)
+            }
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sparse_image::SectorRange,
+        test_support::{build_image, FixtureFile, Quirks},
+        udf_parser::UdfParser,
+    };
+    use std::io::Cursor;
+
+    /// An image whose sector bad_sector fails the first failures reads
+    struct FailingDisc {
+        image: Cursor<Vec<u8>>,
+        bad_sector: u64,
+        failures: u32,
+    }
+    impl Read for FailingDisc {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.image.position() / DVDCSS_BLOCK_SIZE as u64 == self.bad_sector && self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::other("medium error"));
+            }
+            self.image.read(buf)
+        }
+    }
+    impl Seek for FailingDisc {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.image.seek(pos)
+        }
+    }
+
+    fn cache(failures: u32, retries: u32, skip: bool) -> Cache<FailingDisc> {
+        let disc = FailingDisc {
+            image: Cursor::new(vec![7; 4 * DVDCSS_BLOCK_SIZE]),
+            bad_sector: 2,
+            failures,
+        };
+        let policy = ReadErrorPolicy { retries, skip, ..Default::default() };
+        Cache::new(disc).with_read_error_policy(policy)
+    }
+
+    #[test]
+    fn test_read_error_policy() {
+        // retried until it is read
+        let mut retried = cache(2, 2, false);
+        assert!(retried.read_block(2).unwrap().iter().all(|&b| b == 7));
+        assert_eq!(retried.stats().read_errors.load(Ordering::Relaxed), 0);
+        assert_eq!(retried.stats().retries.load(Ordering::Relaxed), 2);
+
+        // aborted when the retries run out
+        let mut aborted = cache(3, 2, false);
+        assert!(aborted.read_block(2).is_err());
+        assert_eq!(aborted.stats().read_errors.load(Ordering::Relaxed), 1);
+
+        // read as zeros and recorded
+        let mut skipped = cache(u32::MAX, 1, true);
+        assert!(skipped.read_block(2).unwrap().iter().all(|&b| b == 0));
+        assert!(skipped.read_block(3).unwrap().iter().all(|&b| b == 7));
+        assert_eq!(skipped.stats().read_errors.load(Ordering::Relaxed), 1);
+        assert_eq!(skipped.read_error_policy.skipped_count(), 1);
+        assert_eq!(skipped.read_error_policy.skipped.lock().unwrap().ranges(), &[SectorRange { start: 2, end: 3 }]);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image: Vec<u8> = (0..10u8).flat_map(|sector| [sector; DVDCSS_BLOCK_SIZE]).collect();
+        for (capacity, hits) in [(1, 0), (4, 4), (100, 4)] {
+            let mut cache = Cache::with_capacity(Cursor::new(image.clone()), capacity).with_readahead(Readahead::Blocks(0));
+            for block in [0, 1, 2, 3, 0, 1, 2, 3] {
+                assert!(cache.read_block(block).unwrap().iter().all(|&b| b == block as u8));
+            }
+            assert_eq!(cache.stats().hits.load(Ordering::Relaxed), hits, "capacity {}", capacity);
+            assert!(cache.blocks.data.len() <= 4 * DVDCSS_BLOCK_SIZE);
+        }
+    }
+
+    #[test]
+    fn test_read_and_seek() {
+        let image: Vec<u8> = (0..5u8).flat_map(|sector| [sector; DVDCSS_BLOCK_SIZE]).collect();
+        let mut cache = Cache::new(Cursor::new(image));
+        let mut buf = [0u8; 20];
+        cache.seek(SeekFrom::Start(2 * DVDCSS_BLOCK_SIZE as u64 - 10)).unwrap();
+        cache.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..10], [1; 10]);
+        assert_eq!(buf[10..], [2; 10]);
+        assert_eq!(cache.seek(SeekFrom::End(-5)).unwrap(), 5 * DVDCSS_BLOCK_SIZE as u64 - 5);
+        let mut rest = vec![];
+        cache.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [4; 5]);
+        assert!(cache.seek(SeekFrom::Current(-100_000)).is_err());
+
+        // the reader of a UdfParser
+        let files = [FixtureFile {
+            path: "VIDEO_TS/VIDEO_TS.IFO",
+            contents: b"DVDVIDEO-VMG",
+        }];
+        let image = build_image(&files, "CACHED", &Quirks::default()).unwrap();
+        let mut parser = UdfParser::new(Cache::with_capacity(Cursor::new(image), 64));
+        let structures = parser.read_volume_structures().unwrap();
+        assert_eq!(structures.primary_volume.volume_identifier.to_string(), "CACHED");
+    }
+
+    #[test]
+    fn test_shared_blocks() {
+        let image: Vec<u8> = (0..10u8).flat_map(|sector| [sector; DVDCSS_BLOCK_SIZE]).collect();
+        let mut disc = CountingDisc {
+            image: Cursor::new(image),
+            reads: vec![],
+        };
+        let mut parser = UdfParser::new(&mut disc).with_cache_blocks(4).with_readahead(Readahead::Blocks(0));
+        let mut buf = [0u8; DVDCSS_BLOCK_SIZE];
+        parser.read_sectors(3, &mut buf).unwrap();
+        // a block that the parser read is not read again by its caches, nor theirs by it
+        assert!(parser.cache().read_block(3).unwrap().iter().all(|&b| b == 3));
+        assert!(parser.cache().read_block(5).unwrap().iter().all(|&b| b == 5));
+        parser.read_sectors(5, &mut buf).unwrap();
+        assert_eq!(buf, [5; DVDCSS_BLOCK_SIZE]);
+        let stats = parser.cache().stats();
+        assert_eq!((stats.hits.load(Ordering::Relaxed), stats.misses.load(Ordering::Relaxed)), (2, 2));
+        parser.clear_cache();
+        parser.read_sectors(3, &mut buf).unwrap();
+        drop(parser);
+        assert_eq!(disc.reads, [1, 1, 1]);
+    }
+
+    /// An image of sectors filled with their number that counts the reads
+    struct CountingDisc {
+        image: Cursor<Vec<u8>>,
+        reads: Vec<usize>,
+    }
+    impl Read for CountingDisc {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.push(buf.len() / DVDCSS_BLOCK_SIZE);
+            self.image.read(buf)
+        }
+    }
+    impl Seek for CountingDisc {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.image.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_readahead() {
+        let sectors = 300;
+        let image: Vec<u8> = (0..sectors).flat_map(|sector| [sector as u8; DVDCSS_BLOCK_SIZE]).collect();
+        let mut disc = CountingDisc {
+            image: Cursor::new(image),
+            reads: vec![],
+        };
+        let mut cache = Cache::new(&mut disc);
+        // sequential reads to the end of the image, where reading ahead fails
+        for block in 0..sectors {
+            assert!(cache.read_block(block).unwrap().iter().all(|&b| b == block as u8), "block {}", block);
+        }
+        // a read elsewhere does not read ahead
+        assert!(cache.read_block(7).unwrap().iter().all(|&b| b == 7));
+        assert_eq!(cache.stats().read_errors.load(Ordering::Relaxed), 0);
+        drop(cache);
+        assert!(disc.reads.len() < 30, "{:?}", disc.reads);
+        assert_eq!(disc.reads[..8], [1, 2, 4, 8, 16, 32, 64, 64]);
+        assert_eq!(disc.reads.last(), Some(&1));
+
+        // a fixed window is read ahead of any block, and none at all with 0
+        disc.reads.clear();
+        let mut cache = Cache::new(&mut disc).with_readahead(Readahead::Blocks(7));
+        for block in [100, 104, 107, 108, 50] {
+            assert!(cache.read_block(block).unwrap().iter().all(|&b| b == block as u8), "block {}", block);
+        }
+        drop(cache);
+        assert_eq!(disc.reads, [8, 8, 8]);
+        disc.reads.clear();
+        let mut cache = Cache::new(&mut disc).with_readahead(Readahead::Blocks(0));
+        for block in 0..5 {
+            cache.read_block(block).unwrap();
+        }
+        drop(cache);
+        assert_eq!(disc.reads, [1, 1, 1, 1, 1]);
+    }
+}
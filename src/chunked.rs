@@ -0,0 +1,156 @@
+//! `--chunk-size`: each file is split into fixed-size chunks that are stored once by their
+//! SHA-256 in a chunk store shared by all discs (like casync's), with an index listing the
+//! file's chunks in order. Repeated rips of the same or similar discs then add few new
+//! chunks, which backup tools that sync whole files deduplicate well.
+
+use std::io::{self, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::output::{OutputFile, OutputSink};
+
+/// The chunk store's directory in the output directory
+pub const CHUNK_STORE_DIR: &str = "chunks";
+
+/// The suffix of a file's index
+pub const INDEX_EXTENSION: &str = ".chunks";
+
+/// The first line of an index
+pub const INDEX_HEADER: &str = "dvdromcopy-chunks 1";
+
+/// The path of a chunk in the chunk store, fanned out by the first byte of its hash
+pub fn chunk_path(sha256: &str) -> String {
+    format!("{}/{}", &sha256[..2], sha256)
+}
+
+/// One line of an index: the chunk's SHA-256 and length
+pub fn index_line(sha256: &str, len: usize) -> String {
+    format!("{} {}\n", sha256, len)
+}
+
+/// Files of index_output as indexes of chunks in chunk_store
+pub struct ChunkedOutput<'a> {
+    index_output: &'a dyn OutputSink,
+    chunk_store: &'a dyn OutputSink,
+    chunk_size: usize,
+}
+impl<'a> ChunkedOutput<'a> {
+    pub fn new(index_output: &'a dyn OutputSink, chunk_store: &'a dyn OutputSink, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        Self {
+            index_output,
+            chunk_store,
+            chunk_size,
+        }
+    }
+    fn index_path(path: &str) -> String {
+        format!("{}{}", path, INDEX_EXTENSION)
+    }
+}
+impl OutputSink for ChunkedOutput<'_> {
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        self.index_output.exists(&Self::index_path(path))
+    }
+    fn create(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+        Ok(Box::new(ChunkedFile {
+            output: self,
+            path: path.to_string(),
+            buf: Vec::new(),
+            index: format!("{}\n", INDEX_HEADER),
+        }))
+    }
+    /// None, since the files are not stored whole
+    fn local_dir(&self) -> Option<&std::path::Path> {
+        None
+    }
+    fn describe(&self, path: &str) -> String {
+        self.index_output.describe(&Self::index_path(path))
+    }
+}
+
+/// The index is written when finished, so that it only refers to stored chunks
+struct ChunkedFile<'a> {
+    output: &'a ChunkedOutput<'a>,
+    path: String,
+    /// the current chunk, which grows as it is written rather than taking chunk_size bytes
+    /// for a small file
+    buf: Vec<u8>,
+    index: String,
+}
+impl ChunkedFile<'_> {
+    fn store_chunk(&mut self) -> io::Result<()> {
+        let sha256: String = Sha256::digest(&self.buf).iter().map(|b| format!("{:02x}", b)).collect();
+        let path = chunk_path(&sha256);
+        if !self.output.chunk_store.exists(&path)? {
+            let mut file = self.output.chunk_store.create(&path)?;
+            file.write_all(&self.buf)?;
+            file.finish()?;
+        }
+        self.index.push_str(&index_line(&sha256, self.buf.len()));
+        self.buf.clear();
+        Ok(())
+    }
+}
+impl Write for ChunkedFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.output.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..len]);
+        if self.buf.len() == self.output.chunk_size {
+            self.store_chunk()?;
+        }
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+impl OutputFile for ChunkedFile<'_> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.store_chunk()?;
+        }
+        let mut index = self.output.index_output.create(&ChunkedOutput::index_path(&self.path))?;
+        index.write_all(self.index.as_bytes())?;
+        index.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{LocalOutput, SubdirectoryOutput};
+
+    #[test]
+    fn test_chunked_output() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-chunked-test-{}", std::process::id()));
+        let root = LocalOutput::new(&dir);
+        let chunk_store = SubdirectoryOutput::new(&root, "chunks");
+        let disc = SubdirectoryOutput::new(&root, "Disc");
+        let output = ChunkedOutput::new(&disc, &chunk_store, 4);
+
+        let mut file = output.create("VIDEO_TS/VTS_01_1.VOB").unwrap();
+        file.write_all(b"abcdabcdef").unwrap();
+        assert!(!output.exists("VIDEO_TS/VTS_01_1.VOB").unwrap());
+        file.finish().unwrap();
+        assert!(output.exists("VIDEO_TS/VTS_01_1.VOB").unwrap());
+
+        let abcd = "88d4266fd4e6338d13b845fcf289579d209c897823b9217da3e161936f031589";
+        let ef = "4ca669ac3713d1f4aea07dae8dcc0d1c9867d27ea82a3ba4e6158a42206f959b";
+        let index = std::fs::read_to_string(dir.join("Disc/VIDEO_TS/VTS_01_1.VOB.chunks")).unwrap();
+        assert_eq!(
+            index,
+            format!("{}\n{}{}{}", INDEX_HEADER, index_line(abcd, 4), index_line(abcd, 4), index_line(ef, 2))
+        );
+        // the repeated chunk is stored once
+        assert_eq!(std::fs::read(dir.join("chunks").join(chunk_path(abcd))).unwrap(), b"abcd");
+        assert_eq!(std::fs::read(dir.join("chunks").join(chunk_path(ef))).unwrap(), b"ef");
+        assert_eq!(std::fs::read_dir(dir.join("chunks")).unwrap().count(), 2);
+
+        // and is not written again by another file
+        let mut file = output.create("VIDEO_TS/VTS_02_1.VOB").unwrap();
+        file.write_all(b"abcd").unwrap();
+        file.finish().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
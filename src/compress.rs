@@ -0,0 +1,152 @@
+//! `--compress`: files are compressed as they are written, e.g. `VIDEO_TS.IFO.zst`, for
+//! archiving discs of compressible data to slow network storage. The manifest and DFXML
+//! still describe the uncompressed contents. The encoders need the `compress` cargo feature.
+
+use std::{io, path::Path};
+#[cfg(feature = "compress")]
+use std::io::Write;
+
+#[cfg(feature = "compress")]
+use flate2::write::GzEncoder;
+
+use crate::output::{OutputFile, OutputSink};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Zstandard, using all cores
+    Zstd,
+    Gzip,
+}
+impl Compression {
+    /// The suffix of the compressed files
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Zstd => ".zst",
+            Compression::Gzip => ".gz",
+        }
+    }
+}
+
+/// The files of another sink, compressed and with the compression's extension
+pub struct CompressedOutput<'a> {
+    parent: &'a dyn OutputSink,
+    compression: Compression,
+}
+impl<'a> CompressedOutput<'a> {
+    /// Fails without the compress feature
+    pub fn new(parent: &'a dyn OutputSink, compression: Compression) -> io::Result<Self> {
+        if cfg!(not(feature = "compress")) {
+            return Err(unsupported());
+        }
+        Ok(Self { parent, compression })
+    }
+    fn parent_path(&self, path: &str) -> String {
+        format!("{}{}", path, self.compression.extension())
+    }
+}
+impl OutputSink for CompressedOutput<'_> {
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        self.parent.exists(&self.parent_path(path))
+    }
+    fn create(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+        let file = self.parent.create(&self.parent_path(path))?;
+        compressed_file(file, self.compression)
+    }
+    /// None, since the checks that read back the files cannot read them compressed
+    fn local_dir(&self) -> Option<&Path> {
+        None
+    }
+    fn describe(&self, path: &str) -> String {
+        self.parent.describe(&self.parent_path(path))
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::other("--compress needs dvdromcopy built with --features compress")
+}
+
+#[cfg(feature = "compress")]
+fn compressed_file<'a>(file: Box<dyn OutputFile + 'a>, compression: Compression) -> io::Result<Box<dyn OutputFile + 'a>> {
+    Ok(Box::new(match compression {
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+            let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+            encoder.multithread(threads as u32)?;
+            CompressedFile::Zstd(encoder)
+        }
+        Compression::Gzip => CompressedFile::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+    }))
+}
+
+#[cfg(not(feature = "compress"))]
+fn compressed_file<'a>(_file: Box<dyn OutputFile + 'a>, _compression: Compression) -> io::Result<Box<dyn OutputFile + 'a>> {
+    Err(unsupported())
+}
+
+#[cfg(feature = "compress")]
+enum CompressedFile<'a> {
+    Zstd(zstd::Encoder<'static, Box<dyn OutputFile + 'a>>),
+    Gzip(GzEncoder<Box<dyn OutputFile + 'a>>),
+}
+#[cfg(feature = "compress")]
+impl Write for CompressedFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedFile::Zstd(encoder) => encoder.write(buf),
+            CompressedFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedFile::Zstd(encoder) => encoder.flush(),
+            CompressedFile::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+#[cfg(feature = "compress")]
+impl OutputFile for CompressedFile<'_> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let file = match *self {
+            CompressedFile::Zstd(encoder) => encoder.finish()?,
+            CompressedFile::Gzip(encoder) => encoder.finish()?,
+        };
+        file.finish()
+    }
+}
+
+#[cfg(all(test, feature = "compress"))]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::output::LocalOutput;
+
+    #[test]
+    fn test_compressed_output() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-compress-test-{}", std::process::id()));
+        let root = LocalOutput::new(&dir);
+        let contents = b"README ".repeat(1000);
+        for compression in [Compression::Zstd, Compression::Gzip] {
+            let output = CompressedOutput::new(&root, compression).unwrap();
+            let mut file = output.create("README.TXT").unwrap();
+            file.write_all(&contents).unwrap();
+            assert!(!output.exists("README.TXT").unwrap());
+            file.finish().unwrap();
+            assert!(output.exists("README.TXT").unwrap());
+            assert_eq!(output.local_dir(), None);
+
+            let compressed = std::fs::read(dir.join(format!("README.TXT{}", compression.extension()))).unwrap();
+            assert!(compressed.len() < contents.len() / 10);
+            let decompressed = match compression {
+                Compression::Zstd => zstd::decode_all(compressed.as_slice()).unwrap(),
+                Compression::Gzip => {
+                    let mut decompressed = Vec::new();
+                    flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed).unwrap();
+                    decompressed
+                }
+            };
+            assert_eq!(decompressed, contents);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,577 @@
+//! A façade over the rest of the crate for programs that embed dvdromcopy, such as GUIs and
+//! batch rippers: DvdCopier opens a disc, describes it, lists its files and copies them. The
+//! walk of the directory tree and the reading of file data that it uses are shared with the
+//! `dvdromcopy` binary.
+
+use log::{debug, error, warn};
+use std::{
+    collections::BTreeMap,
+    fs::create_dir_all,
+    io::{self, Read, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    cache::{BlockDevice, SharedCache},
+    device::{device_parser, device_size, open_device_at},
+    dvdcss_sys::{DvdCss, DVDCSS_BLOCK_SIZE},
+    interrupt::check_interrupted,
+    listing::ListEntry,
+    logical_block_reader::{file_data_extents, read_exact_from_partition},
+    manifest::{HashingWriter, Manifest},
+    output::{LocalOutput, OutputSink},
+    path_filter::PathFilter,
+    sanitize::{is_case_insensitive, PathSanitizer, TargetOs},
+    udf::{
+        FileEntry, FileIdentifierDescriptor, LogicalVolumeDescriptor, LongAd, PartitionDescriptor, PartitionMap,
+        ShortAllocationDescriptor,
+    },
+    udf_file::UdfFile,
+    udf_high_level::{VolumeInfo, VolumeStructures},
+    udf_parser::{
+        embedded_data, read_allocation_descriptors, read_directory_contents, read_file_entries, DirectoryWalk, Result,
+        UdfError, UdfParser,
+    },
+};
+
+/// A disc or image opened to list and copy its files
+pub struct DvdCopier {
+    parser: UdfParser<DvdCss>,
+    structures: VolumeStructures,
+    max_depth: usize,
+}
+
+/// How DvdCopier::copy_to copies
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    /// the files to copy
+    pub filter: PathFilter,
+    /// the file system rules to name the copied files by
+    pub target_os: TargetOs,
+    /// copy the other files after one cannot be read, rather than stopping
+    pub keep_going: bool,
+}
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            filter: PathFilter::default(),
+            target_os: TargetOs::host(),
+            keep_going: false,
+        }
+    }
+}
+
+/// What DvdCopier::copy_to copied
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    /// the SHA-256 of each file copied, by its path in the output directory
+    pub manifest: Manifest,
+    pub bytes_copied: u64,
+    /// the disc path and error of each file that could not be copied, with keep_going
+    pub failed: Vec<(String, String)>,
+    /// the disc path and output path of each file that was renamed for the target OS
+    pub renamed: Vec<(String, String)>,
+}
+
+impl DvdCopier {
+    /// Open the DVD device or image file (or http(s):// URL of an image) and read its UDF
+    /// volume structures
+    pub fn open(device: &str) -> Result<Self> {
+        let css = open_device_at(device, 0)?;
+        let parser = device_parser(device, css);
+        let parser = match device_size(device) {
+            Ok(size) => parser.with_total_sectors((size / DVDCSS_BLOCK_SIZE as u64) as u32),
+            Err(_) => parser,
+        };
+        Self::new(parser)
+    }
+
+    /// A DvdCopier of a disc that is already open
+    pub fn new(mut parser: UdfParser<DvdCss>) -> Result<Self> {
+        let structures = parser.read_volume_structures()?;
+        load_title_keys(&mut parser, &structures, DirectoryWalk::DEFAULT_MAX_DEPTH)?;
+        Ok(Self {
+            parser,
+            structures,
+            max_depth: DirectoryWalk::DEFAULT_MAX_DEPTH,
+        })
+    }
+
+    /// Stop walking the directory tree of a corrupt disc at max_depth
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        Self { max_depth, ..self }
+    }
+
+    pub fn volume_info(&self) -> VolumeInfo {
+        self.structures.volume_info()
+    }
+
+    pub fn structures(&self) -> &VolumeStructures {
+        &self.structures
+    }
+
+    /// The files of the disc in the order of the walk
+    pub fn list(&mut self) -> Result<Vec<ListEntry>> {
+        list_entries(&mut self.parser, &self.structures, self.max_depth)
+    }
+
+    /// The file of the disc at path ('/'-separated, e.g. VIDEO_TS/VTS_01_1.VOB), to read in place
+    pub fn open_file(&mut self, path: &str) -> Result<UdfFile<'_, DvdCss>> {
+        let mut icb = None;
+        let (structures, filter) = (&self.structures, PathFilter::default());
+        walk_file_sets(&mut self.parser, structures, self.max_depth, &filter, &mut |_, file_path, icb_address, _| {
+            if file_path == path {
+                icb = Some(icb_address.clone());
+            }
+            Ok(())
+        })?;
+        let icb = icb.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} is not on the disc", path))
+        })?;
+        let (logical_volume, partitions) = (&self.structures.logical_volume, &self.structures.partition_descriptors);
+        UdfFile::open(self.parser.cache(), logical_volume, partitions, &icb, path)
+    }
+
+    /// Copy the files of the disc that options.filter includes into dir, which is created if
+    /// it does not exist. Files already in dir are not overwritten.
+    pub fn copy_to(&mut self, dir: &Path, options: &CopyOptions) -> Result<CopyReport> {
+        create_dir_all(dir)?;
+        let output = LocalOutput::new(dir);
+        let case_insensitive = options.target_os == TargetOs::Windows || is_case_insensitive(dir)?;
+        let mut sanitizer = PathSanitizer::new(options.target_os, case_insensitive);
+        let mut report = CopyReport::default();
+        let structures = &self.structures;
+        let filter = &options.filter;
+        walk_file_sets(&mut self.parser, structures, self.max_depth, filter, &mut |reader, path, icb_address, _| {
+            let output_path = sanitizer.output_path(path);
+            let copied = (|| -> Result<_> {
+                let output_file = output.create(&output_path)?;
+                let mut hashing_output = HashingWriter::new(output_file);
+                write_file_data(
+                    reader,
+                    &structures.logical_volume,
+                    &structures.partition_descriptors,
+                    path,
+                    icb_address,
+                    0,
+                    &mut hashing_output,
+                )?;
+                let bytes = hashing_output.bytes_written();
+                let (output_file, digest) = hashing_output.finish();
+                output_file.finish()?;
+                Ok((bytes, digest))
+            })();
+            match copied {
+                Ok((bytes, digest)) => {
+                    report.bytes_copied += bytes;
+                    report.manifest.push(&output_path, digest);
+                }
+                Err(e) if options.keep_going => {
+                    error!("Could not copy {:?}; continuing: {}", path, e);
+                    report.failed.push((path.to_string(), e.to_string()));
+                }
+                Err(e) => return Err(e),
+            }
+            Ok(())
+        })?;
+        report.renamed = sanitizer.renamed();
+        Ok(report)
+    }
+}
+
+/// Make the reads of a scrambled disc decrypt each VOB with its own title key by giving
+/// libdvdcss the sectors where the VOBs start, as found in the file system
+pub fn load_title_keys(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    max_depth: usize,
+) -> Result<()> {
+    if !parser.reader.is_scrambled() {
+        return Ok(());
+    }
+    let mut key_sectors = vec![];
+    let filter = PathFilter::default();
+    walk_file_sets(parser, structures, max_depth, &filter, &mut |reader, path, icb_address, _| {
+        if !path.ends_with(".VOB") {
+            return Ok(());
+        }
+        let (logical_volume, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+        let (_, file_entries) = read_icb_entries(reader, logical_volume, partitions, icb_address)?;
+        let partition_reference_number = icb_address.extent_location.partition_reference_number;
+        let extents = file_data_extents(logical_volume, partitions, partition_reference_number, &file_entries, path)?;
+        if let Some((partition_descriptor, pos_in_partition, _)) = extents.first() {
+            let sector = partition_descriptor.partition_starting_location + (pos_in_partition / DVDCSS_BLOCK_SIZE) as u32;
+            debug!("{} starts at sector {}", path, sector);
+            key_sectors.push(sector);
+        }
+        Ok(())
+    })?;
+    parser.reader.set_key_sectors(key_sectors);
+    // sectors that were read ahead of the walk may have been decrypted with another key
+    parser.clear_cache();
+    Ok(())
+}
+
+/// Calls visit_file for every file that filter includes in the file set of each Type 1,
+/// Metadata or virtual partition, descending at most max_depth directories
+pub fn walk_file_sets<R, F>(
+    parser: &mut UdfParser<R>,
+    structures: &VolumeStructures,
+    max_depth: usize,
+    filter: &PathFilter,
+    visit_file: &mut F,
+) -> Result<()>
+where
+    R: Read + Seek,
+    F: FnMut(&mut SharedCache<R>, &str, &LongAd, u8) -> Result<()>,
+{
+    // See UDF 2.6.0 6.9 Requirements for DVD-ROM http://www.osta.org/specs/pdf/udf260.pdf#page=136
+    for (partition_descriptor, first_block) in file_set_locations(structures) {
+        let fsds = parser.read_fileset_descriptors_at(partition_descriptor, first_block)?;
+        // a sequence that starts with its Terminating Descriptor has no file set to walk
+        let fsd = fsds.first().ok_or_else(|| {
+            warn!("There is no File Set Descriptor at block {} of the partition", first_block);
+            UdfError::InvalidDescriptorTag
+        })?;
+        run_on_directory(
+            &mut parser.cache(),
+            &structures.logical_volume,
+            &structures.partition_descriptors,
+            &fsd.root_directory_icb,
+            &mut vec![],
+            &mut DirectoryWalk::new(max_depth).with_filter(filter.clone()),
+            visit_file,
+        )?;
+    }
+    Ok(())
+}
+
+/// The partition and first block of the File Set Descriptor Sequence of each Type 1
+/// partition, or of the Metadata or virtual partition recorded in it, which holds its file set
+pub fn file_set_locations(structures: &VolumeStructures) -> Vec<(&PartitionDescriptor, u32)> {
+    let mut locations = vec![];
+    for partition_map in structures.partition_maps.iter() {
+        match partition_map {
+            PartitionMap::Type1(partition_map) => {
+                let (key, first_block) = match structures.file_set_partition_of(partition_map.partition_number) {
+                    Some(reference) => (reference, structures.file_set_block(reference)),
+                    None => (partition_map.partition_number, 0),
+                };
+                if let Some(partition_descriptor) = structures.partition_descriptors.get(&key) {
+                    debug!("Found matching partition descriptor: {:?} -> starting location: {} sector, length: {} sectors",
+                        partition_descriptor, partition_descriptor.partition_starting_location, partition_descriptor.partition_length);
+                    locations.push((partition_descriptor, first_block));
+                } else {
+                    warn!(
+                        "Could not find matching partition descriptor for partition map: {:?}",
+                        partition_map
+                    );
+                }
+            }
+            // read through the Type 1 map of the partition it is recorded in
+            PartitionMap::Type2(partition_map) if partition_map.metadata().is_some() || partition_map.is_virtual() => {}
+            _ => {
+                log::warn!("Ignoring other partition type");
+            }
+        }
+    }
+    locations
+}
+
+/// Calls visit_file with the reader, '/'-separated path, ICB and file characteristics of every
+/// file below the directory
+pub fn run_on_directory<B, F>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    icb_address: &LongAd,
+    path: &mut Vec<String>,
+    walk: &mut DirectoryWalk,
+    visit_file: &mut F,
+) -> Result<()>
+where
+    B: BlockDevice,
+    F: FnMut(&mut B, &str, &LongAd, u8) -> Result<()>,
+{
+    walk.enter(&path.join("/"), icb_address, path.len())
+        .inspect_err(|e| error!("Stopping the walk of the directory tree: {}", e))?;
+    let partition_descriptor = partition_descriptors
+        .get(&(icb_address.extent_location.partition_reference_number | 0))
+        .ok_or_else(|| {
+            error!(
+                "Could not find partition descriptor for directory ICB: {}",
+                &(icb_address.extent_location.partition_reference_number | 0)
+            );
+            UdfError::InvalidPartitionNumber
+        })?;
+    let file_entries = read_file_entries(
+        reader,
+        logical_volume_descriptor,
+        partition_descriptor,
+        &ShortAllocationDescriptor {
+            extent_length_and_type: icb_address.extent_length_and_type,
+            extent_location: icb_address.extent_location.logical_block_number,
+        },
+    )?;
+    let mut file_identifier_descriptors = read_directory_contents(
+        reader,
+        logical_volume_descriptor,
+        partition_descriptors,
+        icb_address.extent_location.partition_reference_number,
+        &file_entries,
+    )
+    .inspect_err(|e| error!("Could not read the directory {:?}: {}", path.join("/"), e))?;
+    // in order of name rather than the order they were mastered in, so that listings, manifests
+    // and copies are the same for discs with the same files
+    file_identifier_descriptors.sort_by_cached_key(|fid| fid.file_identifier.to_string());
+    for file_identifier_descriptor in file_identifier_descriptors.iter() {
+        if file_identifier_descriptor.file_characteristics
+            & FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT
+            != 0
+        {
+            // don't infinite loop up to parent directory
+            continue;
+        }
+        path.push(file_identifier_descriptor.file_identifier.to_string());
+        let path_string = path.join("/");
+        if file_identifier_descriptor.file_characteristics
+            & FileIdentifierDescriptor::FILE_CHARACTERISTIC_DIRECTORY
+            != 0
+        {
+            if !walk.filter.may_include_below(&path_string) {
+                debug!("run_on_directory: skipping subdirectory {:?}", path_string);
+                path.pop();
+                continue;
+            }
+            debug!(
+                "run_on_directory: descending into subdirectory {:?}",
+                path_string
+            );
+            let result = run_on_directory(
+                reader,
+                logical_volume_descriptor,
+                partition_descriptors,
+                &file_identifier_descriptor.icb,
+                path,
+                walk,
+                visit_file,
+            );
+            path.pop();
+            result?;
+        } else {
+            path.pop();
+            if !walk.filter.includes(&path_string) {
+                continue;
+            }
+            debug!("run_on_directory: file {:?}", path_string);
+            visit_file(
+                reader,
+                &path_string,
+                &file_identifier_descriptor.icb,
+                file_identifier_descriptor.file_characteristics,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The partition and File Entries of the file at icb_address
+pub fn read_icb_entries<'s, B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &'s BTreeMap<u16, PartitionDescriptor>,
+    icb_address: &LongAd,
+) -> Result<(&'s PartitionDescriptor, Vec<FileEntry>)> {
+    let partition_descriptor = partition_descriptors
+        .get(&(icb_address.extent_location.partition_reference_number | 0))
+        .ok_or(UdfError::InvalidPartitionNumber)?;
+    let file_entries = read_file_entries(
+        reader,
+        logical_volume_descriptor,
+        partition_descriptor,
+        &ShortAllocationDescriptor {
+            extent_length_and_type: icb_address.extent_length_and_type,
+            extent_location: icb_address.extent_location.logical_block_number,
+        },
+    )?;
+    Ok((partition_descriptor, file_entries))
+}
+
+/// The files of the disc in the order of the walk, with the details that list prints
+pub fn list_entries<R: Read + Seek>(
+    parser: &mut UdfParser<R>,
+    structures: &VolumeStructures,
+    max_depth: usize,
+) -> Result<Vec<ListEntry>> {
+    let mut entries = vec![];
+    walk_file_sets(parser, structures, max_depth, &PathFilter::default(), &mut |reader, path, icb_address, _| {
+        let (_, file_entries) =
+            read_icb_entries(reader, &structures.logical_volume, &structures.partition_descriptors, icb_address)?;
+        let mut entry = ListEntry {
+            path: path.to_string(),
+            extents: (file_entries.iter())
+                .map(|file_entry| {
+                    let partition_reference_number = icb_address.extent_location.partition_reference_number;
+                    read_allocation_descriptors(file_entry, partition_reference_number).map(|ads| ads.len())
+                })
+                .sum::<Result<usize>>()?,
+            ..Default::default()
+        };
+        if let Some(file_entry) = file_entries.first() {
+            entry.size = file_entry.information_length;
+            entry.modified = file_entry.modification_time.iso8601();
+            entry.accessed = file_entry.access_time.iso8601();
+            entry.attributes_changed = file_entry.attribute_time.iso8601();
+        }
+        entries.push(entry);
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Write the contents of the file at icb_address to output_file, from the byte at start on
+pub fn write_file_data<B: BlockDevice, W: Write + ?Sized>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    path: &str,
+    icb_address: &LongAd,
+    start: u64,
+    output_file: &mut W,
+) -> Result<()> {
+    let partition_descriptor = partition_descriptors
+        .get(&(icb_address.extent_location.partition_reference_number | 0))
+        .ok_or_else(|| {
+            error!(
+                "Could not find partition descriptor for directory ICB: {}",
+                &(icb_address.extent_location.partition_reference_number | 0)
+            );
+            UdfError::InvalidPartitionNumber
+        })?;
+    let file_entries = read_file_entries(reader, logical_volume_descriptor, partition_descriptor,         &ShortAllocationDescriptor {
+        extent_length_and_type: icb_address.extent_length_and_type,
+        extent_location: icb_address.extent_location.logical_block_number,
+    })?;
+    if file_entries.len() != 1 {
+        warn!(
+            "Expected exactly one file entry for file {:?}, but got {}",
+            path,
+            file_entries.len()
+        );
+    }
+    if let Some(data) = file_entries.first().and_then(embedded_data) {
+        if start > data.len() as u64 {
+            return Err(partial_copy_too_long(path, start - data.len() as u64));
+        }
+        output_file.write_all(&data[start as usize..])?;
+        return Ok(());
+    }
+    // the bytes of the file still to skip, in the extent that holds the byte at start
+    let mut skip = start;
+    let extents = file_data_extents(
+        logical_volume_descriptor,
+        partition_descriptors,
+        icb_address.extent_location.partition_reference_number,
+        &file_entries,
+        path,
+    )?;
+    for &(partition_descriptor, pos_in_partition, length) in &extents {
+        if skip >= length {
+            skip -= length;
+            continue;
+        }
+        debug!("path {}: reading {} bytes at {}", path, length, pos_in_partition);
+        let mut buf: Vec<u8> = vec![0u8; 1024*1024];
+        let mut offset: usize = std::mem::take(&mut skip) as usize;
+        while offset < length as usize {
+            check_interrupted()?;
+            let pos_this_iteration = pos_in_partition + offset as usize;
+            let len_this_iteration = (length as usize - offset).min(buf.len());
+            let slice = &mut buf[..len_this_iteration];
+            read_exact_from_partition(
+                reader,
+                partition_descriptor,
+                pos_this_iteration,
+                slice,
+            )?;
+
+            output_file.write_all(slice)?;
+            offset += len_this_iteration;
+        }
+    }
+    if skip > 0 {
+        return Err(partial_copy_too_long(path, skip));
+    }
+    Ok(())
+}
+
+/// The error of resuming a copy whose partial output is excess bytes longer than the file
+pub fn partial_copy_too_long(path: &str, excess: u64) -> UdfError {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("the partial copy of {} is {} bytes longer than the file", path, excess),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{build_image, FixtureFile, Quirks};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_list_and_read_files() {
+        let files = [
+            FixtureFile {
+                path: "VIDEO_TS/VIDEO_TS.IFO",
+                contents: b"DVDVIDEO-VMG",
+            },
+            FixtureFile {
+                path: "README.TXT",
+                contents: &[b'x'; 3000],
+            },
+        ];
+        let image = build_image(&files, "COPIER", &Quirks::default()).unwrap();
+        let mut parser = UdfParser::new(Cursor::new(image));
+        let structures = parser.read_volume_structures().unwrap();
+        let entries = list_entries(&mut parser, &structures, DirectoryWalk::DEFAULT_MAX_DEPTH).unwrap();
+        let listed: Vec<(&str, u64)> = entries.iter().map(|entry| (entry.path.as_str(), entry.size)).collect();
+        assert_eq!(listed, [("README.TXT", 3000), ("VIDEO_TS/VIDEO_TS.IFO", 12)]);
+
+        // resuming a copy writes only the rest of the file
+        let mut rest = vec![];
+        let (max_depth, filter) = (DirectoryWalk::DEFAULT_MAX_DEPTH, PathFilter::default());
+        walk_file_sets(&mut parser, &structures, max_depth, &filter, &mut |reader, path, icb_address, _| {
+            if path == "VIDEO_TS/VIDEO_TS.IFO" {
+                let (logical_volume, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+                write_file_data(reader, logical_volume, partitions, path, icb_address, 9, &mut rest)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rest, b"VMG");
+    }
+
+    #[test]
+    fn test_walk_without_file_set_descriptor() {
+        use crate::test_support::block_offset;
+        use crate::udf::{DescriptorTag, TerminatingDescriptor};
+        let files = [FixtureFile {
+            path: "VIDEO_TS.IFO",
+            contents: b"DVDVIDEO-VMG",
+        }];
+        let mut image = build_image(&files, "NOFSD", &Quirks::default()).unwrap();
+        // a File Set Descriptor Sequence of only its Terminating Descriptor
+        let fsd = &mut image[block_offset(0)..][..TerminatingDescriptor::size()];
+        fsd.fill(0);
+        fsd[..2].copy_from_slice(&TerminatingDescriptor::TAG_IDENTIFIER.to_le_bytes());
+        DescriptorTag::finalize(fsd, 0);
+        let mut parser = UdfParser::new(Cursor::new(image));
+        let structures = parser.read_volume_structures().unwrap();
+        let (max_depth, filter) = (DirectoryWalk::DEFAULT_MAX_DEPTH, PathFilter::default());
+        let result = walk_file_sets(&mut parser, &structures, max_depth, &filter, &mut |_, _, _, _| Ok(()));
+        assert!(matches!(result, Err(UdfError::InvalidDescriptorTag)));
+    }
+}
@@ -0,0 +1,96 @@
+//! Copying from an image file to a local output without passing the data through
+//! dvdromcopy. On Linux, copy_file_range(2) shares the image's blocks with the copy on file
+//! systems with reflinks (btrfs, XFS), copies server-side on NFS and SMB, and otherwise still
+//! copies within the kernel. Other hosts, and pairs of file systems that do not support it,
+//! report that nothing was copied so that the caller can read and write as usual.
+
+use std::{fs::File, io};
+
+/// Copy len bytes of input at input_offset to the current position of output, advancing it.
+/// Returns false, having copied nothing, if the host or file systems cannot copy this way.
+#[cfg(target_os = "linux")]
+pub fn copy_range(input: &File, input_offset: u64, output: &File, len: u64) -> io::Result<bool> {
+    use std::{
+        ffi::{c_int, c_uint},
+        os::fd::AsRawFd,
+    };
+    extern "C" {
+        fn copy_file_range(
+            fd_in: c_int,
+            off_in: *mut i64,
+            fd_out: c_int,
+            off_out: *mut i64,
+            len: usize,
+            flags: c_uint,
+        ) -> isize;
+    }
+    // the errors with which std::io::copy also falls back to reading and writing: EPERM,
+    // EBADF (e.g. an O_APPEND output), EXDEV, EINVAL, ENOSYS and EOPNOTSUPP
+    const UNSUPPORTED: [i32; 6] = [1, 9, 18, 22, 38, 95];
+    let mut offset = i64::try_from(input_offset).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(isize::MAX as u64) as usize;
+        // SAFETY: both descriptors are open for the call and offset outlives it
+        let copied = unsafe {
+            copy_file_range(input.as_raw_fd(), &mut offset, output.as_raw_fd(), std::ptr::null_mut(), chunk, 0)
+        };
+        if copied < 0 {
+            let err = io::Error::last_os_error();
+            if remaining == len && err.raw_os_error().is_some_and(|errno| UNSUPPORTED.contains(&errno)) {
+                return Ok(false);
+            }
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if copied == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("the image ends {} bytes before the end of the extent", remaining),
+            ));
+        }
+        remaining -= copied as u64;
+    }
+    Ok(true)
+}
+
+/// macOS only clones whole files (clonefile(2)) and Windows only on ReFS, so elsewhere the
+/// caller reads and writes
+#[cfg(not(target_os = "linux"))]
+pub fn copy_range(_input: &File, _input_offset: u64, _output: &File, _len: u64) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn test_copy_range() {
+        let path = std::env::temp_dir().join(format!("dvdromcopy-offload-test-{}", std::process::id()));
+        let image: Vec<u8> = (0..8192u32).map(|i| i as u8).collect();
+        std::fs::write(path.with_extension("iso"), &image).unwrap();
+        let input = File::open(path.with_extension("iso")).unwrap();
+        let mut output = File::options().read(true).write(true).create_new(true).open(&path).unwrap();
+        output.write_all(b"VOB").unwrap();
+        if copy_range(&input, 2048, &output, 4096).unwrap() {
+            assert!(copy_range(&input, 8000, &output, 192).unwrap());
+            let mut copied = vec![];
+            output.seek(SeekFrom::Start(0)).unwrap();
+            output.read_to_end(&mut copied).unwrap();
+            assert_eq!(&copied[..3], b"VOB");
+            assert_eq!(&copied[3..4099], &image[2048..6144]);
+            assert_eq!(&copied[4099..], &image[8000..]);
+            // past the end of the image
+            let err = copy_range(&input, 8000, &output, 1000).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        } else {
+            assert_eq!(output.metadata().unwrap().len(), 3);
+        }
+        std::fs::remove_file(path.with_extension("iso")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -25,6 +25,20 @@ pub fn cksum(data: &[u8]) -> u16 {
     UDF_CRC.checksum(data)
 }
 
+// CRC-64 of the Windows DVD ID (IDvdInfo2::GetDiscID), as reverse-engineered by dvdid
+// http://dvdid.cjkey.org.uk/ ; the polynomial is usually given reflected as 0x92c64265d32139a4
+const DVD_ID_CRC_ALGO: Algorithm<u64> = Algorithm {
+    width: 64,
+    poly: 0x259c84cba6426349,
+    init: 0xffff_ffff_ffff_ffff,
+    refin: true,
+    refout: true,
+    xorout: 0x0000,
+    check: 0x75d4b74f024eceea,
+    residue: 0x0000,
+};
+
+pub const DVD_ID_CRC: Crc<u64> = Crc::<u64>::new(&DVD_ID_CRC_ALGO);
 
 #[cfg(test)]
 mod tests {
@@ -37,4 +51,9 @@ mod tests {
         let crc = cksum(&[0x70, 0x6a, 0x77]);
         assert_eq!(crc, 0x3299);
     }
+
+    #[test]
+    fn test_dvd_id_crc() {
+        assert_eq!(DVD_ID_CRC.checksum(b"123456789"), DVD_ID_CRC_ALGO.check);
+    }
 }
@@ -0,0 +1,351 @@
+//! Where the disc is read from for --device: a drive or image file that libdvdcss opens
+//! itself, an image of raw CD sectors, a directory of files such as VIDEO_TS, or (with the
+//! http feature) an image at an http:// or https:// URL. The UDF file system can also start
+//! further into an image, such as in a partition of a whole-disk dump.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    time::SystemTime,
+};
+
+use log::{info, warn};
+
+use crate::{
+    dvdcss_sys::{css_to_io_error, DvdCss, ReadSeek, DVDCSS_BLOCK_SIZE},
+    http_image::is_http_url,
+    udf_parser::UdfParser,
+    udf_writer::{ImageOptions, ImageReader, SourceFile, UdfRevision},
+};
+
+/// The size of the raw sectors of a CD image (e.g. a .bin), each with 2048 bytes of data
+pub const RAW_CD_SECTOR_SIZE: u64 = 2352;
+/// The sync pattern at the start of each raw CD sector
+const RAW_CD_SYNC: [u8; 12] = [0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0];
+
+/// What --device names, which decides how it is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// a drive, or whatever else libdvdcss opens
+    Drive,
+    /// an image of 2048-byte sectors, e.g. a .iso
+    Image,
+    /// an image of raw 2352-byte CD sectors
+    RawCdImage,
+    /// a directory of files, read as the UDF image that make-iso would write of it
+    Directory,
+    /// an image at an http:// or https:// URL
+    Url,
+}
+
+/// Tell what device is: an image of raw CD sectors by the sync pattern of sector 16, where
+/// the volume recognition sequence starts
+pub fn detect_device(device: &str) -> io::Result<DeviceKind> {
+    if is_http_url(device) {
+        return Ok(DeviceKind::Url);
+    }
+    // e.g. D: on Windows, which is the current directory of the drive to the file system
+    let Ok(metadata) = fs::metadata(device) else {
+        return Ok(DeviceKind::Drive);
+    };
+    if metadata.is_dir() && !is_drive_letter(device) {
+        return Ok(DeviceKind::Directory);
+    }
+    if !metadata.is_file() {
+        return Ok(DeviceKind::Drive);
+    }
+    if metadata.len() % RAW_CD_SECTOR_SIZE == 0 && metadata.len() > 17 * RAW_CD_SECTOR_SIZE {
+        let mut file = File::open(device)?;
+        file.seek(SeekFrom::Start(16 * RAW_CD_SECTOR_SIZE))?;
+        let mut sync = [0u8; RAW_CD_SYNC.len()];
+        file.read_exact(&mut sync)?;
+        if sync == RAW_CD_SYNC {
+            return Ok(DeviceKind::RawCdImage);
+        }
+    }
+    Ok(DeviceKind::Image)
+}
+
+fn is_drive_letter(device: &str) -> bool {
+    cfg!(windows) && device.len() == 2 && device.ends_with(':')
+}
+
+pub fn open_device(device: &str) -> io::Result<DvdCss> {
+    match detect_device(device)? {
+        DeviceKind::Url => DvdCss::open_reader(open_http_image(device)?).map_err(css_to_io_error),
+        // CDs are not scrambled, and libdvdcss only seeks to whole 2048-byte sectors
+        DeviceKind::RawCdImage => DvdCss::open_plain(device).map_err(css_to_io_error),
+        DeviceKind::Directory => open_directory(Path::new(device)),
+        DeviceKind::Image => {
+            let size = fs::metadata(device)?.len();
+            if size % DVDCSS_BLOCK_SIZE as u64 != 0 {
+                warn!(
+                    "{} ends with {} bytes that are not a whole sector, which are not read",
+                    device,
+                    size % DVDCSS_BLOCK_SIZE as u64
+                );
+            }
+            DvdCss::open(device).map_err(css_to_io_error)
+        }
+        DeviceKind::Drive => DvdCss::open(device).map_err(css_to_io_error),
+    }
+}
+
+/// Like open_device, with the UDF file system starting offset bytes into the device or image
+pub fn open_device_at(device: &str, offset: u64) -> io::Result<DvdCss> {
+    if offset == 0 {
+        return open_device(device);
+    }
+    let image = OffsetReader::new(open_image(device)?, offset);
+    if detect_device(device)? == DeviceKind::RawCdImage {
+        return Ok(DvdCss::open_plain_reader(Box::new(image)));
+    }
+    DvdCss::open_reader(Box::new(image)).map_err(css_to_io_error)
+}
+
+/// A parser of css, which open_device_at opened from device, for the size of its sectors
+pub fn device_parser(device: &str, css: DvdCss) -> UdfParser<DvdCss> {
+    match detect_device(device) {
+        Ok(DeviceKind::RawCdImage) => UdfParser::new_raw_cd(css),
+        _ => UdfParser::new(css),
+    }
+}
+
+/// Open the UDF image of the files in dir, which reads their data from them. A directory named
+/// VIDEO_TS is recorded as the VIDEO_TS directory of the image.
+fn open_directory(dir: &Path) -> io::Result<DvdCss> {
+    let dir = dir.canonicalize()?;
+    let name = |path: &Path| path.file_name().map(|name| name.to_string_lossy().to_uppercase());
+    let is_video_ts = name(&dir).as_deref() == Some("VIDEO_TS");
+    let volume_dir = if is_video_ts { dir.parent().unwrap_or(&dir) } else { &dir };
+    let options = ImageOptions {
+        volume_identifier: name(volume_dir).unwrap_or_else(|| "DVD".to_string()),
+        revision: UdfRevision::Udf102,
+        recording_time: SystemTime::now(),
+        iso9660: false,
+    };
+    let (image, summary) = if is_video_ts {
+        let mut files = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                files.push((format!("VIDEO_TS/{}", entry.file_name().to_string_lossy()), metadata));
+            }
+        }
+        let sources: Vec<SourceFile> = (files.iter())
+            .map(|(path, metadata)| SourceFile {
+                path,
+                length: metadata.len(),
+                modified: metadata.modified().unwrap_or(options.recording_time),
+            })
+            .collect();
+        ImageReader::of_files(&sources, &options, |path| dir.join(path.trim_start_matches("VIDEO_TS/")))?
+    } else {
+        ImageReader::new(&dir, &options)?
+    };
+    info!("Reading the {} files of {:?} as a UDF image of {} sectors", summary.files, dir, summary.sectors);
+    Ok(DvdCss::open_plain_reader(Box::new(image)))
+}
+
+/// The bytes of the device or image as they are, not through libdvdcss, e.g. to read its
+/// partition table
+pub fn open_image(device: &str) -> io::Result<Box<dyn ReadSeek>> {
+    if is_http_url(device) {
+        return open_http_image(device);
+    }
+    Ok(Box::new(File::open(device)?))
+}
+
+/// The size in bytes of the device or image, or of the 2048-byte sectors of the data of an
+/// image of raw CD sectors
+pub fn device_size(device: &str) -> io::Result<u64> {
+    if is_http_url(device) {
+        return http_image_size(device);
+    }
+    let mut file = File::open(device)?;
+    let mut size = file.seek(SeekFrom::End(0))?;
+    // seeking to the end of macOS's /dev/rdisk* and some block devices gives 0
+    if size == 0 && !file.metadata()?.is_file() {
+        size = disk_size(&file)?;
+    }
+    if detect_device(device)? == DeviceKind::RawCdImage {
+        return Ok(size / RAW_CD_SECTOR_SIZE * DVDCSS_BLOCK_SIZE as u64);
+    }
+    Ok(size)
+}
+
+/// The size in bytes of a block device, from the BLKGETSIZE64 ioctl
+#[cfg(target_os = "linux")]
+fn disk_size(file: &File) -> io::Result<u64> {
+    use std::{
+        ffi::{c_int, c_ulong},
+        os::unix::io::AsRawFd,
+    };
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+    /// _IOR(0x12, 114, size_t) of linux/fs.h
+    const BLKGETSIZE64: c_ulong = 0x8008_1272;
+    let mut size: u64 = 0;
+    // SAFETY: BLKGETSIZE64 writes a u64 to size
+    if unsafe { ioctl(file.as_raw_fd(), BLKGETSIZE64, &mut size as *mut u64) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(size)
+}
+
+/// The size in bytes of a disk, from the DKIOCGETBLOCKSIZE and DKIOCGETBLOCKCOUNT ioctls
+#[cfg(target_os = "macos")]
+fn disk_size(file: &File) -> io::Result<u64> {
+    use std::{
+        ffi::{c_int, c_ulong},
+        os::unix::io::AsRawFd,
+    };
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+    /// _IOR('d', 24, uint32_t) and _IOR('d', 25, uint64_t) of sys/disk.h
+    const DKIOCGETBLOCKSIZE: c_ulong = 0x4004_6418;
+    const DKIOCGETBLOCKCOUNT: c_ulong = 0x4008_6419;
+    let (mut block_size, mut block_count): (u32, u64) = (0, 0);
+    // SAFETY: the ioctls write a u32 to block_size and a u64 to block_count
+    unsafe {
+        if ioctl(file.as_raw_fd(), DKIOCGETBLOCKSIZE, &mut block_size as *mut u32) < 0
+            || ioctl(file.as_raw_fd(), DKIOCGETBLOCKCOUNT, &mut block_count as *mut u64) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(block_size as u64 * block_count)
+}
+
+/// Other hosts cannot tell
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn disk_size(_file: &File) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "telling the size of a device needs Linux or macOS",
+    ))
+}
+
+/// The bytes of inner from offset on, as if they were a whole image
+pub struct OffsetReader<R> {
+    inner: R,
+    offset: u64,
+}
+impl<R: Seek> OffsetReader<R> {
+    pub fn new(inner: R, offset: u64) -> Self {
+        Self { inner, offset }
+    }
+}
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+impl<R: Seek> Seek for OffsetReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => SeekFrom::Start(self.offset.checked_add(pos).ok_or(io::ErrorKind::InvalidInput)?),
+            pos => pos,
+        };
+        let inner_pos = self.inner.seek(pos)?;
+        inner_pos.checked_sub(self.offset).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to before the start of the file system")
+        })
+    }
+}
+
+#[cfg(feature = "http")]
+fn open_http_image(url: &str) -> io::Result<Box<dyn ReadSeek>> {
+    Ok(Box::new(crate::http_image::HttpImage::open(url)?))
+}
+
+#[cfg(feature = "http")]
+fn http_image_size(url: &str) -> io::Result<u64> {
+    Ok(crate::http_image::HttpImage::open(url)?.len())
+}
+
+#[cfg(not(feature = "http"))]
+fn open_http_image(url: &str) -> io::Result<Box<dyn ReadSeek>> {
+    Err(io::Error::other(format!(
+        "reading {} needs dvdromcopy built with --features http",
+        url
+    )))
+}
+
+#[cfg(not(feature = "http"))]
+fn http_image_size(url: &str) -> io::Result<u64> {
+    open_http_image(url).map(|_| 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        copier::DvdCopier,
+        test_support::{build_image, FixtureFile, Quirks},
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_detect_device() {
+        let files = [FixtureFile {
+            path: "VIDEO_TS/VIDEO_TS.IFO",
+            contents: b"DVDVIDEO-VMG",
+        }];
+        let image = build_image(&files, "VIDEO_TS", &Quirks::default()).unwrap();
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-device-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("VIDEO_TS")).unwrap();
+        // an image that ends with part of a sector
+        let mut partial = image.clone();
+        partial.extend([0; 100]);
+        fs::write(dir.join("disc.iso"), &partial).unwrap();
+        let raw: Vec<u8> = (image.chunks(DVDCSS_BLOCK_SIZE))
+            .flat_map(|sector| {
+                let mut raw = RAW_CD_SYNC.to_vec();
+                // the address and mode 1
+                raw.extend([0, 2, 0, 1]);
+                raw.extend(sector);
+                raw.resize(RAW_CD_SECTOR_SIZE as usize, 0);
+                raw
+            })
+            .collect();
+        fs::write(dir.join("disc.bin"), &raw).unwrap();
+        fs::write(dir.join("VIDEO_TS/VIDEO_TS.IFO"), b"DVDVIDEO-VMG").unwrap();
+
+        // the image of a VIDEO_TS directory is named after the directory it is in
+        let dir_name = dir.file_name().unwrap().to_string_lossy().to_uppercase();
+        let devices = [
+            ("disc.iso", DeviceKind::Image, "VIDEO_TS"),
+            ("disc.bin", DeviceKind::RawCdImage, "VIDEO_TS"),
+            ("VIDEO_TS", DeviceKind::Directory, dir_name.as_str()),
+        ];
+        for (name, kind, identifier) in devices {
+            let device = dir.join(name).to_string_lossy().into_owned();
+            assert_eq!(detect_device(&device).unwrap(), kind, "{}", name);
+            let mut copier = DvdCopier::open(&device).unwrap();
+            assert_eq!(copier.volume_info().identifier, identifier, "{}", name);
+            let mut contents = vec![];
+            copier.open_file("VIDEO_TS/VIDEO_TS.IFO").unwrap().read_to_end(&mut contents).unwrap();
+            assert_eq!(contents, b"DVDVIDEO-VMG", "{}", name);
+        }
+        assert_eq!(device_size(&dir.join("disc.bin").to_string_lossy()).unwrap(), image.len() as u64);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_offset_reader() {
+        let image: Vec<u8> = (0..=255).collect();
+        let mut reader = OffsetReader::new(Cursor::new(image), 200);
+        assert_eq!(reader.seek(SeekFrom::Start(10)).unwrap(), 10);
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [210, 211, 212, 213]);
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 56);
+        assert_eq!(reader.seek(SeekFrom::Current(-6)).unwrap(), 50);
+        assert!(reader.seek(SeekFrom::Current(-51)).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! A DFXML (Digital Forensics XML) report of the files copied from a disc, which
+//! forensic tools read natively: each file's size, timestamps, SHA-256 and the byte
+//! runs that map its contents to offsets on the disc.
+//! https://github.com/dfxml-working-group/dfxml_schema
+
+use std::io::{self, Write};
+
+/// A contiguous part of a file on the disc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRun {
+    pub file_offset: u64,
+    /// byte offset from the start of the disc
+    pub img_offset: u64,
+    pub len: u64,
+}
+
+/// A `<fileobject>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileObject {
+    /// '/'-separated path on the disc
+    pub filename: String,
+    pub filesize: u64,
+    /// ISO 8601 times
+    pub mtime: Option<String>,
+    pub atime: Option<String>,
+    /// UDF's attribute time, the closest to ctime
+    pub ctime: Option<String>,
+    pub byte_runs: Vec<ByteRun>,
+    /// lowercase hex SHA-256, as in the manifest
+    pub sha256: Option<String>,
+}
+
+/// The report of one copied volume
+#[derive(Debug, Clone, Default)]
+pub struct Dfxml {
+    /// the device or image file the disc was read from
+    pub image_filename: String,
+    pub volume_name: String,
+    pub files: Vec<FileObject>,
+}
+impl Dfxml {
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<dfxml xmlns="http://www.forensicswiki.org/wiki/Category:Digital_Forensics_XML" xmlns:dc="http://purl.org/dc/elements/1.1/" version="1.0">"#
+        )?;
+        writeln!(writer, "  <metadata><dc:type>File list</dc:type></metadata>")?;
+        writeln!(
+            writer,
+            "  <creator><program>{}</program><version>{}</version></creator>",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(
+            writer,
+            "  <source><image_filename>{}</image_filename><sectorsize>2048</sectorsize></source>",
+            escape_xml(&self.image_filename)
+        )?;
+        writeln!(writer, r#"  <volume offset="0">"#)?;
+        writeln!(writer, "    <ftype_str>udf</ftype_str>")?;
+        writeln!(writer, "    <block_size>2048</block_size>")?;
+        writeln!(writer, "    <volume_name>{}</volume_name>", escape_xml(&self.volume_name))?;
+        for file in &self.files {
+            write_file_object(&mut writer, file)?;
+        }
+        writeln!(writer, "  </volume>")?;
+        writeln!(writer, "</dfxml>")
+    }
+}
+
+fn write_file_object<W: Write>(writer: &mut W, file: &FileObject) -> io::Result<()> {
+    writeln!(writer, "    <fileobject>")?;
+    writeln!(writer, "      <filename>{}</filename>", escape_xml(&file.filename))?;
+    writeln!(writer, "      <filesize>{}</filesize>", file.filesize)?;
+    writeln!(writer, "      <name_type>r</name_type>")?;
+    for (name, time) in [("mtime", &file.mtime), ("atime", &file.atime), ("ctime", &file.ctime)] {
+        if let Some(time) = time {
+            writeln!(writer, "      <{}>{}</{}>", name, time, name)?;
+        }
+    }
+    writeln!(writer, "      <byte_runs>")?;
+    for run in &file.byte_runs {
+        writeln!(
+            writer,
+            r#"        <byte_run file_offset="{}" img_offset="{}" len="{}"/>"#,
+            run.file_offset, run.img_offset, run.len
+        )?;
+    }
+    writeln!(writer, "      </byte_runs>")?;
+    if let Some(sha256) = &file.sha256 {
+        writeln!(writer, r#"      <hashdigest type="sha256">{}</hashdigest>"#, sha256)?;
+    }
+    writeln!(writer, "    </fileobject>")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let dfxml = Dfxml {
+            image_filename: "/dev/rdisk4".to_string(),
+            volume_name: "R&D".to_string(),
+            files: vec![FileObject {
+                filename: "VIDEO_TS/VIDEO_TS.IFO".to_string(),
+                filesize: 4096,
+                mtime: Some("2003-04-11T18:25:00+01:00".to_string()),
+                byte_runs: vec![ByteRun {
+                    file_offset: 0,
+                    img_offset: 0x60000,
+                    len: 4096,
+                }],
+                sha256: Some("ab12".to_string()),
+                ..Default::default()
+            }],
+        };
+        let mut xml = Vec::new();
+        dfxml.write(&mut xml).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains("<volume_name>R&amp;D</volume_name>"));
+        assert!(xml.contains(
+            "    <fileobject>\n      <filename>VIDEO_TS/VIDEO_TS.IFO</filename>\n      <filesize>4096</filesize>\n"
+        ));
+        assert!(xml.contains("<mtime>2003-04-11T18:25:00+01:00</mtime>\n      <byte_runs>"));
+        assert!(xml.contains(r#"<byte_run file_offset="0" img_offset="393216" len="4096"/>"#));
+        assert!(xml.contains(r#"<hashdigest type="sha256">ab12</hashdigest>"#));
+        assert!(xml.ends_with("  </volume>\n</dfxml>\n"));
+    }
+}
@@ -0,0 +1,239 @@
+//! `--format iso`: a decrypted image of the whole disc. Every sector is read through
+//! libdvdcss, which descrambles the sectors of each VOB with the title key found at its start,
+//! so the image plays without CSS. A read that fails is retried, then read again sector by
+//! sector, and the sectors that still cannot be read are written as zeros and reported. A
+//! later pass can read just those again into the image.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use log::{debug, warn};
+
+use crate::{dvdcss_sys::DVDCSS_BLOCK_SIZE, interrupt::check_interrupted, sparse_image::SectorRanges};
+
+/// 1 MiB, as files are copied
+const SECTORS_PER_READ: u32 = 512;
+
+/// What to copy into an image
+#[derive(Debug, Clone)]
+pub struct ImageCopy<'a> {
+    /// the bytes of the image already written by an interrupted copy, which are not read again
+    pub start: u64,
+    pub total_sectors: u32,
+    /// the sectors where each VOB starts, whose title key decrypts the sectors up to the next
+    pub key_sectors: &'a [u32],
+    /// how many more times a failed read is tried
+    pub retries: u32,
+    /// sectors that still cannot be read are written as zeros rather than failing the copy
+    pub skip: bool,
+}
+
+/// Write the sectors of reader from copy.start to copy.total_sectors to output, loading the
+/// title key at each of copy.key_sectors first. Returns the sectors that could not be read.
+pub fn copy_image<R: Read + Seek, W: Write + ?Sized>(
+    reader: &mut R,
+    output: &mut W,
+    copy: &ImageCopy,
+    mut load_key: impl FnMut(&mut R, u32) -> io::Result<()>,
+) -> io::Result<SectorRanges> {
+    let sector = (copy.start / DVDCSS_BLOCK_SIZE as u64) as u32;
+    // the part of the first sector that was already written
+    let skip = (copy.start % DVDCSS_BLOCK_SIZE as u64) as usize;
+    let mut unreadable = SectorRanges::new();
+    copy_sectors(reader, output, sector..copy.total_sectors, skip, copy, &mut load_key, &mut unreadable)?;
+    Ok(unreadable)
+}
+
+/// Read the sectors of ranges again into the image output that an earlier copy_image wrote,
+/// e.g. those of a map file that could not be read. Returns those that still cannot be read.
+pub fn retry_image<R: Read + Seek, W: Write + Seek + ?Sized>(
+    reader: &mut R,
+    output: &mut W,
+    ranges: &SectorRanges,
+    copy: &ImageCopy,
+    mut load_key: impl FnMut(&mut R, u32) -> io::Result<()>,
+) -> io::Result<SectorRanges> {
+    let mut unreadable = SectorRanges::new();
+    for range in ranges.ranges() {
+        let end = range.end.min(copy.total_sectors);
+        if range.start >= end {
+            continue;
+        }
+        output.seek(SeekFrom::Start(range.start as u64 * DVDCSS_BLOCK_SIZE as u64))?;
+        copy_sectors(reader, output, range.start..end, 0, copy, &mut load_key, &mut unreadable)?;
+    }
+    Ok(unreadable)
+}
+
+/// Write the sectors of reader in sectors to output, leaving out the first skip bytes, and
+/// add those that could not be read to unreadable
+fn copy_sectors<R: Read + Seek, W: Write + ?Sized>(
+    reader: &mut R,
+    output: &mut W,
+    sectors: std::ops::Range<u32>,
+    mut skip: usize,
+    copy: &ImageCopy,
+    load_key: &mut impl FnMut(&mut R, u32) -> io::Result<()>,
+    unreadable: &mut SectorRanges,
+) -> io::Result<()> {
+    let mut key_sectors = copy.key_sectors.to_vec();
+    key_sectors.sort_unstable();
+    let mut sector = sectors.start;
+    // a resumed or retried copy can start inside a VOB
+    if let Some(&key_sector) = key_sectors.iter().rev().find(|&&key_sector| key_sector < sector) {
+        try_load_key(reader, key_sector, load_key);
+    }
+    let mut buf = vec![0u8; SECTORS_PER_READ as usize * DVDCSS_BLOCK_SIZE];
+    while sector < sectors.end {
+        check_interrupted()?;
+        if key_sectors.binary_search(&sector).is_ok() {
+            try_load_key(reader, sector, load_key);
+        }
+        // a read ends where the next key starts
+        let next_key = key_sectors[key_sectors.partition_point(|&key_sector| key_sector <= sector)..]
+            .first()
+            .copied()
+            .unwrap_or(u32::MAX);
+        let count = (sectors.end - sector).min(SECTORS_PER_READ).min(next_key - sector);
+        let slice = &mut buf[..count as usize * DVDCSS_BLOCK_SIZE];
+        if let Err(e) = read_sectors(reader, sector, slice, copy.retries) {
+            debug!("copy_image: sectors {}..{}: {}; reading them one at a time", sector, sector + count, e);
+            for (i, sector_buf) in slice.chunks_exact_mut(DVDCSS_BLOCK_SIZE).enumerate() {
+                let bad_sector = sector + i as u32;
+                if let Err(e) = read_sectors(reader, bad_sector, sector_buf, copy.retries) {
+                    if !copy.skip {
+                        return Err(io::Error::new(e.kind(), format!("could not read sector {}: {}", bad_sector, e)));
+                    }
+                    warn!("Could not read sector {}; writing zeros: {}", bad_sector, e);
+                    sector_buf.fill(0);
+                    unreadable.insert(bad_sector, bad_sector + 1);
+                }
+            }
+        }
+        output.write_all(&slice[std::mem::take(&mut skip)..])?;
+        sector += count;
+    }
+    Ok(())
+}
+
+fn try_load_key<R>(reader: &mut R, sector: u32, load_key: &mut impl FnMut(&mut R, u32) -> io::Result<()>) {
+    if let Err(e) = load_key(reader, sector) {
+        warn!("Could not get the title key at sector {}; its VOB stays scrambled: {}", sector, e);
+    }
+}
+
+/// Read the sectors from sector into buf, trying retries more times if a read fails
+fn read_sectors<R: Read + Seek>(reader: &mut R, sector: u32, buf: &mut [u8], retries: u32) -> io::Result<()> {
+    let mut tries = 0;
+    loop {
+        let read = reader
+            .seek(SeekFrom::Start(sector as u64 * DVDCSS_BLOCK_SIZE as u64))
+            .and_then(|_| reader.read_exact(buf));
+        match read {
+            Err(e) if tries < retries && e.kind() != io::ErrorKind::Interrupted => {
+                tries += 1;
+                debug!("read_sectors: sector {}: {}; try {} of {}", sector, e, tries + 1, retries + 1);
+            }
+            read => return read,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sparse_image::SectorRange;
+    use std::io::Cursor;
+
+    /// An image whose sectors are filled with their number, where reading bad_sector fails
+    /// and the sector at flaky fails the first time
+    struct FlakyDisc {
+        image: Cursor<Vec<u8>>,
+        bad_sector: u32,
+        flaky: Option<u32>,
+    }
+    impl Read for FlakyDisc {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let start = (self.image.position() / DVDCSS_BLOCK_SIZE as u64) as u32;
+            let end = start + buf.len().div_ceil(DVDCSS_BLOCK_SIZE) as u32;
+            if (start..end).contains(&self.bad_sector) || self.flaky.is_some_and(|flaky| (start..end).contains(&flaky)) {
+                self.flaky = None;
+                return Err(io::Error::other("medium error"));
+            }
+            self.image.read(buf)
+        }
+    }
+    impl Seek for FlakyDisc {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.image.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_copy_image() {
+        let sectors = 600;
+        let image: Vec<u8> = (0..sectors).flat_map(|sector| [sector as u8; DVDCSS_BLOCK_SIZE]).collect();
+        let mut disc = FlakyDisc {
+            image: Cursor::new(image.clone()),
+            bad_sector: 300,
+            flaky: Some(550),
+        };
+        let mut output = vec![];
+        let mut loaded_keys = vec![];
+        let copy = ImageCopy {
+            start: 0,
+            total_sectors: sectors,
+            key_sectors: &[520, 10],
+            retries: 1,
+            skip: true,
+        };
+        let unreadable = copy_image(&mut disc, &mut output, &copy, |_, sector| {
+            loaded_keys.push(sector);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(unreadable.ranges(), &[SectorRange { start: 300, end: 301 }]);
+        assert_eq!(loaded_keys, vec![10, 520]);
+        assert_eq!(output.len(), image.len());
+        assert!(output[300 * DVDCSS_BLOCK_SIZE..301 * DVDCSS_BLOCK_SIZE].iter().all(|&b| b == 0));
+        assert_eq!(output[..300 * DVDCSS_BLOCK_SIZE], image[..300 * DVDCSS_BLOCK_SIZE]);
+        assert_eq!(output[301 * DVDCSS_BLOCK_SIZE..], image[301 * DVDCSS_BLOCK_SIZE..]);
+
+        // resuming inside a sector of the VOB that starts at 520
+        let start = 530 * DVDCSS_BLOCK_SIZE as u64 + 100;
+        let mut output = vec![];
+        let mut loaded_keys = vec![];
+        let copy = ImageCopy { start, ..copy };
+        copy_image(&mut disc, &mut output, &copy, |_, sector| {
+            loaded_keys.push(sector);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(loaded_keys, vec![520]);
+        assert_eq!(output, image[start as usize..]);
+
+        // a second pass over the sectors that could not be read, which now can except 300
+        disc.flaky = Some(301);
+        let mut ranges = SectorRanges::new();
+        ranges.insert(299, 302);
+        let mut retried = image.clone();
+        for sector in 299..302 {
+            retried[sector * DVDCSS_BLOCK_SIZE..(sector + 1) * DVDCSS_BLOCK_SIZE].fill(0);
+        }
+        let mut loaded_keys = vec![];
+        let mut retried_output = Cursor::new(retried);
+        let still_unreadable = retry_image(&mut disc, &mut retried_output, &ranges, &copy, |_, sector| {
+            loaded_keys.push(sector);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(still_unreadable.ranges(), &[SectorRange { start: 300, end: 301 }]);
+        assert_eq!(loaded_keys, vec![10]);
+        let retried = retried_output.into_inner();
+        assert_eq!(retried[..300 * DVDCSS_BLOCK_SIZE], image[..300 * DVDCSS_BLOCK_SIZE]);
+        assert_eq!(retried[301 * DVDCSS_BLOCK_SIZE..], image[301 * DVDCSS_BLOCK_SIZE..]);
+
+        // without skip, a sector that cannot be read stops the copy
+        let copy = ImageCopy { start: 0, skip: false, ..copy };
+        assert!(copy_image(&mut disc, &mut vec![], &copy, |_, _| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! The region playback control (RPC) state of a DVD drive: the region it is set to and how
+//! many more times that can be changed. RPC phase II drives do not read the scrambled sectors
+//! of discs of other regions. It is read with the MMC REPORT KEY command (key format 08h)
+//! through the DVD ioctl of Linux and macOS.
+
+use serde::Serialize;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DriveRegion {
+    /// the region (1-8) that the drive is set to, if it is set to one
+    pub region: Option<u8>,
+    /// "none" if the region was never set, "set", "last chance" (one change left) or
+    /// "permanent" (no changes left)
+    pub state: &'static str,
+    pub user_changes_left: u8,
+    pub vendor_resets_left: u8,
+    /// RPC phase II drives enforce the region themselves rather than leaving it to the player
+    pub rpc_phase_2: bool,
+}
+impl DriveRegion {
+    /// From the fields of the RPC state. A region is allowed where its bit of region_mask is
+    /// clear, and a drive that is set allows exactly one.
+    pub fn from_rpc_state(type_code: u8, vendor_resets: u8, user_changes: u8, region_mask: u8, rpc_scheme: u8) -> Self {
+        let allowed: Vec<u8> = (1..=8).filter(|region| region_mask & (1 << (region - 1)) == 0).collect();
+        Self {
+            region: match allowed[..] {
+                [region] => Some(region),
+                _ => None,
+            },
+            state: match type_code & 0x03 {
+                0 => "none",
+                1 => "set",
+                2 => "last chance",
+                _ => "permanent",
+            },
+            user_changes_left: user_changes,
+            vendor_resets_left: vendor_resets,
+            rpc_phase_2: rpc_scheme == 1,
+        }
+    }
+
+    /// From the bytes of the REPORT KEY response that follow its 4-byte header
+    pub fn parse(bytes: [u8; 3]) -> Self {
+        Self::from_rpc_state(bytes[0] >> 6, (bytes[0] >> 3) & 0x07, bytes[0] & 0x07, bytes[1], bytes[2])
+    }
+}
+
+/// The RPC state of the drive at device, e.g. /dev/sr0
+#[cfg(target_os = "linux")]
+pub fn read_drive_region(device: &str) -> io::Result<DriveRegion> {
+    use std::{
+        ffi::{c_int, c_ulong},
+        fs::OpenOptions,
+        os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    };
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+    /// linux/cdrom.h
+    const DVD_AUTH: c_ulong = 0x5392;
+    const DVD_LU_SEND_RPC_STATE: u8 = 10;
+    const O_NONBLOCK: c_int = 0o4000;
+    let file = OpenOptions::new().read(true).custom_flags(O_NONBLOCK).open(device)?;
+    // dvd_authinfo, a union of 16 bytes whose first byte is the request type
+    let mut authinfo = [0u8; 32];
+    authinfo[0] = DVD_LU_SEND_RPC_STATE;
+    // SAFETY: authinfo is larger than dvd_authinfo and outlives the call
+    if unsafe { ioctl(file.as_raw_fd(), DVD_AUTH, authinfo.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // dvd_lu_send_rpcstate packs type:2, vra:3 and ucca:3 as bit fields in its first byte
+    let byte = authinfo[0];
+    let (type_code, vendor_resets, user_changes) = if cfg!(target_endian = "little") {
+        (byte & 0x03, (byte >> 2) & 0x07, byte >> 5)
+    } else {
+        (byte >> 6, (byte >> 3) & 0x07, byte & 0x07)
+    };
+    Ok(DriveRegion::from_rpc_state(type_code, vendor_resets, user_changes, authinfo[1], authinfo[2]))
+}
+
+/// The RPC state of the drive at device, e.g. /dev/rdisk4
+#[cfg(target_os = "macos")]
+pub fn read_drive_region(device: &str) -> io::Result<DriveRegion> {
+    use std::{
+        ffi::{c_int, c_ulong, c_void},
+        os::fd::AsRawFd,
+    };
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+    /// dk_dvd_report_key_t of IOKit/storage/IODVDMediaBSDClient.h
+    #[repr(C)]
+    struct DvdReportKey {
+        format: u8,
+        key_class: u8,
+        reserved0: [u8; 2],
+        address: u32,
+        grant_id: u8,
+        reserved1: [u8; 3],
+        buffer_length: u16,
+        reserved2: [u8; 2],
+        buffer: *mut c_void,
+    }
+    /// _IOW('d', 129, dk_dvd_report_key_t)
+    const DKIOCDVDREPORTKEY: c_ulong = 0x8018_6481;
+    const KDVD_KEY_FORMAT_REGION_STATE: u8 = 0x08;
+    let file = std::fs::File::open(device)?;
+    // DVDRegionPlaybackControlInfo: the REPORT KEY response with its header
+    let mut response = [0u8; 8];
+    let mut request = DvdReportKey {
+        format: KDVD_KEY_FORMAT_REGION_STATE,
+        key_class: 0,
+        reserved0: [0; 2],
+        address: 0,
+        grant_id: 0,
+        reserved1: [0; 3],
+        buffer_length: response.len() as u16,
+        reserved2: [0; 2],
+        buffer: response.as_mut_ptr().cast(),
+    };
+    // SAFETY: request and the response it points to outlive the call
+    if unsafe { ioctl(file.as_raw_fd(), DKIOCDVDREPORTKEY, &mut request as *mut DvdReportKey) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(DriveRegion::parse([response[4], response[5], response[6]]))
+}
+
+/// Other hosts have no DVD ioctl that this reads
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn read_drive_region(_device: &str) -> io::Result<DriveRegion> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading the drive region needs Linux or macOS",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // set to region 2 with 4 user changes and 4 vendor resets left, RPC-II
+        let region = DriveRegion::parse([0b01_100_100, !0x02, 1]);
+        assert_eq!(region.region, Some(2));
+        assert_eq!(region.state, "set");
+        assert_eq!((region.user_changes_left, region.vendor_resets_left), (4, 4));
+        assert!(region.rpc_phase_2);
+        // never set: every region is allowed
+        let region = DriveRegion::parse([0b00_100_101, 0, 0]);
+        assert_eq!((region.region, region.state, region.user_changes_left), (None, "none", 5));
+        assert!(!region.rpc_phase_2);
+    }
+
+    #[test]
+    fn test_read_drive_region_of_a_file() {
+        let path = std::env::temp_dir().join(format!("dvdromcopy-region-test-{}", std::process::id()));
+        std::fs::write(&path, [0u8; 2048]).unwrap();
+        assert!(read_drive_region(path.to_str().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
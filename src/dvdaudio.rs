@@ -0,0 +1,221 @@
+//! DVD-Audio navigation data (the .IFO files in AUDIO_TS).
+//! Like DVD-Video, the specification is not public; offsets follow dvda-author's
+//! amg.c and ats.c. Only the audio titles and their tracks are parsed.
+
+use std::{collections::BTreeMap, io};
+
+use crate::dvdvideo::{bytes_at, invalid_data, u16_at, u32_at, u8_at, IFO_SECTOR_SIZE};
+
+pub const AUDIO_MANAGER_IFO_PATH: &str = "AUDIO_TS/AUDIO_TS.IFO";
+
+/// Path of the IFO of audio title set number title_set (1-99)
+pub fn audio_title_set_ifo_path(title_set: u8) -> String {
+    format!("AUDIO_TS/ATS_{:02}_0.IFO", title_set)
+}
+
+/// The audio title set number if path is the IFO of an audio title set
+pub fn parse_audio_title_set_ifo_path(path: &str) -> Option<u8> {
+    let number = path.strip_prefix("AUDIO_TS/ATS_")?.strip_suffix("_0.IFO")?;
+    if number.len() != 2 {
+        return None;
+    }
+    number.parse().ok().filter(|&n| n > 0)
+}
+
+/// Convert a 90 kHz presentation time to milliseconds
+pub fn pts_to_millis(pts: u32) -> u64 {
+    pts as u64 / 90
+}
+
+/// Audio Manager Information (AMGI) from AUDIO_TS.IFO
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioManagerInfo {
+    pub version: u16,
+    pub number_of_volumes: u16,
+    pub volume_number: u16,
+    pub number_of_title_sets: u16,
+}
+impl AudioManagerInfo {
+    pub const IDENTIFIER: &'static [u8; 12] = b"DVDAUDIO-AMG";
+
+    pub fn parse(ifo: &[u8]) -> io::Result<Self> {
+        if bytes_at(ifo, 0, 12)? != Self::IDENTIFIER {
+            return Err(invalid_data("not an audio manager IFO (missing DVDAUDIO-AMG)".to_string()));
+        }
+        Ok(Self {
+            version: u16_at(ifo, 0x20)?,
+            number_of_volumes: u16_at(ifo, 0x26)?,
+            volume_number: u16_at(ifo, 0x28)?,
+            number_of_title_sets: u16_at(ifo, 0x3E)?,
+        })
+    }
+}
+
+/// A track of an audio title
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioTrack {
+    pub track_number: u8,
+    /// start of the track in 90 kHz units from the start of the title
+    pub first_pts: u32,
+    /// length of the track in 90 kHz units
+    pub length_pts: u32,
+    /// sectors of the track relative to the start of ATS_nn_1.AOB (inclusive)
+    pub first_sector: u32,
+    pub last_sector: u32,
+}
+impl AudioTrack {
+    pub const SIZE: usize = 20;
+    pub const SECTOR_POINTER_SIZE: usize = 12;
+}
+
+/// An audio title (album) of an audio title set
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioTitle {
+    pub length_pts: u32,
+    pub tracks: Vec<AudioTrack>,
+}
+
+/// Audio Title Set Information (ATSI) from ATS_nn_0.IFO
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioTitleSetInfo {
+    pub version: u16,
+    pub titles: Vec<AudioTitle>,
+}
+impl AudioTitleSetInfo {
+    pub const IDENTIFIER: &'static [u8; 12] = b"DVDAUDIO-ATS";
+
+    pub fn parse(ifo: &[u8]) -> io::Result<Self> {
+        if bytes_at(ifo, 0, 12)? != Self::IDENTIFIER {
+            return Err(invalid_data("not an audio title set IFO (missing DVDAUDIO-ATS)".to_string()));
+        }
+        // the audio title program chain table is always in the second sector
+        let table = IFO_SECTOR_SIZE;
+        let number_of_titles = u16_at(ifo, table)? as usize;
+        let mut titles = Vec::with_capacity(number_of_titles);
+        for i in 0..number_of_titles {
+            let title = table + u32_at(ifo, table + 8 + i * 8 + 4)? as usize;
+            let number_of_tracks = u8_at(ifo, title + 2)? as usize;
+            let tracks_offset = title + u16_at(ifo, title + 0x0A)? as usize;
+            let sector_pointers_offset = title + u16_at(ifo, title + 0x0C)? as usize;
+            let mut tracks = Vec::with_capacity(number_of_tracks);
+            for j in 0..number_of_tracks {
+                let track = tracks_offset + j * AudioTrack::SIZE;
+                let sector_pointer = sector_pointers_offset + j * AudioTrack::SECTOR_POINTER_SIZE;
+                tracks.push(AudioTrack {
+                    track_number: u8_at(ifo, track + 2)?,
+                    first_pts: u32_at(ifo, track + 4)?,
+                    length_pts: u32_at(ifo, track + 8)?,
+                    first_sector: u32_at(ifo, sector_pointer + 4)?,
+                    last_sector: u32_at(ifo, sector_pointer + 8)?,
+                });
+            }
+            titles.push(AudioTitle {
+                length_pts: u32_at(ifo, title + 4)?,
+                tracks,
+            });
+        }
+        Ok(Self {
+            version: u16_at(ifo, 0x20)?,
+            titles,
+        })
+    }
+}
+
+/// All the navigation data of a DVD-Audio disc
+#[derive(Debug, Clone, Default)]
+pub struct DvdAudio {
+    pub audio_manager: Option<AudioManagerInfo>,
+    /// keyed by audio title set number
+    pub title_sets: BTreeMap<u8, AudioTitleSetInfo>,
+}
+
+/// Check that a copied AUDIO_TS file starts the way its name says it should:
+/// IFOs and their BUP backups with their identifier, and AOBs with an MPEG-2 pack.
+/// Returns a description of the problem, or None if the file looks right or is not checked.
+pub fn check_file_header(path: &str, header: &[u8]) -> Option<String> {
+    let name = path.strip_prefix("AUDIO_TS/")?;
+    let (expected, description): (&[u8], &str) = if name == "AUDIO_TS.IFO" || name == "AUDIO_TS.BUP" {
+        (AudioManagerInfo::IDENTIFIER, "the DVDAUDIO-AMG identifier")
+    } else if name.starts_with("ATS_") && (name.ends_with("_0.IFO") || name.ends_with("_0.BUP")) {
+        (AudioTitleSetInfo::IDENTIFIER, "the DVDAUDIO-ATS identifier")
+    } else if name.starts_with("ATS_") && name.ends_with(".AOB") {
+        (&[0, 0, 1, 0xBA], "an MPEG-2 pack header")
+    } else {
+        return None;
+    };
+    if header.starts_with(expected) {
+        None
+    } else {
+        Some(format!(
+            "expected it to start with {} but it starts with {:02X?}",
+            description,
+            &header[..header.len().min(expected.len())]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+    fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// ATS_01_0.IFO with one title of the given track lengths in seconds, 100 sectors each
+    fn audio_title_set_ifo(track_seconds: &[u32]) -> Vec<u8> {
+        let mut ifo = vec![0u8; 2 * IFO_SECTOR_SIZE];
+        ifo[..12].copy_from_slice(AudioTitleSetInfo::IDENTIFIER);
+        let table = IFO_SECTOR_SIZE;
+        put_u16(&mut ifo, table, 1);
+        ifo[table + 8] = 0x81;
+        put_u32(&mut ifo, table + 12, 16);
+        let title = table + 16;
+        let tracks = track_seconds.len();
+        ifo[title + 2] = tracks as u8;
+        ifo[title + 3] = tracks as u8;
+        put_u32(&mut ifo, title + 4, track_seconds.iter().sum::<u32>() * 90_000);
+        put_u16(&mut ifo, title + 0x0A, 0x10);
+        put_u16(&mut ifo, title + 0x0C, (0x10 + tracks * AudioTrack::SIZE) as u16);
+        let mut start = 0;
+        for (i, &seconds) in track_seconds.iter().enumerate() {
+            let track = title + 0x10 + i * AudioTrack::SIZE;
+            ifo[track + 2] = i as u8 + 1;
+            put_u32(&mut ifo, track + 4, start * 90_000);
+            put_u32(&mut ifo, track + 8, seconds * 90_000);
+            start += seconds;
+            let pointer = title + 0x10 + tracks * AudioTrack::SIZE + i * AudioTrack::SECTOR_POINTER_SIZE;
+            put_u32(&mut ifo, pointer, 0x0100_0000);
+            put_u32(&mut ifo, pointer + 4, i as u32 * 100);
+            put_u32(&mut ifo, pointer + 8, i as u32 * 100 + 99);
+        }
+        ifo
+    }
+
+    #[test]
+    fn test_parse_audio_title_set() {
+        let info = AudioTitleSetInfo::parse(&audio_title_set_ifo(&[180, 240])).unwrap();
+        assert_eq!(info.titles.len(), 1);
+        assert_eq!(pts_to_millis(info.titles[0].length_pts), 420_000);
+        let track = info.titles[0].tracks[1];
+        assert_eq!(track.track_number, 2);
+        assert_eq!(pts_to_millis(track.first_pts), 180_000);
+        assert_eq!((track.first_sector, track.last_sector), (100, 199));
+        assert!(AudioManagerInfo::parse(&audio_title_set_ifo(&[1])).is_err());
+    }
+
+    #[test]
+    fn test_check_file_header() {
+        let ifo = audio_title_set_ifo(&[1]);
+        assert_eq!(check_file_header("AUDIO_TS/ATS_01_0.IFO", &ifo), None);
+        assert_eq!(check_file_header("AUDIO_TS/ATS_01_0.BUP", &ifo), None);
+        assert!(check_file_header("AUDIO_TS/AUDIO_TS.IFO", &ifo).is_some());
+        assert!(check_file_header("AUDIO_TS/ATS_01_1.AOB", &[0u8; 16]).is_some());
+        assert_eq!(check_file_header("AUDIO_TS/ATS_01_1.AOB", &[0, 0, 1, 0xBA, 0x44]), None);
+        assert_eq!(check_file_header("VIDEO_TS/VTS_01_1.VOB", &[]), None);
+        assert_eq!(parse_audio_title_set_ifo_path(&audio_title_set_ifo_path(3)), Some(3));
+    }
+}
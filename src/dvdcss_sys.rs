@@ -1,6 +1,14 @@
-use std::{ffi::{c_char, c_int, c_void, CStr}, io::{Read, Seek}};
+//! The disc, read through libdvdcss, which decrypts the blocks of scrambled DVDs, or without
+//! the dvdcss feature (or when opened with open_plain) read as it is, which is all that an
+//! image or a disc that is not scrambled needs.
+
+#[cfg(feature = "dvdcss")]
+use std::ffi::{c_char, c_int, CStr};
+use std::{ffi::c_void, io::{Read, Seek, SeekFrom}};
 
 use log::debug;
+#[cfg(feature = "dvdcss")]
+use log::warn;
 
 #[repr(C)]
 pub struct DvdCssS {
@@ -47,6 +55,7 @@ pub const DVDCSS_SEEK_MPEG: i32 = 1 << 0;
 /// Flag to ask dvdcss_seek() to check the current title key.
 pub const DVDCSS_SEEK_KEY: i32 = 1 << 1;
 
+#[cfg(all(feature = "dvdcss", not(feature = "dlopen")))]
 #[link(name = "dvdcss")]
 extern "C" {
     /// Opens a DVD device or file.
@@ -83,8 +92,231 @@ extern "C" {
     /// Checks if the DVD is scrambled.
     pub fn dvdcss_is_scrambled(dvdcss: DvdCssT) -> c_int;
 }
+
+#[cfg(feature = "dlopen")]
+pub use dlopen::*;
+
+/// With the dlopen feature, the functions of libdvdcss are found in the library when the
+/// program runs, so that it runs where libdvdcss is not installed. They may only be called
+/// once library() has loaded it, and are as safe as the extern functions they stand in for.
+#[cfg(feature = "dlopen")]
+#[allow(clippy::missing_safety_doc)]
+mod dlopen {
+    use std::sync::OnceLock;
+
+    use log::debug;
+
+    use super::{c_char, c_int, c_void, DvdCssStreamCb, DvdCssT};
+
+    /// The names that libdvdcss 1.4 is installed as, most specific first
+    #[cfg(target_os = "windows")]
+    const LIBRARY_NAMES: &[&str] = &["libdvdcss-2.dll", "libdvdcss.dll"];
+    #[cfg(target_os = "macos")]
+    const LIBRARY_NAMES: &[&str] = &[
+        "libdvdcss.2.dylib",
+        "libdvdcss.dylib",
+        "/opt/homebrew/lib/libdvdcss.2.dylib",
+        "/usr/local/lib/libdvdcss.2.dylib",
+        "/opt/local/lib/libdvdcss.2.dylib",
+    ];
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    const LIBRARY_NAMES: &[&str] = &["libdvdcss.so.2", "libdvdcss.so"];
+
+    pub struct Library {
+        open: unsafe extern "C" fn(*const c_char) -> DvdCssT,
+        open_stream: unsafe extern "C" fn(*mut c_void, *mut DvdCssStreamCb) -> DvdCssT,
+        close: unsafe extern "C" fn(DvdCssT) -> c_int,
+        seek: unsafe extern "C" fn(DvdCssT, c_int, c_int) -> c_int,
+        read: unsafe extern "C" fn(DvdCssT, *mut c_void, c_int, c_int) -> c_int,
+        readv: unsafe extern "C" fn(DvdCssT, *mut c_void, c_int, c_int) -> c_int,
+        error: unsafe extern "C" fn(DvdCssT) -> *const c_char,
+        is_scrambled: unsafe extern "C" fn(DvdCssT) -> c_int,
+        /// kept loaded for as long as the program runs, as the functions are in it
+        _library: libloading::Library,
+    }
+
+    impl Library {
+        unsafe fn new(library: libloading::Library) -> Result<Self, libloading::Error> {
+            Ok(Library {
+                open: *library.get(b"dvdcss_open\0")?,
+                open_stream: *library.get(b"dvdcss_open_stream\0")?,
+                close: *library.get(b"dvdcss_close\0")?,
+                seek: *library.get(b"dvdcss_seek\0")?,
+                read: *library.get(b"dvdcss_read\0")?,
+                readv: *library.get(b"dvdcss_readv\0")?,
+                error: *library.get(b"dvdcss_error\0")?,
+                is_scrambled: *library.get(b"dvdcss_is_scrambled\0")?,
+                _library: library,
+            })
+        }
+    }
+
+    fn load() -> Result<Library, String> {
+        let mut errors = vec![];
+        for name in LIBRARY_NAMES {
+            match unsafe { libloading::Library::new(name) } {
+                Ok(library) => {
+                    debug!("Loaded {}", name);
+                    return unsafe { Library::new(library) }.map_err(|e| format!("{}: {}", name, e));
+                }
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+        Err(errors.join("; "))
+    }
+
+    /// libdvdcss, loaded the first time it is asked for
+    pub fn library() -> Result<&'static Library, &'static str> {
+        static LIBRARY: OnceLock<Result<Library, String>> = OnceLock::new();
+        LIBRARY.get_or_init(load).as_ref().map_err(|e| e.as_str())
+    }
+
+    fn loaded() -> &'static Library {
+        library().expect("libdvdcss is loaded before its functions are called")
+    }
+
+    /// Opens a DVD device or file.
+    pub unsafe fn dvdcss_open(psz_target: *const c_char) -> DvdCssT {
+        (loaded().open)(psz_target)
+    }
+
+    /// Opens a DVD device or file using custom stream callbacks.
+    pub unsafe fn dvdcss_open_stream(p_stream: *mut c_void, p_stream_cb: *mut DvdCssStreamCb) -> DvdCssT {
+        (loaded().open_stream)(p_stream, p_stream_cb)
+    }
+
+    /// Closes a DVD device or file.
+    pub unsafe fn dvdcss_close(dvdcss: DvdCssT) -> c_int {
+        (loaded().close)(dvdcss)
+    }
+
+    /// Seeks to a specific block on the DVD.
+    pub unsafe fn dvdcss_seek(dvdcss: DvdCssT, i_blocks: c_int, i_flags: c_int) -> c_int {
+        (loaded().seek)(dvdcss, i_blocks, i_flags)
+    }
+
+    /// Reads data from the DVD.
+    pub unsafe fn dvdcss_read(dvdcss: DvdCssT, p_buffer: *mut c_void, i_blocks: c_int, i_flags: c_int) -> c_int {
+        (loaded().read)(dvdcss, p_buffer, i_blocks, i_flags)
+    }
+
+    /// Reads data from the DVD using vectored I/O.
+    pub unsafe fn dvdcss_readv(dvdcss: DvdCssT, p_iovec: *mut c_void, i_blocks: c_int, i_flags: c_int) -> c_int {
+        (loaded().readv)(dvdcss, p_iovec, i_blocks, i_flags)
+    }
+
+    /// Returns the last error message.
+    pub unsafe fn dvdcss_error(dvdcss: DvdCssT) -> *const c_char {
+        (loaded().error)(dvdcss)
+    }
+
+    /// Checks if the DVD is scrambled.
+    pub unsafe fn dvdcss_is_scrambled(dvdcss: DvdCssT) -> c_int {
+        (loaded().is_scrambled)(dvdcss)
+    }
+}
 pub struct DvdCss {
-    handle: DvdCssT,
+    backend: Backend,
+    /// the sorted sectors where the VOBs start, from set_key_sectors
+    key_sectors: Vec<u32>,
+    /// the block that the next read starts at
+    #[cfg_attr(not(feature = "dvdcss"), allow(dead_code))]
+    block: u32,
+    /// the key sector that libdvdcss last loaded the title key of
+    #[cfg_attr(not(feature = "dvdcss"), allow(dead_code))]
+    loaded_key: Option<u32>,
+}
+
+enum Backend {
+    #[cfg(feature = "dvdcss")]
+    Css {
+        handle: DvdCssT,
+        /// what libdvdcss reads through when opened with open_reader; dropped after the handle
+        stream: Option<Box<Stream>>,
+    },
+    /// an image or drive read as it is, without decrypting
+    Plain(Box<dyn ReadSeek>),
+}
+
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// The p_stream of dvdcss_open_stream. libdvdcss keeps pointers to it and to callbacks.
+#[cfg(feature = "dvdcss")]
+struct Stream {
+    reader: Box<dyn ReadSeek>,
+    callbacks: DvdCssStreamCb,
+}
+
+#[cfg(feature = "dvdcss")]
+extern "C" fn stream_seek(p_stream: *mut c_void, i_pos: u64) -> i32 {
+    let stream = unsafe { &mut *(p_stream as *mut Stream) };
+    match stream.reader.seek(SeekFrom::Start(i_pos)) {
+        Ok(_) => 0,
+        Err(e) => {
+            warn!("seek to {}: {}", i_pos, e);
+            -1
+        }
+    }
+}
+
+#[cfg(feature = "dvdcss")]
+extern "C" fn stream_read(p_stream: *mut c_void, buffer: *mut c_void, i_read: i32) -> i32 {
+    let stream = unsafe { &mut *(p_stream as *mut Stream) };
+    let buf = unsafe { std::slice::from_raw_parts_mut(buffer as *mut u8, i_read.max(0) as usize) };
+    read_fully(&mut *stream.reader, buf).map_or(-1, |read| read as i32)
+}
+
+/// The struct iovec of the buffers that pf_readv reads into
+#[cfg(feature = "dvdcss")]
+#[repr(C)]
+struct Iovec {
+    iov_base: *mut c_void,
+    iov_len: usize,
+}
+
+/// Reads into the i_blocks buffers of p_iovec in turn, as readv(2) does
+#[cfg(feature = "dvdcss")]
+extern "C" fn stream_readv(p_stream: *mut c_void, p_iovec: *const c_void, i_blocks: i32) -> i32 {
+    let stream = unsafe { &mut *(p_stream as *mut Stream) };
+    let iovecs = unsafe { std::slice::from_raw_parts(p_iovec as *const Iovec, i_blocks.max(0) as usize) };
+    let mut total = 0;
+    for iovec in iovecs {
+        let buf = unsafe { std::slice::from_raw_parts_mut(iovec.iov_base as *mut u8, iovec.iov_len) };
+        match read_fully(&mut *stream.reader, buf) {
+            None if total == 0 => return -1,
+            None => break,
+            Some(read) => {
+                total += read;
+                if read < buf.len() {
+                    break;
+                }
+            }
+        }
+    }
+    total as i32
+}
+
+/// Reads reader into buf until it is full or reader ends, returning how much was read, or
+/// None if reading failed before anything was read
+#[cfg(feature = "dvdcss")]
+fn read_fully(reader: &mut dyn ReadSeek, buf: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                warn!("read: {}", e);
+                if read == 0 {
+                    return None;
+                }
+                break;
+            }
+        }
+    }
+    Some(read)
 }
 pub fn css_to_io_error(css_error: String) -> std::io::Error {
     std::io::Error::new(std::io::ErrorKind::Other, css_error)
@@ -92,102 +324,276 @@ pub fn css_to_io_error(css_error: String) -> std::io::Error {
 
 impl Read for DvdCss {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match self.css_read(buf, buf.len().div_ceil(DVDCSS_BLOCK_SIZE as usize) as i32, DVDCSS_READ_DECRYPT) {
-            Ok(size) => Ok(size as usize * DVDCSS_BLOCK_SIZE),
-            Err(e) => Err(css_to_io_error(e)),
+        match &mut self.backend {
+            #[cfg(feature = "dvdcss")]
+            Backend::Css { .. } => {
+                let mut blocks = buf.len().div_ceil(DVDCSS_BLOCK_SIZE as usize) as i32;
+                if !self.key_sectors.is_empty() {
+                    if let Some(next_blocks) = self.load_key().map_err(css_to_io_error)? {
+                        blocks = blocks.min(next_blocks as i32);
+                    }
+                }
+                match self.css_read(buf, blocks, DVDCSS_READ_DECRYPT) {
+                    Ok(size) => Ok(size as usize * DVDCSS_BLOCK_SIZE),
+                    Err(e) => Err(css_to_io_error(e)),
+                }
+            }
+            Backend::Plain(reader) => reader.read(buf),
         }
     }
 }
 impl Seek for DvdCss {
     fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
-        let blocks = match pos {
-            std::io::SeekFrom::Start(offset) => (offset / DVDCSS_BLOCK_SIZE as u64) as i32,
-            std::io::SeekFrom::End(_offset) => panic!("SeekFrom::End is not supported"),
-            std::io::SeekFrom::Current(_offset) => panic!("SeekFrom::Current is not supported"),
-        };
-        self.css_seek(blocks, DVDCSS_NOFLAGS).map(|x| x as u64).map_err(css_to_io_error)
+        match &mut self.backend {
+            #[cfg(feature = "dvdcss")]
+            Backend::Css { .. } => {
+                let blocks = match pos {
+                    std::io::SeekFrom::Start(offset) => (offset / DVDCSS_BLOCK_SIZE as u64) as i32,
+                    std::io::SeekFrom::End(_offset) => panic!("SeekFrom::End is not supported"),
+                    std::io::SeekFrom::Current(_offset) => panic!("SeekFrom::Current is not supported"),
+                };
+                self.css_seek(blocks, DVDCSS_NOFLAGS).map(|x| x as u64).map_err(css_to_io_error)
+            }
+            Backend::Plain(reader) => reader.seek(pos),
+        }
     }
 }
 
 impl DvdCss {
     /// Opens a DVD device or file.
+    #[cfg(feature = "dvdcss")]
     pub fn open(target: &str) -> Result<Self, String> {
+        #[cfg(feature = "dlopen")]
+        if let Err(e) = library() {
+            warn!("Could not load libdvdcss ({}); reading {} without decrypting it", e, target);
+            return Self::open_plain(target);
+        }
         let c_target = std::ffi::CString::new(target).map_err(|e| e.to_string())?;
         debug!("dvdcss_open({})",target);
         let handle = unsafe { dvdcss_open(c_target.as_ptr()) };
         if handle.is_null() {
             Err("Failed to open DVD device or file".to_string())
         } else {
-            Ok(DvdCss { handle })
+            Ok(Self::with_backend(Backend::Css { handle, stream: None }))
+        }
+    }
+
+    /// Opens a DVD device or file, which is read as it is without libdvdcss.
+    #[cfg(not(feature = "dvdcss"))]
+    pub fn open(target: &str) -> Result<Self, String> {
+        Self::open_plain(target)
+    }
+
+    fn with_backend(backend: Backend) -> Self {
+        DvdCss {
+            backend,
+            key_sectors: vec![],
+            block: 0,
+            loaded_key: None,
+        }
+    }
+
+    /// Sets the sectors where the VOBs of the disc start. Reads then first seek with
+    /// DVDCSS_SEEK_KEY to the start of the VOB that they are in and stop at the start of the
+    /// next one, so that each VOB is decrypted with its own title key. A disc read as it is
+    /// has no title keys, so they are ignored.
+    pub fn set_key_sectors(&mut self, mut key_sectors: Vec<u32>) {
+        key_sectors.sort_unstable();
+        key_sectors.dedup();
+        self.key_sectors = key_sectors;
+    }
+
+    /// Loads the title key of the VOB that the next read starts in, if it is not loaded,
+    /// and returns how many blocks can be read before the next VOB starts
+    #[cfg(feature = "dvdcss")]
+    fn load_key(&mut self) -> Result<Option<u32>, String> {
+        let next = self.key_sectors.partition_point(|&sector| sector <= self.block);
+        let next_blocks = self.key_sectors.get(next).map(|&sector| sector - self.block);
+        let Some(&key_sector) = next.checked_sub(1).and_then(|i| self.key_sectors.get(i)) else {
+            return Ok(next_blocks);
+        };
+        if self.loaded_key != Some(key_sector) {
+            let block = self.block;
+            debug!("Loading the title key at sector {} to read sector {}", key_sector, block);
+            if let Err(e) = self.css_seek(key_sector as i32, DVDCSS_SEEK_KEY) {
+                warn!("Could not get the title key at sector {}: {}", key_sector, e);
+                self.loaded_key = Some(key_sector);
+            }
+            if self.block != block {
+                self.css_seek(block as i32, DVDCSS_NOFLAGS)?;
+            }
         }
+        Ok(next_blocks)
+    }
+
+    /// Opens a DVD device or file that is read as it is, without decrypting it, e.g. an image
+    /// or a disc that is not scrambled.
+    pub fn open_plain(target: &str) -> Result<Self, String> {
+        debug!("open_plain({})", target);
+        let file = std::fs::File::open(target).map_err(|e| format!("Failed to open {}: {}", target, e))?;
+        Ok(Self::open_plain_reader(Box::new(file)))
+    }
+
+    /// Opens a disc image that is read through reader as it is, without decrypting it.
+    pub fn open_plain_reader(reader: Box<dyn ReadSeek>) -> Self {
+        Self::with_backend(Backend::Plain(reader))
+    }
+
+    /// Opens a disc image that is read through reader, e.g. a network stream, decrypting it
+    /// with libdvdcss. The reader is kept until the DvdCss is dropped.
+    pub fn open_stream_from<R: Read + Seek + Send + 'static>(reader: R) -> Result<Self, String> {
+        Self::open_reader(Box::new(reader))
+    }
+
+    /// Opens a disc image that is read through reader, e.g. over the network.
+    #[cfg(feature = "dvdcss")]
+    pub fn open_reader(reader: Box<dyn ReadSeek>) -> Result<Self, String> {
+        #[cfg(feature = "dlopen")]
+        if let Err(e) = library() {
+            warn!("Could not load libdvdcss ({}); reading the image without decrypting it", e);
+            return Ok(Self::open_plain_reader(reader));
+        }
+        let stream = Box::into_raw(Box::new(Stream {
+            reader,
+            callbacks: DvdCssStreamCb {
+                pf_seek: Some(stream_seek),
+                pf_read: Some(stream_read),
+                pf_readv: Some(stream_readv),
+            },
+        }));
+        let result = Self::open_stream(stream as *mut c_void, unsafe { &mut (*stream).callbacks });
+        let stream = unsafe { Box::from_raw(stream) };
+        let mut css = result?;
+        if let Backend::Css { stream: css_stream, .. } = &mut css.backend {
+            *css_stream = Some(stream);
+        }
+        Ok(css)
+    }
+
+    /// Opens a disc image that is read through reader, e.g. over the network, as it is.
+    #[cfg(not(feature = "dvdcss"))]
+    pub fn open_reader(reader: Box<dyn ReadSeek>) -> Result<Self, String> {
+        Ok(Self::open_plain_reader(reader))
     }
 
     /// Opens a DVD device or file using custom stream callbacks.
+    #[cfg(feature = "dvdcss")]
     pub fn open_stream(
         stream: *mut std::ffi::c_void,
         stream_cb: &mut DvdCssStreamCb,
     ) -> Result<Self, String> {
         debug!("dvdcss_open_stream()");
+        #[cfg(feature = "dlopen")]
+        library().map_err(|e| format!("Could not load libdvdcss: {}", e))?;
         let handle = unsafe { dvdcss_open_stream(stream, stream_cb) };
         if handle.is_null() {
             Err("Failed to open DVD device or file with custom stream".to_string())
         } else {
-            Ok(DvdCss { handle })
+            Ok(Self::with_backend(Backend::Css { handle, stream: None }))
         }
     }
 
-    /// Seeks to a specific block on the DVD.
-    pub fn css_seek(&self, blocks: i32, flags: i32) -> Result<i32, String> {
+    /// Seeks to a specific block on the DVD. A disc read as it is has no title keys, so flags
+    /// are ignored.
+    pub fn css_seek(&mut self, blocks: i32, flags: i32) -> Result<i32, String> {
         // debug!("dvdcss_seek({}, {})", blocks, flags);
-        let result = unsafe { dvdcss_seek(self.handle, blocks, flags) };
-        if result < 0 {
-            Err(self.error())
-        } else {
-            Ok(result)
+        match &mut self.backend {
+            #[cfg(feature = "dvdcss")]
+            Backend::Css { handle, .. } => {
+                let result = unsafe { dvdcss_seek(*handle, blocks, flags) };
+                if result < 0 {
+                    Err(self.error())
+                } else {
+                    self.block = result as u32;
+                    if flags & DVDCSS_SEEK_KEY != 0 {
+                        self.loaded_key = Some(result as u32);
+                    }
+                    Ok(result)
+                }
+            }
+            Backend::Plain(reader) => {
+                let _ = flags;
+                let pos = u64::try_from(blocks).map_err(|_| format!("Cannot seek to block {}", blocks))?;
+                reader.seek(SeekFrom::Start(pos * DVDCSS_BLOCK_SIZE as u64)).map_err(|e| e.to_string())?;
+                Ok(blocks)
+            }
         }
     }
 
     /// Reads data from the DVD.
-    pub fn css_read(&self, buffer: &mut [u8], blocks: i32, flags: i32) -> Result<i32, String> {
+    pub fn css_read(&mut self, buffer: &mut [u8], blocks: i32, flags: i32) -> Result<i32, String> {
         // debug!("dvdcss_read(buf with length {}, {}, {})", buffer.len(), blocks, flags);
         assert!(buffer.len() >= (blocks as usize) * DVDCSS_BLOCK_SIZE as usize);
-        let result = unsafe {
-            dvdcss_read(
-                self.handle,
-                buffer.as_mut_ptr() as *mut c_void,
-                blocks,
-                flags,
-            )
-        };
-        if result < 0 {
-            Err(self.error())
-        } else {
-            Ok(result)
+        match &mut self.backend {
+            #[cfg(feature = "dvdcss")]
+            Backend::Css { handle, .. } => {
+                let result = unsafe { dvdcss_read(*handle, buffer.as_mut_ptr() as *mut c_void, blocks, flags) };
+                if result < 0 {
+                    Err(self.error())
+                } else {
+                    self.block += result as u32;
+                    Ok(result)
+                }
+            }
+            Backend::Plain(reader) => {
+                let _ = flags;
+                let buffer = &mut buffer[..blocks.max(0) as usize * DVDCSS_BLOCK_SIZE];
+                let mut read = 0;
+                while read < buffer.len() {
+                    match reader.read(&mut buffer[read..]) {
+                        Ok(0) => break,
+                        Ok(n) => read += n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e.to_string()),
+                    }
+                }
+                Ok((read / DVDCSS_BLOCK_SIZE) as i32)
+            }
         }
     }
 
     /// Reads data from the DVD using vectored I/O.
-    pub fn readv(&self, iovec: *mut c_void, blocks: i32, flags: i32) -> Result<i32, String> {
+    pub fn readv(&mut self, iovec: *mut c_void, blocks: i32, flags: i32) -> Result<i32, String> {
         debug!("dvdcss_readv({}, {})", blocks, flags);
-        let result = unsafe { dvdcss_readv(self.handle, iovec, blocks, flags) };
-        if result < 0 {
-            Err(self.error())
-        } else {
-            Ok(result)
+        match &mut self.backend {
+            #[cfg(feature = "dvdcss")]
+            Backend::Css { handle, .. } => {
+                let result = unsafe { dvdcss_readv(*handle, iovec, blocks, flags) };
+                if result < 0 {
+                    Err(self.error())
+                } else {
+                    self.block += result as u32;
+                    Ok(result)
+                }
+            }
+            Backend::Plain(_) => {
+                let _ = iovec;
+                Err("Vectored reads need libdvdcss".to_string())
+            }
         }
     }
 
-    /// Checks if the DVD is scrambled.
+    /// Checks if the DVD is scrambled. A disc read as it is is taken not to be.
     pub fn is_scrambled(&self) -> bool {
-        debug!("dvdcss_is_scrambled()");
-        unsafe { dvdcss_is_scrambled(self.handle) != 0 }
+        match &self.backend {
+            #[cfg(feature = "dvdcss")]
+            Backend::Css { handle, .. } => {
+                debug!("dvdcss_is_scrambled()");
+                unsafe { dvdcss_is_scrambled(*handle) != 0 }
+            }
+            Backend::Plain(_) => false,
+        }
     }
 
     /// Returns the last error message.
+    #[cfg(feature = "dvdcss")]
     fn error(&self) -> String {
         debug!("dvdcss_error()");
+        let Backend::Css { handle, .. } = &self.backend else {
+            return "Unknown error".to_string();
+        };
         unsafe {
-            let err_ptr = dvdcss_error(self.handle);
+            let err_ptr = dvdcss_error(*handle);
             if err_ptr.is_null() {
                 "Unknown error".to_string()
             } else {
@@ -197,21 +603,76 @@ impl DvdCss {
     }
     /// Closes the DVD device or file.
     pub fn close(self) -> Result<(), String> {
-        debug!("dvdcss_close()");
-        let result = unsafe { dvdcss_close(self.handle) };
-        if result < 0 {
-            Err(self.error())
-        } else {
-            Ok(())
-        }
+        // dropping it closes it
+        drop(self);
+        Ok(())
     }
 }
 
 impl Drop for DvdCss {
     fn drop(&mut self) {
-        debug!("dvdcss_close()");
-        unsafe {
-            dvdcss_close(self.handle);
+        #[cfg(feature = "dvdcss")]
+        if let Backend::Css { handle, .. } = &self.backend {
+            debug!("dvdcss_close()");
+            unsafe {
+                dvdcss_close(*handle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_plain() {
+        let image: Vec<u8> = (0..4u8).flat_map(|sector| [sector; DVDCSS_BLOCK_SIZE]).collect();
+        let mut css = DvdCss::open_plain_reader(Box::new(Cursor::new(image)));
+        assert!(!css.is_scrambled());
+        assert_eq!(css.css_seek(2, DVDCSS_SEEK_KEY), Ok(2));
+        let mut buf = vec![0u8; 3 * DVDCSS_BLOCK_SIZE];
+        // only the blocks to the end of the image
+        assert_eq!(css.css_read(&mut buf, 3, DVDCSS_READ_DECRYPT), Ok(2));
+        assert_eq!(buf[DVDCSS_BLOCK_SIZE], 3);
+        assert_eq!(css.seek(SeekFrom::End(-1)).unwrap(), 4 * DVDCSS_BLOCK_SIZE as u64 - 1);
+        let mut last = vec![];
+        css.read_to_end(&mut last).unwrap();
+        assert_eq!(last, [3]);
+        assert!(DvdCss::open_plain("/nonexistent/disc.iso").is_err());
+    }
+
+    #[test]
+    fn test_open_stream_from() {
+        let image: Vec<u8> = (0..4u8).flat_map(|sector| [sector; DVDCSS_BLOCK_SIZE]).collect();
+        let mut css = DvdCss::open_stream_from(Cursor::new(image)).unwrap();
+        assert_eq!(css.css_seek(1, DVDCSS_NOFLAGS), Ok(1));
+        let mut buf = vec![0u8; 2 * DVDCSS_BLOCK_SIZE];
+        assert_eq!(css.css_read(&mut buf, 2, DVDCSS_NOFLAGS), Ok(2));
+        assert_eq!((buf[0], buf[DVDCSS_BLOCK_SIZE]), (1, 2));
+    }
+
+    /// Title keys are only loaded through libdvdcss
+    #[cfg(feature = "dvdcss")]
+    #[test]
+    fn test_key_sectors() {
+        let image: Vec<u8> = (0..6u8).flat_map(|sector| [sector; DVDCSS_BLOCK_SIZE]).collect();
+        let mut css = DvdCss::open_stream_from(Cursor::new(image)).unwrap();
+        if matches!(css.backend, Backend::Plain(_)) {
+            // with dlopen, when libdvdcss could not be loaded
+            return;
         }
+        css.set_key_sectors(vec![4, 2, 4]);
+        assert_eq!(css.key_sectors, [2, 4]);
+        let mut buf = vec![0u8; 4 * DVDCSS_BLOCK_SIZE];
+        // reads stop where the next VOB starts
+        assert_eq!(css.read(&mut buf).unwrap(), 2 * DVDCSS_BLOCK_SIZE);
+        assert_eq!(css.read(&mut buf).unwrap(), 2 * DVDCSS_BLOCK_SIZE);
+        assert_eq!((buf[0], css.loaded_key), (2, Some(2)));
+        // a read in the middle of a VOB loads the key at its start
+        css.seek(SeekFrom::Start(5 * DVDCSS_BLOCK_SIZE as u64)).unwrap();
+        assert_eq!(css.read(&mut buf).unwrap(), DVDCSS_BLOCK_SIZE);
+        assert_eq!((buf[0], css.loaded_key), (5, Some(4)));
     }
 }
@@ -0,0 +1,105 @@
+//! The "DVD ID" that Windows (IDvdInfo2::GetDiscID) and online metadata databases
+//! use to identify a DVD-Video disc. Format as reverse-engineered by dvdid
+//! (http://dvdid.cjkey.org.uk/): a CRC-64 over the VIDEO_TS file list followed by
+//! the first 64 KiB of VIDEO_TS.IFO and of VTS_01_0.IFO.
+
+use std::fmt;
+
+use crate::crc::DVD_ID_CRC;
+use crate::udf::Timestamp;
+
+/// Only this many bytes of each IFO are included
+pub const IFO_PREFIX_LENGTH: usize = 0x10000;
+
+/// 100 ns intervals between 1601-01-01 (the FILETIME epoch) and 1970-01-01
+const FILETIME_UNIX_EPOCH: i64 = 116_444_736_000_000_000;
+
+/// A Windows FILETIME: 100 ns intervals since 1601-01-01 UTC, or 0 for a time before then
+pub fn filetime(timestamp: &Timestamp) -> u64 {
+    (timestamp.unix_micros() * 10 + FILETIME_UNIX_EPOCH).max(0) as u64
+}
+
+/// A file of the VIDEO_TS directory as Windows reports it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DvdIdFile {
+    pub name: String,
+    /// UDF 1.02 file entries have no creation time; Windows reports the modification time
+    pub creation_time: u64,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DvdId(pub u64);
+impl fmt::Display for DvdId {
+    /// dvdid's format: the high and low 32 bits in hex, separated by |
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}|{:08x}", self.0 >> 32, self.0 & 0xFFFF_FFFF)
+    }
+}
+
+/// Compute the DVD ID from the files of VIDEO_TS (in any order) and the IFO contents
+pub fn dvd_id(files: &[DvdIdFile], video_manager_ifo: &[u8], first_title_set_ifo: Option<&[u8]>) -> DvdId {
+    let mut files: Vec<&DvdIdFile> = files.iter().collect();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut digest = DVD_ID_CRC.digest();
+    for file in files {
+        digest.update(&file.creation_time.to_le_bytes());
+        digest.update(&file.size.to_le_bytes());
+        digest.update(file.name.as_bytes());
+        digest.update(&[0]);
+    }
+    for ifo in [Some(video_manager_ifo), first_title_set_ifo].into_iter().flatten() {
+        digest.update(&ifo[..ifo.len().min(IFO_PREFIX_LENGTH)]);
+    }
+    DvdId(digest.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filetime() {
+        let mut timestamp = Timestamp {
+            type_and_timezone: 0x1000 | (-2047i16 as u16 & 0x0FFF),
+            year: 1970,
+            month: 1,
+            day: 2,
+            ..Default::default()
+        };
+        assert_eq!(filetime(&timestamp), (FILETIME_UNIX_EPOCH + 86400 * 10_000_000) as u64);
+        // 2003-04-11 18:25:00 at UTC+1 is 17:25 UTC
+        timestamp = Timestamp {
+            type_and_timezone: 0x1000 | 60,
+            year: 2003,
+            month: 4,
+            day: 11,
+            hour: 18,
+            minute: 25,
+            ..Default::default()
+        };
+        assert_eq!(timestamp.unix_micros(), 1_050_081_900 * 1_000_000);
+        assert_eq!(timestamp.iso8601(), "2003-04-11T18:25:00+01:00");
+        // corrupt or unset years
+        timestamp.year = u16::MAX;
+        assert_eq!(timestamp.unix_micros(), Timestamp { year: 9999, ..timestamp.clone() }.unix_micros());
+        timestamp.year = 0;
+        assert_eq!(filetime(&timestamp), 0);
+    }
+
+    #[test]
+    fn test_dvd_id() {
+        let file = |name: &str, size| DvdIdFile {
+            name: name.to_string(),
+            creation_time: 0x01C3_0040_0000_0000,
+            size,
+        };
+        let files = [file("VTS_01_0.IFO", 4096), file("VIDEO_TS.IFO", 2048)];
+        let id = dvd_id(&files, &[1; 2048], Some(&[2; 4096]));
+        // the file order does not matter but everything else does
+        assert_eq!(dvd_id(&[files[1].clone(), files[0].clone()], &[1; 2048], Some(&[2; 4096])), id);
+        assert_ne!(dvd_id(&files, &[1; 2048], None), id);
+        assert_ne!(dvd_id(&files[..1], &[1; 2048], Some(&[2; 4096])), id);
+        assert_eq!(DvdId(0x0123_4567_89ab_cdef).to_string(), "01234567|89abcdef");
+    }
+}
@@ -0,0 +1,1069 @@
+//! DVD-Video navigation data (the .IFO files in VIDEO_TS).
+//! The DVD-Video specification is not public; offsets follow libdvdread's ifo_types.h
+//! and http://dvd.sourceforge.net/dvdinfo/ifo.html
+
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, io, ops::RangeInclusive};
+
+/// IFO tables are addressed in 2048-byte sectors relative to the start of the IFO file
+pub const IFO_SECTOR_SIZE: usize = 2048;
+
+pub const VIDEO_MANAGER_IFO_PATH: &str = "VIDEO_TS/VIDEO_TS.IFO";
+
+/// Path of the IFO of title set number title_set (1-99)
+pub fn title_set_ifo_path(title_set: u8) -> String {
+    format!("VIDEO_TS/VTS_{:02}_0.IFO", title_set)
+}
+
+/// Path of the first title VOB of a title set; the cell sectors of the
+/// title set are relative to the start of this file
+pub fn title_vob_path(title_set: u8) -> String {
+    format!("VIDEO_TS/VTS_{:02}_1.VOB", title_set)
+}
+
+/// The title set number and part number (1-9) if path is one of the title VOBs of a title set
+pub fn parse_title_vob_path(path: &str) -> Option<(u8, u8)> {
+    let name = path.strip_prefix("VIDEO_TS/VTS_")?.strip_suffix(".VOB")?;
+    let (title_set, part) = name.split_once('_')?;
+    if title_set.len() != 2 || part.len() != 1 {
+        return None;
+    }
+    let title_set = title_set.parse().ok().filter(|&n| n > 0)?;
+    let part = part.parse().ok().filter(|&n| n > 0)?;
+    Some((title_set, part))
+}
+
+/// The title set number if path is the IFO of a title set
+pub fn parse_title_set_ifo_path(path: &str) -> Option<u8> {
+    let number = path.strip_prefix("VIDEO_TS/VTS_")?.strip_suffix("_0.IFO")?;
+    if number.len() != 2 {
+        return None;
+    }
+    number.parse().ok().filter(|&n| n > 0)
+}
+
+/// The backup (.BUP) of an IFO, or the IFO of a backup, if path is either of them
+pub fn ifo_backup_counterpart(path: &str) -> Option<String> {
+    let (stem, other_extension) = match (path.strip_suffix(".IFO"), path.strip_suffix(".BUP")) {
+        (Some(stem), _) => (stem, "BUP"),
+        (_, Some(stem)) => (stem, "IFO"),
+        _ => return None,
+    };
+    let ifo_path = format!("{}.IFO", stem);
+    if ifo_path != VIDEO_MANAGER_IFO_PATH && parse_title_set_ifo_path(&ifo_path).is_none() {
+        return None;
+    }
+    Some(format!("{}.{}", stem, other_extension))
+}
+
+/// Check that an IFO or its backup parses as the kind of IFO its name says and
+/// is as long as its header says. IFOs have no checksum, so a bad sector read
+/// as zeros or a damaged table shows up as a parse error here.
+pub fn check_ifo(path: &str, ifo: &[u8]) -> io::Result<()> {
+    let ifo_path = path.strip_suffix(".BUP").map_or_else(|| path.to_string(), |stem| format!("{}.IFO", stem));
+    if ifo_path == VIDEO_MANAGER_IFO_PATH {
+        VideoManagerInfo::parse(ifo)?;
+    } else if parse_title_set_ifo_path(&ifo_path).is_some() {
+        TitleSetInfo::parse(ifo)?;
+    } else {
+        return Err(invalid_data(format!("{} is not an IFO", path)));
+    }
+    // VMGI_MAT and VTSI_MAT: last sector of the IFO
+    let length = (u32_at(ifo, 0x1C)? as usize + 1) * IFO_SECTOR_SIZE;
+    if length > ifo.len() {
+        return Err(invalid_data(format!(
+            "IFO is truncated: its header says it is {} bytes but it is {} bytes",
+            length,
+            ifo.len()
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn bytes_at(bytes: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    bytes.get(offset..offset + len).ok_or_else(|| {
+        invalid_data(format!(
+            "IFO is truncated: need bytes {}..{} but it is {} bytes",
+            offset,
+            offset + len,
+            bytes.len()
+        ))
+    })
+}
+pub(crate) fn u8_at(bytes: &[u8], offset: usize) -> io::Result<u8> {
+    Ok(bytes_at(bytes, offset, 1)?[0])
+}
+pub(crate) fn u16_at(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    Ok(u16::from_be_bytes(bytes_at(bytes, offset, 2)?.try_into().unwrap()))
+}
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from_be_bytes(bytes_at(bytes, offset, 4)?.try_into().unwrap()))
+}
+fn sector_offset(bytes: &[u8], sector_pointer_offset: usize) -> io::Result<usize> {
+    Ok(u32_at(bytes, sector_pointer_offset)? as usize * IFO_SECTOR_SIZE)
+}
+
+/// BCD-coded playback time: hours, minutes, seconds, and frames
+/// whose top two bits give the frame rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PlaybackTime {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    /// 25 (PAL) or 30 (NTSC, really 29.97); None if the rate is not set
+    pub frames_per_second: Option<u8>,
+}
+impl PlaybackTime {
+    pub fn read(bytes: &[u8]) -> Self {
+        fn bcd(b: u8) -> u8 {
+            (b >> 4) * 10 + (b & 0x0F)
+        }
+        Self {
+            hours: bcd(bytes[0]),
+            minutes: bcd(bytes[1]),
+            seconds: bcd(bytes[2]),
+            frames: bcd(bytes[3] & 0x3F),
+            frames_per_second: match bytes[3] >> 6 {
+                1 => Some(25),
+                3 => Some(30),
+                _ => None,
+            },
+        }
+    }
+    pub fn as_millis(&self) -> u64 {
+        let seconds = self.hours as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64;
+        let frame_millis = match self.frames_per_second {
+            Some(fps) => self.frames as u64 * 1000 / fps as u64,
+            None => 0,
+        };
+        seconds * 1000 + frame_millis
+    }
+}
+
+/// Formats a duration in milliseconds as h:mm:ss
+pub struct Hms(pub u64);
+impl fmt::Display for Hms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0 / 1000;
+        write!(f, "{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+}
+
+/// One entry of the title search pointer table (TT_SRPT) in VIDEO_TS.IFO
+#[derive(Debug, Clone, PartialEq)]
+pub struct TitleEntry {
+    pub playback_type: u8,
+    pub number_of_angles: u8,
+    pub number_of_chapters: u16,
+    pub parental_id_mask: u16,
+    /// VTS number, i.e. the nn of VTS_nn_0.IFO
+    pub title_set: u8,
+    /// number of the title within its title set (VTS_TTN)
+    pub title_set_title_number: u8,
+    pub title_set_start_sector: u32,
+}
+impl TitleEntry {
+    pub const SIZE: usize = 12;
+    fn read(bytes: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            playback_type: u8_at(bytes, 0)?,
+            number_of_angles: u8_at(bytes, 1)?,
+            number_of_chapters: u16_at(bytes, 2)?,
+            parental_id_mask: u16_at(bytes, 4)?,
+            title_set: u8_at(bytes, 6)?,
+            title_set_title_number: u8_at(bytes, 7)?,
+            title_set_start_sector: u32_at(bytes, 8)?,
+        })
+    }
+}
+
+/// Video Manager Information (VMGI) from VIDEO_TS.IFO
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoManagerInfo {
+    pub version: u16,
+    /// region mask is in the second byte; a set bit means the region is *not* allowed
+    pub category: u32,
+    pub number_of_volumes: u16,
+    pub volume_number: u16,
+    pub number_of_title_sets: u16,
+    pub provider_id: String,
+    pub titles: Vec<TitleEntry>,
+}
+impl VideoManagerInfo {
+    pub const IDENTIFIER: &'static [u8; 12] = b"DVDVIDEO-VMG";
+
+    pub fn parse(ifo: &[u8]) -> io::Result<Self> {
+        if bytes_at(ifo, 0, 12)? != Self::IDENTIFIER {
+            return Err(invalid_data("not a video manager IFO (missing DVDVIDEO-VMG)".to_string()));
+        }
+        let provider_id = bytes_at(ifo, 0x40, 32)?;
+        let provider_id = String::from_utf8_lossy(provider_id)
+            .trim_end_matches(['\0', ' '])
+            .to_string();
+        let title_table = sector_offset(ifo, 0xC4)?;
+        let number_of_titles = u16_at(ifo, title_table)?;
+        let mut titles = Vec::with_capacity(number_of_titles as usize);
+        for i in 0..number_of_titles as usize {
+            let offset = title_table + 8 + i * TitleEntry::SIZE;
+            titles.push(TitleEntry::read(bytes_at(ifo, offset, TitleEntry::SIZE)?)?);
+        }
+        Ok(Self {
+            version: u16_at(ifo, 0x20)?,
+            category: u32_at(ifo, 0x22)?,
+            number_of_volumes: u16_at(ifo, 0x26)?,
+            volume_number: u16_at(ifo, 0x28)?,
+            number_of_title_sets: u16_at(ifo, 0x3E)?,
+            provider_id,
+            titles,
+        })
+    }
+
+    /// The regions (1-8) that the disc may be played in
+    pub fn regions(&self) -> Vec<u8> {
+        let mask = (self.category >> 16) as u8;
+        (1..=8).filter(|region| mask & (1 << (region - 1)) == 0).collect()
+    }
+}
+
+/// A chapter (part of title, PTT): the program of a PGC where it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartOfTitle {
+    /// 1-based program chain number within the title set
+    pub program_chain: u16,
+    /// 1-based program number within the program chain
+    pub program: u16,
+}
+
+/// Cell playback information: where the cell's VOBUs are in the title VOBs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellPlayback {
+    /// first byte of the cell category: block mode, block type, seamless flags
+    pub category: u8,
+    pub playback_time: PlaybackTime,
+    /// sector of the first VOBU, relative to the start of the title set's title VOBs
+    pub first_sector: u32,
+    pub first_ilvu_end_sector: u32,
+    pub last_vobu_start_sector: u32,
+    /// last sector of the cell (inclusive)
+    pub last_sector: u32,
+}
+impl CellPlayback {
+    pub const SIZE: usize = 24;
+    /// block type bits of category: the cell belongs to an angle block
+    pub const BLOCK_TYPE_ANGLE: u8 = 0b01;
+    fn read(bytes: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            category: u8_at(bytes, 0)?,
+            playback_time: PlaybackTime::read(bytes_at(bytes, 4, 4)?),
+            first_sector: u32_at(bytes, 8)?,
+            first_ilvu_end_sector: u32_at(bytes, 12)?,
+            last_vobu_start_sector: u32_at(bytes, 16)?,
+            last_sector: u32_at(bytes, 20)?,
+        })
+    }
+    /// the cell's VOBUs are interleaved with those of other angles in ILVUs
+    pub fn is_interleaved(&self) -> bool {
+        self.category & 0x04 != 0
+    }
+    /// 0 = not in a block, 1 = first cell, 2 = in block, 3 = last cell of block
+    pub fn block_mode(&self) -> u8 {
+        self.category >> 6
+    }
+    pub fn block_type(&self) -> u8 {
+        (self.category >> 4) & 0b11
+    }
+}
+
+/// Cell position information: which VOB and cell id a cell plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellPosition {
+    pub vob_id: u16,
+    pub cell_id: u8,
+}
+
+/// A two-letter ISO 639 language code, if the attributes say one is present
+fn read_language(present: bool, bytes: &[u8]) -> Option<String> {
+    if !present || !bytes.iter().all(u8::is_ascii_alphabetic) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(bytes).to_ascii_lowercase())
+}
+
+/// Video attributes of a title set (VTS_V_ATR)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoAttributes {
+    /// 1 or 2
+    pub mpeg_version: u8,
+    /// 25 for PAL, 30 for NTSC
+    pub frames_per_second: u8,
+    /// 4:3 or 16:9
+    pub widescreen: bool,
+    pub width: u16,
+    pub height: u16,
+}
+impl VideoAttributes {
+    fn read(bytes: &[u8]) -> io::Result<Self> {
+        let (b0, b1) = (u8_at(bytes, 0)?, u8_at(bytes, 1)?);
+        let pal = (b0 >> 4) & 0b11 == 1;
+        let height = if pal { 576 } else { 480 };
+        let (width, height) = match (b1 >> 3) & 0b111 {
+            0 => (720, height),
+            1 => (704, height),
+            2 => (352, height),
+            _ => (352, height / 2),
+        };
+        Ok(Self {
+            mpeg_version: (b0 >> 6) + 1,
+            frames_per_second: if pal { 25 } else { 30 },
+            widescreen: (b0 >> 2) & 0b11 == 3,
+            width,
+            height,
+        })
+    }
+}
+impl fmt::Display for VideoAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MPEG-{} {} {}x{} {}",
+            self.mpeg_version,
+            if self.frames_per_second == 25 { "PAL" } else { "NTSC" },
+            self.width,
+            self.height,
+            if self.widescreen { "16:9" } else { "4:3" }
+        )
+    }
+}
+
+/// Coding mode of an audio stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Ac3,
+    Mpeg1,
+    Mpeg2Extended,
+    Lpcm,
+    Dts,
+    Other(u8),
+}
+impl fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioFormat::Ac3 => write!(f, "AC-3"),
+            AudioFormat::Mpeg1 => write!(f, "MPEG-1"),
+            AudioFormat::Mpeg2Extended => write!(f, "MPEG-2"),
+            AudioFormat::Lpcm => write!(f, "LPCM"),
+            AudioFormat::Dts => write!(f, "DTS"),
+            AudioFormat::Other(mode) => write!(f, "coding mode {}", mode),
+        }
+    }
+}
+
+/// Attributes of one audio stream of a title set (VTS_AST_ATR)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioAttributes {
+    pub format: AudioFormat,
+    pub channels: u8,
+    pub sample_rate: u32,
+    pub language: Option<String>,
+    /// 1 = normal, 2 = visually impaired, 3 = director's comments, 4 = alternate director's comments
+    pub code_extension: u8,
+}
+impl AudioAttributes {
+    pub const SIZE: usize = 8;
+    fn read(bytes: &[u8]) -> io::Result<Self> {
+        let (b0, b1) = (u8_at(bytes, 0)?, u8_at(bytes, 1)?);
+        Ok(Self {
+            format: match b0 >> 5 {
+                0 => AudioFormat::Ac3,
+                2 => AudioFormat::Mpeg1,
+                3 => AudioFormat::Mpeg2Extended,
+                4 => AudioFormat::Lpcm,
+                6 => AudioFormat::Dts,
+                mode => AudioFormat::Other(mode),
+            },
+            channels: (b1 & 0b111) + 1,
+            sample_rate: if (b1 >> 4) & 0b11 == 1 { 96_000 } else { 48_000 },
+            language: read_language((b0 >> 2) & 0b11 == 1, bytes_at(bytes, 2, 2)?),
+            code_extension: u8_at(bytes, 5)?,
+        })
+    }
+}
+impl fmt::Display for AudioAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}ch {}kHz",
+            self.language.as_deref().unwrap_or("--"),
+            self.format,
+            self.channels,
+            self.sample_rate / 1000
+        )?;
+        match self.code_extension {
+            2 => write!(f, " (visually impaired)"),
+            3 | 4 => write!(f, " (director's comments)"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Attributes of one subpicture stream of a title set (VTS_SPST_ATR)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubpictureAttributes {
+    pub language: Option<String>,
+    /// 1 = normal, 2 = large, 3 = children, 5-7 = closed captions, 9 = forced,
+    /// 13-15 = director's comments
+    pub code_extension: u8,
+}
+impl SubpictureAttributes {
+    pub const SIZE: usize = 6;
+    fn read(bytes: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            language: read_language(u8_at(bytes, 0)? & 0b11 == 1, bytes_at(bytes, 2, 2)?),
+            code_extension: u8_at(bytes, 5)?,
+        })
+    }
+}
+impl fmt::Display for SubpictureAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.language.as_deref().unwrap_or("--"))?;
+        match self.code_extension {
+            2 => write!(f, " (large)"),
+            3 => write!(f, " (children)"),
+            5..=7 => write!(f, " (closed captions)"),
+            9 => write!(f, " (forced)"),
+            13..=15 => write!(f, " (director's comments)"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Program chain (PGC)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramChain {
+    /// from the PGCI search pointer: whether this is the entry PGC of a title
+    pub entry: bool,
+    /// from the PGCI search pointer: VTS title number this PGC belongs to
+    pub title_set_title_number: u8,
+    pub playback_time: PlaybackTime,
+    pub next_program_chain: u16,
+    pub previous_program_chain: u16,
+    /// per title set audio stream: bit 15 is set if the stream is available in this PGC
+    pub audio_control: [u16; 8],
+    /// per title set subpicture stream: bit 31 is set if the stream is available in this PGC
+    pub subpicture_control: [u32; 32],
+    /// subpicture colors as 0x00YYCrCb
+    pub palette: [u32; 16],
+    /// the 1-based entry cell number of each program
+    pub program_entry_cells: Vec<u8>,
+    pub cells: Vec<CellPlayback>,
+    pub cell_positions: Vec<CellPosition>,
+}
+impl ProgramChain {
+    fn read(pgc: &[u8], entry: bool, title_set_title_number: u8) -> io::Result<Self> {
+        let number_of_programs = u8_at(pgc, 0x02)? as usize;
+        let number_of_cells = u8_at(pgc, 0x03)? as usize;
+        let program_map = u16_at(pgc, 0xE6)? as usize;
+        let cell_playback = u16_at(pgc, 0xE8)? as usize;
+        let cell_position = u16_at(pgc, 0xEA)? as usize;
+        let program_entry_cells = if number_of_programs > 0 {
+            bytes_at(pgc, program_map, number_of_programs)?.to_vec()
+        } else {
+            vec![]
+        };
+        let mut cells = Vec::with_capacity(number_of_cells);
+        let mut cell_positions = Vec::with_capacity(number_of_cells);
+        for i in 0..number_of_cells {
+            cells.push(CellPlayback::read(bytes_at(
+                pgc,
+                cell_playback + i * CellPlayback::SIZE,
+                CellPlayback::SIZE,
+            )?)?);
+            let position = cell_position + i * 4;
+            cell_positions.push(CellPosition {
+                vob_id: u16_at(pgc, position)?,
+                cell_id: u8_at(pgc, position + 3)?,
+            });
+        }
+        let mut palette = [0u32; 16];
+        for (i, color) in palette.iter_mut().enumerate() {
+            *color = u32_at(pgc, 0xA4 + i * 4)?;
+        }
+        let mut audio_control = [0u16; 8];
+        for (i, control) in audio_control.iter_mut().enumerate() {
+            *control = u16_at(pgc, 0x0C + i * 2)?;
+        }
+        let mut subpicture_control = [0u32; 32];
+        for (i, control) in subpicture_control.iter_mut().enumerate() {
+            *control = u32_at(pgc, 0x1C + i * 4)?;
+        }
+        Ok(Self {
+            entry,
+            title_set_title_number,
+            playback_time: PlaybackTime::read(bytes_at(pgc, 0x04, 4)?),
+            next_program_chain: u16_at(pgc, 0x9C)?,
+            previous_program_chain: u16_at(pgc, 0x9E)?,
+            audio_control,
+            subpicture_control,
+            palette,
+            program_entry_cells,
+            cells,
+            cell_positions,
+        })
+    }
+}
+
+/// Video Title Set Information (VTSI) from VTS_nn_0.IFO
+#[derive(Debug, Clone, PartialEq)]
+pub struct TitleSetInfo {
+    pub version: u16,
+    /// sector of VTS_nn_0.VOB relative to the start of the IFO, or 0 if there is no menu
+    pub menu_vob_start_sector: u32,
+    /// sector of VTS_nn_1.VOB relative to the start of the IFO
+    pub title_vob_start_sector: u32,
+    /// chapters of each VTS title, indexed by VTS title number - 1
+    pub parts_of_title: Vec<Vec<PartOfTitle>>,
+    /// indexed by program chain number - 1
+    pub program_chains: Vec<ProgramChain>,
+    pub video_attributes: VideoAttributes,
+    /// indexed by audio stream number (the AC-3/DTS/LPCM sub-stream or MPEG stream number)
+    pub audio_streams: Vec<AudioAttributes>,
+    /// indexed by subpicture stream number
+    pub subpicture_streams: Vec<SubpictureAttributes>,
+}
+impl TitleSetInfo {
+    pub const IDENTIFIER: &'static [u8; 12] = b"DVDVIDEO-VTS";
+
+    pub fn parse(ifo: &[u8]) -> io::Result<Self> {
+        if bytes_at(ifo, 0, 12)? != Self::IDENTIFIER {
+            return Err(invalid_data("not a title set IFO (missing DVDVIDEO-VTS)".to_string()));
+        }
+
+        // VTS_PTT_SRPT: per title, an offset to its list of (PGCN, PGN)
+        let ptt_table = sector_offset(ifo, 0xC8)?;
+        let number_of_titles = u16_at(ifo, ptt_table)? as usize;
+        let end = ptt_table + u32_at(ifo, ptt_table + 4)? as usize + 1;
+        let mut title_offsets = Vec::with_capacity(number_of_titles);
+        for i in 0..number_of_titles {
+            title_offsets.push(ptt_table + u32_at(ifo, ptt_table + 8 + i * 4)? as usize);
+        }
+        let mut parts_of_title = Vec::with_capacity(number_of_titles);
+        for (i, &start) in title_offsets.iter().enumerate() {
+            let title_end = title_offsets.get(i + 1).copied().unwrap_or(end);
+            let mut parts = vec![];
+            let mut offset = start;
+            while offset + 4 <= title_end {
+                parts.push(PartOfTitle {
+                    program_chain: u16_at(ifo, offset)?,
+                    program: u16_at(ifo, offset + 2)?,
+                });
+                offset += 4;
+            }
+            parts_of_title.push(parts);
+        }
+
+        // VTS_PGCI: search pointers with the category and offset of each PGC
+        let pgci = sector_offset(ifo, 0xCC)?;
+        let number_of_program_chains = u16_at(ifo, pgci)? as usize;
+        let mut program_chains = Vec::with_capacity(number_of_program_chains);
+        for i in 0..number_of_program_chains {
+            let search_pointer = pgci + 8 + i * 8;
+            let category = u32_at(ifo, search_pointer)?;
+            let offset = pgci + u32_at(ifo, search_pointer + 4)? as usize;
+            let pgc = ifo.get(offset..).ok_or_else(|| {
+                invalid_data(format!("program chain {} is outside of the IFO", i + 1))
+            })?;
+            program_chains.push(ProgramChain::read(
+                pgc,
+                category & 0x8000_0000 != 0,
+                ((category >> 24) & 0x7F) as u8,
+            )?);
+        }
+
+        // VTS_AST_ATRT and VTS_SPST_ATRT hold up to 8 audio and 32 subpicture streams
+        let number_of_audio_streams = (u16_at(ifo, 0x202)? as usize).min(8);
+        let mut audio_streams = Vec::with_capacity(number_of_audio_streams);
+        for i in 0..number_of_audio_streams {
+            let offset = 0x204 + i * AudioAttributes::SIZE;
+            audio_streams.push(AudioAttributes::read(bytes_at(ifo, offset, AudioAttributes::SIZE)?)?);
+        }
+        let number_of_subpicture_streams = (u16_at(ifo, 0x254)? as usize).min(32);
+        let mut subpicture_streams = Vec::with_capacity(number_of_subpicture_streams);
+        for i in 0..number_of_subpicture_streams {
+            let offset = 0x256 + i * SubpictureAttributes::SIZE;
+            subpicture_streams.push(SubpictureAttributes::read(bytes_at(
+                ifo,
+                offset,
+                SubpictureAttributes::SIZE,
+            )?)?);
+        }
+
+        Ok(Self {
+            version: u16_at(ifo, 0x20)?,
+            menu_vob_start_sector: u32_at(ifo, 0xC0)?,
+            title_vob_start_sector: u32_at(ifo, 0xC4)?,
+            parts_of_title,
+            program_chains,
+            video_attributes: VideoAttributes::read(bytes_at(ifo, 0x200, 2)?)?,
+            audio_streams,
+            subpicture_streams,
+        })
+    }
+
+    pub fn program_chain(&self, number: u16) -> Option<&ProgramChain> {
+        self.program_chains.get((number as usize).checked_sub(1)?)
+    }
+}
+
+/// Keep only the cells of the given 1-based angle. In an angle block,
+/// the n-th cell of the block belongs to angle n; cells outside of angle blocks are kept.
+pub fn select_angle(cells: &[CellPlayback], angle: u8) -> Vec<CellPlayback> {
+    let mut result = vec![];
+    let mut index_in_block = 0;
+    for cell in cells {
+        if cell.block_type() != CellPlayback::BLOCK_TYPE_ANGLE {
+            result.push(*cell);
+            continue;
+        }
+        if cell.block_mode() == 1 {
+            index_in_block = 0;
+        }
+        index_in_block += 1;
+        if index_in_block == angle {
+            result.push(*cell);
+        }
+    }
+    result
+}
+
+/// A title as the user sees it, combining VIDEO_TS.IFO with its title set
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TitleSummary {
+    /// 1-based title number as used by players
+    pub title_number: u16,
+    pub title_set: u8,
+    pub chapters: u16,
+    pub angles: u8,
+    pub duration_millis: u64,
+    pub cells: usize,
+}
+
+/// All the navigation data of a DVD-Video disc
+#[derive(Debug, Clone, Default)]
+pub struct DvdVideo {
+    pub video_manager: Option<VideoManagerInfo>,
+    /// keyed by title set number
+    pub title_sets: BTreeMap<u8, TitleSetInfo>,
+}
+impl DvdVideo {
+    /// The title set of a title and the title's chapters
+    pub fn title_parts(&self, title: &TitleEntry) -> Option<(&TitleSetInfo, &[PartOfTitle])> {
+        let title_set = self.title_sets.get(&title.title_set)?;
+        let parts = title_set
+            .parts_of_title
+            .get((title.title_set_title_number as usize).checked_sub(1)?)?;
+        Some((title_set, parts))
+    }
+
+    /// The audio streams of a title as (0-based stream number, attributes),
+    /// keeping those that the title's first program chain marks as available
+    pub fn title_audio_streams(&self, title: &TitleEntry) -> Vec<(usize, &AudioAttributes)> {
+        let (Some(title_set), Some((_, pgc))) = (
+            self.title_sets.get(&title.title_set),
+            self.title_program_chains(title).first().copied(),
+        ) else {
+            return vec![];
+        };
+        title_set
+            .audio_streams
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| pgc.audio_control[*i] & 0x8000 != 0)
+            .collect()
+    }
+
+    /// The subpicture streams of a title as (0-based stream number, attributes),
+    /// keeping those that the title's first program chain marks as available
+    pub fn title_subpicture_streams(&self, title: &TitleEntry) -> Vec<(usize, &SubpictureAttributes)> {
+        let (Some(title_set), Some((_, pgc))) = (
+            self.title_sets.get(&title.title_set),
+            self.title_program_chains(title).first().copied(),
+        ) else {
+            return vec![];
+        };
+        title_set
+            .subpicture_streams
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| pgc.subpicture_control[*i] & 0x8000_0000 != 0)
+            .collect()
+    }
+
+    /// The distinct program chains that make up a title, in chapter order
+    pub fn title_program_chains(&self, title: &TitleEntry) -> Vec<(u16, &ProgramChain)> {
+        let mut result: Vec<(u16, &ProgramChain)> = vec![];
+        let Some((title_set, parts)) = self.title_parts(title) else {
+            return result;
+        };
+        for part in parts {
+            if result.iter().any(|(number, _)| *number == part.program_chain) {
+                continue;
+            }
+            if let Some(pgc) = title_set.program_chain(part.program_chain) {
+                result.push((part.program_chain, pgc));
+            }
+        }
+        result
+    }
+
+    /// The cells of the given 1-based chapters of a title in playback order.
+    /// A chapter runs from the entry cell of its program up to the entry cell of
+    /// the next chapter, or to the end of its program chain.
+    pub fn chapter_cells(&self, title: &TitleEntry, chapters: RangeInclusive<u16>) -> io::Result<Vec<CellPlayback>> {
+        let Some((title_set, parts)) = self.title_parts(title) else {
+            return Err(invalid_data(format!("title set {} was not found", title.title_set)));
+        };
+        if *chapters.start() < 1 || *chapters.end() as usize > parts.len() {
+            return Err(invalid_data(format!(
+                "chapters {}-{} are out of range; the title has {} chapters",
+                chapters.start(),
+                chapters.end(),
+                parts.len()
+            )));
+        }
+        // 1-based entry cell of a chapter's program
+        let entry_cell = |part: &PartOfTitle| -> io::Result<(&ProgramChain, usize)> {
+            let pgc = title_set.program_chain(part.program_chain).ok_or_else(|| {
+                invalid_data(format!("program chain {} was not found", part.program_chain))
+            })?;
+            let cell = (part.program as usize)
+                .checked_sub(1)
+                .and_then(|i| pgc.program_entry_cells.get(i))
+                .ok_or_else(|| invalid_data(format!("program {} was not found", part.program)))?;
+            Ok((pgc, *cell as usize))
+        };
+        let mut cells = vec![];
+        for chapter in chapters {
+            let index = chapter as usize - 1;
+            let (pgc, first_cell) = entry_cell(&parts[index])?;
+            let end_cell = match parts.get(index + 1) {
+                Some(next) if next.program_chain == parts[index].program_chain => entry_cell(next)?.1,
+                _ => pgc.cells.len() + 1,
+            };
+            let range = first_cell.max(1) - 1..end_cell.saturating_sub(1).min(pgc.cells.len());
+            cells.extend(pgc.cells.get(range).unwrap_or_default());
+        }
+        Ok(cells)
+    }
+
+    /// The title with the given 1-based title number
+    pub fn title(&self, title_number: u16) -> Option<&TitleEntry> {
+        let index = (title_number as usize).checked_sub(1)?;
+        self.video_manager.as_ref()?.titles.get(index)
+    }
+
+    /// The cells of a title in playback order
+    pub fn title_cells(&self, title: &TitleEntry) -> Vec<CellPlayback> {
+        self.title_program_chains(title)
+            .iter()
+            .flat_map(|(_, pgc)| pgc.cells.iter().copied())
+            .collect()
+    }
+
+    /// Start time of each of the given chapters relative to the start of the first one
+    pub fn chapter_start_times(&self, title: &TitleEntry, chapters: RangeInclusive<u16>) -> io::Result<Vec<u64>> {
+        let mut start_times = vec![];
+        let mut elapsed = 0;
+        for duration in self.chapter_durations(title, chapters)? {
+            start_times.push(elapsed);
+            elapsed += duration;
+        }
+        Ok(start_times)
+    }
+
+    /// Playback time of each of the given chapters in milliseconds.
+    /// Of an angle block, only the cell of the first angle counts.
+    pub fn chapter_durations(&self, title: &TitleEntry, chapters: RangeInclusive<u16>) -> io::Result<Vec<u64>> {
+        chapters
+            .map(|chapter| {
+                let cells = select_angle(&self.chapter_cells(title, chapter..=chapter)?, 1);
+                Ok(cells.iter().map(|cell| cell.playback_time.as_millis()).sum())
+            })
+            .collect()
+    }
+
+    /// The title that is most likely the main feature: the longest one,
+    /// then the one with the most chapters, then the first one
+    pub fn main_title(&self) -> Option<TitleSummary> {
+        self.titles()
+            .into_iter()
+            .rev()
+            .max_by_key(|title| (title.duration_millis, title.chapters))
+    }
+
+    pub fn titles(&self) -> Vec<TitleSummary> {
+        let Some(video_manager) = &self.video_manager else {
+            return vec![];
+        };
+        video_manager
+            .titles
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                let program_chains = self.title_program_chains(title);
+                TitleSummary {
+                    title_number: i as u16 + 1,
+                    title_set: title.title_set,
+                    chapters: title.number_of_chapters,
+                    angles: title.number_of_angles,
+                    duration_millis: program_chains
+                        .iter()
+                        .map(|(_, pgc)| pgc.playback_time.as_millis())
+                        .sum(),
+                    cells: program_chains.iter().map(|(_, pgc)| pgc.cells.len()).sum(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+    fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// VIDEO_TS.IFO with a title table in sector 1
+    pub(crate) fn video_manager_ifo(titles: &[(u8, u8, u16, u8)]) -> Vec<u8> {
+        let mut ifo = vec![0u8; 2 * IFO_SECTOR_SIZE];
+        ifo[..12].copy_from_slice(VideoManagerInfo::IDENTIFIER);
+        put_u16(&mut ifo, 0x20, 0x11);
+        put_u16(&mut ifo, 0x3E, 1);
+        put_u32(&mut ifo, 0xC4, 1);
+        let table = IFO_SECTOR_SIZE;
+        put_u16(&mut ifo, table, titles.len() as u16);
+        for (i, &(title_set, title_set_title_number, chapters, angles)) in titles.iter().enumerate() {
+            let entry = table + 8 + i * TitleEntry::SIZE;
+            ifo[entry + 1] = angles;
+            put_u16(&mut ifo, entry + 2, chapters);
+            ifo[entry + 6] = title_set;
+            ifo[entry + 7] = title_set_title_number;
+        }
+        ifo
+    }
+
+    /// VTS_01_0.IFO with one title of one PGC whose programs start at each cell,
+    /// each cell being 10 sectors and 1 minute long
+    pub(crate) fn title_set_ifo(cells: usize) -> Vec<u8> {
+        let mut ifo = vec![0u8; 3 * IFO_SECTOR_SIZE];
+        ifo[..12].copy_from_slice(TitleSetInfo::IDENTIFIER);
+        put_u32(&mut ifo, 0xC4, 3);
+        put_u32(&mut ifo, 0xC8, 1);
+        put_u32(&mut ifo, 0xCC, 2);
+        // PTT_SRPT: 1 title with one chapter per cell
+        let ptt = IFO_SECTOR_SIZE;
+        put_u16(&mut ifo, ptt, 1);
+        put_u32(&mut ifo, ptt + 4, (12 + cells * 4 - 1) as u32);
+        put_u32(&mut ifo, ptt + 8, 12);
+        for i in 0..cells {
+            put_u16(&mut ifo, ptt + 12 + i * 4, 1);
+            put_u16(&mut ifo, ptt + 12 + i * 4 + 2, i as u16 + 1);
+        }
+        // PGCI with one PGC at offset 16
+        let pgci = 2 * IFO_SECTOR_SIZE;
+        put_u16(&mut ifo, pgci, 1);
+        put_u32(&mut ifo, pgci + 8, 0x8100_0000);
+        put_u32(&mut ifo, pgci + 12, 16);
+        let pgc = pgci + 16;
+        ifo[pgc + 2] = cells as u8;
+        ifo[pgc + 3] = cells as u8;
+        ifo[pgc + 4..pgc + 8].copy_from_slice(&[0x00, cells as u8, 0x00, 0xC0]);
+        put_u16(&mut ifo, pgc + 0xE6, 0xEC);
+        put_u16(&mut ifo, pgc + 0xE8, 0xEC + 0x10);
+        let cell_position = 0xEC + 0x10 + cells as u16 * CellPlayback::SIZE as u16;
+        put_u16(&mut ifo, pgc + 0xEA, cell_position);
+        for i in 0..cells {
+            ifo[pgc + 0xEC + i] = i as u8 + 1;
+            let cell = pgc + 0xEC + 0x10 + i * CellPlayback::SIZE;
+            ifo[cell + 4..cell + 8].copy_from_slice(&[0x00, 0x01, 0x00, 0xC0]);
+            put_u32(&mut ifo, cell + 8, i as u32 * 10);
+            put_u32(&mut ifo, cell + 20, i as u32 * 10 + 9);
+            let position = pgc + cell_position as usize + i * 4;
+            put_u16(&mut ifo, position, 1);
+            ifo[position + 3] = i as u8 + 1;
+        }
+        // PAL 16:9 720x576 with English AC-3 5.1, French LPCM stereo,
+        // and English and forced German subtitles, all but the French audio in the PGC
+        ifo[0x200..0x202].copy_from_slice(&[0x5C, 0x00]);
+        put_u16(&mut ifo, 0x202, 2);
+        ifo[0x204..0x20C].copy_from_slice(&[0x04, 0x05, b'e', b'n', 0, 1, 0, 0]);
+        ifo[0x20C..0x214].copy_from_slice(&[0x84, 0x01, b'f', b'r', 0, 1, 0, 0]);
+        put_u16(&mut ifo, 0x254, 2);
+        ifo[0x256..0x25C].copy_from_slice(&[0x01, 0, b'e', b'n', 0, 1]);
+        ifo[0x25C..0x262].copy_from_slice(&[0x01, 0, b'd', b'e', 0, 9]);
+        put_u16(&mut ifo, pgc + 0x0C, 0x8000);
+        put_u32(&mut ifo, pgc + 0x1C, 0x8000_0000);
+        put_u32(&mut ifo, pgc + 0x20, 0x8101_0101);
+        ifo
+    }
+
+    #[test]
+    fn test_playback_time() {
+        let time = PlaybackTime::read(&[0x01, 0x23, 0x45, 0x40 | 0x12]);
+        assert_eq!((time.hours, time.minutes, time.seconds, time.frames), (1, 23, 45, 12));
+        assert_eq!(time.frames_per_second, Some(25));
+        assert_eq!(time.as_millis(), (3600 + 23 * 60 + 45) * 1000 + 480);
+        assert_eq!(Hms(time.as_millis()).to_string(), "1:23:45");
+    }
+
+    #[test]
+    fn test_parse_ifos() {
+        let video_manager = VideoManagerInfo::parse(&video_manager_ifo(&[(1, 1, 3, 1)])).unwrap();
+        assert_eq!(video_manager.titles.len(), 1);
+        assert_eq!(video_manager.titles[0].title_set, 1);
+        assert_eq!(video_manager.titles[0].number_of_chapters, 3);
+        let title_set = TitleSetInfo::parse(&title_set_ifo(3)).unwrap();
+        assert_eq!(title_set.title_vob_start_sector, 3);
+        assert_eq!(title_set.parts_of_title[0].len(), 3);
+        assert_eq!(title_set.parts_of_title[0][2], PartOfTitle { program_chain: 1, program: 3 });
+        let pgc = &title_set.program_chains[0];
+        assert!(pgc.entry);
+        assert_eq!(pgc.title_set_title_number, 1);
+        assert_eq!(pgc.program_entry_cells, vec![1, 2, 3]);
+        assert_eq!(pgc.cells[1].first_sector, 10);
+        assert_eq!(pgc.cells[1].last_sector, 19);
+        assert_eq!(pgc.cell_positions[2], CellPosition { vob_id: 1, cell_id: 3 });
+
+        let dvd = DvdVideo {
+            video_manager: Some(video_manager),
+            title_sets: BTreeMap::from([(1, title_set)]),
+        };
+        let titles = dvd.titles();
+        assert_eq!(titles[0].duration_millis, 3 * 60 * 1000);
+        assert_eq!(titles[0].cells, 3);
+        let title = dvd.title(1).unwrap();
+        let cells = dvd.title_cells(title);
+        assert_eq!(cells.len(), 3);
+        assert_eq!(cells[2].first_sector, 20);
+        assert!(dvd.title(2).is_none());
+        let cells = dvd.chapter_cells(title, 2..=3).unwrap();
+        assert_eq!(cells.iter().map(|c| c.first_sector).collect::<Vec<_>>(), vec![10, 20]);
+        assert!(dvd.chapter_cells(title, 3..=4).is_err());
+        assert_eq!(dvd.main_title().unwrap().title_number, 1);
+        assert_eq!(dvd.chapter_start_times(title, 2..=3).unwrap(), vec![0, 60_000]);
+        assert_eq!(dvd.chapter_durations(title, 1..=3).unwrap(), vec![60_000; 3]);
+    }
+
+    #[test]
+    fn test_stream_attributes() {
+        let title_set = TitleSetInfo::parse(&title_set_ifo(1)).unwrap();
+        assert_eq!(title_set.video_attributes.to_string(), "MPEG-2 PAL 720x576 16:9");
+        assert_eq!(title_set.audio_streams[0].format, AudioFormat::Ac3);
+        assert_eq!(title_set.audio_streams[0].channels, 6);
+        assert_eq!(title_set.audio_streams[1].to_string(), "fr LPCM 2ch 48kHz");
+        let dvd = DvdVideo {
+            video_manager: Some(VideoManagerInfo::parse(&video_manager_ifo(&[(1, 1, 1, 1)])).unwrap()),
+            title_sets: BTreeMap::from([(1, title_set)]),
+        };
+        let title = dvd.title(1).unwrap();
+        let audio = dvd.title_audio_streams(title);
+        assert_eq!(audio.len(), 1);
+        assert_eq!((audio[0].0, audio[0].1.to_string()), (0, "en AC-3 6ch 48kHz".to_string()));
+        let subpictures = dvd.title_subpicture_streams(title);
+        assert_eq!(
+            subpictures.iter().map(|(_, s)| s.to_string()).collect::<Vec<_>>(),
+            vec!["en", "de (forced)"]
+        );
+    }
+
+    #[test]
+    fn test_select_angle() {
+        let cell = |category, first_sector| CellPlayback {
+            category,
+            playback_time: PlaybackTime::default(),
+            first_sector,
+            first_ilvu_end_sector: 0,
+            last_vobu_start_sector: 0,
+            last_sector: 0,
+        };
+        // a normal cell, then an angle block of 2 angles
+        let cells = [cell(0x00, 0), cell(0x54, 10), cell(0xD4, 13)];
+        let firsts = |angle| select_angle(&cells, angle).iter().map(|c| c.first_sector).collect::<Vec<_>>();
+        assert_eq!(firsts(1), vec![0, 10]);
+        assert_eq!(firsts(2), vec![0, 13]);
+        assert!(cells[1].is_interleaved());
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut ifo = video_manager_ifo(&[(1, 1, 1, 1)]);
+        assert_eq!(VideoManagerInfo::parse(&ifo).unwrap().regions(), (1..=8).collect::<Vec<_>>());
+        // region 2 only
+        ifo[0x23] = !0x02;
+        assert_eq!(VideoManagerInfo::parse(&ifo).unwrap().regions(), vec![2]);
+    }
+
+    #[test]
+    fn test_main_title() {
+        // titles 2 and 3 are equally long but title 3 has more chapters
+        let video_manager =
+            VideoManagerInfo::parse(&video_manager_ifo(&[(1, 1, 1, 1), (2, 1, 2, 1), (3, 1, 3, 1)])).unwrap();
+        let mut dvd = DvdVideo {
+            video_manager: Some(video_manager),
+            title_sets: BTreeMap::new(),
+        };
+        for (title_set, cells) in [(1, 1), (2, 3), (3, 3)] {
+            dvd.title_sets
+                .insert(title_set, TitleSetInfo::parse(&title_set_ifo(cells)).unwrap());
+        }
+        assert_eq!(dvd.main_title().unwrap().title_number, 3);
+    }
+
+    #[test]
+    fn test_parse_title_set_ifo_path() {
+        assert_eq!(parse_title_set_ifo_path(&title_set_ifo_path(7)), Some(7));
+        assert_eq!(parse_title_set_ifo_path("VIDEO_TS/VTS_07_1.VOB"), None);
+        assert_eq!(parse_title_set_ifo_path("VIDEO_TS/VTS_00_0.IFO"), None);
+        assert_eq!(parse_title_vob_path(&title_vob_path(7)), Some((7, 1)));
+        assert_eq!(parse_title_vob_path("VIDEO_TS/VTS_07_9.VOB"), Some((7, 9)));
+        assert_eq!(parse_title_vob_path("VIDEO_TS/VTS_07_0.VOB"), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_other_files() {
+        assert!(VideoManagerInfo::parse(&title_set_ifo(1)).is_err());
+        assert!(TitleSetInfo::parse(&[0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn test_check_ifo() {
+        assert_eq!(
+            ifo_backup_counterpart("VIDEO_TS/VTS_03_0.IFO").as_deref(),
+            Some("VIDEO_TS/VTS_03_0.BUP")
+        );
+        assert_eq!(ifo_backup_counterpart("VIDEO_TS/VIDEO_TS.BUP").as_deref(), Some(VIDEO_MANAGER_IFO_PATH));
+        assert_eq!(ifo_backup_counterpart("VIDEO_TS/VTS_03_1.VOB"), None);
+        assert_eq!(ifo_backup_counterpart("AUDIO_TS/AUDIO_TS.IFO"), None);
+
+        let mut ifo = title_set_ifo(2);
+        check_ifo("VIDEO_TS/VTS_01_0.BUP", &ifo).unwrap();
+        assert!(check_ifo(VIDEO_MANAGER_IFO_PATH, &ifo).is_err());
+        check_ifo(VIDEO_MANAGER_IFO_PATH, &video_manager_ifo(&[(1, 1, 2, 1)])).unwrap();
+        // the header says the IFO is longer than it is
+        put_u32(&mut ifo, 0x1C, 3);
+        assert!(check_ifo("VIDEO_TS/VTS_01_0.IFO", &ifo).is_err());
+        // an unreadable first sector read as zeros
+        let mut ifo = title_set_ifo(2);
+        ifo[..IFO_SECTOR_SIZE].fill(0);
+        assert!(check_ifo("VIDEO_TS/VTS_01_0.IFO", &ifo).is_err());
+    }
+}
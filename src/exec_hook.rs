@@ -0,0 +1,69 @@
+//! The command given with --exec that is run after each file is copied.
+//! {path}, {size} and {sha256} in the command are replaced with the copied file's
+//! values, quoted for the shell, and the result is run with `sh -c`.
+
+use std::{
+    io,
+    path::Path,
+    process::{Command, ExitStatus},
+};
+
+/// A copied file as described to the command
+#[derive(Debug, Clone, Copy)]
+pub struct HookFile<'a> {
+    /// the output file
+    pub path: &'a Path,
+    pub size: u64,
+    /// lowercase hex SHA-256, as in the manifest
+    pub sha256: &'a str,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecHook {
+    template: String,
+}
+impl ExecHook {
+    pub fn new(template: &str) -> Self {
+        Self {
+            template: template.to_string(),
+        }
+    }
+
+    /// The shell command line for file
+    pub fn command_line(&self, file: &HookFile) -> String {
+        self.template
+            .replace("{path}", &shell_quote(&file.path.to_string_lossy()))
+            .replace("{size}", &file.size.to_string())
+            .replace("{sha256}", file.sha256)
+    }
+
+    /// Run the command for file and wait for it
+    pub fn run(&self, file: &HookFile) -> io::Result<ExitStatus> {
+        Command::new("sh").arg("-c").arg(self.command_line(file)).status()
+    }
+}
+
+/// Quote s as one word for a POSIX shell
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        let hook = ExecHook::new("upload {path} --size={size} --sum {sha256}");
+        let file = HookFile {
+            path: Path::new("/out/It's Here/VIDEO_TS/VTS_01_1.VOB"),
+            size: 2048,
+            sha256: "ab12",
+        };
+        assert_eq!(
+            hook.command_line(&file),
+            r"upload '/out/It'\''s Here/VIDEO_TS/VTS_01_1.VOB' --size=2048 --sum ab12"
+        );
+        assert_eq!(shell_quote(""), "''");
+    }
+}
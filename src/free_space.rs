@@ -0,0 +1,106 @@
+//! The space that is left on the file system of an output directory, to tell before a copy
+//! whether it will fit: statvfs(3) on Unix and GetDiskFreeSpaceExW on Windows.
+
+use std::{io, path::Path};
+
+/// The bytes that an unprivileged user can still write on the file system that holds path
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    use std::{
+        ffi::{c_char, c_int, c_ulong, CString},
+        os::unix::ffi::OsStrExt,
+    };
+    /// struct statvfs of glibc and musl; the block counts are fsblkcnt_t, an unsigned long
+    #[cfg(not(target_os = "macos"))]
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize: c_ulong,
+        f_frsize: c_ulong,
+        f_blocks: c_ulong,
+        f_bfree: c_ulong,
+        f_bavail: c_ulong,
+        f_files: c_ulong,
+        f_ffree: c_ulong,
+        f_favail: c_ulong,
+        f_fsid: c_ulong,
+        f_flag: c_ulong,
+        f_namemax: c_ulong,
+        spare: [c_int; 6],
+    }
+    /// struct statvfs of sys/statvfs.h, whose block counts are 32-bit
+    #[cfg(target_os = "macos")]
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize: c_ulong,
+        f_frsize: c_ulong,
+        f_blocks: u32,
+        f_bfree: u32,
+        f_bavail: u32,
+        f_files: u32,
+        f_ffree: u32,
+        f_favail: u32,
+        f_fsid: c_ulong,
+        f_flag: c_ulong,
+        f_namemax: c_ulong,
+    }
+    extern "C" {
+        fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut stat = std::mem::MaybeUninit::<StatVfs>::uninit();
+    // SAFETY: path is NUL-terminated and stat is large enough for struct statvfs
+    if unsafe { statvfs(path.as_ptr(), stat.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statvfs filled it in
+    let stat = unsafe { stat.assume_init() };
+    // the fields are narrower than u64 on macOS and 32-bit hosts
+    #[allow(clippy::unnecessary_cast)]
+    let available = stat.f_bavail as u64 * stat.f_frsize as u64;
+    Ok(available)
+}
+
+/// The bytes that the user can still write on the volume that holds path
+#[cfg(windows)]
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_bytes_available: *mut u64,
+            total_bytes: *mut u64,
+            total_free_bytes: *mut u64,
+        ) -> i32;
+    }
+    let directory: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut available = 0;
+    // SAFETY: directory is NUL-terminated and available outlives the call
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(directory.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut())
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+/// Other hosts cannot tell
+#[cfg(not(any(unix, windows)))]
+pub fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "telling the free space needs Unix or Windows",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_available_space() {
+        let dir = std::env::temp_dir();
+        assert!(available_space(&dir).unwrap() > 0);
+        assert!(available_space(&dir.join("dvdromcopy-no-such-directory")).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! A disc image on a web server or in object storage as the input
+//! (`--device https://host/image.iso`), read with HTTP Range requests so that it can
+//! be inspected or partly extracted without downloading all of it. Reads are rounded
+//! up to chunks of CHUNK_SIZE bytes and recent chunks are kept, so that the sector by
+//! sector reads of the UDF parser and the copy cost one request per chunk.
+//! Reading needs the `http` cargo feature.
+
+/// Bytes fetched per request
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Chunks kept in memory
+pub const CACHED_CHUNKS: usize = 64;
+
+pub fn is_http_url(device: &str) -> bool {
+    device.starts_with("http://") || device.starts_with("https://")
+}
+
+/// The complete length from a Content-Range header, e.g. 4700372992 from
+/// `bytes 0-0/4700372992`
+pub fn content_range_length(content_range: &str) -> Option<u64> {
+    let range = content_range.trim().strip_prefix("bytes ")?;
+    let (_, length) = range.split_once('/')?;
+    length.trim().parse().ok()
+}
+
+/// The Range header value of chunk in an image of length bytes, or None past the end
+pub fn chunk_range(chunk: u64, length: u64) -> Option<String> {
+    let start = chunk * CHUNK_SIZE;
+    if start >= length {
+        return None;
+    }
+    let end = (start + CHUNK_SIZE).min(length) - 1;
+    Some(format!("bytes={}-{}", start, end))
+}
+
+#[cfg(feature = "http")]
+pub use remote::HttpImage;
+
+#[cfg(feature = "http")]
+mod remote {
+    use std::{
+        io::{self, Read, Seek, SeekFrom},
+        num::NonZero,
+        time::Duration,
+    };
+
+    use log::{debug, warn};
+    use lru::LruCache;
+
+    use super::{chunk_range, content_range_length, CACHED_CHUNKS, CHUNK_SIZE};
+
+    /// Attempts of each request before a read fails
+    const ATTEMPTS: u32 = 3;
+
+    pub struct HttpImage {
+        url: String,
+        agent: ureq::Agent,
+        length: u64,
+        pos: u64,
+        chunks: LruCache<u64, Vec<u8>>,
+    }
+    impl HttpImage {
+        /// Check that the server supports Range requests and get the image's length
+        pub fn open(url: &str) -> io::Result<Self> {
+            let agent = ureq::AgentBuilder::new().timeout_read(Duration::from_secs(60)).build();
+            let response = get_range(&agent, url, "bytes=0-0")?;
+            let length = (response.header("Content-Range"))
+                .and_then(content_range_length)
+                .ok_or_else(|| io::Error::other(format!("{}: the response has no Content-Range length", url)))?;
+            debug!("HttpImage: {} is {} bytes", url, length);
+            Ok(Self {
+                url: url.to_string(),
+                agent,
+                length,
+                pos: 0,
+                chunks: LruCache::new(NonZero::new(CACHED_CHUNKS).unwrap()),
+            })
+        }
+
+        pub fn len(&self) -> u64 {
+            self.length
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.length == 0
+        }
+
+        fn fetch_chunk(&self, chunk: u64) -> io::Result<Vec<u8>> {
+            let range = chunk_range(chunk, self.length).expect("chunk past the end");
+            let mut attempt = 1;
+            loop {
+                let result = get_range(&self.agent, &self.url, &range).and_then(|response| {
+                    let mut data = Vec::with_capacity(CHUNK_SIZE as usize);
+                    response.into_reader().read_to_end(&mut data)?;
+                    Ok(data)
+                });
+                match result {
+                    Ok(data) => {
+                        let expected = CHUNK_SIZE.min(self.length - chunk * CHUNK_SIZE);
+                        if data.len() as u64 != expected {
+                            return Err(io::Error::other(format!(
+                                "{}: got {} bytes of {} instead of {}",
+                                self.url,
+                                data.len(),
+                                range,
+                                expected
+                            )));
+                        }
+                        return Ok(data);
+                    }
+                    Err(e) if attempt < ATTEMPTS => {
+                        warn!("{} {}: {}; retrying", self.url, range, e);
+                        attempt += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+    impl Read for HttpImage {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pos >= self.length || buf.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.pos / CHUNK_SIZE;
+            if !self.chunks.contains(&chunk) {
+                let data = self.fetch_chunk(chunk)?;
+                self.chunks.put(chunk, data);
+            }
+            let data = self.chunks.get(&chunk).unwrap();
+            let offset = (self.pos - chunk * CHUNK_SIZE) as usize;
+            let len = buf.len().min(data.len() - offset);
+            buf[..len].copy_from_slice(&data[offset..offset + len]);
+            self.pos += len as u64;
+            Ok(len)
+        }
+    }
+    impl Seek for HttpImage {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let pos = match pos {
+                SeekFrom::Start(offset) => Some(offset),
+                SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+                SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            };
+            self.pos = pos.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+            Ok(self.pos)
+        }
+    }
+
+    /// GET with a Range header, failing unless the server answers 206 Partial Content
+    fn get_range(agent: &ureq::Agent, url: &str, range: &str) -> io::Result<ureq::Response> {
+        debug!("HttpImage: GET {} Range: {}", url, range);
+        match agent.get(url).set("Range", range).call() {
+            Ok(response) if response.status() == 206 => Ok(response),
+            Ok(response) => Err(io::Error::other(format!(
+                "{}: the server does not support Range requests ({} {})",
+                url,
+                response.status(),
+                response.status_text()
+            ))),
+            Err(ureq::Error::Status(status, response)) => Err(io::Error::other(format!(
+                "GET {}: {} {}",
+                url,
+                status,
+                response.status_text()
+            ))),
+            Err(ureq::Error::Transport(e)) => Err(io::Error::other(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_range_length() {
+        assert_eq!(content_range_length("bytes 0-0/4700372992"), Some(4_700_372_992));
+        assert_eq!(content_range_length("bytes */4700372992"), Some(4_700_372_992));
+        // the length is unknown
+        assert_eq!(content_range_length("bytes 0-0/*"), None);
+        assert_eq!(content_range_length("0-0/10"), None);
+    }
+
+    #[test]
+    fn test_chunk_range() {
+        let length = 2 * CHUNK_SIZE + 2048;
+        assert_eq!(chunk_range(0, length), Some(format!("bytes=0-{}", CHUNK_SIZE - 1)));
+        assert_eq!(
+            chunk_range(2, length),
+            Some(format!("bytes={}-{}", 2 * CHUNK_SIZE, 2 * CHUNK_SIZE + 2047))
+        );
+        assert_eq!(chunk_range(3, length), None);
+        assert!(is_http_url("https://example.com/Funfancy.iso"));
+        assert!(!is_http_url("/dev/rdisk4"));
+    }
+}
@@ -0,0 +1,243 @@
+//! `info`: what is on a disc before copying it. `info --json` prints DiscInfo, whose
+//! schema is stable: fields may be added, but SCHEMA_VERSION changes if one is removed
+//! or changes meaning.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    drive_region::DriveRegion,
+    udf::{EntityID, LogicalVolumeIntegrityDescriptor, PartitionMap, PrimaryVolumeDescriptor},
+    udf_high_level::{VolumeInfo, VolumeStructures},
+};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct DiscInfo {
+    pub schema_version: u32,
+    pub volume: VolumeInfo,
+    /// e.g. "1.02", from the domain identifier of the logical volume
+    pub udf_revision: Option<String>,
+    pub partition_maps: Vec<PartitionMapInfo>,
+    pub partitions: Vec<PartitionInfo>,
+    /// from the Logical Volume Integrity Descriptor, if the disc has one
+    pub integrity: Option<IntegrityInfo>,
+    pub css: CssInfo,
+    pub region: RegionInfo,
+    pub fingerprints: Fingerprints,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PartitionMapInfo {
+    Type1 {
+        volume_sequence_number: u16,
+        partition_number: u16,
+    },
+    Type2 {
+        /// e.g. "*UDF Virtual Partition"
+        partition_type_identifier: String,
+    },
+    Other {
+        map_type: u8,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct PartitionInfo {
+    pub number: u16,
+    pub starting_sector: u32,
+    pub length_sectors: u32,
+    pub access_type: &'static str,
+    /// e.g. "+NSR02" for UDF 1.02
+    pub contents: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntegrityInfo {
+    /// "closed" if the volume was closed cleanly after it was last written, "open" if not
+    pub state: String,
+    pub recorded: String,
+    pub number_of_files: u32,
+    pub number_of_directories: u32,
+    /// e.g. "1.02"
+    pub minimum_udf_read_revision: String,
+    pub minimum_udf_write_revision: String,
+    pub maximum_udf_write_revision: String,
+    /// per partition; null where the disc does not record it
+    pub free_blocks: Vec<Option<u32>>,
+    pub size_blocks: Vec<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CssInfo {
+    /// whether libdvdcss reports the disc as CSS scrambled
+    pub scrambled: bool,
+    pub title_sets: Vec<TitleSetCss>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitleSetCss {
+    pub title_set: u8,
+    /// whether the first sectors of its title VOBs are scrambled; null if they could not be read
+    pub scrambled: Option<bool>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RegionInfo {
+    /// the regions (1-8) that VIDEO_TS.IFO allows, if the disc has one
+    pub disc: Option<Vec<u8>>,
+    /// the RPC state of the drive, if --device is a drive whose state could be read
+    pub drive: Option<DriveRegion>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Fingerprints {
+    /// the Windows DVD ID, if the disc has a VIDEO_TS
+    pub dvd_id: Option<String>,
+    /// SHA-256 of the Primary Volume Descriptor, which identifies a pressing
+    pub primary_volume_descriptor_sha256: String,
+}
+
+impl DiscInfo {
+    pub fn new(
+        structures: &VolumeStructures,
+        integrity: Option<&LogicalVolumeIntegrityDescriptor>,
+        scrambled: bool,
+        dvd_id: Option<String>,
+    ) -> Self {
+        let mut pvd = vec![0u8; PrimaryVolumeDescriptor::size()];
+        structures.primary_volume.write(&mut pvd);
+        Self {
+            schema_version: SCHEMA_VERSION,
+            volume: structures.volume_info(),
+            udf_revision: domain_udf_revision(&structures.logical_volume.domain_identifier),
+            partition_maps: structures.partition_maps.iter().map(partition_map_info).collect(),
+            partitions: (structures.recorded_partition_descriptors())
+                .map(|partition| PartitionInfo {
+                    number: partition.partition_number,
+                    starting_sector: partition.partition_starting_location,
+                    length_sectors: partition.partition_length,
+                    access_type: access_type_name(partition.access_type),
+                    contents: partition.partition_contents.identifier_string(),
+                })
+                .collect(),
+            integrity: integrity.map(integrity_info),
+            css: CssInfo {
+                scrambled,
+                title_sets: vec![],
+            },
+            region: RegionInfo::default(),
+            fingerprints: Fingerprints {
+                dvd_id,
+                primary_volume_descriptor_sha256: Sha256::digest(&pvd).iter().map(|b| format!("{:02x}", b)).collect(),
+            },
+        }
+    }
+}
+
+fn partition_map_info(partition_map: &PartitionMap) -> PartitionMapInfo {
+    match partition_map {
+        PartitionMap::Type1(map) => PartitionMapInfo::Type1 {
+            volume_sequence_number: map.volume_seq_number,
+            partition_number: map.partition_number,
+        },
+        PartitionMap::Type2(map) => PartitionMapInfo::Type2 {
+            partition_type_identifier: EntityID::read(&map.partition_type_identifier).identifier_string(),
+        },
+        PartitionMap::Other { header, .. } => PartitionMapInfo::Other {
+            map_type: header.partition_map_type,
+        },
+    }
+}
+
+/// The UDF revision that the suffix of an "*OSTA UDF Compliant" domain identifier records
+/// (UDF 2.1.5.3)
+fn domain_udf_revision(domain_identifier: &EntityID) -> Option<String> {
+    (domain_identifier.identifier_string() == "*OSTA UDF Compliant").then(|| {
+        let suffix = &domain_identifier.identifier_suffix;
+        udf_revision(u16::from_le_bytes([suffix[0], suffix[1]]))
+    })
+}
+
+/// ECMA-167 3/10.5.7
+fn access_type_name(access_type: u32) -> &'static str {
+    match access_type {
+        0 => "unspecified",
+        1 => "read-only",
+        2 => "write-once",
+        3 => "rewritable",
+        4 => "overwritable",
+        _ => "reserved",
+    }
+}
+
+/// A UDF revision recorded as BCD, e.g. 0x0102 as "1.02"
+pub fn udf_revision(revision: u16) -> String {
+    format!("{:x}.{:02x}", revision >> 8, revision & 0xFF)
+}
+
+fn integrity_info(lvid: &LogicalVolumeIntegrityDescriptor) -> IntegrityInfo {
+    IntegrityInfo {
+        state: match lvid.integrity_type {
+            LogicalVolumeIntegrityDescriptor::INTEGRITY_OPEN => "open".to_string(),
+            LogicalVolumeIntegrityDescriptor::INTEGRITY_CLOSE => "closed".to_string(),
+            other => format!("unknown ({})", other),
+        },
+        recorded: lvid.recording_date_and_time.iso8601(),
+        number_of_files: lvid.number_of_files,
+        number_of_directories: lvid.number_of_directories,
+        minimum_udf_read_revision: udf_revision(lvid.minimum_udf_read_revision),
+        minimum_udf_write_revision: udf_revision(lvid.minimum_udf_write_revision),
+        maximum_udf_write_revision: udf_revision(lvid.maximum_udf_write_revision),
+        free_blocks: (lvid.free_space_table.iter())
+            .map(|&free| (free != u32::MAX).then_some(free))
+            .collect(),
+        size_blocks: lvid.size_table.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrity_info() {
+        let mut bytes = vec![0u8; 2048];
+        bytes[0] = 9;
+        bytes[28..32].copy_from_slice(&LogicalVolumeIntegrityDescriptor::INTEGRITY_CLOSE.to_le_bytes());
+        // one partition with an unknown free space
+        bytes[72..76].copy_from_slice(&1u32.to_le_bytes());
+        bytes[76..80].copy_from_slice(&46u32.to_le_bytes());
+        bytes[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes[84..88].copy_from_slice(&2_295_104u32.to_le_bytes());
+        bytes[89..89 + 9].copy_from_slice(b"*Mastered");
+        bytes[120..124].copy_from_slice(&9u32.to_le_bytes());
+        bytes[124..128].copy_from_slice(&2u32.to_le_bytes());
+        bytes[128..130].copy_from_slice(&0x0102u16.to_le_bytes());
+        bytes[130..132].copy_from_slice(&0x0102u16.to_le_bytes());
+        bytes[132..134].copy_from_slice(&0x0250u16.to_le_bytes());
+        let lvid = LogicalVolumeIntegrityDescriptor::read(&bytes).unwrap();
+        assert_eq!(lvid.implementation_identifier.identifier_string(), "*Mastered");
+        let info = integrity_info(&lvid);
+        assert_eq!(info.state, "closed");
+        assert_eq!(info.number_of_files, 9);
+        assert_eq!(info.number_of_directories, 2);
+        assert_eq!(info.minimum_udf_read_revision, "1.02");
+        assert_eq!(info.maximum_udf_write_revision, "2.50");
+        assert_eq!(info.free_blocks, vec![None]);
+        assert_eq!(info.size_blocks, vec![2_295_104]);
+
+        // the tables run past the end
+        bytes[72..76].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(LogicalVolumeIntegrityDescriptor::read(&bytes).is_none());
+    }
+
+    #[test]
+    fn test_domain_udf_revision() {
+        let domain = EntityID::new("*OSTA UDF Compliant", [0x50, 0x02, 0x03, 0, 0, 0, 0, 0]);
+        assert_eq!(domain_udf_revision(&domain).as_deref(), Some("2.50"));
+        assert_eq!(domain_udf_revision(&EntityID::new("*Other", [0x50, 0x02, 0, 0, 0, 0, 0, 0])), None);
+    }
+}
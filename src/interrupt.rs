@@ -0,0 +1,97 @@
+//! Ctrl-C during a copy. The first Ctrl-C (SIGINT, or Ctrl-C or Ctrl-Break in a Windows
+//! console) only sets a flag, which the copy checks between blocks so that it can stop with
+//! the file being written left at `<name>.partial` and what was copied recorded. A second
+//! Ctrl-C ends the process at once.
+
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl-C was pressed since install_handler()
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// An Interrupted error once Ctrl-C was pressed. Read and Write implementations retry
+/// Interrupted errors, so this is only for returning from the copy itself.
+pub fn check_interrupted() -> io::Result<()> {
+    check_flag(&INTERRUPTED)
+}
+
+fn check_flag(flag: &AtomicBool) -> io::Result<()> {
+    if flag.load(Ordering::SeqCst) {
+        Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted by Ctrl-C"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Record a Ctrl-C in flag, returning whether there was one before, which ends the process
+fn record_interrupt(flag: &AtomicBool) -> bool {
+    flag.swap(true, Ordering::SeqCst)
+}
+
+/// Catch Ctrl-C for the rest of the process instead of being ended by it
+#[cfg(unix)]
+pub fn install_handler() -> io::Result<()> {
+    use std::ffi::c_int;
+    /// the same on Linux, macOS and the BSDs
+    const SIGINT: c_int = 2;
+    const SIG_ERR: usize = usize::MAX;
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+        fn _exit(status: c_int) -> !;
+    }
+    extern "C" fn handle_sigint(_: c_int) {
+        if record_interrupt(&INTERRUPTED) {
+            // SAFETY: _exit is async-signal-safe; 128 + SIGINT is what a shell reports
+            unsafe { _exit(128 + SIGINT) }
+        }
+    }
+    // SAFETY: the handler only touches an atomic and calls _exit
+    if unsafe { signal(SIGINT, handle_sigint as extern "C" fn(c_int) as usize) } == SIG_ERR {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn install_handler() -> io::Result<()> {
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+    extern "system" fn handle_ctrl(ctrl_type: u32) -> i32 {
+        let is_interrupt = matches!(ctrl_type, CTRL_C_EVENT | CTRL_BREAK_EVENT);
+        // returning FALSE for the second Ctrl-C lets the default handler end the process
+        (is_interrupt && !record_interrupt(&INTERRUPTED)) as i32
+    }
+    // SAFETY: handle_ctrl only touches an atomic
+    if unsafe { SetConsoleCtrlHandler(Some(handle_ctrl), 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// With a flag of its own, since the other tests copy with INTERRUPTED
+    #[test]
+    fn test_interrupt() {
+        install_handler().unwrap();
+        let flag = AtomicBool::new(false);
+        assert!(check_flag(&flag).is_ok());
+        assert!(!record_interrupt(&flag));
+        assert_eq!(check_flag(&flag).unwrap_err().kind(), io::ErrorKind::Interrupted);
+        // the second Ctrl-C
+        assert!(record_interrupt(&flag));
+        assert!(!interrupted());
+    }
+}
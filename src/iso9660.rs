@@ -0,0 +1,761 @@
+//! ISO 9660 and Joliet directories for UDF Bridge images, the format of DVD-Video discs, so that
+//! readers that do not understand UDF still see the files. The volume descriptors go in sectors
+//! 16 to 18, before the UDF Volume Recognition Sequence, and the path tables and directories in
+//! sectors that the UDF writer sets aside; their records point at the same file data as the UDF
+//! File Entries. Discs that have only ISO 9660 are read with read_volume and read_files.
+//! https://www.ecma-international.org/wp-content/uploads/ECMA-119_4th_edition_june_2019.pdf
+
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{self, Read, Seek, SeekFrom},
+    time::SystemTime,
+};
+
+use crate::{udf::Timestamp, udf_parser::is_safe_file_identifier};
+
+const SECTOR_SIZE: usize = 2048;
+/// The sector of the Primary Volume Descriptor, which the Joliet Supplementary Volume
+/// Descriptor and the Volume Descriptor Set Terminator follow
+pub(crate) const VOLUME_DESCRIPTOR_SECTOR: u32 = 16;
+pub(crate) const VOLUME_DESCRIPTOR_SECTORS: u32 = 3;
+/// The longest extent of a directory record that is a whole number of sectors; longer files
+/// are recorded as several extents (ECMA-119 6.5.1)
+const MAX_EXTENT_LENGTH: u64 = 0xFFFF_F800;
+/// ECMA-119 9.1.6 File Flags
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// A file or directory to record; the root is the first
+pub(crate) struct IsoNode<'a> {
+    pub name: &'a str,
+    pub parent: usize,
+    /// the entries of a directory, or None for a file
+    pub children: Option<&'a [usize]>,
+    pub modified: SystemTime,
+    /// the length of a file; ignored for directories
+    pub length: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Flavor {
+    /// interchange level 2 d-characters
+    Iso9660,
+    /// UCS-2 names of up to 64 characters
+    Joliet,
+}
+
+impl Flavor {
+    fn max_length(self) -> usize {
+        match self {
+            Flavor::Iso9660 => 30,
+            Flavor::Joliet => 64,
+        }
+    }
+
+    fn map_char(self, c: char) -> char {
+        match self {
+            Flavor::Iso9660 => match c.to_ascii_uppercase() {
+                c @ ('A'..='Z' | '0'..='9' | '_') => c,
+                _ => '_',
+            },
+            Flavor::Joliet => match c {
+                '*' | '/' | ':' | ';' | '?' | '\\' => '_',
+                c if c.is_control() || c.len_utf16() > 1 => '_',
+                c => c,
+            },
+        }
+    }
+
+    fn encode(self, s: &str) -> Vec<u8> {
+        match self {
+            Flavor::Iso9660 => s.as_bytes().to_vec(),
+            Flavor::Joliet => s.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Flavor::Iso9660 => bytes.iter().map(|&b| b as char).collect(),
+            Flavor::Joliet => {
+                let units = bytes.chunks_exact(2).map(|unit| u16::from_be_bytes([unit[0], unit[1]]));
+                char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+            }
+        }
+    }
+
+    /// Fill a text field of a volume descriptor with s, padded with spaces
+    fn text(self, field: &mut [u8], s: &str) {
+        let encoded = self.encode(s);
+        let padding = self.encode(" ");
+        for (i, byte) in field.iter_mut().enumerate() {
+            *byte = encoded.get(i).copied().unwrap_or(padding[i % padding.len()]);
+        }
+    }
+
+    /// The volume identifier as it fits in the 32 bytes of its field
+    fn volume_identifier(self, identifier: &str) -> String {
+        let length = match self {
+            Flavor::Iso9660 => 32,
+            Flavor::Joliet => 16,
+        };
+        identifier.chars().map(|c| self.map_char(c)).take(length).collect()
+    }
+
+    /// The file or directory identifier of name, with "~n" before any extension if a
+    /// number is given to tell it apart from another entry of the same directory
+    fn identifier(self, name: &str, is_directory: bool, number: Option<usize>) -> Vec<u8> {
+        let (base, extension) = match name.rsplit_once('.') {
+            Some((base, extension)) if !is_directory && !base.is_empty() => (base, extension),
+            _ => (name, ""),
+        };
+        let map = |s: &str| s.chars().map(|c| self.map_char(c)).collect::<String>();
+        let suffix = number.map(|number| format!("~{}", number)).unwrap_or_default();
+        let extension: String = map(extension).chars().take(self.max_length() / 2).collect();
+        // ECMA-119 7.5.1: a file identifier always has the separator
+        let separator = if is_directory || (self == Flavor::Joliet && extension.is_empty()) { "" } else { "." };
+        let base_length = self.max_length() - suffix.len() - separator.len() - extension.chars().count();
+        let base: String = map(base).chars().take(base_length).collect();
+        let version = if is_directory { "" } else { ";1" };
+        self.encode(&format!("{}{}{}{}{}", base, suffix, separator, extension, version))
+    }
+}
+
+/// The identifiers and layout of one of the two hierarchies
+struct Hierarchy {
+    flavor: Flavor,
+    /// by node; unused for the root
+    identifiers: Vec<Vec<u8>>,
+    /// by node, each directory's entries sorted by identifier
+    sorted_children: Vec<Vec<usize>>,
+    /// ECMA-119 9.4: the directories by level, then by parent, then by identifier
+    path_table_order: Vec<usize>,
+    /// by node, the size of each directory in whole sectors
+    directory_sizes: Vec<u32>,
+    path_table_size: u32,
+}
+
+impl Hierarchy {
+    fn new(nodes: &[IsoNode], flavor: Flavor) -> io::Result<Self> {
+        let mut identifiers = vec![vec![]; nodes.len()];
+        let mut sorted_children = vec![vec![]; nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            let Some(children) = node.children else { continue };
+            let mut taken = HashSet::new();
+            for &child in children {
+                let is_directory = nodes[child].children.is_some();
+                let mut number = None;
+                let identifier = loop {
+                    let identifier = flavor.identifier(nodes[child].name, is_directory, number);
+                    if taken.insert(identifier.clone()) {
+                        break identifier;
+                    }
+                    number = Some(number.map_or(1, |number| number + 1));
+                };
+                identifiers[child] = identifier;
+            }
+            let mut sorted = children.to_vec();
+            sorted.sort_by(|&a, &b| identifiers[a].cmp(&identifiers[b]));
+            sorted_children[index] = sorted;
+        }
+
+        let mut path_table_order = vec![];
+        let mut queue = VecDeque::from([0]);
+        while let Some(directory) = queue.pop_front() {
+            path_table_order.push(directory);
+            queue.extend(sorted_children[directory].iter().filter(|&&child| nodes[child].children.is_some()));
+        }
+        if path_table_order.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} directories are too many for an ISO 9660 path table", path_table_order.len()),
+            ));
+        }
+        let mut hierarchy = Hierarchy {
+            flavor,
+            identifiers,
+            sorted_children,
+            path_table_order,
+            directory_sizes: vec![0; nodes.len()],
+            path_table_size: 0,
+        };
+        // the lengths of the records do not depend on where things are
+        let no_sectors = vec![0; nodes.len()];
+        for &directory in &hierarchy.path_table_order {
+            hierarchy.directory_sizes[directory] = hierarchy.directory(nodes, directory, &no_sectors, &no_sectors).len() as u32;
+        }
+        hierarchy.path_table_size = hierarchy.path_table(nodes, &no_sectors, false).len() as u32;
+        Ok(hierarchy)
+    }
+
+    fn path_table_sectors(&self) -> u32 {
+        (self.path_table_size as usize).div_ceil(SECTOR_SIZE) as u32
+    }
+
+    /// The sectors of the L and M path tables and then the directories
+    fn sectors(&self) -> u32 {
+        2 * self.path_table_sectors() + self.directory_sizes.iter().sum::<u32>() / SECTOR_SIZE as u32
+    }
+
+    /// ECMA-119 9.4 Path Table, little or big endian
+    fn path_table(&self, nodes: &[IsoNode], directory_sectors: &[u32], big_endian: bool) -> Vec<u8> {
+        let mut table = vec![];
+        for &directory in &self.path_table_order {
+            let identifier: &[u8] = if directory == 0 { &[0] } else { &self.identifiers[directory] };
+            let parent_number = self.path_table_order.iter().position(|&d| d == nodes[directory].parent).unwrap() + 1;
+            let (location, parent_number) = if big_endian {
+                (directory_sectors[directory].to_be_bytes(), (parent_number as u16).to_be_bytes())
+            } else {
+                (directory_sectors[directory].to_le_bytes(), (parent_number as u16).to_le_bytes())
+            };
+            table.push(identifier.len() as u8);
+            table.push(0);
+            table.extend_from_slice(&location);
+            table.extend_from_slice(&parent_number);
+            table.extend_from_slice(identifier);
+            if identifier.len() % 2 == 1 {
+                table.push(0);
+            }
+        }
+        table
+    }
+
+    /// The records of a directory, none of which crosses a sector boundary (ECMA-119 6.8.1.1)
+    fn directory(&self, nodes: &[IsoNode], directory: usize, directory_sectors: &[u32], file_sectors: &[u32]) -> Vec<u8> {
+        let node = &nodes[directory];
+        let mut records = vec![
+            directory_record(&[0], directory_sectors[directory], self.directory_sizes[directory] as u64, FLAG_DIRECTORY, node.modified),
+            directory_record(
+                &[1],
+                directory_sectors[node.parent],
+                self.directory_sizes[node.parent] as u64,
+                FLAG_DIRECTORY,
+                nodes[node.parent].modified,
+            ),
+        ];
+        for &child in &self.sorted_children[directory] {
+            let identifier = &self.identifiers[child];
+            let entry = &nodes[child];
+            if entry.children.is_some() {
+                records.push(directory_record(
+                    identifier,
+                    directory_sectors[child],
+                    self.directory_sizes[child] as u64,
+                    FLAG_DIRECTORY,
+                    entry.modified,
+                ));
+                continue;
+            }
+            if entry.length == 0 {
+                records.push(directory_record(identifier, 0, 0, 0, entry.modified));
+            }
+            let mut offset = 0;
+            while offset < entry.length {
+                let length = (entry.length - offset).min(MAX_EXTENT_LENGTH);
+                let flags = if offset + length < entry.length { FLAG_MULTI_EXTENT } else { 0 };
+                let sector = file_sectors[child] + (offset / SECTOR_SIZE as u64) as u32;
+                records.push(directory_record(identifier, sector, length, flags, entry.modified));
+                offset += length;
+            }
+        }
+        let mut data = vec![];
+        for record in records {
+            if data.len() % SECTOR_SIZE + record.len() > SECTOR_SIZE {
+                data.resize(data.len().next_multiple_of(SECTOR_SIZE), 0);
+            }
+            data.extend_from_slice(&record);
+        }
+        data.resize(data.len().next_multiple_of(SECTOR_SIZE), 0);
+        data
+    }
+
+    /// ECMA-119 8.4 Primary Volume Descriptor, or for Joliet a Supplementary Volume Descriptor
+    fn volume_descriptor(&self, volume: &BridgeVolume, path_tables: u32, root_sector: u32) -> Vec<u8> {
+        let mut descriptor = vec![0u8; SECTOR_SIZE];
+        descriptor[0] = if self.flavor == Flavor::Joliet { 2 } else { 1 };
+        descriptor[1..6].copy_from_slice(b"CD001");
+        descriptor[6] = 1;
+        let text = |field: &mut [u8], s: &str| self.flavor.text(field, s);
+        text(&mut descriptor[8..40], "");
+        let volume_identifier = self.flavor.volume_identifier(volume.identifier);
+        text(&mut descriptor[40..72], &volume_identifier);
+        descriptor[80..88].copy_from_slice(&both_u32(volume.sectors));
+        if self.flavor == Flavor::Joliet {
+            // UCS-2 level 3
+            descriptor[88..91].copy_from_slice(b"%/E");
+        }
+        descriptor[120..124].copy_from_slice(&both_u16(1));
+        descriptor[124..128].copy_from_slice(&both_u16(1));
+        descriptor[128..132].copy_from_slice(&both_u16(SECTOR_SIZE as u16));
+        descriptor[132..140].copy_from_slice(&both_u32(self.path_table_size));
+        descriptor[140..144].copy_from_slice(&path_tables.to_le_bytes());
+        descriptor[148..152].copy_from_slice(&(path_tables + self.path_table_sectors()).to_be_bytes());
+        let root = directory_record(&[0], root_sector, self.directory_sizes[0] as u64, FLAG_DIRECTORY, volume.recording_time);
+        descriptor[156..190].copy_from_slice(&root);
+        text(&mut descriptor[190..318], &volume_identifier);
+        for field in [318..446, 446..574, 702..739, 739..776, 776..813] {
+            text(&mut descriptor[field], "");
+        }
+        text(&mut descriptor[574..702], "DVDROMCOPY");
+        descriptor[813..830].copy_from_slice(&volume_date(Some(volume.recording_time)));
+        descriptor[830..847].copy_from_slice(&volume_date(Some(volume.recording_time)));
+        descriptor[847..864].copy_from_slice(&volume_date(None));
+        descriptor[864..881].copy_from_slice(&volume_date(None));
+        descriptor[881] = 1;
+        descriptor
+    }
+}
+
+/// Rewrite the volume identifier of an ISO 9660 Primary or Joliet Supplementary Volume
+/// Descriptor, returning whether the sector was one; other Supplementary Volume Descriptors
+/// are left alone
+pub(crate) fn relabel_volume_descriptor(descriptor: &mut [u8], identifier: &str) -> bool {
+    if &descriptor[1..6] != b"CD001" {
+        return false;
+    }
+    let flavor = match descriptor[0] {
+        1 => Flavor::Iso9660,
+        2 if &descriptor[88..91] == b"%/E" => Flavor::Joliet,
+        _ => return false,
+    };
+    let volume_identifier = flavor.volume_identifier(identifier);
+    flavor.text(&mut descriptor[40..72], &volume_identifier);
+    true
+}
+
+/// What the volume descriptors record about the image
+pub(crate) struct BridgeVolume<'a> {
+    pub identifier: &'a str,
+    pub recording_time: SystemTime,
+    /// the size of the whole image
+    pub sectors: u32,
+}
+
+/// The ISO 9660 and Joliet hierarchies of a tree
+pub(crate) struct BridgeLayout {
+    hierarchies: [Hierarchy; 2],
+}
+
+impl BridgeLayout {
+    pub(crate) fn new(nodes: &[IsoNode]) -> io::Result<Self> {
+        Ok(BridgeLayout {
+            hierarchies: [Hierarchy::new(nodes, Flavor::Iso9660)?, Hierarchy::new(nodes, Flavor::Joliet)?],
+        })
+    }
+
+    /// The sectors needed for the path tables and directories
+    pub(crate) fn sectors(&self) -> u32 {
+        self.hierarchies.iter().map(Hierarchy::sectors).sum()
+    }
+
+    /// Write the volume descriptors to sectors 16 to 18 of head, and the path tables and
+    /// directories to the sectors starting at first_sector, where file_sectors are the sectors
+    /// of the files' data
+    pub(crate) fn write(&self, nodes: &[IsoNode], file_sectors: &[u32], head: &mut [u8], first_sector: u32, volume: &BridgeVolume) {
+        let mut sector = first_sector;
+        let mut write_sectors = |sector: u32, bytes: &[u8]| {
+            let start = sector as usize * SECTOR_SIZE;
+            head[start..start + bytes.len()].copy_from_slice(bytes);
+        };
+        for (descriptor_sector, hierarchy) in (VOLUME_DESCRIPTOR_SECTOR..).zip(&self.hierarchies) {
+            let path_tables = sector;
+            sector += 2 * hierarchy.path_table_sectors();
+            let mut directory_sectors = vec![0; nodes.len()];
+            for &directory in &hierarchy.path_table_order {
+                directory_sectors[directory] = sector;
+                sector += hierarchy.directory_sizes[directory] / SECTOR_SIZE as u32;
+            }
+            write_sectors(path_tables, &hierarchy.path_table(nodes, &directory_sectors, false));
+            write_sectors(
+                path_tables + hierarchy.path_table_sectors(),
+                &hierarchy.path_table(nodes, &directory_sectors, true),
+            );
+            for &directory in &hierarchy.path_table_order {
+                write_sectors(directory_sectors[directory], &hierarchy.directory(nodes, directory, &directory_sectors, file_sectors));
+            }
+            write_sectors(descriptor_sector, &hierarchy.volume_descriptor(volume, path_tables, directory_sectors[0]));
+        }
+        // ECMA-119 8.3 Volume Descriptor Set Terminator
+        let mut terminator = vec![0u8; SECTOR_SIZE];
+        terminator[0] = 255;
+        terminator[1..6].copy_from_slice(b"CD001");
+        terminator[6] = 1;
+        write_sectors(VOLUME_DESCRIPTOR_SECTOR + 2, &terminator);
+    }
+}
+
+/// A bound on the volume descriptors read before the Volume Descriptor Set Terminator
+const MAX_VOLUME_DESCRIPTORS: u32 = 32;
+/// A bound on the size of a directory, far more than the records of thousands of files
+const MAX_DIRECTORY_SIZE: u32 = 16 * 1024 * 1024;
+/// The escape sequences of a Joliet Supplementary Volume Descriptor: UCS-2 levels 1 to 3
+const JOLIET_ESCAPE_SEQUENCES: [&[u8; 3]; 3] = [b"%/@", b"%/C", b"%/E"];
+
+/// The hierarchy of an ISO 9660 volume that read_volume chose: the Joliet one if there is
+/// one, which has the names as they were mastered, and otherwise the ISO 9660 one
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsoVolume {
+    pub identifier: String,
+    pub joliet: bool,
+    /// the size of the volume
+    pub sectors: u32,
+    path_table_size: u32,
+    /// the sector of the little-endian path table
+    path_table_sector: u32,
+}
+
+/// A file of an ISO 9660 volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsoFile {
+    /// '/'-separated, without the version number, e.g. VIDEO_TS/VIDEO_TS.IFO
+    pub path: String,
+    /// the sector and length in bytes of each extent, of which files of 4 GB or more have several
+    pub extents: Vec<(u32, u32)>,
+    pub modified: Timestamp,
+}
+
+impl IsoFile {
+    pub fn size(&self) -> u64 {
+        self.extents.iter().map(|&(_, length)| length as u64).sum()
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_sectors<R: Read + Seek>(reader: &mut R, sector: u32, length: u32) -> io::Result<Vec<u8>> {
+    let mut data = vec![0u8; (length as usize).next_multiple_of(SECTOR_SIZE)];
+    reader.seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE as u64))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Read the volume descriptors from sector 16 to the Volume Descriptor Set Terminator
+/// (ECMA-119 8), failing if there is no Primary Volume Descriptor
+pub fn read_volume<R: Read + Seek>(reader: &mut R) -> io::Result<IsoVolume> {
+    let mut primary = None;
+    let mut joliet = None;
+    for sector in VOLUME_DESCRIPTOR_SECTOR..VOLUME_DESCRIPTOR_SECTOR + MAX_VOLUME_DESCRIPTORS {
+        let descriptor = read_sectors(reader, sector, SECTOR_SIZE as u32)?;
+        if &descriptor[1..6] != b"CD001" {
+            break;
+        }
+        match descriptor[0] {
+            1 if primary.is_none() => primary = Some(descriptor),
+            2 if joliet.is_none() && JOLIET_ESCAPE_SEQUENCES.iter().any(|sequence| &descriptor[88..91] == *sequence) => {
+                joliet = Some(descriptor)
+            }
+            255 => break,
+            _ => {}
+        }
+    }
+    let (descriptor, flavor) = match (joliet, primary) {
+        (Some(descriptor), Some(_)) => (descriptor, Flavor::Joliet),
+        (None, Some(descriptor)) => (descriptor, Flavor::Iso9660),
+        (_, None) => return Err(invalid_data("no ISO 9660 Primary Volume Descriptor was found at sector 16".to_string())),
+    };
+    let u32_at = |pos: usize| u32::from_le_bytes(descriptor[pos..pos + 4].try_into().unwrap());
+    let logical_block_size = u16::from_le_bytes([descriptor[128], descriptor[129]]);
+    if logical_block_size as usize != SECTOR_SIZE {
+        return Err(invalid_data(format!("ISO 9660 logical blocks of {} bytes are not supported", logical_block_size)));
+    }
+    Ok(IsoVolume {
+        identifier: flavor.decode(&descriptor[40..72]).trim_end().to_string(),
+        joliet: flavor == Flavor::Joliet,
+        sectors: u32_at(80),
+        path_table_size: u32_at(132),
+        path_table_sector: u32_at(140),
+    })
+}
+
+/// The files of volume: the directories are found through its path table (ECMA-119 9.4), and
+/// their files through their Directory Records (ECMA-119 9.1). They are in the order of their
+/// paths, so that each directory's files and subdirectories are in the order of their names.
+pub fn read_files<R: Read + Seek>(reader: &mut R, volume: &IsoVolume) -> io::Result<Vec<IsoFile>> {
+    let flavor = if volume.joliet { Flavor::Joliet } else { Flavor::Iso9660 };
+    let path_table = read_sectors(reader, volume.path_table_sector, volume.path_table_size)?;
+    let path_table = &path_table[..volume.path_table_size as usize];
+    // the path and sector of each directory, by directory number - 1
+    let mut directories: Vec<(String, u32)> = vec![];
+    let mut pos = 0;
+    while pos + 8 <= path_table.len() {
+        let identifier_length = path_table[pos] as usize;
+        let sector = u32::from_le_bytes(path_table[pos + 2..pos + 6].try_into().unwrap());
+        let parent = u16::from_le_bytes([path_table[pos + 6], path_table[pos + 7]]) as usize;
+        let identifier = path_table
+            .get(pos + 8..pos + 8 + identifier_length)
+            .ok_or_else(|| invalid_data("the ISO 9660 path table ends within a record".to_string()))?;
+        pos += 8 + identifier_length + identifier_length % 2;
+        if directories.is_empty() {
+            directories.push((String::new(), sector));
+            continue;
+        }
+        // ECMA-119 6.9.1: a directory's parent comes before it
+        let Some((parent_path, _)) = parent.checked_sub(1).and_then(|parent| directories.get(parent)) else {
+            return Err(invalid_data(format!("directory {} of the ISO 9660 path table has no parent", directories.len() + 1)));
+        };
+        let name = safe_name(flavor.decode(identifier))?;
+        let path = if parent_path.is_empty() { name } else { format!("{}/{}", parent_path, name) };
+        directories.push((path, sector));
+    }
+
+    let mut files: Vec<IsoFile> = vec![];
+    for (directory, sector) in &directories {
+        // the length of a directory is that of its first record, for the directory itself
+        let first = read_sectors(reader, *sector, SECTOR_SIZE as u32)?;
+        let length = u32::from_le_bytes(first[10..14].try_into().unwrap());
+        if length > MAX_DIRECTORY_SIZE {
+            return Err(invalid_data(format!("the ISO 9660 directory {:?} claims to be {} bytes", directory, length)));
+        }
+        let records = read_sectors(reader, *sector, length)?;
+        let records = &records[..length as usize];
+        // a file of several extents has a record for each, all but the last flagged
+        let mut continued = false;
+        let mut pos = 0;
+        while pos < records.len() {
+            let record_length = records[pos] as usize;
+            if record_length == 0 {
+                // records do not cross sectors, and the rest of this one is padding
+                pos = (pos + 1).next_multiple_of(SECTOR_SIZE);
+                continue;
+            }
+            let record = records
+                .get(pos..pos + record_length)
+                .filter(|record| record.len() >= 33 && 33 + record[32] as usize <= record.len())
+                .ok_or_else(|| invalid_data(format!("a record of the ISO 9660 directory {:?} is damaged", directory)))?;
+            pos += record_length;
+            let flags = record[25];
+            let identifier = &record[33..33 + record[32] as usize];
+            if flags & FLAG_DIRECTORY != 0 || identifier == [0] || identifier == [1] {
+                continue;
+            }
+            let extent = (
+                u32::from_le_bytes(record[2..6].try_into().unwrap()),
+                u32::from_le_bytes(record[10..14].try_into().unwrap()),
+            );
+            match files.last_mut() {
+                Some(file) if continued => file.extents.push(extent),
+                _ => {
+                    let name = safe_name(file_name(&flavor.decode(identifier)))?;
+                    files.push(IsoFile {
+                        path: if directory.is_empty() { name } else { format!("{}/{}", directory, name) },
+                        extents: vec![extent],
+                        modified: recording_time(&record[18..25]),
+                    });
+                }
+            }
+            continued = flags & FLAG_MULTI_EXTENT != 0;
+        }
+    }
+    files.sort_by(|a, b| a.path.split('/').cmp(b.path.split('/')));
+    Ok(files)
+}
+
+/// The name of a file without its version number, and without the separator of a name that
+/// has no extension (ECMA-119 7.5.1)
+fn file_name(identifier: &str) -> String {
+    let name = identifier.split_once(';').map_or(identifier, |(name, _)| name);
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+/// The name, or an error if it could climb out of the copy
+fn safe_name(name: String) -> io::Result<String> {
+    if !is_safe_file_identifier(&name) {
+        return Err(invalid_data(format!("unsafe ISO 9660 file identifier {:?}", name)));
+    }
+    Ok(name)
+}
+
+/// ECMA-119 9.1.5 Recording Date and Time: years since 1900, and the offset from UTC in
+/// 15 minute intervals
+fn recording_time(bytes: &[u8]) -> Timestamp {
+    let offset_minutes = bytes[6] as i8 as i16 * 15;
+    Timestamp {
+        // type 1 (local time) with its offset from UTC
+        type_and_timezone: 0x1000 | (offset_minutes as u16 & 0x0FFF),
+        year: 1900 + bytes[0] as u16,
+        month: bytes[1],
+        day: bytes[2],
+        hour: bytes[3],
+        minute: bytes[4],
+        second: bytes[5],
+        ..Default::default()
+    }
+}
+
+/// ECMA-119 7.3.3 both-byte orders
+fn both_u32(value: u32) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    bytes[0..4].copy_from_slice(&value.to_le_bytes());
+    bytes[4..8].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+fn both_u16(value: u16) -> [u8; 4] {
+    let mut bytes = [0; 4];
+    bytes[0..2].copy_from_slice(&value.to_le_bytes());
+    bytes[2..4].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+/// ECMA-119 9.1 Directory Record
+fn directory_record(identifier: &[u8], sector: u32, length: u64, flags: u8, modified: SystemTime) -> Vec<u8> {
+    let length_of_record = 33 + identifier.len() + (identifier.len() + 1) % 2;
+    let mut record = vec![0u8; length_of_record];
+    record[0] = length_of_record as u8;
+    record[2..10].copy_from_slice(&both_u32(sector));
+    record[10..18].copy_from_slice(&both_u32(length as u32));
+    // ECMA-119 9.1.5 in UTC
+    let time = Timestamp::from_system_time(modified);
+    let year = time.year.saturating_sub(1900).min(255) as u8;
+    record[18..25].copy_from_slice(&[year, time.month, time.day, time.hour, time.minute, time.second, 0]);
+    record[25] = flags;
+    record[28..32].copy_from_slice(&both_u16(1));
+    record[32] = identifier.len() as u8;
+    record[33..33 + identifier.len()].copy_from_slice(identifier);
+    record
+}
+
+/// ECMA-119 8.4.26.1 in UTC, or all zero digits if there is no date
+fn volume_date(time: Option<SystemTime>) -> [u8; 17] {
+    let digits = match time.map(Timestamp::from_system_time) {
+        Some(t) => format!(
+            "{:04}{:02}{:02}{:02}{:02}{:02}{:02}",
+            t.year, t.month, t.day, t.hour, t.minute, t.second, t.centiseconds
+        ),
+        None => "0".repeat(16),
+    };
+    let mut date = [0; 17];
+    date[..16].copy_from_slice(digits.as_bytes());
+    date
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifiers() {
+        let iso = |name, is_directory, number| String::from_utf8(Flavor::Iso9660.identifier(name, is_directory, number)).unwrap();
+        assert_eq!(iso("VIDEO_TS.IFO", false, None), "VIDEO_TS.IFO;1");
+        assert_eq!(iso("VIDEO_TS", true, None), "VIDEO_TS");
+        assert_eq!(iso("Résumé.txt", false, None), "R_SUM_.TXT;1");
+        assert_eq!(iso("README", false, None), "README.;1");
+        assert_eq!(iso("a very long name for a file.text", false, Some(2)), "A_VERY_LONG_NAME_FOR_A_~2.TEXT;1");
+        assert_eq!(Flavor::Joliet.identifier("Résumé.txt", false, None), Flavor::Joliet.encode("Résumé.txt;1"));
+        assert_eq!(Flavor::Joliet.identifier("a:b", true, None), Flavor::Joliet.encode("a_b"));
+    }
+
+    #[test]
+    fn test_write() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_050_085_500);
+        let video_ts_children = [2, 3];
+        let root_children = [1, 4, 5];
+        let node = |name, parent, children, length| IsoNode { name, parent, children, modified: time, length };
+        let nodes = [
+            node("", 0, Some(&root_children[..]), 0),
+            node("VIDEO_TS", 0, Some(&video_ts_children[..]), 0),
+            node("VIDEO_TS.IFO", 1, None, 12288),
+            node("VTS_01_1.VOB", 1, None, 5 * MAX_EXTENT_LENGTH / 2),
+            node("AUDIO_TS", 0, Some(&[]), 0),
+            node("readme.txt", 0, None, 0),
+        ];
+        let layout = BridgeLayout::new(&nodes).unwrap();
+        // each hierarchy has path tables of one sector and three directories of one sector each
+        assert_eq!(layout.sectors(), 2 * (2 + 3));
+        let mut head = vec![0u8; 40 * SECTOR_SIZE];
+        let file_sectors = [0, 0, 1000, 1006, 0, 0];
+        let volume = BridgeVolume { identifier: "Funfancy", recording_time: time, sectors: 5_000_000 };
+        layout.write(&nodes, &file_sectors, &mut head, 30, &volume);
+
+        let sector = |n: usize| &head[n * SECTOR_SIZE..(n + 1) * SECTOR_SIZE];
+        let pvd = sector(16);
+        assert_eq!(&pvd[0..7], b"\x01CD001\x01");
+        assert_eq!(&pvd[40..48], b"FUNFANCY");
+        assert_eq!(&pvd[80..88], &both_u32(5_000_000));
+        assert_eq!(&pvd[813..829], b"2003041118250000");
+        assert_eq!(&sector(17)[0..7], b"\x02CD001\x01");
+        assert_eq!(&sector(17)[88..91], b"%/E");
+        assert_eq!(&sector(18)[0..7], b"\xffCD001\x01");
+        // the path tables at 30 and 31, then the root, AUDIO_TS and VIDEO_TS
+        assert_eq!(u32::from_le_bytes(pvd[140..144].try_into().unwrap()), 30);
+        assert_eq!(u32::from_be_bytes(pvd[148..152].try_into().unwrap()), 31);
+        assert_eq!(&pvd[158..162], &32u32.to_le_bytes());
+        let l_table = sector(30);
+        assert_eq!(&l_table[0..8], &[1, 0, 32, 0, 0, 0, 1, 0]);
+        assert_eq!(&l_table[10..18], &[8, 0, 33, 0, 0, 0, 1, 0]);
+        assert_eq!(&l_table[18..26], b"AUDIO_TS");
+
+        let records = |n: usize| {
+            let mut data = sector(n);
+            let mut records = vec![];
+            while data[0] != 0 {
+                let (record, rest) = data.split_at(data[0] as usize);
+                let identifier = String::from_utf8_lossy(&record[33..33 + record[32] as usize]).to_string();
+                let location = u32::from_le_bytes(record[2..6].try_into().unwrap());
+                let length = u32::from_le_bytes(record[10..14].try_into().unwrap());
+                records.push((identifier, location, length, record[25]));
+                data = rest;
+            }
+            records
+        };
+        let root = records(32);
+        assert_eq!(root[2], ("AUDIO_TS".to_string(), 33, 2048, FLAG_DIRECTORY));
+        assert_eq!(root[3], ("README.TXT;1".to_string(), 0, 0, 0));
+        assert_eq!(root[4], ("VIDEO_TS".to_string(), 34, 2048, FLAG_DIRECTORY));
+        let video_ts = records(34);
+        assert_eq!(video_ts[1], ("\u{1}".to_string(), 32, 2048, FLAG_DIRECTORY));
+        assert_eq!(video_ts[2], ("VIDEO_TS.IFO;1".to_string(), 1000, 12288, 0));
+        let vob_extent = MAX_EXTENT_LENGTH as u32;
+        assert_eq!(video_ts[3], ("VTS_01_1.VOB;1".to_string(), 1006, vob_extent, FLAG_MULTI_EXTENT));
+        assert_eq!(video_ts[4].1, 1006 + vob_extent / 2048);
+        assert_eq!(video_ts[5], ("VTS_01_1.VOB;1".to_string(), 1006 + 2 * (vob_extent / 2048), vob_extent / 2, 0));
+        // the Joliet hierarchy follows, with the names as they are
+        let joliet_root = &sector(37)[68..];
+        assert_eq!(&joliet_root[33..49], &Flavor::Joliet.encode("AUDIO_TS")[..]);
+    }
+
+    #[test]
+    fn test_read_files() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_050_085_500);
+        let video_ts_children = [2, 3];
+        let root_children = [1, 4, 5];
+        let node = |name, parent, children, length| IsoNode { name, parent, children, modified: time, length };
+        let nodes = [
+            node("", 0, Some(&root_children[..]), 0),
+            node("VIDEO_TS", 0, Some(&video_ts_children[..]), 0),
+            node("VIDEO_TS.IFO", 1, None, 12288),
+            node("VTS_01_1.VOB", 1, None, 5 * MAX_EXTENT_LENGTH / 2),
+            node("Extras", 0, Some(&[]), 0),
+            node("read me.txt", 0, None, 3),
+        ];
+        let layout = BridgeLayout::new(&nodes).unwrap();
+        let mut head = vec![0u8; 40 * SECTOR_SIZE];
+        let file_sectors = [0, 0, 1000, 1006, 0, 1005];
+        let volume = BridgeVolume { identifier: "Funfancy", recording_time: time, sectors: 5_000_000 };
+        layout.write(&nodes, &file_sectors, &mut head, 30, &volume);
+
+        let mut reader = io::Cursor::new(head);
+        let volume = read_volume(&mut reader).unwrap();
+        assert_eq!((volume.identifier.as_str(), volume.joliet, volume.sectors), ("Funfancy", true, 5_000_000));
+        let files = read_files(&mut reader, &volume).unwrap();
+        let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, ["VIDEO_TS/VIDEO_TS.IFO", "VIDEO_TS/VTS_01_1.VOB", "read me.txt"]);
+        assert_eq!(files[0].extents, [(1000, 12288)]);
+        assert_eq!(files[0].modified.system_time(), time);
+        // the VOB's three extents are one file
+        assert_eq!(files[1].extents.len(), 3);
+        assert_eq!(files[1].size(), 5 * MAX_EXTENT_LENGTH / 2);
+
+        // without the Joliet descriptor, the ISO 9660 names
+        let mut head = reader.into_inner();
+        head[17 * SECTOR_SIZE..18 * SECTOR_SIZE].fill(0);
+        let mut reader = io::Cursor::new(head);
+        let volume = read_volume(&mut reader).unwrap();
+        assert_eq!((volume.identifier.as_str(), volume.joliet), ("FUNFANCY", false));
+        let files = read_files(&mut reader, &volume).unwrap();
+        assert_eq!(files[0].path, "READ_ME.TXT");
+
+        assert!(read_volume(&mut io::Cursor::new(vec![0u8; 40 * SECTOR_SIZE])).is_err());
+    }
+}
@@ -1,7 +1,58 @@
 pub mod udf;
 pub mod udf_parser;
 pub mod udf_high_level;
+pub mod udf_file;
+pub mod udf_writer;
+pub mod vat;
+pub mod iso9660;
+pub mod repair;
+pub mod patch;
+pub mod recover;
+pub mod relabel;
+pub mod sanitize;
+pub mod attributes;
+pub mod symlink;
+pub mod interrupt;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
 pub mod dvdcss_sys;
+pub mod device;
+pub mod drive_region;
+pub mod partition_table;
+pub mod http_image;
 pub mod crc;
 pub mod cache;
 pub mod logical_block_reader;
+pub mod manifest;
+pub mod dfxml;
+pub mod listing;
+pub mod path_filter;
+pub mod info;
+pub mod copier;
+pub mod verify;
+pub mod dvdvideo;
+pub mod dvdaudio;
+pub mod dvdid;
+pub mod mpeg_ps;
+pub mod nav_check;
+pub mod matroska;
+pub mod remux;
+pub mod transcode;
+pub mod sparse_image;
+pub mod disc_image;
+pub mod rescue_map;
+pub mod title_keys;
+pub mod sidecar;
+pub mod output;
+pub mod copy_offload;
+pub mod free_space;
+pub mod progress;
+pub mod pipeline;
+pub mod compress;
+pub mod chunked;
+pub mod s3;
+pub mod sftp;
+pub mod exec_hook;
+pub mod serve;
+pub mod mount;
+pub mod metrics;
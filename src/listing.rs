@@ -0,0 +1,208 @@
+//! `list --format csv`: one row per file of the disc, for cataloging discs in
+//! spreadsheets and databases; `list --format tree` and `list --json` show the same entries
+//! indented under their directories and as JSON.
+
+use serde::Serialize;
+
+use crate::{
+    dvdaudio::parse_audio_title_set_ifo_path,
+    dvdvideo::{parse_title_set_ifo_path, parse_title_vob_path},
+    progress::format_size,
+};
+
+pub const CSV_HEADER: &str = "path,size,modified,accessed,attributes_changed,type,extents";
+
+/// A file of the disc as listed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ListEntry {
+    /// '/'-separated path on the disc
+    pub path: String,
+    pub size: u64,
+    /// ISO 8601 times
+    pub modified: String,
+    pub accessed: String,
+    pub attributes_changed: String,
+    /// number of allocation descriptors (contiguous runs on the disc)
+    pub extents: usize,
+}
+impl ListEntry {
+    /// The CSV line (without line ending) with the columns of CSV_HEADER
+    pub fn csv_row(&self) -> String {
+        [
+            csv_field(&self.path),
+            self.size.to_string(),
+            csv_field(&self.modified),
+            csv_field(&self.accessed),
+            csv_field(&self.attributes_changed),
+            file_type(&self.path).to_string(),
+            self.extents.to_string(),
+        ]
+        .join(",")
+    }
+}
+
+/// A ListEntry with its file type, as list --json prints it
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a ListEntry,
+    #[serde(rename = "type")]
+    file_type: &'static str,
+}
+
+/// The entries as a JSON array of objects with the fields of ListEntry and a type
+pub fn json(entries: &[ListEntry]) -> serde_json::Result<String> {
+    let entries: Vec<JsonEntry> = (entries.iter())
+        .map(|entry| JsonEntry {
+            entry,
+            file_type: file_type(&entry.path),
+        })
+        .collect();
+    serde_json::to_string_pretty(&entries)
+}
+
+/// The entries indented under their directories, each with its size, type and modification
+/// time in columns. A directory is listed before its first entry, and entries are kept in
+/// their order.
+pub fn tree_lines(entries: &[ListEntry]) -> Vec<String> {
+    const INDENT: &str = "  ";
+    let mut rows = vec![];
+    let mut directories: &[&str] = &[];
+    let components: Vec<Vec<&str>> = entries.iter().map(|entry| entry.path.split('/').collect()).collect();
+    for (entry, components) in entries.iter().zip(&components) {
+        let (name, parents) = components.split_last().expect("split yields at least one component");
+        let common = (directories.iter().zip(parents.iter()))
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, directory) in parents.iter().enumerate().skip(common) {
+            rows.push((format!("{}{}/", INDENT.repeat(depth), directory), None));
+        }
+        directories = parents;
+        rows.push((format!("{}{}", INDENT.repeat(parents.len()), name), Some(entry)));
+    }
+    let name_width = rows.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+    let size_width = (entries.iter())
+        .map(|entry| format_size(entry.size).len())
+        .max()
+        .unwrap_or(0);
+    let type_width = (entries.iter())
+        .map(|entry| file_type(&entry.path).len())
+        .max()
+        .unwrap_or(0);
+    rows.into_iter()
+        .map(|(name, entry)| match entry {
+            None => name,
+            Some(entry) => format!(
+                "{:name_width$}  {:>size_width$}  {:type_width$}  {}",
+                name,
+                format_size(entry.size),
+                file_type(&entry.path),
+                entry.modified
+            ),
+        })
+        .collect()
+}
+
+/// The role of a file in the DVD-Video or DVD-Audio structure, or "other"
+pub fn file_type(path: &str) -> &'static str {
+    let is_backup = path.ends_with(".BUP");
+    let ifo_path = match path.strip_suffix(".BUP") {
+        Some(stem) => format!("{}.IFO", stem),
+        None => path.to_string(),
+    };
+    let file_type = match ifo_path.as_str() {
+        "VIDEO_TS/VIDEO_TS.IFO" => "video manager IFO",
+        "VIDEO_TS/VIDEO_TS.VOB" => "video manager menu VOB",
+        "AUDIO_TS/AUDIO_TS.IFO" => "audio manager IFO",
+        _ if parse_title_set_ifo_path(&ifo_path).is_some() => "title set IFO",
+        _ if parse_audio_title_set_ifo_path(&ifo_path).is_some() => "audio title set IFO",
+        _ if parse_title_vob_path(path).is_some() => "title VOB",
+        _ if path.starts_with("VIDEO_TS/VTS_") && path.ends_with("_0.VOB") => "title set menu VOB",
+        _ if path.starts_with("AUDIO_TS/ATS_") && path.ends_with(".AOB") => "audio object",
+        _ => "other",
+    };
+    match (is_backup, file_type) {
+        (true, "video manager IFO") => "video manager BUP",
+        (true, "audio manager IFO") => "audio manager BUP",
+        (true, "title set IFO") => "title set BUP",
+        (true, "audio title set IFO") => "audio title set BUP",
+        (true, _) => "other",
+        (false, file_type) => file_type,
+    }
+}
+
+/// Quote a field if it contains a comma, quote or line break (RFC 4180)
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_type() {
+        assert_eq!(file_type("VIDEO_TS/VIDEO_TS.IFO"), "video manager IFO");
+        assert_eq!(file_type("VIDEO_TS/VIDEO_TS.BUP"), "video manager BUP");
+        assert_eq!(file_type("VIDEO_TS/VTS_03_0.BUP"), "title set BUP");
+        assert_eq!(file_type("VIDEO_TS/VTS_03_0.VOB"), "title set menu VOB");
+        assert_eq!(file_type("VIDEO_TS/VTS_03_2.VOB"), "title VOB");
+        assert_eq!(file_type("AUDIO_TS/ATS_01_0.IFO"), "audio title set IFO");
+        assert_eq!(file_type("AUDIO_TS/ATS_01_1.AOB"), "audio object");
+        assert_eq!(file_type("JACKET_P/J00___5L.MP2"), "other");
+        assert_eq!(file_type("README.BUP"), "other");
+    }
+
+    #[test]
+    fn test_tree_lines() {
+        let entry = |path: &str, size| ListEntry {
+            path: path.to_string(),
+            size,
+            modified: "2003-04-11T18:25:00Z".to_string(),
+            ..Default::default()
+        };
+        let entries = [
+            entry("AUDIO_TS/README.TXT", 0),
+            entry("VIDEO_TS/VIDEO_TS.IFO", 12288),
+            entry("VIDEO_TS/VTS_01_1.VOB", 1073709056),
+            entry("VIDEO_TS/EXTRAS/A.TXT", 10),
+            entry("VIDEO_TS/VIDEO_TS.BUP", 12288),
+        ];
+        assert_eq!(
+            tree_lines(&entries),
+            [
+                "AUDIO_TS/",
+                "  README.TXT           0 B  other              2003-04-11T18:25:00Z",
+                "VIDEO_TS/",
+                "  VIDEO_TS.IFO    12.0 KiB  video manager IFO  2003-04-11T18:25:00Z",
+                "  VTS_01_1.VOB  1024.0 MiB  title VOB          2003-04-11T18:25:00Z",
+                "  EXTRAS/",
+                "    A.TXT             10 B  other              2003-04-11T18:25:00Z",
+                "  VIDEO_TS.BUP    12.0 KiB  video manager BUP  2003-04-11T18:25:00Z",
+            ]
+        );
+        let json: serde_json::Value = serde_json::from_str(&json(&entries[..1]).unwrap()).unwrap();
+        assert_eq!(json[0]["path"], "AUDIO_TS/README.TXT");
+        assert_eq!(json[0]["type"], "other");
+        assert_eq!(json[0]["size"], 0);
+    }
+
+    #[test]
+    fn test_csv_row() {
+        let entry = ListEntry {
+            path: "EXTRAS/Cast, \"Crew\".TXT".to_string(),
+            size: 2048,
+            modified: "2003-04-11T18:25:00+01:00".to_string(),
+            extents: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            entry.csv_row(),
+            r#""EXTRAS/Cast, ""Crew"".TXT",2048,2003-04-11T18:25:00+01:00,,,other,1"#
+        );
+    }
+}
@@ -1,15 +1,12 @@
-use std::{
-    collections::BTreeMap,
-    io::{Read, Seek},
-};
+use std::{collections::BTreeMap, ops::Range};
 
-use log::debug;
+use log::{debug, warn};
 
 use crate::{
-    cache::Cache,
+    cache::BlockDevice,
     dvdcss_sys::DVDCSS_BLOCK_SIZE,
-    udf::{LogicalVolumeDescriptor, LongAd, PartitionDescriptor, ShortAllocationDescriptor},
-    udf_parser::{Result, UdfError::InvalidPartitionNumber},
+    udf::{ExtentType, FileEntry, LogicalVolumeDescriptor, LongAd, PartitionDescriptor, ShortAllocationDescriptor, SparingTable},
+    udf_parser::{read_allocation_descriptors, Result, UdfError},
 };
 
 pub fn long_ad_to_sector_number(
@@ -28,15 +25,121 @@ pub fn long_ad_to_sector_number(
         None
     }
 }
-// this is wrong
-// pub fn long_ad_to_pos_in_partition(
-//     logical_volume_descriptor: &LogicalVolumeDescriptor,
-//     partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
-//     long_ad: &LongAd,
-// ) -> Option<usize> {
-//     long_ad_to_sector_number(logical_volume_descriptor, partition_descriptors, long_ad)
-//         .map(|sector| sector as usize * DVDCSS_BLOCK_SIZE as usize)
-// }
+/// The partition that a long_ad's extent is in and the byte offset of the extent in it
+pub fn long_ad_partition_pos<'p>(
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &'p BTreeMap<u16, PartitionDescriptor>,
+    long_ad: &LongAd,
+) -> Result<(&'p PartitionDescriptor, usize)> {
+    let partition_reference_number = long_ad.extent_location.partition_reference_number;
+    let partition_descriptor = partition_descriptors.get(&partition_reference_number).ok_or_else(|| {
+        warn!("no partition descriptor for partition reference number {}", partition_reference_number);
+        UdfError::InvalidPartitionNumber
+    })?;
+    let pos = long_ad.extent_location.logical_block_number as usize * logical_volume_descriptor.logical_block_size as usize;
+    Ok((partition_descriptor, pos))
+}
+/// The sectors past the end of any disc that the blocks of virtual partitions are read at.
+/// A virtual partition has a PartitionDescriptor that starts at virtual_partition_start, and a
+/// Cache reads each of its sectors from the physical block that its VAT maps it to.
+pub const VIRTUAL_SECTORS: u32 = 0x8000_0000;
+/// The sectors set aside for each virtual partition, more than a VAT can map
+pub const VIRTUAL_PARTITION_SECTORS: u32 = 0x0100_0000;
+
+/// A virtual partition (UDF 2.2.8) of a disc recorded incrementally: each of its blocks is the
+/// block of the physical partition that the Virtual Allocation Table maps it to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VirtualPartition {
+    /// the sector that the physical partition starts at
+    pub physical_start: u32,
+    /// the physical block of each virtual block, or u32::MAX for one that is not used
+    pub vat: Vec<u32>,
+}
+
+/// The sector that the virtual partition with partition reference number reference is read at
+pub fn virtual_partition_start(reference: u16) -> u32 {
+    VIRTUAL_SECTORS + reference as u32 * VIRTUAL_PARTITION_SECTORS
+}
+
+/// The sector that sector is recorded in: for one of a virtual partition, the sector of the
+/// physical block that its block is mapped to, and otherwise sector itself
+pub fn translate_virtual_sector(virtual_partitions: &BTreeMap<u16, VirtualPartition>, sector: u32) -> Result<u32> {
+    if sector < VIRTUAL_SECTORS {
+        return Ok(sector);
+    }
+    let reference = ((sector - VIRTUAL_SECTORS) / VIRTUAL_PARTITION_SECTORS) as u16;
+    let block = (sector - VIRTUAL_SECTORS) % VIRTUAL_PARTITION_SECTORS;
+    let virtual_partition = virtual_partitions.get(&reference).ok_or(UdfError::InvalidPartitionNumber)?;
+    match virtual_partition.vat.get(block as usize) {
+        Some(&physical_block) if physical_block != u32::MAX => Ok(virtual_partition.physical_start + physical_block),
+        _ => Err(UdfError::UnmappedVirtualBlock(reference, block)),
+    }
+}
+
+/// The sectors that sectors are recorded in, as translate_virtual_sector translates them, in
+/// runs of consecutive sectors
+pub fn translate_virtual_sectors(
+    virtual_partitions: &BTreeMap<u16, VirtualPartition>,
+    sectors: Range<u32>,
+) -> Result<Vec<Range<u32>>> {
+    if sectors.start < VIRTUAL_SECTORS {
+        return Ok(vec![sectors]);
+    }
+    let mut runs: Vec<Range<u32>> = vec![];
+    for sector in sectors {
+        let recorded = translate_virtual_sector(virtual_partitions, sector)?;
+        match runs.last_mut() {
+            Some(run) if run.end == recorded => run.end += 1,
+            _ => runs.push(recorded..recorded + 1),
+        }
+    }
+    Ok(runs)
+}
+
+/// A Sparable Partition (UDF 2.2.9) of a rewritable disc: the packets listed in its Sparing
+/// Table are read from the spare packets they were moved to
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SparablePartition {
+    /// the sector that the partition starts at
+    pub start: u32,
+    /// in sectors
+    pub length: u32,
+    /// in sectors
+    pub packet_length: u32,
+    /// the sector of each moved packet, by the block of the partition that the packet starts at
+    pub spared_packets: BTreeMap<u32, u32>,
+}
+impl SparablePartition {
+    /// The partition of partition_descriptor, with the moved packets of sparing_table
+    pub fn new(partition_descriptor: &PartitionDescriptor, packet_length: u16, sparing_table: &SparingTable) -> Self {
+        let spared_packets = (sparing_table.map_entries.iter())
+            .filter(|entry| entry.is_mapped())
+            .map(|entry| (entry.original_location, entry.mapped_location))
+            .collect();
+        Self {
+            start: partition_descriptor.partition_starting_location,
+            length: partition_descriptor.partition_length,
+            packet_length: packet_length.max(1) as u32,
+            spared_packets,
+        }
+    }
+}
+
+/// The sector that sector is recorded in: for one in a moved packet of a Sparable
+/// Partition, the sector at the same offset in the spare packet, and otherwise sector itself
+pub fn spared_sector(sparable_partitions: &[SparablePartition], sector: u32) -> u32 {
+    for partition in sparable_partitions {
+        let Some(block) = sector.checked_sub(partition.start).filter(|&block| block < partition.length) else {
+            continue;
+        };
+        let offset = block % partition.packet_length;
+        if let Some(&mapped) = partition.spared_packets.get(&(block - offset)) {
+            return mapped + offset;
+        }
+    }
+    sector
+}
+
 pub fn short_ad_to_pos_in_partition(
     logical_volume_descriptor: &LogicalVolumeDescriptor,
     short_ad: &ShortAllocationDescriptor,
@@ -46,8 +149,103 @@ pub fn short_ad_to_pos_in_partition(
     pos
 }
 
-pub fn read_exact_from_partition<R: Read + Seek, const BYTE_SIZE: usize>(
-    cache: &mut Cache<R, BYTE_SIZE>,
+/// The partition, byte offset in the partition and length of each extent of a file's data,
+/// from the allocation descriptors of its file entries but without the bytes past
+/// information_length. Short allocation descriptors are in the partition with
+/// partition_reference_number, that of the file's ICB, and long ones in the partition they name.
+/// An extent that ends past its partition is an error; extents shorter in total than
+/// information_length are only logged, as the data that they do have can still be copied.
+pub fn file_data_extents<'p>(
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &'p BTreeMap<u16, PartitionDescriptor>,
+    partition_reference_number: u16,
+    file_entries: &[FileEntry],
+    path: &str,
+) -> Result<Vec<(&'p PartitionDescriptor, usize, u64)>> {
+    let extents = file_extents(
+        logical_volume_descriptor,
+        partition_descriptors,
+        partition_reference_number,
+        file_entries,
+        path,
+    )?;
+    Ok((extents.into_iter())
+        .map(|(_, _, partition_descriptor, pos_in_partition, length)| (partition_descriptor, pos_in_partition, length))
+        .collect())
+}
+
+/// The extents of file_data_extents that are recorded, each with its offset in the file. The
+/// bytes of an extent that is only allocated, or not even that (ECMA-167 4/14.14.1.1), are zeros
+/// rather than anything recorded on the disc.
+pub fn recorded_file_extents<'p>(
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &'p BTreeMap<u16, PartitionDescriptor>,
+    partition_reference_number: u16,
+    file_entries: &[FileEntry],
+    path: &str,
+) -> Result<Vec<(u64, &'p PartitionDescriptor, usize, u64)>> {
+    let extents = file_extents(
+        logical_volume_descriptor,
+        partition_descriptors,
+        partition_reference_number,
+        file_entries,
+        path,
+    )?;
+    Ok((extents.into_iter())
+        .filter(|(_, extent_type, ..)| *extent_type == ExtentType::RecordedAllocated)
+        .map(|(file_offset, _, partition_descriptor, pos_in_partition, length)| {
+            (file_offset, partition_descriptor, pos_in_partition, length)
+        })
+        .collect())
+}
+
+/// The offset in the file, type, partition, byte offset in the partition and length of an extent
+type FileExtent<'p> = (u64, ExtentType, &'p PartitionDescriptor, usize, u64);
+
+/// The extents of file_data_extents with the offset in the file and the type of each
+fn file_extents<'p>(
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &'p BTreeMap<u16, PartitionDescriptor>,
+    partition_reference_number: u16,
+    file_entries: &[FileEntry],
+    path: &str,
+) -> Result<Vec<FileExtent<'p>>> {
+    let information_length = file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+    let mut extents = vec![];
+    let mut total = 0u64;
+    for file_entry in file_entries {
+        for ad in &read_allocation_descriptors(file_entry, partition_reference_number)? {
+            let (partition_descriptor, pos_in_partition) =
+                long_ad_partition_pos(logical_volume_descriptor, partition_descriptors, ad)?;
+            let partition_size = partition_descriptor.partition_length as u64 * DVDCSS_BLOCK_SIZE as u64;
+            let length = ad.extent_length_bytes() as u64;
+            let end = pos_in_partition as u64 + length;
+            if end > partition_size {
+                return Err(UdfError::ExtentOutsidePartition(path.to_string(), end - partition_size));
+            }
+            let kept = length.min(information_length.saturating_sub(total));
+            if kept > 0 {
+                extents.push((total, ad.extent_type(), partition_descriptor, pos_in_partition, kept));
+            }
+            total += length;
+        }
+    }
+    if total > information_length {
+        warn!(
+            "{}: the allocation descriptors describe {} bytes but the file is {} bytes; copying {} bytes",
+            path, total, information_length, information_length
+        );
+    } else if total < information_length {
+        warn!(
+            "{}: the file is {} bytes but its allocation descriptors only describe {} bytes",
+            path, information_length, total
+        );
+    }
+    Ok(extents)
+}
+
+pub fn read_exact_from_partition<B: BlockDevice>(
+    cache: &mut B,
     partition_descriptor: &PartitionDescriptor,
     pos_in_partition: usize,
     buf: &mut [u8],
@@ -56,6 +254,186 @@ pub fn read_exact_from_partition<R: Read + Seek, const BYTE_SIZE: usize>(
         "read_exact_from_partition: partition_starting_location={}, pos_in_partition={}, len={}",
         partition_descriptor.partition_starting_location, pos_in_partition, buf.len()
     );
-    let pos = partition_descriptor.partition_starting_location as usize * DVDCSS_BLOCK_SIZE + pos_in_partition;
-    cache.read_exact(pos, buf)
+    let pos = partition_pos_to_image_pos(partition_descriptor, pos_in_partition);
+    if !cache.has_sparable_partitions() {
+        return cache.read_exact_at(pos, buf);
+    }
+    // a sector at a time, as each packet of a Sparable Partition may have been moved
+    let mut read = 0;
+    while read < buf.len() {
+        let sector = ((pos + read) / DVDCSS_BLOCK_SIZE) as u32;
+        let offset = (pos + read) % DVDCSS_BLOCK_SIZE;
+        let len = (DVDCSS_BLOCK_SIZE - offset).min(buf.len() - read);
+        let sector = cache.spared_sector(sector);
+        cache.read_exact_at(sector as usize * DVDCSS_BLOCK_SIZE + offset, &mut buf[read..read + len])?;
+        read += len;
+    }
+    Ok(())
+}
+
+/// The byte offset in the image of a byte offset in a partition
+pub fn partition_pos_to_image_pos(partition_descriptor: &PartitionDescriptor, pos_in_partition: usize) -> usize {
+    partition_descriptor.partition_starting_location as usize * DVDCSS_BLOCK_SIZE + pos_in_partition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udf::{AllocationDescriptorType, LbAddr};
+
+    fn file_entry(information_length: u64, extents: &[(u32, u32)]) -> FileEntry {
+        let mut allocation_descriptors = vec![0u8; extents.len() * ShortAllocationDescriptor::size()];
+        for (&(extent_location, length), bytes) in
+            extents.iter().zip(allocation_descriptors.chunks_exact_mut(ShortAllocationDescriptor::size()))
+        {
+            let ad = ShortAllocationDescriptor {
+                extent_length_and_type: length,
+                extent_location,
+            };
+            ad.write(bytes);
+        }
+        FileEntry {
+            information_length,
+            allocation_descriptors,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_file_data_extents() {
+        let logical_volume = LogicalVolumeDescriptor {
+            logical_block_size: 2048,
+            ..Default::default()
+        };
+        let partitions = BTreeMap::from([
+            (
+                0,
+                PartitionDescriptor {
+                    partition_length: 100,
+                    ..Default::default()
+                },
+            ),
+            (
+                1,
+                PartitionDescriptor {
+                    partition_number: 1,
+                    partition_length: 50,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let extents = |file_entry| {
+            file_data_extents(&logical_volume, &partitions, 0, &[file_entry], "VIDEO_TS/VTS_01_1.VOB").map(|extents| {
+                (extents.into_iter())
+                    .map(|(partition, pos, length)| (partition.partition_number, pos, length))
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            extents(file_entry(5000, &[(10, 4096), (20, 904)])).unwrap(),
+            vec![(0, 10 * 2048, 4096), (0, 20 * 2048, 904)]
+        );
+        // the last extent is rounded up to a block, and the one after it is past the file
+        assert_eq!(
+            extents(file_entry(5000, &[(10, 4096), (20, 2048), (30, 2048)])).unwrap(),
+            vec![(0, 10 * 2048, 4096), (0, 20 * 2048, 904)]
+        );
+        // shorter than the file: copied as far as it goes
+        assert_eq!(extents(file_entry(5000, &[(10, 4096)])).unwrap(), vec![(0, 10 * 2048, 4096)]);
+        // an extent that is allocated but not recorded has no bytes on the disc, but keeps its
+        // place in the file
+        let unrecorded = file_entry(5000, &[(10, 2048 | 1 << 30), (20, 4096)]);
+        let recorded = recorded_file_extents(&logical_volume, &partitions, 0, &[unrecorded], "A").unwrap();
+        assert_eq!(
+            (recorded.into_iter())
+                .map(|(file_offset, _, pos, length)| (file_offset, pos, length))
+                .collect::<Vec<_>>(),
+            vec![(2048, 20 * 2048, 2952)]
+        );
+        assert!(matches!(
+            extents(file_entry(5000, &[(99, 4096)])),
+            Err(UdfError::ExtentOutsidePartition(_, 2048))
+        ));
+
+        // long_ads name the partition of each extent
+        let long_ads = [(1, 40, 4096), (0, 99, 904)];
+        let mut allocation_descriptors = vec![0u8; long_ads.len() * LongAd::size()];
+        for (&(partition_reference_number, logical_block_number, length), bytes) in
+            long_ads.iter().zip(allocation_descriptors.chunks_exact_mut(LongAd::size()))
+        {
+            let ad = LongAd {
+                extent_length_and_type: length,
+                extent_location: LbAddr {
+                    logical_block_number,
+                    partition_reference_number,
+                },
+                ..Default::default()
+            };
+            ad.write(bytes);
+        }
+        let mut long_entry = FileEntry {
+            information_length: 5000,
+            allocation_descriptors,
+            ..Default::default()
+        };
+        long_entry.icb_tag.flags = AllocationDescriptorType::LONG as u16;
+        assert_eq!(
+            extents(long_entry.clone()).unwrap(),
+            vec![(1, 40 * 2048, 4096), (0, 99 * 2048, 904)]
+        );
+        // the second partition is shorter
+        long_entry.allocation_descriptors[4..8].copy_from_slice(&49u32.to_le_bytes());
+        assert!(matches!(
+            extents(long_entry.clone()),
+            Err(UdfError::ExtentOutsidePartition(_, 2048))
+        ));
+        long_entry.allocation_descriptors[8..10].copy_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(extents(long_entry), Err(UdfError::InvalidPartitionNumber)));
+    }
+
+    #[test]
+    fn test_translate_virtual_sector() {
+        let virtual_partitions = BTreeMap::from([(
+            1,
+            VirtualPartition {
+                physical_start: 257,
+                vat: vec![0, 1, 700, u32::MAX],
+            },
+        )]);
+        let start = virtual_partition_start(1);
+        assert_eq!(translate_virtual_sector(&virtual_partitions, start + 2).unwrap(), 957);
+        // the sectors of physical partitions are not translated
+        assert_eq!(translate_virtual_sector(&virtual_partitions, 259).unwrap(), 259);
+        assert_eq!(
+            translate_virtual_sectors(&virtual_partitions, start..start + 3).unwrap(),
+            [257..259, 957..958]
+        );
+        let runs = translate_virtual_sectors(&virtual_partitions, 259..262).unwrap();
+        assert_eq!((runs.len(), runs[0].clone()), (1, 259..262));
+        for unmapped in [3, 4] {
+            assert!(matches!(
+                translate_virtual_sector(&virtual_partitions, start + unmapped),
+                Err(UdfError::UnmappedVirtualBlock(1, block)) if block == unmapped
+            ));
+        }
+        assert!(matches!(
+            translate_virtual_sector(&virtual_partitions, virtual_partition_start(2)),
+            Err(UdfError::InvalidPartitionNumber)
+        ));
+    }
+
+    #[test]
+    fn test_spared_sector() {
+        let sparable_partitions = [SparablePartition {
+            start: 257,
+            length: 1000,
+            packet_length: 32,
+            spared_packets: BTreeMap::from([(64, 5000)]),
+        }];
+        assert_eq!(spared_sector(&sparable_partitions, 257 + 64), 5000);
+        assert_eq!(spared_sector(&sparable_partitions, 257 + 95), 5031);
+        for unmoved in [257 + 63, 257 + 96, 100, 257 + 1000 + 64] {
+            assert_eq!(spared_sector(&sparable_partitions, unmoved), unmoved);
+        }
+    }
 }
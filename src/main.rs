@@ -1,301 +1,4136 @@
-use std::collections::BTreeMap;
-use std::io::{Read, Seek, Write};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{
+    atomic::Ordering,
+    Arc,
+};
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand};
+use dvdromcopy::attributes::{set_attributes, set_directory_modified, set_owner, set_times, unix_mode};
+use dvdromcopy::interrupt::{check_interrupted, install_handler, interrupted};
+use dvdromcopy::cache::{BlockDevice, CacheStats, SharedCache, Readahead, ReadErrorPolicy, DEFAULT_CACHE_BLOCKS};
+use dvdromcopy::chunked::{ChunkedOutput, CHUNK_STORE_DIR};
+use dvdromcopy::compress::{CompressedOutput, Compression};
+use dvdromcopy::copy_offload::copy_range;
+use dvdromcopy::free_space::available_space;
+use dvdromcopy::device::{detect_device, device_parser, device_size, open_device, open_device_at, open_image, DeviceKind};
+use dvdromcopy::drive_region::read_drive_region;
+use dvdromcopy::dvdcss_sys::{css_to_io_error, DvdCss, DVDCSS_BLOCK_SIZE, DVDCSS_NOFLAGS, DVDCSS_SEEK_KEY};
+use dvdromcopy::info::{DiscInfo, PartitionMapInfo, TitleSetCss};
+use dvdromcopy::copier::{
+    self, file_set_locations, load_title_keys, partial_copy_too_long, read_icb_entries, walk_file_sets, write_file_data,
+};
+use dvdromcopy::iso9660::{read_files as read_iso9660_files, read_volume as read_iso9660_volume, IsoFile, IsoVolume};
+use dvdromcopy::partition_table::{has_udf_anchor, read_partitions};
+use dvdromcopy::listing::{self, ListEntry, CSV_HEADER};
+use dvdromcopy::path_filter::{matches_path_or_parent, PathFilter};
+use dvdromcopy::logical_block_reader::{
+    file_data_extents, long_ad_partition_pos, partition_pos_to_image_pos, recorded_file_extents,
+    translate_virtual_sectors, VirtualPartition,
+};
+use dvdromcopy::dvdaudio::{
+    check_file_header, parse_audio_title_set_ifo_path, pts_to_millis, AudioManagerInfo, AudioTitleSetInfo, DvdAudio,
+    AUDIO_MANAGER_IFO_PATH,
+};
+use dvdromcopy::exec_hook::{ExecHook, HookFile};
+use dvdromcopy::dfxml::{ByteRun, Dfxml, FileObject};
+use dvdromcopy::dvdid::{dvd_id, filetime, DvdId, DvdIdFile};
+use dvdromcopy::dvdvideo::{
+    check_ifo, ifo_backup_counterpart, parse_title_set_ifo_path, parse_title_vob_path, select_angle, title_vob_path, CellPlayback, DvdVideo, Hms, TitleSetInfo, VideoManagerInfo, VIDEO_MANAGER_IFO_PATH,
+};
+use dvdromcopy::manifest::{hash_reader, verify_manifest, HashingWriter, Manifest, ManifestStatus, VerifyOutcome};
+use dvdromcopy::udf::{
+    Dstring, ExtentType, FileEntry, FileIdentifierDescriptor, FileType, LogicalVolumeDescriptor, LongAd,
+    PartitionDescriptor, ShortAllocationDescriptor, Timestamp,
+};
+use dvdromcopy::matroska::Chapter;
+#[cfg(feature = "serve")]
+use dvdromcopy::metrics::{disc_bytes_read, prometheus_text, ServeStats, Throughput};
+use dvdromcopy::mount::mount;
+use dvdromcopy::mpeg_ps::{is_scrambled_pack, navigation_pack_dsi, DataSearchInfo};
+use dvdromcopy::nav_check::{NavCheckingWriter, NavProblem};
+use dvdromcopy::disc_image::{copy_image, retry_image, ImageCopy};
+use dvdromcopy::rescue_map::RescueMap;
+use dvdromcopy::verify::{verify_directory, FileStatus};
+use dvdromcopy::pipeline::write_pipelined;
+use dvdromcopy::progress::{format_duration, format_size, ProgressSink, ReportingWriter, TerminalProgress};
+use dvdromcopy::output::{open_output, partial_path, LocalOutput, OutputFile, OutputSink, SubdirectoryOutput, PARTIAL_SUFFIX};
+use dvdromcopy::remux::VobRemuxer;
+use dvdromcopy::serve::DiscTree;
+#[cfg(feature = "serve")]
+use dvdromcopy::serve::{
+    content_type, directory_listing_html, http_date, parse_range, path_to_url, propfind_multistatus, url_to_path,
+    RangeRequest,
+};
+use dvdromcopy::sidecar::{Sidecar, VolumeTimes};
+use dvdromcopy::sparse_image::{write_sparse_image, SectorRanges};
+use dvdromcopy::transcode::{ProgressWriter, Transcoder};
+use dvdromcopy::title_keys::{read_key_cache, KeyCacheDir, TitleKey};
+use dvdromcopy::udf_file::UdfFile;
+use dvdromcopy::udf_high_level::{compare_volume_structures, VolumeStructures};
+use dvdromcopy::repair::repair_image;
+use dvdromcopy::patch::{check_patchable, replace_file_contents};
+use dvdromcopy::recover::recover_image;
+use dvdromcopy::relabel::relabel_image;
+use dvdromcopy::sanitize::{is_case_insensitive, sanitize_name, PathSanitizer, TargetOs};
+use dvdromcopy::symlink::{create_symlink, link_target, read_path_components};
+use dvdromcopy::udf_writer::{write_image, write_image_of_files, ImageOptions, SourceFile, UdfRevision};
+use dvdromcopy::vat::{append_to_vat_image, create_vat_image, DVD_R_SECTORS};
+use dvdromcopy::udf_parser::{
+    read_file_contents, embedded_data, read_allocation_descriptors, read_file_entries, DirectoryWalk, Result, UdfError, UdfParser,
+};
+use log::{self, debug, error, info, warn};
+use std::fs::{create_dir, File};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    copy: Option<Args>,
+
+    /// json prints what list, info, verify and the copy report, and errors, as JSON on stdout
+    /// for scripts and frontends; log messages stay on stderr
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check previously extracted files against a checksum manifest
+    /// without needing the disc
+    VerifyManifest(VerifyManifestArgs),
+    /// Read the disc again and compare its files sector by sector with an earlier copy
+    Verify(VerifyArgs),
+    /// Check the disc's volume structures for problems without copying
+    Lint(LintArgs),
+    /// Write a disc-sized sparse image containing only the selected files' data
+    SparseIso(SparseIsoArgs),
+    /// List the files on the disc without copying
+    List(ListArgs),
+    /// Extract a DVD-Video title as a single VOB file
+    Title(TitleArgs),
+    /// Serve the files of the disc over HTTP and WebDAV, reading them from the disc on demand
+    Serve(ServeArgs),
+    /// Mount the files of the disc read-only, decrypting them as they are read
+    Mount(MountArgs),
+    /// Report the disc's volume, partitions, integrity, CSS status and regions without copying
+    Info(InfoArgs),
+    /// Write a UDF image of a directory such as a DVD-Video folder
+    #[command(name = "makeiso")]
+    MakeIso(MakeIsoArgs),
+    /// Write a copy of an image with the tags of its descriptors recomputed, for images
+    /// that other tools edited without updating them
+    RepairImage(RepairImageArgs),
+    /// Replace the contents of one file inside an image in place, with a file of the same or
+    /// a smaller size
+    PatchImage(PatchImageArgs),
+    /// Change the volume name of an image in place
+    Relabel(RelabelArgs),
+    /// Add the files of a directory to an image of a disc recorded incrementally, as a new
+    /// session with a new Virtual Allocation Table
+    AppendImage(AppendImageArgs),
+    /// Write a new, compact UDF image of the disc's files without the excluded ones
+    Remaster(RemasterArgs),
+}
+
+// The disc to read and where on it the file system is, for the commands that read one. Not a
+// doc comment, which clap would take as the about of the commands that flatten it
+#[derive(clap::Args, Debug)]
+struct DeviceArgs {
+    /// The DVD device, image file or directory to open, or the http(s):// URL of an image
+    #[arg(short, long)]
+    device: String,
+
+    /// Read the file system from this many bytes into the device or image
+    #[arg(long, conflicts_with = "partition")]
+    offset: Option<u64>,
+
+    /// Read the file system from this 1-based partition of the image's GPT or MBR
+    #[arg(long)]
+    partition: Option<u32>,
+
+    /// Stop with an error at directories nested deeper than this, which only a corrupt or
+    /// hostile image has
+    #[arg(long, default_value_t = DirectoryWalk::DEFAULT_MAX_DEPTH)]
+    max_depth: usize,
+
+    /// Memory for the sectors of the file system that were read, e.g. 256M, so that
+    /// directories and file entries read again are not read from the disc. By default one
+    /// sector is kept
+    #[arg(long, value_name = "SIZE", value_parser = parse_split_size)]
+    cache_size: Option<u64>,
+}
+impl DeviceArgs {
+    /// --cache-size in sectors
+    fn cache_blocks(&self) -> usize {
+        self.cache_size
+            .map_or(DEFAULT_CACHE_BLOCKS, |cache_size| (cache_size / DVDCSS_BLOCK_SIZE as u64) as usize)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct TitleArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The 1-based title number as shown by list --titles
+    #[arg(short, long, required_unless_present = "main_title")]
+    title: Option<u16>,
+
+    /// Extract the longest title, which is usually the main feature
+    #[arg(long, conflicts_with = "title")]
+    main_title: bool,
+
+    /// Extract only these 1-based chapters, e.g. 3-5 or 4
+    #[arg(long, value_parser = parse_chapter_range)]
+    chapters: Option<RangeInclusive<u16>>,
+
+    /// The 1-based angle to extract from multi-angle titles
+    #[arg(long, default_value_t = 1)]
+    angle: u8,
+
+    /// The file to create
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// vob writes the MPEG program stream as stored on the disc;
+    /// mkv remuxes it into Matroska with the chapters from the IFO
+    #[arg(long, value_enum, default_value_t = TitleFormat::Vob)]
+    format: TitleFormat,
+
+    /// Pipe the VOB stream into this shell command instead of writing the file, to rip and
+    /// encode in one pass, e.g. 'ffmpeg -i - -c:v libx264 {output}'. {output} is replaced with
+    /// the --output path
+    #[arg(long, value_name = "COMMAND", conflicts_with = "format")]
+    transcode: Option<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TitleFormat {
+    Vob,
+    Mkv,
+}
+
+/// The metadata of the disc's files that --preserve gives their copies
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Preserve {
+    Timestamps,
+    Permissions,
+    Ownership,
+}
+
+/// What the copy writes
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+enum CopyFormat {
+    /// The disc's files
+    #[default]
+    Files,
+    /// A decrypted image of every sector of the disc
+    Iso,
+}
+
+/// What the copy makes of a symbolic link on the disc
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SymlinkMode {
+    Link,
+    Text,
+    Skip,
+}
+impl SymlinkMode {
+    fn host() -> Self {
+        if cfg!(unix) {
+            SymlinkMode::Link
+        } else {
+            SymlinkMode::Text
+        }
+    }
+}
+
+/// The order in which the files of the disc are copied
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+enum CopyOrder {
+    /// Directory by directory as the walk of the disc visits them, each in order of name
+    #[default]
+    Disc,
+    /// IFO and BUP files first, then the other files from smallest to largest,
+    /// so that an interrupted copy still has the navigation data
+    MetadataFirst,
+}
+
+/// The file system that the files of the disc are read from
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+enum FileSystem {
+    /// UDF, or ISO 9660 if the disc has no UDF anchor
+    #[default]
+    Auto,
+    Udf,
+    /// ISO 9660, with the Joliet names if the disc has them
+    Iso9660,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The file system to read the files from
+    #[arg(long, value_enum, default_value_t)]
+    fs: FileSystem,
+
+    /// List the DVD-Video titles and DVD-Audio tracks from the IFO files instead of the files
+    #[arg(long)]
+    titles: bool,
+
+    /// text lists the paths; csv adds the size, timestamps, file type and number of extents;
+    /// tree indents the files under their directories with their size, type and modification time
+    #[arg(long, value_enum, default_value_t = ListFormat::Text, conflicts_with = "titles")]
+    format: ListFormat,
+
+    /// Print the files as a JSON array with the fields of --format csv, for scripts
+    #[arg(long, conflicts_with_all = ["titles", "format"])]
+    json: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ListFormat {
+    Text,
+    Csv,
+    Tree,
+}
+
+#[derive(clap::Args, Debug)]
+struct InfoArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// Print the report as JSON with a stable schema, for scripts
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct MakeIsoArgs {
+    /// The directory to record, e.g. one containing VIDEO_TS and AUDIO_TS
+    source: PathBuf,
+
+    /// The image file to create
+    output: PathBuf,
+
+    /// The volume identifier; defaults to the directory's name in upper case
+    #[arg(long)]
+    volume_id: Option<String>,
+
+    /// The UDF revision of the file system
+    #[arg(long, value_enum, default_value = "1.02")]
+    udf_revision: UdfRevision,
+
+    /// Also record ISO 9660 and Joliet directories of the same files, for readers that do not
+    /// understand UDF (a UDF Bridge image, as DVD-Video discs are)
+    #[arg(long)]
+    iso9660: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct RepairImageArgs {
+    /// The image to repair, which is left unchanged
+    image: PathBuf,
+
+    /// The repaired image file to create
+    output: PathBuf,
+
+    /// First rebuild lost anchors and Volume Descriptor Sequences from the volume descriptors
+    /// that survive elsewhere in the image
+    #[arg(long)]
+    rebuild: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct RemasterArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The image file to create
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Files and directories to leave out, or glob patterns for them, e.g. 'VIDEO_TS/VTS_03_*.VOB'
+    #[arg(long, required = true)]
+    exclude: Vec<String>,
+
+    /// The volume identifier; defaults to the disc's
+    #[arg(long)]
+    volume_id: Option<String>,
+
+    /// The UDF revision of the file system
+    #[arg(long, value_enum, default_value = "1.02")]
+    udf_revision: UdfRevision,
+
+    /// Also record ISO 9660 and Joliet directories of the same files
+    #[arg(long)]
+    iso9660: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct PatchImageArgs {
+    /// The image to modify in place
+    image: PathBuf,
+
+    /// The path of the file inside the image, e.g. VIDEO_TS/VTS_01_0.IFO
+    path: String,
+
+    /// The file with the new contents, which can be no larger than the one in the image
+    file: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct RelabelArgs {
+    /// The image to modify in place
+    image: PathBuf,
+
+    /// The new volume identifier, of up to 30 characters (15 if any is not Latin-1)
+    #[arg(long)]
+    volume_id: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct AppendImageArgs {
+    /// The image to add to in place
+    image: PathBuf,
+
+    /// The directory whose files to add, at the same paths; files already in the image are replaced
+    source: PathBuf,
+
+    /// Create the image, as UDF 2.01 with a virtual partition, instead of adding to one
+    #[arg(long)]
+    create: bool,
+
+    /// The volume identifier of a new image; defaults to the directory's name in upper case
+    #[arg(long, requires = "create")]
+    volume_id: Option<String>,
+
+    /// The sectors of the disc the image is for, which the sessions cannot exceed
+    #[arg(long, default_value_t = DVD_R_SECTORS, requires = "create")]
+    capacity: u32,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The address and port to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// How many more times to read sectors that could not be read
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+}
+
+#[derive(clap::Args, Debug)]
+struct MountArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The empty directory to mount the files at
+    mountpoint: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct SparseIsoArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The image file to create
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Files and directories whose data is copied, or glob patterns for them, e.g.
+    /// VIDEO_TS/VTS_01_1.VOB. Filesystem metadata is always copied
+    #[arg(long, required = true)]
+    include: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct LintArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyManifestArgs {
+    /// The directory that the DVD was previously copied to
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// SHA256SUMS-style manifest with paths relative to the output directory
+    #[arg(long)]
+    manifest: PathBuf,
+
+    /// Print per-file results as JSON instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The directory that holds the copied files, i.e. the <name> directory of the copy
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Compare only the files and directories that match these glob patterns, as for the copy
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Leave out the files and directories that match these glob patterns
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Print per-file results as JSON instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+fn parse_chapter_range(s: &str) -> std::result::Result<RangeInclusive<u16>, String> {
+    let (first, last) = s.split_once('-').unwrap_or((s, s));
+    let parse = |n: &str| n.trim().parse::<u16>().map_err(|e| format!("invalid chapter {:?}: {}", n, e));
+    let (first, last) = (parse(first)?, parse(last)?);
+    if first == 0 || first > last {
+        return Err(format!("invalid chapter range {:?}", s));
+    }
+    Ok(first..=last)
+}
+
+/// One item of --on-read-error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReadErrorAction {
+    Retry(u32),
+    Skip,
+    Abort,
+}
+
+/// Parse retry=N, skip or abort
+fn parse_read_error_action(s: &str) -> std::result::Result<ReadErrorAction, String> {
+    match s.trim() {
+        "skip" => Ok(ReadErrorAction::Skip),
+        "abort" => Ok(ReadErrorAction::Abort),
+        action => (action.strip_prefix("retry="))
+            .and_then(|retries| retries.parse().ok())
+            .map(ReadErrorAction::Retry)
+            .ok_or_else(|| format!("invalid read error action {:?}; expected retry=N, skip or abort", s)),
+    }
+}
+
+/// The ReadErrorPolicy of --on-read-error and --retries, where the last of the actions wins
+fn read_error_policy(args: &Args) -> ReadErrorPolicy {
+    let mut policy = ReadErrorPolicy {
+        retries: args.retries,
+        skip: args.format == CopyFormat::Iso,
+        ..Default::default()
+    };
+    for action in &args.on_read_error {
+        match *action {
+            ReadErrorAction::Retry(retries) => policy.retries = retries,
+            ReadErrorAction::Skip => policy.skip = true,
+            ReadErrorAction::Abort => policy.skip = false,
+        }
+    }
+    policy
+}
+
+/// Parse a size such as 4095M, rounded down to whole sectors
+fn parse_split_size(s: &str) -> std::result::Result<u64, String> {
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    let size = number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size {:?}", s))?;
+    let size = size - size % DVDCSS_BLOCK_SIZE as u64;
+    if size == 0 {
+        return Err(format!("size {:?} is smaller than a sector", s));
+    }
+    Ok(size)
+}
+
+/// Exit codes for scripts to branch on. A command that fails exits with 1.
+/// A copy that finished but skipped files or sectors, or whose --exec command failed
+const EXIT_COPY_INCOMPLETE: u8 = 2;
+/// Of the verify commands
+const EXIT_VERIFY_MISMATCHES: u8 = 2;
+const EXIT_VERIFY_READ_ERRORS: u8 = 3;
+
+// clap leaves the group of a struct that flattens another empty, and Cli.copy is only Some
+// when an argument of the group is given, so name --device, which every copy is given
+#[derive(clap::Args, Debug)]
+#[group(args = ["device"])]
+struct Args {
+    #[command(flatten)]
+    disc: DeviceArgs,
+
+    /// The file system to read the files from
+    #[arg(long, value_enum, default_value_t)]
+    fs: FileSystem,
+
+    /// The output directory to write the DVD to, or s3://bucket/prefix or sftp://[user@]host/path when built with the s3 or sftp feature
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Name of the DVD; if not specified then it will read from DVD
+    /// primary_volume.volume_identifier
+    #[arg(long)]
+    name: Option<String>,
+
+    /// What to write: the disc's files in the <name> directory, or a decrypted image of the
+    /// whole disc as <name>.iso
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["include", "exclude", "dfxml", "dump_keys", "join_vobs", "check_nav", "exec", "chunk_size", "rename_report"]
+    )]
+    format: CopyFormat,
+
+    /// How many more times to read sectors that could not be read, unless --on-read-error
+    /// retry=N says
+    #[arg(long, default_value_t = 2)]
+    retries: u32,
+
+    /// What to do with sectors that cannot be read, e.g. retry=5,skip: retry=N reads them N
+    /// more times, then skip writes them as zeros and lists them at the end, or abort stops the
+    /// copy. The default is skip with --format iso and abort otherwise
+    #[arg(long, value_name = "ACTION", value_delimiter = ',', value_parser = parse_read_error_action)]
+    on_read_error: Vec<ReadErrorAction>,
+
+    /// Sectors to read along with each sector of the file system that is not cached, in one
+    /// request to the drive, or 0 for none. By default sequential reads are read ahead of, up to
+    /// 64 sectors at a time
+    #[arg(long, value_name = "SECTORS", value_parser = clap::value_parser!(u32).range(0..=1024))]
+    readahead: Option<u32>,
+
+    /// Copy only the files and directories that match these glob patterns, e.g. VIDEO_TS/*.IFO.
+    /// * and ? do not match '/', ** does, and [...] matches one of a set of characters
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Leave out the files and directories that match these glob patterns, e.g. '**.BUP'
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Write a SHA256SUMS-style checksum manifest of the copied files
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Write a DFXML report of the copied files for forensic tools: their sizes, timestamps,
+    /// SHA-256 and byte runs on the disc
+    #[arg(long, value_name = "PATH")]
+    dfxml: Option<PathBuf>,
+
+    /// Continue with the next file when a file cannot be copied.
+    /// Incomplete files are left with a .partial suffix
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Continue an interrupted copy into the same directory: .partial files are continued from
+    /// their length, and files that were completely copied are kept if they have the size of
+    /// the disc's files
+    #[arg(long, conflicts_with_all = ["join_vobs", "compress", "chunk_size"])]
+    resume: bool,
+
+    /// Show the file being copied, how much of the disc has been copied and the time left.
+    /// The disc is scanned for the size of its files first
+    #[arg(long)]
+    progress: bool,
+
+    /// Threads that write and hash what is read from the disc, so that neither keeps the drive
+    /// waiting: with 2 the copies are written on one and hashed and checked on the other, with
+    /// 1 both are done on one thread, and with 0 on the thread that reads the disc
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=2))]
+    writer_threads: u8,
+
+    /// After copying, print the CSS title key of each VOB and record them
+    /// in the <name>.dvdromcopy.json sidecar file
+    #[arg(long)]
+    dump_keys: bool,
+
+    /// Write the title VOBs of each title set (VTS_nn_1.VOB to VTS_nn_9.VOB)
+    /// as a single VTS_nn_1.VOB instead of the disc's 1 GB pieces
+    #[arg(long)]
+    join_vobs: bool,
+
+    /// With --join-vobs, split the joined VOBs into VTS_nn_1.VOB, VTS_nn_2.VOB, ...
+    /// of at most this many bytes, e.g. 4095M for FAT32. Accepts K, M and G suffixes
+    #[arg(long, requires = "join_vobs", value_parser = parse_split_size)]
+    split_size: Option<u64>,
+
+    /// Check the navigation packs of each VOB while copying it and report
+    /// VOBUs that are corrupt or were not decrypted
+    #[arg(long)]
+    check_nav: bool,
+
+    /// The order in which to copy the files
+    #[arg(long, value_enum, default_value_t)]
+    copy_order: CopyOrder,
+
+    /// Run this shell command after each file is copied. {path}, {size} and {sha256}
+    /// are replaced with the output file's path, size in bytes and SHA-256
+    #[arg(long, value_name = "COMMAND")]
+    exec: Option<String>,
+
+    /// Compress each file as it is written, e.g. to VIDEO_TS.IFO.zst, when built with the
+    /// compress feature. The IFO/BUP check is skipped since it reads back the copied files
+    #[arg(long, value_enum, conflicts_with = "exec")]
+    compress: Option<Compression>,
+
+    /// Write each file as chunks of this size, stored by their SHA-256 in <output>/chunks
+    /// and shared between discs, plus a <file>.chunks index. Accepts K, M and G suffixes
+    #[arg(long, value_parser = parse_split_size, conflicts_with_all = ["exec", "compress"])]
+    chunk_size: Option<u64>,
+
+    /// The OS whose file naming rules the copied files must follow; names that it does not
+    /// allow, such as AUX or ones ending in a dot on Windows, are changed
+    #[arg(long, value_enum, default_value_t = TargetOs::host())]
+    target_os: TargetOs,
+
+    /// Write the disc paths of the renamed files and directories and their output paths,
+    /// separated by a tab, to this file
+    #[arg(long, value_name = "PATH")]
+    rename_report: Option<PathBuf>,
+
+    /// What to make of the disc's symbolic links: links to the same place in the copy, text
+    /// files that hold the target (the default on Windows, which only lets administrators
+    /// create links), or nothing
+    #[arg(long, value_enum, default_value_t = SymlinkMode::host())]
+    symlinks: SymlinkMode,
+
+    /// The metadata of the disc's files to give their copies: timestamps (modification and
+    /// access times), permissions (without write permission for others) and ownership (the
+    /// numeric owner and group, which only root can set)
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [Preserve::Timestamps, Preserve::Permissions])]
+    preserve: Vec<Preserve>,
+
+    /// Leave the copies with the default permissions instead of those of the disc's files
+    /// (without write permission for others)
+    #[arg(long, conflicts_with = "preserve")]
+    no_permissions: bool,
+
+    /// Do not hide the copies of files that the disc marks as hidden
+    #[arg(long)]
+    no_hidden: bool,
+
+    /// Print the files that would be copied and the space they need, and check that they do
+    /// not already exist and fit, without reading their data or writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// --output-format json, for the summary and what --dry-run and --dump-keys print
+    #[arg(skip)]
+    json: bool,
+}
+
+
+fn main() -> Result<ExitCode> {
+    env_logger::init();
+    let mut cli = Cli::parse();
+    let json = cli.output_format == OutputFormat::Json;
+    if json {
+        match &mut cli.command {
+            Some(Command::VerifyManifest(args)) => args.json = true,
+            Some(Command::Verify(args)) => args.json = true,
+            Some(Command::List(args)) => args.json = true,
+            Some(Command::Info(args)) => args.json = true,
+            Some(_) => {}
+            None => {
+                if let Some(args) = &mut cli.copy {
+                    args.json = true;
+                }
+            }
+        }
+    }
+    match run_command(&cli) {
+        Err(e) if json => {
+            println!("{}", serde_json::json!({ "error": e.to_string() }));
+            Ok(ExitCode::FAILURE)
+        }
+        result => result,
+    }
+}
+
+fn run_command(cli: &Cli) -> Result<ExitCode> {
+    match &cli.command {
+        Some(Command::VerifyManifest(args)) => return run_verify_manifest(args),
+        Some(Command::Verify(args)) => return run_verify(args),
+        Some(Command::Lint(args)) => run_lint(args)?,
+        Some(Command::SparseIso(args)) => run_sparse_iso(args)?,
+        Some(Command::List(args)) => run_list(args)?,
+        Some(Command::Title(args)) => run_title(args)?,
+        Some(Command::Serve(args)) => run_serve(args)?,
+        Some(Command::Mount(args)) => run_mount(args)?,
+        Some(Command::Info(args)) => run_info(args)?,
+        Some(Command::MakeIso(args)) => run_make_iso(args)?,
+        Some(Command::RepairImage(args)) => run_repair_image(args)?,
+        Some(Command::PatchImage(args)) => run_patch_image(args)?,
+        Some(Command::Relabel(args)) => run_relabel(args)?,
+        Some(Command::AppendImage(args)) => run_append_image(args)?,
+        Some(Command::Remaster(args)) => run_remaster(args)?,
+        None => {
+            // clap requires the copy arguments when there is no subcommand
+            let args = cli.copy.as_ref().expect("copy arguments are required without a subcommand");
+            if args.dry_run {
+                run_dry_run(args)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            let started = Instant::now();
+            let mut summary = run(args)?;
+            summary.duration = started.elapsed();
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&summary.json()).map_err(std::io::Error::from)?);
+            } else {
+                summary.print();
+            }
+            if summary.has_errors() {
+                return Ok(ExitCode::from(EXIT_COPY_INCOMPLETE));
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Exits with 0 if all files match, EXIT_VERIFY_MISMATCHES if some are different or missing,
+/// or EXIT_VERIFY_READ_ERRORS if some could not be read.
+fn run_verify_manifest(args: &VerifyManifestArgs) -> Result<ExitCode> {
+    let manifest = Manifest::open(&args.manifest)?;
+    let report = verify_manifest(&args.output, &manifest);
+    if args.json {
+        serde_json::to_writer_pretty(std::io::stdout().lock(), &report).map_err(std::io::Error::from)?;
+        println!();
+    } else {
+        for check in &report.files {
+            match &check.status {
+                ManifestStatus::Ok => println!("{}: OK", check.path),
+                ManifestStatus::Mismatch { actual_digest } => {
+                    debug!("{}: actual digest {}", check.path, actual_digest);
+                    println!("{}: FAILED", check.path);
+                }
+                ManifestStatus::Missing => println!("{}: MISSING", check.path),
+                ManifestStatus::ReadError { error } => println!("{}: READ ERROR {}", check.path, error),
+            }
+        }
+    }
+    match report.outcome {
+        VerifyOutcome::Ok => Ok(ExitCode::SUCCESS),
+        VerifyOutcome::Mismatches => {
+            error!(
+                "{} of {} files did not match the manifest",
+                report.mismatched + report.missing,
+                report.files.len()
+            );
+            Ok(ExitCode::from(EXIT_VERIFY_MISMATCHES))
+        }
+        VerifyOutcome::ReadErrors => {
+            error!("{} of {} files could not be read", report.read_errors, report.files.len());
+            Ok(ExitCode::from(EXIT_VERIFY_READ_ERRORS))
+        }
+    }
+}
+
+/// Exits with 0 if all files match the disc, EXIT_VERIFY_MISMATCHES if some are different or
+/// missing, or EXIT_VERIFY_READ_ERRORS if some could not be read.
+fn run_verify(args: &VerifyArgs) -> Result<ExitCode> {
+    let mut parser = open_parser(&args.disc)?;
+    let structures = parser.read_volume_structures()?;
+    let max_depth = parser.max_depth;
+    load_title_keys(&mut parser, &structures, max_depth)?;
+    let filter = PathFilter::new(args.include.clone(), args.exclude.clone());
+    let report = verify_directory(&mut parser, &structures, &args.output, max_depth, &filter)?;
+    if args.json {
+        serde_json::to_writer_pretty(std::io::stdout().lock(), &report).map_err(std::io::Error::from)?;
+        println!();
+    } else {
+        for check in &report.files {
+            match &check.status {
+                FileStatus::Match => println!("{}: OK", check.path),
+                FileStatus::Mismatch {
+                    mismatched_sectors,
+                    zero_filled_sectors,
+                    first_difference,
+                    ranges,
+                } => {
+                    let ranges: Vec<String> =
+                        ranges.iter().map(|range| format!("{}-{}", range.start, range.end - 1)).collect();
+                    println!(
+                        "{}: FAILED at byte {}: {} sectors differ, {} sectors are zeros, in bytes {}",
+                        check.path,
+                        first_difference,
+                        mismatched_sectors,
+                        zero_filled_sectors,
+                        ranges.join(", ")
+                    )
+                }
+                FileStatus::SizeMismatch { copy_size } => {
+                    println!("{}: FAILED: {} bytes instead of {}", check.path, copy_size, check.size)
+                }
+                FileStatus::Missing => println!("{}: MISSING", check.path),
+                FileStatus::ReadError { error } => println!("{}: READ ERROR {}", check.path, error),
+            }
+        }
+    }
+    match report.outcome {
+        VerifyOutcome::Ok => {
+            info!("All {} files match the disc", report.files.len());
+            Ok(ExitCode::SUCCESS)
+        }
+        VerifyOutcome::Mismatches => {
+            error!(
+                "{} of {} files did not match the disc ({} sectors differ, {} are zeros in the copy)",
+                report.mismatched + report.missing,
+                report.files.len(),
+                report.mismatched_sectors,
+                report.zero_filled_sectors
+            );
+            Ok(ExitCode::from(EXIT_VERIFY_MISMATCHES))
+        }
+        VerifyOutcome::ReadErrors => {
+            error!("{} of {} files could not be read", report.read_errors, report.files.len());
+            Ok(ExitCode::from(EXIT_VERIFY_READ_ERRORS))
+        }
+    }
+}
+
+/// The offset in bytes of the file system in device and, if it is in a partition, its length:
+/// --offset, the partition numbered --partition, or else the partition of a whole-disk image
+/// file that has a file system if the image does not start with one
+fn locate_file_system(disc: &DeviceArgs) -> Result<(u64, Option<u64>)> {
+    let (device, offset, partition) = (disc.device.as_str(), disc.offset, disc.partition);
+    if let Some(offset) = offset {
+        return Ok((offset, None));
+    }
+    // drives and URLs are only read as partitioned when asked to
+    if partition.is_none() && !std::fs::metadata(device).is_ok_and(|metadata| metadata.is_file()) {
+        return Ok((0, None));
+    }
+    let mut image = open_image(device)?;
+    let partitions = read_partitions(&mut image)?;
+    if let Some(number) = partition {
+        let partition = partitions.iter().find(|partition| partition.number == number).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} has no partition {}", device, number),
+            )
+        })?;
+        return Ok((partition.start, Some(partition.length)));
+    }
+    if partitions.is_empty() || has_udf_anchor(&mut image, 0)? {
+        return Ok((0, None));
+    }
+    let mut with_file_system = vec![];
+    for partition in &partitions {
+        if has_udf_anchor(&mut image, partition.start)? {
+            with_file_system.push(partition);
+        }
+    }
+    if let [partition] = with_file_system[..] {
+        info!(
+            "Reading partition {} ({}) of {}, which starts {} bytes into it",
+            partition.number, partition.description, device, partition.start
+        );
+        return Ok((partition.start, Some(partition.length)));
+    }
+    let listed: Vec<String> = (partitions.iter())
+        .map(|partition| {
+            format!(
+                "{}: {} bytes at {} ({})",
+                partition.number, partition.length, partition.start, partition.description
+            )
+        })
+        .collect();
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!(
+            "{} is partitioned and {} of its partitions have a UDF file system; choose one with --partition: {}",
+            device,
+            with_file_system.len(),
+            listed.join(", ")
+        ),
+    )
+    .into())
+}
+
+/// A parser of the file system that locate_file_system finds
+fn open_parser(disc: &DeviceArgs) -> Result<UdfParser<DvdCss>> {
+    let (offset, length) = locate_file_system(disc)?;
+    let css = open_device_at(&disc.device, offset)?;
+    let parser = new_parser(css, &disc.device, offset, length)
+        .with_cache_blocks(disc.cache_blocks())
+        .with_max_depth(disc.max_depth);
+    Ok(parser)
+}
+
+/// A parser of css, the file system that starts offset bytes into device and is length bytes
+/// long if it is in a partition. libdvdcss cannot tell the size, which a disc recorded
+/// incrementally is read from the end of.
+fn new_parser(css: DvdCss, device: &str, offset: u64, length: Option<u64>) -> UdfParser<DvdCss> {
+    let size = length.or_else(|| device_size(device).ok().map(|size| size.saturating_sub(offset)));
+    let parser = device_parser(device, css);
+    match size {
+        Some(size) => parser.with_total_sectors((size / DVDCSS_BLOCK_SIZE as u64) as u32),
+        None => parser,
+    }
+}
+
+fn run_lint(args: &LintArgs) -> Result<()> {
+    let mut parser = open_parser(&args.disc)?;
+    let (main, reserve) = parser.read_main_and_reserve_volume_structures()?;
+    match (&main, &reserve) {
+        (Some(main), Some(reserve)) => {
+            let differences = compare_volume_structures(main, reserve);
+            if differences.is_empty() {
+                println!("Main and reserve volume descriptor sequences match");
+            } else {
+                println!(
+                    "{} differences between main and reserve volume descriptor sequences:",
+                    differences.len()
+                );
+                for difference in &differences {
+                    println!(
+                        "  {}.{}: main={} reserve={}",
+                        difference.descriptor, difference.field, difference.main, difference.reserve
+                    );
+                }
+            }
+        }
+        (main, reserve) => {
+            if main.is_none() {
+                println!("Main volume descriptor sequence is missing or incomplete");
+            }
+            if reserve.is_none() {
+                println!("Reserve volume descriptor sequence is missing or incomplete");
+            }
+        }
+    }
+    print_descriptor_stats(&parser.stats());
+    Ok(())
+}
+
+fn print_descriptor_stats(stats: &CacheStats) {
+    let descriptors = stats.descriptors.snapshot();
+    println!(
+        "Descriptors validated: {} (checksum failures: {}, CRC failures: {}, tag location mismatches: {})",
+        descriptors.validated,
+        descriptors.checksum_failures,
+        descriptors.crc_failures,
+        descriptors.tag_location_mismatches
+    );
+    if !descriptors.is_clean() {
+        warn!("Some descriptors failed validation; the disc may be damaged or badly mastered");
+    }
+}
+
+fn titlecase_name(name: &str) -> String {
+    let mut result = String::new();
+    let mut capitalize = true;
+    for c in name.chars() {
+        if c == ' ' || c == '_' {
+            capitalize = true;
+            result.push(' ');
+        } else if capitalize {
+            result.push_str(&c.to_uppercase().to_string());
+            capitalize = false;
+        } else {
+            result.push_str(&c.to_lowercase().to_string());
+        }
+    }
+    result.trim().to_string()
+}
+
+struct RunOnDirectoryOptions<'a> {
+    /// where the files of the disc are written
+    output: &'a dyn OutputSink,
+    /// on file errors, leave the .partial file and continue
+    keep_going: bool,
+    /// continue .partial files and keep the files that an earlier copy completed
+    resume: bool,
+    /// check the navigation packs of VOBs while copying them
+    check_nav: bool,
+    /// run after each file is written
+    exec: Option<ExecHook>,
+    /// record a DFXML file object for each copied file
+    dfxml: bool,
+    /// the virtual partitions of the disc, for the sectors of the DFXML byte runs
+    virtual_partitions: &'a BTreeMap<u16, VirtualPartition>,
+    /// give local copies the permissions of the disc's files
+    permissions: bool,
+    /// threads that write and hash what is read from the disc: 0 to 2
+    writer_threads: u8,
+    symlinks: SymlinkMode,
+    /// give local copies the modification and access times of the disc's files
+    timestamps: bool,
+    /// give local copies the owners and groups of the disc's files
+    ownership: bool,
+    /// the image file to copy files from within the kernel, if it can be and nothing needs
+    /// the bytes of the files
+    offload: Option<&'a OffloadImage>,
+    /// where the bytes copied are reported, for --progress
+    progress: Option<&'a dyn ProgressSink>,
+}
+
+/// What happened during a copy, accumulated across the walk. Its paths are those of the
+/// output, which are the disc paths unless the files were renamed for --target-os
+#[derive(Default)]
+struct CopySummary {
+    manifest: Manifest,
+    /// the size of the files that were written
+    bytes_copied: u64,
+    /// how long the copy took, set once it is done
+    duration: Duration,
+    /// disc paths of files that could not be copied completely;
+    /// their output is left at `<name>.partial`
+    incomplete_files: Vec<String>,
+    /// disc paths of copied files whose contents do not look like their file type, and why
+    suspicious_files: Vec<(String, String)>,
+    /// problems found by --check-nav with the disc path of their VOB
+    nav_problems: Vec<(String, NavProblem)>,
+    /// IFO or BUP files that were replaced by their counterpart
+    substitutions: Vec<IfoSubstitution>,
+    /// disc paths of files for which the --exec command failed, and how
+    failed_commands: Vec<(String, String)>,
+    /// the copied disc files, for --dfxml
+    file_objects: Vec<FileObject>,
+    /// disc paths that the target OS does not allow, and the output paths they were written at
+    renamed: Vec<(String, String)>,
+    /// the sectors that could not be read and were written as zeros, with --on-read-error skip
+    unreadable_sectors: SectorRanges,
+    /// the files that have sectors written as zeros
+    zero_filled_files: Vec<String>,
+    /// the symbolic links that were created, and their targets
+    symlinks: Vec<(String, String)>,
+    /// what was read from the disc, for the descriptors that were validated
+    disc_stats: Arc<CacheStats>,
+}
+
+/// An IFO or BUP that could not be copied or is damaged, replaced by a copy of its counterpart
+struct IfoSubstitution {
+    path: String,
+    source: String,
+    reason: String,
+}
+impl CopySummary {
+    /// Whether files or sectors were skipped or the --exec command failed, which makes the
+    /// copy exit with EXIT_COPY_INCOMPLETE
+    fn has_errors(&self) -> bool {
+        !self.incomplete_files.is_empty()
+            || self.unreadable_sectors.sector_count() > 0
+            || !self.zero_filled_files.is_empty()
+            || !self.failed_commands.is_empty()
+    }
+
+    /// What print prints, for --output-format json
+    fn json(&self) -> serde_json::Value {
+        let descriptors = self.disc_stats.descriptors.snapshot();
+        // (disc path, value) pairs as objects with the value under key
+        let with_paths = |pairs: &[(String, String)], key: &str| -> Vec<serde_json::Value> {
+            (pairs.iter())
+                .map(|(path, value)| serde_json::json!({ "path": path, key: value }))
+                .collect()
+        };
+        serde_json::json!({
+            "status": if self.has_errors() { "completed with errors" } else { "complete" },
+            "files_copied": self.manifest.entries.len(),
+            "bytes_copied": self.bytes_copied,
+            "files_skipped": self.incomplete_files.len(),
+            "bad_sectors": self.unreadable_sectors.sector_count(),
+            "duration_seconds": self.duration.as_secs_f64(),
+            "descriptors": {
+                "validated": descriptors.validated,
+                "checksum_failures": descriptors.checksum_failures,
+                "crc_failures": descriptors.crc_failures,
+                "tag_location_mismatches": descriptors.tag_location_mismatches,
+            },
+            "incomplete_files": self.incomplete_files,
+            "suspicious_files": with_paths(&self.suspicious_files, "problem"),
+            "nav_problems": (self.nav_problems.iter())
+                .map(|(path, problem)| serde_json::json!({ "path": path, "sector": problem.sector, "problem": problem.message }))
+                .collect::<Vec<_>>(),
+            "substitutions": (self.substitutions.iter())
+                .map(|substitution| serde_json::json!({
+                    "path": substitution.path,
+                    "source": substitution.source,
+                    "reason": substitution.reason,
+                }))
+                .collect::<Vec<_>>(),
+            "failed_commands": with_paths(&self.failed_commands, "error"),
+            "symlinks": with_paths(&self.symlinks, "target"),
+            "unreadable_sectors": (self.unreadable_sectors.ranges().iter())
+                .map(|range| serde_json::json!({ "start": range.start, "end": range.end }))
+                .collect::<Vec<_>>(),
+            "zero_filled_files": self.zero_filled_files,
+            "renamed": with_paths(&self.renamed, "output_path"),
+        })
+    }
+
+    /// Print what was copied before the copy stopped with an error, as text; with
+    /// --output-format json only the error is printed, so that stdout stays one JSON value
+    fn print_stopped(&mut self, started: Instant, json: bool) {
+        self.duration = started.elapsed();
+        if !json {
+            self.print();
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "Copied {} files ({}) in {}",
+            self.manifest.entries.len(),
+            format_size(self.bytes_copied),
+            format_duration(self.duration)
+        );
+        print_descriptor_stats(&self.disc_stats);
+        if !self.incomplete_files.is_empty() {
+            println!(
+                "{} files could not be copied completely (partial output is left with a {} suffix):",
+                self.incomplete_files.len(),
+                PARTIAL_SUFFIX
+            );
+            for path in &self.incomplete_files {
+                println!("  {}", path);
+            }
+        }
+        if !self.suspicious_files.is_empty() {
+            println!("{} files do not look like their file type:", self.suspicious_files.len());
+            for (path, problem) in &self.suspicious_files {
+                println!("  {}: {}", path, problem);
+            }
+        }
+        if !self.nav_problems.is_empty() {
+            const MAX_PRINTED: usize = 20;
+            println!("{} problems were found in VOB navigation packs:", self.nav_problems.len());
+            for (path, problem) in self.nav_problems.iter().take(MAX_PRINTED) {
+                println!("  {} sector {}: {}", path, problem.sector, problem.message);
+            }
+            if self.nav_problems.len() > MAX_PRINTED {
+                println!("  and {} more (see RUST_LOG=debug)", self.nav_problems.len() - MAX_PRINTED);
+            }
+        }
+        if !self.substitutions.is_empty() {
+            println!("{} files were replaced by their IFO or BUP counterpart:", self.substitutions.len());
+            for substitution in &self.substitutions {
+                println!(
+                    "  {}: copied from {} because {}",
+                    substitution.path, substitution.source, substitution.reason
+                );
+            }
+        }
+        if !self.failed_commands.is_empty() {
+            println!("The --exec command failed for {} files:", self.failed_commands.len());
+            for (path, problem) in &self.failed_commands {
+                println!("  {}: {}", path, problem);
+            }
+        }
+        if !self.symlinks.is_empty() {
+            println!("Created {} symbolic links", self.symlinks.len());
+        }
+        if self.unreadable_sectors.sector_count() > 0 {
+            println!(
+                "{} sectors could not be read and were written as zeros:",
+                self.unreadable_sectors.sector_count()
+            );
+            for range in self.unreadable_sectors.ranges() {
+                println!("  {}..{}", range.start, range.end);
+            }
+        }
+        if !self.zero_filled_files.is_empty() {
+            println!("{} files have sectors that were written as zeros:", self.zero_filled_files.len());
+            for path in &self.zero_filled_files {
+                println!("  {}", path);
+            }
+        }
+        if !self.renamed.is_empty() {
+            println!(
+                "{} files and directories were renamed for the target OS or to not collide with another:",
+                self.renamed.len()
+            );
+            for (disc_path, output_path) in &self.renamed {
+                println!("  {} -> {}", disc_path, output_path);
+            }
+        }
+    }
+}
+
+/// Give the local copy at output_path the metadata of its File Entry that options preserve.
+/// The times are set last, since setting the others could change them.
+fn preserve_metadata(options: &RunOnDirectoryOptions, output_path: &Path, file_entry: &FileEntry) -> Result<()> {
+    if options.permissions {
+        set_attributes(output_path, Some(unix_mode(file_entry.permissions)), false)?;
+    }
+    if options.ownership {
+        // ECMA-167 4/14.9.3: all ones is an owner or group that was not recorded
+        let known = |id: u32| (id != u32::MAX).then_some(id);
+        set_owner(output_path, known(file_entry.uid), known(file_entry.gid))?;
+    }
+    if options.timestamps {
+        // a year of 0 is an unset time
+        let time = |timestamp: &Timestamp| (timestamp.year != 0).then(|| timestamp.system_time());
+        set_times(
+            output_path,
+            time(&file_entry.modification_time),
+            time(&file_entry.access_time),
+        )?;
+    }
+    Ok(())
+}
+
+/// Sort key of a file for CopyOrder::MetadataFirst: IFO and BUP files, then by size
+fn metadata_first_key(path: &str, size: u64) -> (u8, u64) {
+    let is_navigation = path.ends_with(".IFO") || path.ends_with(".BUP");
+    (if is_navigation { 0 } else { 1 }, size)
+}
+
+/// Copy one file for run_on_directory, recording the result in summary
+fn copy_file<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    options: &RunOnDirectoryOptions,
+    path: &str,
+    icb_address: &LongAd,
+    summary: &mut CopySummary,
+) -> Result<()> {
+    let (_, file_entries) = read_icb_entries(reader, logical_volume_descriptor, partition_descriptors, icb_address)?;
+    if file_entries.first().is_some_and(|file_entry| file_entry.icb_tag.file_type() == FileType::SymbolicLink) {
+        return copy_symlink(reader, logical_volume_descriptor, partition_descriptors, options, path, icb_address, summary);
+    }
+    // with --on-read-error skip, the sectors that are read as zeros are counted as read errors
+    let read_errors = reader.stats().read_errors.load(Ordering::Relaxed);
+    match read_file(
+        reader,
+        logical_volume_descriptor,
+        partition_descriptors,
+        options,
+        path.to_string(),
+        icb_address,
+    ) {
+        Ok((digest, nav_problems)) => {
+            if let Some(dvd_dir) = options.output.local_dir() {
+                let (_, file_entries) =
+                    read_icb_entries(reader, logical_volume_descriptor, partition_descriptors, icb_address)?;
+                if let Some(file_entry) = file_entries.first() {
+                    preserve_metadata(options, &dvd_dir.join(path), file_entry)?;
+                }
+            }
+            if options.dfxml {
+                let file_object = file_object(
+                    reader,
+                    logical_volume_descriptor,
+                    partition_descriptors,
+                    options.virtual_partitions,
+                    path,
+                    icb_address,
+                    Some(digest.clone()),
+                )?;
+                summary.file_objects.push(file_object);
+            }
+            if reader.stats().read_errors.load(Ordering::Relaxed) > read_errors {
+                warn!("{}: some of its sectors could not be read and were written as zeros", path);
+                summary.zero_filled_files.push(path.to_string());
+            }
+            let size = file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+            file_written(options, path, digest, size, summary)?;
+            summary
+                .nav_problems
+                .extend(nav_problems.into_iter().map(|problem| (path.to_string(), problem)));
+            if let Some(dvd_dir) = options.output.local_dir() {
+                if let Some(problem) = check_copied_file_header(dvd_dir, path)? {
+                    warn!("{}: {}", path, problem);
+                    summary.suspicious_files.push((path.to_string(), problem));
+                }
+            }
+        }
+        Err(e) if stops_copy(&e) => {
+            summary.incomplete_files.push(path.to_string());
+            return Err(e);
+        }
+        Err(e) if options.keep_going => {
+            error!("Could not copy {:?}; continuing: {}", path, e);
+            summary.incomplete_files.push(path.to_string());
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Make what options.symlinks says of the symbolic link at path
+fn copy_symlink<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    options: &RunOnDirectoryOptions,
+    path: &str,
+    icb_address: &LongAd,
+    summary: &mut CopySummary,
+) -> Result<()> {
+    let mut data = vec![];
+    write_file_data(reader, logical_volume_descriptor, partition_descriptors, path, icb_address, 0, &mut data)?;
+    if let Some(progress) = options.progress {
+        progress.start_file(path, data.len() as u64);
+        progress.advance(data.len() as u64);
+    }
+    let target = link_target(&read_path_components(&data)?, path.matches('/').count())?;
+    debug!("copy_symlink: {} -> {}", path, target);
+    let link_dir = options.output.local_dir().filter(|_| options.symlinks == SymlinkMode::Link);
+    match (options.symlinks, link_dir) {
+        (SymlinkMode::Skip, _) => {}
+        (_, Some(dvd_dir)) => {
+            let link_path = dvd_dir.join(path);
+            let created = options.resume && std::fs::read_link(&link_path).is_ok_and(|existing| existing == Path::new(&target));
+            if !created {
+                create_symlink(&target, &link_path).map_err(|err| {
+                    error!("Could not create the symbolic link {:?}: {}; try --symlinks text", link_path, err);
+                    err
+                })?;
+            }
+            summary.symlinks.push((path.to_string(), target));
+        }
+        (mode, None) => {
+            if mode == SymlinkMode::Link {
+                warn!("{}: links can only be made in a local directory; writing the target {} instead", path, target);
+            }
+            let contents = format!("{}\n", target);
+            let exists = options.output.exists(path)?;
+            if exists && !options.resume {
+                error!("Output file {} already exists; continue an earlier copy with --resume", options.output.describe(path));
+                return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+            }
+            if !exists {
+                let mut file = options.output.create(path)?;
+                file.write_all(contents.as_bytes())?;
+                file.finish()?;
+            }
+            let mut hashing = HashingWriter::new(std::io::sink());
+            hashing.write_all(contents.as_bytes())?;
+            file_written(options, path, hashing.finish().1, contents.len() as u64, summary)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether e stops the whole copy even with --keep-going: Ctrl-C, or the output file system
+/// (or the user's quota on it) being full, which every file after it would fail with too
+fn stops_copy(e: &UdfError) -> bool {
+    use std::io::ErrorKind::{Interrupted, QuotaExceeded, StorageFull};
+    matches!(e, UdfError::Io(e) if matches!(e.kind(), Interrupted | QuotaExceeded | StorageFull))
+}
+
+/// The size of the disc's files that the copy visits, for --progress, and the bytes that
+/// copying them to output will write: all of each file, less what --resume keeps of it or
+/// continues from. Both come from one walk of the disc
+fn copy_size(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    filter: &PathFilter,
+    output: &dyn OutputSink,
+    sanitizer: &mut PathSanitizer,
+    resume: bool,
+) -> Result<(u64, u64)> {
+    let (mut total, mut to_write) = (0, 0);
+    walk_filtered_filesystem(parser, structures, filter, &mut |reader, path, icb_address, _| {
+        let (_, file_entries) = read_icb(reader, structures, icb_address)?;
+        let size = file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+        let written = if resume { resumed_bytes(output, &sanitizer.output_path(path)) } else { 0 };
+        total += size;
+        to_write += size.saturating_sub(written);
+        Ok(())
+    })?;
+    Ok((total, to_write))
+}
+
+/// What --resume does not write again of the output at output_path: the length of the
+/// complete or .partial file, if there is one
+fn resumed_bytes(output: &dyn OutputSink, output_path: &str) -> u64 {
+    let Some(dir) = output.local_dir() else {
+        return 0;
+    };
+    let output_path = dir.join(output_path);
+    [partial_path(&output_path), output_path]
+        .iter()
+        .find_map(|path| std::fs::metadata(path).ok())
+        .map_or(0, |metadata| metadata.len())
+}
+
+/// Check that a local output has room for needed bytes before copying, so that a copy that
+/// cannot fit fails now rather than when the output fills up. --compress and --chunk-size
+/// can need less, so with them it only warns
+fn check_free_space(args: &Args, root_output: &dyn OutputSink, needed: u64) -> Result<()> {
+    let Some(dir) = root_output.local_dir() else {
+        return Ok(());
+    };
+    let available = match available_space(dir) {
+        Ok(available) => available,
+        Err(e) => {
+            warn!("Could not tell the free space of {}: {}", dir.display(), e);
+            return Ok(());
+        }
+    };
+    debug!("{} bytes to write, {} available", needed, available);
+    if available >= needed {
+        return Ok(());
+    }
+    let message = format!(
+        "the copy needs {} but {} has only {} free",
+        format_size(needed),
+        dir.display(),
+        format_size(available)
+    );
+    if args.compress.is_some() || args.chunk_size.is_some() {
+        warn!("{}; copying anyway, since compressed or shared chunks may take less", message);
+        return Ok(());
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::StorageFull, message).into())
+}
+
+/// The number and total size of the disc's files whose output is not complete, visiting them
+/// the way run() does
+fn remaining_files(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    sanitizer: &mut PathSanitizer,
+    summary: &CopySummary,
+    join_vobs: bool,
+    filter: &PathFilter,
+) -> Result<(usize, u64)> {
+    let complete: BTreeSet<&str> = (summary.manifest.entries.iter().map(|entry| entry.path.as_str()))
+        .chain(summary.symlinks.iter().map(|(path, _)| path.as_str()))
+        .collect();
+    let (mut files, mut bytes) = (0, 0);
+    walk_filtered_filesystem(parser, structures, filter, &mut |reader, path, icb_address, _| {
+        let output_path = sanitizer.output_path(path);
+        // a joined title set is only recorded once all of its outputs are written
+        let joined_path = parse_title_vob_path(&output_path)
+            .filter(|_| join_vobs)
+            .map(|(title_set, _)| title_vob_path(title_set));
+        if !complete.contains(joined_path.as_deref().unwrap_or(&output_path)) {
+            let (_, file_entries) = read_icb(reader, structures, icb_address)?;
+            files += 1;
+            bytes += file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+        }
+        Ok(())
+    })?;
+    Ok((files, bytes))
+}
+
+/// Record a completely written output file of size bytes in the manifest and run the --exec
+/// command for it. A failing command is reported in the summary but does not stop the copy
+fn file_written(
+    options: &RunOnDirectoryOptions,
+    path: &str,
+    digest: String,
+    size: u64,
+    summary: &mut CopySummary,
+) -> Result<()> {
+    // run() only allows --exec with a local output
+    if let (Some(exec), Some(dvd_dir)) = (&options.exec, options.output.local_dir()) {
+        let output_path = dvd_dir.join(path);
+        let file = HookFile {
+            path: &output_path,
+            size: std::fs::metadata(&output_path)?.len(),
+            sha256: &digest,
+        };
+        let problem = match exec.run(&file) {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(status.to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(problem) = problem {
+            warn!("--exec command for {}: {}", path, problem);
+            summary.failed_commands.push((path.to_string(), problem));
+        }
+    }
+    summary.manifest.push(path, digest);
+    summary.bytes_copied += size;
+    Ok(())
+}
+
+/// Compare each copied IFO with its backup (BUP). When one of them could not be copied
+/// or fails check_ifo and the other is fine, overwrite it with the other.
+fn reconcile_ifo_backups(options: &RunOnDirectoryOptions, summary: &mut CopySummary) -> Result<()> {
+    let Some(dvd_dir) = options.output.local_dir() else {
+        debug!("reconcile_ifo_backups: skipped since the output is not local");
+        return Ok(());
+    };
+    let paths: BTreeSet<String> = (summary.manifest.entries.iter().map(|entry| entry.path.clone()))
+        .chain(summary.incomplete_files.iter().cloned())
+        .collect();
+    for path in paths.iter().filter(|path| path.ends_with(".IFO")) {
+        let Some(backup_path) = ifo_backup_counterpart(path).filter(|backup_path| paths.contains(backup_path)) else {
+            continue;
+        };
+        match (read_checked_ifo(dvd_dir, path, summary), read_checked_ifo(dvd_dir, &backup_path, summary)) {
+            (Ok(ifo), Ok(backup)) => {
+                if ifo != backup {
+                    let problem = format!("differs from its backup {}", backup_path);
+                    warn!("{}: {}", path, problem);
+                    summary.suspicious_files.push((path.clone(), problem));
+                }
+            }
+            (Err(reason), Ok(backup)) => {
+                substitute_ifo(options, dvd_dir, path, &backup_path, &backup, reason, summary)?
+            }
+            (Ok(ifo), Err(reason)) => substitute_ifo(options, dvd_dir, &backup_path, path, &ifo, reason, summary)?,
+            (Err(ifo_reason), Err(backup_reason)) => {
+                error!(
+                    "Neither {} ({}) nor its backup {} ({}) is usable",
+                    path, ifo_reason, backup_path, backup_reason
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The contents of a copied IFO or BUP if it was copied completely and passes check_ifo,
+/// otherwise why not
+fn read_checked_ifo(dvd_dir: &Path, path: &str, summary: &CopySummary) -> std::result::Result<Vec<u8>, String> {
+    if summary.incomplete_files.iter().any(|incomplete| incomplete == path) {
+        return Err("it could not be read from the disc".to_string());
+    }
+    let ifo = std::fs::read(dvd_dir.join(path)).map_err(|e| e.to_string())?;
+    check_ifo(path, &ifo).map_err(|e| e.to_string())?;
+    Ok(ifo)
+}
+
+/// Replace the copy of path (complete or .partial) with contents of source_path
+fn substitute_ifo(
+    options: &RunOnDirectoryOptions,
+    dvd_dir: &Path,
+    path: &str,
+    source_path: &str,
+    contents: &[u8],
+    reason: String,
+    summary: &mut CopySummary,
+) -> Result<()> {
+    warn!("{}: {}; replacing it with a copy of {}", path, reason, source_path);
+    let output_path = dvd_dir.join(path);
+    let partial_output_path = partial_path(&output_path);
+    std::fs::write(&partial_output_path, contents)?;
+    std::fs::rename(&partial_output_path, &output_path)?;
+    summary.incomplete_files.retain(|incomplete| incomplete != path);
+    // an IFO and its BUP are the same size, so a replaced complete copy was already counted
+    let was_copied = summary.manifest.entries.iter().any(|entry| entry.path == path);
+    summary.manifest.entries.retain(|entry| entry.path != path);
+    let size = if was_copied { 0 } else { contents.len() as u64 };
+    file_written(options, path, hash_reader(contents)?, size, summary)?;
+    summary.substitutions.push(IfoSubstitution {
+        path: path.to_string(),
+        source: source_path.to_string(),
+        reason,
+    });
+    Ok(())
+}
+
+/// Check the first sector of a copied file whose type can be recognized (currently the
+/// AUDIO_TS files, which are otherwise copied as opaque files)
+fn check_copied_file_header(dvd_dir: &Path, path: &str) -> Result<Option<String>> {
+    if check_file_header(path, &[]).is_none() {
+        return Ok(None);
+    }
+    let mut header = Vec::with_capacity(DVDCSS_BLOCK_SIZE);
+    std::fs::File::open(dvd_dir.join(path))?
+        .take(DVDCSS_BLOCK_SIZE as u64)
+        .read_to_end(&mut header)?;
+    Ok(check_file_header(path, &header))
+}
+
+fn read_file<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    options: &RunOnDirectoryOptions,
+    path: String,
+    icb_address: &LongAd,
+) -> Result<(String, Vec<NavProblem>)> {
+    let output = options.output;
+    // path starts with "/" which would make join() discard dvd_dir
+    let output_path = path.trim_start_matches('/');
+    check_interrupted()?;
+    if output.exists(output_path)? {
+        if options.resume {
+            let digest = check_completed_file(
+                reader,
+                logical_volume_descriptor,
+                partition_descriptors,
+                options,
+                output_path,
+                icb_address,
+            )?;
+            return Ok((digest, vec![]));
+        }
+        error!(
+            "Output file {} already exists; continue an earlier copy with --resume",
+            output.describe(output_path)
+        );
+        return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+    }
+    // the output only appears under its name once complete
+    // so that a failed or interrupted copy is never mistaken for a complete file
+    let output_file = match options.resume {
+        true => output.resume(output_path),
+        false => output.create(output_path),
+    };
+    let output_file = output_file.map_err(|err| {
+        let hint = match err.kind() {
+            std::io::ErrorKind::AlreadyExists => "; continue an earlier copy with --resume",
+            _ => "",
+        };
+        error!("Could not open output file {}: {}{}", output.describe(output_path), err, hint);
+        err
+    })?;
+    let resumed = output_file.resumed()?;
+    if let Some(progress) = options.progress {
+        let (_, file_entries) =
+            read_icb_entries(reader, logical_volume_descriptor, partition_descriptors, icb_address)?;
+        progress.start_file(&path, file_entries.first().map_or(0, |file_entry| file_entry.information_length));
+        progress.advance(resumed.as_ref().map_or(0, |(resume_size, _)| *resume_size));
+    }
+    if let (Some(image), Some(file)) = (options.offload, output_file.local_file()) {
+        let start = resumed.as_ref().map_or(0, |(resume_size, _)| *resume_size);
+        let (_, file_entries) =
+            read_icb_entries(reader, logical_volume_descriptor, partition_descriptors, icb_address)?;
+        let extents = file_data_extents(
+            logical_volume_descriptor,
+            partition_descriptors,
+            icb_address.extent_location.partition_reference_number,
+            &file_entries,
+            &path,
+        )?;
+        // data embedded in the File Entry is not a range of the image to copy
+        let embedded = file_entries.iter().any(|file_entry| embedded_data(file_entry).is_some());
+        let offloaded = !embedded && offload_file_data(image, &extents, &path, start, file)?;
+        if offloaded {
+            if let Some(progress) = options.progress {
+                let size = file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+                progress.advance(size.saturating_sub(start));
+            }
+            output_file.finish()?;
+            // offload is only used when nothing needs the digest
+            return Ok((String::new(), vec![]));
+        }
+        debug!("read_file: {}: copy offload is not supported; reading and writing", path);
+    }
+    // with two writer threads, the output file is written on one while what the disc read
+    // is hashed and checked on the other
+    let ((digest, nav_problems), output_file) =
+        write_pipelined(output_file, options.writer_threads >= 2, |output_file| -> Result<_> {
+            let mut output_file = HashingWriter::new(output_file);
+            // the bytes that an interrupted copy already wrote are not read from the disc again
+            let resume_size = match resumed {
+                Some((resume_size, existing)) => {
+                    info!("Resuming {} after {} bytes", output.describe(output_path), resume_size);
+                    output_file.hash_existing(existing)?
+                }
+                None => 0,
+            };
+            let reporting_output = ReportingWriter::new(&mut output_file, options.progress);
+            let pipelined = options.writer_threads >= 1;
+            // the navigation packs can only be checked from the start of the VOB
+            let nav_problems = if options.check_nav && path.ends_with(".VOB") && resume_size == 0 {
+                let checking_output = NavCheckingWriter::new(reporting_output);
+                let ((), checking_output) = write_pipelined(checking_output, pipelined, |checking_output| {
+                    write_file_data(
+                        reader,
+                        logical_volume_descriptor,
+                        partition_descriptors,
+                        &path,
+                        icb_address,
+                        0,
+                        checking_output,
+                    )
+                })?;
+                let (_, checker) = checking_output.finish();
+                debug!("read_file: {}: checked {} navigation packs", path, checker.nav_packs());
+                checker.finish()
+            } else {
+                write_pipelined(reporting_output, pipelined, |reporting_output| {
+                    write_file_data(
+                        reader,
+                        logical_volume_descriptor,
+                        partition_descriptors,
+                        &path,
+                        icb_address,
+                        resume_size,
+                        reporting_output,
+                    )
+                })?;
+                vec![]
+            };
+            let (_, digest) = output_file.finish();
+            Ok((digest, nav_problems))
+        })?;
+    output_file.finish()?;
+
+    Ok((digest, nav_problems))
+}
+
+/// For --resume, check that the output file that an earlier copy completed has the size of the
+/// disc's file, and return the digest of the output
+fn check_completed_file<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    options: &RunOnDirectoryOptions,
+    output_path: &str,
+    icb_address: &LongAd,
+) -> Result<String> {
+    let (_, file_entries) = read_icb_entries(reader, logical_volume_descriptor, partition_descriptors, icb_address)?;
+    let size = file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+    // run() only allows --resume with a local output
+    let dvd_dir = (options.output.local_dir()).ok_or_else(|| std::io::Error::other("--resume needs a local output"))?;
+    let output_file = File::open(dvd_dir.join(output_path))?;
+    let output_size = output_file.metadata()?.len();
+    if output_size != size {
+        error!(
+            "Output file {} has {} bytes but the disc's file has {}; remove it to copy it again",
+            options.output.describe(output_path),
+            output_size,
+            size
+        );
+        return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+    }
+    info!("Keeping {}, which was already copied", options.output.describe(output_path));
+    if let Some(progress) = options.progress {
+        progress.start_file(output_path, size);
+        progress.advance(size);
+    }
+    Ok(hash_reader(output_file)?)
+}
+
+/// Copy the title VOBs of a title set as one stream, split at split_size if given
+fn copy_joined_vobs(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    options: &RunOnDirectoryOptions,
+    title_set: u8,
+    parts: &[&(String, LongAd)],
+    split_size: Option<u64>,
+    summary: &mut CopySummary,
+) -> Result<()> {
+    let mut copy = || -> Result<(Vec<(String, String, u64)>, Vec<NavProblem>, Vec<FileObject>)> {
+        let mut reader = parser.cache();
+        let mut output = SplitVobOutput::new(options.output, title_set, split_size)?;
+        // the digest of each disc file, for --dfxml
+        let mut write_parts = |output: &mut dyn Write| -> Result<Vec<String>> {
+            let mut part_digests = vec![];
+            for (path, icb_address) in parts {
+                if let Some(progress) = options.progress {
+                    let (_, file_entries) = read_icb(&mut reader, structures, icb_address)?;
+                    progress.start_file(path, file_entries.first().map_or(0, |file_entry| file_entry.information_length));
+                }
+                let mut part_output = HashingWriter::new(ReportingWriter::new(&mut *output, options.progress));
+                write_file_data(
+                    &mut reader,
+                    &structures.logical_volume,
+                    &structures.partition_descriptors,
+                    path,
+                    icb_address,
+                    0,
+                    &mut part_output,
+                )?;
+                part_digests.push(part_output.finish().1);
+            }
+            Ok(part_digests)
+        };
+        let (part_digests, nav_problems) = if options.check_nav {
+            let mut checking_output = NavCheckingWriter::new(&mut output);
+            let part_digests = write_parts(&mut checking_output)?;
+            (part_digests, checking_output.finish().1.finish())
+        } else {
+            (write_parts(&mut output)?, vec![])
+        };
+        let mut file_objects = vec![];
+        if options.dfxml {
+            for ((path, icb_address), digest) in parts.iter().zip(part_digests) {
+                file_objects.push(file_object(
+                    &mut reader,
+                    &structures.logical_volume,
+                    &structures.partition_descriptors,
+                    options.virtual_partitions,
+                    path,
+                    icb_address,
+                    Some(digest),
+                )?);
+            }
+        }
+        Ok((output.finish()?, nav_problems, file_objects))
+    };
+    match copy() {
+        Ok((outputs, nav_problems, file_objects)) => {
+            summary.file_objects.extend(file_objects);
+            for (path, digest, size) in outputs {
+                file_written(options, &path, digest, size, summary)?;
+            }
+            summary
+                .nav_problems
+                .extend(nav_problems.into_iter().map(|problem| (title_vob_path(title_set), problem)));
+        }
+        Err(e) if stops_copy(&e) => {
+            summary.incomplete_files.push(title_vob_path(title_set));
+            return Err(e);
+        }
+        Err(e) if options.keep_going => {
+            error!("Could not copy the VOBs of title set {}; continuing: {}", title_set, e);
+            summary.incomplete_files.push(title_vob_path(title_set));
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Writes one stream as VIDEO_TS/VTS_nn_1.VOB, VTS_nn_2.VOB, ..., starting a new part
+/// whenever split_size bytes have been written to the current one.
+/// The parts only appear under their names once all of them are written, in finish()
+struct SplitVobOutput<'a> {
+    output: &'a dyn OutputSink,
+    title_set: u8,
+    split_size: Option<u64>,
+    current: Option<HashingWriter<Box<dyn OutputFile + 'a>>>,
+    /// disc-style paths of the parts so far
+    parts: Vec<String>,
+    /// the written parts with their digests and sizes
+    written_parts: Vec<(Box<dyn OutputFile + 'a>, String, u64)>,
+}
+impl<'a> SplitVobOutput<'a> {
+    fn new(output: &'a dyn OutputSink, title_set: u8, split_size: Option<u64>) -> std::io::Result<Self> {
+        let mut output = Self {
+            output,
+            title_set,
+            split_size,
+            current: None,
+            parts: vec![],
+            written_parts: vec![],
+        };
+        output.start_part()?;
+        Ok(output)
+    }
+
+    fn finish_part(&mut self) -> std::io::Result<()> {
+        if let Some(writer) = self.current.take() {
+            let size = writer.bytes_written();
+            let (file, digest) = writer.finish();
+            self.written_parts.push((file, digest, size));
+        }
+        Ok(())
+    }
+
+    fn start_part(&mut self) -> std::io::Result<()> {
+        self.finish_part()?;
+        let part = self.parts.len() + 1;
+        if part == 10 {
+            warn!(
+                "Title set {} needs more than 9 VOB parts, which players may not read; use a larger --split-size",
+                self.title_set
+            );
+        }
+        let path = format!("VIDEO_TS/VTS_{:02}_{}.VOB", self.title_set, part);
+        if self.output.exists(&path)? {
+            error!("Output file {} already exists", self.output.describe(&path));
+            return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists));
+        }
+        self.current = Some(HashingWriter::new(self.output.create(&path)?));
+        self.parts.push(path);
+        Ok(())
+    }
+
+    /// Make the parts appear under their names and return the path, digest and size of each
+    fn finish(mut self) -> std::io::Result<Vec<(String, String, u64)>> {
+        self.finish_part()?;
+        let mut outputs = vec![];
+        for (path, (file, digest, size)) in self.parts.into_iter().zip(self.written_parts) {
+            file.finish()?;
+            outputs.push((path, digest, size));
+        }
+        Ok(outputs)
+    }
+}
+impl Write for SplitVobOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.current.as_ref().map_or(0, |writer| writer.bytes_written());
+        let len = match self.split_size {
+            Some(split_size) if written >= split_size => {
+                self.start_part()?;
+                buf.len().min(split_size as usize)
+            }
+            Some(split_size) => buf.len().min((split_size - written) as usize),
+            None => buf.len(),
+        };
+        match &mut self.current {
+            Some(writer) => writer.write(&buf[..len]),
+            None => Err(std::io::Error::other("VOB output is already finished")),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.current {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The DFXML description of the disc file at icb_address
+fn file_object<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    virtual_partitions: &BTreeMap<u16, VirtualPartition>,
+    path: &str,
+    icb_address: &LongAd,
+    sha256: Option<String>,
+) -> Result<FileObject> {
+    let partition_descriptor = partition_descriptors
+        .get(&{ icb_address.extent_location.partition_reference_number })
+        .ok_or(UdfError::InvalidPartitionNumber)?;
+    let file_entries = read_file_entries(
+        reader,
+        logical_volume_descriptor,
+        partition_descriptor,
+        &ShortAllocationDescriptor {
+            extent_length_and_type: icb_address.extent_length_and_type,
+            extent_location: icb_address.extent_location.logical_block_number,
+        },
+    )?;
+    let first_entry = file_entries.first();
+    // embedded data has no byte runs of its own
+    let extents = match first_entry.and_then(embedded_data) {
+        Some(_) => vec![],
+        None => recorded_file_extents(
+            logical_volume_descriptor,
+            partition_descriptors,
+            icb_address.extent_location.partition_reference_number,
+            &file_entries,
+            path,
+        )?,
+    };
+    // the blocks of a virtual partition are recorded wherever its VAT maps them
+    let sector_size = DVDCSS_BLOCK_SIZE as u64;
+    let mut byte_runs = vec![];
+    for (mut file_offset, partition_descriptor, pos_in_partition, mut len) in extents {
+        let pos = partition_pos_to_image_pos(partition_descriptor, pos_in_partition) as u64;
+        let sectors = (pos / sector_size) as u32..(pos + len).div_ceil(sector_size) as u32;
+        let mut skip = pos % sector_size;
+        for run in translate_virtual_sectors(virtual_partitions, sectors)? {
+            let run_len = ((run.end - run.start) as u64 * sector_size - skip).min(len);
+            byte_runs.push(ByteRun {
+                file_offset,
+                img_offset: run.start as u64 * sector_size + skip,
+                len: run_len,
+            });
+            file_offset += run_len;
+            len -= run_len;
+            skip = 0;
+        }
+    }
+    Ok(FileObject {
+        filename: path.trim_start_matches('/').to_string(),
+        filesize: first_entry.map_or(0, |file_entry| file_entry.information_length),
+        mtime: first_entry.map(|file_entry| file_entry.modification_time.iso8601()),
+        atime: first_entry.map(|file_entry| file_entry.access_time.iso8601()),
+        ctime: first_entry.map(|file_entry| file_entry.attribute_time.iso8601()),
+        byte_runs,
+        sha256,
+    })
+}
+
+/// How much of an extent copy_range copies between checks for Ctrl-C
+const OFFLOAD_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Like write_file_data, but copying the extents of a file (from file_data_extents) from the
+/// image file to output within the kernel. Returns false, having written nothing, if the file
+/// systems cannot copy this way.
+fn offload_file_data(
+    image: &OffloadImage,
+    extents: &[(&PartitionDescriptor, usize, u64)],
+    path: &str,
+    start: u64,
+    output: &File,
+) -> Result<bool> {
+    let mut skip = start;
+    let mut copied = 0u64;
+    for &(partition_descriptor, pos_in_partition, length) in extents {
+        if skip >= length {
+            skip -= length;
+            continue;
+        }
+        let extent_start = image.offset + partition_pos_to_image_pos(partition_descriptor, pos_in_partition) as u64;
+        let mut offset = std::mem::take(&mut skip);
+        while offset < length {
+            check_interrupted()?;
+            let len = (length - offset).min(OFFLOAD_CHUNK_SIZE);
+            if !copy_range(&image.file, extent_start + offset, output, len)? {
+                if copied == 0 {
+                    return Ok(false);
+                }
+                return Err(std::io::Error::other(format!(
+                    "copy offload of {} stopped working after {} bytes",
+                    path, copied
+                ))
+                .into());
+            }
+            offset += len;
+            copied += len;
+        }
+    }
+    if skip > 0 {
+        return Err(partial_copy_too_long(path, skip));
+    }
+    Ok(true)
+}
+
+/// An image file to copy files from with copy offload
+struct OffloadImage {
+    file: File,
+    /// where the file system starts in the file
+    offset: u64,
+}
+
+/// The image file to copy files from with copy offload: a local image that is not scrambled,
+/// when no option needs the bytes of the files as they are copied
+fn open_offload_image(args: &Args, css: &DvdCss, offset: u64) -> Option<OffloadImage> {
+    let needs_bytes = args.manifest.is_some()
+        || args.dfxml.is_some()
+        || args.exec.is_some()
+        || args.check_nav
+        || args.compress.is_some()
+        || args.chunk_size.is_some();
+    // the extents are found at 2048-byte sectors, which raw CD images do not have
+    if needs_bytes || css.is_scrambled() || !matches!(detect_device(&args.disc.device), Ok(DeviceKind::Image)) {
+        return None;
+    }
+    let file = File::open(&args.disc.device).ok()?;
+    file.metadata().ok()?.is_file().then_some(OffloadImage { file, offset })
+}
+
+fn run(args: &Args) -> Result<CopySummary> {
+    debug!("run");
+    let started = Instant::now();
+    install_handler()?;
+    let key_cache = args.dump_keys.then(KeyCacheDir::create).transpose()?;
+    let (offset, length) = locate_file_system(&args.disc)?;
+    let css = match &key_cache {
+        Some(key_cache) => key_cache.open(|| open_device_at(&args.disc.device, offset))?,
+        None => open_device_at(&args.disc.device, offset)?,
+    };
+    let offload_image = open_offload_image(args, &css, offset);
+    let readahead = args.readahead.map_or(Readahead::Sequential, Readahead::Blocks);
+    let mut parser = new_parser(css, &args.disc.device, offset, length)
+        .with_read_error_policy(read_error_policy(args))
+        .with_readahead(readahead)
+        .with_cache_blocks(args.disc.cache_blocks())
+        .with_max_depth(args.disc.max_depth);
+    if let Some(volume) = iso9660_volume(&mut parser, args.fs)? {
+        return copy_iso9660(args, &mut parser.reader, &volume, started);
+    }
+    let structures = parser.read_volume_structures()?;
+    debug!("volume structures {:?}", structures);
+    // copy offload would copy the packets that a Sparing Table has moved from where they were,
+    // and cannot find the blocks of virtual partitions
+    let offload_image =
+        offload_image.filter(|_| parser.sparable_partitions.is_empty() && parser.virtual_partitions.is_empty());
+    let virtual_partitions = parser.virtual_partitions.clone();
+    let name_from_dvd = titlecase_name(&structures.primary_volume.volume_identifier.to_string());
+    debug!("name from dvd: {}", name_from_dvd);
+    let name: &str = &sanitize_name(args.name.as_ref().unwrap_or(&name_from_dvd), args.target_os);
+    let root_output = open_copy_output(args, name)?;
+    if args.format == CopyFormat::Iso {
+        let size = match length {
+            Some(length) => length,
+            None => device_size(&args.disc.device)?.saturating_sub(offset),
+        };
+        return copy_disc_image(args, &mut parser, &structures, &*root_output, name, size);
+    }
+    let max_depth = parser.max_depth;
+    load_title_keys(&mut parser, &structures, max_depth)?;
+    let output = SubdirectoryOutput::new(&*root_output, name);
+    let compressed_output = args.compress.map(|compression| CompressedOutput::new(&output, compression)).transpose()?;
+    let chunk_store = SubdirectoryOutput::new(&*root_output, CHUNK_STORE_DIR);
+    let chunked_output = (args.chunk_size).map(|chunk_size| ChunkedOutput::new(&output, &chunk_store, chunk_size as usize));
+    let mut summary = CopySummary {
+        disc_stats: parser.stats(),
+        ..Default::default()
+    };
+    let filter = PathFilter::new(args.include.clone(), args.exclude.clone());
+    let progress = args.progress.then(TerminalProgress::new);
+    let options = RunOnDirectoryOptions {
+        output: match (&compressed_output, &chunked_output) {
+            (Some(compressed_output), _) => compressed_output,
+            (_, Some(chunked_output)) => chunked_output,
+            (None, None) => &output,
+        },
+        keep_going: args.keep_going,
+        resume: args.resume,
+        check_nav: args.check_nav,
+        exec: args.exec.as_deref().map(ExecHook::new),
+        dfxml: args.dfxml.is_some(),
+        virtual_partitions: &virtual_partitions,
+        permissions: args.preserve.contains(&Preserve::Permissions) && !args.no_permissions,
+        timestamps: args.preserve.contains(&Preserve::Timestamps),
+        ownership: args.preserve.contains(&Preserve::Ownership),
+        writer_threads: args.writer_threads,
+        symlinks: args.symlinks,
+        offload: offload_image.as_ref().filter(|_| root_output.local_dir().is_some()),
+        progress: progress.as_ref().map(|progress| progress as &dyn ProgressSink),
+    };
+    // with --join-vobs, the title VOBs of each title set are copied after the walk in part order
+    let mut title_vobs: BTreeMap<u8, BTreeMap<u8, (String, LongAd)>> = BTreeMap::new();
+    // with --copy-order metadata-first, the files are copied after the walk with their sort key
+    let mut deferred_files: Vec<((u8, u64), String, LongAd)> = vec![];
+    let mut sanitizer = path_sanitizer(args, &*root_output, name)?;
+    if progress.is_some() || root_output.local_dir().is_some() {
+        let (total, needed) = copy_size(&mut parser, &structures, &filter, options.output, &mut sanitizer, args.resume)?;
+        if let Some(progress) = &progress {
+            progress.set_total(total);
+        }
+        check_free_space(args, &*root_output, needed)?;
+    }
+    // files that the disc hides from the user, which are hidden once copied
+    let mut hidden_files: Vec<String> = vec![];
+    let copied = (|| -> Result<()> {
+        walk_filtered_filesystem(&mut parser, &structures, &filter, &mut |reader, path, icb_address, characteristics| {
+            let output_path = sanitizer.output_path(path);
+            let path = output_path.as_str();
+            if characteristics & FileIdentifierDescriptor::FILE_CHARACTERISTIC_EXISTENCE != 0 {
+                hidden_files.push(path.to_string());
+            }
+            if let Some((title_set, part)) = parse_title_vob_path(path).filter(|_| args.join_vobs) {
+                title_vobs
+                    .entry(title_set)
+                    .or_default()
+                    .insert(part, (path.to_string(), icb_address.clone()));
+                return Ok(());
+            }
+            if args.copy_order == CopyOrder::MetadataFirst {
+                let (_, file_entries) = read_icb(reader, &structures, icb_address)?;
+                let size = file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+                deferred_files.push((metadata_first_key(path, size), path.to_string(), icb_address.clone()));
+                return Ok(());
+            }
+            copy_file(
+                reader,
+                &structures.logical_volume,
+                &structures.partition_descriptors,
+                &options,
+                path,
+                icb_address,
+                &mut summary,
+            )
+        })?;
+        // stable, so files of the same size keep the disc order
+        deferred_files.sort_by_key(|(key, _, _)| *key);
+        let mut reader = parser.cache();
+        for (_, path, icb_address) in &deferred_files {
+            copy_file(
+                &mut reader,
+                &structures.logical_volume,
+                &structures.partition_descriptors,
+                &options,
+                path,
+                icb_address,
+                &mut summary,
+            )?;
+        }
+        for (title_set, parts) in &title_vobs {
+            let parts: Vec<_> = parts.values().collect();
+            copy_joined_vobs(&mut parser, &structures, &options, *title_set, &parts, args.split_size, &mut summary)?;
+        }
+        Ok(())
+    })();
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    summary.unreadable_sectors = parser.read_error_policy.skipped.lock().unwrap().clone();
+    // when the output fills up or Ctrl-C is pressed, what was copied is still recorded
+    let stopped = match copied {
+        Err(e) if stops_copy(&e) => {
+            error!("Stopping the copy: {}", e);
+            Some(e)
+        }
+        Err(e) => return Err(e),
+        Ok(()) => None,
+    };
+    if stopped.is_none() {
+        reconcile_ifo_backups(&options, &mut summary)?;
+    }
+    if let (Some(dvd_dir), false) = (options.output.local_dir(), args.no_hidden) {
+        let copied: BTreeSet<&str> = summary.manifest.entries.iter().map(|entry| entry.path.as_str()).collect();
+        for path in hidden_files.iter().filter(|path| copied.contains(path.as_str())) {
+            set_attributes(&dvd_dir.join(path), None, true)?;
+        }
+    }
+    summary.renamed = sanitizer.renamed();
+    for (disc_path, output_path) in &summary.renamed {
+        summary.manifest.comments.push(format!("{} was written as {}", disc_path, output_path));
+    }
+    if let Some(report_path) = &args.rename_report {
+        sanitizer.write_report(std::io::BufWriter::new(std::fs::File::create(report_path)?))?;
+    }
+    if let Some(manifest_path) = &args.manifest {
+        summary.manifest.write(std::fs::File::create(manifest_path)?)?;
+    }
+    if let Some(dfxml_path) = &args.dfxml {
+        let dfxml = Dfxml {
+            image_filename: args.disc.device.clone(),
+            volume_name: structures.primary_volume.volume_identifier.to_string(),
+            files: std::mem::take(&mut summary.file_objects),
+        };
+        dfxml.write(std::io::BufWriter::new(std::fs::File::create(dfxml_path)?))?;
+    }
+    if let Some(e) = stopped {
+        if interrupted() {
+            summary.print_stopped(started, args.json);
+            return Err(e);
+        }
+        let (files, bytes) = remaining_files(&mut parser, &structures, &mut sanitizer, &summary, args.join_vobs, &filter)?;
+        summary.print_stopped(started, args.json);
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::StorageFull,
+            format!(
+                "the output ran out of space; {} more bytes are needed for the {} files that were not copied completely",
+                bytes, files
+            ),
+        )
+        .into());
+    }
+    let mut sidecar = Sidecar {
+        dvd_id: read_dvd_id(&mut parser, &structures)?.map(|id| id.to_string()),
+        volume_times: Some(read_volume_times(&mut parser, &structures)),
+        ..Default::default()
+    };
+    if let Some(key_cache) = key_cache {
+        sidecar.title_keys = dump_title_keys(&mut parser, &structures, key_cache.path())?;
+        if sidecar.title_keys.is_empty() {
+            warn!("No title keys were found; the disc may not be scrambled");
+        }
+        // with --output-format json, they are only in the sidecar file
+        for key in sidecar.title_keys.iter().filter(|_| !args.json) {
+            println!("{} (sector {}): {}", key.path, key.sector, key.key);
+        }
+    }
+    if !sidecar.is_empty() {
+        let mut file = root_output.create(&Sidecar::file_name(name))?;
+        sidecar.write(&mut file)?;
+        file.finish()?;
+    }
+    // last, since writing into the directory changes its time
+    let recorded = &structures.primary_volume.recording_date_and_time;
+    if let (Some(output_dir), true) = (root_output.local_dir(), recorded.year != 0) {
+        set_directory_modified(&output_dir.join(name), recorded.system_time())?;
+    }
+    // structures.partition_maps
+    Ok(summary)
+}
+
+/// The output of the copy, with the <name> directory created in it when it is local
+fn open_copy_output(args: &Args, name: &str) -> Result<Box<dyn OutputSink>> {
+    let root_output = open_output(&args.output)?;
+    if let Some(output_dir) = root_output.local_dir() {
+        if args.format == CopyFormat::Files {
+            if let Err(e) = create_dir(output_dir.join(name)) {
+                if e.kind() != std::io::ErrorKind::AlreadyExists {
+                    return Err(e.into());
+                }
+            }
+        }
+    } else if args.exec.is_some() {
+        return Err(std::io::Error::other("--exec needs a local --output directory").into());
+    } else if args.resume {
+        // the files that were already copied are read back to check and checksum them
+        return Err(std::io::Error::other("--resume needs a local --output directory").into());
+    }
+    Ok(root_output)
+}
+
+/// The PathSanitizer of the copy into the <name> directory of root_output
+fn path_sanitizer(args: &Args, root_output: &dyn OutputSink, name: &str) -> Result<PathSanitizer> {
+    // names that differ only in case would overwrite each other on Windows and macOS
+    let case_insensitive = args.target_os == TargetOs::Windows
+        || match root_output.local_dir() {
+            Some(output_dir) => is_case_insensitive(&output_dir.join(name))?,
+            None => false,
+        };
+    Ok(PathSanitizer::new(args.target_os, case_insensitive))
+}
+
+/// A file that --dry-run found the copy would write
+struct PlannedFile {
+    /// the disc path, or the image's name with --format iso
+    path: String,
+    /// the path in the output, which differs for --target-os renames and with --join-vobs
+    output_path: String,
+    size: u64,
+    /// whether the output already has the file, which stops the copy unless --resume
+    exists: bool,
+}
+
+/// Walk the disc the way run() does and print what it would write and the space that needs,
+/// without reading file data. Fails the way the copy would if an output already exists or the
+/// output does not have the space
+fn run_dry_run(args: &Args) -> Result<()> {
+    let mut parser = open_parser(&args.disc)?;
+    let filter = PathFilter::new(args.include.clone(), args.exclude.clone());
+    // the disc path and size of each file, or None for --format iso
+    let mut files: Option<Vec<(String, u64)>> = None;
+    let name_from_disc = match iso9660_volume(&mut parser, args.fs)? {
+        Some(volume) => {
+            files = Some(
+                (read_iso9660_files(&mut parser.reader, &volume)?.into_iter())
+                    .filter(|file| filter.includes(&file.path))
+                    .map(|file| (file.path.clone(), file.size()))
+                    .collect(),
+            );
+            titlecase_name(&volume.identifier)
+        }
+        None => {
+            let structures = parser.read_volume_structures()?;
+            if args.format == CopyFormat::Files {
+                let mut disc_files = vec![];
+                walk_filtered_filesystem(&mut parser, &structures, &filter, &mut |reader, path, icb_address, _| {
+                    let (_, file_entries) = read_icb(reader, &structures, icb_address)?;
+                    disc_files.push((
+                        path.to_string(),
+                        file_entries.first().map_or(0, |file_entry| file_entry.information_length),
+                    ));
+                    Ok(())
+                })?;
+                files = Some(disc_files);
+            }
+            titlecase_name(&structures.primary_volume.volume_identifier.to_string())
+        }
+    };
+    let name: &str = &sanitize_name(args.name.as_ref().unwrap_or(&name_from_disc), args.target_os);
+    let root_output = open_output(&args.output)?;
+    let mut planned = vec![];
+    let mut bytes_needed = 0;
+    let output_dir = match files {
+        None => {
+            let (offset, length) = locate_file_system(&args.disc)?;
+            let size = match length {
+                Some(length) => length,
+                None => device_size(&args.disc.device)?.saturating_sub(offset),
+            };
+            let image_path = format!("{}.iso", name);
+            // with the map of an earlier pass, the copy reads the bad sectors into the image again
+            let retry = root_output.exists(&format!("{}.map", name))?;
+            let exists = root_output.exists(&image_path)?;
+            if !exists || !(args.resume || retry) {
+                bytes_needed = size;
+            }
+            planned.push(PlannedFile {
+                path: image_path.clone(),
+                output_path: image_path,
+                size,
+                exists: exists && !retry,
+            });
+            args.output.display().to_string()
+        }
+        Some(files) => {
+            let output = SubdirectoryOutput::new(&*root_output, name);
+            let compressed_output =
+                args.compress.map(|compression| CompressedOutput::new(&output, compression)).transpose()?;
+            let chunk_store = SubdirectoryOutput::new(&*root_output, CHUNK_STORE_DIR);
+            let chunked_output =
+                (args.chunk_size).map(|chunk_size| ChunkedOutput::new(&output, &chunk_store, chunk_size as usize));
+            let output: &dyn OutputSink = match (&compressed_output, &chunked_output) {
+                (Some(compressed_output), _) => compressed_output,
+                (_, Some(chunked_output)) => chunked_output,
+                (None, None) => &output,
+            };
+            // path_sanitizer would probe the <name> directory, which is not created
+            let case_insensitive = args.target_os == TargetOs::Windows
+                || match root_output.local_dir() {
+                    Some(output_dir) if output_dir.is_dir() => is_case_insensitive(output_dir)?,
+                    _ => false,
+                };
+            let mut sanitizer = PathSanitizer::new(args.target_os, case_insensitive);
+            for (path, size) in files {
+                let output_path = sanitizer.output_path(&path);
+                let output_path = match parse_title_vob_path(&output_path).filter(|_| args.join_vobs) {
+                    Some((title_set, _)) => title_vob_path(title_set),
+                    None => output_path,
+                };
+                let exists = output.exists(&output_path)?;
+                let written = if args.resume { resumed_bytes(output, &output_path) } else { 0 };
+                bytes_needed += size.saturating_sub(written);
+                planned.push(PlannedFile {
+                    path,
+                    output_path,
+                    size,
+                    exists,
+                });
+            }
+            root_output.describe(name)
+        }
+    };
+    let available = root_output.local_dir().map(available_space).transpose()?;
+    let total: u64 = planned.iter().map(|file| file.size).sum();
+    if args.json {
+        let json = serde_json::json!({
+            "output": output_dir,
+            "files": (planned.iter())
+                .map(|file| serde_json::json!({
+                    "path": file.path,
+                    "output_path": file.output_path,
+                    "size": file.size,
+                    "exists": file.exists,
+                }))
+                .collect::<Vec<_>>(),
+            "total_bytes": total,
+            "bytes_needed": bytes_needed,
+            "available_bytes": available,
+        });
+        println!("{}", serde_json::to_string_pretty(&json).map_err(std::io::Error::from)?);
+    } else {
+        println!("Would copy {} files ({}) to {}:", planned.len(), format_size(total), output_dir);
+        for file in &planned {
+            let renamed = if file.output_path != file.path {
+                format!(" as {}", file.output_path)
+            } else {
+                String::new()
+            };
+            let exists = if file.exists { " (exists)" } else { "" };
+            println!("  {}{} {}{}", file.path, renamed, format_size(file.size), exists);
+        }
+        match available {
+            Some(available) => println!("Needs {} of the {} available", format_size(bytes_needed), format_size(available)),
+            None => println!("Needs {}", format_size(bytes_needed)),
+        }
+    }
+    let existing: BTreeSet<&str> = (planned.iter())
+        .filter(|file| file.exists)
+        .map(|file| file.output_path.as_str())
+        .collect();
+    if !existing.is_empty() && !args.resume {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} output files already exist; continue an earlier copy with --resume", existing.len()),
+        )
+        .into());
+    }
+    check_free_space(args, &*root_output, bytes_needed)
+}
+
+/// The ISO 9660 volume to read instead of UDF: with --fs iso9660, or with --fs auto when the
+/// disc has no UDF anchor but has ISO 9660 volume descriptors
+fn iso9660_volume(parser: &mut UdfParser<DvdCss>, fs: FileSystem) -> Result<Option<IsoVolume>> {
+    match fs {
+        FileSystem::Udf => Ok(None),
+        FileSystem::Iso9660 => Ok(Some(read_iso9660_volume(&mut parser.reader)?)),
+        FileSystem::Auto => {
+            let Err(anchor_error) = parser.read_anchor() else {
+                return Ok(None);
+            };
+            match read_iso9660_volume(&mut parser.reader) {
+                Ok(volume) => {
+                    info!("The disc has no UDF anchor ({}); reading its ISO 9660 file system", anchor_error);
+                    Ok(Some(volume))
+                }
+                Err(e) => {
+                    debug!("iso9660_volume: no ISO 9660 file system either: {}", e);
+                    Err(anchor_error)
+                }
+            }
+        }
+    }
+}
+
+/// run() for a disc read as ISO 9660, which has none of the UDF metadata that some options need
+fn copy_iso9660(args: &Args, reader: &mut DvdCss, volume: &IsoVolume, started: Instant) -> Result<CopySummary> {
+    let udf_options = [
+        (args.format == CopyFormat::Iso, "--format iso"),
+        (args.dfxml.is_some(), "--dfxml"),
+        (args.dump_keys, "--dump-keys"),
+        (args.join_vobs, "--join-vobs"),
+        (args.check_nav, "--check-nav"),
+    ];
+    if let Some((_, option)) = udf_options.iter().find(|(used, _)| *used) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} needs a UDF file system, and {} is read as ISO 9660", option, args.disc.device),
+        )
+        .into());
+    }
+    let name_from_disc = titlecase_name(&volume.identifier);
+    let name: &str = &sanitize_name(args.name.as_ref().unwrap_or(&name_from_disc), args.target_os);
+    let root_output = open_copy_output(args, name)?;
+    let output = SubdirectoryOutput::new(&*root_output, name);
+    let compressed_output = args.compress.map(|compression| CompressedOutput::new(&output, compression)).transpose()?;
+    let chunk_store = SubdirectoryOutput::new(&*root_output, CHUNK_STORE_DIR);
+    let chunked_output = (args.chunk_size).map(|chunk_size| ChunkedOutput::new(&output, &chunk_store, chunk_size as usize));
+    let filter = PathFilter::new(args.include.clone(), args.exclude.clone());
+    let mut files: Vec<IsoFile> = (read_iso9660_files(reader, volume)?.into_iter())
+        .filter(|file| filter.includes(&file.path))
+        .collect();
+    if args.copy_order == CopyOrder::MetadataFirst {
+        files.sort_by_key(|file| metadata_first_key(&file.path, file.size()));
+    }
+    let progress = args.progress.then(TerminalProgress::new);
+    if let Some(progress) = &progress {
+        progress.set_total(files.iter().map(IsoFile::size).sum());
+    }
+    let options = RunOnDirectoryOptions {
+        output: match (&compressed_output, &chunked_output) {
+            (Some(compressed_output), _) => compressed_output,
+            (_, Some(chunked_output)) => chunked_output,
+            (None, None) => &output,
+        },
+        keep_going: args.keep_going,
+        resume: args.resume,
+        check_nav: false,
+        exec: args.exec.as_deref().map(ExecHook::new),
+        dfxml: false,
+        virtual_partitions: &BTreeMap::new(),
+        // ISO 9660 records no permissions or owners without Rock Ridge
+        permissions: false,
+        timestamps: args.preserve.contains(&Preserve::Timestamps),
+        ownership: false,
+        writer_threads: args.writer_threads,
+        symlinks: args.symlinks,
+        offload: None,
+        progress: progress.as_ref().map(|progress| progress as &dyn ProgressSink),
+    };
+    let mut sanitizer = path_sanitizer(args, &*root_output, name)?;
+    let needed = (files.iter())
+        .map(|file| {
+            let written = if args.resume { resumed_bytes(options.output, &sanitizer.output_path(&file.path)) } else { 0 };
+            file.size().saturating_sub(written)
+        })
+        .sum();
+    check_free_space(args, &*root_output, needed)?;
+    let mut summary = CopySummary::default();
+    let copied = files.iter().try_for_each(|file| {
+        let path = sanitizer.output_path(&file.path);
+        copy_iso9660_file(reader, &options, file, &path, &mut summary)
+    });
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    // when the output fills up or Ctrl-C is pressed, what was copied is still recorded
+    let stopped = match copied {
+        Err(e) if stops_copy(&e) => {
+            error!("Stopping the copy: {}", e);
+            Some(e)
+        }
+        Err(e) => return Err(e),
+        Ok(()) => None,
+    };
+    if stopped.is_none() {
+        reconcile_ifo_backups(&options, &mut summary)?;
+    }
+    summary.renamed = sanitizer.renamed();
+    for (disc_path, output_path) in &summary.renamed {
+        summary.manifest.comments.push(format!("{} was written as {}", disc_path, output_path));
+    }
+    if let Some(report_path) = &args.rename_report {
+        sanitizer.write_report(std::io::BufWriter::new(std::fs::File::create(report_path)?))?;
+    }
+    if let Some(manifest_path) = &args.manifest {
+        summary.manifest.write(std::fs::File::create(manifest_path)?)?;
+    }
+    if let Some(e) = stopped {
+        summary.print_stopped(started, args.json);
+        return Err(e);
+    }
+    Ok(summary)
+}
+
+/// Copy one file of an ISO 9660 file system to path, recording the result in summary
+fn copy_iso9660_file(
+    reader: &mut DvdCss,
+    options: &RunOnDirectoryOptions,
+    file: &IsoFile,
+    path: &str,
+    summary: &mut CopySummary,
+) -> Result<()> {
+    match write_iso9660_file(reader, options, file, path) {
+        Ok(digest) => {
+            if let (Some(dvd_dir), true) = (options.output.local_dir(), options.timestamps) {
+                set_times(&dvd_dir.join(path), Some(file.modified.system_time()), None)?;
+            }
+            file_written(options, path, digest, file.size(), summary)?;
+        }
+        Err(e) if stops_copy(&e) => {
+            summary.incomplete_files.push(path.to_string());
+            return Err(e);
+        }
+        Err(e) if options.keep_going => {
+            error!("Could not copy {:?}; continuing: {}", path, e);
+            summary.incomplete_files.push(path.to_string());
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Like read_file, for a file of an ISO 9660 file system: write it to path and return its digest
+fn write_iso9660_file(reader: &mut DvdCss, options: &RunOnDirectoryOptions, file: &IsoFile, path: &str) -> Result<String> {
+    check_interrupted()?;
+    let output = options.output;
+    if output.exists(path)? {
+        if options.resume {
+            let dvd_dir = (output.local_dir()).ok_or_else(|| std::io::Error::other("--resume needs a local output"))?;
+            let output_file = File::open(dvd_dir.join(path))?;
+            let output_size = output_file.metadata()?.len();
+            if output_size == file.size() {
+                info!("Keeping {}, which was already copied", output.describe(path));
+                if let Some(progress) = options.progress {
+                    progress.start_file(path, output_size);
+                    progress.advance(output_size);
+                }
+                return Ok(hash_reader(output_file)?);
+            }
+            error!(
+                "Output file {} has {} bytes but the disc's file has {}; remove it to copy it again",
+                output.describe(path),
+                output_size,
+                file.size()
+            );
+        } else {
+            error!("Output file {} already exists; continue an earlier copy with --resume", output.describe(path));
+        }
+        return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+    }
+    let output_file = match options.resume {
+        true => output.resume(path),
+        false => output.create(path),
+    };
+    let output_file = output_file.inspect_err(|err| {
+        error!("Could not open output file {}: {}", output.describe(path), err);
+    })?;
+    let resumed = output_file.resumed()?;
+    if let Some(progress) = options.progress {
+        progress.start_file(path, file.size());
+        progress.advance(resumed.as_ref().map_or(0, |(resume_size, _)| *resume_size));
+    }
+    let (digest, output_file) = write_pipelined(output_file, options.writer_threads >= 1, |output_file| -> Result<_> {
+        let mut output_file = HashingWriter::new(ReportingWriter::new(output_file, options.progress));
+        // the bytes that an interrupted copy already wrote are not read from the disc again
+        let mut skip = match resumed {
+            Some((resume_size, existing)) => {
+                info!("Resuming {} after {} bytes", output.describe(path), resume_size);
+                output_file.hash_existing(existing)?
+            }
+            None => 0,
+        };
+        let mut buf = vec![0u8; 1024 * 1024];
+        for &(sector, length) in &file.extents {
+            let length = length as u64;
+            if skip >= length {
+                skip -= length;
+                continue;
+            }
+            let mut offset = std::mem::take(&mut skip);
+            while offset < length {
+                check_interrupted()?;
+                // the disc is read in whole sectors, of which only the bytes of the file are written
+                let block_offset = offset % DVDCSS_BLOCK_SIZE as u64;
+                let end = (length - offset + block_offset).min(buf.len() as u64);
+                let blocks = end.div_ceil(DVDCSS_BLOCK_SIZE as u64) as usize;
+                let start = sector as u64 * DVDCSS_BLOCK_SIZE as u64 + offset - block_offset;
+                reader.seek(SeekFrom::Start(start))?;
+                reader.read_exact(&mut buf[..blocks * DVDCSS_BLOCK_SIZE])?;
+                output_file.write_all(&buf[block_offset as usize..end as usize])?;
+                offset += end - block_offset;
+            }
+        }
+        if skip > 0 {
+            return Err(partial_copy_too_long(path, skip));
+        }
+        let (_, digest) = output_file.finish();
+        Ok(digest)
+    })?;
+    output_file.finish()?;
+    Ok(digest)
+}
+
+/// --format iso: write the size bytes of the disc, decrypted, as <name>.iso
+fn copy_disc_image(
+    args: &Args,
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    root_output: &dyn OutputSink,
+    name: &str,
+    size: u64,
+) -> Result<CopySummary> {
+    if parser.sector_size != DVDCSS_BLOCK_SIZE as u32 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("--format iso cannot copy {}, an image of raw CD sectors", args.disc.device),
+        )
+        .into());
+    }
+    let total_sectors = (size / DVDCSS_BLOCK_SIZE as u64) as u32;
+    let key_sectors: Vec<u32> = file_start_sectors(parser, structures, |path| path.ends_with(".VOB"))?
+        .into_iter()
+        .map(|(_, sector)| sector)
+        .collect();
+    let compressed_output = args.compress.map(|compression| CompressedOutput::new(root_output, compression)).transpose()?;
+    let output: &dyn OutputSink = match &compressed_output {
+        Some(compressed_output) => compressed_output,
+        None => root_output,
+    };
+    let image_path = format!("{}.iso", name);
+    // the map of an earlier pass that could not read some sectors
+    let map_path = format!("{}.map", name);
+    if !args.resume && output.exists(&image_path)? && root_output.exists(&map_path)? {
+        let dir = match (&compressed_output, root_output.local_dir()) {
+            (None, Some(dir)) => dir,
+            _ => {
+                error!("Retrying the sectors of {} needs an uncompressed image in a local directory", map_path);
+                return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput).into());
+            }
+        };
+        return retry_disc_image(args, parser, dir, &image_path, &map_path, total_sectors, &key_sectors);
+    }
+    if output.exists(&image_path)? {
+        error!("Output file {} already exists", output.describe(&image_path));
+        return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists).into());
+    }
+    let resumed_size = if args.resume { resumed_bytes(output, &image_path) } else { 0 };
+    check_free_space(args, root_output, (total_sectors as u64 * DVDCSS_BLOCK_SIZE as u64).saturating_sub(resumed_size))?;
+    let output_file = match args.resume {
+        true => output.resume(&image_path),
+        false => output.create(&image_path),
+    }
+    .map_err(|err| {
+        error!("Could not open output file {}: {}", output.describe(&image_path), err);
+        err
+    })?;
+    let resumed = output_file.resumed()?;
+    let progress = args.progress.then(TerminalProgress::new);
+    if let Some(progress) = &progress {
+        let image_size = total_sectors as u64 * DVDCSS_BLOCK_SIZE as u64;
+        progress.set_total(image_size);
+        progress.start_file(&image_path, image_size);
+        progress.advance(resumed.as_ref().map_or(0, |(resume_size, _)| *resume_size));
+    }
+    let reader = &mut parser.reader;
+    // written and hashed on writer threads as the files are
+    let copied = write_pipelined(output_file, args.writer_threads >= 2, |output_file| -> Result<_> {
+        let mut output_file = HashingWriter::new(output_file);
+        let start = match resumed {
+            Some((resume_size, existing)) => {
+                info!("Resuming {} after {} bytes", output.describe(&image_path), resume_size);
+                output_file.hash_existing(existing)?
+            }
+            None => 0,
+        };
+        let copy = ImageCopy {
+            start,
+            total_sectors,
+            key_sectors: &key_sectors,
+            retries: parser.read_error_policy.retries,
+            skip: parser.read_error_policy.skip,
+        };
+        let reporting_output = ReportingWriter::new(&mut output_file, progress.as_ref().map(|progress| progress as &dyn ProgressSink));
+        let (unreadable, _) = write_pipelined(reporting_output, args.writer_threads >= 1, |reporting_output| {
+            copy_image(reader, reporting_output, &copy, |css, sector| {
+                css.css_seek(sector as i32, DVDCSS_SEEK_KEY).map_err(css_to_io_error)?;
+                Ok(())
+            })
+        })?;
+        Ok((output_file.finish().1, unreadable))
+    });
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    let ((digest, unreadable), output_file) = copied?;
+    output_file.finish()?;
+    if unreadable.sector_count() > 0 {
+        let map = RescueMap {
+            total_sectors,
+            bad: unreadable.clone(),
+            ..Default::default()
+        };
+        write_rescue_map(root_output, &map_path, &map)?;
+    }
+    let mut summary = CopySummary {
+        bytes_copied: total_sectors as u64 * DVDCSS_BLOCK_SIZE as u64,
+        unreadable_sectors: unreadable,
+        disc_stats: parser.stats(),
+        ..Default::default()
+    };
+    summary.manifest.push(&image_path, digest);
+    if let Some(manifest_path) = &args.manifest {
+        summary.manifest.write(std::fs::File::create(manifest_path)?)?;
+    }
+    Ok(summary)
+}
+
+/// A later pass of --format iso over a damaged disc, which reads the sectors that the map file
+/// of an earlier pass records as bad or untried again into its image
+fn retry_disc_image(
+    args: &Args,
+    parser: &mut UdfParser<DvdCss>,
+    dir: &Path,
+    image_path: &str,
+    map_path: &str,
+    total_sectors: u32,
+    key_sectors: &[u32],
+) -> Result<CopySummary> {
+    let map = RescueMap::read(std::io::BufReader::new(std::fs::File::open(dir.join(map_path))?))?;
+    if map.total_sectors != total_sectors {
+        warn!("{} is of {} sectors but the disc has {}", map_path, map.total_sectors, total_sectors);
+    }
+    let to_retry = map.to_retry();
+    info!("Reading the {} sectors that {} records as not read again", to_retry.sector_count(), map_path);
+    let mut image = std::fs::File::options().write(true).open(dir.join(image_path))?;
+    let copy = ImageCopy {
+        start: 0,
+        total_sectors,
+        key_sectors,
+        retries: parser.read_error_policy.retries,
+        skip: parser.read_error_policy.skip,
+    };
+    let unreadable = retry_image(&mut parser.reader, &mut image, &to_retry, &copy, |css, sector| {
+        css.css_seek(sector as i32, DVDCSS_SEEK_KEY).map_err(css_to_io_error)?;
+        Ok(())
+    })?;
+    image.sync_all()?;
+    info!(
+        "Read {} of the sectors; {} still cannot be read",
+        to_retry.sector_count() - unreadable.sector_count(),
+        unreadable.sector_count()
+    );
+    let map = RescueMap {
+        total_sectors,
+        bad: unreadable.clone(),
+        ..Default::default()
+    };
+    write_rescue_map(&LocalOutput::new(dir), map_path, &map)?;
+    let mut summary = CopySummary {
+        bytes_copied: (to_retry.sector_count() - unreadable.sector_count()) * DVDCSS_BLOCK_SIZE as u64,
+        unreadable_sectors: unreadable,
+        disc_stats: parser.stats(),
+        ..Default::default()
+    };
+    if let Some(manifest_path) = &args.manifest {
+        let digest = hash_reader(std::io::BufReader::new(std::fs::File::open(dir.join(image_path))?))?;
+        summary.manifest.push(image_path, digest);
+        summary.manifest.write(std::fs::File::create(manifest_path)?)?;
+    }
+    Ok(summary)
+}
+
+/// Write map as the ddrescue map file at path
+fn write_rescue_map(output: &dyn OutputSink, path: &str, map: &RescueMap) -> Result<()> {
+    let mut file = output.create(path)?;
+    map.write(&mut file)?;
+    file.finish()?;
+    info!("Wrote the sectors that could not be read to {}", output.describe(path));
+    Ok(())
+}
+
+/// The recording times of the disc's volume, integrity and file set descriptors. Only the
+/// first is needed to read the disc, so the others are left out if they cannot be read.
+fn read_volume_times(parser: &mut UdfParser<DvdCss>, structures: &VolumeStructures) -> VolumeTimes {
+    // a year of 0 is an unset time
+    let recorded = |timestamp: &Timestamp| (timestamp.year != 0).then(|| timestamp.iso8601());
+    let integrity = parser
+        .read_logical_volume_integrity(&structures.logical_volume.integrity_sequence_extent)
+        .unwrap_or_else(|e| {
+            warn!("Could not read the Logical Volume Integrity Descriptor: {}", e);
+            None
+        });
+    let mut file_set_recorded = None;
+    if let Some(&(partition_descriptor, first_block)) = file_set_locations(structures).first() {
+        match parser.read_fileset_descriptors_at(partition_descriptor, first_block) {
+            Ok(fsds) => file_set_recorded = fsds.first().and_then(|fsd| recorded(&fsd.recording_date_and_time)),
+            Err(e) => warn!("Could not read the File Set Descriptor: {}", e),
+        }
+    }
+    VolumeTimes {
+        recorded: recorded(&structures.primary_volume.recording_date_and_time),
+        integrity_recorded: integrity.and_then(|lvid| recorded(&lvid.recording_date_and_time)),
+        file_set_recorded,
+    }
+}
+
+/// Calls visit_file for every file in the file set of each Type 1, Metadata or virtual partition
+fn walk_filesystem<F>(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    visit_file: &mut F,
+) -> Result<()>
+where
+    F: FnMut(&mut SharedCache<DvdCss>, &str, &LongAd, u8) -> Result<()>,
+{
+    walk_filtered_filesystem(parser, structures, &PathFilter::default(), visit_file)
+}
+
+/// walk_filesystem for only the files that filter includes
+fn walk_filtered_filesystem<F>(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    filter: &PathFilter,
+    visit_file: &mut F,
+) -> Result<()>
+where
+    F: FnMut(&mut SharedCache<DvdCss>, &str, &LongAd, u8) -> Result<()>,
+{
+    let max_depth = parser.max_depth;
+    walk_file_sets(parser, structures, max_depth, filter, visit_file)
+}
+
+fn run_list(args: &ListArgs) -> Result<()> {
+    let mut parser = open_parser(&args.disc)?;
+    if let Some(volume) = iso9660_volume(&mut parser, args.fs)? {
+        if args.titles {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("--titles needs a UDF file system, and {} is read as ISO 9660", args.disc.device),
+            )
+            .into());
+        }
+        let files = read_iso9660_files(&mut parser.reader, &volume)?;
+        if args.json || args.format != ListFormat::Text {
+            let entries: Vec<ListEntry> = (files.iter())
+                .map(|file| ListEntry {
+                    path: file.path.clone(),
+                    size: file.size(),
+                    extents: file.extents.len(),
+                    modified: file.modified.iso8601(),
+                    ..Default::default()
+                })
+                .collect();
+            print_list_entries(args, &entries)?;
+        } else {
+            for file in &files {
+                println!("{}", file.path);
+            }
+        }
+        return Ok(());
+    }
+    let structures = parser.read_volume_structures()?;
+    if args.titles {
+        let dvd_id = read_dvd_id(&mut parser, &structures)?;
+        let dvd_video = read_dvd_video(&mut parser, &structures)?;
+        // --json conflicts with --titles, so this is --output-format json
+        if args.json {
+            let titles = serde_json::json!({
+                "dvd_id": dvd_id.map(|id| id.to_string()),
+                "titles": dvd_video.titles(),
+            });
+            println!("{}", serde_json::to_string_pretty(&titles).map_err(std::io::Error::from)?);
+            return Ok(());
+        }
+        if let Some(id) = dvd_id {
+            println!("DVD ID: {}", id);
+        }
+        if dvd_video.video_manager.is_none() {
+            println!("No DVD-Video titles ({} not found)", VIDEO_MANAGER_IFO_PATH);
+        }
+        for title in dvd_video.titles() {
+            println!(
+                "Title {}: title set {}, {} chapters, {} angles, {} cells, {}",
+                title.title_number,
+                title.title_set,
+                title.chapters,
+                title.angles,
+                title.cells,
+                Hms(title.duration_millis)
+            );
+            if let Some(title_set) = dvd_video.title_sets.get(&title.title_set) {
+                println!("  Video: {}", title_set.video_attributes);
+            }
+            let Some(entry) = dvd_video.title(title.title_number) else {
+                continue;
+            };
+            if let Ok(durations) = dvd_video.chapter_durations(entry, 1..=entry.number_of_chapters) {
+                let durations: Vec<String> = durations.into_iter().map(|millis| Hms(millis).to_string()).collect();
+                println!("  Chapters: {}", durations.join(" "));
+            }
+            for (i, audio) in dvd_video.title_audio_streams(entry) {
+                println!("  Audio {}: {}", i + 1, audio);
+            }
+            for (i, subpicture) in dvd_video.title_subpicture_streams(entry) {
+                println!("  Subtitle {}: {}", i + 1, subpicture);
+            }
+        }
+        let dvd_audio = read_dvd_audio(&mut parser, &structures)?;
+        if let Some(audio_manager) = &dvd_audio.audio_manager {
+            println!("DVD-Audio: {} audio title sets", audio_manager.number_of_title_sets);
+        }
+        for (title_set_number, title_set) in &dvd_audio.title_sets {
+            for (i, title) in title_set.titles.iter().enumerate() {
+                println!(
+                    "Audio title set {}, title {}: {} tracks, {}",
+                    title_set_number,
+                    i + 1,
+                    title.tracks.len(),
+                    Hms(pts_to_millis(title.length_pts))
+                );
+                for track in &title.tracks {
+                    println!("  Track {}: {}", track.track_number, Hms(pts_to_millis(track.length_pts)));
+                }
+            }
+        }
+    } else if args.json || args.format != ListFormat::Text {
+        print_list_entries(args, &list_entries(&mut parser, &structures)?)?;
+    } else {
+        walk_filesystem(&mut parser, &structures, &mut |_, path, _, _| {
+            println!("{}", path);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
 
-use clap::Parser;
-use dvdromcopy::cache::Cache;
-use dvdromcopy::dvdcss_sys::{css_to_io_error, DvdCss, DVDCSS_BLOCK_SIZE};
-use dvdromcopy::logical_block_reader::{read_exact_from_partition, short_ad_to_pos_in_partition};
-use dvdromcopy::udf::{
-    Dstring, FileIdentifierDescriptor, LogicalVolumeDescriptor, LongAd, PartitionDescriptor,
-    PartitionMap, ShortAllocationDescriptor, Type1PartitionMap,
-};
-use dvdromcopy::udf_parser::{
-    read_directory_contents, read_file_entries, read_short_allocation_descriptors, Result, UdfError, UdfParser
-};
-use log::{self, debug, error, warn};
-use std::fs::{create_dir, create_dir_all};
+/// Print entries as --json, --format csv or --format tree
+fn print_list_entries(args: &ListArgs, entries: &[ListEntry]) -> Result<()> {
+    if args.json {
+        println!("{}", listing::json(entries).map_err(std::io::Error::from)?);
+    } else if args.format == ListFormat::Csv {
+        println!("{}", CSV_HEADER);
+        for entry in entries {
+            println!("{}", entry.csv_row());
+        }
+    } else {
+        for line in listing::tree_lines(entries) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// The DVD device or file to open
-    #[arg(short, long)]
-    device: String,
+/// The files of the disc in the order of the walk, with the details that list prints
+fn list_entries(parser: &mut UdfParser<DvdCss>, structures: &VolumeStructures) -> Result<Vec<ListEntry>> {
+    let max_depth = parser.max_depth;
+    copier::list_entries(parser, structures, max_depth)
+}
 
-    /// The output directory to write the DVD to
-    #[arg(short, long)]
-    output: PathBuf,
+fn run_info(args: &InfoArgs) -> Result<()> {
+    let mut parser = open_parser(&args.disc)?;
+    let scrambled = parser.reader.is_scrambled();
+    let structures = parser.read_volume_structures()?;
+    let integrity = parser
+        .read_logical_volume_integrity(&structures.logical_volume.integrity_sequence_extent)
+        .unwrap_or_else(|e| {
+            warn!("Could not read the Logical Volume Integrity Descriptor: {}", e);
+            None
+        });
+    let dvd_id = read_dvd_id(&mut parser, &structures)?.map(|id| id.to_string());
+    let mut info = DiscInfo::new(&structures, integrity.as_ref(), scrambled, dvd_id);
+    info.css.title_sets = title_set_scrambling(&mut parser, &structures)?;
+    let dvd_video = read_dvd_video(&mut parser, &structures)?;
+    info.region.disc = dvd_video.video_manager.as_ref().map(|video_manager| video_manager.regions());
+    if detect_device(&args.disc.device)? == DeviceKind::Drive {
+        info.region.drive = read_drive_region(&args.disc.device)
+            .inspect_err(|e| warn!("Could not read the region of the drive: {}", e))
+            .ok();
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&info).map_err(std::io::Error::from)?);
+        return Ok(());
+    }
+    println!("Volume: {}", info.volume.identifier);
+    println!("  Volume set: {}", info.volume.set_identifier);
+    println!("  Logical volume: {}", info.volume.logical_volume_identifier);
+    println!("  Recorded: {}", info.volume.recording_timestamp.iso8601());
+    println!("  Application: {}", info.volume.application_id);
+    println!("  Block size: {}", info.volume.logical_block_size);
+    if let Some(udf_revision) = &info.udf_revision {
+        println!("  UDF revision: {}", udf_revision);
+    }
+    for partition_map in &info.partition_maps {
+        match partition_map {
+            PartitionMapInfo::Type1 {
+                volume_sequence_number,
+                partition_number,
+            } => println!(
+                "Partition map: type 1, partition {} on volume {}",
+                partition_number, volume_sequence_number
+            ),
+            PartitionMapInfo::Type2 {
+                partition_type_identifier,
+            } => println!("Partition map: type 2, {}", partition_type_identifier),
+            PartitionMapInfo::Other { map_type } => println!("Partition map: type {}", map_type),
+        }
+    }
+    for partition in &info.partitions {
+        println!(
+            "Partition {}: sectors {}-{}, {}, {}",
+            partition.number,
+            partition.starting_sector,
+            partition.starting_sector as u64 + partition.length_sectors as u64,
+            partition.access_type,
+            partition.contents
+        );
+    }
+    match &info.integrity {
+        Some(integrity) => println!(
+            "Integrity: {} ({}), {} files, {} directories, UDF {} to read",
+            integrity.state,
+            integrity.recorded,
+            integrity.number_of_files,
+            integrity.number_of_directories,
+            integrity.minimum_udf_read_revision
+        ),
+        None => println!("Integrity: no Logical Volume Integrity Descriptor"),
+    }
+    println!("CSS: {}", if info.css.scrambled { "scrambled" } else { "not scrambled" });
+    for title_set in &info.css.title_sets {
+        let scrambled = match title_set.scrambled {
+            Some(true) => "scrambled",
+            Some(false) => "not scrambled",
+            None => "could not be read",
+        };
+        println!("  Title set {}: {}", title_set.title_set, scrambled);
+    }
+    if let Some(regions) = &info.region.disc {
+        let regions: Vec<String> = regions.iter().map(|region| region.to_string()).collect();
+        println!("Disc regions: {}", if regions.is_empty() { "none".to_string() } else { regions.join(", ") });
+    }
+    if let Some(drive) = &info.region.drive {
+        println!(
+            "Drive region: {} ({}, {} user changes and {} vendor resets left{})",
+            drive.region.map_or("none".to_string(), |region| region.to_string()),
+            drive.state,
+            drive.user_changes_left,
+            drive.vendor_resets_left,
+            if drive.rpc_phase_2 { ", RPC-II" } else { "" }
+        );
+    }
+    if let Some(dvd_id) = &info.fingerprints.dvd_id {
+        println!("DVD ID: {}", dvd_id);
+    }
+    println!("Primary Volume Descriptor SHA-256: {}", info.fingerprints.primary_volume_descriptor_sha256);
+    Ok(())
+}
 
-    /// Name of the DVD; if not specified then it will read from DVD
-    /// primary_volume.volume_identifier
-    #[arg(long)]
-    name: Option<String>,
+/// Whether the first sectors of each title set's title VOBs are scrambled, read without
+/// decrypting them
+fn title_set_scrambling(parser: &mut UdfParser<DvdCss>, structures: &VolumeStructures) -> Result<Vec<TitleSetCss>> {
+    // enough to get past the navigation packs and unscrambled packs at the start of a VOB
+    const SAMPLE_SECTORS: u64 = 64;
+    let mut vobs = file_start_sectors_and_lengths(parser, structures, |path| {
+        parse_title_vob_path(path).is_some_and(|(_, part)| part == 1)
+    })?;
+    vobs.sort();
+    let mut title_sets = vec![];
+    for (path, sector, length) in vobs {
+        let Some((title_set, _)) = parse_title_vob_path(&path) else {
+            continue;
+        };
+        let sectors = length.div_ceil(DVDCSS_BLOCK_SIZE as u64).min(SAMPLE_SECTORS) as i32;
+        let mut buf = vec![0u8; sectors as usize * DVDCSS_BLOCK_SIZE];
+        let css = &mut parser.reader;
+        let read = (css.css_seek(sector as i32, DVDCSS_NOFLAGS))
+            .and_then(|_| css.css_read(&mut buf, sectors, DVDCSS_NOFLAGS));
+        let scrambled = match read {
+            Ok(read) => Some(buf[..read as usize * DVDCSS_BLOCK_SIZE].chunks(DVDCSS_BLOCK_SIZE).any(is_scrambled_pack)),
+            Err(e) => {
+                warn!("Could not read {}: {}", path, e);
+                None
+            }
+        };
+        title_sets.push(TitleSetCss { title_set, scrambled });
+    }
+    Ok(title_sets)
+}
 
-    /// Include only the specified files and directories
-    #[arg(long)]
-    include: Option<Vec<String>>,
+/// Read the file entries of the file at icb_address
+fn read_icb<'s, B: BlockDevice>(
+    reader: &mut B,
+    structures: &'s VolumeStructures,
+    icb_address: &LongAd,
+) -> Result<(&'s PartitionDescriptor, Vec<FileEntry>)> {
+    read_icb_entries(reader, &structures.logical_volume, &structures.partition_descriptors, icb_address)
 }
 
+/// The sector where the data of the file at path starts
+fn find_file_start_sector(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    path: &str,
+) -> Result<Option<u32>> {
+    let start_sectors = file_start_sectors(parser, structures, |file_path| file_path == path)?;
+    Ok(start_sectors.first().map(|(_, sector)| *sector))
+}
 
-fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
-    run(&args)?;
-    println!("Hello, world!");
+/// The paths and start sectors of the non-empty files whose path matches
+fn file_start_sectors(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    matches: impl Fn(&str) -> bool,
+) -> Result<Vec<(String, u32)>> {
+    Ok(file_start_sectors_and_lengths(parser, structures, matches)?
+        .into_iter()
+        .map(|(path, sector, _)| (path, sector))
+        .collect())
+}
+
+/// The paths, start sectors and lengths in bytes of the non-empty files whose path matches
+fn file_start_sectors_and_lengths(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    matches: impl Fn(&str) -> bool,
+) -> Result<Vec<(String, u32, u64)>> {
+    let mut start_sectors = vec![];
+    walk_filesystem(parser, structures, &mut |reader, file_path, icb_address, _| {
+        if !matches(file_path) {
+            return Ok(());
+        }
+        let (_, file_entries) = read_icb(reader, structures, icb_address)?;
+        let Some(file_entry) = file_entries.first() else {
+            return Ok(());
+        };
+        let partition_reference_number = icb_address.extent_location.partition_reference_number;
+        if let Some(ad) = read_allocation_descriptors(file_entry, partition_reference_number)?.first() {
+            let (partition_descriptor, pos) =
+                long_ad_partition_pos(&structures.logical_volume, &structures.partition_descriptors, ad)?;
+            let sector = partition_descriptor.partition_starting_location + (pos / DVDCSS_BLOCK_SIZE) as u32;
+            start_sectors.push((file_path.to_string(), sector, file_entry.information_length));
+        }
+        Ok(())
+    })?;
+    Ok(start_sectors)
+}
+
+/// Check that the title VOBs of a title set follow each other on the disc,
+/// since the cell sectors of its IFO count from the start of the first one
+fn check_title_vobs_contiguous(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    title_set: u8,
+) -> Result<()> {
+    let mut vobs = file_start_sectors_and_lengths(parser, structures, |path| {
+        parse_title_vob_path(path).is_some_and(|(number, _)| number == title_set)
+    })?;
+    vobs.sort_by(|a, b| a.0.cmp(&b.0));
+    for pair in vobs.windows(2) {
+        let (previous_path, previous_sector, previous_length) = &pair[0];
+        let (path, sector, _) = &pair[1];
+        let expected = *previous_sector as u64 + previous_length.div_ceil(DVDCSS_BLOCK_SIZE as u64);
+        if *sector as u64 != expected {
+            error!(
+                "{} starts at sector {} instead of right after {} at sector {}",
+                path, sector, previous_path, expected
+            );
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the title VOBs of title set {} are not contiguous", title_set),
+            )
+            .into());
+        }
+    }
     Ok(())
 }
 
-fn titlecase_name(name: &str) -> String {
-    let mut result = String::new();
-    let mut capitalize = true;
-    for c in name.chars() {
-        if c == ' ' || c == '_' {
-            capitalize = true;
-            result.push(' ');
-        } else if capitalize {
-            result.push_str(&c.to_uppercase().to_string());
-            capitalize = false;
-        } else {
-            result.push_str(&c.to_lowercase().to_string());
+/// Ask libdvdcss for the title key of every VOB, then collect the keys it stored in key_cache_dir
+fn dump_title_keys(
+    parser: &mut UdfParser<DvdCss>,
+    structures: &VolumeStructures,
+    key_cache_dir: &Path,
+) -> Result<Vec<TitleKey>> {
+    let vobs = file_start_sectors(parser, structures, |path| {
+        path.starts_with("VIDEO_TS/") && path.ends_with(".VOB")
+    })?;
+    for (path, sector) in &vobs {
+        if let Err(e) = parser.reader.css_seek(*sector as i32, DVDCSS_SEEK_KEY) {
+            warn!("Could not get the title key of {}: {}", path, e);
         }
     }
-    result.trim().to_string()
+    let cached_keys = read_key_cache(key_cache_dir)?;
+    Ok(vobs
+        .into_iter()
+        .filter_map(|(path, sector)| {
+            let key = cached_keys.get(&sector)?.clone();
+            Some(TitleKey { path, sector, key })
+        })
+        .collect())
 }
 
-struct RunOnDirectoryOptions<'a> {
-    dvd_dir: &'a Path,
-    
+/// Extract one DVD-Video title as a single VOB stream by reading its cells in playback order
+fn run_title(args: &TitleArgs) -> Result<()> {
+    let mut parser = open_parser(&args.disc)?;
+    let structures = parser.read_volume_structures()?;
+    let dvd_video = read_dvd_video(&mut parser, &structures)?;
+    let title_number = match args.title {
+        Some(title_number) => title_number,
+        None => {
+            let main_title = dvd_video.main_title().ok_or_else(|| {
+                error!("The disc has no DVD-Video titles");
+                std::io::Error::from(std::io::ErrorKind::NotFound)
+            })?;
+            println!(
+                "Main title is title {} ({}, {} chapters)",
+                main_title.title_number,
+                Hms(main_title.duration_millis),
+                main_title.chapters
+            );
+            main_title.title_number
+        }
+    };
+    let title = dvd_video.title(title_number).ok_or_else(|| {
+        error!("Title {} not found; the disc has {} titles", title_number, dvd_video.titles().len());
+        std::io::Error::from(std::io::ErrorKind::NotFound)
+    })?;
+    let vob_path = title_vob_path(title.title_set);
+    let vob_start_sector = find_file_start_sector(&mut parser, &structures, &vob_path)?.ok_or_else(|| {
+        error!("Title {} is in title set {} but {} was not found", title_number, title.title_set, vob_path);
+        std::io::Error::from(std::io::ErrorKind::NotFound)
+    })?;
+    check_title_vobs_contiguous(&mut parser, &structures, title.title_set)?;
+    let cells = match &args.chapters {
+        Some(chapters) => dvd_video
+            .chapter_cells(title, chapters.clone())
+            .inspect_err(|e| error!("Title {}: {}", title_number, e))?,
+        None => dvd_video.title_cells(title),
+    };
+    if args.angle == 0 || args.angle > title.number_of_angles.max(1) {
+        error!("Title {} has {} angles", title_number, title.number_of_angles);
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput).into());
+    }
+    let cells = select_angle(&cells, args.angle);
+    if let Some(command) = &args.transcode {
+        return transcode_title(&mut parser.reader, command, args, title_number, vob_start_sector, &cells);
+    }
+    let output = std::fs::File::create_new(&args.output).map_err(|err| {
+        error!("Could not open output file {:?}: {}", args.output, err);
+        err
+    })?;
+    let sectors = match args.format {
+        TitleFormat::Vob => {
+            let mut output = output;
+            let sectors = copy_cells(&mut parser.reader, &mut output, vob_start_sector, &cells)?;
+            output.sync_all()?;
+            sectors
+        }
+        TitleFormat::Mkv => {
+            let palette = dvd_video
+                .title_program_chains(title)
+                .first()
+                .map(|(_, pgc)| pgc.palette);
+            let mut remuxer = VobRemuxer::new(output, palette)?;
+            let sectors = copy_cells(&mut parser.reader, &mut remuxer, vob_start_sector, &cells)?;
+            let chapters = args.chapters.clone().unwrap_or(1..=title.number_of_chapters);
+            let chapters: Vec<Chapter> = dvd_video
+                .chapter_start_times(title, chapters.clone())
+                .unwrap_or_else(|e| {
+                    warn!("Could not compute chapter times: {}", e);
+                    vec![]
+                })
+                .into_iter()
+                .zip(chapters)
+                .map(|(start_ms, chapter)| Chapter {
+                    start_ms,
+                    title: format!("Chapter {}", chapter),
+                })
+                .collect();
+            let name = titlecase_name(&structures.primary_volume.volume_identifier.to_string());
+            let output = remuxer.finish(Some(&name), &chapters)?;
+            output.sync_all()?;
+            sectors
+        }
+    };
+    println!(
+        "Wrote title {} ({} cells, {} sectors) to {:?}",
+        title_number,
+        cells.len(),
+        sectors,
+        args.output
+    );
+    Ok(())
 }
 
-fn run_on_directory<R: Read + Seek>(
-    reader: &mut Cache<&mut R, DVDCSS_BLOCK_SIZE>,
-    logical_volume_descriptor: &LogicalVolumeDescriptor,
-    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
-    icb_address: &LongAd,
-    dvd_dir: &Path,
-    path: &mut Vec<String>,
+/// Pipe the cells into the --transcode command, reporting how much of the title has been read
+fn transcode_title(
+    css: &mut DvdCss,
+    command: &str,
+    args: &TitleArgs,
+    title_number: u16,
+    vob_start_sector: u32,
+    cells: &[CellPlayback],
 ) -> Result<()> {
-    let partition_descriptor = partition_descriptors
-        .get(&(icb_address.extent_location.partition_reference_number | 0))
-        .ok_or_else(|| {
-            error!(
-                "Could not find partition descriptor for directory ICB: {}",
-                &(icb_address.extent_location.partition_reference_number | 0)
-            );
-            UdfError::InvalidPartitionNumber
-        })?;
-    let file_entries = read_file_entries(
-        reader,
-        logical_volume_descriptor,
-        partition_descriptor,
-        &ShortAllocationDescriptor {
-            extent_length_and_type: icb_address.extent_length_and_type,
-            extent_location: icb_address.extent_location.logical_block_number,
-        },
-    )?;
-    let file_identifier_descriptors = read_directory_contents(
-        reader,
-        logical_volume_descriptor,
-        partition_descriptor,
-        &*file_entries,
-    )?;
-    for file_identifier_descriptor in file_identifier_descriptors.iter() {
-        let path_string =
-            path.join("/") + "/" + &file_identifier_descriptor.file_identifier.to_string();
-        if file_identifier_descriptor.file_characteristics
-            & FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT
-            != 0
-        {
-            // don't infinite loop up to parent directory
+    // interleaved cells also count the other angles' sectors, so this can be an overestimate
+    let expected_bytes: u64 = (cells.iter())
+        .filter(|cell| cell.last_sector >= cell.first_sector)
+        .map(|cell| (cell.last_sector - cell.first_sector + 1) as u64 * DVDCSS_BLOCK_SIZE as u64)
+        .sum();
+    let transcoder = Transcoder::spawn(command, &args.output).inspect_err(|e| error!("Could not run {:?}: {}", command, e))?;
+    let mut output = ProgressWriter::new(transcoder, &format!("Title {}", title_number), expected_bytes);
+    let sectors = copy_cells(css, &mut output, vob_start_sector, cells)?;
+    let status = output.into_inner().finish()?;
+    if !status.success() {
+        error!("The --transcode command failed: {}", status);
+        return Err(std::io::Error::other(format!("--transcode command: {}", status)).into());
+    }
+    println!(
+        "Transcoded title {} ({} cells, {} sectors) to {:?}",
+        title_number,
+        cells.len(),
+        sectors,
+        args.output
+    );
+    Ok(())
+}
+
+/// Copy the sectors of cells, which are relative to vob_start_sector; returns the number of sectors
+fn copy_cells<W: Write>(css: &mut DvdCss, output: &mut W, vob_start_sector: u32, cells: &[CellPlayback]) -> Result<u64> {
+    let mut sectors = 0;
+    for cell in cells {
+        if cell.last_sector < cell.first_sector {
+            warn!("Skipping cell with invalid sectors {:?}", cell);
             continue;
         }
-        if file_identifier_descriptor.file_characteristics
-            & FileIdentifierDescriptor::FILE_CHARACTERISTIC_DIRECTORY
-            != 0
-        {
-            path.push(file_identifier_descriptor.file_identifier.to_string());
-            debug!(
-                "run_on_directory: descending into subdirectory {:?}",
-                path_string
-            );
-            let result = run_on_directory(
-                reader,
-                logical_volume_descriptor,
-                partition_descriptors,
-                &file_identifier_descriptor.icb,
-                dvd_dir,
-                path,
-            );
-            path.pop();
-            result?;
+        if cell.is_interleaved() {
+            sectors += copy_interleaved_cell(css, output, vob_start_sector, cell)?;
+            continue;
+        }
+        let count = cell.last_sector - cell.first_sector + 1;
+        copy_title_sectors(css, output, vob_start_sector + cell.first_sector, count)?;
+        sectors += count as u64;
+    }
+    Ok(sectors)
+}
+
+/// Copy only this angle's interleaved units (ILVUs) of a cell by following
+/// the next-ILVU pointers in the DSI of each navigation pack
+fn copy_interleaved_cell<W: Write>(
+    css: &mut DvdCss,
+    output: &mut W,
+    vob_start_sector: u32,
+    cell: &CellPlayback,
+) -> Result<u64> {
+    let mut sectors = 0;
+    let mut sector = cell.first_sector;
+    let mut nav_pack = vec![0u8; DVDCSS_BLOCK_SIZE];
+    while sector <= cell.last_sector {
+        css.css_seek((vob_start_sector + sector) as i32, DVDCSS_SEEK_KEY)
+            .map_err(css_to_io_error)?;
+        css.read_exact(&mut nav_pack)?;
+        let Some(dsi) = navigation_pack_dsi(&nav_pack) else {
+            warn!("Expected a navigation pack at sector {} of the title VOBs; copying the rest of the cell", sector);
+            let count = cell.last_sector - sector + 1;
+            copy_title_sectors(css, output, vob_start_sector + sector, count)?;
+            return Ok(sectors + count as u64);
+        };
+        let (unit_end, next) = if dsi.is_ilvu_start() {
+            let next = match dsi.next_ilvu_start {
+                0 | DataSearchInfo::NO_NEXT_ILVU => None,
+                next_ilvu_start => Some(sector + next_ilvu_start),
+            };
+            (sector + dsi.ilvu_end, next)
         } else {
-            path.push(file_identifier_descriptor.file_identifier.to_string());
-            debug!("run_on_directory: file {:?}", path_string);
-            path.pop();
-            // read file
-            read_file(
+            (sector + dsi.vobu_end, Some(sector + dsi.vobu_end + 1))
+        };
+        let unit_end = unit_end.min(cell.last_sector);
+        debug!("copy_interleaved_cell: unit {}..={}, next {:?}", sector, unit_end, next);
+        output.write_all(&nav_pack)?;
+        copy_title_sectors(css, output, vob_start_sector + sector + 1, unit_end - sector)?;
+        sectors += (unit_end - sector + 1) as u64;
+        match next {
+            Some(next) if next > sector => sector = next,
+            _ => break,
+        }
+    }
+    Ok(sectors)
+}
+
+/// Copy count sectors starting at start, decrypting them with the title key
+/// that libdvdcss finds when seeking with DVDCSS_SEEK_KEY
+fn copy_title_sectors<W: Write>(css: &mut DvdCss, output: &mut W, start: u32, count: u32) -> Result<()> {
+    const SECTORS_PER_READ: u32 = 512;
+    debug!("copy_title_sectors: sectors {}..{}", start, start + count);
+    css.css_seek(start as i32, DVDCSS_SEEK_KEY).map_err(css_to_io_error)?;
+    let mut buf = vec![0u8; SECTORS_PER_READ as usize * DVDCSS_BLOCK_SIZE];
+    let mut remaining = count;
+    while remaining > 0 {
+        let sectors_this_iteration = remaining.min(SECTORS_PER_READ);
+        let slice = &mut buf[..sectors_this_iteration as usize * DVDCSS_BLOCK_SIZE];
+        css.read_exact(slice)?;
+        output.write_all(slice)?;
+        remaining -= sectors_this_iteration;
+    }
+    Ok(())
+}
+
+/// Read and parse VIDEO_TS.IFO and the VTS_nn_0.IFO files.
+/// IFOs that cannot be parsed are skipped with a warning.
+fn read_dvd_video(parser: &mut UdfParser<DvdCss>, structures: &VolumeStructures) -> Result<DvdVideo> {
+    let mut dvd_video = DvdVideo::default();
+    walk_filesystem(parser, structures, &mut |reader, path, icb_address, _| {
+        let title_set = parse_title_set_ifo_path(path);
+        if path != VIDEO_MANAGER_IFO_PATH && title_set.is_none() {
+            return Ok(());
+        }
+        let (_, file_entries) = read_icb(reader, structures, icb_address)?;
+        let ifo = read_file_contents(
+            reader,
+            &structures.logical_volume,
+            &structures.partition_descriptors,
+            icb_address.extent_location.partition_reference_number,
+            &file_entries,
+        )?;
+        let result = match title_set {
+            Some(title_set) => TitleSetInfo::parse(&ifo).map(|info| {
+                dvd_video.title_sets.insert(title_set, info);
+            }),
+            None => VideoManagerInfo::parse(&ifo).map(|info| {
+                dvd_video.video_manager = Some(info);
+            }),
+        };
+        if let Err(e) = result {
+            warn!("Could not parse {}: {}", path, e);
+        }
+        Ok(())
+    })?;
+    Ok(dvd_video)
+}
+
+/// Compute the Windows DVD ID from the VIDEO_TS directory, or None if there is no VIDEO_TS.IFO
+fn read_dvd_id(parser: &mut UdfParser<DvdCss>, structures: &VolumeStructures) -> Result<Option<DvdId>> {
+    const FIRST_TITLE_SET_IFO: &str = "VIDEO_TS/VTS_01_0.IFO";
+    let mut files = vec![];
+    let mut video_manager_ifo = None;
+    let mut first_title_set_ifo = None;
+    walk_filesystem(parser, structures, &mut |reader, path, icb_address, _| {
+        let Some(name) = path.strip_prefix("VIDEO_TS/").filter(|name| !name.contains('/')) else {
+            return Ok(());
+        };
+        let (_, file_entries) = read_icb(reader, structures, icb_address)?;
+        let Some(file_entry) = file_entries.first() else {
+            return Ok(());
+        };
+        files.push(DvdIdFile {
+            name: name.to_string(),
+            creation_time: filetime(&file_entry.modification_time),
+            size: file_entry.information_length as u32,
+        });
+        if path == VIDEO_MANAGER_IFO_PATH || path == FIRST_TITLE_SET_IFO {
+            let ifo = read_file_contents(
                 reader,
-                logical_volume_descriptor,
-                partition_descriptors,
-                dvd_dir,
-                path_string,
-                &file_identifier_descriptor.icb,
+                &structures.logical_volume,
+                &structures.partition_descriptors,
+                icb_address.extent_location.partition_reference_number,
+                &file_entries,
             )?;
+            if path == VIDEO_MANAGER_IFO_PATH {
+                video_manager_ifo = Some(ifo);
+            } else {
+                first_title_set_ifo = Some(ifo);
+            }
+        }
+        Ok(())
+    })?;
+    Ok(video_manager_ifo.map(|ifo| dvd_id(&files, &ifo, first_title_set_ifo.as_deref())))
+}
+
+/// Parse AUDIO_TS.IFO and the audio title set IFOs if the disc has DVD-Audio content
+fn read_dvd_audio(parser: &mut UdfParser<DvdCss>, structures: &VolumeStructures) -> Result<DvdAudio> {
+    let mut dvd_audio = DvdAudio::default();
+    walk_filesystem(parser, structures, &mut |reader, path, icb_address, _| {
+        let title_set = parse_audio_title_set_ifo_path(path);
+        if path != AUDIO_MANAGER_IFO_PATH && title_set.is_none() {
+            return Ok(());
+        }
+        let (_, file_entries) = read_icb(reader, structures, icb_address)?;
+        let ifo = read_file_contents(
+            reader,
+            &structures.logical_volume,
+            &structures.partition_descriptors,
+            icb_address.extent_location.partition_reference_number,
+            &file_entries,
+        )?;
+        let result = match title_set {
+            Some(title_set) => AudioTitleSetInfo::parse(&ifo).map(|info| {
+                dvd_audio.title_sets.insert(title_set, info);
+            }),
+            None => AudioManagerInfo::parse(&ifo).map(|info| {
+                dvd_audio.audio_manager = Some(info);
+            }),
+        };
+        if let Err(e) = result {
+            warn!("Could not parse {}: {}", path, e);
+        }
+        Ok(())
+    })?;
+    Ok(dvd_audio)
+}
+
+/// Whether the '/'-separated disc path matches one of the glob patterns or is inside a
+/// directory that does
+fn path_is_included(path: &str, patterns: &[String]) -> bool {
+    patterns.iter().any(|pattern| matches_path_or_parent(pattern, path))
+}
+
+/// Write an image with the disc's layout in which the data of files that are not included
+/// is left as holes. All other sectors, including every UDF descriptor, directory and
+/// the included files, are copied so that the image still mounts and plays.
+fn run_sparse_iso(args: &SparseIsoArgs) -> Result<()> {
+    let (offset, length) = locate_file_system(&args.disc)?;
+    let size = match length {
+        Some(length) => length,
+        None => device_size(&args.disc.device)?.saturating_sub(offset),
+    };
+    let total_sectors = (size / DVDCSS_BLOCK_SIZE as u64) as u32;
+    let css = open_device_at(&args.disc.device, offset)?;
+    let mut parser = device_parser(&args.disc.device, css)
+        .with_total_sectors(total_sectors)
+        .with_cache_blocks(args.disc.cache_blocks())
+        .with_max_depth(args.disc.max_depth);
+    let structures = parser.read_volume_structures()?;
+    let virtual_partitions = parser.virtual_partitions.clone();
+    let mut excluded = SectorRanges::new();
+    // the sectors of the included files, which an excluded file can share with them
+    let mut included = SectorRanges::new();
+    let mut included_files = 0;
+    let mut excluded_files = 0;
+    walk_filesystem(&mut parser, &structures, &mut |reader, path, icb_address, _| {
+        let is_included = path_is_included(path, &args.include);
+        let sectors = if is_included {
+            included_files += 1;
+            &mut included
+        } else {
+            excluded_files += 1;
+            &mut excluded
+        };
+        let (_, file_entries) = read_icb(reader, &structures, icb_address)?;
+        for file_entry in file_entries.iter() {
+            let partition_reference_number = icb_address.extent_location.partition_reference_number;
+            for ad in read_allocation_descriptors(file_entry, partition_reference_number)? {
+                if ad.extent_type() != ExtentType::RecordedAllocated {
+                    // unrecorded extents hold no data, and the next extent of
+                    // allocation descriptors is metadata that must be kept
+                    continue;
+                }
+                let (partition_descriptor, pos_in_partition) =
+                    long_ad_partition_pos(&structures.logical_volume, &structures.partition_descriptors, &ad)?;
+                let pos = partition_pos_to_image_pos(partition_descriptor, pos_in_partition);
+                let start = pos / DVDCSS_BLOCK_SIZE;
+                let end = (pos + ad.extent_length_bytes() as usize).div_ceil(DVDCSS_BLOCK_SIZE);
+                // the blocks of a virtual partition are recorded wherever its VAT maps them
+                for run in translate_virtual_sectors(&virtual_partitions, start as u32..end as u32)? {
+                    if !is_included {
+                        debug!("run_sparse_iso: skipping {:?} sectors {:?}", path, run);
+                    }
+                    sectors.insert(run.start, run.end);
+                }
+            }
         }
-        // debug!(
-        //     "Found file identifier descriptor: {:?} {}",
-        //     file_identifier_descriptor, path_string
-        // );
+        Ok(())
+    })?;
+    // two File Identifiers can lead to one File Entry, and protected discs cross-link the
+    // extents of files, so that an excluded file's sectors can be an included one's too
+    for range in included.ranges() {
+        excluded.remove(range.start, range.end);
     }
+    let populated = excluded.complement(total_sectors);
+    let mut output = std::fs::File::create_new(&args.output).map_err(|err| {
+        error!("Could not open output file {:?}: {}", args.output, err);
+        err
+    })?;
+    // extending the file without writing leaves holes on filesystems that support sparse files
+    output.set_len(total_sectors as u64 * DVDCSS_BLOCK_SIZE as u64)?;
+    write_sparse_image(&mut parser.reader, &mut output, total_sectors, &populated)?;
+    output.sync_all()?;
+    println!(
+        "Wrote {} of {} sectors ({} files included, data of {} files skipped)",
+        populated.sector_count(),
+        total_sectors,
+        included_files,
+        excluded_files
+    );
+    print_descriptor_stats(&parser.stats());
+    Ok(())
+}
+
+/// The name of the source directory in upper case, as makeiso names the volume by default
+fn default_volume_identifier(source: &Path) -> Result<String> {
+    Ok((source.canonicalize()?.file_name()).map_or("DVD".to_string(), |name| name.to_string_lossy().to_uppercase()))
+}
 
+fn run_make_iso(args: &MakeIsoArgs) -> Result<()> {
+    let volume_identifier = match &args.volume_id {
+        Some(volume_id) => volume_id.clone(),
+        None => default_volume_identifier(&args.source)?,
+    };
+    let options = ImageOptions {
+        volume_identifier,
+        revision: args.udf_revision,
+        recording_time: std::time::SystemTime::now(),
+        iso9660: args.iso9660,
+    };
+    let output = std::fs::File::create_new(&args.output).map_err(|err| {
+        error!("Could not open output file {:?}: {}", args.output, err);
+        err
+    })?;
+    let mut writer = std::io::BufWriter::new(output);
+    let summary = write_image(&args.source, &options, &mut writer)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    println!(
+        "Wrote {} sectors ({} files, {} directories) to {:?}",
+        summary.sectors, summary.files, summary.directories, args.output
+    );
     Ok(())
 }
 
-fn read_file<R: Read + Seek>(
-    reader: &mut Cache<&mut R, 2048>,
-    logical_volume_descriptor: &LogicalVolumeDescriptor,
-    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
-    dvd_dir: &Path,
-    path: String,
-    icb_address: &LongAd,
-) -> Result<()> {
-    let output_path = dvd_dir.join(&path);
-    if let Some(parent) = output_path.parent() {
-        create_dir_all(parent)?;
-    }
-    let partition_descriptor = partition_descriptors
-        .get(&(icb_address.extent_location.partition_reference_number | 0))
-        .ok_or_else(|| {
-            error!(
-                "Could not find partition descriptor for directory ICB: {}",
-                &(icb_address.extent_location.partition_reference_number | 0)
-            );
-            UdfError::InvalidPartitionNumber
-        })?;
-    let file_entries = read_file_entries(reader, logical_volume_descriptor, partition_descriptor,         &ShortAllocationDescriptor {
-        extent_length_and_type: icb_address.extent_length_and_type,
-        extent_location: icb_address.extent_location.logical_block_number,
+fn run_repair_image(args: &RepairImageArgs) -> Result<()> {
+    let mut output = std::fs::File::create_new(&args.output).map_err(|err| {
+        error!("Could not open output file {:?}: {}", args.output, err);
+        err
     })?;
-    if file_entries.len() != 1 {
-        warn!(
-            "Expected exactly one file entry for file {:?}, but got {}",
-            path,
-            file_entries.len()
+    std::io::copy(&mut std::fs::File::open(&args.image)?, &mut output)?;
+    if args.rebuild {
+        let summary = recover_image(&mut output)?;
+        println!(
+            "Found {} volume descriptors in {} sectors; rebuilt sequences of {} descriptors and the anchors at {:?}",
+            summary.descriptors_found, summary.sectors_scanned, summary.sequence_length, summary.anchors
         );
     }
-    // let mut output_file = std::fs::File::open(&output_path).map_err(|err| {
-    //     error!("Could not open output file {:?}: {}", output_path, err);
-    //     err
-    // })?;
-    let mut output_file = std::fs::File::create_new(&output_path).map_err(|err| {
-        error!("Could not open output file {:?}: {}", output_path, err);
+    let summary = repair_image(&mut output)?;
+    output.sync_all()?;
+    println!(
+        "Checked {} descriptors and repaired the tags of {}; wrote {:?}",
+        summary.descriptors, summary.repaired, args.output
+    );
+    Ok(())
+}
+
+fn run_patch_image(args: &PatchImageArgs) -> Result<()> {
+    let path = args.path.trim_start_matches('/');
+    let image = args.image.to_string_lossy();
+    let css = open_device(&image)?;
+    let mut parser = new_parser(css, &image, 0, None);
+    let structures = parser.read_volume_structures()?;
+    check_patchable(&parser, &structures).inspect_err(|e| error!("Could not patch {:?}: {}", args.image, e))?;
+    let mut file_entry_location = None;
+    walk_filesystem(&mut parser, &structures, &mut |_, file_path, icb_address, _| {
+        if file_path == path {
+            let partition_descriptor = structures
+                .partition_descriptors
+                .get(&{ icb_address.extent_location.partition_reference_number })
+                .ok_or(UdfError::InvalidPartitionNumber)?;
+            file_entry_location = Some((
+                partition_descriptor.partition_starting_location,
+                icb_address.extent_location.logical_block_number,
+            ));
+        }
+        Ok(())
+    })?;
+    drop(parser);
+    let (partition_start, icb_block) = file_entry_location.ok_or_else(|| {
+        error!("{} was not found in {:?}", path, args.image);
+        std::io::Error::from(std::io::ErrorKind::NotFound)
+    })?;
+
+    let mut contents = std::fs::File::open(&args.file).map_err(|err| {
+        error!("Could not open {:?}: {}", args.file, err);
         err
     })?;
-    let mut partition_count_match: u32 = 0;
-    let mut partition_count_fix_zero: u32 = 0;
-    let mut partition_count_mismatch: u32 = 0;
-    for file_entry in file_entries.iter() {
-        let allocation_descriptors =
-        read_short_allocation_descriptors(&*file_entry.allocation_descriptors);
-        for ad in &allocation_descriptors {
-            debug!("path {}: reading part {:?}", path, ad);
-            let pos_in_partition = short_ad_to_pos_in_partition(logical_volume_descriptor, ad);
-            let mut buf: Vec<u8> = vec![0u8; 1024*1024];
-            let mut output_buf: Vec<u8> = vec![0u8; 1024*1024];
-            let mut offset: usize = 0;
-            while offset < ad.extent_length_bytes() as usize {
-                let pos_this_iteration = pos_in_partition + offset as usize;
-                let len_this_iteration = (ad.extent_length_bytes() as usize - offset).min(buf.len());
-                let slice = &mut buf[..len_this_iteration];
-                read_exact_from_partition(
-                    reader,
-                    partition_descriptor,
-                    pos_this_iteration,
-                    slice,
-                )?;
+    let length = contents.metadata()?.len();
+    let mut image = std::fs::OpenOptions::new().read(true).write(true).open(&args.image)?;
+    replace_file_contents(&mut image, partition_start, icb_block, &mut contents, length, std::time::SystemTime::now())
+        .inspect_err(|e| error!("Could not replace {}: {}", path, e))?;
+    image.sync_all()?;
+    println!("Replaced {} in {:?} with the {} bytes of {:?}", path, args.image, length, args.file);
+    Ok(())
+}
+
+fn run_relabel(args: &RelabelArgs) -> Result<()> {
+    let mut image = std::fs::OpenOptions::new().read(true).write(true).open(&args.image).map_err(|err| {
+        error!("Could not open {:?}: {}", args.image, err);
+        err
+    })?;
+    let summary = relabel_image(&mut image, &args.volume_id)
+        .inspect_err(|e| error!("Could not relabel {:?}: {}", args.image, e))?;
+    image.sync_all()?;
+    println!(
+        "Relabeled {:?} as {:?} ({} UDF and {} ISO 9660 volume descriptors rewritten)",
+        args.image, args.volume_id, summary.descriptors, summary.iso9660_descriptors
+    );
+    Ok(())
+}
 
-                output_file.write_all(slice)?;
-                // let output_slice = &mut output_buf[..len_this_iteration];
-                // output_file.read_exact(output_slice)?;
-
-                // for i in 0..((slice.len() as u32).div_ceil(logical_volume_descriptor.logical_block_size)) { 
-                //     let logical_block_in_slice_start = i as usize * logical_volume_descriptor.logical_block_size as usize;
-                //     let logical_block_in_slice_end = ((i + 1) as usize * logical_volume_descriptor.logical_block_size as usize).min(slice.len());
-                //     if slice[logical_block_in_slice_start..logical_block_in_slice_end] == output_slice[logical_block_in_slice_start..logical_block_in_slice_end] {
-                //         partition_count_match += 1;
-                //     } else if output_slice[logical_block_in_slice_start..logical_block_in_slice_end].iter().all(|&x| x == 0) {
-                //         partition_count_fix_zero += 1;
-                //     } else {
-                //         partition_count_mismatch += 1;
-                //     }
-                // }
-                offset += len_this_iteration;
-            }
-        }
-    }
-    output_file.sync_all()?;
-    debug!(
-        "read_file: {:?}: partitions match: {}, fix_zero: {}, mismatch: {}",
-        output_path, partition_count_match, partition_count_fix_zero, partition_count_mismatch
+fn run_append_image(args: &AppendImageArgs) -> Result<()> {
+    let recording_time = std::time::SystemTime::now();
+    let mut open_options = std::fs::OpenOptions::new();
+    open_options.read(true).write(true).create_new(args.create);
+    let mut image = open_options.open(&args.image).map_err(|err| {
+        error!("Could not open {:?}: {}", args.image, err);
+        err
+    })?;
+    let summary = if args.create {
+        let volume_identifier = match &args.volume_id {
+            Some(volume_id) => volume_id.clone(),
+            None => default_volume_identifier(&args.source)?,
+        };
+        let options = ImageOptions {
+            volume_identifier,
+            revision: UdfRevision::Udf201,
+            recording_time,
+            iso9660: false,
+        };
+        create_vat_image(&mut image, &args.source, &options, args.capacity)
+    } else {
+        append_to_vat_image(&mut image, &args.source, recording_time)
+    }
+    .inspect_err(|e| error!("Could not add {:?} to {:?}: {}", args.source, args.image, e))?;
+    image.sync_all()?;
+    println!(
+        "Added {} files and {} directories and replaced {} files; {:?} is now {} sectors",
+        summary.files_added, summary.directories_added, summary.files_replaced, args.image, summary.sectors
     );
+    Ok(())
+}
 
+/// Write a UDF image of the disc's files except the excluded ones, laid out as makeiso does
+/// so that the image is no larger than they need
+fn run_remaster(args: &RemasterArgs) -> Result<()> {
+    let mut parser = open_parser(&args.disc)?;
+    let structures = parser.read_volume_structures()?;
+    let tree = read_disc_tree(&mut parser, &structures)?;
+    let (excluded, kept): (Vec<_>, Vec<_>) =
+        tree.files.iter().partition(|(path, _)| path_is_included(path, &args.exclude));
+    let files: Vec<SourceFile> = kept
+        .iter()
+        .map(|(path, file)| SourceFile { path, length: file.size, modified: file.modified })
+        .collect();
+    let options = ImageOptions {
+        volume_identifier: (args.volume_id.clone())
+            .unwrap_or_else(|| structures.primary_volume.volume_identifier.to_string()),
+        revision: args.udf_revision,
+        recording_time: std::time::SystemTime::now(),
+        iso9660: args.iso9660,
+    };
+    let output = std::fs::File::create_new(&args.output).map_err(|err| {
+        error!("Could not open output file {:?}: {}", args.output, err);
+        err
+    })?;
+    let mut writer = std::io::BufWriter::new(output);
+    let (logical_volume, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+    let summary = write_image_of_files(&files, &options, &mut writer, |path, writer| {
+        let file = &tree.files[path];
+        let mut udf_file = UdfFile::open(parser.cache(), logical_volume, partitions, &file.icb, path)
+            .map_err(std::io::Error::other)?;
+        std::io::copy(&mut udf_file, writer)
+    })?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    println!(
+        "Wrote {} sectors ({} files, {} directories; {} files left out) to {:?}",
+        summary.sectors,
+        summary.files,
+        summary.directories,
+        excluded.len(),
+        args.output
+    );
     Ok(())
 }
 
-fn run(args: &Args) -> Result<()> {
-    println!("run");
-    let css = DvdCss::open(&args.device).map_err(css_to_io_error)?;
-    let mut parser = UdfParser::new(css);
+fn run_mount(args: &MountArgs) -> Result<()> {
+    let mut parser = open_parser(&args.disc)?;
     let structures = parser.read_volume_structures()?;
-    debug!("volume structures {:?}", structures);
-    let name_from_dvd = titlecase_name(&structures.primary_volume.volume_identifier.to_string());
-    debug!("name from dvd: {}", name_from_dvd);
-    let name = args.name.as_ref().unwrap_or(&name_from_dvd);
-    let dvd_dir = args.output.join(name);
-    if let Err(e) = create_dir(&dvd_dir) {
-        if e.kind() != std::io::ErrorKind::AlreadyExists {
-            return Err(e.into());
+    let max_depth = parser.max_depth;
+    load_title_keys(&mut parser, &structures, max_depth)?;
+    let tree = read_disc_tree(&mut parser, &structures)?;
+    info!("Mounting {} files at {:?}; unmount it to stop", tree.files.len(), args.mountpoint);
+    mount(parser, structures, tree, &args.mountpoint)?;
+    Ok(())
+}
+
+/// The files of the disc with their sizes and modification times
+fn read_disc_tree(parser: &mut UdfParser<DvdCss>, structures: &VolumeStructures) -> Result<DiscTree> {
+    let mut tree = DiscTree::default();
+    walk_filesystem(parser, structures, &mut |reader, path, icb_address, _| {
+        let (_, file_entries) = read_icb(reader, structures, icb_address)?;
+        if let Some(file_entry) = file_entries.first() {
+            let modified = file_entry.modification_time.system_time();
+            tree.insert(path, icb_address.clone(), file_entry.information_length, modified);
+        }
+        Ok(())
+    })?;
+    Ok(tree)
+}
+
+/// Serve the disc's files over HTTP and read-only WebDAV until the process is killed
+#[cfg(feature = "serve")]
+fn run_serve(args: &ServeArgs) -> Result<()> {
+    let read_error_policy = ReadErrorPolicy {
+        retries: args.retries,
+        ..Default::default()
+    };
+    let mut parser = open_parser(&args.disc)?.with_read_error_policy(read_error_policy);
+    let structures = parser.read_volume_structures()?;
+    let max_depth = parser.max_depth;
+    load_title_keys(&mut parser, &structures, max_depth)?;
+    let tree = read_disc_tree(&mut parser, &structures)?;
+    let server = tiny_http::Server::http(&args.listen).map_err(std::io::Error::other)?;
+    println!("Serving {} files at http://{}/", tree.files.len(), server.server_addr());
+    // requests for the disc are handled one at a time on this thread since they share the drive
+    // and the sector cache. /metrics is answered by the receiving thread so that it does not
+    // wait behind a long download
+    let cache_stats = parser.stats();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || receive_serve_requests(&server, &cache_stats, sender));
+    for request in receiver {
+        let description = format!("{} {}", request.method(), request.url());
+        debug!("serve: {}", description);
+        if let Err(e) = handle_serve_request(&mut parser, &structures, &tree, request) {
+            warn!("{}: {}", description, e);
         }
     }
-    // parser.read_filesystem(&structures, output, name)?;
-    // See UDF 2.6.0 6.9 Requirements for DVD-ROM http://www.osta.org/specs/pdf/udf260.pdf#page=136
-    for partition_map in structures.partition_maps.iter() {
-        match partition_map {
-            PartitionMap::Type1(partition_map) => {
-                let partition_descriptor = structures
-                    .partition_descriptors
-                    .get(&partition_map.partition_number);
-                if let Some(partition_descriptor) = partition_descriptor {
-                    debug!("Found matching partition descriptor: {:?} -> starting location: {} sector, length: {} sectors",
-                        partition_descriptor, partition_descriptor.partition_starting_location, partition_descriptor.partition_length);
-                    let fsds =
-                        parser.read_fileset_descriptors(partition_descriptor, partition_map)?;
-                    let mut reader =
-                        Cache::<&mut DvdCss, DVDCSS_BLOCK_SIZE>::new(&mut parser.reader);
-
-                    for fsd in &fsds[..1] {
-                        run_on_directory(
-                            &mut reader,
-                            &structures.logical_volume,
-                            &structures.partition_descriptors,
-                            &fsd.root_directory_icb,
-                            &dvd_dir,
-                            &mut vec![],
-                        )?;
-                    }
-                } else {
-                    warn!(
-                        "Could not find matching partition descriptor for partition map: {:?}",
-                        partition_map
-                    );
-                }
+    Ok(())
+}
+
+#[cfg(not(feature = "serve"))]
+fn run_serve(_args: &ServeArgs) -> Result<()> {
+    Err(std::io::Error::other("serve needs dvdromcopy built with --features serve").into())
+}
+
+/// Receive the requests of serve, answering GET /metrics and passing the others to sender
+#[cfg(feature = "serve")]
+fn receive_serve_requests(
+    server: &tiny_http::Server,
+    cache_stats: &CacheStats,
+    sender: std::sync::mpsc::Sender<tiny_http::Request>,
+) {
+    use tiny_http::{Method, Response};
+    let serve_stats = ServeStats::default();
+    let mut throughput = Throughput::new(Duration::from_secs(10));
+    loop {
+        throughput.sample(Instant::now(), disc_bytes_read(cache_stats));
+        let request = match server.recv_timeout(Duration::from_secs(1)) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("serve: could not receive a request: {}", e);
+                return;
             }
-            _ => {
-                log::warn!("Ignoring other partition type");
+        };
+        serve_stats.requests.fetch_add(1, Ordering::Relaxed);
+        if request.url() == "/metrics" && matches!(request.method(), Method::Get | Method::Head) {
+            let text = prometheus_text(cache_stats, &serve_stats, throughput.bytes_per_second());
+            let response = Response::from_string(text)
+                .with_header(http_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8"));
+            if let Err(e) = request.respond(response) {
+                warn!("GET /metrics: {}", e);
             }
+        } else if sender.send(request).is_err() {
+            return;
         }
     }
-    // structures.partition_maps
+}
+
+#[cfg(feature = "serve")]
+fn http_header(name: &str, value: &str) -> tiny_http::Header {
+    tiny_http::Header::from_bytes(name, value).expect("header names and values are ASCII")
+}
+
+/// Respond to GET or HEAD of a file (honoring Range) or of a directory listing,
+/// or to the OPTIONS and PROPFIND requests of WebDAV clients
+#[cfg(feature = "serve")]
+fn handle_serve_request<R: Read + Seek>(
+    parser: &mut UdfParser<R>,
+    structures: &VolumeStructures,
+    tree: &DiscTree,
+    request: tiny_http::Request,
+) -> Result<()> {
+    use tiny_http::{Method, Response, StatusCode};
+    const ALLOW: &str = "OPTIONS, GET, HEAD, PROPFIND";
+    let Some(path) = url_to_path(request.url()) else {
+        request.respond(Response::empty(400))?;
+        return Ok(());
+    };
+    match request.method() {
+        Method::Get | Method::Head => {}
+        Method::Options => {
+            // class 1: no locking, which clients take to mean that the share is read-only
+            let response = Response::empty(200)
+                .with_header(http_header("DAV", "1"))
+                .with_header(http_header("Allow", ALLOW))
+                .with_header(http_header("MS-Author-Via", "DAV"));
+            request.respond(response)?;
+            return Ok(());
+        }
+        method if method.as_str() == "PROPFIND" => {
+            let depth = request.headers().iter().find(|header| header.field.equiv("Depth"));
+            let include_children = depth.is_none_or(|depth| depth.value.as_str().trim() != "0");
+            let response = match propfind_multistatus(tree, &path, include_children) {
+                Some(xml) => Response::from_string(xml)
+                    .with_status_code(207)
+                    .with_header(http_header("Content-Type", "application/xml; charset=utf-8")),
+                None => Response::from_string("").with_status_code(404),
+            };
+            request.respond(response)?;
+            return Ok(());
+        }
+        _ => {
+            request.respond(Response::empty(405).with_header(http_header("Allow", ALLOW)))?;
+            return Ok(());
+        }
+    }
+    if let Some(file) = tree.file(&path) {
+        let (logical_volume, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+        let mut udf_file = UdfFile::open(parser.cache(), logical_volume, partitions, &file.icb, &path)?;
+        // what can be read, which is less than the File Entry's length if its extents end early
+        let size = udf_file.size();
+        let range_header = request.headers().iter().find(|header| header.field.equiv("Range"));
+        let (status, range) = match range_header.and_then(|header| parse_range(header.value.as_str(), size)) {
+            None => (200, 0..size),
+            Some(RangeRequest::Satisfiable(range)) => (206, range),
+            Some(RangeRequest::Unsatisfiable) => {
+                let content_range = format!("bytes */{}", size);
+                request.respond(Response::empty(416).with_header(http_header("Content-Range", &content_range)))?;
+                return Ok(());
+            }
+        };
+        let mut headers = vec![
+            http_header("Accept-Ranges", "bytes"),
+            http_header("Content-Type", content_type(&path)),
+        ];
+        headers.extend(http_date(file.modified).map(|date| http_header("Last-Modified", &date)));
+        if status == 206 {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, size);
+            headers.push(http_header("Content-Range", &content_range));
+        }
+        let length = range.end - range.start;
+        udf_file.seek(SeekFrom::Start(range.start))?;
+        let body = udf_file.take(length);
+        // tiny_http would use chunked encoding for large bodies, which leaves HEAD without a length
+        let response = Response::new(StatusCode(status), headers, body, Some(length as usize), None)
+            .with_chunked_threshold(usize::MAX);
+        request.respond(response)?;
+    } else if tree.is_directory(&path) {
+        if !path.is_empty() && !request.url().split('?').next().unwrap_or_default().ends_with('/') {
+            // the relative links of the listing need the trailing slash
+            let location = format!("/{}/", path_to_url(&path));
+            request.respond(Response::empty(301).with_header(http_header("Location", &location)))?;
+            return Ok(());
+        }
+        let html = directory_listing_html(&path, &tree.children(&path));
+        request.respond(Response::from_string(html).with_header(http_header("Content-Type", "text/html; charset=utf-8")))?;
+    } else {
+        request.respond(Response::empty(404))?;
+    }
     Ok(())
 }
+
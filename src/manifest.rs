@@ -0,0 +1,306 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+};
+
+use log::debug;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Writer that computes the SHA-256 of everything written through it.
+/// This is the hashing pipeline shared by the copy path (which hashes
+/// data as it is written to the output file) and manifest verification
+/// (which hashes previously extracted files).
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    bytes_written: u64,
+}
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes_written: 0,
+        }
+    }
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+    /// Hash the bytes that are already in the output, such as those of a resumed file, without
+    /// writing them again; they count as written
+    pub fn hash_existing<R: Read>(&mut self, mut existing: R) -> io::Result<u64> {
+        let length = io::copy(&mut existing, &mut self.hasher)?;
+        self.bytes_written += length;
+        Ok(length)
+    }
+    /// Returns the inner writer and the lowercase hex SHA-256 digest
+    pub fn finish(self) -> (W, String) {
+        let digest = to_hex(&self.hasher.finalize());
+        (self.inner, digest)
+    }
+}
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes_written += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hash everything readable from reader
+pub fn hash_reader<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut writer = HashingWriter::new(io::sink());
+    io::copy(&mut reader, &mut writer)?;
+    let (_, digest) = writer.finish();
+    Ok(digest)
+}
+
+/// One line of a SHA256SUMS file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// lowercase hex SHA-256
+    pub digest: String,
+    /// path relative to the output directory, '/'-separated
+    pub path: String,
+}
+
+/// A checksum manifest in the format written by `sha256sum`:
+/// `<hex digest> <space or '*'><path>` per line.
+#[derive(Debug, Default, Clone)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+    /// written as `#` lines before the entries, which sha256sum and read() skip
+    pub comments: Vec<String>,
+}
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, path: &str, digest: String) {
+        self.entries.push(ManifestEntry {
+            digest,
+            path: path.trim_start_matches('/').to_string(),
+        });
+    }
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut manifest = Self::new();
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            manifest.entries.push(parse_line(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid manifest line {}: {:?}", line_number + 1, line),
+                )
+            })?);
+        }
+        Ok(manifest)
+    }
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for comment in &self.comments {
+            writeln!(writer, "# {}", comment)?;
+        }
+        for entry in &self.entries {
+            writeln!(writer, "{}  {}", entry.digest, entry.path)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<ManifestEntry> {
+    let (digest, rest) = line.split_once(' ')?;
+    if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    // text mode is "<digest>  <path>", binary mode is "<digest> *<path>"
+    let path = rest
+        .strip_prefix(' ')
+        .or_else(|| rest.strip_prefix('*'))?;
+    if path.is_empty() {
+        return None;
+    }
+    Some(ManifestEntry {
+        digest: digest.to_ascii_lowercase(),
+        path: path.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ManifestStatus {
+    Ok,
+    Mismatch { actual_digest: String },
+    Missing,
+    /// the file exists but could not be read
+    ReadError { error: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestCheck {
+    pub path: String,
+    pub expected_digest: String,
+    #[serde(flatten)]
+    pub status: ManifestStatus,
+}
+
+/// Overall result of a verification, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyOutcome {
+    Ok,
+    /// some files were missing or had different contents
+    Mismatches,
+    /// some files could not be read, so the verification is incomplete
+    ReadErrors,
+}
+
+/// Per-file results of verifying a directory, suitable for serializing as JSON
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub outcome: VerifyOutcome,
+    pub ok: usize,
+    pub mismatched: usize,
+    pub missing: usize,
+    pub read_errors: usize,
+    pub files: Vec<ManifestCheck>,
+}
+impl VerifyReport {
+    pub fn new(files: Vec<ManifestCheck>) -> Self {
+        let count = |f: fn(&ManifestStatus) -> bool| files.iter().filter(|c| f(&c.status)).count();
+        let ok = count(|s| matches!(s, ManifestStatus::Ok));
+        let mismatched = count(|s| matches!(s, ManifestStatus::Mismatch { .. }));
+        let missing = count(|s| matches!(s, ManifestStatus::Missing));
+        let read_errors = count(|s| matches!(s, ManifestStatus::ReadError { .. }));
+        let outcome = if read_errors > 0 {
+            VerifyOutcome::ReadErrors
+        } else if mismatched > 0 || missing > 0 {
+            VerifyOutcome::Mismatches
+        } else {
+            VerifyOutcome::Ok
+        };
+        Self {
+            outcome,
+            ok,
+            mismatched,
+            missing,
+            read_errors,
+            files,
+        }
+    }
+}
+
+/// Hash every file listed in the manifest relative to dir
+/// and compare it with the recorded digest.
+pub fn verify_manifest(dir: &Path, manifest: &Manifest) -> VerifyReport {
+    let mut checks = Vec::with_capacity(manifest.entries.len());
+    for entry in &manifest.entries {
+        let path = dir.join(&entry.path);
+        debug!("verify_manifest: hashing {:?}", path);
+        let status = match File::open(&path).and_then(|file| hash_reader(BufReader::new(file))) {
+            Ok(actual_digest) if actual_digest == entry.digest => ManifestStatus::Ok,
+            Ok(actual_digest) => ManifestStatus::Mismatch { actual_digest },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => ManifestStatus::Missing,
+            Err(e) => ManifestStatus::ReadError {
+                error: e.to_string(),
+            },
+        };
+        checks.push(ManifestCheck {
+            path: entry.path.clone(),
+            expected_digest: entry.digest.clone(),
+            status,
+        });
+    }
+    VerifyReport::new(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_reader() {
+        // echo -n abc | sha256sum
+        assert_eq!(
+            hash_reader(&b"abc"[..]).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_hash_existing() {
+        let mut writer = HashingWriter::new(Vec::new());
+        assert_eq!(writer.hash_existing(&b"ab"[..]).unwrap(), 2);
+        writer.write_all(b"c").unwrap();
+        assert_eq!(writer.bytes_written(), 3);
+        let (written, digest) = writer.finish();
+        assert_eq!(written, b"c");
+        assert_eq!(digest, hash_reader(&b"abc"[..]).unwrap());
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let text = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  VIDEO_TS/VIDEO_TS.IFO\n\
+            E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855 *VIDEO_TS/VTS_01_1.VOB\n";
+        let manifest = Manifest::read(text.as_bytes()).unwrap();
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(manifest.entries[1].path, "VIDEO_TS/VTS_01_1.VOB");
+        assert_eq!(
+            manifest.entries[1].digest,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let mut manifest = manifest;
+        manifest.comments.push("README.TXT was written as README~1.TXT".to_string());
+        let mut out = Vec::new();
+        manifest.write(&mut out).unwrap();
+        assert!(out.starts_with(b"# README.TXT was written as README~1.TXT\n"));
+        let reread = Manifest::read(&out[..]).unwrap();
+        assert_eq!(reread.entries, manifest.entries);
+    }
+
+    #[test]
+    fn test_verify_report_outcome() {
+        let check = |status| ManifestCheck {
+            path: "A".to_string(),
+            expected_digest: String::new(),
+            status,
+        };
+        assert_eq!(
+            VerifyReport::new(vec![check(ManifestStatus::Ok)]).outcome,
+            VerifyOutcome::Ok
+        );
+        let report = VerifyReport::new(vec![check(ManifestStatus::Ok), check(ManifestStatus::Missing)]);
+        assert_eq!(report.outcome, VerifyOutcome::Mismatches);
+        assert_eq!((report.ok, report.missing), (1, 1));
+        let report = VerifyReport::new(vec![
+            check(ManifestStatus::Missing),
+            check(ManifestStatus::ReadError {
+                error: "bad".to_string(),
+            }),
+        ]);
+        assert_eq!(report.outcome, VerifyOutcome::ReadErrors);
+        assert_eq!(
+            serde_json::to_value(&report.files[1]).unwrap(),
+            serde_json::json!({"path": "A", "expected_digest": "", "status": "read_error", "error": "bad"})
+        );
+    }
+
+    #[test]
+    fn test_manifest_invalid_line() {
+        assert!(Manifest::read(&b"not a checksum line\n"[..]).is_err());
+    }
+}
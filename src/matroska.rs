@@ -0,0 +1,397 @@
+//! Minimal Matroska writer: one segment with clusters of SimpleBlocks, tracks and chapters.
+//! See https://www.matroska.org/technical/elements.html and RFC 8794 (EBML).
+//!
+//! Since the tracks are only known after demuxing, space for the Info, Tracks and
+//! Chapters elements is reserved with a Void element before the first cluster
+//! and filled in by finish().
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+mod id {
+    pub const EBML: u32 = 0x1A45DFA3;
+    pub const EBML_VERSION: u32 = 0x4286;
+    pub const EBML_READ_VERSION: u32 = 0x42F7;
+    pub const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+    pub const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+    pub const DOC_TYPE: u32 = 0x4282;
+    pub const DOC_TYPE_VERSION: u32 = 0x4287;
+    pub const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+    pub const VOID: u32 = 0xEC;
+    pub const SEGMENT: u32 = 0x18538067;
+    pub const INFO: u32 = 0x1549A966;
+    pub const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+    pub const DURATION: u32 = 0x4489;
+    pub const TITLE: u32 = 0x7BA9;
+    pub const MUXING_APP: u32 = 0x4D80;
+    pub const WRITING_APP: u32 = 0x5741;
+    pub const TRACKS: u32 = 0x1654AE6B;
+    pub const TRACK_ENTRY: u32 = 0xAE;
+    pub const TRACK_NUMBER: u32 = 0xD7;
+    pub const TRACK_UID: u32 = 0x73C5;
+    pub const TRACK_TYPE: u32 = 0x83;
+    pub const FLAG_LACING: u32 = 0x9C;
+    pub const LANGUAGE: u32 = 0x22B59C;
+    pub const CODEC_ID: u32 = 0x86;
+    pub const CODEC_PRIVATE: u32 = 0x63A2;
+    pub const DEFAULT_DURATION: u32 = 0x23E383;
+    pub const VIDEO: u32 = 0xE0;
+    pub const PIXEL_WIDTH: u32 = 0xB0;
+    pub const PIXEL_HEIGHT: u32 = 0xBA;
+    pub const DISPLAY_WIDTH: u32 = 0x54B0;
+    pub const DISPLAY_HEIGHT: u32 = 0x54BA;
+    pub const AUDIO: u32 = 0xE1;
+    pub const SAMPLING_FREQUENCY: u32 = 0xB5;
+    pub const CHANNELS: u32 = 0x9F;
+    pub const BIT_DEPTH: u32 = 0x6264;
+    pub const CLUSTER: u32 = 0x1F43B675;
+    pub const TIMESTAMP: u32 = 0xE7;
+    pub const SIMPLE_BLOCK: u32 = 0xA3;
+    pub const CHAPTERS: u32 = 0x1043A770;
+    pub const EDITION_ENTRY: u32 = 0x45B9;
+    pub const CHAPTER_ATOM: u32 = 0xB6;
+    pub const CHAPTER_UID: u32 = 0x73C4;
+    pub const CHAPTER_TIME_START: u32 = 0x91;
+    pub const CHAPTER_DISPLAY: u32 = 0x80;
+    pub const CHAP_STRING: u32 = 0x85;
+    pub const CHAP_LANGUAGE: u32 = 0x437C;
+}
+
+/// Block timestamps are in milliseconds
+pub const TIMESTAMP_SCALE_NS: u64 = 1_000_000;
+
+/// Bytes reserved before the first cluster for Info, Tracks and Chapters
+const RESERVED_HEADER_SIZE: usize = 64 * 1024;
+
+/// Start a new cluster after this many milliseconds
+const CLUSTER_DURATION_MS: i64 = 5000;
+
+/// EBML element IDs already contain their length marker, so they are written as-is
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+/// Variable-size integer with the shortest length that can hold size
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    let mut length = 1;
+    // all ones is reserved for unknown sizes
+    while length < 8 && size >= (1 << (7 * length)) - 1 {
+        length += 1;
+    }
+    write_size_with_length(out, size, length);
+}
+
+fn write_size_with_length(out: &mut Vec<u8>, size: u64, length: usize) {
+    let marked = size | (1 << (7 * length));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - length..]);
+}
+
+pub fn element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 12);
+    write_id(&mut out, id);
+    write_size(&mut out, payload.len() as u64);
+    out.extend_from_slice(payload);
+    out
+}
+
+pub fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    element(id, &bytes[skip..])
+}
+
+pub fn float_element(id: u32, value: f64) -> Vec<u8> {
+    element(id, &value.to_be_bytes())
+}
+
+pub fn string_element(id: u32, value: &str) -> Vec<u8> {
+    element(id, value.as_bytes())
+}
+
+/// A Void element of exactly size bytes (at least 9)
+fn void_element(size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(size);
+    write_id(&mut out, id::VOID);
+    write_size_with_length(&mut out, (size - 9) as u64, 8);
+    out.resize(size, 0);
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackKind {
+    Video {
+        pixel_width: u32,
+        pixel_height: u32,
+        display_width: u32,
+        display_height: u32,
+    },
+    Audio {
+        sampling_frequency: f64,
+        channels: u8,
+        bit_depth: Option<u8>,
+    },
+    Subtitle,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track {
+    /// 1-based, as used in SimpleBlocks
+    pub number: u8,
+    pub codec_id: &'static str,
+    pub codec_private: Option<Vec<u8>>,
+    /// ISO 639-2 language code
+    pub language: Option<String>,
+    pub default_duration_ns: Option<u64>,
+    pub kind: TrackKind,
+}
+impl Track {
+    fn to_element(&self) -> Vec<u8> {
+        let (track_type, settings) = match &self.kind {
+            TrackKind::Video {
+                pixel_width,
+                pixel_height,
+                display_width,
+                display_height,
+            } => (
+                1,
+                element(
+                    id::VIDEO,
+                    &[
+                        uint_element(id::PIXEL_WIDTH, *pixel_width as u64),
+                        uint_element(id::PIXEL_HEIGHT, *pixel_height as u64),
+                        uint_element(id::DISPLAY_WIDTH, *display_width as u64),
+                        uint_element(id::DISPLAY_HEIGHT, *display_height as u64),
+                    ]
+                    .concat(),
+                ),
+            ),
+            TrackKind::Audio {
+                sampling_frequency,
+                channels,
+                bit_depth,
+            } => {
+                let mut audio = float_element(id::SAMPLING_FREQUENCY, *sampling_frequency);
+                audio.extend(uint_element(id::CHANNELS, *channels as u64));
+                if let Some(bit_depth) = bit_depth {
+                    audio.extend(uint_element(id::BIT_DEPTH, *bit_depth as u64));
+                }
+                (2, element(id::AUDIO, &audio))
+            }
+            TrackKind::Subtitle => (0x11, vec![]),
+        };
+        let mut entry = [
+            uint_element(id::TRACK_NUMBER, self.number as u64),
+            uint_element(id::TRACK_UID, self.number as u64),
+            uint_element(id::TRACK_TYPE, track_type),
+            uint_element(id::FLAG_LACING, 0),
+            string_element(id::LANGUAGE, self.language.as_deref().unwrap_or("und")),
+            string_element(id::CODEC_ID, self.codec_id),
+        ]
+        .concat();
+        if let Some(codec_private) = &self.codec_private {
+            entry.extend(element(id::CODEC_PRIVATE, codec_private));
+        }
+        if let Some(default_duration_ns) = self.default_duration_ns {
+            entry.extend(uint_element(id::DEFAULT_DURATION, default_duration_ns));
+        }
+        entry.extend(settings);
+        element(id::TRACK_ENTRY, &entry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub start_ms: u64,
+    pub title: String,
+}
+
+/// Writes a Matroska file; call finish() to write the headers and sizes
+pub struct MatroskaWriter<W: Write + Seek> {
+    inner: W,
+    segment_size_pos: u64,
+    segment_data_pos: u64,
+    reserved_pos: u64,
+    cluster: Vec<u8>,
+    cluster_timestamp: i64,
+    max_timestamp: i64,
+}
+impl<W: Write + Seek> MatroskaWriter<W> {
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let header = [
+            uint_element(id::EBML_VERSION, 1),
+            uint_element(id::EBML_READ_VERSION, 1),
+            uint_element(id::EBML_MAX_ID_LENGTH, 4),
+            uint_element(id::EBML_MAX_SIZE_LENGTH, 8),
+            string_element(id::DOC_TYPE, "matroska"),
+            uint_element(id::DOC_TYPE_VERSION, 4),
+            uint_element(id::DOC_TYPE_READ_VERSION, 2),
+        ]
+        .concat();
+        inner.write_all(&element(id::EBML, &header))?;
+        let mut segment = vec![];
+        write_id(&mut segment, id::SEGMENT);
+        inner.write_all(&segment)?;
+        let segment_size_pos = inner.stream_position()?;
+        // patched by finish()
+        let mut size = vec![];
+        write_size_with_length(&mut size, 0, 8);
+        inner.write_all(&size)?;
+        let segment_data_pos = inner.stream_position()?;
+        inner.write_all(&void_element(RESERVED_HEADER_SIZE))?;
+        Ok(Self {
+            inner,
+            segment_size_pos,
+            segment_data_pos,
+            reserved_pos: segment_data_pos,
+            cluster: vec![],
+            cluster_timestamp: 0,
+            max_timestamp: 0,
+        })
+    }
+
+    /// Add a frame to the current cluster; timestamp_ms may be slightly out of order
+    pub fn write_block(&mut self, track_number: u8, timestamp_ms: i64, keyframe: bool, data: &[u8]) -> io::Result<()> {
+        let timestamp_ms = timestamp_ms.max(0);
+        let relative = timestamp_ms - self.cluster_timestamp;
+        if self.cluster.is_empty()
+            || relative >= CLUSTER_DURATION_MS
+            || relative < i16::MIN as i64
+            || (keyframe && relative >= 1000)
+        {
+            self.flush_cluster()?;
+            self.cluster_timestamp = timestamp_ms;
+            self.cluster = uint_element(id::TIMESTAMP, timestamp_ms as u64);
+        }
+        let relative = (timestamp_ms - self.cluster_timestamp) as i16;
+        let mut block = Vec::with_capacity(data.len() + 4);
+        write_size(&mut block, track_number as u64);
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0 });
+        block.extend_from_slice(data);
+        self.cluster.extend(element(id::SIMPLE_BLOCK, &block));
+        self.max_timestamp = self.max_timestamp.max(timestamp_ms);
+        Ok(())
+    }
+
+    fn flush_cluster(&mut self) -> io::Result<()> {
+        if !self.cluster.is_empty() {
+            self.inner.write_all(&element(id::CLUSTER, &self.cluster))?;
+            self.cluster.clear();
+        }
+        Ok(())
+    }
+
+    /// Write the remaining blocks and the Info, Tracks and Chapters elements
+    pub fn finish(mut self, title: Option<&str>, tracks: &[Track], chapters: &[Chapter]) -> io::Result<W> {
+        self.flush_cluster()?;
+        let end = self.inner.stream_position()?;
+
+        let mut info = [
+            uint_element(id::TIMESTAMP_SCALE, TIMESTAMP_SCALE_NS),
+            float_element(id::DURATION, self.max_timestamp as f64),
+            string_element(id::MUXING_APP, env!("CARGO_PKG_NAME")),
+            string_element(id::WRITING_APP, env!("CARGO_PKG_NAME")),
+        ]
+        .concat();
+        if let Some(title) = title {
+            info.extend(string_element(id::TITLE, title));
+        }
+        let mut headers = element(id::INFO, &info);
+        headers.extend(element(
+            id::TRACKS,
+            &tracks.iter().flat_map(|track| track.to_element()).collect::<Vec<_>>(),
+        ));
+        if !chapters.is_empty() {
+            let atoms: Vec<u8> = chapters
+                .iter()
+                .enumerate()
+                .flat_map(|(i, chapter)| {
+                    let display = [
+                        string_element(id::CHAP_STRING, &chapter.title),
+                        string_element(id::CHAP_LANGUAGE, "eng"),
+                    ]
+                    .concat();
+                    element(
+                        id::CHAPTER_ATOM,
+                        &[
+                            uint_element(id::CHAPTER_UID, i as u64 + 1),
+                            uint_element(id::CHAPTER_TIME_START, chapter.start_ms * TIMESTAMP_SCALE_NS),
+                            element(id::CHAPTER_DISPLAY, &display),
+                        ]
+                        .concat(),
+                    )
+                })
+                .collect();
+            headers.extend(element(id::CHAPTERS, &element(id::EDITION_ENTRY, &atoms)));
+        }
+        if headers.len() + 9 > RESERVED_HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Matroska headers of {} bytes do not fit in the reserved space", headers.len()),
+            ));
+        }
+        headers.extend(void_element(RESERVED_HEADER_SIZE - headers.len()));
+        self.inner.seek(SeekFrom::Start(self.reserved_pos))?;
+        self.inner.write_all(&headers)?;
+
+        let mut size = vec![];
+        write_size_with_length(&mut size, end - self.segment_data_pos, 8);
+        self.inner.seek(SeekFrom::Start(self.segment_size_pos))?;
+        self.inner.write_all(&size)?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_size() {
+        let size = |n| {
+            let mut out = vec![];
+            write_size(&mut out, n);
+            out
+        };
+        assert_eq!(size(0), vec![0x80]);
+        assert_eq!(size(126), vec![0xFE]);
+        // 127 would be all ones, which means unknown size
+        assert_eq!(size(127), vec![0x40, 0x7F]);
+        assert_eq!(size(0x1234), vec![0x52, 0x34]);
+        assert_eq!(uint_element(id::TRACK_NUMBER, 0), vec![0xD7, 0x81, 0x00]);
+        assert_eq!(void_element(12).len(), 12);
+    }
+
+    #[test]
+    fn test_matroska_writer() {
+        let mut writer = MatroskaWriter::new(io::Cursor::new(vec![])).unwrap();
+        writer.write_block(1, 0, true, b"frame").unwrap();
+        writer.write_block(1, 40, false, b"frame").unwrap();
+        let track = Track {
+            number: 1,
+            codec_id: "V_MPEG2",
+            codec_private: None,
+            language: None,
+            default_duration_ns: None,
+            kind: TrackKind::Video {
+                pixel_width: 720,
+                pixel_height: 576,
+                display_width: 1024,
+                display_height: 576,
+            },
+        };
+        let chapters = [Chapter {
+            start_ms: 0,
+            title: "Chapter 1".to_string(),
+        }];
+        let bytes = writer.finish(None, &[track], &chapters).unwrap().into_inner();
+        assert_eq!(bytes[..4], [0x1A, 0x45, 0xDF, 0xA3]);
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"V_MPEG2"));
+        assert!(contains(b"Chapter 1"));
+        // the cluster follows the reserved headers
+        assert!(contains(&[0x1F, 0x43, 0xB6, 0x75]));
+    }
+}
@@ -0,0 +1,150 @@
+//! Prometheus metrics of `serve` at /metrics, in the text exposition format:
+//! https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{cache::CacheStats, dvdcss_sys::DVDCSS_BLOCK_SIZE};
+
+/// Counters of the HTTP side of serve
+#[derive(Debug, Default)]
+pub struct ServeStats {
+    pub requests: AtomicU64,
+}
+
+/// Bytes per second over a sliding window of samples of a byte counter
+#[derive(Debug)]
+pub struct Throughput {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+impl Throughput {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record the counter's value at now, forgetting samples older than the window
+    pub fn sample(&mut self, now: Instant, bytes: u64) {
+        self.samples.push_back((now, bytes));
+        while let Some(&(time, _)) = self.samples.front() {
+            if now.duration_since(time) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(&(start, start_bytes)), Some(&(end, end_bytes))) if end > start => {
+                end_bytes.saturating_sub(start_bytes) as f64 / end.duration_since(start).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Bytes read from the disc so far
+pub fn disc_bytes_read(cache: &CacheStats) -> u64 {
+    cache.misses.load(Ordering::Relaxed) * DVDCSS_BLOCK_SIZE as u64
+}
+
+/// The response to GET /metrics
+pub fn prometheus_text(cache: &CacheStats, serve: &ServeStats, bytes_per_second: f64) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        let _ = write!(text, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+    };
+    let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+    metric(
+        "dvdromcopy_disc_read_bytes_total",
+        "counter",
+        "Bytes read from the disc.",
+        disc_bytes_read(cache).to_string(),
+    );
+    metric(
+        "dvdromcopy_disc_read_errors_total",
+        "counter",
+        "Sectors that could not be read from the disc.",
+        counter(&cache.read_errors),
+    );
+    metric(
+        "dvdromcopy_read_retries_total",
+        "counter",
+        "Reads of sectors that were tried again after failing.",
+        counter(&cache.retries),
+    );
+    metric(
+        "dvdromcopy_cache_hits_total",
+        "counter",
+        "Sectors served from the sector cache.",
+        counter(&cache.hits),
+    );
+    metric(
+        "dvdromcopy_cache_misses_total",
+        "counter",
+        "Sectors that were not in the sector cache.",
+        counter(&cache.misses),
+    );
+    metric(
+        "dvdromcopy_http_requests_total",
+        "counter",
+        "HTTP requests received.",
+        counter(&serve.requests),
+    );
+    metric(
+        "dvdromcopy_disc_read_bytes_per_second",
+        "gauge",
+        "Recent disc read throughput.",
+        format!("{:.0}", bytes_per_second),
+    );
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throughput() {
+        let start = Instant::now();
+        let mut throughput = Throughput::new(Duration::from_secs(10));
+        assert_eq!(throughput.bytes_per_second(), 0.0);
+        throughput.sample(start, 1000);
+        assert_eq!(throughput.bytes_per_second(), 0.0);
+        throughput.sample(start + Duration::from_secs(2), 5000);
+        assert_eq!(throughput.bytes_per_second(), 2000.0);
+        // the first samples fall out of the window
+        throughput.sample(start + Duration::from_secs(11), 5000);
+        throughput.sample(start + Duration::from_secs(12), 5000);
+        assert_eq!(throughput.bytes_per_second(), 0.0);
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let cache = CacheStats::default();
+        cache.misses.store(3, Ordering::Relaxed);
+        cache.hits.store(5, Ordering::Relaxed);
+        cache.retries.store(4, Ordering::Relaxed);
+        let serve = ServeStats::default();
+        serve.requests.store(2, Ordering::Relaxed);
+        let text = prometheus_text(&cache, &serve, 1234.4);
+        assert!(text.contains(
+            "# HELP dvdromcopy_disc_read_bytes_total Bytes read from the disc.\n\
+             # TYPE dvdromcopy_disc_read_bytes_total counter\n\
+             dvdromcopy_disc_read_bytes_total 6144\n"
+        ));
+        assert!(text.contains("dvdromcopy_cache_hits_total 5\n"));
+        assert!(text.contains("dvdromcopy_disc_read_errors_total 0\n"));
+        assert!(text.contains("dvdromcopy_read_retries_total 4\n"));
+        assert!(text.contains("dvdromcopy_http_requests_total 2\n"));
+        assert!(text.ends_with("dvdromcopy_disc_read_bytes_per_second 1234\n"));
+    }
+}
@@ -0,0 +1,260 @@
+//! `dvdromcopy mount`: the files of the disc as a read-only file system, decrypted as they are
+//! read, so that players such as mpv and VLC can open them without copying the disc first.
+//! Mounting needs the `fuse` cargo feature; the inode numbering here does not.
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use crate::{
+    serve::DiscTree,
+    udf_high_level::VolumeStructures,
+    udf_parser::UdfParser,
+};
+
+/// The inode of the root directory, which FUSE expects to be 1
+pub const ROOT_INODE: u64 = 1;
+
+/// The inode numbers of the directories and files of a DiscTree: the root, then every path in
+/// name order
+#[derive(Debug, Default)]
+pub struct InodeTable {
+    /// the '/'-separated path ("" for the root) of inode i + 1
+    paths: Vec<String>,
+    inodes: BTreeMap<String, u64>,
+}
+
+impl InodeTable {
+    pub fn new(tree: &DiscTree) -> Self {
+        let mut table = Self::default();
+        table.push("");
+        for path in tree.files.keys() {
+            // the directories that hold the file, then the file
+            for (i, _) in path.match_indices('/') {
+                table.push(&path[..i]);
+            }
+            table.push(path);
+        }
+        table
+    }
+
+    fn push(&mut self, path: &str) {
+        if !self.inodes.contains_key(path) {
+            self.paths.push(path.to_string());
+            self.inodes.insert(path.to_string(), self.paths.len() as u64);
+        }
+    }
+
+    pub fn path(&self, inode: u64) -> Option<&str> {
+        let index = inode.checked_sub(ROOT_INODE)?;
+        self.paths.get(usize::try_from(index).ok()?).map(String::as_str)
+    }
+
+    pub fn inode(&self, path: &str) -> Option<u64> {
+        self.inodes.get(path).copied()
+    }
+
+    /// The inode of the entry called name in the directory at inode parent
+    pub fn child(&self, parent: u64, name: &str) -> Option<u64> {
+        self.inode(&child_path(self.path(parent)?, name))
+    }
+}
+
+/// The path of an entry called name in the directory at path ("" for the root)
+pub fn child_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+/// Mount the files of tree at mountpoint and serve them until it is unmounted
+#[cfg(feature = "fuse")]
+pub fn mount<R: io::Read + io::Seek>(
+    parser: UdfParser<R>,
+    structures: VolumeStructures,
+    tree: DiscTree,
+    mountpoint: &Path,
+) -> io::Result<()> {
+    let inodes = InodeTable::new(&tree);
+    let file_system = fuse::DiscFileSystem { parser, structures, tree, inodes };
+    let options = [fuser::MountOption::RO, fuser::MountOption::FSName("dvdromcopy".to_string())];
+    fuser::mount2(file_system, mountpoint, &options)
+}
+
+#[cfg(not(feature = "fuse"))]
+pub fn mount<R: io::Read + io::Seek>(
+    _parser: UdfParser<R>,
+    _structures: VolumeStructures,
+    _tree: DiscTree,
+    _mountpoint: &Path,
+) -> io::Result<()> {
+    Err(io::Error::other("mount needs dvdromcopy built with --features fuse"))
+}
+
+#[cfg(feature = "fuse")]
+mod fuse {
+    use std::{
+        ffi::OsStr,
+        io::{Read, Seek, SeekFrom},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use fuser::{FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request};
+    use log::{debug, warn};
+
+    use super::*;
+    use crate::{dvdcss_sys::DVDCSS_BLOCK_SIZE, serve::DirectoryEntry, udf_file::UdfFile};
+
+    // errno values, which are the same on Linux and macOS
+    const ENOENT: i32 = 2;
+    const EIO: i32 = 5;
+
+    /// The disc does not change while it is mounted
+    const TTL: Duration = Duration::from_secs(3600);
+
+    pub struct DiscFileSystem<R: Read + Seek> {
+        pub parser: UdfParser<R>,
+        pub structures: VolumeStructures,
+        pub tree: DiscTree,
+        pub inodes: InodeTable,
+    }
+
+    impl<R: Read + Seek> DiscFileSystem<R> {
+        fn attr(&self, inode: u64) -> Option<FileAttr> {
+            let path = self.inodes.path(inode)?;
+            let (kind, size, modified, perm) = match self.tree.file(path) {
+                Some(file) => (FileType::RegularFile, file.size, file.modified, 0o444),
+                None => (FileType::Directory, 0, UNIX_EPOCH, 0o555),
+            };
+            Some(FileAttr {
+                ino: inode,
+                size,
+                blocks: size.div_ceil(512),
+                atime: modified,
+                mtime: modified,
+                ctime: modified,
+                crtime: modified,
+                kind,
+                perm,
+                nlink: 1,
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                blksize: DVDCSS_BLOCK_SIZE as u32,
+                flags: 0,
+            })
+        }
+
+        /// size bytes of the file at path from offset on
+        fn read_file(&mut self, path: &str, offset: u64, size: u32) -> crate::udf_parser::Result<Vec<u8>> {
+            let file = self.tree.file(path).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            let structures = &self.structures;
+            let (logical_volume, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+            let mut udf_file = UdfFile::open(self.parser.cache(), logical_volume, partitions, &file.icb, path)?;
+            udf_file.seek(SeekFrom::Start(offset))?;
+            let mut data = vec![];
+            udf_file.take(size as u64).read_to_end(&mut data)?;
+            Ok(data)
+        }
+    }
+
+    impl<R: Read + Seek> Filesystem for DiscFileSystem<R> {
+        fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+            let attr = (name.to_str())
+                .and_then(|name| self.inodes.child(parent, name))
+                .and_then(|inode| self.attr(inode));
+            match attr {
+                Some(attr) => reply.entry(&TTL, &attr, 0),
+                None => reply.error(ENOENT),
+            }
+        }
+
+        fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+            match self.attr(ino) {
+                Some(attr) => reply.attr(&TTL, &attr),
+                None => reply.error(ENOENT),
+            }
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn read(
+            &mut self,
+            _req: &Request<'_>,
+            ino: u64,
+            _fh: u64,
+            offset: i64,
+            size: u32,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            reply: ReplyData,
+        ) {
+            let Some(path) = self.inodes.path(ino).map(str::to_string) else {
+                return reply.error(ENOENT);
+            };
+            debug!("mount: read {} bytes of {:?} at {}", size, path, offset);
+            match self.read_file(&path, offset.max(0) as u64, size) {
+                Ok(data) => reply.data(&data),
+                Err(e) => {
+                    warn!("Could not read {:?}: {}", path, e);
+                    reply.error(EIO)
+                }
+            }
+        }
+
+        fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+            let Some(path) = self.inodes.path(ino).filter(|path| self.tree.is_directory(path)) else {
+                return reply.error(ENOENT);
+            };
+            let mut entries = vec![
+                (ino, FileType::Directory, ".".to_string()),
+                (ino, FileType::Directory, "..".to_string()),
+            ];
+            for child in self.tree.children(path) {
+                let (DirectoryEntry::Directory(name) | DirectoryEntry::File(name, _)) = child;
+                let Some(child_ino) = self.inodes.inode(&child_path(path, name)) else {
+                    continue;
+                };
+                let kind = match child {
+                    DirectoryEntry::Directory(_) => FileType::Directory,
+                    DirectoryEntry::File(..) => FileType::RegularFile,
+                };
+                entries.push((child_ino, kind, name.to_string()));
+            }
+            // offset is that of the entry after the last one the kernel was given
+            for (i, (entry_ino, kind, name)) in entries.into_iter().enumerate().skip(offset.max(0) as usize) {
+                if reply.add(entry_ino, (i + 1) as i64, kind, name) {
+                    break;
+                }
+            }
+            reply.ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udf::LongAd;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_inode_table() {
+        let mut tree = DiscTree::default();
+        for path in ["VIDEO_TS/VIDEO_TS.IFO", "VIDEO_TS/VTS_01_1.VOB", "EXTRAS/DOCS/README.TXT"] {
+            tree.insert(path, LongAd::default(), 1, UNIX_EPOCH);
+        }
+        let inodes = InodeTable::new(&tree);
+        assert_eq!(inodes.path(ROOT_INODE), Some(""));
+        let extras = inodes.child(ROOT_INODE, "EXTRAS").unwrap();
+        let docs = inodes.child(extras, "DOCS").unwrap();
+        let readme = inodes.child(docs, "README.TXT").unwrap();
+        assert_eq!(inodes.path(readme), Some("EXTRAS/DOCS/README.TXT"));
+        let video_ts = inodes.inode("VIDEO_TS").unwrap();
+        assert_eq!(inodes.inode("VIDEO_TS/VTS_01_1.VOB"), inodes.child(video_ts, "VTS_01_1.VOB"));
+        // 1 root, 3 directories and 3 files
+        assert!(inodes.path(7).is_some());
+        assert_eq!(inodes.path(8), None);
+        assert_eq!(inodes.path(0), None);
+        assert_eq!(inodes.child(readme, "X"), None);
+    }
+}
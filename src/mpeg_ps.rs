@@ -0,0 +1,334 @@
+//! MPEG-2 program stream packs as found in DVD-Video VOBs.
+//! See ISO/IEC 13818-1 2.5.3 (pack layer) and 2.4.3.6 (PES packets).
+//! Every DVD-Video sector holds exactly one pack.
+
+use std::io;
+
+pub const PACK_SIZE: usize = 2048;
+
+pub const STREAM_ID_SYSTEM_HEADER: u8 = 0xBB;
+pub const STREAM_ID_PRIVATE_1: u8 = 0xBD;
+pub const STREAM_ID_PADDING: u8 = 0xBE;
+/// Carries the PCI and DSI navigation packets on DVDs
+pub const STREAM_ID_PRIVATE_2: u8 = 0xBF;
+
+/// The kind of elementary stream a PES packet belongs to.
+/// Private stream 1 is split by the sub-stream id in its first payload byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StreamKind {
+    /// 0xE0-0xEF
+    Video(u8),
+    /// 0xC0-0xDF
+    MpegAudio(u8),
+    /// private stream 1 sub-streams 0x80-0x87
+    Ac3(u8),
+    /// private stream 1 sub-streams 0x88-0x8F
+    Dts(u8),
+    /// private stream 1 sub-streams 0xA0-0xA7
+    Lpcm(u8),
+    /// private stream 1 sub-streams 0x20-0x3F
+    Subpicture(u8),
+    /// PCI and DSI packets
+    Navigation,
+    Other(u8),
+}
+
+/// A PES packet in a pack
+#[derive(Debug, Clone, PartialEq)]
+pub struct PesPacket<'a> {
+    pub stream_id: u8,
+    /// presentation time stamp in 90 kHz units
+    pub pts: Option<u64>,
+    /// PES_scrambling_control is set; libdvdcss clears it when it decrypts a pack
+    pub scrambled: bool,
+    /// the payload after the PES header; for private stream 1 this still starts with the sub-stream id
+    pub payload: &'a [u8],
+}
+impl PesPacket<'_> {
+    pub fn kind(&self) -> StreamKind {
+        match self.stream_id {
+            0xE0..=0xEF => StreamKind::Video(self.stream_id & 0x0F),
+            0xC0..=0xDF => StreamKind::MpegAudio(self.stream_id & 0x1F),
+            STREAM_ID_PRIVATE_2 => StreamKind::Navigation,
+            STREAM_ID_PRIVATE_1 => match self.payload.first() {
+                Some(&id @ 0x80..=0x87) => StreamKind::Ac3(id & 0x07),
+                Some(&id @ 0x88..=0x8F) => StreamKind::Dts(id & 0x07),
+                Some(&id @ 0xA0..=0xA7) => StreamKind::Lpcm(id & 0x07),
+                Some(&id @ 0x20..=0x3F) => StreamKind::Subpicture(id & 0x1F),
+                _ => StreamKind::Other(self.stream_id),
+            },
+            _ => StreamKind::Other(self.stream_id),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read a 33-bit PTS or DTS from its 5-byte encoding
+fn read_timestamp(bytes: &[u8]) -> u64 {
+    ((bytes[0] as u64 >> 1) & 0x07) << 30
+        | (bytes[1] as u64) << 22
+        | (bytes[2] as u64 >> 1) << 15
+        | (bytes[3] as u64) << 7
+        | (bytes[4] as u64 >> 1)
+}
+
+/// The system clock reference base (90 kHz) from an MPEG-2 pack header
+pub fn pack_scr(pack: &[u8]) -> Option<u64> {
+    if pack.len() < 14 || pack[..4] != [0, 0, 1, 0xBA] || pack[4] >> 6 != 0b01 {
+        return None;
+    }
+    let b = &pack[4..9];
+    Some(
+        ((b[0] as u64 >> 3) & 0x07) << 30
+            | (b[0] as u64 & 0x03) << 28
+            | (b[1] as u64) << 20
+            | (b[2] as u64 >> 3) << 15
+            | (b[2] as u64 & 0x03) << 13
+            | (b[3] as u64) << 5
+            | (b[4] as u64 >> 3),
+    )
+}
+
+/// Split a pack into its PES packets, skipping the system header and padding
+pub fn parse_pack(pack: &[u8]) -> io::Result<Vec<PesPacket<'_>>> {
+    if pack.len() < 14 || pack[..4] != [0, 0, 1, 0xBA] {
+        return Err(invalid_data("missing pack start code".to_string()));
+    }
+    if pack[4] >> 6 != 0b01 {
+        return Err(invalid_data("not an MPEG-2 pack header".to_string()));
+    }
+    let mut pos = 14 + (pack[13] & 0x07) as usize;
+    let mut packets = vec![];
+    while pos + 6 <= pack.len() {
+        if pack[pos..pos + 3] != [0, 0, 1] {
+            return Err(invalid_data(format!("missing start code at offset {} of pack", pos)));
+        }
+        let stream_id = pack[pos + 3];
+        let length = u16::from_be_bytes([pack[pos + 4], pack[pos + 5]]) as usize;
+        let end = pos + 6 + length;
+        let packet = pack
+            .get(pos + 6..end)
+            .ok_or_else(|| invalid_data(format!("packet of stream {:#x} extends past the pack", stream_id)))?;
+        pos = end;
+        match stream_id {
+            STREAM_ID_SYSTEM_HEADER | STREAM_ID_PADDING => continue,
+            // private stream 2 has no PES header extension
+            STREAM_ID_PRIVATE_2 => packets.push(PesPacket {
+                stream_id,
+                pts: None,
+                scrambled: false,
+                payload: packet,
+            }),
+            _ => {
+                if packet.len() < 3 || packet[0] >> 6 != 0b10 {
+                    return Err(invalid_data(format!("invalid PES header of stream {:#x}", stream_id)));
+                }
+                let header_length = packet[2] as usize;
+                let payload = packet
+                    .get(3 + header_length..)
+                    .ok_or_else(|| invalid_data(format!("PES header of stream {:#x} is too long", stream_id)))?;
+                let pts = if packet[1] & 0x80 != 0 && header_length >= 5 {
+                    Some(read_timestamp(&packet[3..8]))
+                } else {
+                    None
+                };
+                packets.push(PesPacket {
+                    stream_id,
+                    pts,
+                    scrambled: packet[0] & 0x30 != 0,
+                    payload,
+                });
+            }
+        }
+    }
+    Ok(packets)
+}
+
+/// The sector of the navigation pack as recorded in its PCI packet
+pub fn parse_pci_lbn(pci_payload: &[u8]) -> Option<u32> {
+    if pci_payload.first() != Some(&0x00) || pci_payload.len() < 5 {
+        return None;
+    }
+    Some(u32::from_be_bytes(pci_payload[1..5].try_into().unwrap()))
+}
+
+/// Start and end presentation time of a VOBU from the PCI packet of its navigation pack
+pub fn parse_pci_times(pci_payload: &[u8]) -> Option<(u64, u64)> {
+    // the payload starts with the sub-stream id 0x00 for PCI
+    if pci_payload.first() != Some(&0x00) || pci_payload.len() < 21 {
+        return None;
+    }
+    let pci = &pci_payload[1..];
+    let start = u32::from_be_bytes(pci[12..16].try_into().unwrap());
+    let end = u32::from_be_bytes(pci[16..20].try_into().unwrap());
+    Some((start as u64, end as u64))
+}
+
+/// Fields of the DSI packet of a navigation pack that locate the next VOBU or ILVU.
+/// Addresses are in sectors relative to the navigation pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataSearchInfo {
+    /// sector of this navigation pack, relative to the start of the VOBs of its domain
+    pub nav_pack_lbn: u32,
+    /// last sector of this VOBU
+    pub vobu_end: u32,
+    /// VOB id number of this VOBU
+    pub vob_id: u16,
+    /// seamless playback category; see the ILVU_* constants
+    pub category: u16,
+    /// last sector of this interleaved unit
+    pub ilvu_end: u32,
+    /// start of the next interleaved unit of the same angle
+    pub next_ilvu_start: u32,
+}
+impl DataSearchInfo {
+    /// the VOBU is part of an interleaved unit
+    pub const ILVU: u16 = 0x4000;
+    /// the VOBU is the first of its interleaved unit
+    pub const ILVU_START: u16 = 0x2000;
+    /// next_ilvu_start of the last interleaved unit
+    pub const NO_NEXT_ILVU: u32 = 0x7FFF_FFFF;
+
+    pub fn is_ilvu_start(&self) -> bool {
+        self.category & (Self::ILVU | Self::ILVU_START) == Self::ILVU | Self::ILVU_START
+    }
+}
+
+/// Parse the DSI packet of a navigation pack
+pub fn parse_dsi(dsi_payload: &[u8]) -> Option<DataSearchInfo> {
+    // the payload starts with the sub-stream id 0x01 for DSI
+    if dsi_payload.first() != Some(&0x01) || dsi_payload.len() < 45 {
+        return None;
+    }
+    let dsi = &dsi_payload[1..];
+    let u32_at = |offset: usize| u32::from_be_bytes(dsi[offset..offset + 4].try_into().unwrap());
+    Some(DataSearchInfo {
+        nav_pack_lbn: u32_at(4),
+        vobu_end: u32_at(8),
+        vob_id: u16::from_be_bytes([dsi[24], dsi[25]]),
+        category: u16::from_be_bytes([dsi[32], dsi[33]]),
+        ilvu_end: u32_at(34),
+        next_ilvu_start: u32_at(38),
+    })
+}
+
+/// Whether a pack that was read without decrypting it holds a CSS scrambled PES packet
+pub fn is_scrambled_pack(pack: &[u8]) -> bool {
+    parse_pack(pack).is_ok_and(|packets| packets.iter().any(|packet| packet.scrambled))
+}
+
+/// The DSI of a navigation pack, if pack is one
+pub fn navigation_pack_dsi(pack: &[u8]) -> Option<DataSearchInfo> {
+    parse_pack(pack)
+        .ok()?
+        .iter()
+        .filter(|packet| packet.stream_id == STREAM_ID_PRIVATE_2)
+        .find_map(|packet| parse_dsi(packet.payload))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn write_timestamp(pts: u64) -> [u8; 5] {
+        [
+            0x21 | ((pts >> 29) & 0x0E) as u8,
+            (pts >> 22) as u8,
+            0x01 | ((pts >> 14) & 0xFE) as u8,
+            (pts >> 7) as u8,
+            0x01 | ((pts << 1) & 0xFE) as u8,
+        ]
+    }
+
+    /// A PES packet with an MPEG-2 header
+    pub(crate) fn pes(stream_id: u8, pts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+        let header: Vec<u8> = match pts {
+            Some(pts) => [&[0x81, 0x80, 5][..], &write_timestamp(pts)].concat(),
+            None => vec![0x81, 0x00, 0],
+        };
+        let length = (header.len() + payload.len()) as u16;
+        [&[0, 0, 1, stream_id][..], &length.to_be_bytes(), &header, payload].concat()
+    }
+
+    /// A 2048-byte pack holding the given packets, padded with a padding packet
+    pub(crate) fn pack(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut pack = vec![0, 0, 1, 0xBA, 0x44, 0, 4, 0, 4, 1, 1, 0x89, 0xC3, 0xF8];
+        for packet in packets {
+            pack.extend_from_slice(packet);
+        }
+        let padding = PACK_SIZE - pack.len() - 6;
+        pack.extend_from_slice(&[0, 0, 1, STREAM_ID_PADDING]);
+        pack.extend_from_slice(&(padding as u16).to_be_bytes());
+        pack.resize(PACK_SIZE, 0xFF);
+        pack
+    }
+
+    /// Set the SCR base of a pack header, leaving the SCR extension 0
+    pub(crate) fn set_scr(pack: &mut [u8], scr: u64) {
+        pack[4] = 0x44 | (((scr >> 30) & 0x07) << 3) as u8 | ((scr >> 28) & 0x03) as u8;
+        pack[5] = (scr >> 20) as u8;
+        pack[6] = (((scr >> 15) & 0x1F) << 3) as u8 | 0x04 | ((scr >> 13) & 0x03) as u8;
+        pack[7] = (scr >> 5) as u8;
+        pack[8] = ((scr & 0x1F) << 3) as u8 | 0x04;
+    }
+
+    /// A navigation pack with PCI and DSI packets of their usual sizes
+    pub(crate) fn nav_pack(lbn: u32, vobu_end: u32, category: u16, vob_id: u16) -> Vec<u8> {
+        let mut pci = vec![0u8; 980];
+        pci[1..5].copy_from_slice(&lbn.to_be_bytes());
+        let mut dsi = vec![0u8; 1018];
+        dsi[0] = 0x01;
+        dsi[1 + 4..1 + 8].copy_from_slice(&lbn.to_be_bytes());
+        dsi[1 + 8..1 + 12].copy_from_slice(&vobu_end.to_be_bytes());
+        dsi[1 + 24..1 + 26].copy_from_slice(&vob_id.to_be_bytes());
+        dsi[1 + 32..1 + 34].copy_from_slice(&category.to_be_bytes());
+        let packet = |payload: &[u8]| {
+            [&[0, 0, 1, STREAM_ID_PRIVATE_2][..], &(payload.len() as u16).to_be_bytes(), payload].concat()
+        };
+        pack(&[packet(&pci), packet(&dsi)])
+    }
+
+    #[test]
+    fn test_parse_pack() {
+        let pack = pack(&[
+            pes(0xE0, Some(0x1_2345_6789), &[0, 0, 1, 0xB3]),
+            pes(STREAM_ID_PRIVATE_1, None, &[0x81, 1, 0, 1, 0x0B, 0x77]),
+        ]);
+        let packets = parse_pack(&pack).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].kind(), StreamKind::Video(0));
+        assert_eq!(packets[0].pts, Some(0x1_2345_6789));
+        assert_eq!(packets[0].payload, &[0, 0, 1, 0xB3]);
+        assert_eq!(packets[1].kind(), StreamKind::Ac3(1));
+        assert_eq!(packets[1].pts, None);
+        assert!(!packets[1].scrambled);
+        let mut pack = pack;
+        set_scr(&mut pack, 0x1_2345_6789);
+        assert_eq!(pack_scr(&pack), Some(0x1_2345_6789));
+        assert!(parse_pack(&[0u8; PACK_SIZE]).is_err());
+        assert!(!is_scrambled_pack(&pack));
+        // the PES scrambling control bits of the first packet
+        pack[0x14] |= 0x30;
+        assert!(is_scrambled_pack(&pack));
+    }
+
+    #[test]
+    fn test_navigation_pack_dsi() {
+        let mut dsi = vec![0u8; 1018];
+        dsi[0] = 0x01;
+        dsi[1 + 8..1 + 12].copy_from_slice(&5u32.to_be_bytes());
+        dsi[1 + 32..1 + 34].copy_from_slice(&0x6000u16.to_be_bytes());
+        dsi[1 + 34..1 + 38].copy_from_slice(&2u32.to_be_bytes());
+        dsi[1 + 38..1 + 42].copy_from_slice(&6u32.to_be_bytes());
+        let mut packet = vec![0, 0, 1, STREAM_ID_PRIVATE_2];
+        packet.extend_from_slice(&(dsi.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&dsi);
+        let info = navigation_pack_dsi(&pack(&[packet])).unwrap();
+        assert_eq!(info.vobu_end, 5);
+        assert!(info.is_ilvu_start());
+        assert_eq!((info.ilvu_end, info.next_ilvu_start), (2, 6));
+    }
+}
@@ -0,0 +1,290 @@
+//! Consistency checks of the navigation packs of a VOB while it is copied,
+//! to find VOBUs that are corrupt or were not decrypted instead of finding out at playback.
+//! Navigation pack fields follow http://dvd.sourceforge.net/dvdinfo/pci_pkt.html
+//! and http://dvd.sourceforge.net/dvdinfo/dsi_pkt.html
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use log::debug;
+
+use crate::mpeg_ps::{
+    pack_scr, parse_dsi, parse_pack, parse_pci_lbn, parse_pci_times, DataSearchInfo, StreamKind, PACK_SIZE,
+    STREAM_ID_PRIVATE_2,
+};
+
+/// A problem found in a VOB
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NavProblem {
+    /// sector relative to the start of the checked stream
+    pub sector: u64,
+    pub message: String,
+}
+
+/// Checks a VOB pack by pack: that every pack parses and is not still scrambled,
+/// that navigation pack addresses agree with where the packs are and with the
+/// previous VOBU's end address, that SCRs increase within a VOB, and that each
+/// VOBU's video starts with a start code (which it does not if the title key is wrong)
+#[derive(Debug, Default)]
+pub struct NavPackChecker {
+    /// index of the next pack
+    sector: u64,
+    nav_packs: u64,
+    /// recorded navigation pack address minus its sector in the stream
+    lbn_base: Option<i64>,
+    /// where the next navigation pack should be according to the previous DSI,
+    /// unless the previous VOBU was part of an interleaved unit
+    expected_nav_pack: Option<u64>,
+    /// the VOB id of the current VOBU
+    vob_id: Option<u16>,
+    /// last SCR of each VOB id
+    last_scr: BTreeMap<u16, u64>,
+    /// whether the first video packet of the current VOBU is still to come
+    awaiting_video: bool,
+    /// problems that can occur on every pack are reported once per VOBU
+    reported_invalid_pack: bool,
+    reported_scrambled: bool,
+    reported_scr: bool,
+    problems: Vec<NavProblem>,
+}
+impl NavPackChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn problem(&mut self, sector: u64, message: String) {
+        debug!("NavPackChecker: sector {}: {}", sector, message);
+        self.problems.push(NavProblem { sector, message });
+    }
+
+    pub fn check_pack(&mut self, pack: &[u8]) {
+        let sector = self.sector;
+        self.sector += 1;
+        let packets = match parse_pack(pack) {
+            Ok(packets) => packets,
+            Err(e) => {
+                // the VOBU's first video packet may be in this pack
+                self.awaiting_video = false;
+                if !self.reported_invalid_pack {
+                    self.reported_invalid_pack = true;
+                    self.problem(sector, format!("invalid pack: {}", e));
+                }
+                return;
+            }
+        };
+        let pci = packets
+            .iter()
+            .find(|packet| packet.stream_id == STREAM_ID_PRIVATE_2 && packet.payload.first() == Some(&0));
+        let dsi = packets
+            .iter()
+            .filter(|packet| packet.stream_id == STREAM_ID_PRIVATE_2)
+            .find_map(|packet| parse_dsi(packet.payload));
+        if let (Some(pci), Some(dsi)) = (pci, dsi) {
+            self.check_nav_pack(sector, pci.payload, &dsi);
+        }
+        if let (Some(scr), Some(vob_id)) = (pack_scr(pack), self.vob_id) {
+            if let Some(&last) = self.last_scr.get(&vob_id) {
+                if scr < last && !self.reported_scr {
+                    self.reported_scr = true;
+                    self.problem(sector, format!("SCR {} is before the previous pack's {} in VOB {}", scr, last, vob_id));
+                }
+            }
+            self.last_scr.insert(vob_id, scr);
+        }
+        for packet in &packets {
+            if packet.scrambled && !self.reported_scrambled {
+                self.reported_scrambled = true;
+                self.problem(sector, "pack is still scrambled; it was not decrypted".to_string());
+            }
+            if self.awaiting_video && matches!(packet.kind(), StreamKind::Video(_)) {
+                self.awaiting_video = false;
+                if !packet.payload.starts_with(&[0, 0, 1]) {
+                    self.problem(
+                        sector,
+                        "the VOBU's video does not start with a start code; it may be decrypted with the wrong key"
+                            .to_string(),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_nav_pack(&mut self, sector: u64, pci: &[u8], dsi: &DataSearchInfo) {
+        self.nav_packs += 1;
+        self.reported_invalid_pack = false;
+        self.reported_scrambled = false;
+        self.reported_scr = false;
+        self.awaiting_video = true;
+        self.vob_id = Some(dsi.vob_id);
+        if parse_pci_lbn(pci) != Some(dsi.nav_pack_lbn) {
+            self.problem(
+                sector,
+                format!(
+                    "PCI and DSI disagree on the navigation pack's address ({:?} and {})",
+                    parse_pci_lbn(pci),
+                    dsi.nav_pack_lbn
+                ),
+            );
+        }
+        let base = dsi.nav_pack_lbn as i64 - sector as i64;
+        match self.lbn_base {
+            Some(previous) if previous != base => {
+                self.problem(
+                    sector,
+                    format!(
+                        "navigation pack records address {} but the previous ones imply {}; sectors are missing or misplaced",
+                        dsi.nav_pack_lbn,
+                        sector as i64 + previous
+                    ),
+                );
+                self.lbn_base = Some(base);
+            }
+            Some(_) => {}
+            None => self.lbn_base = Some(base),
+        }
+        if let Some(expected) = self.expected_nav_pack {
+            if expected != sector {
+                self.problem(
+                    sector,
+                    format!("the previous VOBU should have ended before sector {} but the next one starts here", expected),
+                );
+            }
+        }
+        self.expected_nav_pack = if dsi.category & DataSearchInfo::ILVU != 0 {
+            None
+        } else {
+            Some(sector + dsi.vobu_end as u64 + 1)
+        };
+        if let Some((start, end)) = parse_pci_times(pci) {
+            if start > end {
+                self.problem(sector, format!("VOBU ends (PTS {}) before it starts (PTS {})", end, start));
+            }
+        }
+    }
+
+    /// Number of packs checked so far
+    pub fn packs(&self) -> u64 {
+        self.sector
+    }
+
+    pub fn nav_packs(&self) -> u64 {
+        self.nav_packs
+    }
+
+    pub fn finish(self) -> Vec<NavProblem> {
+        self.problems
+    }
+}
+
+/// Passes everything written through to inner while checking it pack by pack
+pub struct NavCheckingWriter<W: Write> {
+    inner: W,
+    checker: NavPackChecker,
+    pending: Vec<u8>,
+}
+impl<W: Write> NavCheckingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            checker: NavPackChecker::new(),
+            pending: Vec::with_capacity(PACK_SIZE),
+        }
+    }
+
+    /// The inner writer and the checker; a partial pack at the end is not checked
+    pub fn finish(self) -> (W, NavPackChecker) {
+        (self.inner, self.checker)
+    }
+}
+impl<W: Write> Write for NavCheckingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut data = &buf[..written];
+        while !data.is_empty() {
+            if self.pending.is_empty() && data.len() >= PACK_SIZE {
+                self.checker.check_pack(&data[..PACK_SIZE]);
+                data = &data[PACK_SIZE..];
+                continue;
+            }
+            let take = (PACK_SIZE - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() == PACK_SIZE {
+                self.checker.check_pack(&self.pending);
+                self.pending.clear();
+            }
+        }
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpeg_ps::tests::{nav_pack, pack, pes, set_scr};
+
+    fn with_scr(mut pack: Vec<u8>, scr: u64) -> Vec<u8> {
+        set_scr(&mut pack, scr);
+        pack
+    }
+
+    fn video_pack(scr: u64, payload: &[u8]) -> Vec<u8> {
+        with_scr(pack(&[pes(0xE0, None, payload)]), scr)
+    }
+
+    /// Two VOBUs of a nav pack and two video packs each
+    fn vob() -> Vec<Vec<u8>> {
+        vec![
+            with_scr(nav_pack(100, 2, 0, 1), 5),
+            video_pack(10, &[0, 0, 1, 0xB3]),
+            video_pack(20, &[1, 2, 3]),
+            with_scr(nav_pack(103, 2, 0, 1), 25),
+            video_pack(30, &[0, 0, 1, 0xB3]),
+            video_pack(40, &[1, 2, 3]),
+        ]
+    }
+
+    fn check(packs: &[Vec<u8>]) -> Vec<NavProblem> {
+        let mut writer = NavCheckingWriter::new(vec![]);
+        // write in pieces that do not line up with packs
+        for chunk in packs.concat().chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        let (output, checker) = writer.finish();
+        assert_eq!(output, packs.concat());
+        assert_eq!(checker.packs(), packs.len() as u64);
+        checker.finish()
+    }
+
+    #[test]
+    fn test_valid_vob() {
+        assert_eq!(check(&vob()), vec![]);
+    }
+
+    #[test]
+    fn test_problems() {
+        let sectors = |problems: Vec<NavProblem>| problems.iter().map(|p| p.sector).collect::<Vec<_>>();
+        // a lost sector shifts the second VOBU
+        let mut packs = vob();
+        packs.remove(2);
+        assert_eq!(sectors(check(&packs)), vec![2, 2]);
+        // zeros from an unreadable sector
+        let mut packs = vob();
+        packs[4] = vec![0; PACK_SIZE];
+        assert_eq!(sectors(check(&packs)), vec![4]);
+        // garbage video after a wrong key, and a still-scrambled pack
+        let mut packs = vob();
+        packs[1] = video_pack(10, &[0x12, 0x34]);
+        packs[5][14 + 6] |= 0x10;
+        assert_eq!(sectors(check(&packs)), vec![1, 5]);
+        // SCR going backwards
+        let mut packs = vob();
+        set_scr(&mut packs[5], 5);
+        assert_eq!(sectors(check(&packs)), vec![5]);
+    }
+}
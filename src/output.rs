@@ -0,0 +1,312 @@
+//! Where the copied files are written. Files are written through an [`OutputSink`] so
+//! that a copy can go to a local directory or (with the s3 feature) straight to object
+//! storage. Either way a file only appears under its name once it is complete.
+
+use std::{
+    fs::{create_dir_all, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{s3::S3Location, sftp::SftpLocation};
+
+/// The output for --output: a local directory, `s3://bucket/prefix` with the s3 feature
+/// or `sftp://host/path` with the sftp feature
+pub fn open_output(output: &Path) -> io::Result<Box<dyn OutputSink>> {
+    if let Some(location) = output.to_str().and_then(S3Location::parse) {
+        return open_s3_output(location);
+    }
+    if let Some(location) = output.to_str().and_then(SftpLocation::parse) {
+        return open_sftp_output(location);
+    }
+    Ok(Box::new(LocalOutput::new(output)))
+}
+
+#[cfg(feature = "s3")]
+fn open_s3_output(location: S3Location) -> io::Result<Box<dyn OutputSink>> {
+    Ok(Box::new(crate::s3::S3Output::new(location)?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_s3_output(location: S3Location) -> io::Result<Box<dyn OutputSink>> {
+    Err(io::Error::other(format!(
+        "writing to s3://{} needs dvdromcopy built with --features s3",
+        location.bucket
+    )))
+}
+
+#[cfg(feature = "sftp")]
+fn open_sftp_output(location: SftpLocation) -> io::Result<Box<dyn OutputSink>> {
+    Ok(Box::new(crate::sftp::SftpOutput::new(location)?))
+}
+
+#[cfg(not(feature = "sftp"))]
+fn open_sftp_output(location: SftpLocation) -> io::Result<Box<dyn OutputSink>> {
+    Err(io::Error::other(format!(
+        "writing to sftp://{} needs dvdromcopy built with --features sftp",
+        location.host
+    )))
+}
+
+/// Suffix of local output files that are still being written or that failed
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+pub fn partial_path(output_path: &Path) -> PathBuf {
+    let mut file_name = output_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(PARTIAL_SUFFIX);
+    output_path.with_file_name(file_name)
+}
+
+/// A file being written. If it is dropped without finish(), it does not appear
+/// under its name (a local file is left at `<name>.partial`). It is Send so that the copy can
+/// write it on a thread of its own
+pub trait OutputFile: Write + Send {
+    /// Make the complete file appear under its name
+    fn finish(self: Box<Self>) -> io::Result<()>;
+    /// The length and bytes of what an earlier, interrupted copy wrote to the file, which
+    /// write() continues after. None unless the file came from OutputSink::resume
+    fn resumed(&self) -> io::Result<Option<(u64, Box<dyn Read>)>> {
+        Ok(None)
+    }
+    /// The local file that is being written, positioned where write() continues, for copying
+    /// into it without write(). None unless the file is written as it is
+    fn local_file(&self) -> Option<&File> {
+        None
+    }
+}
+
+/// A destination for files by '/'-separated path. It is Sync so that its files can be written
+/// on other threads
+pub trait OutputSink: Sync {
+    /// Whether a complete file already exists at path
+    fn exists(&self, path: &str) -> io::Result<bool>;
+    fn create(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>>;
+    /// Like create, but continuing the partial file of an interrupted copy if there is one
+    /// and the sink can append to it
+    fn resume(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+        self.create(path)
+    }
+    /// The local directory of the files, for the checks that read back what was written
+    fn local_dir(&self) -> Option<&Path>;
+    /// The location of path for messages, e.g. a local path or a URL
+    fn describe(&self, path: &str) -> String;
+}
+
+/// Files in a local directory
+#[derive(Debug, Clone)]
+pub struct LocalOutput {
+    dir: PathBuf,
+}
+impl LocalOutput {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf() }
+    }
+}
+impl OutputSink for LocalOutput {
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        long_path(&self.dir.join(inside_output(path)?))?.try_exists()
+    }
+    fn create(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+        let output_path = long_path(&self.dir.join(inside_output(path)?))?;
+        if let Some(parent) = output_path.parent() {
+            create_dir_all(parent)?;
+        }
+        let partial_output_path = partial_path(&output_path);
+        let file = File::create_new(&partial_output_path)?;
+        Ok(Box::new(LocalFile {
+            file,
+            partial_output_path,
+            output_path,
+            resume_size: 0,
+        }))
+    }
+    fn resume(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+        let output_path = long_path(&self.dir.join(inside_output(path)?))?;
+        if let Some(parent) = output_path.parent() {
+            create_dir_all(parent)?;
+        }
+        let partial_output_path = partial_path(&output_path);
+        // not opened for appending, which copy_file_range refuses to write to
+        let mut file = File::options().write(true).create(true).truncate(false).open(&partial_output_path)?;
+        let resume_size = file.seek(SeekFrom::End(0))?;
+        Ok(Box::new(LocalFile {
+            file,
+            partial_output_path,
+            output_path,
+            resume_size,
+        }))
+    }
+    fn local_dir(&self) -> Option<&Path> {
+        Some(&self.dir)
+    }
+    fn describe(&self, path: &str) -> String {
+        self.dir.join(path).display().to_string()
+    }
+}
+
+/// path as a relative path below the output directory, or an error if it could lead outside
+/// it, which only the names on a hostile disc would
+fn inside_output(path: &str) -> io::Result<&Path> {
+    let relative = Path::new(path);
+    if relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        Ok(relative)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a path inside the output directory", path),
+        ))
+    }
+}
+
+/// On Windows, the extended-length form of a path (`\\?\C:\...`), which is not limited to
+/// MAX_PATH (260 characters) as deeply nested discs need; elsewhere the path itself
+#[cfg(windows)]
+fn long_path(path: &Path) -> io::Result<PathBuf> {
+    // absolute() also turns the '/'s of disc paths into '\', which extended-length paths need
+    let absolute = std::path::absolute(path)?;
+    Ok(match absolute.to_str() {
+        Some(absolute) => PathBuf::from(extended_length_path(absolute)),
+        None => absolute,
+    })
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> io::Result<PathBuf> {
+    Ok(path.to_path_buf())
+}
+
+/// The extended-length form of an absolute Windows path
+#[cfg_attr(not(windows), allow(dead_code))]
+fn extended_length_path(absolute: &str) -> String {
+    if absolute.starts_with(r"\\?\") || absolute.starts_with(r"\\.\") {
+        absolute.to_string()
+    } else if let Some(unc) = absolute.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", unc)
+    } else {
+        format!(r"\\?\{}", absolute)
+    }
+}
+
+/// Written to `<name>.partial` and renamed when finished
+struct LocalFile {
+    file: File,
+    partial_output_path: PathBuf,
+    output_path: PathBuf,
+    /// the length of the partial file when it was resumed
+    resume_size: u64,
+}
+impl Write for LocalFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+impl OutputFile for LocalFile {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.file.sync_all()?;
+        std::fs::rename(&self.partial_output_path, &self.output_path)
+    }
+    fn resumed(&self) -> io::Result<Option<(u64, Box<dyn Read>)>> {
+        if self.resume_size == 0 {
+            return Ok(None);
+        }
+        let partial = File::open(&self.partial_output_path)?.take(self.resume_size);
+        Ok(Some((self.resume_size, Box::new(partial))))
+    }
+    fn local_file(&self) -> Option<&File> {
+        Some(&self.file)
+    }
+}
+
+/// The files of another sink below a directory of it
+pub struct SubdirectoryOutput<'a> {
+    parent: &'a dyn OutputSink,
+    prefix: String,
+    local_dir: Option<PathBuf>,
+}
+impl<'a> SubdirectoryOutput<'a> {
+    pub fn new(parent: &'a dyn OutputSink, directory: &str) -> Self {
+        Self {
+            parent,
+            prefix: directory.trim_matches('/').to_string(),
+            local_dir: parent.local_dir().map(|dir| dir.join(directory)),
+        }
+    }
+    fn parent_path(&self, path: &str) -> String {
+        format!("{}/{}", self.prefix, path.trim_start_matches('/'))
+    }
+}
+impl OutputSink for SubdirectoryOutput<'_> {
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        self.parent.exists(&self.parent_path(path))
+    }
+    fn create(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+        self.parent.create(&self.parent_path(path))
+    }
+    fn resume(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+        self.parent.resume(&self.parent_path(path))
+    }
+    fn local_dir(&self) -> Option<&Path> {
+        self.local_dir.as_deref()
+    }
+    fn describe(&self, path: &str) -> String {
+        self.parent.describe(&self.parent_path(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_output() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-output-test-{}", std::process::id()));
+        let root = LocalOutput::new(&dir);
+        let output = SubdirectoryOutput::new(&root, "Disc");
+        assert_eq!(output.local_dir(), Some(dir.join("Disc").as_path()));
+
+        let mut file = output.create("VIDEO_TS/VIDEO_TS.IFO").unwrap();
+        file.write_all(b"IFO").unwrap();
+        assert!(dir.join("Disc/VIDEO_TS/VIDEO_TS.IFO.partial").exists());
+        assert!(!output.exists("VIDEO_TS/VIDEO_TS.IFO").unwrap());
+        file.finish().unwrap();
+        assert!(output.exists("VIDEO_TS/VIDEO_TS.IFO").unwrap());
+        assert_eq!(std::fs::read(dir.join("Disc/VIDEO_TS/VIDEO_TS.IFO")).unwrap(), b"IFO");
+
+        // an abandoned file stays partial
+        let mut file = output.create("VIDEO_TS/VTS_01_1.VOB").unwrap();
+        file.write_all(b"VOB").unwrap();
+        drop(file);
+        assert!(!output.exists("VIDEO_TS/VTS_01_1.VOB").unwrap());
+        assert!(output.create("VIDEO_TS/VTS_01_1.VOB").is_err());
+        // unless it is resumed
+        let mut file = output.resume("VIDEO_TS/VTS_01_1.VOB").unwrap();
+        let (resume_size, mut resumed) = file.resumed().unwrap().unwrap();
+        assert_eq!(resume_size, 3);
+        let mut contents = vec![];
+        resumed.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"VOB");
+        file.write_all(b" PACKS").unwrap();
+        file.finish().unwrap();
+        assert_eq!(std::fs::read(dir.join("Disc/VIDEO_TS/VTS_01_1.VOB")).unwrap(), b"VOB PACKS");
+        assert!(output.resume("VIDEO_TS/VTS_01_2.VOB").unwrap().resumed().unwrap().is_none());
+
+        // nothing is written outside the directory
+        for path in ["../escaped", "VIDEO_TS/../../escaped", "/tmp/escaped"] {
+            let result = root.create(path);
+            assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput), "{}", path);
+        }
+        assert!(!dir.with_file_name("escaped.partial").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extended_length_path() {
+        assert_eq!(extended_length_path(r"C:\Movies\Disc"), r"\\?\C:\Movies\Disc");
+        assert_eq!(extended_length_path(r"\\nas\dvd\Disc"), r"\\?\UNC\nas\dvd\Disc");
+        assert_eq!(extended_length_path(r"\\?\C:\Movies"), r"\\?\C:\Movies");
+    }
+}
@@ -0,0 +1,208 @@
+//! The partitions of a whole-disk image, for reading a UDF file system that is in one of them
+//! rather than at the start of the image: those of a GUID Partition Table (UEFI 2.10 5.3), or
+//! else the primary partitions of a Master Boot Record. Extended MBR partitions are not
+//! followed.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::udf::{AnchorVolumeDescriptorPointer, DescriptorTag};
+
+/// A partition, with its position in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// 1-based, in the order of the table, as Linux numbers them
+    pub number: u32,
+    pub start: u64,
+    pub length: u64,
+    /// the GPT partition name, or the MBR partition type
+    pub description: String,
+}
+
+/// MBR partition type of the single partition that covers a disk with a GPT
+const GPT_PROTECTIVE: u8 = 0xEE;
+/// More than any partitioning tool creates, to bound what a corrupt header makes us read
+const MAX_GPT_ENTRIES_SIZE: u64 = 1024 * 1024;
+
+/// The partitions of the image, or none if it has no MBR (as disc images do not)
+pub fn read_partitions<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<Partition>> {
+    let mut mbr = [0u8; 512];
+    reader.seek(SeekFrom::Start(0))?;
+    if let Err(e) = reader.read_exact(&mut mbr) {
+        return match e.kind() {
+            io::ErrorKind::UnexpectedEof => Ok(vec![]),
+            _ => Err(e),
+        };
+    }
+    if mbr[510..512] != [0x55, 0xAA] {
+        return Ok(vec![]);
+    }
+    let entries: Vec<&[u8]> = mbr[446..510].chunks_exact(16).collect();
+    if entries.iter().any(|entry| entry[4] == GPT_PROTECTIVE) {
+        // the GPT header is in the second logical block, which is 512 or 4096 bytes
+        for block_size in [512, 4096] {
+            if let Some(partitions) = read_gpt(reader, block_size)? {
+                return Ok(partitions);
+            }
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the MBR is the protective MBR of a GPT, but there is no GPT header",
+        ));
+    }
+    let mut partitions = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        let partition_type = entry[4];
+        let start_block = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+        let blocks = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+        if partition_type == 0 || blocks == 0 {
+            continue;
+        }
+        partitions.push(Partition {
+            number: i as u32 + 1,
+            start: start_block as u64 * 512,
+            length: blocks as u64 * 512,
+            description: format!("type 0x{:02X}", partition_type),
+        });
+    }
+    Ok(partitions)
+}
+
+/// Whether a UDF file system starting offset bytes into the image has its Anchor Volume
+/// Descriptor Pointer at sector 256, as discs do. Unlike reading the volume structures, this
+/// neither logs nor counts a descriptor that is not there.
+pub fn has_udf_anchor<R: Read + Seek>(reader: &mut R, offset: u64) -> io::Result<bool> {
+    const SECTOR_SIZE: u64 = 2048;
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    reader.seek(SeekFrom::Start(offset.saturating_add(256 * SECTOR_SIZE)))?;
+    if reader.read_exact(&mut sector).is_err() {
+        return Ok(false);
+    }
+    let tag = DescriptorTag::read(&sector);
+    Ok(tag.tag_identifier == AnchorVolumeDescriptorPointer::TAG_IDENTIFIER
+        && tag.tag_checksum == DescriptorTag::checksum(&sector)
+        && tag.tag_location == 256)
+}
+
+/// The partitions of the GPT whose header is at block 1, or None if there is no header there
+fn read_gpt<R: Read + Seek>(reader: &mut R, block_size: u64) -> io::Result<Option<Vec<Partition>>> {
+    let mut header = [0u8; 92];
+    reader.seek(SeekFrom::Start(block_size))?;
+    if reader.read_exact(&mut header).is_err() || &header[0..8] != b"EFI PART" {
+        return Ok(None);
+    }
+    let entries_block = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let entry_count = u32::from_le_bytes(header[80..84].try_into().unwrap()) as u64;
+    let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as u64;
+    if entry_size < 128 || entry_count * entry_size > MAX_GPT_ENTRIES_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("GPT with {} partition entries of {} bytes", entry_count, entry_size),
+        ));
+    }
+    let mut entries = vec![0u8; (entry_count * entry_size) as usize];
+    let entries_start = entries_block.checked_mul(block_size).ok_or(io::ErrorKind::InvalidData)?;
+    reader.seek(SeekFrom::Start(entries_start))?;
+    reader.read_exact(&mut entries)?;
+    let mut partitions = vec![];
+    for (i, entry) in entries.chunks_exact(entry_size as usize).enumerate() {
+        // an unused entry has a zero partition type GUID
+        if entry[0..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first_block = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+        let last_block = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+        let name: Vec<u16> = (entry[56..128].chunks_exact(2))
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        let (Some(start), Some(length)) = (
+            first_block.checked_mul(block_size),
+            (last_block.checked_sub(first_block)).and_then(|blocks| (blocks + 1).checked_mul(block_size)),
+        ) else {
+            continue;
+        };
+        partitions.push(Partition {
+            number: i as u32 + 1,
+            start,
+            length,
+            description: String::from_utf16_lossy(&name),
+        });
+    }
+    Ok(Some(partitions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mbr(entries: &[(u8, u32, u32)]) -> Vec<u8> {
+        let mut image = vec![0u8; 512];
+        for (i, &(partition_type, start, blocks)) in entries.iter().enumerate() {
+            let entry = &mut image[446 + 16 * i..446 + 16 * (i + 1)];
+            entry[4] = partition_type;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&blocks.to_le_bytes());
+        }
+        image[510..512].copy_from_slice(&[0x55, 0xAA]);
+        image
+    }
+
+    #[test]
+    fn test_read_mbr_partitions() {
+        assert_eq!(read_partitions(&mut Cursor::new(vec![0u8; 32768])).unwrap(), vec![]);
+        assert_eq!(read_partitions(&mut Cursor::new(vec![0u8; 100])).unwrap(), vec![]);
+        let image = mbr(&[(0x83, 2048, 4096), (0, 0, 0), (0x07, 8192, 100)]);
+        assert_eq!(
+            read_partitions(&mut Cursor::new(image)).unwrap(),
+            vec![
+                Partition {
+                    number: 1,
+                    start: 1024 * 1024,
+                    length: 2 * 1024 * 1024,
+                    description: "type 0x83".to_string()
+                },
+                Partition {
+                    number: 3,
+                    start: 8192 * 512,
+                    length: 100 * 512,
+                    description: "type 0x07".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_gpt_partitions() {
+        let mut image = mbr(&[(GPT_PROTECTIVE, 1, 0xFFFF_FFFF)]);
+        image.resize(512 * 34, 0);
+        let header = &mut image[512..604];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        // the second entry, as the first is unused
+        let entry = &mut image[1024 + 128..1024 + 256];
+        entry[0..16].copy_from_slice(&[0xA2; 16]);
+        entry[32..40].copy_from_slice(&2048u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&4095u64.to_le_bytes());
+        for (i, c) in "DVD".encode_utf16().enumerate() {
+            entry[56 + 2 * i..58 + 2 * i].copy_from_slice(&c.to_le_bytes());
+        }
+        assert_eq!(
+            read_partitions(&mut Cursor::new(&image)).unwrap(),
+            vec![Partition {
+                number: 2,
+                start: 1024 * 1024,
+                length: 1024 * 1024,
+                description: "DVD".to_string()
+            }]
+        );
+
+        image[512..520].copy_from_slice(b"NOT PART");
+        assert_eq!(
+            read_partitions(&mut Cursor::new(&image)).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! `patch-image`: replaces the contents of one file inside a UDF image in place, for small
+//! fixes such as an edited IFO that should not need the whole disc to be mastered again.
+//! The new contents are written over the file's recorded extents, so they can be no longer
+//! than the old ones, and the File Entry's lengths, extents and times are updated to match.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::SystemTime,
+};
+
+use log::debug;
+
+use crate::{
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    udf::{
+        AllocationDescriptorType, DescriptorTag, ExtentType, FileEntry, PartitionMap, ShortAllocationDescriptor,
+        Timestamp,
+    },
+    udf_high_level::VolumeStructures,
+    udf_parser::{read_short_allocation_descriptors, UdfParser},
+};
+
+const BLOCK_SIZE: usize = DVDCSS_BLOCK_SIZE;
+
+/// Refuse the volumes that replace_file_contents would leave reading the old file: those
+/// whose blocks a Sparing Table or a Virtual Allocation Table records elsewhere than in their
+/// partition, and those with a Metadata Partition, whose File Entries have a second copy in
+/// its Mirror File
+pub fn check_patchable<R: Read + Seek>(parser: &UdfParser<R>, structures: &VolumeStructures) -> io::Result<()> {
+    let has_metadata_partition = (structures.partition_maps.iter())
+        .any(|map| matches!(map, PartitionMap::Type2(map) if map.metadata().is_some()));
+    let unsupported = [
+        (!parser.sparable_partitions.is_empty(), "a Sparable Partition"),
+        (!parser.virtual_partitions.is_empty(), "a Virtual Allocation Table"),
+        (has_metadata_partition, "a Metadata Partition"),
+    ];
+    match unsupported.iter().find(|(found, _)| *found) {
+        Some((_, what)) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("files cannot be replaced in volumes with {}", what),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Replace the contents of the file whose File Entry is at logical block icb_block of the
+/// partition starting at sector partition_start with length bytes of contents
+pub fn replace_file_contents<F: Read + Write + Seek, R: Read>(
+    image: &mut F,
+    partition_start: u32,
+    icb_block: u32,
+    contents: &mut R,
+    length: u64,
+    modified: SystemTime,
+) -> io::Result<()> {
+    let block_pos = |block: u32| (partition_start as u64 + block as u64) * BLOCK_SIZE as u64;
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    image.seek(SeekFrom::Start(block_pos(icb_block)))?;
+    image.read_exact(&mut buf)?;
+    if DescriptorTag::read(&buf).tag_identifier != FileEntry::TAG_IDENTIFIER {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("there is no File Entry at block {}", icb_block),
+        ));
+    }
+    let mut file_entry = FileEntry::read(&buf)?;
+    if !matches!(file_entry.icb_tag.allocation_descriptor_type(), AllocationDescriptorType::SHORT) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only files recorded with short allocation descriptors can be replaced",
+        ));
+    }
+    if length > file_entry.information_length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the new contents are {} bytes but the file has only {} bytes on the image",
+                length, file_entry.information_length
+            ),
+        ));
+    }
+
+    // write over the extents in order, keeping only as many as the new contents need
+    let mut extents = vec![];
+    let mut written = 0;
+    for ad in read_short_allocation_descriptors(&file_entry.allocation_descriptors) {
+        if written == length {
+            break;
+        }
+        if ad.extent_type() != ExtentType::RecordedAllocated {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only files whose extents are all recorded can be replaced",
+            ));
+        }
+        let extent_length = (ad.extent_length_bytes() as u64).min(length - written);
+        let mut data = vec![0u8; extent_length.div_ceil(BLOCK_SIZE as u64) as usize * BLOCK_SIZE];
+        contents.read_exact(&mut data[..extent_length as usize])?;
+        debug!(
+            "replace_file_contents: writing {} bytes at block {}",
+            extent_length, ad.extent_location
+        );
+        // the rest of the last block is zeroed so that none of the old contents are left after the end
+        image.seek(SeekFrom::Start(block_pos(ad.extent_location)))?;
+        image.write_all(&data)?;
+        extents.push(ShortAllocationDescriptor {
+            extent_length_and_type: extent_length as u32,
+            extent_location: ad.extent_location,
+        });
+        written += extent_length;
+    }
+
+    let mut allocation_descriptors = vec![0u8; extents.len() * ShortAllocationDescriptor::size()];
+    for (ad, bytes) in extents.iter().zip(allocation_descriptors.chunks_exact_mut(ShortAllocationDescriptor::size())) {
+        ad.write(bytes);
+    }
+    file_entry.information_length = length;
+    file_entry.logical_blocks_recorded = length.div_ceil(BLOCK_SIZE as u64);
+    file_entry.modification_time = Timestamp::from_system_time(modified);
+    file_entry.attribute_time = file_entry.modification_time.clone();
+    file_entry.length_of_allocation_descriptors = allocation_descriptors.len() as u32;
+    file_entry.allocation_descriptors = allocation_descriptors;
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    file_entry.write(&mut buf[..file_entry.get_length()]);
+    DescriptorTag::finalize(&mut buf[..file_entry.get_length()], icb_block);
+    image.seek(SeekFrom::Start(block_pos(icb_block)))?;
+    image.write_all(&buf)?;
+    image.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        cache::Cache,
+        udf::PartitionMap,
+        test_support::{build_image, spare_packet, FixtureFile},
+        udf_parser::{read_file_contents, read_file_entries},
+        udf_writer::{write_image, ImageOptions, UdfRevision},
+    };
+
+    #[test]
+    fn test_replace_file_contents() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-patch-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("VIDEO_TS.IFO"), b"DVDVIDEO-VMG".repeat(200)).unwrap();
+        let options = ImageOptions {
+            volume_identifier: "FUNFANCY".to_string(),
+            revision: UdfRevision::Udf102,
+            recording_time: SystemTime::UNIX_EPOCH,
+            iso9660: false,
+        };
+        let mut image = Cursor::new(vec![]);
+        write_image(&dir, &options, &mut image).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // the root's File Entry and identifiers are followed by the file's File Entry
+        let (partition_start, icb_block) = (257, 4);
+
+        let contents = b"DVDVIDEO-VMG patched".repeat(10);
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_050_085_500);
+        let result = replace_file_contents(&mut image, partition_start, icb_block, &mut &contents[..], 2401, modified);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        replace_file_contents(&mut image, partition_start, icb_block, &mut &contents[..], 200, modified).unwrap();
+
+        image.set_position(0);
+        let mut parser = UdfParser::new(image);
+        let structures = parser.read_volume_structures().unwrap();
+        assert!(matches!(structures.partition_maps[0], PartitionMap::Type1(_)));
+        let partition_descriptor = &structures.partition_descriptors[&0];
+        let mut reader = Cache::new(&mut parser.reader);
+        let icb = ShortAllocationDescriptor {
+            extent_length_and_type: BLOCK_SIZE as u32,
+            extent_location: icb_block,
+        };
+        // the File Entry's tag is valid again
+        let file_entries =
+            read_file_entries(&mut reader, &structures.logical_volume, partition_descriptor, &icb).unwrap();
+        assert_eq!(file_entries[0].information_length, 200);
+        assert_eq!(file_entries[0].logical_blocks_recorded, 1);
+        assert_eq!(file_entries[0].modification_time.iso8601(), "2003-04-11T18:25:00Z");
+        let read_back = read_file_contents(
+            &mut reader,
+            &structures.logical_volume,
+            &structures.partition_descriptors,
+            0,
+            &file_entries,
+        )
+        .unwrap();
+        assert_eq!(read_back, contents[..200]);
+        // nothing of the old contents is left in the block
+        let data = &parser.reader.get_ref()[(257 + 5) * BLOCK_SIZE..(257 + 6) * BLOCK_SIZE];
+        assert!(data[200..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_check_patchable() {
+        let files = [FixtureFile {
+            path: "VIDEO_TS.IFO",
+            contents: b"DVDVIDEO-VMG",
+        }];
+        let mut image = build_image(&files, "SPARED", &Default::default()).unwrap();
+        let mut parser = UdfParser::new(Cursor::new(image.clone()));
+        let structures = parser.read_volume_structures().unwrap();
+        check_patchable(&parser, &structures).unwrap();
+        // a packet moved by the Sparing Table would be read from its spare, not where it is patched
+        spare_packet(&mut image, 32, 0);
+        let mut parser = UdfParser::new(Cursor::new(image));
+        let structures = parser.read_volume_structures().unwrap();
+        assert_eq!(parser.sparable_partitions.len(), 1);
+        let result = check_patchable(&parser, &structures);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,174 @@
+//! --include and --exclude: which files of the disc a command reads, chosen by glob patterns
+//! over their '/'-separated paths such as `VIDEO_TS/VTS_01_*.VOB`. A pattern that matches a
+//! directory chooses everything in it, so `VIDEO_TS` still means the whole directory.
+
+/// Whether the whole path matches the glob pattern: `*` matches any characters but '/', `**`
+/// any characters including '/', `?` one character but '/', and `[...]` one of a set of
+/// characters such as `[0-9]` or, with `[!...]`, one that is not in it. Matching is
+/// case-sensitive, as disc paths are.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    match_from(&pattern, &path)
+}
+
+fn match_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `a/**/b` also matches a/b
+            if let ['/', after_slash @ ..] = rest {
+                if match_from(after_slash, path) {
+                    return true;
+                }
+            }
+            (0..=path.len()).any(|i| match_from(rest, &path[i..]))
+        }
+        ['*', rest @ ..] => {
+            for i in 0..=path.len() {
+                if match_from(rest, &path[i..]) {
+                    return true;
+                }
+                if path.get(i) == Some(&'/') {
+                    return false;
+                }
+            }
+            false
+        }
+        ['?', rest @ ..] => matches!(path, [c, ..] if *c != '/') && match_from(rest, &path[1..]),
+        ['[', class @ ..] => match (match_class(class, path.first()), path) {
+            (Some((true, length)), [_, path_rest @ ..]) => match_from(&class[length..], path_rest),
+            (Some(_), _) => false,
+            // without a closing ']', '[' is an ordinary character
+            (None, ['[', path_rest @ ..]) => match_from(class, path_rest),
+            (None, _) => false,
+        },
+        [c, rest @ ..] => path.first() == Some(c) && match_from(rest, &path[1..]),
+    }
+}
+
+/// Whether c is in the set of characters that starts class (after the '['), and the length of
+/// the set up to and including its ']', or None if the set is not closed
+fn match_class(class: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    let (negated, mut i) = match class.first() {
+        Some('!' | '^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match class.get(i..)? {
+            // a ']' first in the set is in the set
+            [']', ..] if !first => break,
+            [low, '-', high, ..] if *high != ']' => {
+                matched |= c.is_some_and(|c| (low..=high).contains(&c));
+                i += 3;
+            }
+            [member, ..] => {
+                matched |= c == Some(member);
+                i += 1;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+    Some((c.is_some_and(|&c| c != '/') && matched != negated, i + 1))
+}
+
+/// Whether the pattern matches path or one of the directories that contain it
+pub fn matches_path_or_parent(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_matches('/');
+    (path.match_indices('/'))
+        .map(|(i, _)| &path[..i])
+        .chain([path])
+        .any(|prefix| glob_match(pattern, prefix))
+}
+
+/// The files chosen by --include and --exclude patterns
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+impl PathFilter {
+    /// No include patterns include every file
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        let trim = |patterns: Vec<String>| -> Vec<String> {
+            patterns.iter().map(|pattern| pattern.trim_matches('/').to_string()).collect()
+        };
+        Self {
+            include: trim(include),
+            exclude: trim(exclude),
+        }
+    }
+
+    /// Whether the file at path matches an include pattern, if there are any, and no exclude
+    /// pattern
+    pub fn includes(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| matches_path_or_parent(pattern, path)))
+            && !self.exclude.iter().any(|pattern| matches_path_or_parent(pattern, path))
+    }
+
+    /// Whether the directory at path can contain included files, so that a walk can leave
+    /// out the directories that cannot
+    pub fn may_include_below(&self, directory: &str) -> bool {
+        if self.exclude.iter().any(|pattern| matches_path_or_parent(pattern, directory)) {
+            return false;
+        }
+        self.include.is_empty()
+            || self.include.iter().any(|pattern| {
+                let mut pattern_components = pattern.split('/');
+                for component in directory.split('/') {
+                    match pattern_components.next() {
+                        // the pattern matched a directory above this one
+                        None => return true,
+                        Some(pattern_component) if pattern_component.contains("**") => return true,
+                        Some(pattern_component) if !glob_match(pattern_component, component) => return false,
+                        Some(_) => {}
+                    }
+                }
+                true
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("VIDEO_TS/*.VOB", "VIDEO_TS/VTS_01_1.VOB"));
+        assert!(!glob_match("VIDEO_TS/*.VOB", "VIDEO_TS/VTS_01_0.IFO"));
+        assert!(!glob_match("*.VOB", "VIDEO_TS/VTS_01_1.VOB"));
+        assert!(glob_match("**.VOB", "VIDEO_TS/VTS_01_1.VOB"));
+        assert!(glob_match("VIDEO_TS/**/*.TXT", "VIDEO_TS/A.TXT"));
+        assert!(glob_match("VIDEO_TS/**/*.TXT", "VIDEO_TS/EXTRAS/B/A.TXT"));
+        assert!(glob_match("VIDEO_TS/VTS_0?_1.VOB", "VIDEO_TS/VTS_03_1.VOB"));
+        assert!(glob_match("VIDEO_TS/VTS_01_[1-3].VOB", "VIDEO_TS/VTS_01_2.VOB"));
+        assert!(!glob_match("VIDEO_TS/VTS_01_[!0].VOB", "VIDEO_TS/VTS_01_0.VOB"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("A[B", "A[B"));
+        assert!(!glob_match("video_ts", "VIDEO_TS"));
+    }
+
+    #[test]
+    fn test_path_filter() {
+        let filter = PathFilter::new(
+            vec!["VIDEO_TS/VTS_01_*".to_string(), "/JACKET_P/".to_string()],
+            vec!["**.BUP".to_string()],
+        );
+        assert!(filter.includes("VIDEO_TS/VTS_01_1.VOB"));
+        assert!(!filter.includes("VIDEO_TS/VTS_01_0.BUP"));
+        assert!(!filter.includes("VIDEO_TS/VTS_02_1.VOB"));
+        assert!(filter.includes("JACKET_P/J00___5L.MP2"));
+        assert!(filter.may_include_below("VIDEO_TS"));
+        assert!(filter.may_include_below("JACKET_P/SMALL"));
+        assert!(!filter.may_include_below("AUDIO_TS"));
+
+        let filter = PathFilter::new(vec![], vec!["AUDIO_TS".to_string()]);
+        assert!(filter.includes("VIDEO_TS/VIDEO_TS.IFO"));
+        assert!(!filter.includes("AUDIO_TS/AUDIO_TS.IFO"));
+        assert!(!filter.may_include_below("AUDIO_TS"));
+    }
+}
@@ -0,0 +1,146 @@
+//! The copy pipeline: what is read from the disc is handed through a bounded queue to writer
+//! threads, so that writing the output and hashing it never keep the drive, which is the
+//! slowest part of a copy, waiting. The queue is bounded so that an output that is slower
+//! than the drive holds back the reading instead of filling memory.
+
+use std::{
+    io::{self, Write},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread::{Scope, ScopedJoinHandle},
+};
+
+/// The writes that can wait in the queue of a PipelinedWriter; the copy writes 1 MiB at a time
+pub const QUEUE_DEPTH: usize = 16;
+
+/// A writer whose writes are written in order to inner by a thread of scope. Once the thread
+/// has failed, the next write returns its error.
+pub struct PipelinedWriter<'scope, W> {
+    sender: Option<SyncSender<Vec<u8>>>,
+    /// buffers that the thread has written, to reuse
+    written: Receiver<Vec<u8>>,
+    thread: Option<ScopedJoinHandle<'scope, io::Result<W>>>,
+}
+impl<'scope, W: Write + Send + 'scope> PipelinedWriter<'scope, W> {
+    pub fn new<'env>(scope: &'scope Scope<'scope, 'env>, mut inner: W, depth: usize) -> Self {
+        let (sender, queue) = mpsc::sync_channel::<Vec<u8>>(depth);
+        let (written_sender, written) = mpsc::channel();
+        let thread = scope.spawn(move || {
+            for buf in queue {
+                inner.write_all(&buf)?;
+                // the writer is gone once it has been finished
+                let _ = written_sender.send(buf);
+            }
+            inner.flush()?;
+            Ok(inner)
+        });
+        Self {
+            sender: Some(sender),
+            written,
+            thread: Some(thread),
+        }
+    }
+
+    /// Wait for the thread to write and flush everything, and return inner
+    pub fn finish(mut self) -> io::Result<W> {
+        // the thread ends once its queue is closed and empty
+        self.sender = None;
+        self.join()
+    }
+
+    fn join(&mut self) -> io::Result<W> {
+        match self.thread.take().map(ScopedJoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Err(io::Error::other("the writer thread already failed")),
+        }
+    }
+}
+impl<'scope, W: Write + Send + 'scope> Write for PipelinedWriter<'scope, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut queued = self.written.try_recv().unwrap_or_default();
+        queued.clear();
+        queued.extend_from_slice(buf);
+        match self.sender.as_ref().map(|sender| sender.send(queued)) {
+            Some(Ok(())) => Ok(buf.len()),
+            // the thread only stops reading its queue when writing failed
+            _ => {
+                self.sender = None;
+                self.join().and(Err(io::Error::other("the writer thread stopped")))
+            }
+        }
+    }
+    /// The thread flushes inner when it is finished
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run write with a writer that passes what is written to output, on a thread of its own when
+/// pipelined, and return what write returns along with output
+pub fn write_pipelined<W, T, E>(
+    output: W,
+    pipelined: bool,
+    write: impl FnOnce(&mut (dyn Write + Send)) -> Result<T, E>,
+) -> Result<(T, W), E>
+where
+    W: Write + Send,
+    E: From<io::Error>,
+{
+    if !pipelined {
+        let mut output = output;
+        let result = write(&mut output)?;
+        return Ok((result, output));
+    }
+    std::thread::scope(|scope| {
+        let mut writer = PipelinedWriter::new(scope, output, QUEUE_DEPTH);
+        let result = write(&mut writer)?;
+        Ok((result, writer.finish()?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipelined_writer() {
+        let ((), output) = write_pipelined(vec![], true, |writer| -> io::Result<()> {
+            for i in 0..100u8 {
+                writer.write_all(&[i; 1000])?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(output.len(), 100_000);
+        assert!(output.chunks(1000).enumerate().all(|(i, chunk)| chunk == [i as u8; 1000]));
+
+        let ((), output) = write_pipelined(vec![], false, |writer| writer.write_all(b"VOB")).unwrap();
+        assert_eq!(output, b"VOB");
+    }
+
+    #[test]
+    fn test_pipelined_writer_error() {
+        /// An output that is full after 10 bytes
+        struct Full(usize);
+        impl Write for Full {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.0 + buf.len() > 10 {
+                    return Err(io::ErrorKind::StorageFull.into());
+                }
+                self.0 += buf.len();
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let result = write_pipelined(Full(0), true, |writer| -> io::Result<()> {
+            // the queue holds the writes until the thread fails
+            for _ in 0..QUEUE_DEPTH + 10 {
+                writer.write_all(b"IFO")?;
+            }
+            Ok(())
+        });
+        assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::StorageFull));
+    }
+}
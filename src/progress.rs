@@ -0,0 +1,298 @@
+//! Progress of a copy for --progress: the file being copied, and how much of the size of all
+//! the files, found by a scan of the disc before copying, has been copied. The copy reports to
+//! a [`ProgressSink`], which [`TerminalProgress`] draws as a line redrawn in place.
+
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Receives the progress of a copy. The methods take &self so that the copy can share a sink,
+/// including with the threads that write the copy.
+pub trait ProgressSink: Sync {
+    /// The size of all the files to copy
+    fn set_total(&self, bytes: u64);
+    /// A file of size bytes is being copied
+    fn start_file(&self, path: &str, size: u64);
+    /// bytes more of the file were copied
+    fn advance(&self, bytes: u64);
+    /// The copy ended, whether or not everything was copied
+    fn finish(&self);
+}
+
+/// How much of a copy is done
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub total: u64,
+    pub copied: u64,
+    pub file: String,
+    pub file_size: u64,
+    pub file_copied: u64,
+    started: Instant,
+}
+impl Progress {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            total: 0,
+            copied: 0,
+            file: String::new(),
+            file_size: 0,
+            file_copied: 0,
+            started,
+        }
+    }
+
+    /// The whole percentage of the total copied
+    pub fn percent(&self) -> u64 {
+        (self.copied * 100).checked_div(self.total).unwrap_or(100).min(100)
+    }
+
+    /// The time left at the average rate so far, once something has been copied
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        if self.copied == 0 {
+            return None;
+        }
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        let left = self.total.saturating_sub(self.copied) as f64;
+        Some(Duration::from_secs_f64(elapsed * left / self.copied as f64))
+    }
+
+    /// A line of at most width characters like
+    /// `[#######>            ]  35% 1.0 GiB/2.9 GiB 10.5 MiB/s 0:03:10 left VIDEO_TS/VTS_01_1.VOB 61%`
+    pub fn line(&self, now: Instant, width: usize) -> String {
+        const BAR_WIDTH: usize = 20;
+        let percent = self.percent() as usize;
+        let filled = percent * BAR_WIDTH / 100;
+        let mut bar = "#".repeat(filled);
+        if filled < BAR_WIDTH {
+            bar.push('>');
+            bar.push_str(&" ".repeat(BAR_WIDTH - filled - 1));
+        }
+        let elapsed = now.duration_since(self.started).as_secs_f64().max(0.001);
+        let remaining = match self.remaining(now) {
+            Some(remaining) => format!("{} left", format_duration(remaining)),
+            None => "-:--:-- left".to_string(),
+        };
+        let mut line = format!(
+            "[{}] {:>3}% {}/{} {}/s {}",
+            bar,
+            percent,
+            format_size(self.copied),
+            format_size(self.total),
+            format_size((self.copied as f64 / elapsed) as u64),
+            remaining
+        );
+        if !self.file.is_empty() {
+            let file_percent = (self.file_copied * 100)
+                .checked_div(self.file_size)
+                .unwrap_or(100)
+                .min(100);
+            let file_percent = format!(" {}%", file_percent);
+            // the end of a long path says the most
+            let room = width.saturating_sub(line.chars().count() + 1 + file_percent.len());
+            let chars = self.file.chars().count();
+            if room >= 4 {
+                line.push(' ');
+                if chars > room {
+                    line.push('…');
+                    line.extend(self.file.chars().skip(chars - room + 1));
+                } else {
+                    line.push_str(&self.file);
+                }
+                line.push_str(&file_percent);
+            }
+        }
+        line.chars().take(width).collect()
+    }
+}
+
+/// bytes in B, KiB, MiB or GiB
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// H:MM:SS
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Draws the progress on stderr: on a terminal as a line redrawn at most every
+/// REDRAW_INTERVAL, and otherwise as a line for each file
+pub struct TerminalProgress {
+    state: Mutex<TerminalState>,
+}
+struct TerminalState {
+    progress: Progress,
+    is_terminal: bool,
+    width: usize,
+    last_drawn: Option<Instant>,
+}
+impl TerminalProgress {
+    const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new() -> Self {
+        // the terminal's width when the shell exports it
+        let width = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(80);
+        Self {
+            state: Mutex::new(TerminalState {
+                progress: Progress::new(Instant::now()),
+                is_terminal: io::stderr().is_terminal(),
+                // the last column is left empty, where some terminals would wrap
+                width: usize::max(width, 2) - 1,
+                last_drawn: None,
+            }),
+        }
+    }
+
+    fn update(&self, force: bool, update: impl FnOnce(&mut Progress)) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        update(&mut state.progress);
+        let now = Instant::now();
+        if !state.is_terminal
+            || !force
+                && state
+                    .last_drawn
+                    .is_some_and(|drawn| now.duration_since(drawn) < Self::REDRAW_INTERVAL)
+        {
+            return;
+        }
+        state.last_drawn = Some(now);
+        let line = state.progress.line(now, state.width);
+        // clear the line, since the new one can be shorter; progress that cannot be drawn is
+        // not worth failing the copy for
+        let _ = write!(io::stderr(), "\r\x1b[2K{}", line);
+    }
+}
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ProgressSink for TerminalProgress {
+    fn set_total(&self, bytes: u64) {
+        self.update(true, |progress| progress.total = bytes);
+    }
+    fn start_file(&self, path: &str, size: u64) {
+        self.update(true, |progress| {
+            progress.file = path.to_string();
+            progress.file_size = size;
+            progress.file_copied = 0;
+        });
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.is_terminal {
+            eprintln!("[{:>3}%] {}", state.progress.percent(), path);
+        }
+    }
+    fn advance(&self, bytes: u64) {
+        self.update(false, |progress| {
+            progress.copied += bytes;
+            progress.file_copied += bytes;
+        });
+    }
+    fn finish(&self) {
+        self.update(true, |progress| progress.file.clear());
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.is_terminal {
+            eprintln!();
+        } else {
+            eprintln!(
+                "[{:>3}%] {} copied",
+                state.progress.percent(),
+                format_size(state.progress.copied)
+            );
+        }
+    }
+}
+
+/// Reports what is written through it to a ProgressSink, if there is one
+pub struct ReportingWriter<'a, W: Write> {
+    inner: W,
+    sink: Option<&'a dyn ProgressSink>,
+}
+impl<'a, W: Write> ReportingWriter<'a, W> {
+    pub fn new(inner: W, sink: Option<&'a dyn ProgressSink>) -> Self {
+        Self { inner, sink }
+    }
+}
+impl<W: Write> Write for ReportingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(sink) = self.sink {
+            sink.advance(n as u64);
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        let started = Instant::now();
+        let mut progress = Progress::new(started);
+        progress.total = 4 * 1024 * 1024 * 1024;
+        assert_eq!(
+            progress.line(started, 79),
+            "[>                   ]   0% 0 B/4.0 GiB 0 B/s -:--:-- left"
+        );
+        progress.copied = 1024 * 1024 * 1024;
+        progress.file = "VIDEO_TS/VTS_01_1.VOB".to_string();
+        progress.file_size = 1024 * 1024 * 1024;
+        progress.file_copied = 512 * 1024 * 1024;
+        let now = started + Duration::from_secs(100);
+        assert_eq!(progress.remaining(now), Some(Duration::from_secs(300)));
+        assert_eq!(
+            progress.line(now, 79),
+            "[#####>              ]  25% 1.0 GiB/4.0 GiB 10.2 MiB/s 0:05:00 left …_1.VOB 50%"
+        );
+        assert!(progress.line(now, 200).ends_with(" VIDEO_TS/VTS_01_1.VOB 50%"));
+        // too narrow for the file
+        assert_eq!(progress.line(now, 20), "[#####>             ");
+        progress.copied = progress.total;
+        assert!(progress
+            .line(now, 79)
+            .starts_with("[####################] 100% 4.0 GiB/4.0 GiB"));
+    }
+
+    #[test]
+    fn test_reporting_writer() {
+        struct Recording(Mutex<Vec<u64>>);
+        impl ProgressSink for Recording {
+            fn set_total(&self, _bytes: u64) {}
+            fn start_file(&self, _path: &str, _size: u64) {}
+            fn advance(&self, bytes: u64) {
+                self.0.lock().unwrap().push(bytes);
+            }
+            fn finish(&self) {}
+        }
+        let recording = Recording(Mutex::new(vec![]));
+        let mut output = vec![];
+        let mut writer = ReportingWriter::new(&mut output, Some(&recording));
+        writer.write_all(b"VOB").unwrap();
+        writer.write_all(b"IFO!").unwrap();
+        assert_eq!(output, b"VOBIFO!");
+        assert_eq!(*recording.0.lock().unwrap(), vec![3, 4]);
+        assert_eq!(format_size(1536), "1.5 KiB");
+    }
+}
@@ -0,0 +1,295 @@
+//! `repair-image --rebuild`: for images whose anchors (and often the main Volume Descriptor
+//! Sequence) were lost, e.g. to a truncated or partly overwritten copy, scans every sector for
+//! volume descriptors whose tags are still valid, and writes new anchors, Volume Descriptor
+//! Sequences and, if it is missing, Volume Recognition Sequence from the newest copy of each,
+//! so that readers can find the file system again.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use log::{debug, info};
+
+use crate::{
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    repair::{descriptor_length, tag_is_valid},
+    udf::{DescriptorTag, LogicalVolumeDescriptor, PartitionDescriptor, PrimaryVolumeDescriptor},
+    udf_writer::{
+        write_anchor, write_terminating_descriptor, write_volume_recognition_sequence, UdfRevision, ANCHOR_SECTOR,
+        MAIN_VDS_SECTOR, RESERVE_VDS_SECTOR, VDS_SECTORS, VOLUME_RECOGNITION_SECTOR,
+    },
+};
+
+const BLOCK_SIZE: usize = DVDCSS_BLOCK_SIZE;
+/// ECMA-167 3/10.4 Implementation Use Volume Descriptor
+const IMPLEMENTATION_USE_TAG_IDENTIFIER: u16 = 4;
+/// ECMA-167 3/10.8 Unallocated Space Descriptor
+const UNALLOCATED_SPACE_TAG_IDENTIFIER: u16 = 7;
+/// ECMA-167 3/10.2
+const ANCHOR_TAG_IDENTIFIER: u16 = 2;
+/// Sectors read at a time while scanning
+const SCAN_SECTORS: usize = 64;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RecoverySummary {
+    pub sectors_scanned: u64,
+    /// volume descriptors with valid tags found while scanning, including older copies
+    pub descriptors_found: u64,
+    /// descriptors written to each Volume Descriptor Sequence, including the Terminating Descriptor
+    pub sequence_length: u32,
+    /// sectors the anchors were written to
+    pub anchors: Vec<u32>,
+}
+
+/// A descriptor's Volume Descriptor Sequence Number and bytes
+type Versioned = Option<(u32, Vec<u8>)>;
+
+/// The copy of each volume descriptor with the highest Volume Descriptor Sequence Number
+/// (ECMA-167 3/8.4.3), so that the main and reserve sequences need not both survive
+#[derive(Default)]
+struct SurvivingDescriptors {
+    found: u64,
+    primary: Versioned,
+    implementation_use: Versioned,
+    /// by partition number
+    partitions: BTreeMap<u16, Versioned>,
+    logical_volume: Versioned,
+    unallocated_space: Versioned,
+}
+
+impl SurvivingDescriptors {
+    fn add(&mut self, sector: u32, bytes: &[u8]) {
+        let Some(length) = descriptor_length(bytes).filter(|&length| length <= BLOCK_SIZE) else {
+            return;
+        };
+        // the location must match, so that the descriptors of images stored as files are skipped
+        if !tag_is_valid(bytes, length, sector) {
+            return;
+        }
+        let slot = match DescriptorTag::read(bytes).tag_identifier {
+            PrimaryVolumeDescriptor::TAG_IDENTIFIER => &mut self.primary,
+            IMPLEMENTATION_USE_TAG_IDENTIFIER => &mut self.implementation_use,
+            PartitionDescriptor::TAG_IDENTIFIER => self
+                .partitions
+                .entry(u16::from_le_bytes([bytes[22], bytes[23]]))
+                .or_default(),
+            LogicalVolumeDescriptor::TAG_IDENTIFIER => &mut self.logical_volume,
+            UNALLOCATED_SPACE_TAG_IDENTIFIER => &mut self.unallocated_space,
+            _ => return,
+        };
+        debug!("recover: found a volume descriptor at {}", sector);
+        self.found += 1;
+        let sequence_number = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+        if !matches!(slot, Some((newest, _)) if *newest >= sequence_number) {
+            *slot = Some((sequence_number, bytes[..length].to_vec()));
+        }
+    }
+}
+
+fn read_sector<F: Read + Seek>(image: &mut F, sector: u32) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    image.seek(SeekFrom::Start(sector as u64 * BLOCK_SIZE as u64))?;
+    image.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn write_sector<F: Write + Seek>(image: &mut F, sector: u32, bytes: &[u8]) -> io::Result<()> {
+    image.seek(SeekFrom::Start(sector as u64 * BLOCK_SIZE as u64))?;
+    image.write_all(bytes)
+}
+
+/// Rebuild the anchors and Volume Descriptor Sequences of the UDF image in place from the
+/// volume descriptors that survive in it. The last anchor is appended as a new last sector
+/// if the image's last sector is in use.
+pub fn recover_image<F: Read + Write + Seek>(image: &mut F) -> io::Result<RecoverySummary> {
+    let total_sectors = (image.seek(SeekFrom::End(0))? / BLOCK_SIZE as u64) as u32;
+    let mut surviving = SurvivingDescriptors::default();
+    image.seek(SeekFrom::Start(0))?;
+    let mut chunk = vec![0u8; SCAN_SECTORS * BLOCK_SIZE];
+    let mut sector = 0;
+    while sector < total_sectors {
+        let sectors = (total_sectors - sector).min(SCAN_SECTORS as u32);
+        let chunk = &mut chunk[..sectors as usize * BLOCK_SIZE];
+        image.read_exact(chunk)?;
+        for (offset, bytes) in (0..).zip(chunk.chunks_exact(BLOCK_SIZE)) {
+            surviving.add(sector + offset, bytes);
+        }
+        sector += sectors;
+    }
+    info!("recover: found {} volume descriptors in {} sectors", surviving.found, total_sectors);
+
+    let missing = |name: &str| io::Error::new(io::ErrorKind::InvalidData, format!("no valid {} was found", name));
+    let (_, primary) = surviving.primary.ok_or_else(|| missing("Primary Volume Descriptor"))?;
+    let (_, logical_volume) = surviving.logical_volume.ok_or_else(|| missing("Logical Volume Descriptor"))?;
+    let partitions: Vec<Vec<u8>> = surviving.partitions.into_values().flatten().map(|(_, bytes)| bytes).collect();
+    if partitions.is_empty() {
+        return Err(missing("Partition Descriptor"));
+    }
+    let revision = UdfRevision::from_descriptor_version(DescriptorTag::read(&logical_volume).descriptor_version);
+
+    let mut sequence = vec![primary];
+    sequence.extend(surviving.implementation_use.map(|(_, bytes)| bytes));
+    sequence.extend(partitions.iter().cloned());
+    sequence.push(logical_volume);
+    sequence.push(match surviving.unallocated_space {
+        Some((_, bytes)) => bytes,
+        None => {
+            // with no extents, since the partitions hold all of the space
+            let mut usd = vec![0u8; 24];
+            let tag = DescriptorTag {
+                tag_identifier: UNALLOCATED_SPACE_TAG_IDENTIFIER,
+                descriptor_version: DescriptorTag::read(&sequence[0]).descriptor_version,
+                ..Default::default()
+            };
+            tag.write(&mut usd[0..16]);
+            usd[16..20].copy_from_slice(&(sequence.len() as u32).to_le_bytes());
+            usd
+        }
+    });
+    let sequence_length = sequence.len() as u32 + 1;
+    if sequence_length > VDS_SECTORS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} volume descriptors do not fit in a sequence", sequence_length),
+        ));
+    }
+
+    // the sectors written must be outside the partitions and must not hold other descriptors
+    let partition_ranges: Vec<_> = partitions
+        .iter()
+        .map(|bytes| PartitionDescriptor::read(bytes))
+        .map(|pd| pd.partition_starting_location as u64..pd.partition_starting_location as u64 + pd.partition_length as u64)
+        .collect();
+    let is_free = |sector: u32| !partition_ranges.iter().any(|range| range.contains(&(sector as u64)));
+    let mut written = vec![ANCHOR_SECTOR];
+    for start in [MAIN_VDS_SECTOR, RESERVE_VDS_SECTOR] {
+        written.extend(start..start + sequence_length);
+    }
+    for &sector in &written {
+        if sector >= total_sectors || !is_free(sector) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("sector {} is inside a partition or past the end of the image", sector),
+            ));
+        }
+        let bytes = read_sector(image, sector)?;
+        if let Some(length) = descriptor_length(&bytes).filter(|&length| length <= BLOCK_SIZE) {
+            let tag_identifier = DescriptorTag::read(&bytes).tag_identifier;
+            if tag_is_valid(&bytes, length, sector) && !(1..=8).contains(&tag_identifier) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("sector {} holds a descriptor with tag {}", sector, tag_identifier),
+                ));
+            }
+        }
+    }
+
+    // ECMA-167 2/8.3: readers look for an NSR descriptor before anything else
+    let has_nsr = (VOLUME_RECOGNITION_SECTOR..MAIN_VDS_SECTOR).any(|sector| {
+        read_sector(image, sector).is_ok_and(|bytes| matches!(&bytes[1..6], b"NSR02" | b"NSR03"))
+    });
+    if !has_nsr {
+        let mut head = vec![0u8; (VOLUME_RECOGNITION_SECTOR as usize + 3) * BLOCK_SIZE];
+        write_volume_recognition_sequence(&mut head, VOLUME_RECOGNITION_SECTOR, revision);
+        image.seek(SeekFrom::Start(VOLUME_RECOGNITION_SECTOR as u64 * BLOCK_SIZE as u64))?;
+        image.write_all(&head[VOLUME_RECOGNITION_SECTOR as usize * BLOCK_SIZE..])?;
+    }
+
+    for start in [MAIN_VDS_SECTOR, RESERVE_VDS_SECTOR] {
+        for (location, descriptor) in (start..).zip(&sequence) {
+            let mut buf = vec![0u8; BLOCK_SIZE];
+            buf[..descriptor.len()].copy_from_slice(descriptor);
+            DescriptorTag::finalize(&mut buf[..descriptor.len()], location);
+            write_sector(image, location, &buf)?;
+        }
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        let location = start + sequence_length - 1;
+        write_terminating_descriptor(&mut buf, location, revision);
+        write_sector(image, location, &buf)?;
+    }
+
+    // ECMA-167 3/8.4.2.1: an anchor at 256 and at the last sector
+    let last = total_sectors - 1;
+    let last_anchor = if last > ANCHOR_SECTOR && is_free(last) && {
+        let bytes = read_sector(image, last)?;
+        bytes.iter().all(|&b| b == 0) || DescriptorTag::read(&bytes).tag_identifier == ANCHOR_TAG_IDENTIFIER
+    } {
+        last
+    } else {
+        total_sectors
+    };
+    let anchors = vec![ANCHOR_SECTOR, last_anchor];
+    for &location in &anchors {
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        write_anchor(&mut buf, location, revision);
+        write_sector(image, location, &buf)?;
+    }
+    image.flush()?;
+    Ok(RecoverySummary {
+        sectors_scanned: total_sectors as u64,
+        descriptors_found: surviving.found,
+        sequence_length,
+        anchors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::SystemTime};
+
+    use super::*;
+    use crate::{
+        repair::repair_image,
+        udf_parser::UdfParser,
+        udf_writer::{write_image, ImageOptions},
+    };
+
+    #[test]
+    fn test_recover_image() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-recover-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("VIDEO_TS")).unwrap();
+        std::fs::write(dir.join("VIDEO_TS/VIDEO_TS.IFO"), b"DVDVIDEO-VMG".repeat(300)).unwrap();
+        let options = ImageOptions {
+            volume_identifier: "FUNFANCY".to_string(),
+            revision: UdfRevision::Udf102,
+            recording_time: SystemTime::UNIX_EPOCH,
+            iso9660: false,
+        };
+        let mut original = Cursor::new(vec![]);
+        write_image(&dir, &options, &mut original).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let original = original.into_inner();
+        let total_sectors = original.len() / BLOCK_SIZE;
+
+        // lose the recognition sequence, the main sequence and both anchors
+        let mut damaged = original.clone();
+        for sector in (16..19).chain(32..38).chain([256, total_sectors - 1]) {
+            damaged[sector * BLOCK_SIZE..(sector + 1) * BLOCK_SIZE].fill(0);
+        }
+        let mut image = Cursor::new(damaged);
+        assert!(repair_image(&mut image).is_err());
+        let summary = recover_image(&mut image).unwrap();
+        assert_eq!(summary.descriptors_found, 5);
+        assert_eq!(summary.sequence_length, 6);
+        assert_eq!(summary.anchors, [256, total_sectors as u32 - 1]);
+        // the reserve sequence was copied back to where the main one was
+        assert!(image.get_ref() == &original);
+
+        // with the last sector in use, the anchor is appended instead
+        let mut damaged = original.clone();
+        damaged[256 * BLOCK_SIZE..257 * BLOCK_SIZE].fill(0);
+        damaged[(total_sectors - 1) * BLOCK_SIZE..].fill(0xff);
+        let mut image = Cursor::new(damaged);
+        let summary = recover_image(&mut image).unwrap();
+        assert_eq!(summary.descriptors_found, 10);
+        assert_eq!(summary.anchors, [256, total_sectors as u32]);
+        image.set_position(0);
+        let mut parser = UdfParser::new(image);
+        let structures = parser.read_volume_structures().unwrap();
+        assert_eq!(structures.primary_volume.volume_identifier.to_string(), "FUNFANCY");
+
+        let mut image = Cursor::new(vec![0u8; 300 * BLOCK_SIZE]);
+        assert_eq!(recover_image(&mut image).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
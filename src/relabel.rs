@@ -0,0 +1,203 @@
+//! `relabel`: changes the name of the volume in an image in place, without mastering it again.
+//! The volume identifier of the Primary Volume Descriptor and the logical volume identifiers
+//! of the Logical Volume and Implementation Use Volume Descriptors are rewritten in both
+//! Volume Descriptor Sequences and their tags resealed, along with the identifiers of any
+//! ISO 9660 and Joliet volume descriptors of a UDF Bridge image. No file data is touched.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use log::debug;
+
+use crate::{
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    iso9660::{relabel_volume_descriptor, VOLUME_DESCRIPTOR_SECTOR},
+    repair::{descriptor_length, tag_is_valid},
+    udf::{
+        AnchorVolumeDescriptorPointer, DescriptorTag, Dstring, DynamicDstring, ExtentAd, LogicalVolumeDescriptor,
+        PrimaryVolumeDescriptor, TerminatingDescriptor,
+    },
+    udf_writer::{ANCHOR_SECTOR, MAIN_VDS_SECTOR},
+};
+
+const BLOCK_SIZE: usize = DVDCSS_BLOCK_SIZE;
+/// ECMA-167 3/10.2
+const ANCHOR_TAG_IDENTIFIER: u16 = 2;
+/// ECMA-167 3/10.4 Implementation Use Volume Descriptor
+const IMPLEMENTATION_USE_TAG_IDENTIFIER: u16 = 4;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RelabelSummary {
+    /// UDF volume descriptors rewritten, counting those of both sequences
+    pub descriptors: u64,
+    /// ISO 9660 and Joliet volume descriptors rewritten
+    pub iso9660_descriptors: u64,
+}
+
+fn read_sector<F: Read + Seek>(image: &mut F, sector: u32) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    image.seek(SeekFrom::Start(sector as u64 * BLOCK_SIZE as u64))?;
+    image.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Rename the volume of the image to volume_identifier. Every sector to change is read and
+/// checked before any is written, so an image whose descriptors are damaged is left as it was.
+pub fn relabel_image<F: Read + Write + Seek>(image: &mut F, volume_identifier: &str) -> io::Result<RelabelSummary> {
+    // UDF 2.2.2.4: the identifier must fit in the 32 bytes of the Primary Volume Descriptor's
+    // dstring, which leaves 30 8-bit or 15 16-bit characters
+    let encoded_length = DynamicDstring::from_str(volume_identifier).0.len();
+    if volume_identifier.is_empty() || encoded_length > 31 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} does not fit in a volume identifier", volume_identifier),
+        ));
+    }
+    let total_sectors = (image.seek(SeekFrom::End(0))? / BLOCK_SIZE as u64) as u32;
+
+    // ECMA-167 3/8.4.2.1: anchors at 256, N - 256 and N - 1
+    let mut sequences: Vec<ExtentAd> = vec![];
+    for sector in [ANCHOR_SECTOR, total_sectors.saturating_sub(256), total_sectors.saturating_sub(1)] {
+        if sector < ANCHOR_SECTOR || sector >= total_sectors {
+            continue;
+        }
+        let buf = read_sector(image, sector)?;
+        if DescriptorTag::read(&buf).tag_identifier != ANCHOR_TAG_IDENTIFIER || !tag_is_valid(&buf, 512, sector) {
+            continue;
+        }
+        let anchor = AnchorVolumeDescriptorPointer::read(&buf[..AnchorVolumeDescriptorPointer::size()]);
+        for extent in [
+            anchor.main_volume_descriptor_sequence_location,
+            anchor.reserve_volume_descriptor_sequence_location,
+        ] {
+            if !sequences.iter().any(|known| known.location_sector == extent.location_sector) {
+                sequences.push(extent);
+            }
+        }
+    }
+    if sequences.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no Anchor Volume Descriptor Pointer was found; try repair-image --rebuild",
+        ));
+    }
+
+    let mut summary = RelabelSummary::default();
+    let mut sectors = vec![];
+    for extent in &sequences {
+        let end = extent.location_sector + extent.length_bytes / BLOCK_SIZE as u32;
+        for sector in extent.location_sector..end.min(total_sectors) {
+            let mut buf = read_sector(image, sector)?;
+            let Some(length) = descriptor_length(&buf).filter(|&length| length <= BLOCK_SIZE) else {
+                break;
+            };
+            if !tag_is_valid(&buf, length, sector) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the volume descriptor at sector {} is damaged; run repair-image first", sector),
+                ));
+            }
+            match DescriptorTag::read(&buf).tag_identifier {
+                PrimaryVolumeDescriptor::TAG_IDENTIFIER => {
+                    buf[24..56].copy_from_slice(&Dstring::<32>::from_str(volume_identifier).0);
+                }
+                // UDF 2.2.7.2 Logical Volume Information, which repeats the logical volume identifier
+                IMPLEMENTATION_USE_TAG_IDENTIFIER if &buf[21..33] == b"*UDF LV Info" => {
+                    buf[116..244].copy_from_slice(&Dstring::<128>::from_str(volume_identifier).0);
+                }
+                LogicalVolumeDescriptor::TAG_IDENTIFIER => {
+                    buf[84..212].copy_from_slice(&Dstring::<128>::from_str(volume_identifier).0);
+                }
+                TerminatingDescriptor::TAG_IDENTIFIER => break,
+                _ => continue,
+            }
+            DescriptorTag::write_checksums(&mut buf[..length]);
+            summary.descriptors += 1;
+            sectors.push((sector, buf));
+        }
+    }
+
+    // ECMA-119 8.1: the ISO 9660 volume descriptors start at sector 16, before the UDF
+    // Volume Recognition Sequence if they come first, and end with a Set Terminator
+    for sector in VOLUME_DESCRIPTOR_SECTOR..MAIN_VDS_SECTOR {
+        let mut buf = read_sector(image, sector)?;
+        if &buf[1..6] != b"CD001" || buf[0] == 255 {
+            break;
+        }
+        if relabel_volume_descriptor(&mut buf, volume_identifier) {
+            summary.iso9660_descriptors += 1;
+            sectors.push((sector, buf));
+        }
+    }
+
+    for (sector, buf) in &sectors {
+        debug!("relabel_image: rewriting sector {}", sector);
+        image.seek(SeekFrom::Start(*sector as u64 * BLOCK_SIZE as u64))?;
+        image.write_all(buf)?;
+    }
+    image.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::SystemTime};
+
+    use super::*;
+    use crate::{
+        repair::repair_image,
+        udf_parser::UdfParser,
+        udf_writer::{write_image_of_files, ImageOptions, SourceFile, UdfRevision},
+    };
+
+    #[test]
+    fn test_relabel_image() {
+        let contents = b"DVDVIDEO-VMG".repeat(200);
+        let files = [SourceFile {
+            path: "VIDEO_TS/VIDEO_TS.IFO",
+            length: contents.len() as u64,
+            modified: SystemTime::UNIX_EPOCH,
+        }];
+        let options = ImageOptions {
+            volume_identifier: "FUNFANCY".to_string(),
+            revision: UdfRevision::Udf102,
+            recording_time: SystemTime::UNIX_EPOCH,
+            iso9660: true,
+        };
+        let mut image = Cursor::new(vec![]);
+        write_image_of_files(&files, &options, &mut image, |_, writer| {
+            writer.write_all(&contents)?;
+            Ok(contents.len() as u64)
+        })
+        .unwrap();
+        let original = image.get_ref().clone();
+
+        let result = relabel_image(&mut image, "A VOLUME NAME THAT IS FAR TOO LONG");
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let summary = relabel_image(&mut image, "Home Movies").unwrap();
+        // the Primary, Implementation Use and Logical Volume Descriptors of both sequences
+        assert_eq!(
+            summary,
+            RelabelSummary {
+                descriptors: 6,
+                iso9660_descriptors: 2
+            }
+        );
+        // the tags were resealed
+        assert_eq!(repair_image(&mut image).unwrap().repaired, 0);
+
+        let relabeled = image.get_ref().clone();
+        let changed: Vec<usize> = (0..relabeled.len() / BLOCK_SIZE)
+            .filter(|&sector| {
+                relabeled[sector * BLOCK_SIZE..][..BLOCK_SIZE] != original[sector * BLOCK_SIZE..][..BLOCK_SIZE]
+            })
+            .collect();
+        assert_eq!(changed, vec![16, 17, 32, 33, 35, 48, 49, 51]);
+        assert_eq!(&relabeled[16 * BLOCK_SIZE + 40..16 * BLOCK_SIZE + 53], b"HOME_MOVIES  ");
+
+        image.set_position(0);
+        let mut parser = UdfParser::new(image);
+        let structures = parser.read_volume_structures().unwrap();
+        assert_eq!(structures.primary_volume.volume_identifier.to_string(), "Home Movies");
+        assert_eq!(structures.logical_volume.logical_volume_identifier.to_string(), "Home Movies");
+    }
+}
@@ -0,0 +1,620 @@
+//! Remux the MPEG-2 program stream of a DVD-Video title into Matroska.
+//! MPEG-2 video, AC-3, MPEG audio, 16-bit LPCM and subpicture (VobSub) streams
+//! become tracks; other streams are dropped with a warning.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Seek, Write},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    matroska::{Chapter, MatroskaWriter, Track, TrackKind, TIMESTAMP_SCALE_NS},
+    mpeg_ps::{parse_pack, parse_pci_times, StreamKind, PACK_SIZE},
+};
+
+/// PTS clock rate
+const TICKS_PER_SECOND: i64 = 90_000;
+
+fn ticks_to_ms(ticks: i64) -> i64 {
+    ticks * 1000 / TICKS_PER_SECOND
+}
+
+/// Elementary stream bytes with the PES timestamps of where they were received
+#[derive(Default)]
+struct Framer {
+    data: Vec<u8>,
+    /// (offset in data, PTS on the output timeline)
+    timestamps: VecDeque<(usize, i64)>,
+    last_timestamp: Option<i64>,
+    /// in ticks; used for frames without their own PTS
+    frame_duration: i64,
+}
+impl Framer {
+    fn push(&mut self, payload: &[u8], pts: Option<i64>) {
+        if let Some(pts) = pts {
+            self.timestamps.push_back((self.data.len(), pts));
+        }
+        self.data.extend_from_slice(payload);
+    }
+
+    /// The PTS of the access unit starting at offset: the PTS of the latest PES
+    /// that started at or before it, unless an earlier access unit already used it
+    fn timestamp_at(&mut self, offset: usize) -> i64 {
+        let mut found = None;
+        while let Some(&(start, pts)) = self.timestamps.front() {
+            if start > offset {
+                break;
+            }
+            found = Some(pts);
+            self.timestamps.pop_front();
+        }
+        let timestamp = found
+            .or_else(|| self.last_timestamp.map(|t| t + self.frame_duration))
+            .unwrap_or(0);
+        self.last_timestamp = Some(timestamp);
+        timestamp
+    }
+
+    /// Remove the first len bytes
+    fn consume(&mut self, len: usize) {
+        self.data.drain(..len);
+        for (start, _) in self.timestamps.iter_mut() {
+            *start = start.saturating_sub(len);
+        }
+    }
+
+    /// Split off frames whose size can be read from their header
+    fn sized_frames(&mut self, frame_size: impl Fn(&[u8]) -> Option<usize>, sync: &[u8]) -> Vec<(i64, Vec<u8>)> {
+        let mut frames = vec![];
+        loop {
+            if !sync.is_empty() && !self.data.starts_with(sync) {
+                // resynchronize on the next sync word
+                match self.data.windows(sync.len()).position(|w| w == sync) {
+                    Some(skip) => self.consume(skip),
+                    None => {
+                        let keep = self.data.len().min(sync.len() - 1);
+                        self.consume(self.data.len() - keep);
+                        break;
+                    }
+                }
+            }
+            let Some(size) = frame_size(&self.data) else { break };
+            if size == 0 {
+                // invalid header; skip past this sync word
+                self.consume(1);
+                continue;
+            }
+            if self.data.len() < size {
+                break;
+            }
+            let timestamp = self.timestamp_at(0);
+            frames.push((timestamp, self.data[..size].to_vec()));
+            self.consume(size);
+        }
+        frames
+    }
+}
+
+/// MPEG-2 video sequence header fields (ISO/IEC 13818-2 6.2.2.1)
+#[derive(Debug, Clone, Copy, Default)]
+struct SequenceHeader {
+    width: u32,
+    height: u32,
+    aspect_ratio: u8,
+    frame_rate: u8,
+}
+impl SequenceHeader {
+    fn display_size(&self) -> (u32, u32) {
+        match self.aspect_ratio {
+            2 => (self.height * 4 / 3, self.height),
+            3 => (self.height * 16 / 9, self.height),
+            _ => (self.width, self.height),
+        }
+    }
+    /// frame duration in ticks
+    fn frame_duration(&self) -> i64 {
+        match self.frame_rate {
+            1 => 3754, // 23.976
+            2 => 3750, // 24
+            3 => 3600, // 25
+            4 => 3003, // 29.97
+            5 => 3000, // 30
+            _ => 3600,
+        }
+    }
+}
+
+/// Splits MPEG-2 video into frames, each starting with its sequence/GOP header if any
+#[derive(Default)]
+struct VideoFramer {
+    framer: Framer,
+    scan: usize,
+    frame_start: usize,
+    picture: Option<(usize, bool)>,
+    sequence_header: Option<SequenceHeader>,
+}
+impl VideoFramer {
+    /// Returns (timestamp, keyframe, frame)
+    fn frames(&mut self, flush: bool) -> Vec<(i64, bool, Vec<u8>)> {
+        let mut frames = vec![];
+        loop {
+            let data = &self.framer.data;
+            let Some(found) = data[self.scan.min(data.len())..]
+                .windows(3)
+                .position(|w| w == [0, 0, 1])
+            else {
+                self.scan = data.len().saturating_sub(2);
+                break;
+            };
+            let pos = self.scan + found;
+            // need the whole header to read it
+            let Some(&code) = data.get(pos + 3) else {
+                self.scan = pos;
+                break;
+            };
+            if (code == 0xB3 && data.len() < pos + 12) || (code == 0x00 && data.len() < pos + 6) {
+                self.scan = pos;
+                break;
+            }
+            if matches!(code, 0xB3 | 0xB8 | 0x00) {
+                if let Some((picture_pos, keyframe)) = self.picture.take() {
+                    let timestamp = self.framer.timestamp_at(picture_pos);
+                    frames.push((timestamp, keyframe, self.framer.data[self.frame_start..pos].to_vec()));
+                    self.frame_start = pos;
+                }
+            }
+            let data = &self.framer.data;
+            match code {
+                0xB3 => {
+                    let header = SequenceHeader {
+                        width: (data[pos + 4] as u32) << 4 | (data[pos + 5] as u32) >> 4,
+                        height: (data[pos + 5] as u32 & 0x0F) << 8 | data[pos + 6] as u32,
+                        aspect_ratio: data[pos + 7] >> 4,
+                        frame_rate: data[pos + 7] & 0x0F,
+                    };
+                    self.framer.frame_duration = header.frame_duration();
+                    self.sequence_header.get_or_insert(header);
+                }
+                0x00 => {
+                    // picture_coding_type 1 = I picture
+                    let coding_type = (data[pos + 5] >> 3) & 0x07;
+                    self.picture = Some((pos, coding_type == 1));
+                }
+                _ => {}
+            }
+            self.scan = pos + 4;
+        }
+        if flush {
+            if let Some((picture_pos, keyframe)) = self.picture.take() {
+                let timestamp = self.framer.timestamp_at(picture_pos);
+                frames.push((timestamp, keyframe, self.framer.data[self.frame_start..].to_vec()));
+                self.frame_start = self.framer.data.len();
+            }
+        }
+        if self.frame_start > 0 {
+            let consumed = self.frame_start;
+            self.framer.consume(consumed);
+            self.scan -= consumed.min(self.scan);
+            if let Some((picture_pos, _)) = &mut self.picture {
+                *picture_pos -= consumed;
+            }
+            self.frame_start = 0;
+        }
+        frames
+    }
+}
+
+/// AC-3 frame size in bytes at 48 kHz, indexed by frmsizecod (ATSC A/52 table 5.18)
+fn ac3_frame_size(header: &[u8]) -> Option<usize> {
+    const WORDS_48KHZ: [u16; 19] = [
+        64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 640, 768, 896, 1024, 1152, 1280,
+    ];
+    if header.len() < 5 {
+        return None;
+    }
+    let fscod = header[4] >> 6;
+    let frmsizecod = (header[4] & 0x3F) as usize;
+    if fscod != 0 || frmsizecod / 2 >= WORDS_48KHZ.len() {
+        // DVD-Video only allows 48 kHz
+        return Some(0);
+    }
+    Some(WORDS_48KHZ[frmsizecod / 2] as usize * 2)
+}
+
+/// Number of channels of an AC-3 frame from acmod and lfeon (ATSC A/52 5.4.2)
+fn ac3_channels(frame: &[u8]) -> Option<u8> {
+    let bits = u16::from_be_bytes([*frame.get(6)?, *frame.get(7)?]);
+    let acmod = (bits >> 13) as u8;
+    let mut lfe_bit = 12;
+    if acmod & 1 != 0 && acmod != 1 {
+        lfe_bit -= 2;
+    }
+    if acmod & 4 != 0 {
+        lfe_bit -= 2;
+    }
+    if acmod == 2 {
+        lfe_bit -= 2;
+    }
+    let lfeon = (bits >> lfe_bit) & 1;
+    Some([2, 1, 2, 3, 3, 4, 4, 5][acmod as usize] + lfeon as u8)
+}
+
+/// Convert a DVD palette entry (0, Y, Cr, Cb) to RGB
+fn palette_to_rgb(entry: u32) -> u32 {
+    let y = ((entry >> 16) & 0xFF) as f64;
+    let cr = ((entry >> 8) & 0xFF) as f64 - 128.0;
+    let cb = (entry & 0xFF) as f64 - 128.0;
+    let clamp = |v: f64| v.round().clamp(0.0, 255.0) as u32;
+    let r = clamp(y + 1.402 * cr);
+    let g = clamp(y - 0.344136 * cb - 0.714136 * cr);
+    let b = clamp(y + 1.772 * cb);
+    r << 16 | g << 8 | b
+}
+
+enum StreamState {
+    Video(VideoFramer),
+    Ac3 { framer: Framer, channels: Option<u8> },
+    MpegAudio { last_timestamp: i64, channels: u8 },
+    Lpcm { last_timestamp: i64, channels: u8 },
+    Subpicture(Framer),
+}
+
+struct Stream {
+    track_number: u8,
+    state: StreamState,
+}
+
+/// Accepts the sectors of a title through Write and writes them to a Matroska file.
+/// Call finish() after the last sector.
+pub struct VobRemuxer<W: Write + Seek> {
+    writer: MatroskaWriter<W>,
+    pending: Vec<u8>,
+    streams: BTreeMap<StreamKind, Stream>,
+    ignored_streams: Vec<StreamKind>,
+    /// added to PTSs to get the output timeline in ticks
+    offset: Option<i64>,
+    last_vobu_end: Option<i64>,
+    palette: Option<[u32; 16]>,
+    invalid_packs: u64,
+}
+impl<W: Write + Seek> VobRemuxer<W> {
+    /// palette is the subpicture palette of the title's program chain
+    pub fn new(inner: W, palette: Option<[u32; 16]>) -> io::Result<Self> {
+        Ok(Self {
+            writer: MatroskaWriter::new(inner)?,
+            pending: Vec::with_capacity(PACK_SIZE),
+            streams: BTreeMap::new(),
+            ignored_streams: vec![],
+            offset: None,
+            last_vobu_end: None,
+            palette,
+            invalid_packs: 0,
+        })
+    }
+
+    fn process_pack(&mut self, pack: &[u8]) -> io::Result<()> {
+        let packets = match parse_pack(pack) {
+            Ok(packets) => packets,
+            Err(e) => {
+                debug!("VobRemuxer: skipping invalid pack: {}", e);
+                self.invalid_packs += 1;
+                return Ok(());
+            }
+        };
+        for packet in packets {
+            let kind = packet.kind();
+            if kind == StreamKind::Navigation {
+                if let Some((start, end)) = parse_pci_times(packet.payload) {
+                    self.start_vobu(start as i64, end as i64);
+                }
+                continue;
+            }
+            let pts = packet.pts.map(|pts| {
+                let offset = *self.offset.get_or_insert(-(pts as i64));
+                pts as i64 + offset
+            });
+            self.process_packet(kind, pts, packet.payload)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the output timeline continuous when the PTSs jump between VOBUs,
+    /// e.g. between cells of different VOBs
+    fn start_vobu(&mut self, start: i64, end: i64) {
+        match (self.offset, self.last_vobu_end) {
+            (None, _) => self.offset = Some(-start),
+            (Some(offset), Some(last_end)) if start != last_end => {
+                debug!("VobRemuxer: timestamp discontinuity from {} to {}", last_end, start);
+                self.offset = Some(offset + last_end - start);
+            }
+            _ => {}
+        }
+        self.last_vobu_end = Some(end);
+    }
+
+    fn stream(&mut self, kind: StreamKind, payload: &[u8]) -> Option<&mut Stream> {
+        if !self.streams.contains_key(&kind) {
+            let state = match kind {
+                StreamKind::Video(_) => StreamState::Video(VideoFramer::default()),
+                StreamKind::Ac3(_) => StreamState::Ac3 {
+                    framer: Framer {
+                        frame_duration: 2880,
+                        ..Default::default()
+                    },
+                    channels: None,
+                },
+                StreamKind::MpegAudio(_) => StreamState::MpegAudio {
+                    last_timestamp: 0,
+                    channels: match payload.get(3) {
+                        Some(b) if b >> 6 == 3 => 1,
+                        _ => 2,
+                    },
+                },
+                // 16-bit LPCM is the only sample format Matroska can store as-is
+                StreamKind::Lpcm(_) if payload.len() > 6 && payload[5] >> 6 == 0 && (payload[5] >> 4) & 3 == 0 => {
+                    StreamState::Lpcm {
+                        last_timestamp: 0,
+                        channels: (payload[5] & 0x07) + 1,
+                    }
+                }
+                StreamKind::Subpicture(_) => StreamState::Subpicture(Framer::default()),
+                _ => {
+                    if !self.ignored_streams.contains(&kind) {
+                        warn!("Dropping stream {:?}, which cannot be remuxed to Matroska", kind);
+                        self.ignored_streams.push(kind);
+                    }
+                    return None;
+                }
+            };
+            let track_number = self.streams.len() as u8 + 1;
+            self.streams.insert(kind, Stream { track_number, state });
+        }
+        self.streams.get_mut(&kind)
+    }
+
+    fn process_packet(&mut self, kind: StreamKind, pts: Option<i64>, payload: &[u8]) -> io::Result<()> {
+        let Some(stream) = self.stream(kind, payload) else {
+            return Ok(());
+        };
+        let track_number = stream.track_number;
+        let mut blocks: Vec<(i64, bool, Vec<u8>)> = vec![];
+        match &mut stream.state {
+            StreamState::Video(video) => {
+                video.framer.push(payload, pts);
+                blocks = video.frames(false);
+            }
+            StreamState::Ac3 { framer, channels } => {
+                // sub-stream id, number of frames, first access unit pointer
+                framer.push(payload.get(4..).unwrap_or_default(), pts);
+                for (timestamp, frame) in framer.sized_frames(ac3_frame_size, &[0x0B, 0x77]) {
+                    if channels.is_none() {
+                        *channels = ac3_channels(&frame);
+                    }
+                    blocks.push((timestamp, true, frame));
+                }
+            }
+            StreamState::MpegAudio { last_timestamp, .. } => {
+                *last_timestamp = pts.unwrap_or(*last_timestamp);
+                blocks.push((*last_timestamp, true, payload.to_vec()));
+            }
+            StreamState::Lpcm { last_timestamp, .. } => {
+                *last_timestamp = pts.unwrap_or(*last_timestamp);
+                blocks.push((*last_timestamp, true, payload.get(7..).unwrap_or_default().to_vec()));
+            }
+            StreamState::Subpicture(framer) => {
+                framer.push(payload.get(1..).unwrap_or_default(), pts);
+                // each subpicture unit starts with its 16-bit size
+                let spu_size = |data: &[u8]| Some(u16::from_be_bytes([*data.first()?, *data.get(1)?]) as usize);
+                for (timestamp, spu) in framer.sized_frames(spu_size, &[]) {
+                    blocks.push((timestamp, true, spu));
+                }
+            }
+        }
+        for (timestamp, keyframe, data) in blocks {
+            self.writer
+                .write_block(track_number, ticks_to_ms(timestamp), keyframe, &data)?;
+        }
+        Ok(())
+    }
+
+    fn tracks(&self) -> Vec<Track> {
+        let sequence_header = self.streams.values().find_map(|stream| match &stream.state {
+            StreamState::Video(video) => video.sequence_header,
+            _ => None,
+        });
+        let sequence_header = sequence_header.unwrap_or(SequenceHeader {
+            width: 720,
+            height: 480,
+            ..Default::default()
+        });
+        let mut tracks: Vec<Track> = self
+            .streams
+            .values()
+            .map(|stream| {
+                let (codec_id, codec_private, default_duration_ns, kind) = match &stream.state {
+                    StreamState::Video(_) => {
+                        let (display_width, display_height) = sequence_header.display_size();
+                        (
+                            "V_MPEG2",
+                            None,
+                            Some(sequence_header.frame_duration() as u64 * 1_000_000_000 / TICKS_PER_SECOND as u64),
+                            TrackKind::Video {
+                                pixel_width: sequence_header.width,
+                                pixel_height: sequence_header.height,
+                                display_width,
+                                display_height,
+                            },
+                        )
+                    }
+                    StreamState::Ac3 { channels, .. } => (
+                        "A_AC3",
+                        None,
+                        None,
+                        TrackKind::Audio {
+                            sampling_frequency: 48000.0,
+                            channels: channels.unwrap_or(2),
+                            bit_depth: None,
+                        },
+                    ),
+                    StreamState::MpegAudio { channels, .. } => (
+                        "A_MPEG/L2",
+                        None,
+                        None,
+                        TrackKind::Audio {
+                            sampling_frequency: 48000.0,
+                            channels: *channels,
+                            bit_depth: None,
+                        },
+                    ),
+                    StreamState::Lpcm { channels, .. } => (
+                        "A_PCM/INT/BIG",
+                        None,
+                        None,
+                        TrackKind::Audio {
+                            sampling_frequency: 48000.0,
+                            channels: *channels,
+                            bit_depth: Some(16),
+                        },
+                    ),
+                    StreamState::Subpicture(_) => {
+                        let mut idx = format!("size: {}x{}\n", sequence_header.width, sequence_header.height);
+                        if let Some(palette) = &self.palette {
+                            let colors: Vec<String> = palette
+                                .iter()
+                                .map(|&entry| format!("{:06x}", palette_to_rgb(entry)))
+                                .collect();
+                            idx.push_str(&format!("palette: {}\n", colors.join(", ")));
+                        }
+                        ("S_VOBSUB", Some(idx.into_bytes()), None, TrackKind::Subtitle)
+                    }
+                };
+                Track {
+                    number: stream.track_number,
+                    codec_id,
+                    codec_private,
+                    language: None,
+                    default_duration_ns,
+                    kind,
+                }
+            })
+            .collect();
+        tracks.sort_by_key(|track| track.number);
+        tracks
+    }
+
+    /// Write the remaining frames and the Matroska headers
+    pub fn finish(mut self, title: Option<&str>, chapters: &[Chapter]) -> io::Result<W> {
+        let mut blocks = vec![];
+        for stream in self.streams.values_mut() {
+            if let StreamState::Video(video) = &mut stream.state {
+                for (timestamp, keyframe, frame) in video.frames(true) {
+                    blocks.push((stream.track_number, timestamp, keyframe, frame));
+                }
+            }
+        }
+        for (track_number, timestamp, keyframe, frame) in blocks {
+            self.writer
+                .write_block(track_number, ticks_to_ms(timestamp), keyframe, &frame)?;
+        }
+        if self.invalid_packs > 0 {
+            warn!("Skipped {} invalid packs", self.invalid_packs);
+        }
+        let tracks = self.tracks();
+        debug!(
+            "VobRemuxer: {} tracks, timestamp scale {} ns",
+            tracks.len(),
+            TIMESTAMP_SCALE_NS
+        );
+        self.writer.finish(title, &tracks, chapters)
+    }
+}
+impl<W: Write + Seek> Write for VobRemuxer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let take = (PACK_SIZE - self.pending.len()).min(rest.len());
+            self.pending.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.pending.len() == PACK_SIZE {
+                let pack = std::mem::take(&mut self.pending);
+                self.process_pack(&pack)?;
+                self.pending = pack;
+                self.pending.clear();
+            }
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mpeg_ps::{
+        tests::{pack, pes},
+        STREAM_ID_PRIVATE_1,
+    };
+
+    fn sequence_and_picture(coding_type: u8) -> Vec<u8> {
+        let mut es = vec![0, 0, 1, 0xB3, 0x2D, 0x02, 0x40, 0x33, 0xFF, 0xFF, 0xE0, 0x18];
+        es.extend_from_slice(&[0, 0, 1, 0x00, 0x00, coding_type << 3, 0xFF, 0xF8]);
+        es.extend_from_slice(&[0, 0, 1, 0x01, 0x12, 0x34]);
+        es
+    }
+
+    #[test]
+    fn test_remux() {
+        let mut ac3 = vec![0x80, 1, 0, 1];
+        let mut frame = vec![0x0B, 0x77, 0, 0, 0x00, 0x40, 0x40, 0x00];
+        frame.resize(128, 0);
+        ac3.extend_from_slice(&frame);
+        let packs = [
+            pack(&[pes(0xE0, Some(1000), &sequence_and_picture(1))]),
+            pack(&[pes(0xE0, Some(4600), &sequence_and_picture(2)), pes(STREAM_ID_PRIVATE_1, Some(1000), &ac3)]),
+        ]
+        .concat();
+        let mut remuxer = VobRemuxer::new(io::Cursor::new(vec![]), None).unwrap();
+        remuxer.write_all(&packs).unwrap();
+        let tracks = remuxer.tracks();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(
+            tracks[0].kind,
+            TrackKind::Video {
+                pixel_width: 720,
+                pixel_height: 576,
+                display_width: 1024,
+                display_height: 576
+            }
+        );
+        assert_eq!(tracks[1].codec_id, "A_AC3");
+        assert_eq!(
+            tracks[1].kind,
+            TrackKind::Audio {
+                sampling_frequency: 48000.0,
+                channels: 2,
+                bit_depth: None
+            }
+        );
+        let chapters = [Chapter {
+            start_ms: 0,
+            title: "Chapter 1".to_string(),
+        }];
+        let bytes = remuxer.finish(None, &chapters).unwrap().into_inner();
+        let count = |needle: &[u8]| bytes.windows(needle.len()).filter(|w| *w == needle).count();
+        // both pictures became blocks
+        assert_eq!(count(&sequence_and_picture(2)), 1);
+        assert_eq!(count(&sequence_and_picture(1)), 1);
+    }
+
+    #[test]
+    fn test_ac3_channels() {
+        // acmod 7 (3/2) with lfeon: 5.1
+        assert_eq!(ac3_channels(&[0x0B, 0x77, 0, 0, 0, 0, 0xE0 | 0x01, 0]), Some(6));
+        // acmod 2 (2/0) without lfe
+        assert_eq!(ac3_channels(&[0x0B, 0x77, 0, 0, 0, 0, 0x40, 0]), Some(2));
+    }
+}
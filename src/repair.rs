@@ -0,0 +1,443 @@
+//! `repair-image`: walks the descriptors of an image the way a reader finds them (anchors,
+//! volume descriptor sequences, integrity sequence, file sets, and the ICBs and File Identifier
+//! Descriptors of the directory tree) without trusting their tags, and rewrites the CRC length,
+//! CRC, checksum and location of each tag that does not match the descriptor as it is recorded.
+//! Images that other tools edited without updating the tags then read cleanly again.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    crc::cksum,
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    udf::{
+        AnchorVolumeDescriptorPointer, DescriptorTag, ExtendedFileEntry, ExtentAd, FileEntry, FileIdentifierDescriptor,
+        FileSetDescriptor, IndirectEntry, LogicalVolumeDescriptor, LogicalVolumeIntegrityDescriptor, LongAd,
+        PartitionDescriptor, PartitionMap, TerminalEntry, TerminatingDescriptor,
+    },
+};
+
+const BLOCK_SIZE: usize = DVDCSS_BLOCK_SIZE;
+/// ECMA-167 3/10.2
+const ANCHOR_TAG_IDENTIFIER: u16 = 2;
+/// A bound on the integrity extents followed, since a damaged image could link them in a loop
+const MAX_INTEGRITY_EXTENTS: usize = 16;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RepairSummary {
+    /// descriptors whose tags were checked
+    pub descriptors: u64,
+    /// descriptors whose tags were rewritten
+    pub repaired: u64,
+}
+
+/// The length of the descriptor at the start of bytes as given by its tag identifier and
+/// length fields, or None if it is not a descriptor that UDF records
+pub fn descriptor_length(bytes: &[u8]) -> Option<usize> {
+    let u32_at = |offset: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as usize)
+    };
+    let tag = DescriptorTag::read(bytes.get(0..16)?);
+    if !(2..=3).contains(&tag.descriptor_version) {
+        return None;
+    }
+    match tag.tag_identifier {
+        // the Primary, Anchor, Volume Descriptor Pointer, Implementation Use, Partition,
+        // Terminating and File Set Descriptors are fixed size
+        1..=5 | 8 | 256 => Some(512),
+        LogicalVolumeDescriptor::TAG_IDENTIFIER => Some(LogicalVolumeDescriptor::size() + u32_at(264)?),
+        // Unallocated Space Descriptor
+        7 => Some(24 + 8 * u32_at(20)?),
+        LogicalVolumeIntegrityDescriptor::TAG_IDENTIFIER => Some(80 + 8 * u32_at(72)? + u32_at(76)?),
+        FileIdentifierDescriptor::TAG_IDENTIFIER => {
+            let length_of_implementation_use = u16::from_le_bytes(bytes.get(36..38)?.try_into().ok()?) as usize;
+            Some((38 + length_of_implementation_use + *bytes.get(19)? as usize + 3) & !3)
+        }
+        IndirectEntry::TAG_IDENTIFIER => Some(IndirectEntry::size()),
+        TerminalEntry::TAG_IDENTIFIER => Some(TerminalEntry::size()),
+        FileEntry::TAG_IDENTIFIER => Some(176 + u32_at(168)? + u32_at(172)?),
+        ExtendedFileEntry::TAG_IDENTIFIER => Some(216 + u32_at(208)? + u32_at(212)?),
+        _ => None,
+    }
+}
+
+/// Whether the tag of the descriptor at the start of bytes is already consistent. A CRC
+/// length shorter than the descriptor is accepted, since some writers leave out the padding
+/// of File Identifier Descriptors.
+pub(crate) fn tag_is_valid(bytes: &[u8], length: usize, location: u32) -> bool {
+    let tag = DescriptorTag::read(bytes);
+    let crc_length = tag.descriptor_crc_length as usize;
+    tag.tag_checksum == DescriptorTag::checksum(bytes)
+        && tag.tag_location == location
+        && crc_length <= length - DescriptorTag::size()
+        && cksum(&bytes[DescriptorTag::size()..DescriptorTag::size() + crc_length]) == tag.descriptor_crc
+}
+
+/// Rewrite the CRC length, CRC, checksum and location of the tag of the descriptor at the
+/// start of bytes unless it is valid, and return whether they changed
+fn repair_tag(bytes: &mut [u8], length: usize, location: u32) -> bool {
+    if tag_is_valid(bytes, length, location) {
+        return false;
+    }
+    let before: [u8; 16] = bytes[0..16].try_into().unwrap();
+    DescriptorTag::finalize(&mut bytes[..length], location);
+    bytes[0..16] != before
+}
+
+struct Repairer<'a, F> {
+    image: &'a mut F,
+    summary: RepairSummary,
+}
+
+impl<F: Read + Write + Seek> Repairer<'_, F> {
+    fn read_sector(&mut self, sector: u64) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; BLOCK_SIZE];
+        self.image.seek(SeekFrom::Start(sector * BLOCK_SIZE as u64))?;
+        self.image.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_at(&mut self, pos: u64, bytes: &[u8]) -> io::Result<()> {
+        self.image.seek(SeekFrom::Start(pos))?;
+        self.image.write_all(bytes)
+    }
+
+    /// Repair the descriptor at the start of bytes if it is one, and return its tag identifier
+    fn repair(&mut self, bytes: &mut [u8], location: u32) -> Option<u16> {
+        let length = descriptor_length(bytes)?;
+        if length > bytes.len() {
+            warn!("repair-image: the descriptor at {} is longer than its space; leaving it", location);
+            return None;
+        }
+        self.summary.descriptors += 1;
+        if repair_tag(bytes, length, location) {
+            debug!("repair-image: repaired the tag of the descriptor at {}", location);
+            self.summary.repaired += 1;
+        }
+        Some(DescriptorTag::read(bytes).tag_identifier)
+    }
+
+    /// Repair the descriptor in a volume sector, writing it back if it changed
+    fn repair_sector(&mut self, sector: u32) -> io::Result<Option<(u16, Vec<u8>)>> {
+        let mut buf = self.read_sector(sector as u64)?;
+        let repaired = self.summary.repaired;
+        let Some(tag_identifier) = self.repair(&mut buf, sector) else {
+            return Ok(None);
+        };
+        if self.summary.repaired != repaired {
+            self.write_at(sector as u64 * BLOCK_SIZE as u64, &buf)?;
+        }
+        Ok(Some((tag_identifier, buf)))
+    }
+}
+
+/// The partition and where the file sets start, from a Volume Descriptor Sequence
+#[derive(Default)]
+struct VolumeLayout {
+    /// partition number to starting sector
+    partitions: BTreeMap<u16, u32>,
+    /// partition reference numbers of the Type 1 maps to partition numbers
+    partition_maps: Vec<Option<u16>>,
+    file_set: Option<LongAd>,
+    integrity_extent: Option<ExtentAd>,
+}
+
+/// Repair the tags of the descriptors of the UDF image in place
+pub fn repair_image<F: Read + Write + Seek>(image: &mut F) -> io::Result<RepairSummary> {
+    let total_sectors = (image.seek(SeekFrom::End(0))? / BLOCK_SIZE as u64) as u32;
+    let mut repairer = Repairer {
+        image,
+        summary: RepairSummary::default(),
+    };
+
+    // ECMA-167 3/8.4.2.1: anchors at 256, N - 256 and N - 1
+    let mut sequences = vec![];
+    for sector in [256, total_sectors.saturating_sub(256), total_sectors.saturating_sub(1)] {
+        if sector < 256 || sector >= total_sectors {
+            continue;
+        }
+        // N - 256 is usually file data, which must not be changed even if it looks like a descriptor
+        if DescriptorTag::read(&repairer.read_sector(sector as u64)?).tag_identifier != ANCHOR_TAG_IDENTIFIER {
+            continue;
+        }
+        if let Some((ANCHOR_TAG_IDENTIFIER, buf)) = repairer.repair_sector(sector)? {
+            let anchor = AnchorVolumeDescriptorPointer::read(&buf[..AnchorVolumeDescriptorPointer::size()]);
+            for extent in [
+                anchor.main_volume_descriptor_sequence_location,
+                anchor.reserve_volume_descriptor_sequence_location,
+            ] {
+                if !sequences.iter().any(|known: &ExtentAd| known.location_sector == extent.location_sector) {
+                    sequences.push(extent);
+                }
+            }
+        }
+    }
+    if sequences.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no Anchor Volume Descriptor Pointer was found",
+        ));
+    }
+
+    let mut layout = VolumeLayout::default();
+    for extent in &sequences {
+        let end = extent.location_sector + extent.length_bytes / BLOCK_SIZE as u32;
+        for sector in extent.location_sector..end.min(total_sectors) {
+            let Some((tag_identifier, buf)) = repairer.repair_sector(sector)? else {
+                break;
+            };
+            match tag_identifier {
+                PartitionDescriptor::TAG_IDENTIFIER => {
+                    let pd = PartitionDescriptor::read(&buf);
+                    layout.partitions.insert(pd.partition_number, pd.partition_starting_location);
+                }
+                LogicalVolumeDescriptor::TAG_IDENTIFIER => {
+                    let lvd = LogicalVolumeDescriptor::read(&buf[..LogicalVolumeDescriptor::size()]);
+                    let mut maps = &buf[LogicalVolumeDescriptor::size()..];
+                    layout.partition_maps.clear();
+                    for _ in 0..lvd.number_of_partition_maps {
+                        let Ok(map) = PartitionMap::read(maps) else { break };
+                        maps = &maps[map.get_length() as usize..];
+                        layout.partition_maps.push(match map {
+                            PartitionMap::Type1(map) => Some(map.partition_number),
+                            _ => None,
+                        });
+                    }
+                    layout.file_set = Some(LongAd::read(&lvd.logical_volume_contents_use));
+                    layout.integrity_extent = Some(lvd.integrity_sequence_extent);
+                }
+                TerminatingDescriptor::TAG_IDENTIFIER => break,
+                _ => {}
+            }
+        }
+    }
+
+    let mut integrity_extent = layout.integrity_extent.clone().filter(|extent| extent.length_bytes > 0);
+    for _ in 0..MAX_INTEGRITY_EXTENTS {
+        let Some(extent) = integrity_extent.take() else { break };
+        let end = extent.location_sector + extent.length_bytes / BLOCK_SIZE as u32;
+        for sector in extent.location_sector..end.min(total_sectors) {
+            match repairer.repair_sector(sector)? {
+                Some((LogicalVolumeIntegrityDescriptor::TAG_IDENTIFIER, buf)) => {
+                    let next = ExtentAd::read(&buf[32..40]);
+                    integrity_extent = Some(next).filter(|next| next.length_bytes > 0);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    if let Some(file_set) = &layout.file_set {
+        let partition = file_set.extent_location.partition_reference_number;
+        let partition_start = (layout.partition_maps.get(partition as usize).copied().flatten())
+            .and_then(|partition_number| layout.partitions.get(&partition_number).copied());
+        match partition_start {
+            Some(partition_start) => {
+                let mut partition = PartitionRepairer {
+                    repairer: &mut repairer,
+                    start: partition_start,
+                    visited: HashSet::new(),
+                };
+                partition.repair_file_sets(file_set.extent_location.logical_block_number)?;
+            }
+            None => warn!("repair-image: the file set is not on a Type 1 partition; its descriptors were not checked"),
+        }
+    }
+    Ok(repairer.summary)
+}
+
+/// Walks the file structure of a Type 1 partition, where tag locations are logical blocks
+/// relative to the partition start
+struct PartitionRepairer<'a, 'b, F> {
+    repairer: &'a mut Repairer<'b, F>,
+    start: u32,
+    /// ICB blocks already walked, since a damaged directory could refer to an ancestor
+    visited: HashSet<u32>,
+}
+
+impl<F: Read + Write + Seek> PartitionRepairer<'_, '_, F> {
+    fn block_pos(&self, block: u32) -> u64 {
+        (self.start as u64 + block as u64) * BLOCK_SIZE as u64
+    }
+
+    fn read_blocks(&mut self, block: u32, length: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; length];
+        self.repairer.image.seek(SeekFrom::Start(self.block_pos(block)))?;
+        self.repairer.image.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// ECMA-167 4/8.3.1 File Set Descriptor Sequence, ended by a Terminating Descriptor
+    fn repair_file_sets(&mut self, mut block: u32) -> io::Result<()> {
+        loop {
+            let mut buf = self.read_blocks(block, BLOCK_SIZE)?;
+            let repaired = self.repairer.summary.repaired;
+            let tag_identifier = self.repairer.repair(&mut buf, block);
+            if self.repairer.summary.repaired != repaired {
+                self.repairer.write_at(self.block_pos(block), &buf)?;
+            }
+            match tag_identifier {
+                Some(FileSetDescriptor::TAG_IDENTIFIER) => {
+                    let fsd = FileSetDescriptor::read(&buf);
+                    self.repair_icb(fsd.root_directory_icb.extent_location.logical_block_number)?;
+                }
+                _ => return Ok(()),
+            }
+            block += 1;
+        }
+    }
+
+    /// Repair the File Entry at block and, for a directory, its File Identifier Descriptors
+    /// and the entries they refer to
+    fn repair_icb(&mut self, block: u32) -> io::Result<()> {
+        if !self.visited.insert(block) {
+            return Ok(());
+        }
+        let mut buf = self.read_blocks(block, BLOCK_SIZE)?;
+        let (header_length, ea_length, ad_length) = match DescriptorTag::read(&buf).tag_identifier {
+            FileEntry::TAG_IDENTIFIER => (176, u32_at(&buf, 168), u32_at(&buf, 172)),
+            ExtendedFileEntry::TAG_IDENTIFIER => (216, u32_at(&buf, 208), u32_at(&buf, 212)),
+            tag_identifier => {
+                warn!("repair-image: expected a File Entry at block {} but found tag {}", block, tag_identifier);
+                return Ok(());
+            }
+        };
+        let is_directory = buf[16 + 11] == 4;
+        let ad_type = u16::from_le_bytes([buf[16 + 18], buf[16 + 19]]) & 0b111;
+        let ad_start = header_length + ea_length;
+        let Some(ad_end) = Some(ad_start + ad_length).filter(|&end| end <= BLOCK_SIZE) else {
+            warn!("repair-image: the File Entry at block {} is longer than a block; leaving it", block);
+            return Ok(());
+        };
+
+        let mut children = vec![];
+        if is_directory && ad_type == 3 {
+            // the identifiers are embedded in the File Entry, and so are in its block
+            children = self.repair_identifiers(&mut buf[ad_start..ad_end], block);
+        }
+        let repaired = self.repairer.summary.repaired;
+        self.repairer.repair(&mut buf, block);
+        if self.repairer.summary.repaired != repaired {
+            self.repairer.write_at(self.block_pos(block), &buf)?;
+        }
+
+        if is_directory && ad_type != 3 {
+            let ad_size = match ad_type {
+                0 => 8,
+                1 => LongAd::size(),
+                _ => {
+                    warn!("repair-image: unsupported allocation descriptors in the directory at block {}", block);
+                    return Ok(());
+                }
+            };
+            for ad in buf[ad_start..ad_end].chunks_exact(ad_size) {
+                let length_and_type = u32_at(ad, 0);
+                let (extent_type, length) = (length_and_type >> 30, length_and_type & 0x3FFF_FFFF);
+                if extent_type != 0 || length == 0 {
+                    continue;
+                }
+                let extent_block = u32_at(ad, 4) as u32;
+                let mut data = self.read_blocks(extent_block, length)?;
+                let repaired = self.repairer.summary.repaired;
+                children.extend(self.repair_identifiers(&mut data, extent_block));
+                if self.repairer.summary.repaired != repaired {
+                    self.repairer.write_at(self.block_pos(extent_block), &data)?;
+                }
+            }
+        }
+        for child in children {
+            self.repair_icb(child)?;
+        }
+        Ok(())
+    }
+
+    /// Repair the File Identifier Descriptors of directory data starting at block and return
+    /// the ICB blocks of the entries other than the parent
+    fn repair_identifiers(&mut self, data: &mut [u8], block: u32) -> Vec<u32> {
+        let mut children = vec![];
+        let mut offset = 0;
+        while data.len() - offset > DescriptorTag::size() {
+            let location = block + (offset / BLOCK_SIZE) as u32;
+            let fid = &mut data[offset..];
+            if DescriptorTag::read(fid).tag_identifier != FileIdentifierDescriptor::TAG_IDENTIFIER {
+                break;
+            }
+            let Some(length) = descriptor_length(fid) else { break };
+            if self.repairer.repair(fid, location).is_none() {
+                break;
+            }
+            let characteristics = fid[18];
+            let skipped = FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT
+                | FileIdentifierDescriptor::FILE_CHARACTERISTIC_DELETED;
+            if characteristics & skipped == 0 {
+                children.push(LongAd::read(&fid[20..36]).extent_location.logical_block_number);
+            }
+            offset += length;
+        }
+        children
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, time::SystemTime};
+
+    use super::*;
+    use crate::udf_writer::{write_image, ImageOptions, UdfRevision};
+
+    #[test]
+    fn test_repair_image() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-repair-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("VIDEO_TS")).unwrap();
+        std::fs::write(dir.join("VIDEO_TS/VIDEO_TS.IFO"), b"DVDVIDEO-VMG").unwrap();
+        let options = ImageOptions {
+            volume_identifier: "FUNFANCY".to_string(),
+            revision: UdfRevision::Udf102,
+            recording_time: SystemTime::UNIX_EPOCH,
+            iso9660: false,
+        };
+        let mut image = Cursor::new(vec![]);
+        write_image(&dir, &options, &mut image).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let original = image.get_ref().clone();
+
+        // an intact image is left alone
+        let summary = repair_image(&mut image).unwrap();
+        assert_eq!(summary.repaired, 0);
+        // 2 anchors, 2 sequences of 6, the integrity descriptor and its terminator, the file set
+        // and its terminator, and the File Entries and identifiers of /, VIDEO_TS and VIDEO_TS.IFO
+        assert_eq!(summary.descriptors, 2 + 12 + 2 + 2 + 3 + 4);
+        assert_eq!(image.get_ref(), &original);
+
+        let bytes = image.get_mut();
+        let sector = |sector: usize| sector * BLOCK_SIZE;
+        // the Primary Volume Descriptor's location
+        bytes[sector(32) + 12] = 7;
+        // the Logical Volume Descriptor's CRC length
+        bytes[sector(35) + 10] = 0;
+        // the reserve Partition Descriptor's checksum
+        bytes[sector(50) + 4] ^= 0xFF;
+        // the root directory's File Entry, which is edited without updating the CRC
+        bytes[sector(257 + 2) + 48] = 9;
+        let edited_file_entry = bytes[sector(257 + 2)..sector(257 + 3)].to_vec();
+        // the identifier of VIDEO_TS in the root directory
+        bytes[sector(257 + 3) + 40 + 8] ^= 0xFF;
+
+        let summary = repair_image(&mut image).unwrap();
+        assert_eq!(summary.repaired, 5);
+        let bytes = image.get_ref();
+        assert_eq!(bytes[..sector(259)], original[..sector(259)]);
+        // the CRC now covers the edit
+        assert_eq!(bytes[sector(259) + 16..sector(260)], edited_file_entry[16..]);
+        assert_ne!(bytes[sector(259)..sector(260)], original[sector(259)..sector(260)]);
+        assert_eq!(bytes[sector(260)..], original[sector(260)..]);
+    }
+}
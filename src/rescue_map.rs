@@ -0,0 +1,153 @@
+//! Map files in the format of GNU ddrescue, which record which parts of a damaged disc's image
+//! were read, so that a later pass reads only the rest. The map is in bytes; here it is kept in
+//! whole sectors.
+
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    sparse_image::{SectorRange, SectorRanges},
+};
+
+/// The state of the sectors of an image. Those that are neither bad nor untried were read.
+#[derive(Debug, Default, Clone)]
+pub struct RescueMap {
+    pub total_sectors: u32,
+    /// the sectors that could not be read
+    pub bad: SectorRanges,
+    /// the sectors that were not read yet
+    pub untried: SectorRanges,
+}
+
+impl RescueMap {
+    /// The sectors that a later pass should read
+    pub fn to_retry(&self) -> SectorRanges {
+        let mut ranges = self.bad.clone();
+        for range in self.untried.ranges() {
+            ranges.insert(range.start, range.end);
+        }
+        ranges
+    }
+
+    /// Read a map written by ddrescue or write(). The non-trimmed, non-scraped and bad-sector
+    /// blocks of ddrescue are all bad, and a block that covers part of a sector covers all of it.
+    pub fn read(reader: impl BufRead) -> io::Result<Self> {
+        let mut map = RescueMap::default();
+        let mut status_line_seen = false;
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // the first line is the current position, status and pass
+            if !std::mem::replace(&mut status_line_seen, true) {
+                continue;
+            }
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid map file line {:?}", line));
+            let mut fields = line.split_whitespace();
+            let (Some(pos), Some(size), Some(status)) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(invalid());
+            };
+            let (pos, size) = (parse_number(pos).ok_or_else(invalid)?, parse_number(size).ok_or_else(invalid)?);
+            let end = pos.checked_add(size).ok_or_else(invalid)?;
+            let start_sector = u32::try_from(pos / DVDCSS_BLOCK_SIZE as u64).map_err(|_| invalid())?;
+            let end_sector = u32::try_from(end.div_ceil(DVDCSS_BLOCK_SIZE as u64)).map_err(|_| invalid())?;
+            match status {
+                "+" => {}
+                "?" => map.untried.insert(start_sector, end_sector),
+                "*" | "/" | "-" => map.bad.insert(start_sector, end_sector),
+                _ => return Err(invalid()),
+            }
+            map.total_sectors = map.total_sectors.max(end_sector);
+        }
+        if !status_line_seen {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty map file"));
+        }
+        Ok(map)
+    }
+
+    /// Write the map as ddrescue does, with a block for each run of read, bad and untried
+    /// sectors
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let sector_size = DVDCSS_BLOCK_SIZE as u64;
+        writeln!(writer, "# Mapfile. Created by dvdromcopy {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(writer, "# current_pos  current_status  current_pass")?;
+        let status = if self.to_retry().sector_count() == 0 { '+' } else { '-' };
+        writeln!(writer, "0x{:08X}     {}               1", 0, status)?;
+        writeln!(writer, "#      pos        size  status")?;
+        let mut blocks: Vec<(SectorRange, char)> = vec![];
+        let labelled = [(&self.bad, '-'), (&self.untried, '?')];
+        for (ranges, status) in labelled {
+            blocks.extend(ranges.ranges().iter().map(|range| (*range, status)));
+        }
+        let to_retry = self.to_retry();
+        let total_sectors = to_retry.ranges().last().map_or(0, |range| range.end).max(self.total_sectors);
+        let read = to_retry.complement(total_sectors);
+        blocks.extend(read.ranges().iter().map(|range| (*range, '+')));
+        blocks.sort_by_key(|(range, _)| range.start);
+        for (range, status) in blocks {
+            let (pos, size) = (range.start as u64 * sector_size, (range.end - range.start) as u64 * sector_size);
+            writeln!(writer, "0x{:08X}  0x{:08X}  {}", pos, size, status)?;
+        }
+        Ok(())
+    }
+}
+
+/// A position or size of a map file: hexadecimal with 0x, octal with 0 or decimal
+fn parse_number(s: &str) -> Option<u64> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()
+    } else if s.len() > 1 && s.starts_with('0') {
+        u64::from_str_radix(&s[1..], 8).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read() {
+        let mut map = RescueMap {
+            total_sectors: 100,
+            ..Default::default()
+        };
+        map.bad.insert(10, 12);
+        map.bad.insert(50, 51);
+        map.untried.insert(90, 100);
+        let mut written = vec![];
+        map.write(&mut written).unwrap();
+        let text = String::from_utf8(written.clone()).unwrap();
+        assert!(text.contains("0x00005000  0x00001000  -\n"), "{}", text);
+        assert!(text.contains("0x0002D000  0x00005000  ?\n"), "{}", text);
+        assert!(text.ends_with("0x0002D000  0x00005000  ?\n"), "{}", text);
+
+        let read = RescueMap::read(&written[..]).unwrap();
+        assert_eq!(read.total_sectors, 100);
+        assert_eq!(read.bad.ranges(), map.bad.ranges());
+        assert_eq!(read.untried.ranges(), map.untried.ranges());
+        assert_eq!(read.to_retry().sector_count(), 13);
+    }
+
+    #[test]
+    fn test_read_ddrescue_map() {
+        let map = "# Mapfile. Created by GNU ddrescue version 1.27\n\
+                   # current_pos  current_status  current_pass\n\
+                   0x00100000     *               1\n\
+                   #      pos        size  status\n\
+                   0x00000000  0x00100000  +\n\
+                   0x00100000  0x00000200  *\n\
+                   0x00100200  0x00000E00  /\n\
+                   0x00101000  0x000FF000  +\n\
+                   0x00200000  0x00100000  ?\n";
+        let map = RescueMap::read(map.as_bytes()).unwrap();
+        assert_eq!(map.total_sectors, 0x300000 / DVDCSS_BLOCK_SIZE as u32);
+        assert_eq!(map.bad.ranges(), &[SectorRange { start: 512, end: 514 }]);
+        assert_eq!(map.untried.ranges(), &[SectorRange { start: 1024, end: 1536 }]);
+        assert!(RescueMap::read("0 + 1\n0x0 0x800 x\n".as_bytes()).is_err());
+        assert!(RescueMap::read("".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Names of copied files that the host can store. The names on a disc come from whatever
+//! mastered it: a UDF file identifier can be `AUX`, end in a dot or contain `\` or `:`, which
+//! Windows either refuses or silently changes. Each component of a disc path is made safe for
+//! the target OS, renamed components get a `~N` suffix if they would collide with another
+//! entry of their directory, and the renames are kept for a report. UDF names are case
+//! sensitive, so on a case-insensitive output (Windows, and macOS by default) names that
+//! differ only in case collide too and all but the first get a suffix.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use log::warn;
+
+/// The file system rules to name the copied files by
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetOs {
+    /// Also safe for FAT32 and exFAT drives, and SMB shares
+    Windows,
+    Unix,
+}
+impl TargetOs {
+    /// The OS dvdromcopy was built for
+    pub fn host() -> Self {
+        if cfg!(windows) {
+            TargetOs::Windows
+        } else {
+            TargetOs::Unix
+        }
+    }
+}
+
+/// Whether the file system of a directory treats names that differ only in case as the same,
+/// found by creating a file and looking for it under an upper-case name
+pub fn is_case_insensitive(dir: &Path) -> io::Result<bool> {
+    let probe = dir.join(format!(".dvdromcopy-case-{}", std::process::id()));
+    File::create(&probe)?;
+    let result = dir
+        .join(format!(".DVDROMCOPY-CASE-{}", std::process::id()))
+        .try_exists();
+    fs::remove_file(&probe)?;
+    result
+}
+
+/// Characters that Windows does not allow in names, besides the control characters
+const WINDOWS_RESERVED_CHARACTERS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+/// Device names that Windows does not allow as a name, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+    "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A single path component that the target can store, or name itself if it already can
+pub fn sanitize_name(name: &str, target: TargetOs) -> Cow<'_, str> {
+    let mut sanitized: String = match target {
+        TargetOs::Windows => name
+            .chars()
+            .map(|c| {
+                if c.is_control() || WINDOWS_RESERVED_CHARACTERS.contains(&c) {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect(),
+        TargetOs::Unix => name
+            .chars()
+            .map(|c| if c == '/' || c == '\0' { '_' } else { c })
+            .collect(),
+    };
+    if target == TargetOs::Windows {
+        // trailing dots and spaces are dropped by Windows, so "A." would be written as "A"
+        let kept = sanitized.trim_end_matches(['.', ' ']).len();
+        let trailing = sanitized.len() - kept;
+        sanitized.truncate(kept);
+        sanitized.push_str(&"_".repeat(trailing));
+        let stem = sanitized.split('.').next().unwrap_or_default().trim_end();
+        if WINDOWS_RESERVED_NAMES
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        {
+            sanitized.insert(0, '_');
+        }
+    }
+    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
+        sanitized = sanitized.replace('.', "_");
+        sanitized.push('_');
+    }
+    if sanitized == name {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(sanitized)
+    }
+}
+
+/// Maps the '/'-separated disc paths of a copy to the output paths to write them at
+pub struct PathSanitizer {
+    target: TargetOs,
+    case_insensitive: bool,
+    /// the output path of every disc path seen so far, including directories
+    output_paths: BTreeMap<String, String>,
+    /// normalized output paths in use
+    taken: HashSet<String>,
+}
+impl PathSanitizer {
+    /// With case_insensitive, names that differ only in case are treated as the same
+    pub fn new(target: TargetOs, case_insensitive: bool) -> Self {
+        Self {
+            target,
+            case_insensitive,
+            output_paths: BTreeMap::new(),
+            taken: HashSet::new(),
+        }
+    }
+
+    /// The key of an output path in taken
+    fn normalize(&self, output_path: &str) -> String {
+        if self.case_insensitive {
+            output_path.to_lowercase()
+        } else {
+            output_path.to_string()
+        }
+    }
+
+    /// The output path of a disc path; the same disc path always gets the same output path
+    pub fn output_path(&mut self, disc_path: &str) -> String {
+        let disc_path = disc_path.trim_matches('/');
+        let mut output_path = String::new();
+        let mut end = 0;
+        for component in disc_path.split('/') {
+            end += component.len();
+            let prefix = &disc_path[..end];
+            end += 1;
+            if let Some(known) = self.output_paths.get(prefix) {
+                output_path = known.clone();
+                continue;
+            }
+            if !output_path.is_empty() {
+                output_path.push('/');
+            }
+            let parent_length = output_path.len();
+            let name = sanitize_name(component, self.target);
+            output_path.push_str(&name);
+            if matches!(name, Cow::Owned(_)) || self.taken.contains(&self.normalize(&output_path)) {
+                // a renamed name can collide with another entry of the directory, and on a
+                // case-insensitive output so can names that differ only in case
+                let (stem, extension) = match name.rfind('.').filter(|&dot| dot > 0) {
+                    Some(dot) => name.split_at(dot),
+                    None => (&*name, ""),
+                };
+                let mut n = 1;
+                while self.taken.contains(&self.normalize(&output_path)) {
+                    output_path.truncate(parent_length);
+                    output_path.push_str(&format!("{}~{}{}", stem, n, extension));
+                    n += 1;
+                }
+                if n > 1 {
+                    warn!(
+                        "{} has the name of another file of its directory; writing it as {}",
+                        prefix, output_path
+                    );
+                }
+            }
+            self.taken.insert(self.normalize(&output_path));
+            self.output_paths.insert(prefix.to_string(), output_path.clone());
+        }
+        output_path
+    }
+
+    /// The disc paths written under another path, with their output paths
+    pub fn renamed(&self) -> Vec<(String, String)> {
+        (self.output_paths.iter())
+            .filter(|(disc_path, output_path)| disc_path != output_path)
+            .map(|(disc_path, output_path)| (disc_path.clone(), output_path.clone()))
+            .collect()
+    }
+
+    /// Write the renames as lines of disc path, tab and output path
+    pub fn write_report<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (disc_path, output_path) in self.renamed() {
+            writeln!(writer, "{}\t{}", disc_path, output_path)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_name() {
+        let windows = |name| sanitize_name(name, TargetOs::Windows).into_owned();
+        assert!(matches!(
+            sanitize_name("VIDEO_TS.IFO", TargetOs::Windows),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(windows("AUX"), "_AUX");
+        assert_eq!(windows("con.txt"), "_con.txt");
+        assert_eq!(windows("CONSOLE"), "CONSOLE");
+        assert_eq!(windows("README. "), "README__");
+        assert_eq!(windows("A\\B:C?"), "A_B_C_");
+        assert_eq!(windows(".."), "__");
+        assert_eq!(windows(""), "_");
+        assert_eq!(sanitize_name("A\\B:C?", TargetOs::Unix), "A\\B:C?");
+        assert_eq!(sanitize_name("A/B", TargetOs::Unix), "A_B");
+        assert_eq!(sanitize_name("..", TargetOs::Unix), "___");
+    }
+
+    #[test]
+    fn test_path_sanitizer() {
+        let mut sanitizer = PathSanitizer::new(TargetOs::Windows, true);
+        assert_eq!(sanitizer.output_path("VIDEO_TS/VIDEO_TS.IFO"), "VIDEO_TS/VIDEO_TS.IFO");
+        assert_eq!(sanitizer.output_path("EXTRAS/A?.TXT"), "EXTRAS/A_.TXT");
+        // a renamed name does not take the place of a file of the same name
+        assert_eq!(sanitizer.output_path("EXTRAS/A*.TXT"), "EXTRAS/A_~1.TXT");
+        assert_eq!(sanitizer.output_path("EXTRAS/a?.txt"), "EXTRAS/a_~2.txt");
+        // nor do names that differ only in case
+        assert_eq!(
+            sanitizer.output_path("video_ts/VIDEO_TS.BUP"),
+            "video_ts~1/VIDEO_TS.BUP"
+        );
+        assert_eq!(sanitizer.output_path("AUX/CON"), "_AUX/_CON");
+        assert_eq!(sanitizer.output_path("AUX/PRN"), "_AUX/_PRN");
+        assert_eq!(sanitizer.output_path("/EXTRAS/A?.TXT"), "EXTRAS/A_.TXT");
+
+        let mut report = vec![];
+        sanitizer.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "AUX\t_AUX\nAUX/CON\t_AUX/_CON\nAUX/PRN\t_AUX/_PRN\nEXTRAS/A*.TXT\tEXTRAS/A_~1.TXT\n\
+             EXTRAS/A?.TXT\tEXTRAS/A_.TXT\nEXTRAS/a?.txt\tEXTRAS/a_~2.txt\nvideo_ts\tvideo_ts~1\n\
+             video_ts/VIDEO_TS.BUP\tvideo_ts~1/VIDEO_TS.BUP\n"
+        );
+
+        let mut sanitizer = PathSanitizer::new(TargetOs::Unix, false);
+        assert_eq!(sanitizer.output_path("video_ts/A?.TXT"), "video_ts/A?.TXT");
+        assert_eq!(sanitizer.output_path("VIDEO_TS/A?.TXT"), "VIDEO_TS/A?.TXT");
+        assert!(sanitizer.renamed().is_empty());
+
+        // e.g. macOS
+        let mut sanitizer = PathSanitizer::new(TargetOs::Unix, true);
+        assert_eq!(sanitizer.output_path("Readme.txt"), "Readme.txt");
+        assert_eq!(sanitizer.output_path("README.TXT"), "README~1.TXT");
+        assert_eq!(sanitizer.output_path("readme.txt"), "readme~2.txt");
+        assert_eq!(sanitizer.output_path("README.TXT"), "README~1.TXT");
+    }
+
+    #[test]
+    fn test_is_case_insensitive() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-case-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Linux file systems are case sensitive
+        if cfg!(target_os = "linux") {
+            assert!(!is_case_insensitive(&dir).unwrap());
+        }
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
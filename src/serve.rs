@@ -0,0 +1,324 @@
+//! The pieces of `dvdromcopy serve` that do not touch the disc: the tree of
+//! files that is served, HTTP Range headers (RFC 9110 14.2), directory listings,
+//! and the WebDAV PROPFIND responses (RFC 4918) that let it be mounted read-only.
+//! Serving needs the `serve` cargo feature, as the PROPFIND responses here do.
+
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    time::SystemTime,
+};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+
+use crate::udf::LongAd;
+
+/// A file of the disc as it is served
+#[derive(Debug, Clone)]
+pub struct DiscFile {
+    pub icb: LongAd,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// The files of the disc by '/'-separated path; directories are the prefixes of the paths
+#[derive(Debug, Default, Clone)]
+pub struct DiscTree {
+    pub files: BTreeMap<String, DiscFile>,
+}
+
+/// An entry of a directory listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirectoryEntry<'a> {
+    Directory(&'a str),
+    File(&'a str, u64),
+}
+
+impl DiscTree {
+    pub fn insert(&mut self, path: &str, icb: LongAd, size: u64, modified: SystemTime) {
+        self.files.insert(path.to_string(), DiscFile { icb, size, modified });
+    }
+
+    pub fn file(&self, path: &str) -> Option<&DiscFile> {
+        self.files.get(path)
+    }
+
+    /// Whether path ("" for the root) is a directory containing files
+    pub fn is_directory(&self, path: &str) -> bool {
+        path.is_empty() || self.files.keys().any(|file| file.starts_with(&format!("{}/", path)))
+    }
+
+    /// The subdirectories and files directly in directory path ("" for the root), in name order
+    pub fn children(&self, path: &str) -> Vec<DirectoryEntry<'_>> {
+        let prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+        let mut children: Vec<DirectoryEntry> = vec![];
+        for (file_path, file) in self.files.range(prefix.clone()..) {
+            let Some(rest) = file_path.strip_prefix(&prefix) else {
+                break;
+            };
+            let child = match rest.split_once('/') {
+                Some((directory, _)) => DirectoryEntry::Directory(directory),
+                None => DirectoryEntry::File(rest, file.size),
+            };
+            if children.last() != Some(&child) {
+                children.push(child);
+            }
+        }
+        children
+    }
+}
+
+/// The Content-Type of a file of the disc
+pub fn content_type(path: &str) -> &'static str {
+    if path.ends_with(".VOB") {
+        "video/mpeg"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// The parsed Range header of a request for a file of size bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// serve these bytes with 206 Partial Content
+    Satisfiable(Range<u64>),
+    /// respond with 416 Range Not Satisfiable
+    Unsatisfiable,
+}
+
+/// Parse a Range header value. None means that it should be ignored and the whole
+/// file served, which RFC 9110 allows for ranges that are invalid or that have
+/// several parts (which this server does not support)
+pub fn parse_range(value: &str, size: u64) -> Option<RangeRequest> {
+    let spec = value.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let range = if first.is_empty() {
+        // suffix range: the last n bytes
+        let length: u64 = last.parse().ok()?;
+        if length == 0 {
+            return Some(RangeRequest::Unsatisfiable);
+        }
+        size.saturating_sub(length)..size
+    } else {
+        let first: u64 = first.parse().ok()?;
+        let end = match last {
+            "" => size,
+            last => {
+                let last: u64 = last.parse().ok()?;
+                if last < first {
+                    return None;
+                }
+                last.saturating_add(1).min(size)
+            }
+        };
+        first..end
+    };
+    if range.start >= size {
+        return Some(RangeRequest::Unsatisfiable);
+    }
+    Some(RangeRequest::Satisfiable(range))
+}
+
+/// Characters that are escaped in the paths of links
+const PATH_ESCAPED: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?');
+
+/// The disc path of a request URL: percent-decoded, without the query or the leading
+/// and trailing slashes. None if it is not UTF-8 or tries to leave the root
+pub fn url_to_path(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let path = path.trim_matches('/');
+    if path.split('/').any(|component| component == "." || component == "..") {
+        return None;
+    }
+    Some(path.to_string())
+}
+
+/// Percent-encode a disc path for use in a URL
+pub fn path_to_url(path: &str) -> String {
+    utf8_percent_encode(path, PATH_ESCAPED).to_string()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// An HTML page listing the entries of directory path, with relative links
+pub fn directory_listing_html(path: &str, entries: &[DirectoryEntry]) -> String {
+    let title = escape_html(&format!("/{}", path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body><h1>{0}</h1>\n<ul>\n",
+        title
+    );
+    if !path.is_empty() {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for entry in entries {
+        let (href, text) = match entry {
+            DirectoryEntry::Directory(name) => (format!("{}/", path_to_url(name)), format!("{}/", name)),
+            DirectoryEntry::File(name, size) => (path_to_url(name), format!("{} ({} bytes)", name, size)),
+        };
+        html.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            escape_html(&href),
+            escape_html(&text)
+        ));
+    }
+    html.push_str("</ul>\n</body></html>\n");
+    html
+}
+
+/// The 207 Multi-Status body of a PROPFIND of path with all properties, or None if
+/// there is no such file or directory. Depth 0 describes only path itself; otherwise the
+/// children of a directory are included too (Depth: infinity is treated as 1)
+#[cfg(feature = "serve")]
+pub fn propfind_multistatus(tree: &DiscTree, path: &str, include_children: bool) -> Option<String> {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    if let Some(file) = tree.file(path) {
+        push_dav_response(&mut xml, path, Some(file));
+    } else if tree.is_directory(path) {
+        push_dav_response(&mut xml, path, None);
+        if include_children {
+            for child in tree.children(path) {
+                let name = match child {
+                    DirectoryEntry::Directory(name) | DirectoryEntry::File(name, _) => name,
+                };
+                let child_path = if path.is_empty() { name.to_string() } else { format!("{}/{}", path, name) };
+                push_dav_response(&mut xml, &child_path, tree.file(&child_path));
+            }
+        }
+    } else {
+        return None;
+    }
+    xml.push_str("</D:multistatus>\n");
+    Some(xml)
+}
+
+/// time as an HTTP date, or None if it is before 1970, as the unset timestamps of many discs
+/// are, or after 9999, which HTTP dates cannot be
+#[cfg(feature = "serve")]
+pub fn http_date(time: SystemTime) -> Option<String> {
+    const LAST_HTTP_DATE: std::time::Duration = std::time::Duration::from_secs(253_402_300_799);
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+    (since_epoch <= LAST_HTTP_DATE).then(|| httpdate::fmt_http_date(time))
+}
+
+/// The response element of a file, or of a directory if file is None
+#[cfg(feature = "serve")]
+fn push_dav_response(xml: &mut String, path: &str, file: Option<&DiscFile>) {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let href = match file {
+        Some(_) => format!("/{}", path_to_url(path)),
+        None if path.is_empty() => "/".to_string(),
+        None => format!("/{}/", path_to_url(path)),
+    };
+    let properties = match file {
+        Some(file) => format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength><D:getcontenttype>{}</D:getcontenttype>{}",
+            file.size,
+            content_type(path),
+            (http_date(file.modified))
+                .map(|date| format!("<D:getlastmodified>{}</D:getlastmodified>", date))
+                .unwrap_or_default()
+        ),
+        None => "<D:resourcetype><D:collection/></D:resourcetype>".to_string(),
+    };
+    xml.push_str(&format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>{}</D:prop>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape_html(&href),
+        escape_html(name),
+        properties
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_range() {
+        let satisfiable = |range| Some(RangeRequest::Satisfiable(range));
+        assert_eq!(parse_range("bytes=0-99", 1000), satisfiable(0..100));
+        assert_eq!(parse_range("bytes=900-", 1000), satisfiable(900..1000));
+        assert_eq!(parse_range("bytes=900-5000", 1000), satisfiable(900..1000));
+        assert_eq!(parse_range("bytes=-100", 1000), satisfiable(900..1000));
+        assert_eq!(parse_range("bytes=-5000", 1000), satisfiable(0..1000));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(RangeRequest::Unsatisfiable));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(RangeRequest::Unsatisfiable));
+        // ignored: invalid, other units, and multiple ranges
+        assert_eq!(parse_range("bytes=5-1", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), None);
+    }
+
+    fn tree() -> DiscTree {
+        let mut tree = DiscTree::default();
+        for (path, size) in [("VIDEO_TS/VIDEO_TS.IFO", 2048), ("VIDEO_TS/VTS_01_1.VOB", 4096), ("AUDIO_TS/A", 1)] {
+            tree.insert(path, LongAd::default(), size, SystemTime::UNIX_EPOCH);
+        }
+        // an unset timestamp
+        let year_0 = SystemTime::UNIX_EPOCH - Duration::from_secs(62_167_219_200);
+        tree.insert("README.TXT", LongAd::default(), 10, year_0);
+        tree
+    }
+
+    #[test]
+    fn test_disc_tree() {
+        let tree = tree();
+        assert_eq!(
+            tree.children(""),
+            vec![
+                DirectoryEntry::Directory("AUDIO_TS"),
+                DirectoryEntry::File("README.TXT", 10),
+                DirectoryEntry::Directory("VIDEO_TS"),
+            ]
+        );
+        assert_eq!(
+            tree.children("VIDEO_TS"),
+            vec![
+                DirectoryEntry::File("VIDEO_TS.IFO", 2048),
+                DirectoryEntry::File("VTS_01_1.VOB", 4096)
+            ]
+        );
+        assert!(tree.is_directory("VIDEO_TS"));
+        assert!(!tree.is_directory("VIDEO"));
+        assert_eq!(tree.file("VIDEO_TS/VTS_01_1.VOB").map(|file| file.size), Some(4096));
+    }
+
+    #[test]
+    fn test_urls() {
+        assert_eq!(url_to_path("/VIDEO_TS/"), Some("VIDEO_TS".to_string()));
+        assert_eq!(url_to_path("/A%20B/C?x=1"), Some("A B/C".to_string()));
+        assert_eq!(url_to_path("/"), Some(String::new()));
+        assert_eq!(url_to_path("/VIDEO_TS/../etc"), None);
+        assert_eq!(path_to_url("A B#1.txt"), "A%20B%231.txt");
+        let html = directory_listing_html("VIDEO_TS", &[DirectoryEntry::File("<A>", 1)]);
+        assert!(html.contains("<a href=\"%3CA%3E\">&lt;A&gt; (1 bytes)</a>"));
+    }
+
+    #[cfg(feature = "serve")]
+    #[test]
+    fn test_propfind_multistatus() {
+        let tree = tree();
+        let xml = propfind_multistatus(&tree, "VIDEO_TS", true).unwrap();
+        assert_eq!(xml.matches("<D:response>").count(), 3);
+        assert!(xml.contains("<D:href>/VIDEO_TS/</D:href><D:propstat><D:prop><D:displayname>VIDEO_TS</D:displayname><D:resourcetype><D:collection/></D:resourcetype>"));
+        assert!(xml.contains("<D:href>/VIDEO_TS/VTS_01_1.VOB</D:href>"));
+        assert!(xml.contains("<D:getcontentlength>4096</D:getcontentlength><D:getcontenttype>video/mpeg</D:getcontenttype><D:getlastmodified>Thu, 01 Jan 1970 00:00:00 GMT</D:getlastmodified>"));
+        // the root's children, and depth 0
+        assert_eq!(propfind_multistatus(&tree, "", true).unwrap().matches("<D:response>").count(), 4);
+        assert_eq!(propfind_multistatus(&tree, "", false).unwrap().matches("<D:response>").count(), 1);
+        let readme = propfind_multistatus(&tree, "README.TXT", true).unwrap();
+        assert!(readme.contains("<D:href>/README.TXT</D:href>"));
+        assert!(!readme.contains("getlastmodified"));
+        assert_eq!(propfind_multistatus(&tree, "VIDEO", true), None);
+    }
+}
@@ -0,0 +1,246 @@
+//! An SFTP server as an output (`--output sftp://[user@]host[:port]/path`), for copying
+//! onto a NAS without mounting it. As with local output, each file is written to
+//! `<name>.partial` and renamed when complete. A `.partial` file left on the server by an
+//! interrupted copy is overwritten, since --resume needs a local output to check it.
+//! The upload itself needs the `sftp` cargo feature.
+
+/// Where the files go on an SFTP server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SftpLocation {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: u16,
+    /// absolute directory on the server without trailing '/', empty for the root
+    pub path: String,
+}
+impl SftpLocation {
+    /// Parse `sftp://[user@]host[:port][/path]`; an IPv6 host is written in brackets
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("sftp://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user.to_string()), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let (host, after) = bracketed.split_once(']')?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => 22,
+        };
+        Some(Self {
+            user,
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// The server path of a '/'-separated path below the directory
+    pub fn remote_path(&self, path: &str) -> String {
+        format!("{}/{}", self.path, path.trim_start_matches('/'))
+    }
+}
+
+#[cfg(feature = "sftp")]
+pub use upload::SftpOutput;
+
+#[cfg(feature = "sftp")]
+mod upload {
+    use std::io::{self, Write};
+    use std::net::TcpStream;
+    use std::path::{Path, PathBuf};
+
+    use log::debug;
+    use ssh2::{CheckResult, FileStat, KnownHostFileKind, OpenFlags, OpenType, Session, Sftp};
+
+    use super::*;
+    use crate::output::{OutputFile, OutputSink, PARTIAL_SUFFIX};
+
+    /// Files in a directory of an SFTP server
+    pub struct SftpOutput {
+        location: SftpLocation,
+        sftp: Sftp,
+        // the session must outlive sftp
+        _session: Session,
+    }
+    impl SftpOutput {
+        /// Connect, check the server's key against ~/.ssh/known_hosts and log in with
+        /// the ssh agent or the default key files in ~/.ssh
+        pub fn new(location: SftpLocation) -> io::Result<Self> {
+            let stream = TcpStream::connect((location.host.as_str(), location.port))?;
+            let mut session = Session::new()?;
+            session.set_tcp_stream(stream);
+            session.handshake()?;
+            let ssh_dir = std::env::var_os("HOME").map_or_else(PathBuf::new, |home| PathBuf::from(home).join(".ssh"));
+            check_host_key(&session, &location, &ssh_dir.join("known_hosts"))?;
+
+            let user = match &location.user {
+                Some(user) => user.clone(),
+                None => std::env::var("USER").map_err(|_| io::Error::other("no user in the sftp:// URL or $USER"))?,
+            };
+            if let Err(e) = session.userauth_agent(&user) {
+                debug!("SftpOutput: ssh agent authentication failed: {}", e);
+                for key in ["id_ed25519", "id_ecdsa", "id_rsa"].map(|name| ssh_dir.join(name)) {
+                    if key.exists() && session.userauth_pubkey_file(&user, None, &key, None).is_ok() {
+                        break;
+                    }
+                }
+            }
+            if !session.authenticated() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("could not log in to {} as {} with the ssh agent or ~/.ssh keys", location.host, user),
+                ));
+            }
+            let sftp = session.sftp()?;
+            Ok(Self {
+                location,
+                sftp,
+                _session: session,
+            })
+        }
+
+        fn stat(&self, path: &str) -> io::Result<Option<FileStat>> {
+            match self.sftp.stat(Path::new(path)) {
+                Ok(stat) => Ok(Some(stat)),
+                Err(e) => match io::Error::from(e) {
+                    e if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    e => Err(e),
+                },
+            }
+        }
+
+        /// mkdir -p the parent directories of remote_path
+        fn create_parents(&self, remote_path: &str) -> io::Result<()> {
+            let Some((parent, _)) = remote_path.rsplit_once('/') else {
+                return Ok(());
+            };
+            if parent.is_empty() || self.stat(parent)?.is_some() {
+                return Ok(());
+            }
+            self.create_parents(parent)?;
+            self.sftp.mkdir(Path::new(parent), 0o755)?;
+            Ok(())
+        }
+    }
+
+    fn check_host_key(session: &Session, location: &SftpLocation, known_hosts_path: &Path) -> io::Result<()> {
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| io::Error::other("the server sent no host key"))?;
+        let mut known_hosts = session.known_hosts()?;
+        if known_hosts_path.exists() {
+            known_hosts.read_file(known_hosts_path, KnownHostFileKind::OpenSSH)?;
+        }
+        match known_hosts.check_port(&location.host, location.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(io::Error::other(format!(
+                "the host key of {} does not match {}",
+                location.host,
+                known_hosts_path.display()
+            ))),
+            CheckResult::NotFound | CheckResult::Failure => Err(io::Error::other(format!(
+                "{} is not in {}; connect to it with ssh once to add it",
+                location.host,
+                known_hosts_path.display()
+            ))),
+        }
+    }
+
+    impl OutputSink for SftpOutput {
+        fn exists(&self, path: &str) -> io::Result<bool> {
+            Ok(self.stat(&self.location.remote_path(path))?.is_some())
+        }
+        fn create(&self, path: &str) -> io::Result<Box<dyn OutputFile + '_>> {
+            let output_path = self.location.remote_path(path);
+            self.create_parents(&output_path)?;
+            let partial_output_path = format!("{}{}", output_path, PARTIAL_SUFFIX);
+            let file = self.sftp.open_mode(
+                Path::new(&partial_output_path),
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                0o644,
+                OpenType::File,
+            )?;
+            Ok(Box::new(SftpFile {
+                output: self,
+                file,
+                partial_output_path,
+                output_path,
+            }))
+        }
+        fn local_dir(&self) -> Option<&Path> {
+            None
+        }
+        fn describe(&self, path: &str) -> String {
+            format!("sftp://{}{}", self.location.host, self.location.remote_path(path))
+        }
+    }
+
+    /// Written to `<name>.partial` and renamed when finished
+    struct SftpFile<'a> {
+        output: &'a SftpOutput,
+        file: ssh2::File,
+        partial_output_path: String,
+        output_path: String,
+    }
+    impl Write for SftpFile<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.file.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.file.flush()
+        }
+    }
+    impl OutputFile for SftpFile<'_> {
+        fn finish(mut self: Box<Self>) -> io::Result<()> {
+            self.file.close()?;
+            self.output.sftp.rename(
+                Path::new(&self.partial_output_path),
+                Path::new(&self.output_path),
+                None,
+            )?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        assert_eq!(
+            SftpLocation::parse("sftp://nas/volume1/Movies/"),
+            Some(SftpLocation {
+                user: None,
+                host: "nas".to_string(),
+                port: 22,
+                path: "/volume1/Movies".to_string(),
+            })
+        );
+        let location = SftpLocation::parse("sftp://me@[fe80::1]:2222").unwrap();
+        assert_eq!(location.user.as_deref(), Some("me"));
+        assert_eq!(location.host, "fe80::1");
+        assert_eq!(location.port, 2222);
+        assert_eq!(location.remote_path("Disc/VIDEO_TS"), "/Disc/VIDEO_TS");
+        assert_eq!(SftpLocation::parse("sftp://me@nas:22/x").unwrap().remote_path("/a"), "/x/a");
+        assert_eq!(SftpLocation::parse("sftp:///x"), None);
+        assert_eq!(SftpLocation::parse("sftp://nas:ssh/x"), None);
+        assert_eq!(SftpLocation::parse("s3://bucket"), None);
+    }
+}
@@ -0,0 +1,49 @@
+//! Metadata about a copied disc that is not part of the disc itself,
+//! written as JSON next to the copy's directory.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::title_keys::TitleKey;
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Sidecar {
+    /// the Windows DVD ID as formatted by dvdid, for matching against online metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dvd_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub title_keys: Vec<TitleKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_times: Option<VolumeTimes>,
+}
+
+/// When the disc was mastered, as the ISO 8601 times of its descriptors; a time that is not
+/// recorded or could not be read is left out
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct VolumeTimes {
+    /// of the Primary Volume Descriptor, which the copy's directory is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recorded: Option<String>,
+    /// of the Logical Volume Integrity Descriptor, when the volume was last written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity_recorded: Option<String>,
+    /// of the File Set Descriptor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_set_recorded: Option<String>,
+}
+impl Sidecar {
+    /// `<name>.dvdromcopy.json` next to the copy's directory `<name>`,
+    /// so that it is not mistaken for a file of the disc
+    pub fn file_name(dvd_name: &str) -> String {
+        format!("{}.dvdromcopy.json", dvd_name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dvd_id.is_none() && self.title_keys.is_empty() && self.volume_times.is_none()
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)
+    }
+}
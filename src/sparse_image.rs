@@ -0,0 +1,139 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use log::debug;
+
+use crate::dvdcss_sys::DVDCSS_BLOCK_SIZE;
+
+/// Half-open range of 2048-byte sectors `start..end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Sorted, non-overlapping set of sector ranges
+#[derive(Debug, Default, Clone)]
+pub struct SectorRanges {
+    ranges: Vec<SectorRange>,
+}
+impl SectorRanges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, start: u32, end: u32) {
+        if start >= end {
+            return;
+        }
+        let i = self.ranges.partition_point(|r| r.end < start);
+        let mut merged = SectorRange { start, end };
+        while i < self.ranges.len() && self.ranges[i].start <= merged.end {
+            let r = self.ranges.remove(i);
+            merged.start = merged.start.min(r.start);
+            merged.end = merged.end.max(r.end);
+        }
+        self.ranges.insert(i, merged);
+    }
+    /// Take the sectors start..end out of the ranges
+    pub fn remove(&mut self, start: u32, end: u32) {
+        if start >= end {
+            return;
+        }
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        for r in self.ranges.drain(..) {
+            if r.end <= start || r.start >= end {
+                ranges.push(r);
+                continue;
+            }
+            if r.start < start {
+                ranges.push(SectorRange { start: r.start, end: start });
+            }
+            if r.end > end {
+                ranges.push(SectorRange { start: end, end: r.end });
+            }
+        }
+        self.ranges = ranges;
+    }
+    pub fn ranges(&self) -> &[SectorRange] {
+        &self.ranges
+    }
+    pub fn sector_count(&self) -> u64 {
+        self.ranges.iter().map(|r| (r.end - r.start) as u64).sum()
+    }
+    /// The ranges of 0..total_sectors that are not in self
+    pub fn complement(&self, total_sectors: u32) -> SectorRanges {
+        let mut result = SectorRanges::new();
+        let mut pos = 0;
+        for r in &self.ranges {
+            if r.start >= total_sectors {
+                break;
+            }
+            result.insert(pos, r.start);
+            pos = r.end;
+        }
+        result.insert(pos, total_sectors);
+        result
+    }
+}
+
+/// Write an image of total_sectors sectors where only the given ranges
+/// are copied from reader; everything else is left as a hole.
+/// Sectors keep their original offsets so that the image has the same layout as the disc.
+pub fn write_sparse_image<R: Read + Seek, W: Write + Seek>(
+    reader: &mut R,
+    output: &mut W,
+    total_sectors: u32,
+    populated: &SectorRanges,
+) -> io::Result<()> {
+    const SECTORS_PER_READ: u32 = 512;
+    let mut buf = vec![0u8; SECTORS_PER_READ as usize * DVDCSS_BLOCK_SIZE];
+    for range in populated.ranges() {
+        let end = range.end.min(total_sectors);
+        debug!("write_sparse_image: copying sectors {}..{}", range.start, end);
+        let mut sector = range.start;
+        while sector < end {
+            let count = (end - sector).min(SECTORS_PER_READ);
+            let slice = &mut buf[..count as usize * DVDCSS_BLOCK_SIZE];
+            let pos = sector as u64 * DVDCSS_BLOCK_SIZE as u64;
+            reader.seek(SeekFrom::Start(pos))?;
+            reader.read_exact(slice)?;
+            output.seek(SeekFrom::Start(pos))?;
+            output.write_all(slice)?;
+            sector += count;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sector_ranges() {
+        let mut ranges = SectorRanges::new();
+        ranges.insert(10, 20);
+        ranges.insert(30, 40);
+        ranges.insert(18, 25);
+        ranges.insert(25, 30);
+        ranges.insert(50, 50);
+        assert_eq!(ranges.ranges(), &[SectorRange { start: 10, end: 40 }]);
+        ranges.insert(60, 70);
+        assert_eq!(ranges.sector_count(), 40);
+        assert_eq!(
+            ranges.complement(65).ranges(),
+            &[SectorRange { start: 0, end: 10 }, SectorRange { start: 40, end: 60 }]
+        );
+        ranges.remove(15, 20);
+        ranges.remove(35, 65);
+        ranges.remove(0, 5);
+        ranges.remove(80, 80);
+        assert_eq!(
+            ranges.ranges(),
+            &[
+                SectorRange { start: 10, end: 15 },
+                SectorRange { start: 20, end: 35 },
+                SectorRange { start: 65, end: 70 }
+            ]
+        );
+    }
+}
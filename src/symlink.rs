@@ -0,0 +1,138 @@
+//! Symbolic links on the disc (ECMA-167 4/8.7): File Entries of type 12 whose data is the
+//! sequence of Path Components (4/14.16) that leads to the target. A copy gets a link to the
+//! same place in the copy, or on hosts without symbolic links, a text file that holds the
+//! target.
+
+use std::{io, path::Path};
+
+use crate::udf::PathComponent;
+
+/// The Path Components in the data of a symbolic link
+pub fn read_path_components(data: &[u8]) -> io::Result<Vec<PathComponent>> {
+    let mut components = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let component = PathComponent::read(&data[pos..])?;
+        pos += component.size();
+        components.push(component);
+    }
+    Ok(components)
+}
+
+/// The '/'-separated target of a symbolic link that is depth directories below the root of
+/// the disc, such as `../VIDEO_TS/VTS_01_1.VOB`. A target from the root of the disc is made
+/// relative, so that the link points into the copy instead of the root of the host.
+pub fn link_target(components: &[PathComponent], depth: usize) -> io::Result<String> {
+    let mut parts: Vec<String> = vec![];
+    for component in components {
+        match component.component_type {
+            PathComponent::TYPE_AGREED_ROOT | PathComponent::TYPE_ROOT => parts = vec!["..".to_string(); depth],
+            PathComponent::TYPE_PARENT => parts.push("..".to_string()),
+            PathComponent::TYPE_CURRENT => {}
+            PathComponent::TYPE_NAMED => {
+                let name = component.component_identifier.to_string();
+                // a name must not add components of its own
+                if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("symbolic link with the path component {:?}", name),
+                    ));
+                }
+                parts.push(name);
+            }
+            component_type => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("symbolic link with a path component of type {}", component_type),
+                ))
+            }
+        }
+    }
+    if parts.is_empty() {
+        return Ok(".".to_string());
+    }
+    Ok(parts.join("/"))
+}
+
+/// Create a symbolic link at path to target
+#[cfg(unix)]
+pub fn create_symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Create a symbolic link at path to target
+#[cfg(not(unix))]
+pub fn create_symlink(_target: &str, _path: &Path) -> io::Result<()> {
+    // Windows only lets administrators and developer mode create them
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links can only be created on Unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::udf::DynamicDstring;
+
+    fn component(component_type: u8, name: &str) -> PathComponent {
+        let component_identifier = match name {
+            "" => DynamicDstring::default(),
+            name => DynamicDstring::from_str(name),
+        };
+        PathComponent {
+            component_type,
+            length_of_component_identifier: component_identifier.0.len() as u8,
+            component_file_version_number: 0,
+            component_identifier,
+        }
+    }
+
+    fn data(components: &[PathComponent]) -> Vec<u8> {
+        let mut data = vec![0u8; components.iter().map(PathComponent::size).sum()];
+        let mut pos = 0;
+        for component in components {
+            component.write(&mut data[pos..]);
+            pos += component.size();
+        }
+        data
+    }
+
+    #[test]
+    fn test_link_target() {
+        let relative = [
+            component(PathComponent::TYPE_PARENT, ""),
+            component(PathComponent::TYPE_CURRENT, ""),
+            component(PathComponent::TYPE_NAMED, "VIDEO_TS"),
+            component(PathComponent::TYPE_NAMED, "VTS_01_1.VOB"),
+        ];
+        let components = read_path_components(&data(&relative)).unwrap();
+        assert_eq!(components, relative);
+        assert_eq!(link_target(&components, 1).unwrap(), "../VIDEO_TS/VTS_01_1.VOB");
+
+        let from_root = [
+            component(PathComponent::TYPE_ROOT, ""),
+            component(PathComponent::TYPE_NAMED, "AUDIO_TS"),
+        ];
+        assert_eq!(link_target(&from_root, 2).unwrap(), "../../AUDIO_TS");
+        assert_eq!(link_target(&from_root, 0).unwrap(), "AUDIO_TS");
+        assert_eq!(link_target(&from_root[..1], 0).unwrap(), ".");
+
+        let escaping = [component(PathComponent::TYPE_NAMED, "../../etc")];
+        assert_eq!(link_target(&escaping, 0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let truncated = data(&relative[2..]);
+        assert_eq!(
+            read_path_components(&truncated[..truncated.len() - 1]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_symlink() {
+        let path = std::env::temp_dir().join(format!("dvdromcopy-test-symlink-{}", std::process::id()));
+        create_symlink("VIDEO_TS/VIDEO_TS.IFO", &path).unwrap();
+        assert_eq!(std::fs::read_link(&path).unwrap(), Path::new("VIDEO_TS/VIDEO_TS.IFO"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
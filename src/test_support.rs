@@ -0,0 +1,441 @@
+//! Small in-memory UDF images for tests, here and in crates that build on this one (with the
+//! `test-support` feature). The images are written as makeiso writes them and then their File
+//! Entries are altered to record files the ways other mastering tools do, or to be damaged, so
+//! that the parser's handling of each can be tested without a disc. Only files are recorded
+//! differently, so that the directory tree can always be walked.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor},
+    time::SystemTime,
+};
+
+use crate::{
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    udf::{
+        AllocationDescriptorType, AnyFileEntry, DescriptorTag, EntityID, ExtendedFileEntry, FileEntry,
+        FileIdentifierDescriptor, FileType, GenericPartitionMapHeader, LbAddr, LogicalVolumeDescriptor, LongAd,
+        PartitionMap, SparablePartitionMap, SparingMapEntry, SparingTable, Type2PartitionMap,
+        SPARABLE_PARTITION_IDENTIFIER,
+    },
+    udf_parser::{parse_file_identifiers, read_short_allocation_descriptors},
+    udf_writer::{
+        write_image_of_files, ImageOptions, SourceFile, UdfRevision, FILE_SET_BLOCKS, PARTITION_START_SECTOR,
+    },
+};
+
+const BLOCK_SIZE: usize = DVDCSS_BLOCK_SIZE;
+/// ECMA-167 4/A.6: an ICB of a direct entry followed by an indirect entry to the next
+const STRATEGY_4096: u16 = 4096;
+
+/// A file to record in a fixture image
+pub struct FixtureFile<'a> {
+    /// '/'-separated, e.g. VIDEO_TS/VIDEO_TS.IFO
+    pub path: &'a str,
+    pub contents: &'a [u8],
+}
+
+/// How the File Entries of the files differ from what makeiso writes
+#[derive(Clone, Debug, Default)]
+pub struct Quirks {
+    /// extents are recorded with long allocation descriptors (ECMA-167 4/14.14.2), which UDF
+    /// allows on other media than DVD
+    pub long_ads: bool,
+    /// files that fit are recorded inside their File Entries (ECMA-167 4/14.6.8); their
+    /// blocks are zeroed
+    pub embedded_data: bool,
+    /// files are recorded with Extended File Entries (ECMA-167 4/14.17), as UDF 2.00 and later
+    /// record them
+    pub extended_file_entries: bool,
+    /// ICBs use strategy 4096 instead of 4
+    pub strategy_4096: bool,
+    /// paths of the files or directories ("" for the root) whose File Entries have a wrong
+    /// descriptor CRC, with the tag checksum still right
+    pub broken_crcs: Vec<String>,
+}
+
+/// Write an image of files, recorded with quirks, and return its bytes
+pub fn build_image(files: &[FixtureFile], volume_identifier: &str, quirks: &Quirks) -> io::Result<Vec<u8>> {
+    let source_files: Vec<SourceFile> = files
+        .iter()
+        .map(|file| SourceFile {
+            path: file.path,
+            length: file.contents.len() as u64,
+            modified: SystemTime::UNIX_EPOCH,
+        })
+        .collect();
+    let contents: BTreeMap<&str, &[u8]> = files
+        .iter()
+        .map(|file| (file.path.trim_start_matches('/'), file.contents))
+        .collect();
+    let options = ImageOptions {
+        volume_identifier: volume_identifier.to_string(),
+        revision: UdfRevision::Udf102,
+        recording_time: SystemTime::UNIX_EPOCH,
+        iso9660: false,
+    };
+    let mut image = Cursor::new(vec![]);
+    write_image_of_files(&source_files, &options, &mut image, |path, writer| {
+        writer.write_all(contents[path])?;
+        Ok(contents[path].len() as u64)
+    })?;
+    let mut image = image.into_inner();
+
+    for (path, block) in file_entry_blocks(&image)? {
+        let start = block_offset(block);
+        let mut file_entry = FileEntry::read(&image[start..start + BLOCK_SIZE])?;
+        let is_file = !matches!(file_entry.icb_tag.file_type(), FileType::Directory);
+        if is_file {
+            alter_file_entry(&mut image, &mut file_entry, contents[path.as_str()], quirks);
+        }
+        let entry = &mut image[start..start + BLOCK_SIZE];
+        entry.fill(0);
+        let length = if is_file && quirks.extended_file_entries {
+            let file_entry = extended_file_entry(file_entry);
+            file_entry.write(&mut entry[..file_entry.get_length()]);
+            file_entry.get_length()
+        } else {
+            file_entry.write(&mut entry[..file_entry.get_length()]);
+            file_entry.get_length()
+        };
+        DescriptorTag::finalize(&mut entry[..length], block);
+        if quirks.broken_crcs.contains(&path) {
+            entry[8] ^= 0xFF;
+            entry[4] = DescriptorTag::checksum(entry);
+        }
+    }
+    Ok(image)
+}
+
+fn alter_file_entry(image: &mut [u8], file_entry: &mut FileEntry, contents: &[u8], quirks: &Quirks) {
+    let extents = read_short_allocation_descriptors(&file_entry.allocation_descriptors);
+    // an Extended File Entry is 40 bytes longer
+    let header_length = file_entry.get_length() - file_entry.allocation_descriptors.len()
+        + if quirks.extended_file_entries { 40 } else { 0 };
+    let fits = header_length + contents.len() <= BLOCK_SIZE;
+    let (ad_type, allocation_descriptors) = if quirks.embedded_data && fits {
+        for ad in &extents {
+            let start = block_offset(ad.extent_location);
+            image[start..start + ad.extent_length_bytes() as usize].fill(0);
+        }
+        file_entry.logical_blocks_recorded = 0;
+        (AllocationDescriptorType::ONE, contents.to_vec())
+    } else if quirks.long_ads {
+        let mut allocation_descriptors = vec![0u8; extents.len() * LongAd::size()];
+        for (ad, bytes) in extents
+            .iter()
+            .zip(allocation_descriptors.chunks_exact_mut(LongAd::size()))
+        {
+            let long_ad = LongAd {
+                extent_length_and_type: ad.extent_length_and_type,
+                extent_location: LbAddr {
+                    logical_block_number: ad.extent_location,
+                    partition_reference_number: 0,
+                },
+                ..Default::default()
+            };
+            long_ad.write(bytes);
+        }
+        (AllocationDescriptorType::LONG, allocation_descriptors)
+    } else {
+        (
+            AllocationDescriptorType::SHORT,
+            file_entry.allocation_descriptors.clone(),
+        )
+    };
+    file_entry.icb_tag.flags = (file_entry.icb_tag.flags & !0b111) | ad_type as u16;
+    file_entry.length_of_allocation_descriptors = allocation_descriptors.len() as u32;
+    file_entry.allocation_descriptors = allocation_descriptors;
+    if quirks.strategy_4096 {
+        file_entry.icb_tag.strategy_type = STRATEGY_4096;
+        file_entry.icb_tag.maximum_number_of_entries = 2;
+    }
+}
+
+/// The File Entry as an Extended File Entry, with no named streams
+fn extended_file_entry(e: FileEntry) -> ExtendedFileEntry {
+    ExtendedFileEntry {
+        tag: DescriptorTag {
+            tag_identifier: ExtendedFileEntry::TAG_IDENTIFIER,
+            ..e.tag
+        },
+        icb_tag: e.icb_tag,
+        uid: e.uid,
+        gid: e.gid,
+        permissions: e.permissions,
+        file_link_count: e.file_link_count,
+        record_format: e.record_format,
+        record_display_attributes: e.record_display_attributes,
+        record_length: e.record_length,
+        information_length: e.information_length,
+        object_size: e.information_length,
+        logical_blocks_recorded: e.logical_blocks_recorded,
+        access_time: e.access_time,
+        creation_time: e.modification_time.clone(),
+        modification_time: e.modification_time,
+        attribute_time: e.attribute_time,
+        checkpoint: e.checkpoint,
+        reserved: 0,
+        extended_attribute_icb: e.extended_attribute_icb,
+        stream_directory_icb: Default::default(),
+        implementation_identifier: e.implementation_identifier,
+        unique_id: e.unique_id,
+        length_of_extended_attributes: e.length_of_extended_attributes,
+        length_of_allocation_descriptors: e.length_of_allocation_descriptors,
+        extended_attributes: e.extended_attributes,
+        allocation_descriptors: e.allocation_descriptors,
+    }
+}
+
+/// Where a logical block of the partition of an image that build_image wrote starts in it
+pub fn block_offset(block: u32) -> usize {
+    (PARTITION_START_SECTOR + block) as usize * BLOCK_SIZE
+}
+
+/// The File Entry of a file or directory ("" for the root) of an image as build_image
+/// writes it, read as a File Entry even if it is an Extended File Entry
+pub fn file_entry(image: &[u8], path: &str) -> io::Result<FileEntry> {
+    let blocks = file_entry_blocks(image)?;
+    let block = blocks
+        .get(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no file {} in the image", path)))?;
+    Ok(AnyFileEntry::read(&image[block_offset(*block)..][..BLOCK_SIZE])?.into_file_entry())
+}
+
+/// Map the partition of an image that build_image wrote as a Sparable Partition (UDF 2.2.9)
+/// of packets of packet_length blocks, and move the packet that starts at block packet past
+/// the end of the image, after the Sparing Table. Its old place is left as it is, for tests to
+/// damage. Returns the sector of the spare packet.
+pub fn spare_packet(image: &mut Vec<u8>, packet_length: u32, packet: u32) -> u32 {
+    let sparing_table_sector = (image.len() / BLOCK_SIZE) as u32;
+    let spare_sector = sparing_table_sector + 1;
+    let mut spare = image[block_offset(packet)..].to_vec();
+    spare.resize(packet_length as usize * BLOCK_SIZE, 0);
+    let sparing_table = SparingTable {
+        tag: DescriptorTag {
+            descriptor_version: 2,
+            ..Default::default()
+        },
+        sparing_identifier: EntityID::new(SparingTable::SPARING_IDENTIFIER, [0; 8]),
+        sequence_number: 1,
+        map_entries: vec![
+            SparingMapEntry {
+                original_location: packet,
+                mapped_location: spare_sector,
+            },
+            SparingMapEntry {
+                original_location: SparingMapEntry::AVAILABLE,
+                mapped_location: spare_sector + packet_length,
+            },
+        ],
+    };
+    let mut table = vec![0u8; BLOCK_SIZE];
+    sparing_table.write(&mut table);
+    DescriptorTag::finalize(&mut table[..sparing_table.size()], sparing_table_sector);
+    image.extend_from_slice(&table);
+    image.extend_from_slice(&spare);
+
+    // the Logical Volume Descriptors of both sequences map a Sparable Partition
+    let mut partition_type_data = [0u8; 24];
+    SparablePartitionMap {
+        packet_length: packet_length as u16,
+        size_of_each_sparing_table: sparing_table.size() as u32,
+        sparing_table_locations: vec![sparing_table_sector],
+    }
+    .write(&mut partition_type_data);
+    let mut partition_type_identifier = [0u8; 32];
+    EntityID::new(SPARABLE_PARTITION_IDENTIFIER, [0; 8]).write(&mut partition_type_identifier);
+    let sparable_map = PartitionMap::Type2(Type2PartitionMap {
+        header: GenericPartitionMapHeader {
+            partition_map_type: 2,
+            partition_map_length: 64,
+        },
+        partition_type_identifier,
+        volume_seq_number: 1,
+        partition_type_data,
+        ..Default::default()
+    });
+    for sector in 0..PARTITION_START_SECTOR {
+        let start = sector as usize * BLOCK_SIZE;
+        if DescriptorTag::read(&image[start..]).tag_identifier != LogicalVolumeDescriptor::TAG_IDENTIFIER {
+            continue;
+        }
+        let mut lvd = LogicalVolumeDescriptor::read(&image[start..start + LogicalVolumeDescriptor::size()]);
+        lvd.map_table_length = 64;
+        lvd.number_of_partition_maps = 1;
+        let bytes = &mut image[start..start + LogicalVolumeDescriptor::size() + 64];
+        bytes.fill(0);
+        lvd.write(bytes);
+        sparable_map
+            .write(&mut bytes[LogicalVolumeDescriptor::size()..])
+            .expect("a Type 2 map fits in 64 bytes");
+        DescriptorTag::finalize(bytes, sector);
+    }
+    spare_sector
+}
+
+/// The logical block of the File Entry of each file and directory ("" for the root) of an
+/// image as build_image writes it, for tests that read or damage particular ones
+pub fn file_entry_blocks(image: &[u8]) -> io::Result<BTreeMap<String, u32>> {
+    let mut blocks = BTreeMap::new();
+    let mut pending = vec![(String::new(), FILE_SET_BLOCKS)];
+    while let Some((path, block)) = pending.pop() {
+        let start = block_offset(block);
+        let file_entry = AnyFileEntry::read(&image[start..start + BLOCK_SIZE])?.into_file_entry();
+        if matches!(file_entry.icb_tag.file_type(), FileType::Directory) {
+            for ad in read_short_allocation_descriptors(&file_entry.allocation_descriptors) {
+                let start = block_offset(ad.extent_location);
+                let fids = parse_file_identifiers(&image[start..start + ad.extent_length_bytes() as usize])
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                for fid in fids {
+                    if fid.file_characteristics & FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT != 0 {
+                        continue;
+                    }
+                    let name = fid.file_identifier.to_string();
+                    let child = if path.is_empty() {
+                        name
+                    } else {
+                        format!("{}/{}", path, name)
+                    };
+                    pending.push((child, fid.icb.extent_location.logical_block_number));
+                }
+            }
+        }
+        blocks.insert(path, block);
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::Cache,
+        repair::repair_image,
+        udf::{ExtentType, ShortAllocationDescriptor},
+        udf_parser::{read_file_contents, read_file_entries, UdfError, UdfParser},
+    };
+
+    #[test]
+    fn test_build_image() {
+        let vob = b"VOB".repeat(1000);
+        let files = [
+            FixtureFile {
+                path: "VIDEO_TS/VIDEO_TS.IFO",
+                contents: b"DVDVIDEO-VMG",
+            },
+            FixtureFile {
+                path: "VIDEO_TS/VTS_01_1.VOB",
+                contents: &vob,
+            },
+        ];
+        let quirks = Quirks {
+            long_ads: true,
+            embedded_data: true,
+            extended_file_entries: false,
+            strategy_4096: true,
+            broken_crcs: vec!["VIDEO_TS/VIDEO_TS.IFO".to_string()],
+        };
+        let image = build_image(&files, "FUNFANCY", &quirks).unwrap();
+        let blocks = file_entry_blocks(&image).unwrap();
+        assert_eq!(
+            blocks.keys().collect::<Vec<_>>(),
+            ["", "VIDEO_TS", "VIDEO_TS/VIDEO_TS.IFO", "VIDEO_TS/VTS_01_1.VOB"]
+        );
+
+        let entry = |path: &str| file_entry(&image, path).unwrap();
+        let ifo = entry("VIDEO_TS/VIDEO_TS.IFO");
+        assert!(matches!(
+            ifo.icb_tag.allocation_descriptor_type(),
+            AllocationDescriptorType::ONE
+        ));
+        assert_eq!(ifo.allocation_descriptors, b"DVDVIDEO-VMG");
+        let vob_entry = entry("VIDEO_TS/VTS_01_1.VOB");
+        assert!(matches!(
+            vob_entry.icb_tag.allocation_descriptor_type(),
+            AllocationDescriptorType::LONG
+        ));
+        assert_eq!(vob_entry.icb_tag.strategy_type, STRATEGY_4096);
+        let long_ad = LongAd::read(&vob_entry.allocation_descriptors);
+        assert_eq!(
+            (long_ad.extent_length_bytes(), long_ad.extent_type()),
+            (3000, ExtentType::RecordedAllocated)
+        );
+        assert_eq!(
+            &image[block_offset(long_ad.extent_location.logical_block_number)..][..3],
+            b"VOB"
+        );
+        // directories are as makeiso writes them
+        assert!(matches!(
+            entry("VIDEO_TS").icb_tag.allocation_descriptor_type(),
+            AllocationDescriptorType::SHORT
+        ));
+
+        let mut parser = UdfParser::new(Cursor::new(image));
+        let structures = parser.read_volume_structures().unwrap();
+        let partition_descriptor = &structures.partition_descriptors[&0];
+        let mut reader = Cache::new(&mut parser.reader);
+        let icb = |path: &str| ShortAllocationDescriptor {
+            extent_length_and_type: BLOCK_SIZE as u32,
+            extent_location: blocks[path],
+        };
+        let result = read_file_entries(
+            &mut reader,
+            &structures.logical_volume,
+            partition_descriptor,
+            &icb("VIDEO_TS/VIDEO_TS.IFO"),
+        );
+        assert!(matches!(result, Err(UdfError::InvalidDescriptorTag)));
+        let entries = read_file_entries(
+            &mut reader,
+            &structures.logical_volume,
+            partition_descriptor,
+            &icb("VIDEO_TS/VTS_01_1.VOB"),
+        )
+        .unwrap();
+        assert_eq!(entries[0].information_length, 3000);
+
+        // without quirks, with long_ads, with embedded data or with Extended File Entries, the
+        // files read back as written
+        let long_ads = Quirks {
+            long_ads: true,
+            ..Default::default()
+        };
+        let embedded_data = Quirks {
+            embedded_data: true,
+            ..Default::default()
+        };
+        let extended_file_entries = Quirks {
+            extended_file_entries: true,
+            long_ads: true,
+            ..Default::default()
+        };
+        for quirks in [Quirks::default(), long_ads, embedded_data, extended_file_entries] {
+            let mut image = Cursor::new(build_image(&files, "FUNFANCY", &quirks).unwrap());
+            let vob_tag = DescriptorTag::read(&image.get_ref()[block_offset(blocks["VIDEO_TS/VTS_01_1.VOB"])..]);
+            assert_eq!(
+                vob_tag.tag_identifier == ExtendedFileEntry::TAG_IDENTIFIER,
+                quirks.extended_file_entries
+            );
+            assert_eq!(repair_image(&mut image).unwrap().repaired, 0);
+            let mut parser = UdfParser::new(image);
+            let structures = parser.read_volume_structures().unwrap();
+            let partition_descriptor = &structures.partition_descriptors[&0];
+            let mut reader = Cache::new(&mut parser.reader);
+            for file in &files {
+                let entries =
+                    read_file_entries(&mut reader, &structures.logical_volume, partition_descriptor, &icb(file.path))
+                        .unwrap();
+                let contents = read_file_contents(
+                    &mut reader,
+                    &structures.logical_volume,
+                    &structures.partition_descriptors,
+                    0,
+                    &entries,
+                )
+                .unwrap();
+                assert_eq!(contents, file.contents, "{} {:?}", file.path, quirks);
+            }
+        }
+    }
+}
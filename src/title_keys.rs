@@ -0,0 +1,134 @@
+//! CSS title keys as libdvdcss caches them.
+//! libdvdcss has no API to return the keys it cracks, but when the DVDCSS_CACHE
+//! environment variable names a directory it stores each title key in
+//! `<cache>/<disc id>/<sector as %.10x>` as five colon-separated hex bytes.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::Serialize;
+
+/// Environment variable read by dvdcss_open for the key cache directory
+pub const DVDCSS_CACHE_ENV: &str = "DVDCSS_CACHE";
+
+/// A temporary key cache directory for one disc, which is removed when it is dropped so that
+/// a copy that fails does not leave it behind
+#[derive(Debug)]
+pub struct KeyCacheDir {
+    path: PathBuf,
+}
+
+impl KeyCacheDir {
+    /// Create the directory under the system's temporary directory
+    pub fn create() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("dvdromcopy-keys-{}", std::process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Call open, which opens a disc with libdvdcss, so that the disc stores its keys here.
+    /// libdvdcss only takes the directory from DVDCSS_CACHE when a disc is opened, so the
+    /// variable is set for the call and then put back, and discs opened later and the
+    /// commands of --exec do not see it
+    pub fn open<T>(&self, open: impl FnOnce() -> T) -> T {
+        let previous = std::env::var_os(DVDCSS_CACHE_ENV);
+        std::env::set_var(DVDCSS_CACHE_ENV, &self.path);
+        let opened = open();
+        match previous {
+            Some(previous) => std::env::set_var(DVDCSS_CACHE_ENV, previous),
+            None => std::env::remove_var(DVDCSS_CACHE_ENV),
+        }
+        opened
+    }
+}
+
+impl Drop for KeyCacheDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.path) {
+            warn!("Could not remove the key cache {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The title key that libdvdcss found for a VOB file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TitleKey {
+    pub path: String,
+    /// first sector of the file, for which the key was requested
+    pub sector: u32,
+    /// five colon-separated hex bytes, as in the libdvdcss cache
+    pub key: String,
+}
+
+/// Parse one key cache file; None if it is not a key file (e.g. CACHEDIR.TAG)
+pub fn parse_cache_file(file_name: &str, contents: &[u8]) -> Option<(u32, String)> {
+    if file_name.is_empty() || !file_name.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let sector = u32::from_str_radix(file_name, 16).ok()?;
+    let key = std::str::from_utf8(contents).ok()?.lines().next()?.trim();
+    let valid = key.split(':').count() == 5
+        && key.split(':').all(|byte| byte.len() == 2 && byte.bytes().all(|b| b.is_ascii_hexdigit()));
+    valid.then(|| (sector, key.to_ascii_lowercase()))
+}
+
+/// All keys in a libdvdcss cache directory by sector, from every disc subdirectory
+pub fn read_key_cache(cache_dir: &Path) -> io::Result<BTreeMap<u32, String>> {
+    let mut keys = BTreeMap::new();
+    for disc_dir in fs::read_dir(cache_dir)? {
+        let disc_dir = disc_dir?;
+        if !disc_dir.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(disc_dir.path())? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+            if let Some((sector, key)) = parse_cache_file(file_name, &fs::read(entry.path())?) {
+                keys.insert(sector, key);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cache_file() {
+        assert_eq!(
+            parse_cache_file("00000004b2", b"0A:1b:2c:3d:4e\r\n"),
+            Some((0x4b2, "0a:1b:2c:3d:4e".to_string()))
+        );
+        assert_eq!(parse_cache_file("CACHEDIR.TAG", b"Signature: 8a477f597d28d172789f06886806bc55"), None);
+        assert_eq!(parse_cache_file("00000004b2", b"0a:1b:2c"), None);
+        assert_eq!(parse_cache_file("00000004b2", b"0a:1b:2c:3d:4e:5f"), None);
+    }
+
+    #[test]
+    fn test_key_cache_dir() {
+        let key_cache = KeyCacheDir::create().unwrap();
+        let path = key_cache.path().to_path_buf();
+        let seen = key_cache.open(|| std::env::var_os(DVDCSS_CACHE_ENV));
+        assert_eq!(seen.as_deref(), Some(path.as_os_str()));
+        assert_eq!(std::env::var_os(DVDCSS_CACHE_ENV), None);
+
+        fs::create_dir(path.join("disc")).unwrap();
+        fs::write(path.join("disc").join("00000004b2"), b"0a:1b:2c:3d:4e\n").unwrap();
+        assert_eq!(read_key_cache(&path).unwrap().len(), 1);
+        drop(key_cache);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,164 @@
+//! `title --transcode`: the title's VOB stream is piped into an encoder command so that
+//! ripping and encoding take one pass over the disc. Writes block while the pipe is
+//! full, so the disc is read only as fast as the encoder consumes it.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+    process::{Child, ChildStdin, Command, ExitStatus, Stdio},
+    time::Instant,
+};
+
+use crate::exec_hook::shell_quote;
+
+/// The command line for template, with {output} replaced by the quoted output path
+pub fn command_line(template: &str, output: &Path) -> String {
+    template.replace("{output}", &shell_quote(&output.to_string_lossy()))
+}
+
+/// An encoder run with `sh -c` that reads the stream from its stdin
+pub struct Transcoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+impl Transcoder {
+    pub fn spawn(template: &str, output: &Path) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command_line(template, output))
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        Ok(Self { child, stdin })
+    }
+
+    /// Close the encoder's stdin and wait for it to exit
+    pub fn finish(mut self) -> io::Result<ExitStatus> {
+        drop(self.stdin.take());
+        self.child.wait()
+    }
+
+    /// The error for a write that failed because the encoder stopped reading
+    fn exited_early(&mut self, e: io::Error) -> io::Error {
+        drop(self.stdin.take());
+        match self.child.wait() {
+            Ok(status) => io::Error::other(format!("the encoder exited ({}) before reading the whole title", status)),
+            Err(_) => e,
+        }
+    }
+}
+impl Write for Transcoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(stdin) = &mut self.stdin else {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+        };
+        match stdin.write(buf) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Err(self.exited_early(e)),
+            result => result,
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stdin {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writer that prints how much of expected_bytes has been written, every 5%
+pub struct ProgressWriter<W: Write> {
+    inner: W,
+    label: String,
+    /// an estimate; the percentage stops at 100
+    expected_bytes: u64,
+    bytes_written: u64,
+    reported_percent: u64,
+    start: Instant,
+}
+impl<W: Write> ProgressWriter<W> {
+    pub fn new(inner: W, label: &str, expected_bytes: u64) -> Self {
+        Self {
+            inner,
+            label: label.to_string(),
+            expected_bytes,
+            bytes_written: 0,
+            reported_percent: 0,
+            start: Instant::now(),
+        }
+    }
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+    /// The whole percentage written so far
+    pub fn percent(&self) -> u64 {
+        (self.bytes_written * 100).checked_div(self.expected_bytes).unwrap_or(100).min(100)
+    }
+}
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes_written += n as u64;
+        let percent = self.percent();
+        if percent >= self.reported_percent + 5 {
+            self.reported_percent = percent - percent % 5;
+            const MIB: f64 = 1024.0 * 1024.0;
+            let seconds = self.start.elapsed().as_secs_f64().max(0.001);
+            eprintln!(
+                "{}: {}% ({:.0} of {:.0} MiB, {:.1} MiB/s)",
+                self.label,
+                percent,
+                self.bytes_written as f64 / MIB,
+                self.expected_bytes as f64 / MIB,
+                self.bytes_written as f64 / MIB / seconds
+            );
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        assert_eq!(
+            command_line("ffmpeg -i - -c:v libx264 {output}", Path::new("/out/Main Feature.mp4")),
+            "ffmpeg -i - -c:v libx264 '/out/Main Feature.mp4'"
+        );
+    }
+
+    #[test]
+    fn test_transcoder() {
+        let output = std::env::temp_dir().join(format!("dvdromcopy-transcode-test-{}", std::process::id()));
+        let mut transcoder = Transcoder::spawn("tr a-z A-Z > {output}", &output).unwrap();
+        transcoder.write_all(b"vob").unwrap();
+        assert!(transcoder.finish().unwrap().success());
+        assert_eq!(std::fs::read(&output).unwrap(), b"VOB");
+        std::fs::remove_file(&output).unwrap();
+
+        // an encoder that stops reading fails the copy instead of hanging it
+        let mut transcoder = Transcoder::spawn("exit 3", &output).unwrap();
+        let e = transcoder.write_all(&vec![0; 1 << 20]).unwrap_err();
+        assert!(e.to_string().contains("exit status: 3"), "{}", e);
+    }
+
+    #[test]
+    fn test_progress_writer() {
+        let mut progress = ProgressWriter::new(Vec::new(), "Title 1", 200);
+        progress.write_all(&[0; 50]).unwrap();
+        assert_eq!(progress.percent(), 25);
+        assert_eq!(progress.reported_percent, 25);
+        progress.write_all(&[0; 300]).unwrap();
+        assert_eq!(progress.percent(), 100);
+        assert_eq!(progress.bytes_written(), 350);
+        assert_eq!(progress.into_inner().len(), 350);
+        assert_eq!(ProgressWriter::new(io::sink(), "", 0).percent(), 100);
+    }
+}
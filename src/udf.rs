@@ -8,7 +8,8 @@ use std::str;
 use crc::Algorithm;
 use static_assertions::assert_eq_size;
 
-use crate::udf_parser::osta;
+use crate::crc::cksum;
+use crate::udf_parser::{osta, UdfError};
 
 #[macro_export]
 macro_rules! offsets_of {
@@ -22,11 +23,21 @@ macro_rules! offsets_of {
 #[derive(Clone)]
 pub struct Dstring<const N: usize>(pub [u8; N]);
 impl<const n: usize> Dstring<n> {
+    /// ECMA-167 1/7.2.12: the compressed characters, truncated to fit, zero padding,
+    /// and the number of bytes used in the last byte
     pub fn from_str(s: &str) -> Self {
-        let v = osta::encode(s);
         let mut x = [0; n];
-        let min = v.len().min(n);
-        x[..min].copy_from_slice(&v[..min]);
+        if s.is_empty() {
+            return Self(x);
+        }
+        let v = encode_unterminated(s);
+        let mut len = v.len().min(n - 1);
+        if v[0] == 16 {
+            // whole 16-bit characters
+            len -= (len - 1) % 2;
+        }
+        x[..len].copy_from_slice(&v[..len]);
+        x[n - 1] = len as u8;
         Self(x)
     }
     pub fn to_string(&self) -> String {
@@ -43,12 +54,20 @@ impl<const N: usize> Debug for Dstring<N> {
         f.write_str(&self.to_string())
     }
 }
+/// OSTA compressed Unicode without the terminating null of osta::encode,
+/// since dstrings are sized by their length fields
+fn encode_unterminated(s: &str) -> Vec<u8> {
+    let mut v = osta::encode(s);
+    let terminator = if v[0] == 16 { 2 } else { 1 };
+    v.truncate(v.len() - terminator);
+    v
+}
+
 #[derive(Clone, PartialEq)]
 pub struct DynamicDstring(pub Vec<u8>);
 impl DynamicDstring {
     pub fn from_str(s: &str) -> Self {
-        let v = osta::encode(s);
-        Self(v)
+        Self(encode_unterminated(s))
     }
     pub fn to_string(&self) -> String {
         osta::decode(&self.0)
@@ -184,6 +203,95 @@ impl Timestamp {
         bytes[10] = self.hundreds_of_microseconds;
         bytes[11] = self.microseconds;
     }
+
+    /// Microseconds since 1970-01-01 UTC.
+    /// ECMA-167 1/7.3.1: the low 12 bits of type_and_timezone are the signed offset
+    /// from UTC in minutes, or -2047 if unspecified, in which case the time is taken as UTC.
+    /// Years outside 1 to 9999, which ECMA-167 does not allow, are taken as the nearest.
+    pub fn unix_micros(&self) -> i64 {
+        // days since 1970-01-01 of a proleptic Gregorian date
+        // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year.clamp(1, 9999) as i64 - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        let offset_minutes = ((self.type_and_timezone << 4) as i16 >> 4) as i64;
+        let offset_minutes = if offset_minutes == -2047 { 0 } else { offset_minutes };
+        let seconds =
+            days * 86400 + self.hour as i64 * 3600 + (self.minute as i64 - offset_minutes) * 60 + self.second as i64;
+        seconds * 1_000_000
+            + self.centiseconds as i64 * 10_000
+            + self.hundreds_of_microseconds as i64 * 100
+            + self.microseconds as i64
+    }
+
+    /// ISO 8601 with the recorded offset from UTC, e.g. 2003-04-11T18:25:00+01:00,
+    /// or Z if the offset is unspecified
+    pub fn iso8601(&self) -> String {
+        let offset_minutes = ((self.type_and_timezone << 4) as i16 >> 4) as i32;
+        let offset = match offset_minutes {
+            0 | -2047 => "Z".to_string(),
+            _ => format!(
+                "{}{:02}:{:02}",
+                if offset_minutes < 0 { '-' } else { '+' },
+                offset_minutes.abs() / 60,
+                offset_minutes.abs() % 60
+            ),
+        };
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, offset
+        )
+    }
+
+    pub fn system_time(&self) -> std::time::SystemTime {
+        let micros = self.unix_micros();
+        let since_epoch = std::time::Duration::from_micros(micros.unsigned_abs());
+        if micros >= 0 {
+            std::time::UNIX_EPOCH + since_epoch
+        } else {
+            std::time::UNIX_EPOCH - since_epoch
+        }
+    }
+
+    /// The time in UTC, as recorded by dvdromcopy makeiso
+    pub fn from_system_time(time: std::time::SystemTime) -> Self {
+        let micros = match time.duration_since(std::time::UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_micros() as i64,
+            Err(e) => -(e.duration().as_micros() as i64),
+        };
+        let seconds = micros.div_euclid(1_000_000);
+        let micros = micros.rem_euclid(1_000_000);
+        let second_of_day = seconds.rem_euclid(86400);
+        // the proleptic Gregorian date of days since 1970-01-01
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = seconds.div_euclid(86400) + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        Self {
+            // type 1 (local time) with an offset of 0 minutes from UTC
+            type_and_timezone: 0x1000,
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (second_of_day / 3600) as u8,
+            minute: (second_of_day / 60 % 60) as u8,
+            second: (second_of_day % 60) as u8,
+            centiseconds: (micros / 10_000) as u8,
+            hundreds_of_microseconds: (micros / 100 % 100) as u8,
+            microseconds: (micros % 100) as u8,
+        }
+    }
 }
 
 /// 2.1.5 Entity Identifier aka ISO 13346 1/7.4
@@ -237,6 +345,18 @@ impl EntityID {
         bytes[1..24].copy_from_slice(&self.identifier);
         bytes[24..32].copy_from_slice(&self.identifier_suffix);
     }
+    /// The identifier as text, e.g. "*OSTA UDF Compliant"
+    pub fn identifier_string(&self) -> String {
+        String::from_utf8_lossy(&self.identifier).trim_end_matches('\0').to_string()
+    }
+    pub fn new(identifier: &str, identifier_suffix: [u8; 8]) -> Self {
+        let mut r = Self {
+            identifier_suffix,
+            ..Self::default()
+        };
+        r.identifier[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        r
+    }
 }
 
 pub struct IdentifierSuffix {
@@ -292,6 +412,33 @@ impl DescriptorTag {
         bytes[10..12].copy_from_slice(&self.descriptor_crc_length.to_le_bytes());
         bytes[12..16].copy_from_slice(&self.tag_location.to_le_bytes());
     }
+
+    /// ECMA-167 3/7.2.3: the sum modulo 256 of bytes 0-3 and 5-15 of the tag
+    pub fn checksum(tag_bytes: &[u8]) -> u8 {
+        tag_bytes[0..4]
+            .iter()
+            .chain(&tag_bytes[5..16])
+            .fold(0u8, |acc, &b| acc.wrapping_add(b))
+    }
+
+    /// Fill in the CRC of the descriptor_crc_length bytes after the tag, and then the
+    /// tag checksum, of a descriptor that has been written with its tag
+    pub fn write_checksums(descriptor: &mut [u8]) {
+        let crc_length = u16::from_le_bytes([descriptor[10], descriptor[11]]) as usize;
+        let crc = cksum(&descriptor[16..16 + crc_length]);
+        descriptor[8..10].copy_from_slice(&crc.to_le_bytes());
+        descriptor[4] = Self::checksum(descriptor);
+    }
+
+    /// Fill in the CRC length, tag location, CRC and tag checksum of a descriptor that has
+    /// been written with its tag, where descriptor is exactly as long as the descriptor and
+    /// location is its sector, or its logical block for descriptors within a partition
+    pub fn finalize(descriptor: &mut [u8], location: u32) {
+        let crc_length = (descriptor.len() - Self::size()) as u16;
+        descriptor[10..12].copy_from_slice(&crc_length.to_le_bytes());
+        descriptor[12..16].copy_from_slice(&location.to_le_bytes());
+        Self::write_checksums(descriptor);
+    }
 }
 
 /// There is exactly one of these per volume.
@@ -477,6 +624,7 @@ impl Default for AnchorVolumeDescriptorPointer {
     }
 }
 impl AnchorVolumeDescriptorPointer {
+    pub const TAG_IDENTIFIER: u16 = 2;
     pub fn size() -> usize {
         std::mem::size_of::<AnchorVolumeDescriptorPointer>()
     }
@@ -528,12 +676,17 @@ pub struct Type1PartitionMap {
     pub partition_number: u16,
 }
 
+/// UDF 2.6.0 2.2.8 and 2.2.9: the Virtual, Sparable and Metadata Partition Maps
 #[derive(Debug, Clone)]
 pub struct Type2PartitionMap {
     pub header: GenericPartitionMapHeader,
     pub reserved1: [u8; 2],
     pub partition_type_identifier: [u8; 32],
-    // TODO: complete
+    pub volume_seq_number: u16,
+    /// the partition that the map's partition is recorded in
+    pub partition_number: u16,
+    /// the rest of the map, which depends on the partition type
+    pub partition_type_data: [u8; 24],
 }
 impl Default for Type2PartitionMap {
     fn default() -> Self {
@@ -541,6 +694,189 @@ impl Default for Type2PartitionMap {
             header: GenericPartitionMapHeader::default(),
             reserved1: [0; 2],
             partition_type_identifier: [0; 32],
+            volume_seq_number: 0,
+            partition_number: 0,
+            partition_type_data: [0; 24],
+        }
+    }
+}
+impl Type2PartitionMap {
+    /// Whether this is the map of a virtual partition, whose blocks are found through a
+    /// Virtual Allocation Table
+    pub fn is_virtual(&self) -> bool {
+        EntityID::read(&self.partition_type_identifier).identifier_string() == VIRTUAL_PARTITION_IDENTIFIER
+    }
+
+    /// The map of a Metadata Partition, if this is one
+    pub fn metadata(&self) -> Option<MetadataPartitionMap> {
+        (EntityID::read(&self.partition_type_identifier).identifier_string() == METADATA_PARTITION_IDENTIFIER)
+            .then(|| MetadataPartitionMap::read(&self.partition_type_data))
+    }
+
+    /// The map of a Sparable Partition, if this is one
+    pub fn sparable(&self) -> Option<SparablePartitionMap> {
+        (EntityID::read(&self.partition_type_identifier).identifier_string() == SPARABLE_PARTITION_IDENTIFIER)
+            .then(|| SparablePartitionMap::read(&self.partition_type_data))
+    }
+}
+
+/// UDF 2.2.8 Virtual Partition Map
+pub const VIRTUAL_PARTITION_IDENTIFIER: &str = "*UDF Virtual Partition";
+/// The partition type identifier of a Metadata Partition Map
+pub const METADATA_PARTITION_IDENTIFIER: &str = "*UDF Metadata Partition";
+/// The partition type identifier of a Sparable Partition Map
+pub const SPARABLE_PARTITION_IDENTIFIER: &str = "*UDF Sparable Partition";
+
+/// UDF 2.6.0 2.2.10 Metadata Partition Map: UDF 2.50 and later record the File Entries and
+/// directories in the Metadata File, a file of the physical partition whose blocks are the
+/// blocks of the Metadata Partition. The Mirror File is a copy of it.
+/// http://www.osta.org/specs/pdf/udf260.pdf#page=30
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct MetadataPartitionMap {
+    /// the block of the Metadata File's File Entry in the physical partition
+    pub metadata_file_location: u32,
+    pub metadata_mirror_file_location: u32,
+    /// u32::MAX when there is no Metadata Bitmap File
+    pub metadata_bitmap_file_location: u32,
+    /// in blocks
+    pub allocation_unit_size: u32,
+    /// in blocks
+    pub alignment_unit_size: u16,
+    pub flags: u8,
+}
+impl MetadataPartitionMap {
+    /// File Entry file types of the Metadata File, Mirror File and Bitmap File
+    pub const METADATA_FILE_TYPE: u8 = 250;
+    pub const MIRROR_FILE_TYPE: u8 = 251;
+    pub const BITMAP_FILE_TYPE: u8 = 252;
+    /// flags bit 0: the Mirror File is a separate copy instead of sharing the Metadata File's blocks
+    pub const DUPLICATE_METADATA_FLAG: u8 = 1;
+
+    /// Read from the partition type data of a Type 2 map (bytes 40 to 63 of the map)
+    pub fn read(partition_type_data: &[u8; 24]) -> Self {
+        let u32_at = |pos: usize| u32::from_le_bytes(partition_type_data[pos..pos + 4].try_into().unwrap());
+        Self {
+            metadata_file_location: u32_at(0),
+            metadata_mirror_file_location: u32_at(4),
+            metadata_bitmap_file_location: u32_at(8),
+            allocation_unit_size: u32_at(12),
+            alignment_unit_size: u16::from_le_bytes([partition_type_data[16], partition_type_data[17]]),
+            flags: partition_type_data[18],
+        }
+    }
+    pub fn write(&self, partition_type_data: &mut [u8; 24]) {
+        partition_type_data[0..4].copy_from_slice(&self.metadata_file_location.to_le_bytes());
+        partition_type_data[4..8].copy_from_slice(&self.metadata_mirror_file_location.to_le_bytes());
+        partition_type_data[8..12].copy_from_slice(&self.metadata_bitmap_file_location.to_le_bytes());
+        partition_type_data[12..16].copy_from_slice(&self.allocation_unit_size.to_le_bytes());
+        partition_type_data[16..18].copy_from_slice(&self.alignment_unit_size.to_le_bytes());
+        partition_type_data[18] = self.flags;
+    }
+}
+
+/// UDF 2.6.0 2.2.9 Sparable Partition Map: rewritable discs (DVD-RW, DVD+RW) record a packet
+/// that has gone bad somewhere else, and list where in the Sparing Tables
+/// http://www.osta.org/specs/pdf/udf260.pdf#page=28
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SparablePartitionMap {
+    /// in blocks; 32 for DVD
+    pub packet_length: u16,
+    /// in bytes
+    pub size_of_each_sparing_table: u32,
+    /// the sector of each copy of the Sparing Table, at most 4
+    pub sparing_table_locations: Vec<u32>,
+}
+impl SparablePartitionMap {
+    /// Read from the partition type data of a Type 2 map (bytes 40 to 63 of the map)
+    pub fn read(partition_type_data: &[u8; 24]) -> Self {
+        let u32_at = |pos: usize| u32::from_le_bytes(partition_type_data[pos..pos + 4].try_into().unwrap());
+        let number_of_sparing_tables = (partition_type_data[2] as usize).min(4);
+        Self {
+            packet_length: u16::from_le_bytes([partition_type_data[0], partition_type_data[1]]),
+            size_of_each_sparing_table: u32_at(4),
+            sparing_table_locations: (0..number_of_sparing_tables).map(|i| u32_at(8 + 4 * i)).collect(),
+        }
+    }
+    pub fn write(&self, partition_type_data: &mut [u8; 24]) {
+        assert!(self.sparing_table_locations.len() <= 4);
+        partition_type_data[0..2].copy_from_slice(&self.packet_length.to_le_bytes());
+        partition_type_data[2] = self.sparing_table_locations.len() as u8;
+        partition_type_data[4..8].copy_from_slice(&self.size_of_each_sparing_table.to_le_bytes());
+        for (i, location) in self.sparing_table_locations.iter().enumerate() {
+            partition_type_data[8 + 4 * i..12 + 4 * i].copy_from_slice(&location.to_le_bytes());
+        }
+    }
+}
+
+/// UDF 2.6.0 2.2.12 Sparing Table: the packets of a Sparable Partition that have been moved,
+/// and the spare packets that they can be moved to
+/// http://www.osta.org/specs/pdf/udf260.pdf#page=33
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SparingTable {
+    pub tag: DescriptorTag,
+    pub sparing_identifier: EntityID,
+    /// incremented each time the table is rewritten
+    pub sequence_number: u32,
+    /// sorted by original_location
+    pub map_entries: Vec<SparingMapEntry>,
+}
+/// An entry of a Sparing Table
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct SparingMapEntry {
+    /// the first block in the partition of the packet that has been moved, or AVAILABLE or DEFECTIVE
+    pub original_location: u32,
+    /// the sector that the packet is recorded at
+    pub mapped_location: u32,
+}
+impl SparingMapEntry {
+    /// the original_location of a spare packet that is not in use
+    pub const AVAILABLE: u32 = 0xFFFF_FFFF;
+    /// the original_location of a spare packet that has itself gone bad
+    pub const DEFECTIVE: u32 = 0xFFFF_FFF0;
+
+    /// Whether this entry moves a packet, rather than listing a spare one
+    pub fn is_mapped(&self) -> bool {
+        self.original_location < Self::DEFECTIVE
+    }
+}
+impl SparingTable {
+    /// UDF 2.2.12: the tag identifier is 0, which no ECMA-167 descriptor uses
+    pub const TAG_IDENTIFIER: u16 = 0;
+    pub const SPARING_IDENTIFIER: &str = "*UDF Sparing Table";
+
+    /// bytes must hold the whole table; None if its map entries overrun it
+    pub fn read(bytes: &[u8]) -> Option<Self> {
+        let header = bytes.get(0..56)?;
+        let reallocation_table_length = u16::from_le_bytes([header[48], header[49]]) as usize;
+        let entries = bytes.get(56..56 + 8 * reallocation_table_length)?;
+        Some(Self {
+            tag: DescriptorTag::read(&header[0..16]),
+            sparing_identifier: EntityID::read(&header[16..48]),
+            sequence_number: u32::from_le_bytes(header[52..56].try_into().unwrap()),
+            map_entries: entries
+                .chunks_exact(8)
+                .map(|entry| SparingMapEntry {
+                    original_location: u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                    mapped_location: u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+                })
+                .collect(),
+        })
+    }
+
+    /// The length of the table as written
+    pub fn size(&self) -> usize {
+        56 + 8 * self.map_entries.len()
+    }
+
+    pub fn write(&self, bytes: &mut [u8]) {
+        self.tag.write(&mut bytes[0..16]);
+        self.sparing_identifier.write(&mut bytes[16..48]);
+        bytes[48..50].copy_from_slice(&(self.map_entries.len() as u16).to_le_bytes());
+        bytes[50..52].fill(0);
+        bytes[52..56].copy_from_slice(&self.sequence_number.to_le_bytes());
+        for (entry, chunk) in self.map_entries.iter().zip(bytes[56..].chunks_exact_mut(8)) {
+            chunk[0..4].copy_from_slice(&entry.original_location.to_le_bytes());
+            chunk[4..8].copy_from_slice(&entry.mapped_location.to_le_bytes());
         }
     }
 }
@@ -557,6 +893,13 @@ impl PartitionMap {
         let map_type = bytes[0];
         let map_length = bytes[1];
 
+        // the length includes the header, so a shorter one would never advance to the next map
+        if map_length < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid partition map length",
+            ));
+        }
         if bytes.len() < map_length as usize {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
@@ -594,11 +937,16 @@ impl PartitionMap {
                 reserved1.copy_from_slice(&bytes[2..4]);
                 let mut partition_type_identifier = [0u8; 32];
                 partition_type_identifier.copy_from_slice(&bytes[4..36]);
+                let mut partition_type_data = [0u8; 24];
+                partition_type_data.copy_from_slice(&bytes[40..64]);
 
                 Ok(PartitionMap::Type2(Type2PartitionMap {
                     header,
                     reserved1,
                     partition_type_identifier,
+                    volume_seq_number: u16::from_le_bytes([bytes[36], bytes[37]]),
+                    partition_number: u16::from_le_bytes([bytes[38], bytes[39]]),
+                    partition_type_data,
                 }))
             }
             // Handle other partition map types by storing their raw data
@@ -633,7 +981,11 @@ impl PartitionMap {
                 }
                 bytes[0] = map.header.partition_map_type;
                 bytes[1] = map.header.partition_map_length;
-                bytes[2..64].copy_from_slice(&map.partition_type_identifier);
+                bytes[2..4].copy_from_slice(&map.reserved1);
+                bytes[4..36].copy_from_slice(&map.partition_type_identifier);
+                bytes[36..38].copy_from_slice(&map.volume_seq_number.to_le_bytes());
+                bytes[38..40].copy_from_slice(&map.partition_number.to_le_bytes());
+                bytes[40..64].copy_from_slice(&map.partition_type_data);
             }
             PartitionMap::Other { header: _, data } => {
                 if bytes.len() < data.len() {
@@ -845,6 +1197,96 @@ impl PartitionDescriptor {
     }
 }
 
+/// ECMA-167 3/10.10 Logical Volume Integrity Descriptor, with the implementation use
+/// of UDF 2.6.0 2.2.6.4. Whether the volume was closed cleanly, and its file counts
+#[derive(Debug, Clone, Default)]
+pub struct LogicalVolumeIntegrityDescriptor {
+    pub tag: DescriptorTag,
+    pub recording_date_and_time: Timestamp,
+    /// INTEGRITY_OPEN or INTEGRITY_CLOSE
+    pub integrity_type: u32,
+    pub next_integrity_extent: ExtentAd,
+    /// UDF 3.2.1.1 Logical Volume Header Descriptor: the next unused unique ID of a file
+    pub next_unique_id: u64,
+    /// free blocks of each partition, or 0xFFFFFFFF if unknown
+    pub free_space_table: Vec<u32>,
+    /// size in blocks of each partition
+    pub size_table: Vec<u32>,
+    pub implementation_identifier: EntityID,
+    pub number_of_files: u32,
+    pub number_of_directories: u32,
+    /// UDF revisions as BCD, e.g. 0x0102 for 1.02
+    pub minimum_udf_read_revision: u16,
+    pub minimum_udf_write_revision: u16,
+    pub maximum_udf_write_revision: u16,
+}
+impl LogicalVolumeIntegrityDescriptor {
+    pub const TAG_IDENTIFIER: u16 = 9;
+    pub const INTEGRITY_OPEN: u32 = 0;
+    pub const INTEGRITY_CLOSE: u32 = 1;
+
+    /// bytes must hold the whole descriptor; None if the tables overrun it
+    pub fn read(bytes: &[u8]) -> Option<Self> {
+        let u32_at = |offset: usize| -> Option<u32> { Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?)) };
+        let u16_at = |offset: usize| -> Option<u16> { Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?)) };
+        let number_of_partitions = u32_at(72)? as usize;
+        let length_of_implementation_use = u32_at(76)? as usize;
+        let table = |start: usize| (0..number_of_partitions).map(|i| u32_at(start + 4 * i)).collect::<Option<Vec<u32>>>();
+        let free_space_table = table(80)?;
+        let size_table = table(80 + 4 * number_of_partitions)?;
+        let implementation_use_start = 80 + 8 * number_of_partitions;
+        let mut r = Self {
+            tag: DescriptorTag::read(bytes.get(0..16)?),
+            recording_date_and_time: Timestamp::read(&bytes[16..28]),
+            integrity_type: u32_at(28)?,
+            next_integrity_extent: ExtentAd::read(bytes.get(32..40)?),
+            next_unique_id: u64::from_le_bytes(bytes.get(40..48)?.try_into().ok()?),
+            free_space_table,
+            size_table,
+            ..Default::default()
+        };
+        // UDF 2.2.6.4: EntityID, number of files and directories, then the three revisions
+        if length_of_implementation_use >= 46 {
+            let start = implementation_use_start;
+            r.implementation_identifier = EntityID::read(bytes.get(start..start + 32)?);
+            r.number_of_files = u32_at(start + 32)?;
+            r.number_of_directories = u32_at(start + 36)?;
+            r.minimum_udf_read_revision = u16_at(start + 40)?;
+            r.minimum_udf_write_revision = u16_at(start + 42)?;
+            r.maximum_udf_write_revision = u16_at(start + 44)?;
+        }
+        Some(r)
+    }
+
+    /// The length of the descriptor as written, with the UDF implementation use
+    pub fn size(&self) -> usize {
+        80 + 8 * self.size_table.len() + 46
+    }
+
+    pub fn write(&self, bytes: &mut [u8]) {
+        assert_eq!(self.free_space_table.len(), self.size_table.len());
+        let number_of_partitions = self.size_table.len();
+        self.tag.write(&mut bytes[0..16]);
+        self.recording_date_and_time.write(&mut bytes[16..28]);
+        bytes[28..32].copy_from_slice(&self.integrity_type.to_le_bytes());
+        self.next_integrity_extent.write(&mut bytes[32..40]);
+        bytes[40..48].copy_from_slice(&self.next_unique_id.to_le_bytes());
+        bytes[72..76].copy_from_slice(&(number_of_partitions as u32).to_le_bytes());
+        bytes[76..80].copy_from_slice(&46u32.to_le_bytes());
+        let tables = self.free_space_table.iter().chain(&self.size_table);
+        for (i, value) in tables.enumerate() {
+            bytes[80 + 4 * i..84 + 4 * i].copy_from_slice(&value.to_le_bytes());
+        }
+        let start = 80 + 8 * number_of_partitions;
+        self.implementation_identifier.write(&mut bytes[start..start + 32]);
+        bytes[start + 32..start + 36].copy_from_slice(&self.number_of_files.to_le_bytes());
+        bytes[start + 36..start + 40].copy_from_slice(&self.number_of_directories.to_le_bytes());
+        bytes[start + 40..start + 42].copy_from_slice(&self.minimum_udf_read_revision.to_le_bytes());
+        bytes[start + 42..start + 44].copy_from_slice(&self.minimum_udf_write_revision.to_le_bytes());
+        bytes[start + 44..start + 46].copy_from_slice(&self.maximum_udf_write_revision.to_le_bytes());
+    }
+}
+
 
 /// ECMA-167 7.1 Recorded address aka lb_addr
 /// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=73
@@ -1097,7 +1539,12 @@ impl FileEntry {
     pub fn get_length(&self) -> usize {
         176 + self.length_of_extended_attributes as usize + self.length_of_allocation_descriptors as usize
     }
-    pub fn read(bytes: &[u8]) -> Self {
+    /// Fails if the descriptor, with the extended attributes and allocation descriptors it
+    /// declares, does not fit in bytes
+    pub fn read(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 176 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Buffer too small for File Entry"));
+        }
         let mut r = Self::default();
         r.tag = DescriptorTag::read(&bytes[0..16]);
         r.icb_tag = ICBTag::read(&bytes[16..36]);
@@ -1128,14 +1575,17 @@ impl FileEntry {
             u32::from_le_bytes([bytes[168], bytes[169], bytes[170], bytes[171]]);
         r.length_of_allocation_descriptors =
             u32::from_le_bytes([bytes[172], bytes[173], bytes[174], bytes[175]]);
-        r.extended_attributes =
-            bytes[176..(176 + r.length_of_extended_attributes as usize)].to_vec();
-        r.allocation_descriptors = bytes[(176 + r.length_of_extended_attributes as usize)
-            ..176
-                + (r.length_of_extended_attributes as usize)
-                + (r.length_of_allocation_descriptors as usize)]
-            .to_vec();
-        r
+        let attributes_end = 176 + r.length_of_extended_attributes as usize;
+        let end = attributes_end + r.length_of_allocation_descriptors as usize;
+        if bytes.len() < end {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for the File Entry's extended attributes and allocation descriptors",
+            ));
+        }
+        r.extended_attributes = bytes[176..attributes_end].to_vec();
+        r.allocation_descriptors = bytes[attributes_end..end].to_vec();
+        Ok(r)
     }
 
     pub fn write(&self, bytes: &mut [u8]) {
@@ -1166,6 +1616,188 @@ impl FileEntry {
     }
 }
 
+/// Extended File Entry, which UDF 2.00 and later record instead of a File Entry. It is a File
+/// Entry with an object size, a creation time and a stream directory.
+/// ECMA-167 4/14.17 Extended File Entry
+/// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=114
+#[derive(Debug, Clone, Default)]
+pub struct ExtendedFileEntry {
+    pub tag: DescriptorTag,
+    pub icb_tag: ICBTag,
+    pub uid: u32,
+    pub gid: u32,
+    pub permissions: u32,
+    pub file_link_count: u16,
+    pub record_format: u8,
+    pub record_display_attributes: u8,
+    pub record_length: u32,
+    pub information_length: u64,
+    /// information_length plus the lengths of the file's named streams
+    pub object_size: u64,
+    pub logical_blocks_recorded: u64,
+    pub access_time: Timestamp,
+    pub modification_time: Timestamp,
+    pub creation_time: Timestamp,
+    pub attribute_time: Timestamp,
+    pub checkpoint: u32,
+    pub reserved: u32,
+    pub extended_attribute_icb: LongAd,
+    pub stream_directory_icb: LongAd,
+    pub implementation_identifier: EntityID,
+    pub unique_id: u64,
+    pub length_of_extended_attributes: u32,
+    pub length_of_allocation_descriptors: u32,
+    pub extended_attributes: Vec<u8>,
+    pub allocation_descriptors: Vec<u8>,
+}
+impl ExtendedFileEntry {
+    /// ECMA-167 4/7.2.1 Tag Identifier (RBP 0)
+    /// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=74
+    pub const TAG_IDENTIFIER: u16 = 266;
+    pub fn get_length(&self) -> usize {
+        216 + self.length_of_extended_attributes as usize + self.length_of_allocation_descriptors as usize
+    }
+    /// Fails if the descriptor, with the extended attributes and allocation descriptors it
+    /// declares, does not fit in bytes
+    pub fn read(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 216 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for Extended File Entry",
+            ));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        let mut r = Self {
+            tag: DescriptorTag::read(&bytes[0..16]),
+            icb_tag: ICBTag::read(&bytes[16..36]),
+            uid: u32_at(36),
+            gid: u32_at(40),
+            permissions: u32_at(44),
+            file_link_count: u16::from_le_bytes([bytes[48], bytes[49]]),
+            record_format: bytes[50],
+            record_display_attributes: bytes[51],
+            record_length: u32_at(52),
+            information_length: u64_at(56),
+            object_size: u64_at(64),
+            logical_blocks_recorded: u64_at(72),
+            access_time: Timestamp::read(&bytes[80..92]),
+            modification_time: Timestamp::read(&bytes[92..104]),
+            creation_time: Timestamp::read(&bytes[104..116]),
+            attribute_time: Timestamp::read(&bytes[116..128]),
+            checkpoint: u32_at(128),
+            reserved: u32_at(132),
+            extended_attribute_icb: LongAd::read(&bytes[136..152]),
+            stream_directory_icb: LongAd::read(&bytes[152..168]),
+            implementation_identifier: EntityID::read(&bytes[168..200]),
+            unique_id: u64_at(200),
+            length_of_extended_attributes: u32_at(208),
+            length_of_allocation_descriptors: u32_at(212),
+            ..Default::default()
+        };
+        let attributes_end = 216 + r.length_of_extended_attributes as usize;
+        let end = attributes_end + r.length_of_allocation_descriptors as usize;
+        if bytes.len() < end {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for the Extended File Entry's extended attributes and allocation descriptors",
+            ));
+        }
+        r.extended_attributes = bytes[216..attributes_end].to_vec();
+        r.allocation_descriptors = bytes[attributes_end..end].to_vec();
+        Ok(r)
+    }
+
+    pub fn write(&self, bytes: &mut [u8]) {
+        self.tag.write(&mut bytes[0..16]);
+        self.icb_tag.write(&mut bytes[16..36]);
+        bytes[36..40].copy_from_slice(&self.uid.to_le_bytes());
+        bytes[40..44].copy_from_slice(&self.gid.to_le_bytes());
+        bytes[44..48].copy_from_slice(&self.permissions.to_le_bytes());
+        bytes[48..50].copy_from_slice(&self.file_link_count.to_le_bytes());
+        bytes[50] = self.record_format;
+        bytes[51] = self.record_display_attributes;
+        bytes[52..56].copy_from_slice(&self.record_length.to_le_bytes());
+        bytes[56..64].copy_from_slice(&self.information_length.to_le_bytes());
+        bytes[64..72].copy_from_slice(&self.object_size.to_le_bytes());
+        bytes[72..80].copy_from_slice(&self.logical_blocks_recorded.to_le_bytes());
+        self.access_time.write(&mut bytes[80..92]);
+        self.modification_time.write(&mut bytes[92..104]);
+        self.creation_time.write(&mut bytes[104..116]);
+        self.attribute_time.write(&mut bytes[116..128]);
+        bytes[128..132].copy_from_slice(&self.checkpoint.to_le_bytes());
+        bytes[132..136].copy_from_slice(&self.reserved.to_le_bytes());
+        self.extended_attribute_icb.write(&mut bytes[136..152]);
+        self.stream_directory_icb.write(&mut bytes[152..168]);
+        self.implementation_identifier.write(&mut bytes[168..200]);
+        bytes[200..208].copy_from_slice(&self.unique_id.to_le_bytes());
+        bytes[208..212].copy_from_slice(&self.length_of_extended_attributes.to_le_bytes());
+        bytes[212..216].copy_from_slice(&self.length_of_allocation_descriptors.to_le_bytes());
+        let attributes_end = 216 + self.length_of_extended_attributes as usize;
+        bytes[216..attributes_end].copy_from_slice(&self.extended_attributes);
+        bytes[attributes_end..].copy_from_slice(&self.allocation_descriptors);
+    }
+}
+
+/// Either of the descriptors that can record a file in an ICB
+#[derive(Debug, Clone)]
+pub enum AnyFileEntry {
+    File(FileEntry),
+    Extended(ExtendedFileEntry),
+}
+impl AnyFileEntry {
+    /// The File Entry or Extended File Entry at the start of bytes, by its tag identifier
+    pub fn read(bytes: &[u8]) -> io::Result<Self> {
+        match DescriptorTag::read(&bytes[0..16]).tag_identifier {
+            FileEntry::TAG_IDENTIFIER => Ok(Self::File(FileEntry::read(bytes)?)),
+            ExtendedFileEntry::TAG_IDENTIFIER => Ok(Self::Extended(ExtendedFileEntry::read(bytes)?)),
+            tag_identifier => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("tag identifier {} is not a File Entry", tag_identifier),
+            )),
+        }
+    }
+    /// The length of the descriptor as recorded
+    pub fn get_length(&self) -> usize {
+        match self {
+            Self::File(file_entry) => file_entry.get_length(),
+            Self::Extended(file_entry) => file_entry.get_length(),
+        }
+    }
+    /// The entry as a File Entry, so that both can be read the same way. An Extended File
+    /// Entry's object size, creation time and stream directory are dropped, and its tag is
+    /// kept as recorded.
+    pub fn into_file_entry(self) -> FileEntry {
+        match self {
+            Self::File(file_entry) => file_entry,
+            Self::Extended(e) => FileEntry {
+                tag: e.tag,
+                icb_tag: e.icb_tag,
+                uid: e.uid,
+                gid: e.gid,
+                permissions: e.permissions,
+                file_link_count: e.file_link_count,
+                record_format: e.record_format,
+                record_display_attributes: e.record_display_attributes,
+                record_length: e.record_length,
+                information_length: e.information_length,
+                logical_blocks_recorded: e.logical_blocks_recorded,
+                access_time: e.access_time,
+                modification_time: e.modification_time,
+                attribute_time: e.attribute_time,
+                checkpoint: e.checkpoint,
+                extended_attribute_icb: e.extended_attribute_icb,
+                implementation_identifier: e.implementation_identifier,
+                unique_id: e.unique_id,
+                length_of_extended_attributes: e.length_of_extended_attributes,
+                length_of_allocation_descriptors: e.length_of_allocation_descriptors,
+                extended_attributes: e.extended_attributes,
+                allocation_descriptors: e.allocation_descriptors,
+            },
+        }
+    }
+}
+
 pub struct TerminalEntry {
     tag: DescriptorTag,
     icb_tag: ICBTag,
@@ -1199,6 +1831,7 @@ impl TerminalEntry {
 }
 
 /// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=96
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum AllocationDescriptorType {
     SHORT = 0,
@@ -1206,14 +1839,23 @@ pub enum AllocationDescriptorType {
     EXTENDED = 2,
     ONE = 3,
 }
-impl From<u8> for AllocationDescriptorType {
-    fn from(v: u8) -> AllocationDescriptorType {
-        match v {
+impl AllocationDescriptorType {
+    /// The type in the low two bits of v, so any flags give a type
+    fn from_bits(v: u8) -> AllocationDescriptorType {
+        match v & 0b11 {
             0 => AllocationDescriptorType::SHORT,
             1 => AllocationDescriptorType::LONG,
             2 => AllocationDescriptorType::EXTENDED,
-            3 => AllocationDescriptorType::ONE,
-            _ => panic!("Invalid AllocationDescriptorType"),
+            _ => AllocationDescriptorType::ONE,
+        }
+    }
+}
+impl TryFrom<u8> for AllocationDescriptorType {
+    type Error = UdfError;
+    fn try_from(v: u8) -> Result<AllocationDescriptorType, UdfError> {
+        match v {
+            0..=3 => Ok(AllocationDescriptorType::from_bits(v)),
+            _ => Err(UdfError::InvalidAllocationDescriptorType(v)),
         }
     }
 }
@@ -1270,8 +1912,8 @@ impl From<u8> for FileType {
             12 => FileType::SymbolicLink,
             13 => FileType::StreamDirectory,
             14..=247 => FileType::Reserved,
+            // every byte is a file type, if only a reserved one
             248..=255 => FileType::Agreement,
-            _ => panic!("Invalid FileType"),
         }
     }
 }
@@ -1319,7 +1961,7 @@ impl ICBTag {
         bytes[18..20].copy_from_slice(&self.flags.to_le_bytes());
     }
     pub fn allocation_descriptor_type(&self) -> AllocationDescriptorType {
-        AllocationDescriptorType::from(self.flags as u8 & 0b11)
+        AllocationDescriptorType::from_bits(self.flags as u8)
     }
     pub fn file_type(&self) -> FileType {
         FileType::from(self.file_type)
@@ -1380,7 +2022,15 @@ impl FileIdentifierDescriptor {
         38 + self.length_of_implementation_use as usize + self.length_of_file_identifier as usize
     }
 
-    pub fn read(bytes: &[u8]) -> Self {
+    /// Fails if the descriptor, with the implementation use and file identifier it declares,
+    /// does not fit in bytes
+    pub fn read(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 38 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for File Identifier Descriptor",
+            ));
+        }
         let mut r = Self::default();
         r.tag = DescriptorTag::read(&bytes[0..16]);
         r.file_version_number = u16::from_le_bytes([bytes[16], bytes[17]]);
@@ -1390,9 +2040,15 @@ impl FileIdentifierDescriptor {
         r.length_of_implementation_use = u16::from_le_bytes([bytes[36], bytes[37]]);
         let impl_use_len = r.length_of_implementation_use as usize;
         let file_id_len = r.length_of_file_identifier as usize;
+        if bytes.len() < r.size() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for the File Identifier Descriptor's implementation use and file identifier",
+            ));
+        }
         r.implementation_use = bytes[38..38 + impl_use_len].to_vec();
         r.file_identifier = DynamicDstring(bytes[38 + impl_use_len..38 + impl_use_len + file_id_len].to_vec());
-        r
+        Ok(r)
     }
 
     pub fn write(&self, bytes: &mut [u8]) {
@@ -1407,6 +2063,60 @@ impl FileIdentifierDescriptor {
     }
 }
 
+/// ECMA-167 4/14.16.1 Path Component, of which the data of a symbolic link (4/8.7) is a
+/// sequence
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathComponent {
+    pub component_type: u8,
+    pub length_of_component_identifier: u8,
+    pub component_file_version_number: u16,
+    pub component_identifier: DynamicDstring,
+}
+impl PathComponent {
+    /// ECMA-167 4/14.16.1.1: a root agreed between originator and recipient, which UDF 2.6.2 uses
+    /// with an empty identifier for the root of the file system
+    pub const TYPE_AGREED_ROOT: u8 = 1;
+    /// the root of the directory hierarchy of the file set
+    pub const TYPE_ROOT: u8 = 2;
+    /// the parent of the directory so far, `..`
+    pub const TYPE_PARENT: u8 = 3;
+    /// the directory so far, `.`
+    pub const TYPE_CURRENT: u8 = 4;
+    /// the file or directory named by the component identifier
+    pub const TYPE_NAMED: u8 = 5;
+
+    pub fn size(&self) -> usize {
+        4 + self.length_of_component_identifier as usize
+    }
+
+    /// Fails if the component, with the identifier it declares, does not fit in bytes
+    pub fn read(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 4 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Buffer too small for Path Component"));
+        }
+        let length_of_component_identifier = bytes[1];
+        let identifier = bytes.get(4..4 + length_of_component_identifier as usize).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for the Path Component's component identifier",
+            )
+        })?;
+        Ok(Self {
+            component_type: bytes[0],
+            length_of_component_identifier,
+            component_file_version_number: u16::from_le_bytes([bytes[2], bytes[3]]),
+            component_identifier: DynamicDstring(identifier.to_vec()),
+        })
+    }
+
+    pub fn write(&self, bytes: &mut [u8]) {
+        bytes[0] = self.component_type;
+        bytes[1] = self.length_of_component_identifier;
+        bytes[2..4].copy_from_slice(&self.component_file_version_number.to_le_bytes());
+        bytes[4..self.size()].copy_from_slice(&self.component_identifier.0);
+    }
+}
+
 /// ECMA-167 4/14.7 https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=97
 #[derive(Debug, Clone)]
 pub struct IndirectEntry {
@@ -1461,17 +2171,76 @@ pub enum ExtentType {
     NextExtent = 3,
 }
 impl ExtentType {
-    pub fn from_u8(v: u8) -> ExtentType {
-        match v {
+    /// The type in the two most significant bits of an extent length, so any length gives a type
+    fn from_extent_length(extent_length_and_type: u32) -> ExtentType {
+        match extent_length_and_type >> 30 {
             0 => ExtentType::RecordedAllocated,
             1 => ExtentType::NotRecordedAllocated,
             2 => ExtentType::NotRecordedNotAllocated,
-            3 => ExtentType::NextExtent,
-            _ => panic!("Invalid ExtentType"),
+            _ => ExtentType::NextExtent,
+        }
+    }
+}
+impl TryFrom<u8> for ExtentType {
+    type Error = UdfError;
+    fn try_from(v: u8) -> Result<ExtentType, UdfError> {
+        match v {
+            0..=3 => Ok(ExtentType::from_extent_length((v as u32) << 30)),
+            _ => Err(UdfError::InvalidExtentType(v)),
         }
     }
 }
 
+/// Allocation Extent Descriptor: the allocation descriptors of a file that continue past its
+/// File Entry, in the extent that an allocation descriptor of type NextExtent points to
+/// ECMA-167 4/14.5 Allocation Extent Descriptor
+/// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=95
+#[derive(Debug, Clone, Default)]
+pub struct AllocationExtentDescriptor {
+    pub tag: DescriptorTag,
+    pub previous_allocation_extent_location: u32,
+    pub length_of_allocation_descriptors: u32,
+    pub allocation_descriptors: Vec<u8>,
+}
+impl AllocationExtentDescriptor {
+    /// ECMA-167 4/7.2.1 Tag Identifier (RBP 0)
+    /// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=74
+    pub const TAG_IDENTIFIER: u16 = 258;
+    pub fn get_length(&self) -> usize {
+        24 + self.length_of_allocation_descriptors as usize
+    }
+    /// Fails if the allocation descriptors it declares do not fit in bytes
+    pub fn read(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < 24 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for Allocation Extent Descriptor",
+            ));
+        }
+        let mut r = Self {
+            tag: DescriptorTag::read(&bytes[0..16]),
+            previous_allocation_extent_location: u32::from_le_bytes(bytes[16..20].try_into().unwrap()),
+            length_of_allocation_descriptors: u32::from_le_bytes(bytes[20..24].try_into().unwrap()),
+            allocation_descriptors: vec![],
+        };
+        let end = r.get_length();
+        if bytes.len() < end {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Buffer too small for the Allocation Extent Descriptor's allocation descriptors",
+            ));
+        }
+        r.allocation_descriptors = bytes[24..end].to_vec();
+        Ok(r)
+    }
+    pub fn write(&self, bytes: &mut [u8]) {
+        self.tag.write(&mut bytes[0..16]);
+        bytes[16..20].copy_from_slice(&self.previous_allocation_extent_location.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.length_of_allocation_descriptors.to_le_bytes());
+        bytes[24..].copy_from_slice(&self.allocation_descriptors);
+    }
+}
+
 /// ECMA-167 4/14.14.1 Short Allocation Descriptor aka struct short_ad
 /// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=116
 #[derive(Debug, Clone)]
@@ -1492,7 +2261,7 @@ impl ShortAllocationDescriptor {
         self.extent_length_and_type & 0x3FFFFFFF
     }
     pub fn extent_type(&self)-> ExtentType{
-        ExtentType::from_u8((self.extent_length_and_type >> 30) as u8)
+        ExtentType::from_extent_length(self.extent_length_and_type)
     }
 
     pub fn read(bytes: &[u8]) -> Self {
@@ -1553,6 +2322,6 @@ impl LongAd {
         self.extent_length_and_type & 0x3FFFFFFF
     }
     pub fn extent_type(&self)-> ExtentType{
-        ExtentType::from_u8((self.extent_length_and_type >> 30) as u8)
+        ExtentType::from_extent_length(self.extent_length_and_type)
     }
 }
@@ -0,0 +1,168 @@
+//! Reading a file on the disc in place, e.g. to stream a VOB into a transcoder without copying
+//! it out first. Its data is read on demand through the cache.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+use crate::{
+    cache::SharedCache,
+    copier::read_icb_entries,
+    logical_block_reader::{file_data_extents, read_exact_from_partition},
+    udf::{LogicalVolumeDescriptor, LongAd, PartitionDescriptor},
+    udf_parser::{embedded_data, Result},
+};
+
+/// The data of a file: recorded in its File Entry, or in extents of partitions
+enum FileData<'a> {
+    Embedded(Vec<u8>),
+    /// the partition, byte offset in the partition and length of each extent
+    Extents(Vec<(&'a PartitionDescriptor, usize, u64)>),
+}
+
+/// A file of the disc, read and seeked like a local file
+pub struct UdfFile<'a, R: Read + Seek> {
+    reader: SharedCache<'a, R>,
+    data: FileData<'a>,
+    size: u64,
+    position: u64,
+}
+
+impl<'a, R: Read + Seek> UdfFile<'a, R> {
+    /// Open the file whose ICB is at icb_address, reading it through reader (from
+    /// UdfParser::cache). path is only used in messages.
+    pub fn open(
+        mut reader: SharedCache<'a, R>,
+        logical_volume_descriptor: &LogicalVolumeDescriptor,
+        partition_descriptors: &'a BTreeMap<u16, PartitionDescriptor>,
+        icb_address: &LongAd,
+        path: &str,
+    ) -> Result<Self> {
+        let (_, file_entries) =
+            read_icb_entries(&mut reader, logical_volume_descriptor, partition_descriptors, icb_address)?;
+        let data = match file_entries.first().and_then(embedded_data) {
+            Some(data) => FileData::Embedded(data.to_vec()),
+            None => FileData::Extents(file_data_extents(
+                logical_volume_descriptor,
+                partition_descriptors,
+                icb_address.extent_location.partition_reference_number,
+                &file_entries,
+                path,
+            )?),
+        };
+        let size = match &data {
+            FileData::Embedded(data) => data.len() as u64,
+            FileData::Extents(extents) => extents.iter().map(|&(_, _, length)| length).sum(),
+        };
+        Ok(Self {
+            reader,
+            data,
+            size,
+            position: 0,
+        })
+    }
+
+    /// The number of bytes that can be read, which is less than the length in the File Entry
+    /// if its allocation descriptors do not describe all of it
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<R: Read + Seek> Read for UdfFile<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let extents = match &self.data {
+            FileData::Extents(extents) => extents,
+            FileData::Embedded(data) => {
+                let start = self.position.min(data.len() as u64) as usize;
+                let len = buf.len().min(data.len() - start);
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                self.position += len as u64;
+                return Ok(len);
+            }
+        };
+        let mut extent_start = 0;
+        for &(partition_descriptor, pos_in_partition, length) in extents {
+            if self.position < extent_start + length {
+                let offset = self.position - extent_start;
+                let len = (buf.len() as u64).min(length - offset) as usize;
+                read_exact_from_partition(
+                    &mut self.reader,
+                    partition_descriptor,
+                    pos_in_partition + offset as usize,
+                    &mut buf[..len],
+                )
+                .map_err(io::Error::other)?;
+                self.position += len as u64;
+                return Ok(len);
+            }
+            extent_start += length;
+        }
+        Ok(0)
+    }
+}
+
+impl<R: Read + Seek> Seek for UdfFile<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        copier::walk_file_sets,
+        path_filter::PathFilter,
+        test_support::{build_image, FixtureFile, Quirks},
+        udf_parser::{DirectoryWalk, UdfParser},
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_and_seek() {
+        let contents: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let files = [FixtureFile {
+            path: "VIDEO_TS/VTS_01_1.VOB",
+            contents: &contents,
+        }];
+        for quirks in [Quirks::default(), Quirks { long_ads: true, ..Default::default() }] {
+            let image = build_image(&files, "STREAM", &quirks).unwrap();
+            let mut parser = UdfParser::new(Cursor::new(image));
+            let structures = parser.read_volume_structures().unwrap();
+            let mut icb = None;
+            let max_depth = DirectoryWalk::DEFAULT_MAX_DEPTH;
+            walk_file_sets(&mut parser, &structures, max_depth, &PathFilter::default(), &mut |_, _, icb_address, _| {
+                icb = Some(icb_address.clone());
+                Ok(())
+            })
+            .unwrap();
+            let (logical_volume, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+            let mut file =
+                UdfFile::open(parser.cache(), logical_volume, partitions, &icb.unwrap(), "VTS_01_1.VOB").unwrap();
+            assert_eq!(file.size(), 5000);
+
+            let mut all = vec![];
+            file.read_to_end(&mut all).unwrap();
+            assert_eq!(all, contents);
+
+            let mut buf = [0u8; 100];
+            assert_eq!(file.seek(SeekFrom::Start(4000)).unwrap(), 4000);
+            file.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[..], contents[4000..4100]);
+            assert_eq!(file.seek(SeekFrom::End(-10)).unwrap(), 4990);
+            assert_eq!(file.read(&mut buf).unwrap(), 10);
+            assert_eq!(file.read(&mut buf).unwrap(), 0);
+            assert!(file.seek(SeekFrom::Current(-6000)).is_err());
+        }
+    }
+}
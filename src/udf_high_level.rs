@@ -1,16 +1,23 @@
-use log::debug;
+use log::{debug, warn};
+use serde::{Serialize, Serializer};
 use std::{
     collections::BTreeMap, fs::File, io::{Read, Seek}
 };
 
 use crate::{
     dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    logical_block_reader::{virtual_partition_start, SparablePartition, VirtualPartition},
+    offsets_of,
     udf::{
-        DescriptorTag, LogicalVolumeDescriptor, PartitionDescriptor, PartitionMap, PrimaryVolumeDescriptor, TerminatingDescriptor, Timestamp
+        DescriptorTag, FileEntry, LogicalVolumeDescriptor, LongAd, MetadataPartitionMap, PartitionDescriptor, PartitionMap, PrimaryVolumeDescriptor, ShortAllocationDescriptor, SparingTable, TerminatingDescriptor, Timestamp
     },
-    udf_parser::{Result, UdfError, UdfParser},
+    udf_parser::{read_allocation_descriptors, read_file_contents, read_file_entries, Result, UdfError, UdfParser},
+    vat::read_virtual_allocation_table,
 };
 
+/// The sectors before the last one where the File Entry of a Virtual Allocation Table is looked for
+const VAT_SEARCH_SECTORS: u32 = 16;
+
 // ... [Previous error definitions remain the same]
 
 #[derive(Debug)]
@@ -18,7 +25,8 @@ pub struct VolumeStructures {
     pub primary_volume: PrimaryVolumeDescriptor,
     pub logical_volume: LogicalVolumeDescriptor,
     pub partition_maps: Vec<PartitionMap>,
-    /// mapping from partition number to partition descriptor
+    /// mapping from partition number to partition descriptor, and from the partition reference
+    /// number of each Metadata Partition to the blocks of its Metadata File
     pub partition_descriptors: BTreeMap<u16, PartitionDescriptor>,
 }
 
@@ -44,8 +52,9 @@ impl<R: Read + Seek> UdfParser<R> {
         )?;
 
         // If main sequence failed or was incomplete, try reserve sequence
-        if structures.is_none() {
-            return self
+        let structures = match structures {
+            Some(structures) => structures,
+            None => self
                 .read_volume_descriptor_sequence(
                     anchor
                         .reserve_volume_descriptor_sequence_location
@@ -54,10 +63,242 @@ impl<R: Read + Seek> UdfParser<R> {
                         .reserve_volume_descriptor_sequence_location
                         .length_bytes,
                 )?
-                .ok_or(UdfError::InvalidDescriptorTag);
+                .ok_or(UdfError::InvalidDescriptorTag)?,
+        };
+        let structures = self.map_sparable_partitions(structures)?;
+        let structures = self.map_metadata_partitions(structures)?;
+        self.map_virtual_partitions(structures)
+    }
+
+    /// Read the Sparing Table of each Sparable Partition Map (UDF 2.2.9) into
+    /// sparable_partitions, and add its partition to partition_descriptors under its partition
+    /// reference number. A partition whose Sparing Table cannot be read is read as it is.
+    fn map_sparable_partitions(&mut self, mut structures: VolumeStructures) -> Result<VolumeStructures> {
+        for (reference, partition_map) in structures.partition_maps.iter().enumerate() {
+            let PartitionMap::Type2(partition_map) = partition_map else {
+                continue;
+            };
+            let Some(sparable_map) = partition_map.sparable() else {
+                continue;
+            };
+            let reference = reference as u16;
+            let physical = structures
+                .partition_descriptors
+                .get(&partition_map.partition_number)
+                .ok_or(UdfError::InvalidPartitionNumber)?
+                .clone();
+            // the copies are the same, but one may have been rewritten last
+            let mut sparing_table: Option<SparingTable> = None;
+            for &location in &sparable_map.sparing_table_locations {
+                match self.read_sparing_table(location, sparable_map.size_of_each_sparing_table) {
+                    Ok(table) => {
+                        if sparing_table.as_ref().is_none_or(|last| last.sequence_number < table.sequence_number) {
+                            sparing_table = Some(table);
+                        }
+                    }
+                    Err(e) => warn!("Could not read the Sparing Table at sector {}: {}", location, e),
+                }
+            }
+            match sparing_table {
+                Some(sparing_table) => {
+                    let sparable_partition = SparablePartition::new(&physical, sparable_map.packet_length, &sparing_table);
+                    debug!(
+                        "map_sparable_partitions: partition {} has {} moved packets",
+                        physical.partition_number,
+                        sparable_partition.spared_packets.len()
+                    );
+                    self.sparable_partitions.push(sparable_partition);
+                }
+                None => warn!(
+                    "Sparable Partition {}: no Sparing Table could be read, so packets that were moved are read from where they were",
+                    reference
+                ),
+            }
+            structures.partition_descriptors.entry(reference).or_insert(physical);
+        }
+        Ok(structures)
+    }
+
+    /// Add the partition of each Virtual Partition Map (UDF 2.2.8) to partition_descriptors
+    /// under its partition reference number, at sectors that the caches of the parser read
+    /// through its Virtual Allocation Table
+    fn map_virtual_partitions(&mut self, mut structures: VolumeStructures) -> Result<VolumeStructures> {
+        for (reference, partition_map) in structures.partition_maps.iter().enumerate() {
+            let PartitionMap::Type2(partition_map) = partition_map else {
+                continue;
+            };
+            if !partition_map.is_virtual() {
+                continue;
+            }
+            let reference = reference as u16;
+            let physical = structures
+                .partition_descriptors
+                .get(&partition_map.partition_number)
+                .ok_or(UdfError::InvalidPartitionNumber)?;
+            if structures.partition_descriptors.contains_key(&reference) {
+                warn!("the virtual partition's reference number {} is also the number of a partition", reference);
+                return Err(UdfError::InvalidPartitionNumber);
+            }
+            let vat = self.find_virtual_allocation_table(&structures, partition_map.partition_number)?;
+            let virtual_partition = PartitionDescriptor {
+                partition_number: reference,
+                partition_starting_location: virtual_partition_start(reference),
+                partition_length: vat.len() as u32,
+                ..physical.clone()
+            };
+            self.virtual_partitions.insert(
+                reference,
+                VirtualPartition {
+                    physical_start: physical.partition_starting_location,
+                    vat,
+                },
+            );
+            structures.partition_descriptors.insert(reference, virtual_partition);
+        }
+        Ok(structures)
+    }
+
+    /// The entries of the Virtual Allocation Table of the virtual partition recorded in the
+    /// partition with partition_number. Its File Entry is the last sector recorded (UDF 2.2.11),
+    /// but some sectors before the end are tried too, as drives can count run-out sectors
+    /// after it.
+    fn find_virtual_allocation_table(
+        &mut self,
+        structures: &VolumeStructures,
+        partition_number: u16,
+    ) -> Result<Vec<u32>> {
+        let physical = &structures.partition_descriptors[&partition_number];
+        let total_sectors = self.get_total_sectors()?;
+        let last_block = total_sectors
+            .checked_sub(physical.partition_starting_location + 1)
+            .ok_or(UdfError::InvalidPartitionNumber)?;
+        let mut reader = self.cache();
+        for block in (last_block.saturating_sub(VAT_SEARCH_SECTORS)..=last_block).rev() {
+            let icb = ShortAllocationDescriptor {
+                extent_length_and_type: structures.logical_volume.logical_block_size,
+                extent_location: block,
+            };
+            let vat = read_file_entries(&mut reader, &structures.logical_volume, physical, &icb).and_then(|file_entries| {
+                let file_entry = file_entries.first().ok_or(UdfError::InvalidDescriptorTag)?;
+                let mut data = read_file_contents(
+                    &mut reader,
+                    &structures.logical_volume,
+                    &structures.partition_descriptors,
+                    partition_number,
+                    &file_entries,
+                )?;
+                data.truncate(file_entry.information_length as usize);
+                Ok(read_virtual_allocation_table(file_entry.icb_tag.file_type, &data)?)
+            });
+            match vat {
+                Ok(vat) => {
+                    debug!("find_virtual_allocation_table: {} entries at block {}", vat.len(), block);
+                    return Ok(vat);
+                }
+                Err(e) => debug!("find_virtual_allocation_table: block {}: {}", block, e),
+            }
+        }
+        Err(UdfError::NoVirtualAllocationTable(VAT_SEARCH_SECTORS + 1))
+    }
+
+    /// Add the partition of each Metadata Partition Map (UDF 2.50 and later) to
+    /// partition_descriptors under its partition reference number, so that the File Entries and
+    /// directories in it are read like those of a Type 1 partition. The Mirror File is read if
+    /// the Metadata File cannot be.
+    fn map_metadata_partitions(&mut self, mut structures: VolumeStructures) -> Result<VolumeStructures> {
+        for (reference, partition_map) in structures.partition_maps.iter().enumerate() {
+            let PartitionMap::Type2(partition_map) = partition_map else {
+                continue;
+            };
+            let Some(metadata_map) = partition_map.metadata() else {
+                continue;
+            };
+            let reference = reference as u16;
+            let physical = structures
+                .partition_descriptors
+                .get(&partition_map.partition_number)
+                .ok_or_else(|| {
+                    UdfError::MetadataPartition(
+                        reference,
+                        format!("there is no partition {} to record it in", partition_map.partition_number),
+                    )
+                })?;
+            // the key is the partition number of the physical partitions, which on every disc
+            // with a Metadata Partition is that of the Type 1 map before it
+            if structures.partition_descriptors.contains_key(&reference) {
+                return Err(UdfError::MetadataPartition(
+                    reference,
+                    format!("its partition reference number is also the number of partition {}", reference),
+                ));
+            }
+            let mut metadata_partition = Err(UdfError::InvalidDescriptorTag);
+            for (name, location) in [
+                ("Metadata File", metadata_map.metadata_file_location),
+                ("Metadata Mirror File", metadata_map.metadata_mirror_file_location),
+            ] {
+                metadata_partition = self.read_metadata_file(&structures.logical_volume, physical, location).and_then(
+                    |file_entries| {
+                        metadata_partition_descriptor(&structures.logical_volume, physical, reference, &file_entries)
+                    },
+                );
+                match &metadata_partition {
+                    Ok(descriptor) => {
+                        debug!(
+                            "map_metadata_partitions: partition {} is the {}: {:?}",
+                            reference, name, descriptor
+                        );
+                        break;
+                    }
+                    Err(e) => warn!("Could not read the {}: {}", name, e),
+                }
+            }
+            let metadata_partition = metadata_partition?;
+            structures.partition_descriptors.insert(reference, metadata_partition);
         }
+        Ok(structures)
+    }
+
+    /// The File Entries of the Metadata File or Mirror File at block location of physical
+    fn read_metadata_file(
+        &mut self,
+        logical_volume: &LogicalVolumeDescriptor,
+        physical: &PartitionDescriptor,
+        location: u32,
+    ) -> Result<Vec<FileEntry>> {
+        let mut reader = self.cache();
+        let icb = ShortAllocationDescriptor {
+            extent_length_and_type: logical_volume.logical_block_size,
+            extent_location: location,
+        };
+        read_file_entries(&mut reader, logical_volume, physical, &icb)
+    }
 
-        structures.ok_or(UdfError::InvalidDescriptorTag)
+    /// Read both the main and the reserve Volume Descriptor Sequence
+    /// so they can be compared with [compare_volume_structures].
+    /// A sequence that cannot be read is returned as None.
+    pub fn read_main_and_reserve_volume_structures(
+        &mut self,
+    ) -> Result<(Option<VolumeStructures>, Option<VolumeStructures>)> {
+        let anchor = self.read_anchor()?;
+        let main = self
+            .read_volume_descriptor_sequence(
+                anchor.main_volume_descriptor_sequence_location.location_sector,
+                anchor.main_volume_descriptor_sequence_location.length_bytes,
+            )
+            .unwrap_or_else(|e| {
+                warn!("Could not read main volume descriptor sequence: {}", e);
+                None
+            });
+        let reserve = self
+            .read_volume_descriptor_sequence(
+                anchor.reserve_volume_descriptor_sequence_location.location_sector,
+                anchor.reserve_volume_descriptor_sequence_location.length_bytes,
+            )
+            .unwrap_or_else(|e| {
+                warn!("Could not read reserve volume descriptor sequence: {}", e);
+                None
+            });
+        Ok((main, reserve))
     }
 
     /** Read a Volume Descriptor Sequence.
@@ -90,8 +331,7 @@ impl<R: Read + Seek> UdfParser<R> {
         while current_location < end_location {
             // Read the tag to determine the descriptor type
             let mut tag_buf: Vec<u8> = vec![0u8; DescriptorTag::size().max(DVDCSS_BLOCK_SIZE)];
-            self.seek_to_sector(current_location)?;
-            self.reader.read_exact(&mut tag_buf)?;
+            self.read_sectors(current_location, &mut tag_buf)?;
 
             let tag: DescriptorTag = DescriptorTag::read(&tag_buf);
 
@@ -140,8 +380,98 @@ impl<R: Read + Seek> UdfParser<R> {
     }
 }
 
+/// The partition that holds the blocks of a Metadata Partition: those of its Metadata or
+/// Mirror File, whose file_entries are in physical. Only a Metadata File whose extents follow
+/// one another can be read, as a partition is read as a single run of sectors; that is how
+/// authoring tools record it.
+pub fn metadata_partition_descriptor(
+    logical_volume: &LogicalVolumeDescriptor,
+    physical: &PartitionDescriptor,
+    reference: u16,
+    file_entries: &[FileEntry],
+) -> Result<PartitionDescriptor> {
+    let error = |message: String| UdfError::MetadataPartition(reference, message);
+    let file_entry = file_entries.first().ok_or_else(|| error("the File Entry is not recorded".to_string()))?;
+    let file_type = file_entry.icb_tag.file_type;
+    if file_type != MetadataPartitionMap::METADATA_FILE_TYPE && file_type != MetadataPartitionMap::MIRROR_FILE_TYPE {
+        return Err(error(format!("the File Entry has file type {} instead of a Metadata File's", file_type)));
+    }
+    let block_size = logical_volume.logical_block_size as u64;
+    // the extents are in the physical partition, whatever partition a long_ad names
+    let mut extents: Vec<LongAd> = vec![];
+    for file_entry in file_entries {
+        extents.extend(read_allocation_descriptors(file_entry, 0)?);
+    }
+    let first_block = extents
+        .first()
+        .ok_or_else(|| error("the Metadata File has no extents".to_string()))?
+        .extent_location
+        .logical_block_number as u64;
+    let mut next_block = first_block;
+    for extent in &extents {
+        if extent.extent_location.logical_block_number as u64 != next_block {
+            return Err(error(format!(
+                "the Metadata File continues at block {} instead of {}",
+                { extent.extent_location.logical_block_number },
+                next_block
+            )));
+        }
+        next_block += (extent.extent_length_bytes() as u64).div_ceil(block_size);
+    }
+    let sectors_per_block = block_size / DVDCSS_BLOCK_SIZE as u64;
+    if next_block * sectors_per_block > physical.partition_length as u64 {
+        return Err(error(format!(
+            "the Metadata File ends at block {}, past the end of its partition",
+            next_block
+        )));
+    }
+    Ok(PartitionDescriptor {
+        partition_number: reference,
+        partition_starting_location: physical.partition_starting_location + (first_block * sectors_per_block) as u32,
+        partition_length: ((next_block - first_block) * sectors_per_block) as u32,
+        ..physical.clone()
+    })
+}
+
 // Add a convenience method to get volume information
 impl VolumeStructures {
+    /// The Partition Descriptors recorded on the disc, without those of Metadata and virtual
+    /// partitions, which read_volume_structures adds
+    pub fn recorded_partition_descriptors(&self) -> impl Iterator<Item = &PartitionDescriptor> {
+        self.partition_descriptors.iter().filter_map(|(&key, partition_descriptor)| {
+            let mapped = matches!(
+                self.partition_maps.get(key as usize),
+                Some(PartitionMap::Type2(map)) if map.metadata().is_some() || map.is_virtual()
+            );
+            (!mapped).then_some(partition_descriptor)
+        })
+    }
+
+    /// The partition reference number of the Metadata or virtual partition recorded in the
+    /// partition with partition_number, which holds the file set instead of it
+    pub fn file_set_partition_of(&self, partition_number: u16) -> Option<u16> {
+        self.partition_maps.iter().enumerate().find_map(|(reference, partition_map)| match partition_map {
+            PartitionMap::Type2(map)
+                if map.partition_number == partition_number && (map.metadata().is_some() || map.is_virtual()) =>
+            {
+                Some(reference as u16)
+            }
+            _ => None,
+        })
+    }
+
+    /// The block of the partition with reference number reference where its File Set
+    /// Descriptor Sequence starts: where the Logical Volume Descriptor says it is, if it is in
+    /// that partition, or else the first block
+    pub fn file_set_block(&self, reference: u16) -> u32 {
+        let file_set = LongAd::read(&self.logical_volume.logical_volume_contents_use);
+        if file_set.extent_location.partition_reference_number == reference {
+            file_set.extent_location.logical_block_number
+        } else {
+            0
+        }
+    }
+
     pub fn volume_info(&self) -> VolumeInfo {
         VolumeInfo {
             identifier: self.primary_volume.volume_identifier.to_string(),
@@ -159,16 +489,162 @@ impl VolumeStructures {
     }
 }
 
-#[derive(Debug)]
+/// A field that differs between the main and reserve Volume Descriptor Sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct VdsDifference {
+    /// e.g. "primary_volume" or "partition_descriptors[0]"
+    pub descriptor: String,
+    pub field: &'static str,
+    pub main: String,
+    pub reserve: String,
+}
+
+/// Compare the serialized bytes of each listed field of two descriptors.
+/// The tag is deliberately not compared since the tag location
+/// (and therefore the checksum and CRC) differs between the two sequences.
+macro_rules! diff_fields {
+    ($differences:expr, $descriptor:expr, $type:ty, $main:expr, $reserve:expr, [$($field:ident),* $(,)?]) => {{
+        let mut main_bytes = vec![0u8; <$type>::size()];
+        let mut reserve_bytes = vec![0u8; <$type>::size()];
+        $main.write(&mut main_bytes);
+        $reserve.write(&mut reserve_bytes);
+        $(
+            let range = offsets_of!($type, $field);
+            if main_bytes[range.clone()] != reserve_bytes[range] {
+                $differences.push(VdsDifference {
+                    descriptor: $descriptor.to_string(),
+                    field: stringify!($field),
+                    main: format!("{:?}", $main.$field),
+                    reserve: format!("{:?}", $reserve.$field),
+                });
+            }
+        )*
+    }};
+}
+
+/// Field-level comparison of the main and reserve Volume Descriptor Sequences.
+/// The two copies should be identical apart from their locations;
+/// disagreement usually indicates a mastering or media problem.
+pub fn compare_volume_structures(
+    main: &VolumeStructures,
+    reserve: &VolumeStructures,
+) -> Vec<VdsDifference> {
+    let mut differences = vec![];
+    diff_fields!(
+        differences,
+        "primary_volume",
+        PrimaryVolumeDescriptor,
+        main.primary_volume,
+        reserve.primary_volume,
+        [
+            volume_descriptor_sequence_number,
+            primary_volume_descriptor_number,
+            volume_identifier,
+            volume_sequence_number,
+            maximum_volume_sequence_number,
+            interchange_level,
+            maximum_interchange_level,
+            character_set_list,
+            maximum_character_set_list,
+            volume_set_identifier,
+            descriptor_character_set,
+            explanatory_character_set,
+            volume_abstract,
+            volume_copyright_notice,
+            application_identifier,
+            recording_date_and_time,
+            implementation_identifier,
+            implementation_use,
+            predecessor_volume_descriptor_sequence_location,
+            flags,
+        ]
+    );
+    diff_fields!(
+        differences,
+        "logical_volume",
+        LogicalVolumeDescriptor,
+        main.logical_volume,
+        reserve.logical_volume,
+        [
+            volume_descriptor_sequence_number,
+            descriptor_character_set,
+            logical_volume_identifier,
+            logical_block_size,
+            domain_identifier,
+            logical_volume_contents_use,
+            map_table_length,
+            number_of_partition_maps,
+            implementation_identifier,
+            implementation_use,
+            integrity_sequence_extent,
+        ]
+    );
+    let main_maps = format!("{:?}", main.partition_maps);
+    let reserve_maps = format!("{:?}", reserve.partition_maps);
+    if main_maps != reserve_maps {
+        differences.push(VdsDifference {
+            descriptor: "logical_volume".to_string(),
+            field: "partition_maps",
+            main: main_maps,
+            reserve: reserve_maps,
+        });
+    }
+    let partition_numbers = main
+        .partition_descriptors
+        .keys()
+        .chain(reserve.partition_descriptors.keys())
+        .collect::<std::collections::BTreeSet<_>>();
+    for partition_number in partition_numbers {
+        let descriptor = format!("partition_descriptors[{}]", partition_number);
+        match (
+            main.partition_descriptors.get(partition_number),
+            reserve.partition_descriptors.get(partition_number),
+        ) {
+            (Some(main_pd), Some(reserve_pd)) => diff_fields!(
+                differences,
+                descriptor,
+                PartitionDescriptor,
+                main_pd,
+                reserve_pd,
+                [
+                    volume_descriptor_sequence_number,
+                    partition_flags,
+                    partition_number,
+                    partition_contents,
+                    partition_contents_use,
+                    access_type,
+                    partition_starting_location,
+                    partition_length,
+                    implementation_identifier,
+                    implementation_use,
+                ]
+            ),
+            (main_pd, reserve_pd) => differences.push(VdsDifference {
+                descriptor,
+                field: "present",
+                main: main_pd.is_some().to_string(),
+                reserve: reserve_pd.is_some().to_string(),
+            }),
+        }
+    }
+    differences
+}
+
+#[derive(Debug, Serialize)]
 pub struct VolumeInfo {
     pub identifier: String,
     pub set_identifier: String,
     pub logical_volume_identifier: String,
     pub logical_block_size: u32,
+    #[serde(serialize_with = "serialize_iso8601")]
     pub recording_timestamp: Timestamp,
     pub application_id: String,
 }
 
+fn serialize_iso8601<S: Serializer>(timestamp: &Timestamp, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&timestamp.iso8601())
+}
+
 // Example usage in tests
 #[cfg(test)]
 mod tests {
@@ -238,6 +714,106 @@ mod tests {
         assert_eq!(info.identifier, "TEST_VOLUME");
         assert_eq!(info.logical_block_size, 2048);
     }
+
+    fn test_structures() -> VolumeStructures {
+        let mut partition_descriptors = BTreeMap::new();
+        partition_descriptors.insert(0, PartitionDescriptor::default());
+        VolumeStructures {
+            primary_volume: PrimaryVolumeDescriptor::default(),
+            logical_volume: LogicalVolumeDescriptor::default(),
+            partition_maps: vec![PartitionMap::Type1(Type1PartitionMap::default())],
+            partition_descriptors,
+        }
+    }
+
+    #[test]
+    fn test_metadata_partition_descriptor() {
+        let data = [1, 0, 0, 0, 2, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 32, 0, 0, 0, 16, 0, 1, 0, 0, 0, 0, 0];
+        let metadata_map = MetadataPartitionMap::read(&data);
+        assert_eq!(metadata_map.metadata_mirror_file_location, 2);
+        assert_eq!(metadata_map.flags, MetadataPartitionMap::DUPLICATE_METADATA_FLAG);
+        let mut written = [0; 24];
+        metadata_map.write(&mut written);
+        assert_eq!(written, data);
+
+        let logical_volume = LogicalVolumeDescriptor {
+            logical_block_size: 2048,
+            ..Default::default()
+        };
+        let physical = PartitionDescriptor {
+            partition_starting_location: 272,
+            partition_length: 1000,
+            ..Default::default()
+        };
+        let metadata_file = |file_type: u8, extents: &[(u32, u32)]| {
+            let mut file_entry = FileEntry::default();
+            file_entry.icb_tag.file_type = file_type;
+            for &(extent_location, length) in extents {
+                let mut ad = [0; 8];
+                ShortAllocationDescriptor {
+                    extent_length_and_type: length,
+                    extent_location,
+                }
+                .write(&mut ad);
+                file_entry.allocation_descriptors.extend_from_slice(&ad);
+            }
+            file_entry
+        };
+        let file_entries = [metadata_file(MetadataPartitionMap::METADATA_FILE_TYPE, &[(32, 2048 * 10), (42, 2048 * 6)])];
+        let descriptor = metadata_partition_descriptor(&logical_volume, &physical, 1, &file_entries).unwrap();
+        assert_eq!(
+            (descriptor.partition_number, descriptor.partition_starting_location, descriptor.partition_length),
+            (1, 304, 16)
+        );
+
+        let fragmented = [metadata_file(MetadataPartitionMap::MIRROR_FILE_TYPE, &[(32, 2048), (40, 2048)])];
+        assert!(matches!(
+            metadata_partition_descriptor(&logical_volume, &physical, 1, &fragmented),
+            Err(UdfError::MetadataPartition(1, _))
+        ));
+        let not_metadata = [metadata_file(5, &[(32, 2048)])];
+        assert!(metadata_partition_descriptor(&logical_volume, &physical, 1, &not_metadata).is_err());
+        let past_end = [metadata_file(MetadataPartitionMap::METADATA_FILE_TYPE, &[(990, 2048 * 20)])];
+        assert!(metadata_partition_descriptor(&logical_volume, &physical, 1, &past_end).is_err());
+    }
+
+    #[test]
+    fn test_compare_volume_structures() {
+        // in-memory field offsets must match the on-disc layout used by write()
+        assert_eq!(offsets_of!(PrimaryVolumeDescriptor, volume_identifier), 24..56);
+        assert_eq!(offsets_of!(PrimaryVolumeDescriptor, flags), 488..490);
+        assert_eq!(offsets_of!(LogicalVolumeDescriptor, logical_block_size), 212..216);
+        assert_eq!(offsets_of!(PartitionDescriptor, partition_length), 192..196);
+
+        let main = test_structures();
+        let mut reserve = test_structures();
+        assert_eq!(compare_volume_structures(&main, &reserve), vec![]);
+
+        // tags are expected to differ
+        reserve.primary_volume.tag.tag_location = 48;
+        reserve.primary_volume.volume_identifier = crate::udf::Dstring::from_str("OTHER");
+        reserve
+            .partition_descriptors
+            .get_mut(&0)
+            .unwrap()
+            .partition_length = 100;
+        reserve
+            .partition_descriptors
+            .insert(1, PartitionDescriptor::default());
+        let differences = compare_volume_structures(&main, &reserve);
+        assert_eq!(
+            differences
+                .iter()
+                .map(|d| format!("{}.{}", d.descriptor, d.field))
+                .collect::<Vec<_>>(),
+            vec![
+                "primary_volume.volume_identifier",
+                "partition_descriptors[0].partition_length",
+                "partition_descriptors[1].present",
+            ]
+        );
+        assert_eq!(differences[0].reserve, "OTHER");
+    }
 }
 
 // Example usage
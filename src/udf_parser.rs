@@ -1,24 +1,32 @@
-use log::{debug, error};
+use log::{debug, error, warn};
 use std::{
     arch::aarch64::__crc32b,
-    collections::BTreeMap,
+    collections::{BTreeMap, HashSet},
     io::{self, Read, Seek, SeekFrom},
     mem::offset_of,
     ptr::addr_of,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     vec,
 };
 use thiserror::Error;
 
 use crate::{
-    cache::Cache,
+    cache::{BlockDevice, BlockStore, Cache, CacheStats, Readahead, ReadErrorPolicy, SharedCache},
     crc::cksum,
-    dvdcss_sys::DVDCSS_BLOCK_SIZE,
-    logical_block_reader::{read_exact_from_partition, short_ad_to_pos_in_partition},
+    logical_block_reader::{
+        long_ad_partition_pos, read_exact_from_partition, spared_sector, translate_virtual_sector, SparablePartition,
+        VirtualPartition,
+    },
+    path_filter::PathFilter,
     udf::{
-        AnchorVolumeDescriptorPointer, DescriptorTag, FileEntry, FileIdentifierDescriptor,
-        FileSetDescriptor, ICBTag, IndirectEntry, LbAddr, LogicalVolumeDescriptor, LongAd,
-        PartitionDescriptor, PartitionMap, PrimaryVolumeDescriptor, ShortAllocationDescriptor,
-        TerminalEntry, TerminatingDescriptor, Type1PartitionMap,
+        AllocationDescriptorType, AllocationExtentDescriptor, AnchorVolumeDescriptorPointer, AnyFileEntry, DescriptorTag,
+        ExtendedFileEntry, ExtentAd, ExtentType, FileEntry, FileIdentifierDescriptor, FileSetDescriptor, ICBTag,
+        IndirectEntry, LbAddr, LogicalVolumeDescriptor, LogicalVolumeIntegrityDescriptor, LongAd, PartitionDescriptor,
+        PartitionMap, PrimaryVolumeDescriptor, ShortAllocationDescriptor, SparingTable, TerminalEntry,
+        TerminatingDescriptor, Type1PartitionMap,
     },
 };
 
@@ -36,6 +44,30 @@ pub enum UdfError {
     InvalidOffset,
     #[error("Invalid partition number")]
     InvalidPartitionNumber,
+    #[error("Invalid allocation descriptor type {0}")]
+    InvalidAllocationDescriptorType(u8),
+    #[error("Invalid extent type {0}")]
+    InvalidExtentType(u8),
+    #[error("Unsafe file identifier {0:?}")]
+    UnsafeFileIdentifier(String),
+    #[error("Directory {0} is recorded more than once in the directory tree, which would loop")]
+    DirectoryLoop(String),
+    #[error("Directory {0} is more than {1} directories deep")]
+    DirectoryTooDeep(String, usize),
+    #[error("An extent of {0} ends {1} bytes past the end of its partition")]
+    ExtentOutsidePartition(String, u64),
+    #[error("The ICB at block {0} leads through more than {1} indirect entries, which may loop")]
+    IndirectEntryLoop(u32, usize),
+    #[error("The allocation descriptors of the File Entry at block {0} continue through more than {1} Allocation Extent Descriptors, which may loop")]
+    AllocationExtentLoop(u32, usize),
+    #[error("Metadata Partition {0}: {1}")]
+    MetadataPartition(u16, String),
+    #[error("Block {1} of virtual partition {0} is not in its Virtual Allocation Table")]
+    UnmappedVirtualBlock(u16, u32),
+    #[error("No Virtual Allocation Table was found in the last {0} sectors of the disc")]
+    NoVirtualAllocationTable(u32),
+    #[error("The ICB at block {0} has a descriptor with tag identifier {1}, which an ICB does not hold")]
+    UnexpectedIcbTag(u32, u16),
 }
 
 pub type Result<T> = std::result::Result<T, UdfError>;
@@ -45,10 +77,30 @@ pub struct UdfParser<R: Read + Seek> {
     pub reader: R,
     pub sector_size: u32,
     data_offset: u32,
+    /// the size of the image, for readers that cannot seek from the end
+    total_sectors: Option<u32>,
+    /// the virtual partitions that read_volume_structures found
+    pub virtual_partitions: BTreeMap<u16, VirtualPartition>,
+    /// the Sparable Partitions that read_volume_structures found
+    pub sparable_partitions: Vec<SparablePartition>,
+    /// how the caches of the reader handle blocks that cannot be read
+    pub read_error_policy: ReadErrorPolicy,
+    /// which blocks the caches read along with those they do not have
+    pub readahead: Readahead,
+    /// how deep the directories of a walk may be nested before it stops with an error
+    pub max_depth: usize,
+    /// the blocks read so far, which read_sectors and every cache share
+    blocks: BlockStore,
 }
 
 /// Standard logical sector size for UDF
 const LOGICAL_SECTOR_SIZE: u32 = 2048;
+/// A bound on the indirect entries followed from an ICB, since a damaged image could link
+/// them in a loop
+const MAX_INDIRECT_ENTRIES: usize = 64;
+/// A bound on the Allocation Extent Descriptors followed for a File Entry, far more than a
+/// 1 GB DVD file needs, since each holds the allocation descriptors of hundreds of extents
+const MAX_ALLOCATION_EXTENTS: usize = 1024;
 /// Raw CD-ROM mode 1/2 sector size
 const RAW_CD_SECTOR_SIZE: u32 = 2352;
 /// Offset to user data in raw CD-ROM mode 1/2 sectors
@@ -70,6 +122,13 @@ impl<R: Read + Seek> UdfParser<R> {
             reader,
             sector_size,
             data_offset,
+            total_sectors: None,
+            virtual_partitions: BTreeMap::new(),
+            sparable_partitions: vec![],
+            read_error_policy: ReadErrorPolicy::default(),
+            readahead: Readahead::default(),
+            max_depth: DirectoryWalk::DEFAULT_MAX_DEPTH,
+            blocks: BlockStore::default(),
         }
     }
 
@@ -88,14 +147,16 @@ impl<R: Read + Seek> UdfParser<R> {
         // Get total size to try N-256 and N
         let total_sectors = self.get_total_sectors()?;
 
-        // Try N-256
-        if let Ok(anchor) = self.read_anchor_at_sector(total_sectors - 256, &mut buf) {
-            return Ok(anchor);
+        // Try N-256, which an image too small to have one does not have
+        if let Some(sector) = total_sectors.checked_sub(256) {
+            if let Ok(anchor) = self.read_anchor_at_sector(sector, &mut buf) {
+                return Ok(anchor);
+            }
         }
 
         debug!("read_anchor: trying N");
         // Try N
-        self.read_anchor_at_sector(total_sectors - 1, &mut buf)
+        self.read_anchor_at_sector(total_sectors.saturating_sub(1), &mut buf)
     }
 
     pub fn seek_to_sector(&mut self, sector: u32) -> Result<()> {
@@ -103,6 +164,18 @@ impl<R: Read + Seek> UdfParser<R> {
         self.reader.seek(SeekFrom::Start(position))?;
         Ok(())
     }
+    /// Read buf from sector on, through the blocks that the caches share, so that
+    /// read_error_policy applies to the volume structures as it does to the files and a block
+    /// is read from the disc once.
+    pub fn read_sectors(&mut self, sector: u32, buf: &mut [u8]) -> Result<()> {
+        let mut cache = Cache::with_blocks(&mut self.reader, &mut self.blocks)
+            .with_sector_layout(self.sector_size, self.data_offset)
+            .with_read_error_policy(self.read_error_policy.clone())
+            .with_readahead(self.readahead);
+        cache.seek(SeekFrom::Start(sector as u64 * LOGICAL_SECTOR_SIZE as u64))?;
+        cache.read_exact(buf)?;
+        Ok(())
+    }
     fn read_anchor_at_sector(
         &mut self,
         sector: u32,
@@ -113,12 +186,13 @@ impl<R: Read + Seek> UdfParser<R> {
             buf.len(),
             sector
         );
-        self.seek_to_sector(sector);
-        self.reader.read_exact(buf)?;
+        self.read_sectors(sector, buf)?;
         let anchor = AnchorVolumeDescriptorPointer::read(buf);
 
-        // Validate descriptor tag
-        if !validate_descriptor_tag(&anchor.tag, buf) {
+        // Validate descriptor tag; a zeroed sector would otherwise pass as an anchor
+        if anchor.tag.tag_identifier != AnchorVolumeDescriptorPointer::TAG_IDENTIFIER
+            || !validate_descriptor_tag(&self.blocks.stats().descriptors, &anchor.tag, buf, sector)
+        {
             return Err(UdfError::InvalidDescriptorTag);
         }
 
@@ -133,13 +207,12 @@ impl<R: Read + Seek> UdfParser<R> {
         let mut buf: Vec<u8> =
             vec![0u8; PrimaryVolumeDescriptor::size().max(LOGICAL_SECTOR_SIZE as usize)];
         debug!("read_primary_volume_descriptor");
-        self.seek_to_sector(location)?;
-        self.reader.read_exact(&mut buf)?;
+        self.read_sectors(location, &mut buf)?;
 
         let pvd = PrimaryVolumeDescriptor::read(&buf);
 
         // Validate descriptor tag
-        if !validate_descriptor_tag(&pvd.tag, &buf) {
+        if !validate_descriptor_tag(&self.blocks.stats().descriptors, &pvd.tag, &buf, location) {
             return Err(UdfError::InvalidDescriptorTag);
         }
 
@@ -154,8 +227,7 @@ impl<R: Read + Seek> UdfParser<R> {
         // Read the fixed portion first
         let mut buf = vec![0u8; LogicalVolumeDescriptor::size().max(LOGICAL_SECTOR_SIZE as usize)];
         debug!("read_logical_volume_descriptor");
-        self.seek_to_sector(location)?;
-        self.reader.read_exact(&mut buf)?;
+        self.read_sectors(location, &mut buf)?;
 
         let lvd = LogicalVolumeDescriptor::read(&buf[..LogicalVolumeDescriptor::size()]);
 
@@ -180,11 +252,10 @@ impl<R: Read + Seek> UdfParser<R> {
             "reading extra partition map {}",
             partition_map_extra_buf[LOGICAL_SECTOR_SIZE as usize..].len()
         );
-        self.reader
-            .read_exact(&mut partition_map_extra_buf[LOGICAL_SECTOR_SIZE as usize..])?;
+        self.read_sectors(location + 1, &mut partition_map_extra_buf[LOGICAL_SECTOR_SIZE as usize..])?;
 
         // Validate descriptor tag
-        if !validate_descriptor_tag(&lvd.tag, &partition_map_extra_buf) {
+        if !validate_descriptor_tag(&self.blocks.stats().descriptors, &lvd.tag, &partition_map_extra_buf, location) {
             return Err(UdfError::InvalidDescriptorTag);
         }
 
@@ -239,8 +310,115 @@ impl<R: Read + Seek> UdfParser<R> {
         Ok((lvd, partition_maps))
     }
 
-    fn get_total_sectors(&mut self) -> Result<u32> {
+    /// Read the Logical Volume Integrity Sequence at extent (following next_integrity_extent)
+    /// and return its last Logical Volume Integrity Descriptor, which is the one in effect
+    pub fn read_logical_volume_integrity(
+        &mut self,
+        extent: &ExtentAd,
+    ) -> Result<Option<LogicalVolumeIntegrityDescriptor>> {
+        // a bound on the extents followed, since a damaged disc could link them in a loop
+        const MAX_EXTENTS: usize = 16;
+        let mut buf = vec![0u8; LOGICAL_SECTOR_SIZE as usize];
+        let mut last = None;
+        let mut extent = extent.clone();
+        for _ in 0..MAX_EXTENTS {
+            let mut next_extent = None;
+            for sector in extent.location_sector..extent.location_sector + extent.length_bytes / LOGICAL_SECTOR_SIZE {
+                self.read_sectors(sector, &mut buf)?;
+                let tag = DescriptorTag::read(&buf);
+                if !validate_descriptor_tag(&self.blocks.stats().descriptors, &tag, &buf, sector) {
+                    debug!("read_logical_volume_integrity: no valid descriptor at sector {}", sector);
+                    break;
+                }
+                if tag.tag_identifier == TerminatingDescriptor::TAG_IDENTIFIER {
+                    break;
+                }
+                if tag.tag_identifier == LogicalVolumeIntegrityDescriptor::TAG_IDENTIFIER {
+                    let Some(lvid) = LogicalVolumeIntegrityDescriptor::read(&buf) else {
+                        return Err(UdfError::BufferTooSmall);
+                    };
+                    if lvid.next_integrity_extent.length_bytes != 0 {
+                        next_extent = Some(lvid.next_integrity_extent.clone());
+                    }
+                    last = Some(lvid);
+                }
+            }
+            match next_extent {
+                Some(next_extent) => extent = next_extent,
+                None => break,
+            }
+        }
+        Ok(last)
+    }
+
+    /// Read the copy of a Sparing Table of size bytes at sector location
+    pub fn read_sparing_table(&mut self, location: u32, size: u32) -> Result<SparingTable> {
+        let mut buf = vec![0u8; size.max(1).div_ceil(LOGICAL_SECTOR_SIZE) as usize * LOGICAL_SECTOR_SIZE as usize];
+        self.read_sectors(location, &mut buf)?;
+        let tag = DescriptorTag::read(&buf);
+        if tag.tag_identifier != SparingTable::TAG_IDENTIFIER || !validate_descriptor_tag(&self.blocks.stats().descriptors, &tag, &buf, location) {
+            return Err(UdfError::InvalidDescriptorTag);
+        }
+        let sparing_table = SparingTable::read(&buf).ok_or(UdfError::BufferTooSmall)?;
+        if sparing_table.sparing_identifier.identifier_string() != SparingTable::SPARING_IDENTIFIER {
+            return Err(UdfError::InvalidDescriptorTag);
+        }
+        Ok(sparing_table)
+    }
+
+    /// The image has total_sectors sectors
+    pub fn with_total_sectors(self, total_sectors: u32) -> Self {
+        Self {
+            total_sectors: Some(total_sectors),
+            ..self
+        }
+    }
+
+    /// Retry or skip the blocks of the files that cannot be read as read_error_policy says
+    pub fn with_read_error_policy(self, read_error_policy: ReadErrorPolicy) -> Self {
+        Self { read_error_policy, ..self }
+    }
+    /// Read ahead of the blocks that are not cached as readahead says
+    pub fn with_readahead(self, readahead: Readahead) -> Self {
+        Self { readahead, ..self }
+    }
+    /// Stop walks at directories nested deeper than max_depth
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        Self { max_depth, ..self }
+    }
+    /// Keep up to cache_blocks blocks for read_sectors and the caches
+    pub fn with_cache_blocks(self, cache_blocks: usize) -> Self {
+        Self {
+            blocks: BlockStore::with_capacity(cache_blocks),
+            ..self
+        }
+    }
+    /// What the parser and its caches have read and found, which they all count in
+    pub fn stats(&self) -> Arc<CacheStats> {
+        Arc::clone(self.blocks.stats())
+    }
+    /// Forget the blocks read so far, e.g. after changing how the reader decrypts them
+    pub fn clear_cache(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// A Cache of the reader that reads the blocks of the virtual partitions through their
+    /// Virtual Allocation Tables, and the moved packets of Sparable Partitions from their spares.
+    /// It shares its blocks with read_sectors and the other caches of the parser.
+    pub fn cache(&mut self) -> SharedCache<'_, R> {
+        Cache::with_blocks(&mut self.reader, &mut self.blocks)
+            .with_sector_layout(self.sector_size, self.data_offset)
+            .with_virtual_partitions(self.virtual_partitions.clone())
+            .with_sparable_partitions(self.sparable_partitions.clone())
+            .with_read_error_policy(self.read_error_policy.clone())
+            .with_readahead(self.readahead)
+    }
+
+    pub(crate) fn get_total_sectors(&mut self) -> Result<u32> {
         debug!("get_total_sectors");
+        if let Some(total_sectors) = self.total_sectors {
+            return Ok(total_sectors);
+        }
         let current = self.reader.stream_position()?;
         let size = self.reader.seek(SeekFrom::End(0))?;
         self.reader.seek(SeekFrom::Start(current))?;
@@ -251,6 +429,16 @@ impl<R: Read + Seek> UdfParser<R> {
         &mut self,
         partition_descriptor: &crate::udf::PartitionDescriptor,
         _partition_map: &Type1PartitionMap,
+    ) -> Result<Vec<FileSetDescriptor>> {
+        self.read_fileset_descriptors_at(partition_descriptor, 0)
+    }
+
+    /// The File Set Descriptors of the sequence that starts at first_block of the partition,
+    /// where the Logical Volume Descriptor's logical_volume_contents_use says it is
+    pub fn read_fileset_descriptors_at(
+        &mut self,
+        partition_descriptor: &crate::udf::PartitionDescriptor,
+        first_block: u32,
     ) -> Result<Vec<FileSetDescriptor>> {
         // for type 1, file set descriptor is always at partition starting location
         // ECMA-167 4/8.3.1 File Set Descriptor Sequence
@@ -260,30 +448,31 @@ impl<R: Read + Seek> UdfParser<R> {
         // Partition_Location + FSD_Location through
         // Partition_Location + FSD_Location + (FSD_Length - 1) / BlockSize”
         // http://www.osta.org/specs/pdf/udf260.pdf#page=136
-        self.reader.seek(SeekFrom::Start(
-            partition_descriptor.partition_starting_location as u64 * self.sector_size as u64
-                + self.data_offset as u64,
-        ))?;
-
         let mut buf = vec![0u8; LOGICAL_SECTOR_SIZE as usize];
-        let mut read_block_count = 0;
+        let mut read_block_count = first_block;
         let mut fsds: Vec<FileSetDescriptor> = Vec::new();
-        'outer: while read_block_count < partition_descriptor.partition_length {
-            self.reader.read_exact(&mut buf)?;
+        while read_block_count < partition_descriptor.partition_length {
+            // the blocks of a virtual partition are wherever its VAT maps them
+            let sector = translate_virtual_sector(
+                &self.virtual_partitions,
+                partition_descriptor.partition_starting_location + read_block_count,
+            )?;
+            let sector = spared_sector(&self.sparable_partitions, sector);
+            self.read_sectors(sector, &mut buf)?;
+            let block = read_block_count;
             read_block_count += 1;
-            for chunk in buf.chunks_exact(512) {
-                let tag = DescriptorTag::read(&buf);
-                if !validate_descriptor_tag(&tag, &buf) {
-                    return Err(UdfError::InvalidDescriptorTag);
-                }
-                if tag.tag_identifier == TerminatingDescriptor::TAG_IDENTIFIER {
-                    debug!("read_fileset_descriptor: found terminating descriptor");
-                    break 'outer;
-                } else if tag.tag_identifier == FileSetDescriptor::TAG_IDENTIFIER {
-                    let fsd = crate::udf::FileSetDescriptor::read(&buf);
-                    debug!("read_fileset_descriptor: {:?}", fsd);
-                    fsds.push(fsd);
-                }
+            // each descriptor of the sequence is recorded in a block of its own
+            let tag = DescriptorTag::read(&buf);
+            if !validate_descriptor_tag(&self.blocks.stats().descriptors, &tag, &buf, block) {
+                return Err(UdfError::InvalidDescriptorTag);
+            }
+            if tag.tag_identifier == TerminatingDescriptor::TAG_IDENTIFIER {
+                debug!("read_fileset_descriptor: found terminating descriptor");
+                break;
+            } else if tag.tag_identifier == FileSetDescriptor::TAG_IDENTIFIER {
+                let fsd = crate::udf::FileSetDescriptor::read(&buf);
+                debug!("read_fileset_descriptor: {:?}", fsd);
+                fsds.push(fsd);
             }
         }
         Ok(fsds)
@@ -296,17 +485,107 @@ pub fn read_short_allocation_descriptors(descriptors: &[u8]) -> Vec<ShortAllocat
         .collect()
 }
 
-fn validate_descriptor_tag(tag: &DescriptorTag, full_descriptor: &[u8]) -> bool {
-    // sum modulo 256 of bytes 0-3 and 5-15 of the tag
-    let tag_checksum = full_descriptor[0..4]
-        .iter()
-        .chain(&full_descriptor[5..16])
-        .fold(0u8, |acc, &b| acc.wrapping_add(b));
+/// The extents of a file entry's data as long_ads, whether its allocation descriptors are
+/// short_ads (ECMA-167 4/14.14.1), which are in the file entry's own partition, or long_ads
+/// (4/14.14.2), which can be in any partition of the logical volume. Data embedded in the file
+/// entry has no extents; see embedded_data.
+pub fn read_allocation_descriptors(file_entry: &FileEntry, partition_reference_number: u16) -> Result<Vec<LongAd>> {
+    match file_entry.icb_tag.allocation_descriptor_type() {
+        AllocationDescriptorType::SHORT => Ok(read_short_allocation_descriptors(&file_entry.allocation_descriptors)
+            .into_iter()
+            .map(|ad| LongAd {
+                extent_length_and_type: ad.extent_length_and_type,
+                extent_location: LbAddr {
+                    logical_block_number: ad.extent_location,
+                    partition_reference_number,
+                },
+                ..Default::default()
+            })
+            .collect()),
+        AllocationDescriptorType::LONG => Ok(file_entry
+            .allocation_descriptors
+            .chunks_exact(LongAd::size())
+            .map(LongAd::read)
+            .collect()),
+        AllocationDescriptorType::ONE => Ok(vec![]),
+        ad_type => Err(UdfError::InvalidAllocationDescriptorType(ad_type as u8)),
+    }
+}
+
+/// The data of a file entry that records it in the allocation descriptors field instead of in
+/// extents (ECMA-167 4/14.6.8), as authoring tools do for small files and directories
+pub fn embedded_data(file_entry: &FileEntry) -> Option<&[u8]> {
+    if !matches!(file_entry.icb_tag.allocation_descriptor_type(), AllocationDescriptorType::ONE) {
+        return None;
+    }
+    let data = &file_entry.allocation_descriptors;
+    if file_entry.information_length > data.len() as u64 {
+        warn!(
+            "a file entry has {} bytes of embedded data but the file is {} bytes",
+            data.len(),
+            file_entry.information_length
+        );
+    }
+    Some(&data[..data.len().min(file_entry.information_length as usize)])
+}
+
+/// Counters of descriptor tag validation results of a disc, kept in the CacheStats of its
+/// parser, so that marginal discs can be distinguished from clean ones.
+#[derive(Debug, Default)]
+pub struct DescriptorStats {
+    validated: AtomicU64,
+    checksum_failures: AtomicU64,
+    crc_failures: AtomicU64,
+    tag_location_mismatches: AtomicU64,
+}
+impl DescriptorStats {
+    pub fn snapshot(&self) -> DescriptorCounts {
+        DescriptorCounts {
+            validated: self.validated.load(Ordering::Relaxed),
+            checksum_failures: self.checksum_failures.load(Ordering::Relaxed),
+            crc_failures: self.crc_failures.load(Ordering::Relaxed),
+            tag_location_mismatches: self.tag_location_mismatches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DescriptorCounts {
+    /// number of descriptor tags checked
+    pub validated: u64,
+    /// tag checksum did not match; the descriptor was rejected
+    pub checksum_failures: u64,
+    /// descriptor CRC did not match; the descriptor was rejected
+    pub crc_failures: u64,
+    /// tag_location did not match where the descriptor was read;
+    /// the descriptor is still accepted
+    pub tag_location_mismatches: u64,
+}
+impl DescriptorCounts {
+    pub fn is_clean(&self) -> bool {
+        self.checksum_failures == 0 && self.crc_failures == 0 && self.tag_location_mismatches == 0
+    }
+}
+
+/// Validates the tag checksum and descriptor CRC.
+/// expected_location is the sector (for volume descriptors) or the logical block
+/// within the partition (for file structure descriptors) that the descriptor was read from;
+/// see ECMA-167 3/7.2.8 and 4/7.2.8 Tag Location.
+/// The results are counted in stats.
+fn validate_descriptor_tag(
+    stats: &DescriptorStats,
+    tag: &DescriptorTag,
+    full_descriptor: &[u8],
+    expected_location: u32,
+) -> bool {
+    stats.validated.fetch_add(1, Ordering::Relaxed);
+    let tag_checksum = DescriptorTag::checksum(full_descriptor);
     if tag.tag_checksum != tag_checksum {
         error!(
             "Descriptor checksum mismatch: expected {:X} but got {:X}",
             tag.tag_checksum, tag_checksum
         );
+        stats.checksum_failures.fetch_add(1, Ordering::Relaxed);
         return false;
     }
 
@@ -327,8 +606,16 @@ fn validate_descriptor_tag(tag: &DescriptorTag, full_descriptor: &[u8]) -> bool
             tag.descriptor_crc,
             cksum(checked_bytes)
         );
+        stats.crc_failures.fetch_add(1, Ordering::Relaxed);
         return false;
     }
+    if tag.tag_location != expected_location {
+        warn!(
+            "Descriptor tag location mismatch: tag says {} but it was read from {}",
+            tag.tag_location, expected_location
+        );
+        stats.tag_location_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
     // TODO:
     // - Check descriptor version
     true
@@ -356,33 +643,66 @@ impl FileIdentifierEnum {
 }
 
 /// Typically there should be just one FileEntry in a file's ICB
-/// but there can be mulitple ones to handle overflow
+/// but there can be mulitple ones to handle overflow.
+/// Extended File Entries are read as File Entries (see AnyFileEntry::into_file_entry).
+/// An Indirect Entry continues the ICB at its indirect_icb, as in strategy 4096
+/// (ECMA-167 4/A.6), and the entries of the ICB it leads to replace those before it.
+/// The indirect ICBs are read from the same partition, since DVDs have just one.
 /// see 8.10 Information Control Block (ICB) https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=81
-pub fn read_file_entries<R: Read + Seek>(
-    reader: &mut Cache<&mut R, DVDCSS_BLOCK_SIZE>,
+pub fn read_file_entries<B: BlockDevice>(
+    reader: &mut B,
     logical_volume_descriptor: &LogicalVolumeDescriptor,
     partition_descriptor: &PartitionDescriptor,
     short_ad: &ShortAllocationDescriptor,
 ) -> Result<Vec<FileEntry>> {
-    let mut bytes = vec![0u8; short_ad.extent_length_bytes() as usize];
-    read_exact_from_partition(
-        reader,
-        partition_descriptor,
-        short_ad.extent_location as usize
-            * logical_volume_descriptor.logical_block_size as usize,
-        &mut bytes,
-    )?;
-
     debug!(
         "Found matching partition descriptor: {:?} -> starting location: {} sector",
         partition_descriptor, partition_descriptor.partition_starting_location
     );
 
+    let mut entries = vec![];
+    let mut icb = short_ad.clone();
+    for _ in 0..=MAX_INDIRECT_ENTRIES {
+        let (icb_entries, indirect_icb) =
+            read_icb_entries(reader, logical_volume_descriptor, partition_descriptor, &icb)?;
+        // an ICB that an indirect entry leads to records the file as it is now
+        if !icb_entries.is_empty() {
+            entries = icb_entries;
+        }
+        match indirect_icb {
+            Some(indirect_icb) => icb = indirect_icb,
+            None => {
+                debug!("read_file_entries: done");
+                return Ok(entries);
+            }
+        }
+    }
+    Err(UdfError::IndirectEntryLoop(short_ad.extent_location, MAX_INDIRECT_ENTRIES))
+}
+
+/// The File Entries of a single ICB extent, and the ICB that its Indirect Entry (if any)
+/// continues at
+fn read_icb_entries<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptor: &PartitionDescriptor,
+    short_ad: &ShortAllocationDescriptor,
+) -> Result<(Vec<FileEntry>, Option<ShortAllocationDescriptor>)> {
+    // the extent is read a block at a time, since its length comes from the disc and no entry
+    // is longer than a block
+    let extent_length = short_ad.extent_length_bytes() as usize;
+    let block_size = logical_volume_descriptor.logical_block_size as usize;
+    let extent_start = short_ad.extent_location as usize * block_size;
+    let mut block = vec![0u8; block_size];
+
     let mut entries = vec![];
     let mut pos_in_icb: u32 = 0;
     let address = short_ad.extent_location;
-    while bytes.len() - pos_in_icb as usize >= DescriptorTag::size() {
-        let buf = &bytes[pos_in_icb as usize..];
+    while pos_in_icb as usize + DescriptorTag::size() <= extent_length {
+        let length = (extent_length - pos_in_icb as usize).min(block_size);
+        let buf = &mut block[..length];
+        read_exact_from_partition(reader, partition_descriptor, extent_start + pos_in_icb as usize, buf)?;
+        let buf = &*buf;
         let tag = DescriptorTag::read(&buf[..DescriptorTag::size()]);
         if tag.tag_identifier == 0 {
             // “an unrecorded logical block, indicating that there are no more entries recorded after this entry”
@@ -393,100 +713,309 @@ pub fn read_file_entries<R: Read + Seek>(
             );
             break;
         }
-        if !validate_descriptor_tag(&tag, &buf) {
+        let location = address + pos_in_icb / logical_volume_descriptor.logical_block_size;
+        if !validate_descriptor_tag(&reader.stats().descriptors, &tag, &buf, location) {
             debug!(
                 "read_file_entries at {:?} {}: invalid descriptor tag {:?}",
                 address, pos_in_icb, tag
             );
             return Err(UdfError::InvalidDescriptorTag);
         }
-        if tag.tag_identifier == FileEntry::TAG_IDENTIFIER {
+        if tag.tag_identifier == FileEntry::TAG_IDENTIFIER || tag.tag_identifier == ExtendedFileEntry::TAG_IDENTIFIER {
             // file entry is variable length but
             // “The total length of a File Entry shall not exceed the size of one logical block.”
             // http://www.osta.org/specs/pdf/udf260.pdf#page=75
-            let file_entry = FileEntry::read(&buf);
+            let file_entry = AnyFileEntry::read(&buf)?;
             debug!(
-                "read_file_entries at {:?} {}: FileEntry {:?}",
+                "read_file_entries at {:?} {}: {:?}",
                 address, pos_in_icb, file_entry
             );
-            pos_in_icb += file_entry.get_length() as u32;
+            // the entries of an ICB are recorded a logical block each, and as many as its
+            // strategy allows (one, for strategy 4)
+            pos_in_icb += block_size as u32;
+            let mut file_entry = file_entry.into_file_entry();
+            read_allocation_extents(reader, logical_volume_descriptor, partition_descriptor, location, &mut file_entry)?;
+            let maximum_number_of_entries = file_entry.icb_tag.maximum_number_of_entries as usize;
             entries.push(file_entry);
+            if entries.len() >= maximum_number_of_entries {
+                break;
+            }
         } else if tag.tag_identifier == TerminalEntry::TAG_IDENTIFIER {
             debug!("read_file_entries at {:?}: found terminal entry", address);
             break;
         } else if tag.tag_identifier == IndirectEntry::TAG_IDENTIFIER {
+            if buf.len() < IndirectEntry::size() {
+                return Err(UdfError::BufferTooSmall);
+            }
             let entry = IndirectEntry::read(&buf[..IndirectEntry::size()]);
             debug!("read_file_entries at {:?}: {:?}", address, entry);
-            pos_in_icb += IndirectEntry::size() as u32;
-            // TODO
-            panic!("IndirectEntry not implemented");
+            // the entries after an indirect entry are not part of the ICB
+            let indirect_icb = ShortAllocationDescriptor {
+                extent_length_and_type: entry.indirect_icb.extent_length_and_type,
+                extent_location: entry.indirect_icb.extent_location.logical_block_number,
+            };
+            return Ok((entries, Some(indirect_icb)));
         } else {
-            error!(
-                "read_file_entries at {:?} unknown tag identifier in information control block (ICB): {}",
-                address,
-                tag.tag_identifier
-            );
-            panic!("unknown tag identifier");
+            return Err(UdfError::UnexpectedIcbTag(location, tag.tag_identifier));
         }
     }
-    debug!("read_file_entries: done");
-    Ok(entries)
+    Ok((entries, None))
+}
+
+/// Replace an allocation descriptor of type NextExtent at the end of the File Entry's with the
+/// allocation descriptors of the Allocation Extent Descriptor it points to (ECMA-167 4/12),
+/// and so on, so that the File Entry lists all of the file's extents. As with Indirect Entries,
+/// the Allocation Extent Descriptors are read from the partition of the ICB.
+fn read_allocation_extents<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptor: &PartitionDescriptor,
+    location: u32,
+    file_entry: &mut FileEntry,
+) -> Result<()> {
+    let ad_size = match file_entry.icb_tag.allocation_descriptor_type() {
+        AllocationDescriptorType::SHORT => ShortAllocationDescriptor::size(),
+        AllocationDescriptorType::LONG => LongAd::size(),
+        _ => return Ok(()),
+    };
+    let block_size = logical_volume_descriptor.logical_block_size as usize;
+    let mut allocation_descriptors = vec![];
+    let mut pending = std::mem::take(&mut file_entry.allocation_descriptors);
+    for _ in 0..=MAX_ALLOCATION_EXTENTS {
+        // short_ads and long_ads both start with the extent length and logical block number
+        let next_extent = (pending.chunks_exact(ad_size))
+            .position(|ad| ShortAllocationDescriptor::read(ad).extent_type() == ExtentType::NextExtent);
+        let Some(index) = next_extent else {
+            allocation_descriptors.extend_from_slice(&pending);
+            file_entry.length_of_allocation_descriptors = allocation_descriptors.len() as u32;
+            file_entry.allocation_descriptors = allocation_descriptors;
+            return Ok(());
+        };
+        // the descriptors after the NextExtent one, if any, are not part of the file
+        allocation_descriptors.extend_from_slice(&pending[..index * ad_size]);
+        let next = ShortAllocationDescriptor::read(&pending[index * ad_size..]);
+        debug!("read_allocation_extents at {}: continuing at {:?}", location, next);
+        let mut buf = vec![0u8; (next.extent_length_bytes() as usize).min(block_size)];
+        read_exact_from_partition(reader, partition_descriptor, next.extent_location as usize * block_size, &mut buf)?;
+        let tag = DescriptorTag::read(&buf);
+        if tag.tag_identifier != AllocationExtentDescriptor::TAG_IDENTIFIER
+            || !validate_descriptor_tag(&reader.stats().descriptors, &tag, &buf, next.extent_location)
+        {
+            debug!("read_allocation_extents at {}: invalid descriptor tag {:?}", next.extent_location, tag);
+            return Err(UdfError::InvalidDescriptorTag);
+        }
+        pending = AllocationExtentDescriptor::read(&buf)?.allocation_descriptors;
+    }
+    Err(UdfError::AllocationExtentLoop(location, MAX_ALLOCATION_EXTENTS))
 }
 
 /// Given a FileEntry which is assumed to be from a directory,
 /// reads the content of the file and parses the FileIdentifierDescriptors.
-pub fn read_directory_contents<R: Read + Seek>(
-    reader: &mut Cache<&mut R, DVDCSS_BLOCK_SIZE>,
+/// partition_reference_number is that of the directory's ICB, where its short_ads point.
+pub fn read_directory_contents<B: BlockDevice>(
+    reader: &mut B,
     logical_volume_descriptor: &LogicalVolumeDescriptor,
-    partition_descriptor: &PartitionDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    partition_reference_number: u16,
     file_entries: &[FileEntry],
 ) -> Result<Vec<FileIdentifierDescriptor>> {
     let mut file_identifiers = vec![];
+    // the directory is parsed a block at a time, since its length comes from the disc; a
+    // descriptor that continues in the next block is kept here until it is complete
+    let block_size = logical_volume_descriptor.logical_block_size as usize;
+    let mut pending: Vec<u8> = vec![];
     for file_entry in file_entries {
-        let allocation_descriptors =
-        read_short_allocation_descriptors(&*file_entry.allocation_descriptors);
-        
-        for ad in &allocation_descriptors {
-            let pos_in_partition = short_ad_to_pos_in_partition(logical_volume_descriptor, ad);
-            let mut buf: Vec<u8> = vec![0u8; ad.extent_length_bytes() as usize];
-            if ad.extent_length_bytes() > 0 {
+        // parsed with the rest at the end, as it is shorter than a block
+        if let Some(data) = embedded_data(file_entry) {
+            pending.extend_from_slice(data);
+            continue;
+        }
+        for ad in &read_allocation_descriptors(file_entry, partition_reference_number)? {
+            let (partition_descriptor, pos_in_partition) =
+                long_ad_partition_pos(logical_volume_descriptor, partition_descriptors, ad)?;
+            let extent_length = ad.extent_length_bytes() as usize;
+            let mut offset = 0;
+            while offset < extent_length {
+                let start = pending.len();
+                pending.resize(start + (extent_length - offset).min(block_size), 0);
                 read_exact_from_partition(
                     reader,
                     partition_descriptor,
-                    pos_in_partition,
-                    &mut buf,
+                    pos_in_partition + offset,
+                    &mut pending[start..],
                 )?;
-                file_identifiers.extend(parse_file_identifiers(&buf)?);
+                offset += pending.len() - start;
+                match parse_file_identifiers_in(&pending, false, &mut file_identifiers)? {
+                    ParsedFileIdentifiers::End => {
+                        pending.clear();
+                        break;
+                    }
+                    ParsedFileIdentifiers::Consumed(length) => {
+                        pending.drain(..length);
+                    }
+                }
+                // UDF 2.3.4: “The total length of a File Identifier Descriptor shall not
+                // exceed the size of one Logical Block.”
+                if pending.len() >= block_size {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "a File Identifier Descriptor is longer than a logical block",
+                    )
+                    .into());
+                }
             }
         }
     }
+    parse_file_identifiers_in(&pending, true, &mut file_identifiers)?;
+    for file_identifier in &file_identifiers {
+        let name = file_identifier.file_identifier.to_string();
+        let is_parent = file_identifier.file_characteristics & FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT != 0;
+        if !is_parent && !is_safe_file_identifier(&name) {
+            error!("read_directory_contents: rejecting file identifier {:?}", name);
+            return Err(UdfError::UnsafeFileIdentifier(name));
+        }
+    }
     Ok(file_identifiers)
 }
 
+/// The directories entered by a walk of a directory tree, which stops it from recursing forever
+/// when a corrupt or hostile image records a directory in itself or in one of its descendants,
+/// and the files that the walk visits
+pub struct DirectoryWalk {
+    max_depth: usize,
+    /// the partition reference number and logical block of each directory's ICB
+    visited: HashSet<(u16, u32)>,
+    pub filter: PathFilter,
+}
+impl DirectoryWalk {
+    /// Far deeper than the directories of real discs; ISO 9660 allows only 8 levels
+    pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            max_depth,
+            visited: HashSet::new(),
+            filter: PathFilter::default(),
+        }
+    }
+
+    /// Visit only the files that filter includes
+    pub fn with_filter(self, filter: PathFilter) -> Self {
+        Self { filter, ..self }
+    }
+
+    /// Record entering the directory at path ("" for the root) with its ICB, at depth (0 for
+    /// the root). A directory can have only one File Identifier (ECMA-167 4/8.8.3), so one that
+    /// was entered before is a loop.
+    pub fn enter(&mut self, path: &str, icb: &LongAd, depth: usize) -> Result<()> {
+        let LbAddr {
+            logical_block_number,
+            partition_reference_number,
+        } = icb.extent_location;
+        if depth > self.max_depth {
+            return Err(UdfError::DirectoryTooDeep(path.to_string(), self.max_depth));
+        }
+        if !self.visited.insert((partition_reference_number, logical_block_number)) {
+            return Err(UdfError::DirectoryLoop(path.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Whether a file identifier can be used as one component of an output path. A hostile image
+/// can name a file `..` or `../../etc/cron.d/x` to have it written outside the output directory
+pub fn is_safe_file_identifier(name: &str) -> bool {
+    // '\\' only separates components on Windows, which --target-os windows renames otherwise
+    !name.is_empty() && !name.contains(['/', '\0']) && !name.split('\\').any(|part| part == "." || part == "..")
+}
+
+/// Given the FileEntries of a file, reads the whole content of the file into memory.
+/// Only meant for small files such as IFOs. Short allocation descriptors are in the partition
+/// with partition_reference_number, that of the file's ICB.
+pub fn read_file_contents<B: BlockDevice>(
+    reader: &mut B,
+    logical_volume_descriptor: &LogicalVolumeDescriptor,
+    partition_descriptors: &BTreeMap<u16, PartitionDescriptor>,
+    partition_reference_number: u16,
+    file_entries: &[FileEntry],
+) -> Result<Vec<u8>> {
+    let mut contents = vec![];
+    for file_entry in file_entries {
+        if let Some(data) = embedded_data(file_entry) {
+            contents.extend_from_slice(data);
+            continue;
+        }
+        for ad in &read_allocation_descriptors(file_entry, partition_reference_number)? {
+            let (partition_descriptor, pos_in_partition) =
+                long_ad_partition_pos(logical_volume_descriptor, partition_descriptors, ad)?;
+            let start = contents.len();
+            contents.resize(start + ad.extent_length_bytes() as usize, 0);
+            read_exact_from_partition(
+                reader,
+                partition_descriptor,
+                pos_in_partition,
+                &mut contents[start..],
+            )?;
+        }
+    }
+    Ok(contents)
+}
+
 /// ECMA-167 4/8.6 Directories
 /// https://ecma-international.org/wp-content/uploads/ECMA-167_3rd_edition_june_1997.pdf#page=77
-pub fn parse_file_identifiers(mut buf: &[u8]) -> Result<Vec<FileIdentifierDescriptor>> {
+pub fn parse_file_identifiers(buf: &[u8]) -> Result<Vec<FileIdentifierDescriptor>> {
     let mut entries = Vec::new();
+    parse_file_identifiers_in(buf, true, &mut entries)?;
+    Ok(entries)
+}
+
+/// How far parse_file_identifiers_in got
+enum ParsedFileIdentifiers {
+    /// the directory ends in the buffer, at an unrecorded block or a Terminal Entry
+    End,
+    /// the length of the complete descriptors at the start of the buffer
+    Consumed(usize),
+}
+
+/// Parse the File Identifier Descriptors at the start of buf into entries. Unless at_end, a
+/// descriptor that continues past buf is left for when the rest of it has been read.
+fn parse_file_identifiers_in(
+    buf: &[u8],
+    at_end: bool,
+    entries: &mut Vec<FileIdentifierDescriptor>,
+) -> Result<ParsedFileIdentifiers> {
     debug!("read_in_range: reading buf of size {}", buf.len());
-    while buf.len() > DescriptorTag::size() {
-        let tag = DescriptorTag::read(&buf);
+    let mut pos = 0;
+    while buf.len() - pos > DescriptorTag::size() {
+        let rest = &buf[pos..];
+        let tag = DescriptorTag::read(rest);
         if tag.tag_identifier == 0 {
             debug!("read_in_range: found unrecorded logical block; breaking");
-            break;
+            return Ok(ParsedFileIdentifiers::End);
         }
         match tag.tag_identifier {
             FileIdentifierDescriptor::TAG_IDENTIFIER => {
-                let file_identifier = FileIdentifierDescriptor::read(&buf);
-                debug!("read_in_range: {:?}", file_identifier);
                 // claude.ai says FileIdentifierDescriptor is aligned to 4 bytes
                 // but can't find a citation from the spec.
-                let size_aligned_4_byte = file_identifier.size() + 3 & !3;
-                buf = &buf[size_aligned_4_byte..];
+                let size_aligned_4_byte = match rest.get(..38) {
+                    // the lengths of the implementation use and file identifier
+                    Some(header) => {
+                        (38 + u16::from_le_bytes([header[36], header[37]]) as usize + header[19] as usize + 3) & !3
+                    }
+                    None => usize::MAX,
+                };
+                if !at_end && size_aligned_4_byte > rest.len() {
+                    break;
+                }
+                let file_identifier = FileIdentifierDescriptor::read(rest)?;
+                debug!("read_in_range: {:?}", file_identifier);
+                // the padding of the last descriptor can be missing
+                pos += size_aligned_4_byte.min(rest.len());
                 entries.push(file_identifier);
             }
             TerminalEntry::TAG_IDENTIFIER => {
                 debug!("read_in_range: found terminal entry");
-                break;
+                return Ok(ParsedFileIdentifiers::End);
             }
             _ => {
                 error!(
@@ -497,8 +1026,8 @@ pub fn parse_file_identifiers(mut buf: &[u8]) -> Result<Vec<FileIdentifierDescri
             }
         }
     }
-    debug!("read_in_range: remaining bytes: {:?}", buf);
-    Ok(entries)
+    debug!("read_in_range: remaining bytes: {:?}", &buf[pos..]);
+    Ok(ParsedFileIdentifiers::Consumed(pos))
 }
 
 // Helper functions for working with OSTA compressed Unicode
@@ -600,8 +1129,54 @@ mod tests {
 
     use super::*;
 
+    use crate::dvdcss_sys::DVDCSS_BLOCK_SIZE;
+    use crate::test_support::{block_offset, file_entry, file_entry_blocks};
+    use crate::udf_high_level::VolumeStructures;
     use std::io::Cursor;
 
+    /// An uncached reader of a Fixture's image
+    type FixtureReader<'a> = Cache<&'a mut Cursor<Vec<u8>>>;
+
+    /// An image that build_image wrote, opened: its parser and volume structures, and the
+    /// block of the File Entry of each of its files (the root directory's at "")
+    struct Fixture {
+        parser: UdfParser<Cursor<Vec<u8>>>,
+        structures: VolumeStructures,
+        blocks: BTreeMap<String, u32>,
+    }
+    impl Fixture {
+        fn open(image: Vec<u8>) -> Self {
+            let blocks = file_entry_blocks(&image).unwrap();
+            let mut parser = UdfParser::new(Cursor::new(image));
+            let structures = parser.read_volume_structures().unwrap();
+            Self {
+                parser,
+                structures,
+                blocks,
+            }
+        }
+
+        /// The one-block ICB of the File Entry of path
+        fn icb(&self, path: &str) -> ShortAllocationDescriptor {
+            ShortAllocationDescriptor {
+                extent_length_and_type: DVDCSS_BLOCK_SIZE as u32,
+                extent_location: self.blocks[path],
+            }
+        }
+
+        /// An uncached reader of the image, the volume structures and the File Entries of icb
+        fn read_entries(
+            &mut self,
+            icb: &ShortAllocationDescriptor,
+        ) -> (FixtureReader<'_>, &VolumeStructures, Result<Vec<FileEntry>>) {
+            let mut reader = Cache::new(&mut self.parser.reader);
+            let structures = &self.structures;
+            let partition_descriptor = &structures.partition_descriptors[&0];
+            let entries = read_file_entries(&mut reader, &structures.logical_volume, partition_descriptor, icb);
+            (reader, structures, entries)
+        }
+    }
+
     #[test]
     fn test_read_anchor() {
         // Create test data
@@ -648,6 +1223,364 @@ mod tests {
         assert_eq!(input, decoded);
     }
 
+    #[test]
+    fn test_is_safe_file_identifier() {
+        assert!(is_safe_file_identifier("VIDEO_TS"));
+        assert!(is_safe_file_identifier("..."));
+        assert!(is_safe_file_identifier("AC\\DC"));
+        for name in ["", ".", "..", "../../etc/cron.d/x", "a/b", "..\\..\\x", "a\0b"] {
+            assert!(!is_safe_file_identifier(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_enum_conversions() {
+        use crate::udf::{AllocationDescriptorType, ExtentType, FileType, ICBTag, LongAd};
+        assert_eq!(AllocationDescriptorType::try_from(1).unwrap(), AllocationDescriptorType::LONG);
+        assert!(matches!(
+            AllocationDescriptorType::try_from(4),
+            Err(UdfError::InvalidAllocationDescriptorType(4))
+        ));
+        assert_eq!(ExtentType::try_from(3).unwrap(), ExtentType::NextExtent);
+        assert!(matches!(ExtentType::try_from(0xFF), Err(UdfError::InvalidExtentType(0xFF))));
+        assert_eq!(FileType::from(200), FileType::Reserved);
+        assert_eq!(FileType::from(255), FileType::Agreement);
+
+        // the flags of a scratched ICB tag still give a type
+        let icb_tag = ICBTag {
+            flags: 0xFFFF,
+            file_type: 0xFF,
+            ..Default::default()
+        };
+        assert_eq!(icb_tag.allocation_descriptor_type(), AllocationDescriptorType::ONE);
+        assert_eq!(icb_tag.file_type(), FileType::Agreement);
+        let ad = LongAd {
+            extent_length_and_type: 0x8000_0800,
+            ..Default::default()
+        };
+        assert_eq!(ad.extent_type(), ExtentType::NotRecordedNotAllocated);
+    }
+
+    #[test]
+    fn test_read_directory_contents_rejects_traversal() {
+        use crate::test_support::{build_image, FixtureFile, Quirks};
+        // a file identifier of ".." that is not the parent directory's
+        let files = [FixtureFile {
+            path: "../../etc/cron.d/x",
+            contents: b"* * * * * root rm -rf /",
+        }];
+        let mut fixture = Fixture::open(build_image(&files, "HOSTILE", &Quirks::default()).unwrap());
+        assert!(fixture.blocks.contains_key("../../etc/cron.d/x"));
+        let root = fixture.icb("");
+        let (mut reader, structures, entries) = fixture.read_entries(&root);
+        let entries = entries.unwrap();
+        let result = read_directory_contents(
+            &mut reader,
+            &structures.logical_volume,
+            &structures.partition_descriptors,
+            0,
+            &entries,
+        );
+        assert!(matches!(result, Err(UdfError::UnsafeFileIdentifier(name)) if name == ".."));
+    }
+
+    #[test]
+    fn test_read_embedded_directory() {
+        use crate::test_support::{build_image, FixtureFile};
+        let files = [
+            FixtureFile {
+                path: "VIDEO_TS.IFO",
+                contents: b"DVDVIDEO-VMG",
+            },
+            FixtureFile {
+                path: "VTS_01_0.IFO",
+                contents: b"DVDVIDEO-VTS",
+            },
+        ];
+        let mut fixture = Fixture::open(build_image(&files, "EMBEDDED", &Default::default()).unwrap());
+        let root = fixture.icb("");
+        let (mut reader, structures, entries) = fixture.read_entries(&root);
+        let mut entries = entries.unwrap();
+        let partitions = &structures.partition_descriptors;
+        // the directory's File Identifier Descriptors, moved into its File Entry
+        let directory = read_file_contents(&mut reader, &structures.logical_volume, partitions, 0, &entries).unwrap();
+        entries[0].information_length = directory.len() as u64;
+        entries[0].allocation_descriptors = directory;
+        entries[0].icb_tag.flags = (entries[0].icb_tag.flags & !0b111) | AllocationDescriptorType::ONE as u16;
+        let fids = read_directory_contents(&mut reader, &structures.logical_volume, partitions, 0, &entries).unwrap();
+        let names: Vec<String> = fids.iter().skip(1).map(|fid| fid.file_identifier.to_string()).collect();
+        assert_eq!(names, ["VIDEO_TS.IFO", "VTS_01_0.IFO"]);
+    }
+
+    #[test]
+    fn test_descriptor_stats() {
+        use crate::test_support::{build_image, FixtureFile, Quirks};
+        let files = [FixtureFile {
+            path: "VIDEO_TS.IFO",
+            contents: b"DVDVIDEO-VMG",
+        }];
+        let quirks = Quirks {
+            broken_crcs: vec!["VIDEO_TS.IFO".to_string()],
+            ..Default::default()
+        };
+        let mut broken = Fixture::open(build_image(&files, "BROKEN", &quirks).unwrap());
+        let mut clean = Fixture::open(build_image(&files, "CLEAN", &Default::default()).unwrap());
+        let icb = broken.icb("VIDEO_TS.IFO");
+        let structures = &broken.structures;
+        let partition_descriptor = &structures.partition_descriptors[&0];
+        let result = read_file_entries(&mut broken.parser.cache(), &structures.logical_volume, partition_descriptor, &icb);
+        assert!(matches!(result, Err(UdfError::InvalidDescriptorTag)));
+        // each parser counts only the descriptors that it and its caches read
+        let counts = broken.parser.stats().descriptors.snapshot();
+        assert_eq!((counts.checksum_failures, counts.crc_failures), (0, 1));
+        // the 3 descriptors that read_volume_structures validates, then the File Set Descriptor
+        // and the Terminating Descriptor after it, once each
+        assert_eq!(clean.parser.stats().descriptors.snapshot().validated, 3);
+        let fsds = clean.parser.read_fileset_descriptors_at(&clean.structures.partition_descriptors[&0], 0).unwrap();
+        assert_eq!(fsds.len(), 1);
+        let counts = clean.parser.stats().descriptors.snapshot();
+        assert!(counts.is_clean());
+        assert_eq!(counts.validated, 5);
+    }
+
+    #[test]
+    fn test_read_unexpected_icb_tag() {
+        use crate::test_support::{build_image, FixtureFile};
+        let files = [FixtureFile {
+            path: "VIDEO_TS.IFO",
+            contents: b"DVDVIDEO-VMG",
+        }];
+        let mut fixture = Fixture::open(build_image(&files, "ICBTAG", &Default::default()).unwrap());
+        let icb = fixture.icb("VIDEO_TS.IFO");
+        let block = icb.extent_location;
+        // a valid descriptor, but a Terminating Descriptor rather than a File Entry
+        let start = block_offset(block);
+        let bytes = &mut fixture.parser.reader.get_mut()[start..start + DVDCSS_BLOCK_SIZE];
+        bytes.fill(0);
+        bytes[0..2].copy_from_slice(&TerminatingDescriptor::TAG_IDENTIFIER.to_le_bytes());
+        DescriptorTag::finalize(&mut bytes[..DescriptorTag::size()], block);
+        let result = fixture.read_entries(&icb).2;
+        assert!(matches!(result, Err(UdfError::UnexpectedIcbTag(b, 8)) if b == block));
+    }
+
+    #[test]
+    fn test_read_large_directory() {
+        use crate::test_support::{build_image, FixtureFile};
+        // 52 bytes for each File Identifier Descriptor, so some continue in the next block
+        let paths: Vec<String> = (0..120).map(|i| format!("FILE_{:03}.DAT", i)).collect();
+        let files: Vec<FixtureFile> = (paths.iter())
+            .map(|path| FixtureFile { path, contents: b"" })
+            .collect();
+        let mut fixture = Fixture::open(build_image(&files, "LARGE", &Default::default()).unwrap());
+        // an ICB that claims to be a gigabyte long is still read a block at a time
+        let root = ShortAllocationDescriptor {
+            extent_length_and_type: 0x3FFF_F800,
+            ..fixture.icb("")
+        };
+        let (mut reader, structures, entries) = fixture.read_entries(&root);
+        let entries = entries.unwrap();
+        assert_eq!(entries.len(), 1);
+        let fids = read_directory_contents(
+            &mut reader,
+            &structures.logical_volume,
+            &structures.partition_descriptors,
+            0,
+            &entries,
+        )
+        .unwrap();
+        let names: Vec<String> = fids.iter().skip(1).map(|fid| fid.file_identifier.to_string()).collect();
+        assert_eq!(names, paths);
+    }
+
+    #[test]
+    fn test_read_indirect_entries() {
+        use crate::test_support::{build_image, FixtureFile};
+        let old = b"OLD".repeat(1000);
+        let files = [
+            FixtureFile {
+                path: "NEW.IFO",
+                contents: b"NEWER",
+            },
+            FixtureFile {
+                path: "OLD.IFO",
+                contents: &old,
+            },
+        ];
+        let image = build_image(&files, "INDIRECT", &Default::default()).unwrap();
+        let blocks = file_entry_blocks(&image).unwrap();
+        let mut old_entry = file_entry(&image, "OLD.IFO").unwrap();
+        old_entry.icb_tag.strategy_type = 4096;
+        old_entry.icb_tag.maximum_number_of_entries = 2;
+        // a strategy 4096 ICB in the 2 blocks of OLD.IFO's data: its File Entry, and then an
+        // Indirect Entry to the ICB of that many blocks at indirect_block
+        let icb = read_short_allocation_descriptors(&old_entry.allocation_descriptors)[0].clone();
+        let with_indirect_entry = |indirect_block: u32, blocks: u32| {
+            let mut image = image.clone();
+            let start = block_offset(icb.extent_location);
+            let bytes = &mut image[start..start + old_entry.get_length()];
+            old_entry.write(bytes);
+            DescriptorTag::finalize(bytes, icb.extent_location);
+            let entry = IndirectEntry {
+                tag: DescriptorTag {
+                    tag_identifier: IndirectEntry::TAG_IDENTIFIER,
+                    ..Default::default()
+                },
+                indirect_icb: LongAd {
+                    extent_length_and_type: blocks * DVDCSS_BLOCK_SIZE as u32,
+                    extent_location: LbAddr {
+                        logical_block_number: indirect_block,
+                        partition_reference_number: 0,
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let start = block_offset(icb.extent_location + 1);
+            let bytes = &mut image[start..start + IndirectEntry::size()];
+            entry.write(bytes);
+            DescriptorTag::finalize(bytes, icb.extent_location + 1);
+            image
+        };
+        let read = |image: Vec<u8>| Fixture::open(image).read_entries(&icb).2;
+
+        // the File Entry of the ICB that the indirect entry leads to replaces the one before it
+        let entries = read(with_indirect_entry(blocks["NEW.IFO"], 1)).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].information_length, 5);
+        // an indirect entry back to its own ICB
+        let result = read(with_indirect_entry(icb.extent_location, 2));
+        assert!(matches!(result, Err(UdfError::IndirectEntryLoop(_, MAX_INDIRECT_ENTRIES))));
+    }
+
+    #[test]
+    fn test_read_icb_ending_inside_an_entry() {
+        use crate::test_support::{build_image, FixtureFile};
+        let old = b"OLD".repeat(1000);
+        let files = [FixtureFile {
+            path: "OLD.IFO",
+            contents: &old,
+        }];
+        let mut image = build_image(&files, "SHORTICB", &Default::default()).unwrap();
+        let mut old_entry = file_entry(&image, "OLD.IFO").unwrap();
+        old_entry.icb_tag.maximum_number_of_entries = 3;
+        // an ICB in the 2 blocks of OLD.IFO's data that ends with the second File Entry, so that
+        // the position after it is past the end of the extent
+        let data = read_short_allocation_descriptors(&old_entry.allocation_descriptors)[0].clone();
+        for block in [data.extent_location, data.extent_location + 1] {
+            let start = block_offset(block);
+            let bytes = &mut image[start..start + old_entry.get_length()];
+            old_entry.write(bytes);
+            DescriptorTag::finalize(bytes, block);
+        }
+        let icb = ShortAllocationDescriptor {
+            extent_length_and_type: (DVDCSS_BLOCK_SIZE + old_entry.get_length()) as u32,
+            extent_location: data.extent_location,
+        };
+        let entries = Fixture::open(image).read_entries(&icb).2.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].information_length, 3000);
+    }
+
+    #[test]
+    fn test_read_allocation_extents() {
+        use crate::test_support::{build_image, FixtureFile};
+        let vob: Vec<u8> = (0..3 * DVDCSS_BLOCK_SIZE).map(|i| (i / 7) as u8).collect();
+        let files = [
+            FixtureFile {
+                path: "A.VOB",
+                contents: &vob,
+            },
+            FixtureFile {
+                path: "B.IFO",
+                contents: b"B",
+            },
+        ];
+        let image = build_image(&files, "AED", &Default::default()).unwrap();
+        let blocks = file_entry_blocks(&image).unwrap();
+        let entry = |path: &str| file_entry(&image, path).unwrap();
+        let vob_extent = read_short_allocation_descriptors(&entry("A.VOB").allocation_descriptors)[0].clone();
+        // B.IFO's data block holds the rest of A.VOB's allocation descriptors
+        let aed_block = read_short_allocation_descriptors(&entry("B.IFO").allocation_descriptors)[0].extent_location;
+        let short_ads = |ads: &[(u32, u32)]| {
+            let mut bytes = vec![0u8; ads.len() * ShortAllocationDescriptor::size()];
+            for (&(extent_length_and_type, extent_location), bytes) in
+                ads.iter().zip(bytes.chunks_exact_mut(ShortAllocationDescriptor::size()))
+            {
+                ShortAllocationDescriptor {
+                    extent_length_and_type,
+                    extent_location,
+                }
+                .write(bytes);
+            }
+            bytes
+        };
+        const NEXT_EXTENT: u32 = 3 << 30;
+        let block_size = DVDCSS_BLOCK_SIZE as u32;
+        let with_allocation_extent = |next_block: Option<u32>| {
+            let mut image = image.clone();
+            let mut file_entry = entry("A.VOB");
+            file_entry.allocation_descriptors = short_ads(&[
+                (block_size, vob_extent.extent_location),
+                (NEXT_EXTENT | block_size, aed_block),
+            ]);
+            file_entry.length_of_allocation_descriptors = file_entry.allocation_descriptors.len() as u32;
+            let start = block_offset(blocks["A.VOB"]);
+            let bytes = &mut image[start..start + file_entry.get_length()];
+            file_entry.write(bytes);
+            DescriptorTag::finalize(bytes, blocks["A.VOB"]);
+            let mut ads = vec![(2 * block_size, vob_extent.extent_location + 1)];
+            ads.extend(next_block.map(|block| (NEXT_EXTENT | block_size, block)));
+            let allocation_descriptors = short_ads(&ads);
+            let aed = AllocationExtentDescriptor {
+                tag: DescriptorTag {
+                    tag_identifier: AllocationExtentDescriptor::TAG_IDENTIFIER,
+                    ..Default::default()
+                },
+                length_of_allocation_descriptors: allocation_descriptors.len() as u32,
+                allocation_descriptors,
+                ..Default::default()
+            };
+            let start = block_offset(aed_block);
+            let bytes = &mut image[start..start + aed.get_length()];
+            aed.write(bytes);
+            DescriptorTag::finalize(bytes, aed_block);
+            image
+        };
+        let read = |image: Vec<u8>| {
+            let mut fixture = Fixture::open(image);
+            let icb = fixture.icb("A.VOB");
+            let (mut reader, structures, entries) = fixture.read_entries(&icb);
+            read_file_contents(&mut reader, &structures.logical_volume, &structures.partition_descriptors, 0, &entries?)
+        };
+
+        assert_eq!(read(with_allocation_extent(None)).unwrap(), vob);
+        // an Allocation Extent Descriptor that continues at itself
+        let result = read(with_allocation_extent(Some(aed_block)));
+        assert!(matches!(result, Err(UdfError::AllocationExtentLoop(_, MAX_ALLOCATION_EXTENTS))));
+    }
+
+    #[test]
+    fn test_directory_walk() {
+        let icb = |block| LongAd {
+            extent_length_and_type: DVDCSS_BLOCK_SIZE as u32,
+            extent_location: LbAddr {
+                logical_block_number: block,
+                partition_reference_number: 0,
+            },
+            ..Default::default()
+        };
+        let mut walk = DirectoryWalk::new(2);
+        walk.enter("", &icb(2), 0).unwrap();
+        walk.enter("VIDEO_TS", &icb(4), 1).unwrap();
+        walk.enter("AUDIO_TS", &icb(6), 1).unwrap();
+        // a subdirectory whose ICB is its parent's
+        let result = walk.enter("VIDEO_TS/LOOP", &icb(4), 2);
+        assert!(matches!(result, Err(UdfError::DirectoryLoop(path)) if path == "VIDEO_TS/LOOP"));
+        walk.enter("A/B", &icb(8), 2).unwrap();
+        let result = walk.enter("A/B/C", &icb(10), 3);
+        assert!(matches!(result, Err(UdfError::DirectoryTooDeep(path, 2)) if path == "A/B/C"));
+    }
+
     #[test]
     fn test_parse_file_identifiers() {
         let _ = env_logger::try_init();
@@ -669,5 +1602,67 @@ mod tests {
                 .collect::<Vec<String>>(),
             vec!["", "AUDIO_TS", "VIDEO_TS"]
         );
+        // a descriptor cut off in the middle of its file identifier
+        assert!(matches!(parse_file_identifiers(&bytes[..80]), Err(UdfError::Io(_))));
+    }
+
+    /// Inputs the fuzz targets in fuzz/ found to panic or loop
+    #[test]
+    fn test_read_truncated_descriptors() {
+        use crate::udf::{FileEntry, FileIdentifierDescriptor, PartitionMap};
+        let mut file_entry = vec![0u8; 176];
+        assert!(FileEntry::read(&file_entry[..100]).is_err());
+        assert!(FileEntry::read(&file_entry).is_ok());
+        // length_of_allocation_descriptors
+        file_entry[172] = 8;
+        assert!(FileEntry::read(&file_entry).is_err());
+        file_entry[168..172].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(FileEntry::read(&file_entry).is_err());
+
+        let mut fid = vec![0u8; 38];
+        assert!(FileIdentifierDescriptor::read(&fid[..20]).is_err());
+        // length_of_implementation_use
+        fid[36] = 1;
+        assert!(FileIdentifierDescriptor::read(&fid).is_err());
+
+        // a partition map of length 0 would be read forever
+        assert!(PartitionMap::read(&[7, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_read_sparable_partition() {
+        use crate::test_support::{build_image, spare_packet, FixtureFile};
+        let vob: Vec<u8> = (0..3 * DVDCSS_BLOCK_SIZE).map(|i| (i / 11) as u8).collect();
+        let files = [FixtureFile {
+            path: "A.VOB",
+            contents: &vob,
+        }];
+        let mut image = build_image(&files, "SPARED", &Default::default()).unwrap();
+        let vob_entry = file_entry(&image, "A.VOB").unwrap();
+        let extent = read_short_allocation_descriptors(&vob_entry.allocation_descriptors)[0].clone();
+
+        // the packet of A.VOB's data is moved past the end of the image, where the Sparing Table is
+        const PACKET_LENGTH: u32 = 32;
+        let packet = extent.extent_location - extent.extent_location % PACKET_LENGTH;
+        let spare_sector = spare_packet(&mut image, PACKET_LENGTH, packet);
+        let data = block_offset(extent.extent_location);
+        image[data..data + vob.len()].fill(0xEE);
+
+        let mut fixture = Fixture::open(image);
+        assert_eq!(fixture.parser.sparable_partitions.len(), 1);
+        assert_eq!(fixture.parser.sparable_partitions[0].spared_packets, BTreeMap::from([(packet, spare_sector)]));
+        let icb = fixture.icb("A.VOB");
+        let structures = &fixture.structures;
+        let partition_descriptor = &structures.partition_descriptors[&0];
+        let partitions = &structures.partition_descriptors;
+        let mut reader = fixture.parser.cache();
+        let entries = read_file_entries(&mut reader, &structures.logical_volume, partition_descriptor, &icb).unwrap();
+        let contents = read_file_contents(&mut reader, &structures.logical_volume, partitions, 0, &entries).unwrap();
+        assert_eq!(contents, vob);
+        // where the packet was before it was moved
+        let (mut reader, structures, _) = fixture.read_entries(&icb);
+        let partitions = &structures.partition_descriptors;
+        let contents = read_file_contents(&mut reader, &structures.logical_volume, partitions, 0, &entries).unwrap();
+        assert!(contents.iter().all(|&b| b == 0xEE));
     }
 }
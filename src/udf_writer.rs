@@ -0,0 +1,1230 @@
+//! `makeiso`: authors a UDF image of a directory tree, laid out the way DVD mastering
+//! tools do (UDF 2.6.0 6.9): one read-only Type 1 partition, File Entries with short_ad
+//! extents, and all of the metadata before the file data, so that every file is contiguous.
+//! In a VIDEO_TS directory, each title set's IFO, VOBs and BUP are recorded in that order.
+//! With ImageOptions::iso9660 the image is a UDF Bridge image, as DVD-Video discs are, with
+//! ISO 9660 and Joliet directories of the same files (see iso9660).
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::{debug, warn};
+
+use crate::{
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    iso9660::{self, BridgeLayout, BridgeVolume, IsoNode},
+    udf::{
+        AllocationDescriptorType, AnchorVolumeDescriptorPointer, CharSpec, DescriptorTag, Dstring, DynamicDstring,
+        EntityID, ExtentAd, FileEntry, FileIdentifierDescriptor, FileSetDescriptor, FileType,
+        GenericPartitionMapHeader, ICBTag, LbAddr, LogicalVolumeDescriptor, LogicalVolumeIntegrityDescriptor,
+        LongAd, PartitionDescriptor, PartitionMap, PrimaryVolumeDescriptor, ShortAllocationDescriptor,
+        TerminatingDescriptor, Timestamp, Type1PartitionMap, Type2PartitionMap, VIRTUAL_PARTITION_IDENTIFIER,
+    },
+};
+
+const BLOCK_SIZE: usize = DVDCSS_BLOCK_SIZE;
+/// ECMA-167 2/8.3 Volume Recognition Sequence
+pub(crate) const VOLUME_RECOGNITION_SECTOR: u32 = 16;
+pub(crate) const MAIN_VDS_SECTOR: u32 = 32;
+pub(crate) const RESERVE_VDS_SECTOR: u32 = 48;
+/// Each Volume Descriptor Sequence extent is 16 sectors, as UDF requires
+pub(crate) const VDS_SECTORS: u32 = 16;
+pub(crate) const INTEGRITY_SECTOR: u32 = 64;
+pub(crate) const ANCHOR_SECTOR: u32 = 256;
+pub(crate) const PARTITION_START_SECTOR: u32 = 257;
+/// The longest extent that is a whole number of blocks, since extent lengths are 30 bits
+pub(crate) const MAX_EXTENT_LENGTH: u64 = 0x3FFF_F800;
+/// UDF 3.2.1.1: unique IDs 1-15 are reserved, and the root directory's is 0
+pub(crate) const FIRST_UNIQUE_ID: u64 = 16;
+const IMPLEMENTATION_IDENTIFIER: &str = "*dvdromcopy";
+/// ECMA-167 4/14.9.5: read for everyone, plus execute for directories
+const FILE_PERMISSIONS: u32 = 0x1084;
+const DIRECTORY_PERMISSIONS: u32 = 0x14A5;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UdfRevision {
+    /// What DVD-Video players read
+    #[value(name = "1.02")]
+    Udf102,
+    #[value(name = "2.01")]
+    Udf201,
+}
+impl UdfRevision {
+    /// As BCD, e.g. 0x0102
+    pub fn bcd(self) -> u16 {
+        match self {
+            UdfRevision::Udf102 => 0x0102,
+            UdfRevision::Udf201 => 0x0201,
+        }
+    }
+    /// UDF 1.02 uses the descriptors of ECMA-167 2nd edition (NSR02) and 2.01 those of the 3rd (NSR03)
+    fn descriptor_version(self) -> u16 {
+        match self {
+            UdfRevision::Udf102 => 2,
+            UdfRevision::Udf201 => 3,
+        }
+    }
+    /// The revision whose descriptors have the given descriptor version
+    pub(crate) fn from_descriptor_version(descriptor_version: u16) -> Self {
+        if descriptor_version == 2 {
+            UdfRevision::Udf102
+        } else {
+            UdfRevision::Udf201
+        }
+    }
+    fn nsr_identifier(self) -> &'static str {
+        match self {
+            UdfRevision::Udf102 => "NSR02",
+            UdfRevision::Udf201 => "NSR03",
+        }
+    }
+    /// UDF 2.1.5.2 Domain Identifier Suffix
+    fn identifier_suffix(self) -> [u8; 8] {
+        let mut suffix = [0; 8];
+        suffix[0..2].copy_from_slice(&self.bcd().to_le_bytes());
+        suffix
+    }
+}
+
+pub struct ImageOptions {
+    pub volume_identifier: String,
+    pub revision: UdfRevision,
+    pub recording_time: SystemTime,
+    /// Also record ISO 9660 and Joliet directories of the same files (a UDF Bridge image)
+    pub iso9660: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImageSummary {
+    pub sectors: u32,
+    pub files: u32,
+    /// including the root directory
+    pub directories: u32,
+}
+
+/// A file to record with write_image_of_files
+pub struct SourceFile<'a> {
+    /// '/'-separated, e.g. VIDEO_TS/VIDEO_TS.IFO
+    pub path: &'a str,
+    pub length: u64,
+    pub modified: SystemTime,
+}
+
+/// A file or directory of the source tree and where it is recorded
+pub(crate) struct Node {
+    /// the file identifier in its parent directory; empty for the root
+    pub name: String,
+    /// the path of the file in the local filesystem, or in the source of write_image_of_files
+    pub path: PathBuf,
+    pub modified: SystemTime,
+    accessed: SystemTime,
+    pub parent: usize,
+    /// the nodes of a directory's entries, or None for a file
+    pub children: Option<Vec<usize>>,
+    unique_id: u64,
+    /// the logical block of the File Entry within the partition
+    icb_block: u32,
+    /// the first logical block of the file's data or the directory's File Identifier Descriptors
+    data_block: u32,
+    pub length: u64,
+}
+
+/// Write a UDF image of the directory source and return its size
+pub fn write_image<W: Write>(source: &Path, options: &ImageOptions, writer: &mut W) -> io::Result<ImageSummary> {
+    let nodes = scan(source)?;
+    write_nodes(nodes, options, writer, |node, writer| io::copy(&mut fs::File::open(&node.path)?.take(node.length), writer))
+}
+
+/// Write a UDF image of files from elsewhere, e.g. those of another image, in directories
+/// made from their paths (so there are no empty directories). copy_file writes the data of
+/// the file at a path and returns its length.
+pub fn write_image_of_files<W: Write>(
+    files: &[SourceFile],
+    options: &ImageOptions,
+    writer: &mut W,
+    mut copy_file: impl FnMut(&str, &mut dyn Write) -> io::Result<u64>,
+) -> io::Result<ImageSummary> {
+    let nodes = nodes_of_files(files, options.recording_time)?;
+    write_nodes(nodes, options, writer, |node, writer| {
+        copy_file(node.path.to_str().expect("the paths are from strs"), writer)
+    })
+}
+
+fn write_nodes<W: Write>(
+    nodes: Vec<Node>,
+    options: &ImageOptions,
+    writer: &mut W,
+    mut copy_file: impl FnMut(&Node, &mut dyn Write) -> io::Result<u64>,
+) -> io::Result<ImageSummary> {
+    let Layout {
+        nodes,
+        head,
+        data_order,
+        summary,
+        anchor,
+    } = lay_out(nodes, options)?;
+    writer.write_all(&head)?;
+    for &index in &data_order {
+        let node = &nodes[index];
+        let copied = copy_file(node, writer)?;
+        if copied != node.length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{:?} is {} bytes instead of {}", node.path, copied, node.length),
+            ));
+        }
+        let padding = blocks(node.length) as usize * BLOCK_SIZE - node.length as usize;
+        writer.write_all(&vec![0; padding])?;
+    }
+    writer.write_all(&anchor)?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// An image of nodes as write_nodes records it: the sectors before the file data, then the
+/// data of the nodes in data_order, each padded to whole sectors, then the anchor sector
+struct Layout {
+    nodes: Vec<Node>,
+    head: Vec<u8>,
+    data_order: Vec<usize>,
+    summary: ImageSummary,
+    anchor: Vec<u8>,
+}
+
+fn lay_out(mut nodes: Vec<Node>, options: &ImageOptions) -> io::Result<Layout> {
+    let bridge = if options.iso9660 { Some(BridgeLayout::new(&iso_nodes(&nodes))?) } else { None };
+    let bridge_blocks = bridge.as_ref().map_or(0, BridgeLayout::sectors);
+    let data_order = allocate(&mut nodes, bridge_blocks);
+    let partition_length = data_order
+        .iter()
+        .map(|&index| &nodes[index])
+        .filter(|node| node.length > 0)
+        .map(|node| node.data_block + blocks(node.length))
+        .max()
+        .unwrap_or(0)
+        .max(metadata_blocks(&nodes) + bridge_blocks);
+    let summary = ImageSummary {
+        sectors: PARTITION_START_SECTOR + partition_length + 1,
+        files: nodes.iter().filter(|node| node.children.is_none()).count() as u32,
+        directories: nodes.iter().filter(|node| node.children.is_some()).count() as u32,
+    };
+    debug!("write_image: {:?} with a partition of {} blocks", summary, partition_length);
+
+    let head_sectors = PARTITION_START_SECTOR + metadata_blocks(&nodes) + bridge_blocks;
+    let mut head = vec![0u8; head_sectors as usize * BLOCK_SIZE];
+    match &bridge {
+        Some(bridge) => {
+            // the ISO 9660 volume descriptors come first, and the path tables and directories
+            // between the UDF metadata and the file data
+            let file_sectors: Vec<u32> = nodes.iter().map(|node| PARTITION_START_SECTOR + node.data_block).collect();
+            let volume = BridgeVolume {
+                identifier: &options.volume_identifier,
+                recording_time: options.recording_time,
+                sectors: summary.sectors,
+            };
+            let first_sector = PARTITION_START_SECTOR + metadata_blocks(&nodes);
+            bridge.write(&iso_nodes(&nodes), &file_sectors, &mut head, first_sector, &volume);
+            let start = iso9660::VOLUME_DESCRIPTOR_SECTOR + iso9660::VOLUME_DESCRIPTOR_SECTORS;
+            write_volume_recognition_sequence(&mut head, start, options.revision);
+        }
+        None => write_volume_recognition_sequence(&mut head, VOLUME_RECOGNITION_SECTOR, options.revision),
+    }
+    for start in [MAIN_VDS_SECTOR, RESERVE_VDS_SECTOR] {
+        write_volume_descriptor_sequence(&mut head, start, options, partition_length, false);
+    }
+    let next_unique_id = FIRST_UNIQUE_ID + nodes.len() as u64 - 1;
+    write_integrity_sequence(&mut head, options, next_unique_id, &summary, partition_length);
+    write_anchor(sector_mut(&mut head, ANCHOR_SECTOR), ANCHOR_SECTOR, options.revision);
+    write_file_set(&mut head, options, 0);
+    for index in 0..nodes.len() {
+        write_file_entry(&mut head, options, &nodes, index);
+        if nodes[index].children.is_some() {
+            write_directory(&mut head, options.revision, &nodes, index);
+        }
+    }
+    let mut anchor = vec![0u8; BLOCK_SIZE];
+    write_anchor(&mut anchor, summary.sectors - 1, options.revision);
+    Ok(Layout {
+        nodes,
+        head,
+        data_order,
+        summary,
+        anchor,
+    })
+}
+
+/// The image that write_image or write_image_of_files would write of local files, read from
+/// the files as it is read instead of being written anywhere
+pub struct ImageReader {
+    head: Vec<u8>,
+    /// the first byte of the data of each file in the image, its local path and its length
+    files: Vec<(u64, PathBuf, u64)>,
+    anchor: Vec<u8>,
+    length: u64,
+    pos: u64,
+    /// the file of files that was read from last
+    open: Option<(usize, fs::File)>,
+}
+impl ImageReader {
+    /// The image of the directory source
+    pub fn new(source: &Path, options: &ImageOptions) -> io::Result<(Self, ImageSummary)> {
+        Self::of_nodes(scan(source)?, options, |node| node.path.clone())
+    }
+
+    /// The image of files, whose data is read from the local file at local_path(path)
+    pub fn of_files(
+        files: &[SourceFile],
+        options: &ImageOptions,
+        local_path: impl Fn(&str) -> PathBuf,
+    ) -> io::Result<(Self, ImageSummary)> {
+        let nodes = nodes_of_files(files, options.recording_time)?;
+        Self::of_nodes(nodes, options, |node| {
+            local_path(node.path.to_str().expect("the paths are from strs"))
+        })
+    }
+
+    fn of_nodes(
+        nodes: Vec<Node>,
+        options: &ImageOptions,
+        local_path: impl Fn(&Node) -> PathBuf,
+    ) -> io::Result<(Self, ImageSummary)> {
+        let layout = lay_out(nodes, options)?;
+        let mut start = layout.head.len() as u64;
+        let mut files = vec![];
+        for &index in &layout.data_order {
+            let node = &layout.nodes[index];
+            files.push((start, local_path(node), node.length));
+            start += blocks(node.length) as u64 * BLOCK_SIZE as u64;
+        }
+        let reader = Self {
+            head: layout.head,
+            files,
+            anchor: layout.anchor,
+            length: start + BLOCK_SIZE as u64,
+            pos: 0,
+            open: None,
+        };
+        Ok((reader, layout.summary))
+    }
+
+    /// Read from the data of files[index], or the padding after it, at offset
+    fn read_file(&mut self, index: usize, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let (_, path, length) = &self.files[index];
+        if offset >= *length {
+            buf.fill(0);
+            return Ok(buf.len());
+        }
+        let len = buf.len().min((length - offset) as usize);
+        if !matches!(&self.open, Some((open, _)) if *open == index) {
+            self.open = Some((index, fs::File::open(path)?));
+        }
+        let (_, file) = self.open.as_mut().expect("opened");
+        file.seek(SeekFrom::Start(offset))?;
+        match file.read(&mut buf[..len])? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{:?} is shorter than when the image was made", path),
+            )),
+            read => Ok(read),
+        }
+    }
+}
+impl Read for ImageReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos;
+        let data_start = self.head.len() as u64;
+        let anchor_start = self.length - BLOCK_SIZE as u64;
+        let read = if pos >= self.length {
+            0
+        } else if pos < data_start {
+            (&self.head[pos as usize..]).read(buf)?
+        } else if pos >= anchor_start {
+            (&self.anchor[(pos - anchor_start) as usize..]).read(buf)?
+        } else {
+            // the last file that starts at or before pos, after any empty ones
+            let index = self.files.partition_point(|(start, ..)| *start <= pos) - 1;
+            let (start, _, length) = self.files[index];
+            let end = start + blocks(length) as u64 * BLOCK_SIZE as u64;
+            let len = buf.len().min((end - pos) as usize);
+            self.read_file(index, pos - start, &mut buf[..len])?
+        };
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+impl Seek for ImageReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(self.pos)
+    }
+}
+
+fn iso_nodes(nodes: &[Node]) -> Vec<IsoNode<'_>> {
+    nodes
+        .iter()
+        .map(|node| IsoNode {
+            name: &node.name,
+            parent: node.parent,
+            children: node.children.as_deref(),
+            modified: node.modified,
+            length: node.length,
+        })
+        .collect()
+}
+
+pub(crate) fn blocks(length: u64) -> u32 {
+    length.div_ceil(BLOCK_SIZE as u64) as u32
+}
+
+pub(crate) fn sector_mut(head: &mut [u8], sector: u32) -> &mut [u8] {
+    let start = sector as usize * BLOCK_SIZE;
+    &mut head[start..start + BLOCK_SIZE]
+}
+
+/// The nodes of the tree under source in depth-first order, with the root first and
+/// the entries of each directory sorted by name
+pub(crate) fn scan(source: &Path) -> io::Result<Vec<Node>> {
+    let metadata = fs::metadata(source)?;
+    if !metadata.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a directory", source),
+        ));
+    }
+    let mut nodes = vec![new_node(String::new(), source.to_path_buf(), 0, &metadata)?];
+    scan_directory(&mut nodes, 0)?;
+    Ok(nodes)
+}
+
+fn new_node(name: String, path: PathBuf, parent: usize, metadata: &fs::Metadata) -> io::Result<Node> {
+    Ok(Node {
+        name,
+        path,
+        modified: metadata.modified()?,
+        accessed: metadata.accessed()?,
+        parent,
+        children: if metadata.is_dir() { Some(vec![]) } else { None },
+        unique_id: 0,
+        icb_block: 0,
+        data_block: 0,
+        length: if metadata.is_dir() { 0 } else { metadata.len() },
+    })
+}
+
+fn scan_directory(nodes: &mut Vec<Node>, index: usize) -> io::Result<()> {
+    let mut entries = fs::read_dir(&nodes[index].path)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    let mut children = vec![];
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name().into_string().map_err(|name| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: file names must be Unicode", name))
+        })?;
+        if DynamicDstring::from_str(&name).0.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?}: the file name is too long for UDF", path),
+            ));
+        }
+        // symbolic links to files are recorded as the files, but links to directories
+        // are skipped since they could form a loop
+        let metadata = fs::metadata(&path)?;
+        let is_directory = metadata.is_dir() && !entry.file_type()?.is_symlink();
+        if !metadata.is_file() && !is_directory {
+            warn!("makeiso: skipping {:?}, which is not a file or directory", path);
+            continue;
+        }
+        let child = nodes.len();
+        nodes.push(new_node(name, path, index, &metadata)?);
+        children.push(child);
+        if metadata.is_dir() {
+            scan_directory(nodes, child)?;
+        }
+    }
+    nodes[index].children = Some(children);
+    Ok(())
+}
+
+/// The nodes of the directories that the paths of files make, in the same order as scan
+fn nodes_of_files(files: &[SourceFile], directory_time: SystemTime) -> io::Result<Vec<Node>> {
+    enum Entry<'a> {
+        Directory(BTreeMap<&'a str, Entry<'a>>),
+        File(&'a SourceFile<'a>),
+    }
+    let mut root = BTreeMap::new();
+    for file in files {
+        let invalid = |problem: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", file.path, problem));
+        let mut directory = &mut root;
+        let mut components = file.path.split('/').filter(|component| !component.is_empty()).peekable();
+        while let Some(component) = components.next() {
+            if components.peek().is_none() {
+                if directory.insert(component, Entry::File(file)).is_some() {
+                    return Err(invalid("the path is given more than once"));
+                }
+                break;
+            }
+            match directory.entry(component).or_insert_with(|| Entry::Directory(BTreeMap::new())) {
+                Entry::Directory(entries) => directory = entries,
+                Entry::File(_) => return Err(invalid("a parent directory is also a file")),
+            }
+        }
+    }
+
+    fn add_entries(nodes: &mut Vec<Node>, index: usize, entries: &BTreeMap<&str, Entry>, directory_time: SystemTime) {
+        let mut children = vec![];
+        for (&name, entry) in entries {
+            let child = nodes.len();
+            let path = nodes[index].path.join(name);
+            let (modified, length) = match entry {
+                Entry::Directory(_) => (directory_time, 0),
+                Entry::File(file) => (file.modified, file.length),
+            };
+            let is_directory = matches!(entry, Entry::Directory(_));
+            nodes.push(Node {
+                name: name.to_string(),
+                path,
+                modified,
+                accessed: modified,
+                parent: index,
+                children: if is_directory { Some(vec![]) } else { None },
+                unique_id: 0,
+                icb_block: 0,
+                data_block: 0,
+                length,
+            });
+            children.push(child);
+            if let Entry::Directory(entries) = entry {
+                add_entries(nodes, child, entries, directory_time);
+            }
+        }
+        nodes[index].children = Some(children);
+    }
+    let mut nodes = vec![Node {
+        name: String::new(),
+        path: PathBuf::new(),
+        modified: directory_time,
+        accessed: directory_time,
+        parent: 0,
+        children: Some(vec![]),
+        unique_id: 0,
+        icb_block: 0,
+        data_block: 0,
+        length: 0,
+    }];
+    add_entries(&mut nodes, 0, &root, directory_time);
+    for node in &nodes {
+        if DynamicDstring::from_str(&node.name).0.len() > u8::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?}: the file name is too long for UDF", node.path),
+            ));
+        }
+    }
+    Ok(nodes)
+}
+
+/// The order of the files in a VIDEO_TS directory: by title set, and in each the IFO,
+/// then the VOBs, then the BUP, e.g. VIDEO_TS.IFO, VIDEO_TS.VOB, VIDEO_TS.BUP, VTS_01_0.IFO
+fn video_ts_order(name: &str) -> (&str, u8, &str) {
+    let title_set = match name.get(..6) {
+        Some(prefix) if prefix.starts_with("VTS_") => prefix,
+        _ => name.split('.').next().unwrap_or(name),
+    };
+    let rank = match name.rsplit('.').next() {
+        Some("IFO") => 0,
+        Some("VOB") => 1,
+        Some("BUP") => 2,
+        _ => 3,
+    };
+    (title_set, rank, name)
+}
+
+/// The partition blocks of the File Set Descriptor and its Terminating Descriptor
+pub(crate) const FILE_SET_BLOCKS: u32 = 2;
+
+/// Assign unique IDs and blocks: the File Entry of each node in depth-first order, each
+/// followed by the directory's identifiers, then reserved_blocks, and then the data of the
+/// files in the returned order
+fn allocate(nodes: &mut [Node], reserved_blocks: u32) -> Vec<usize> {
+    for (index, node) in nodes.iter_mut().enumerate() {
+        node.unique_id = if index == 0 { 0 } else { FIRST_UNIQUE_ID + index as u64 - 1 };
+    }
+    for index in 0..nodes.len() {
+        if let Some(children) = &nodes[index].children {
+            let length = children.iter().map(|&child| file_identifier_length(&nodes[child].name)).sum::<usize>()
+                + file_identifier_length("");
+            nodes[index].length = length as u64;
+        }
+    }
+    // directories are recorded between the File Entries, so that a directory's
+    // entries are near each other
+    let mut metadata_block = FILE_SET_BLOCKS;
+    for node in nodes.iter_mut() {
+        node.icb_block = metadata_block;
+        metadata_block += 1;
+        if node.children.is_some() {
+            node.data_block = metadata_block;
+            metadata_block += blocks(node.length);
+        }
+    }
+
+    let mut data_order = vec![];
+    for node in nodes.iter() {
+        let Some(children) = &node.children else { continue };
+        let mut files: Vec<usize> = children.iter().copied().filter(|&child| nodes[child].children.is_none()).collect();
+        if node.name.eq_ignore_ascii_case("VIDEO_TS") {
+            files.sort_by(|&a, &b| video_ts_order(&nodes[a].name).cmp(&video_ts_order(&nodes[b].name)));
+        }
+        data_order.extend(files);
+    }
+    let mut data_block = metadata_block + reserved_blocks;
+    for &index in &data_order {
+        let node = &mut nodes[index];
+        if node.length > 0 {
+            node.data_block = data_block;
+            data_block += blocks(node.length);
+        }
+    }
+    data_order
+}
+
+/// The blocks of the partition before the file data
+fn metadata_blocks(nodes: &[Node]) -> u32 {
+    FILE_SET_BLOCKS
+        + nodes
+            .iter()
+            .map(|node| 1 + if node.children.is_some() { blocks(node.length) } else { 0 })
+            .sum::<u32>()
+}
+
+/// A tag whose CRC length, location and checksums are filled in by DescriptorTag::finalize
+pub(crate) fn tag(tag_identifier: u16, revision: UdfRevision) -> DescriptorTag {
+    DescriptorTag {
+        tag_identifier,
+        descriptor_version: revision.descriptor_version(),
+        ..Default::default()
+    }
+}
+
+pub(crate) fn implementation_identifier() -> EntityID {
+    EntityID::new(IMPLEMENTATION_IDENTIFIER, [0; 8])
+}
+
+fn domain_identifier(revision: UdfRevision) -> EntityID {
+    EntityID::new("*OSTA UDF Compliant", revision.identifier_suffix())
+}
+
+/// The long_ad of a File Entry, with its unique ID as UDF 2.3.4.3 requires in FIDs
+pub(crate) fn icb_long_ad(icb_block: u32, partition_reference_number: u16, unique_id: u64) -> LongAd {
+    let mut implementation_use = [0; 6];
+    implementation_use[2..6].copy_from_slice(&(unique_id as u32).to_le_bytes());
+    LongAd {
+        extent_length_and_type: BLOCK_SIZE as u32,
+        extent_location: LbAddr {
+            logical_block_number: icb_block,
+            partition_reference_number,
+        },
+        implementation_use,
+    }
+}
+
+pub(crate) fn write_volume_recognition_sequence(head: &mut [u8], start: u32, revision: UdfRevision) {
+    let identifiers = ["BEA01", revision.nsr_identifier(), "TEA01"];
+    for (sector, identifier) in (start..).zip(identifiers) {
+        let descriptor = sector_mut(head, sector);
+        // structure type 0, then the identifier and structure version 1
+        descriptor[1..6].copy_from_slice(identifier.as_bytes());
+        descriptor[6] = 1;
+    }
+}
+
+/// The Volume Descriptor Sequence at sector start, with a Virtual Partition Map after the
+/// physical partition's if virtual_partition (UDF 2.2.8), for volumes recorded incrementally
+/// with a Virtual Allocation Table
+pub(crate) fn write_volume_descriptor_sequence(
+    head: &mut [u8],
+    start: u32,
+    options: &ImageOptions,
+    partition_length: u32,
+    virtual_partition: bool,
+) {
+    let revision = options.revision;
+    let recording_time = Timestamp::from_system_time(options.recording_time);
+    let seconds = (options.recording_time.duration_since(std::time::UNIX_EPOCH)).map_or(0, |d| d.as_secs());
+
+    let location = start;
+    let pvd = PrimaryVolumeDescriptor {
+        tag: tag(PrimaryVolumeDescriptor::TAG_IDENTIFIER, revision),
+        volume_descriptor_sequence_number: 0,
+        volume_identifier: Dstring::from_str(&options.volume_identifier),
+        volume_sequence_number: 1,
+        maximum_volume_sequence_number: 1,
+        interchange_level: 2,
+        maximum_interchange_level: 3,
+        character_set_list: 1,
+        maximum_character_set_list: 1,
+        // UDF 2.2.2.5: unique, starting with 16 hex digits
+        volume_set_identifier: Dstring::from_str(&format!("{:016X}{}", seconds, options.volume_identifier)),
+        descriptor_character_set: CharSpec::new(),
+        explanatory_character_set: CharSpec::new(),
+        application_identifier: implementation_identifier(),
+        recording_date_and_time: recording_time.clone(),
+        implementation_identifier: implementation_identifier(),
+        ..Default::default()
+    };
+    let descriptor = &mut sector_mut(head, location)[..PrimaryVolumeDescriptor::size()];
+    pvd.write(descriptor);
+    DescriptorTag::finalize(descriptor, location);
+
+    // UDF 2.2.7 Implementation Use Volume Descriptor
+    let location = start + 1;
+    let iuvd = &mut sector_mut(head, location)[..512];
+    tag(4, revision).write(&mut iuvd[0..16]);
+    iuvd[16..20].copy_from_slice(&1u32.to_le_bytes());
+    EntityID::new("*UDF LV Info", revision.identifier_suffix()).write(&mut iuvd[20..52]);
+    CharSpec::new().write(&mut iuvd[52..116]);
+    iuvd[116..244].copy_from_slice(&Dstring::<128>::from_str(&options.volume_identifier).0);
+    implementation_identifier().write(&mut iuvd[352..384]);
+    DescriptorTag::finalize(iuvd, location);
+
+    let location = start + 2;
+    let pd = PartitionDescriptor {
+        tag: tag(PartitionDescriptor::TAG_IDENTIFIER, revision),
+        volume_descriptor_sequence_number: 2,
+        // allocated
+        partition_flags: 1,
+        partition_number: 0,
+        partition_contents: EntityID::new(&format!("+{}", revision.nsr_identifier()), [0; 8]),
+        // write-once for a virtual partition, and otherwise read-only
+        access_type: if virtual_partition { 2 } else { 1 },
+        partition_starting_location: PARTITION_START_SECTOR,
+        partition_length,
+        implementation_identifier: implementation_identifier(),
+        ..Default::default()
+    };
+    let descriptor = &mut sector_mut(head, location)[..PartitionDescriptor::size()];
+    pd.write(descriptor);
+    DescriptorTag::finalize(descriptor, location);
+
+    let location = start + 3;
+    let mut partition_maps = vec![PartitionMap::Type1(Type1PartitionMap {
+        header: GenericPartitionMapHeader {
+            partition_map_type: 1,
+            partition_map_length: 6,
+        },
+        volume_seq_number: 1,
+        partition_number: 0,
+    })];
+    if virtual_partition {
+        let mut partition_type_identifier = [0; 32];
+        EntityID::new(VIRTUAL_PARTITION_IDENTIFIER, revision.identifier_suffix()).write(&mut partition_type_identifier);
+        partition_maps.push(PartitionMap::Type2(Type2PartitionMap {
+            header: GenericPartitionMapHeader {
+                partition_map_type: 2,
+                partition_map_length: 64,
+            },
+            partition_type_identifier,
+            volume_seq_number: 1,
+            partition_number: 0,
+            ..Default::default()
+        }));
+    }
+    let map_table_length: usize = partition_maps.iter().map(|map| map.get_length() as usize).sum();
+    let lvd_length = LogicalVolumeDescriptor::size() + map_table_length;
+    let mut logical_volume_contents_use = [0; 16];
+    // the File Set Descriptor is in the last partition: the virtual one if there is one
+    file_set_long_ad(partition_maps.len() as u16 - 1).write(&mut logical_volume_contents_use);
+    let lvd = LogicalVolumeDescriptor {
+        tag: tag(LogicalVolumeDescriptor::TAG_IDENTIFIER, revision),
+        volume_descriptor_sequence_number: 3,
+        descriptor_character_set: CharSpec::new(),
+        logical_volume_identifier: Dstring::from_str(&options.volume_identifier),
+        logical_block_size: BLOCK_SIZE as u32,
+        domain_identifier: domain_identifier(revision),
+        logical_volume_contents_use,
+        map_table_length: map_table_length as u32,
+        number_of_partition_maps: partition_maps.len() as u32,
+        implementation_identifier: implementation_identifier(),
+        integrity_sequence_extent: ExtentAd {
+            length_bytes: 2 * BLOCK_SIZE as u32,
+            location_sector: INTEGRITY_SECTOR,
+        },
+        ..Default::default()
+    };
+    let descriptor = &mut sector_mut(head, location)[..lvd_length];
+    lvd.write(descriptor);
+    let mut offset = LogicalVolumeDescriptor::size();
+    for partition_map in &partition_maps {
+        partition_map.write(&mut descriptor[offset..]).expect("the maps fit");
+        offset += partition_map.get_length() as usize;
+    }
+    DescriptorTag::finalize(descriptor, location);
+
+    // ECMA-167 3/10.8 Unallocated Space Descriptor with no extents
+    let location = start + 4;
+    let usd = &mut sector_mut(head, location)[..24];
+    tag(7, revision).write(&mut usd[0..16]);
+    usd[16..20].copy_from_slice(&4u32.to_le_bytes());
+    DescriptorTag::finalize(usd, location);
+
+    write_terminating_descriptor(sector_mut(head, start + 5), start + 5, revision);
+}
+
+pub(crate) fn write_terminating_descriptor(block: &mut [u8], location: u32, revision: UdfRevision) {
+    let td = TerminatingDescriptor {
+        tag: tag(TerminatingDescriptor::TAG_IDENTIFIER, revision),
+        ..Default::default()
+    };
+    let descriptor = &mut block[..TerminatingDescriptor::size()];
+    td.write(descriptor);
+    DescriptorTag::finalize(descriptor, location);
+}
+
+/// A closed Logical Volume Integrity Descriptor, since the image is complete
+pub(crate) fn write_integrity_sequence(
+    head: &mut [u8],
+    options: &ImageOptions,
+    next_unique_id: u64,
+    summary: &ImageSummary,
+    partition_length: u32,
+) {
+    let revision = options.revision;
+    let mut lvid = LogicalVolumeIntegrityDescriptor {
+        recording_date_and_time: Timestamp::from_system_time(options.recording_time),
+        integrity_type: LogicalVolumeIntegrityDescriptor::INTEGRITY_CLOSE,
+        next_unique_id,
+        free_space_table: vec![0],
+        size_table: vec![partition_length],
+        implementation_identifier: implementation_identifier(),
+        number_of_files: summary.files,
+        number_of_directories: summary.directories,
+        minimum_udf_read_revision: revision.bcd(),
+        minimum_udf_write_revision: revision.bcd(),
+        maximum_udf_write_revision: revision.bcd(),
+        ..Default::default()
+    };
+    lvid.tag = tag(LogicalVolumeIntegrityDescriptor::TAG_IDENTIFIER, revision);
+    let descriptor = &mut sector_mut(head, INTEGRITY_SECTOR)[..lvid.size()];
+    lvid.write(descriptor);
+    DescriptorTag::finalize(descriptor, INTEGRITY_SECTOR);
+    write_terminating_descriptor(sector_mut(head, INTEGRITY_SECTOR + 1), INTEGRITY_SECTOR + 1, revision);
+}
+
+pub(crate) fn write_anchor(block: &mut [u8], location: u32, revision: UdfRevision) {
+    let avdp = AnchorVolumeDescriptorPointer {
+        tag: tag(0x0002, revision),
+        main_volume_descriptor_sequence_location: ExtentAd {
+            length_bytes: VDS_SECTORS * BLOCK_SIZE as u32,
+            location_sector: MAIN_VDS_SECTOR,
+        },
+        reserve_volume_descriptor_sequence_location: ExtentAd {
+            length_bytes: VDS_SECTORS * BLOCK_SIZE as u32,
+            location_sector: RESERVE_VDS_SECTOR,
+        },
+        ..Default::default()
+    };
+    let descriptor = &mut block[..AnchorVolumeDescriptorPointer::size()];
+    avdp.write(descriptor);
+    DescriptorTag::finalize(descriptor, location);
+}
+
+/// The File Set Descriptor is the first block of the partition
+fn file_set_long_ad(partition_reference_number: u16) -> LongAd {
+    LongAd {
+        extent_length_and_type: BLOCK_SIZE as u32,
+        extent_location: LbAddr {
+            logical_block_number: 0,
+            partition_reference_number,
+        },
+        ..Default::default()
+    }
+}
+
+/// The partition block of a node's File Entry or data, as a sector of the head
+fn partition_block_mut(head: &mut [u8], block: u32) -> &mut [u8] {
+    sector_mut(head, PARTITION_START_SECTOR + block)
+}
+
+/// The File Set Descriptor and its Terminating Descriptor, with the root directory's File
+/// Entry after them, in the partition with the given reference number
+pub(crate) fn write_file_set(head: &mut [u8], options: &ImageOptions, partition_reference_number: u16) {
+    let revision = options.revision;
+    let fsd = FileSetDescriptor {
+        tag: tag(FileSetDescriptor::TAG_IDENTIFIER, revision),
+        recording_date_and_time: Timestamp::from_system_time(options.recording_time),
+        interchange_level: 3,
+        maximum_interchange_level: 3,
+        character_set_list: 1,
+        maximum_character_set_list: 1,
+        logical_volume_identifier_character_set: CharSpec::new(),
+        logical_volume_identifier: Dstring::from_str(&options.volume_identifier),
+        file_set_character_set: CharSpec::new(),
+        file_set_identifier: Dstring::from_str(&options.volume_identifier),
+        root_directory_icb: LongAd {
+            extent_length_and_type: BLOCK_SIZE as u32,
+            extent_location: LbAddr {
+                logical_block_number: FILE_SET_BLOCKS,
+                partition_reference_number,
+            },
+            ..Default::default()
+        },
+        domain_identifier: domain_identifier(revision),
+        ..Default::default()
+    };
+    let descriptor = &mut partition_block_mut(head, 0)[..FileSetDescriptor::size()];
+    fsd.write(descriptor);
+    DescriptorTag::finalize(descriptor, 0);
+    write_terminating_descriptor(partition_block_mut(head, 1), 1, revision);
+}
+
+fn write_file_entry(head: &mut [u8], options: &ImageOptions, nodes: &[Node], index: usize) {
+    let node = &nodes[index];
+    let mut allocation_descriptors = vec![];
+    let mut offset = 0;
+    while offset < node.length {
+        let extent_length = (node.length - offset).min(MAX_EXTENT_LENGTH);
+        let mut ad = [0; 8];
+        ShortAllocationDescriptor {
+            extent_length_and_type: extent_length as u32,
+            extent_location: node.data_block + blocks(offset),
+        }
+        .write(&mut ad);
+        allocation_descriptors.extend_from_slice(&ad);
+        offset += extent_length;
+    }
+    let subdirectories = node.children.iter().flatten().filter(|&&child| nodes[child].children.is_some()).count();
+    let mut file_entry = new_file_entry(options.revision, node.children.is_some(), node.length, node.unique_id);
+    file_entry.file_link_count += subdirectories as u16;
+    file_entry.access_time = Timestamp::from_system_time(node.accessed);
+    file_entry.modification_time = Timestamp::from_system_time(node.modified);
+    file_entry.attribute_time = Timestamp::from_system_time(node.modified);
+    file_entry.length_of_allocation_descriptors = allocation_descriptors.len() as u32;
+    file_entry.allocation_descriptors = allocation_descriptors;
+    let descriptor = &mut partition_block_mut(head, node.icb_block)[..file_entry.get_length()];
+    file_entry.write(descriptor);
+    DescriptorTag::finalize(descriptor, node.icb_block);
+}
+
+/// A File Entry of a single direct entry with short allocation descriptors, for the caller
+/// to fill in the times and allocation descriptors of, and the link count of a directory
+/// with subdirectories
+pub(crate) fn new_file_entry(revision: UdfRevision, is_directory: bool, length: u64, unique_id: u64) -> FileEntry {
+    let (file_type, permissions) = match is_directory {
+        true => (FileType::Directory, DIRECTORY_PERMISSIONS),
+        false => (FileType::SequenceOfBytes, FILE_PERMISSIONS),
+    };
+    let mut file_entry = FileEntry {
+        icb_tag: ICBTag {
+            // ECMA-167 4/14.6.2: a single direct entry
+            strategy_type: 4,
+            maximum_number_of_entries: 1,
+            file_type: file_type as u8,
+            flags: AllocationDescriptorType::SHORT as u16,
+            ..Default::default()
+        },
+        uid: u32::MAX,
+        gid: u32::MAX,
+        permissions,
+        file_link_count: 1,
+        information_length: length,
+        logical_blocks_recorded: blocks(length) as u64,
+        checkpoint: 1,
+        implementation_identifier: implementation_identifier(),
+        unique_id,
+        ..Default::default()
+    };
+    file_entry.tag = tag(FileEntry::TAG_IDENTIFIER, revision);
+    file_entry
+}
+
+/// The length of a File Identifier Descriptor, which is padded to 4 bytes
+fn file_identifier_length(name: &str) -> usize {
+    let identifier_length = if name.is_empty() { 0 } else { DynamicDstring::from_str(name).0.len() };
+    (38 + identifier_length + 3) & !3
+}
+
+/// The File Identifier Descriptors of the parent directory and of each entry
+fn write_directory(head: &mut [u8], revision: UdfRevision, nodes: &[Node], index: usize) {
+    let node = &nodes[index];
+    let icb = |node: &Node| icb_long_ad(node.icb_block, 0, node.unique_id);
+    let parent = (FileIdentifierDescriptor::FILE_CHARACTERISTIC_DIRECTORY
+        | FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT, "", icb(&nodes[node.parent]));
+    let entries = node.children.iter().flatten().map(|&child| {
+        let characteristics = match nodes[child].children {
+            Some(_) => FileIdentifierDescriptor::FILE_CHARACTERISTIC_DIRECTORY,
+            None => 0,
+        };
+        (characteristics, nodes[child].name.as_str(), icb(&nodes[child]))
+    });
+    let data = directory_data(revision, std::iter::once(parent).chain(entries), node.data_block);
+    debug_assert_eq!(data.len() as u64, node.length);
+    let start = (PARTITION_START_SECTOR + node.data_block) as usize * BLOCK_SIZE;
+    head[start..start + data.len()].copy_from_slice(&data);
+}
+
+/// The File Identifier Descriptors of a directory recorded from logical block first_block,
+/// from the characteristics, name and ICB of each entry, the parent directory first
+pub(crate) fn directory_data<'a>(
+    revision: UdfRevision,
+    entries: impl Iterator<Item = (u8, &'a str, LongAd)>,
+    first_block: u32,
+) -> Vec<u8> {
+    let mut data = vec![];
+    for (file_characteristics, name, icb) in entries {
+        let file_identifier = if name.is_empty() { DynamicDstring::default() } else { DynamicDstring::from_str(name) };
+        let start = data.len();
+        let length = file_identifier_length(name);
+        let fid = FileIdentifierDescriptor {
+            tag: tag(FileIdentifierDescriptor::TAG_IDENTIFIER, revision),
+            file_version_number: 1,
+            file_characteristics,
+            length_of_file_identifier: file_identifier.0.len() as u8,
+            icb,
+            file_identifier,
+            ..Default::default()
+        };
+        data.resize(start + length, 0);
+        fid.write(&mut data[start..]);
+        DescriptorTag::finalize(&mut data[start..], first_block + (start / BLOCK_SIZE) as u32);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        cache::Cache,
+        udf_parser::{
+            read_directory_contents, read_file_contents, read_file_entries, read_short_allocation_descriptors, UdfParser,
+        },
+    };
+
+    #[test]
+    fn test_video_ts_order() {
+        let mut names = vec![
+            "VTS_01_1.VOB",
+            "VIDEO_TS.BUP",
+            "VTS_01_0.BUP",
+            "VIDEO_TS.VOB",
+            "VTS_01_0.IFO",
+            "VIDEO_TS.IFO",
+            "VTS_01_0.VOB",
+        ];
+        names.sort_by_key(|name| video_ts_order(name));
+        assert_eq!(
+            names,
+            [
+                "VIDEO_TS.IFO",
+                "VIDEO_TS.VOB",
+                "VIDEO_TS.BUP",
+                "VTS_01_0.IFO",
+                "VTS_01_0.VOB",
+                "VTS_01_1.VOB",
+                "VTS_01_0.BUP"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_image() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-makeiso-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("VIDEO_TS")).unwrap();
+        fs::create_dir_all(dir.join("AUDIO_TS")).unwrap();
+        fs::write(dir.join("VIDEO_TS/VIDEO_TS.IFO"), b"DVDVIDEO-VMG".repeat(300)).unwrap();
+        fs::write(dir.join("VIDEO_TS/VIDEO_TS.BUP"), b"DVDVIDEO-VMG").unwrap();
+        fs::write(dir.join("README.TXT"), b"").unwrap();
+
+        for (revision, iso9660) in [(UdfRevision::Udf102, false), (UdfRevision::Udf201, false), (UdfRevision::Udf102, true)] {
+            let options = ImageOptions {
+                volume_identifier: "FUNFANCY".to_string(),
+                revision,
+                recording_time: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_050_085_500),
+                iso9660,
+            };
+            let mut image = Cursor::new(vec![]);
+            let summary = write_image(&dir, &options, &mut image).unwrap();
+            assert_eq!(summary.files, 3);
+            assert_eq!(summary.directories, 3);
+            assert_eq!(image.get_ref().len(), summary.sectors as usize * BLOCK_SIZE);
+            let vrs = if iso9660 { 19 } else { 16 };
+            assert_eq!(&image.get_ref()[vrs * BLOCK_SIZE + 1..vrs * BLOCK_SIZE + 6], b"BEA01");
+            assert_eq!(&image.get_ref()[16 * BLOCK_SIZE + 1..16 * BLOCK_SIZE + 6], if iso9660 { b"CD001" } else { b"BEA01" });
+            image.set_position(0);
+            let mut parser = UdfParser::new(image);
+            let structures = parser.read_volume_structures().unwrap();
+            assert_eq!(structures.primary_volume.volume_identifier.to_string(), "FUNFANCY");
+            assert_eq!(structures.primary_volume.recording_date_and_time.iso8601(), "2003-04-11T18:25:00Z");
+            let PartitionMap::Type1(partition_map) = &structures.partition_maps[0] else {
+                panic!("expected a Type 1 partition map");
+            };
+            let partition_descriptor = &structures.partition_descriptors[&0];
+            let lvid = parser
+                .read_logical_volume_integrity(&structures.logical_volume.integrity_sequence_extent)
+                .unwrap()
+                .unwrap();
+            assert_eq!(lvid.integrity_type, LogicalVolumeIntegrityDescriptor::INTEGRITY_CLOSE);
+            assert_eq!((lvid.number_of_files, lvid.number_of_directories), (3, 3));
+            assert_eq!(lvid.minimum_udf_read_revision, revision.bcd());
+            let fsds = parser.read_fileset_descriptors(partition_descriptor, partition_map).unwrap();
+            assert_eq!({ fsds[0].root_directory_icb.extent_location.logical_block_number }, FILE_SET_BLOCKS);
+
+            let mut reader = Cache::new(&mut parser.reader);
+            let logical_volume = &structures.logical_volume;
+            let read_entries = |reader: &mut Cache<&mut Cursor<Vec<u8>>>, icb: &LongAd| {
+                let short_ad = ShortAllocationDescriptor {
+                    extent_length_and_type: icb.extent_length_and_type,
+                    extent_location: icb.extent_location.logical_block_number,
+                };
+                read_file_entries(reader, logical_volume, partition_descriptor, &short_ad).unwrap()
+            };
+            let root = read_entries(&mut reader, &fsds[0].root_directory_icb);
+            assert_eq!(root[0].file_link_count, 3);
+            let root_fids = read_directory_contents(
+                &mut reader,
+                logical_volume,
+                &structures.partition_descriptors,
+                0,
+                &root,
+            )
+            .unwrap();
+            let names: Vec<String> = root_fids.iter().map(|fid| fid.file_identifier.to_string()).collect();
+            assert_eq!(names, ["", "AUDIO_TS", "README.TXT", "VIDEO_TS"]);
+            assert_eq!(root_fids[0].icb, fsds[0].root_directory_icb);
+
+            let video_ts = read_entries(&mut reader, &root_fids[3].icb);
+            let fids = read_directory_contents(
+                &mut reader,
+                logical_volume,
+                &structures.partition_descriptors,
+                0,
+                &video_ts,
+            )
+            .unwrap();
+            let ifo = read_entries(&mut reader, &fids[2].icb);
+            assert_eq!(fids[2].file_identifier.to_string(), "VIDEO_TS.IFO");
+            assert_eq!(
+                read_file_contents(&mut reader, logical_volume, &structures.partition_descriptors, 0, &ifo).unwrap(),
+                b"DVDVIDEO-VMG".repeat(300)
+            );
+            let bup = read_entries(&mut reader, &fids[1].icb);
+            let bup_block = read_short_allocation_descriptors(&bup[0].allocation_descriptors)[0].extent_location;
+            let ifo_block = read_short_allocation_descriptors(&ifo[0].allocation_descriptors)[0].extent_location;
+            assert!(ifo_block < bup_block);
+            let empty = read_entries(&mut reader, &root_fids[2].icb);
+            assert_eq!(empty[0].information_length, 0);
+            assert!(empty[0].allocation_descriptors.is_empty());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_image_of_files() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_050_085_500);
+        let contents = BTreeMap::from([
+            ("VIDEO_TS/VTS_01_1.VOB", b"VOB".repeat(1000)),
+            ("VIDEO_TS/VIDEO_TS.IFO", b"DVDVIDEO-VMG".to_vec()),
+            ("EXTRAS/README.TXT", b"extras".to_vec()),
+        ]);
+        let files: Vec<SourceFile> = contents
+            .iter()
+            .map(|(&path, data)| SourceFile { path, length: data.len() as u64, modified: time })
+            .collect();
+        let options = ImageOptions {
+            volume_identifier: "FUNFANCY".to_string(),
+            revision: UdfRevision::Udf102,
+            recording_time: time,
+            iso9660: false,
+        };
+        let copy_file = |path: &str, writer: &mut dyn Write| {
+            writer.write_all(&contents[path])?;
+            Ok(contents[path].len() as u64)
+        };
+        let mut image = Cursor::new(vec![]);
+        let summary = write_image_of_files(&files, &options, &mut image, copy_file).unwrap();
+        assert_eq!((summary.files, summary.directories), (3, 3));
+
+        // the same image, read from local files
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-image-reader-test-{}", std::process::id()));
+        for (path, data) in &contents {
+            fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+            fs::write(dir.join(path), data).unwrap();
+        }
+        let (mut reader, reader_summary) = ImageReader::of_files(&files, &options, |path| dir.join(path)).unwrap();
+        assert_eq!(reader_summary, summary);
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+        assert!(read == *image.get_ref());
+        let last_sector = (summary.sectors as usize - 2) * BLOCK_SIZE;
+        reader.seek(SeekFrom::Start(last_sector as u64 + 7)).unwrap();
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, image.get_ref()[last_sector + 7..last_sector + 17]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        image.set_position(0);
+        let mut parser = UdfParser::new(image);
+        let structures = parser.read_volume_structures().unwrap();
+        let partition_descriptor = &structures.partition_descriptors[&0];
+        let logical_volume = &structures.logical_volume;
+        let mut reader = Cache::new(&mut parser.reader);
+        let read_entries = |reader: &mut Cache<&mut Cursor<Vec<u8>>>, icb: &LongAd| {
+            let short_ad = ShortAllocationDescriptor {
+                extent_length_and_type: icb.extent_length_and_type,
+                extent_location: icb.extent_location.logical_block_number,
+            };
+            read_file_entries(reader, logical_volume, partition_descriptor, &short_ad).unwrap()
+        };
+        let root_icb = LongAd {
+            extent_length_and_type: BLOCK_SIZE as u32,
+            extent_location: LbAddr { logical_block_number: FILE_SET_BLOCKS, partition_reference_number: 0 },
+            ..Default::default()
+        };
+        let root = read_entries(&mut reader, &root_icb);
+        let root_fids = read_directory_contents(
+            &mut reader,
+            logical_volume,
+            &structures.partition_descriptors,
+            0,
+            &root,
+        )
+        .unwrap();
+        let names: Vec<String> = root_fids.iter().map(|fid| fid.file_identifier.to_string()).collect();
+        assert_eq!(names, ["", "EXTRAS", "VIDEO_TS"]);
+        let video_ts = read_entries(&mut reader, &root_fids[2].icb);
+        let fids = read_directory_contents(
+            &mut reader,
+            logical_volume,
+            &structures.partition_descriptors,
+            0,
+            &video_ts,
+        )
+        .unwrap();
+        assert_eq!(fids[2].file_identifier.to_string(), "VTS_01_1.VOB");
+        let vob = read_entries(&mut reader, &fids[2].icb);
+        assert_eq!(vob[0].modification_time.iso8601(), "2003-04-11T18:25:00Z");
+        assert_eq!(
+            read_file_contents(&mut reader, logical_volume, &structures.partition_descriptors, 0, &vob).unwrap(),
+            b"VOB".repeat(1000)
+        );
+
+        let short = |_: &str, _: &mut dyn Write| Ok(0);
+        let result = write_image_of_files(&files, &options, &mut Cursor::new(vec![]), short);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        let twice = [SourceFile { path: "A", length: 0, modified: time }, SourceFile { path: "/A", length: 0, modified: time }];
+        let result = write_image_of_files(&twice, &options, &mut Cursor::new(vec![]), short);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
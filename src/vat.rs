@@ -0,0 +1,794 @@
+//! `append-image`: adds files to a volume recorded incrementally, as DVD-R and DVD+R discs
+//! written a session at a time (and CD-Rs) are. Such a volume has a Virtual Partition Map
+//! (UDF 2.2.8) whose blocks are found through a Virtual Allocation Table (UDF 2.2.11), a file
+//! whose ICB is the last recorded sector. Nothing already recorded is rewritten: new files and
+//! new copies of the File Entries and identifiers of the directories that change are recorded
+//! after the end, followed by a new VAT that maps their virtual blocks to the new copies, so
+//! that the ICB addresses in the directories above them stay the same.
+//! The File Entries and the File Set Descriptor are recorded in the virtual partition, and the
+//! data of files and directories in the physical partition with long allocation descriptors.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use log::debug;
+
+use crate::{
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    cache::BlockDevice,
+    udf::{
+        AllocationDescriptorType, DescriptorTag, EntityID, FileIdentifierDescriptor, FileType, LbAddr, LongAd, PartitionMap, ShortAllocationDescriptor,
+        Timestamp,
+    },
+    udf_high_level::VolumeStructures,
+    udf_parser::{read_directory_contents, read_file_entries, DirectoryWalk, UdfError, UdfParser},
+    udf_writer::{
+        blocks, directory_data, icb_long_ad, new_file_entry, scan, sector_mut, write_anchor, write_file_set,
+        write_integrity_sequence, write_volume_descriptor_sequence, write_volume_recognition_sequence, ImageOptions,
+        ImageSummary, Node, UdfRevision, ANCHOR_SECTOR, FILE_SET_BLOCKS, FIRST_UNIQUE_ID, MAIN_VDS_SECTOR,
+        MAX_EXTENT_LENGTH, PARTITION_START_SECTOR, RESERVE_VDS_SECTOR,
+        VOLUME_RECOGNITION_SECTOR,
+    },
+};
+
+const BLOCK_SIZE: usize = DVDCSS_BLOCK_SIZE;
+/// The sectors of a single-layer DVD-R or DVD+R
+pub const DVD_R_SECTORS: u32 = 2_295_104;
+/// UDF 2.2.11: the file type of the VAT's File Entry
+pub const VAT_FILE_TYPE: u8 = 248;
+/// UDF 2.2.11: the header before the VAT's entries, without implementation use
+const VAT_HEADER_LENGTH: usize = 152;
+/// UDF 2.2.11: the Previous VAT ICB Location of the first VAT
+const NO_PREVIOUS_VAT: u32 = u32::MAX;
+/// UDF 1.50 2.2.10: the identifier after the entries of a VAT, which has no header
+const VAT_150_IDENTIFIER: &str = "*UDF Virtual Alloc Tbl";
+/// UDF 1.50 2.2.10: the identifier and the Previous VAT ICB Location
+const VAT_150_TRAILER_LENGTH: usize = 36;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AppendSummary {
+    pub files_added: u32,
+    pub files_replaced: u32,
+    pub directories_added: u32,
+    /// the size of the image afterwards
+    pub sectors: u32,
+}
+
+/// A file or directory of the volume, or one to add
+struct Entry {
+    name: String,
+    parent: usize,
+    /// the entries of a directory, or None for a file
+    children: Option<Vec<usize>>,
+    /// the virtual block of the File Entry; None for a new entry until one is allocated
+    virtual_block: Option<u32>,
+    unique_id: u64,
+    /// the source node of a file to record
+    source: Option<usize>,
+    /// whether the File Entry (and a directory's identifiers) must be recorded again
+    changed: bool,
+}
+
+/// What is needed of a volume to append to it
+struct Volume {
+    revision: UdfRevision,
+    partition_start: u32,
+    partition_length: u32,
+    physical_reference: u16,
+    virtual_reference: u16,
+    /// the LVD's Logical Volume Identifier, which the VAT repeats
+    logical_volume_identifier: [u8; 128],
+    /// the block of the physical partition recording each virtual block
+    vat: Vec<u32>,
+    /// the block of the physical partition of the latest VAT's ICB
+    vat_icb: Option<u32>,
+    /// the first block of the physical partition after the recorded ones
+    next_block: u32,
+    /// the root first
+    entries: Vec<Entry>,
+    next_unique_id: u64,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The io::Error of a read that failed, or else an InvalidData one
+fn udf_error(e: UdfError) -> io::Error {
+    match e {
+        UdfError::Io(e) => e,
+        e => invalid_data(e.to_string()),
+    }
+}
+
+/// The entries of the Virtual Allocation Table that is the data of a File Entry of file_type:
+/// the physical block of each virtual block, or u32::MAX for one that is not used. From UDF
+/// 2.00 on (2.2.11) the File Entry has the VAT file type and the entries follow a header; in
+/// UDF 1.50 (2.2.10) the file type is unspecified and the entries are followed by an
+/// identifier and the location of the previous VAT.
+pub fn read_virtual_allocation_table(file_type: u8, data: &[u8]) -> io::Result<Vec<u32>> {
+    let entries = if file_type == VAT_FILE_TYPE {
+        let header_length = match data {
+            [low, high, ..] => u16::from_le_bytes([*low, *high]) as usize,
+            _ => 0,
+        };
+        if header_length < VAT_HEADER_LENGTH || header_length > data.len() {
+            return Err(invalid_data(format!(
+                "the Virtual Allocation Table's header is {} bytes",
+                header_length
+            )));
+        }
+        &data[header_length..]
+    } else {
+        let identifier = data
+            .len()
+            .checked_sub(VAT_150_TRAILER_LENGTH)
+            .map(|start| EntityID::read(&data[start..start + 32]).identifier_string());
+        if file_type != FileType::Unspecified as u8 || identifier.as_deref() != Some(VAT_150_IDENTIFIER) {
+            return Err(invalid_data(format!(
+                "a File Entry of file type {} is not a Virtual Allocation Table",
+                file_type
+            )));
+        }
+        &data[..data.len() - VAT_150_TRAILER_LENGTH]
+    };
+    if entries.len() % 4 != 0 {
+        return Err(invalid_data(format!(
+            "the Virtual Allocation Table's entries are {} bytes",
+            entries.len()
+        )));
+    }
+    Ok(entries
+        .chunks_exact(4)
+        .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+        .collect())
+}
+
+/// Write a new image of an incrementally recorded volume with the files of source, with
+/// room for capacity sectors in all, to be added to later with append_to_vat_image
+pub fn create_vat_image<F: Read + Write + Seek>(
+    image: &mut F,
+    source: &Path,
+    options: &ImageOptions,
+    capacity: u32,
+) -> io::Result<AppendSummary> {
+    // UDF 2.2.11: the VAT as a file with a header is that of UDF 2.00 and later
+    if options.revision != UdfRevision::Udf201 || options.iso9660 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a volume with a Virtual Allocation Table is recorded with UDF 2.01 and without ISO 9660",
+        ));
+    }
+    let nodes = scan(source)?;
+    let partition_length = capacity.saturating_sub(PARTITION_START_SECTOR);
+    let mut head = vec![0u8; (PARTITION_START_SECTOR + FILE_SET_BLOCKS) as usize * BLOCK_SIZE];
+    write_volume_recognition_sequence(&mut head, VOLUME_RECOGNITION_SECTOR, options.revision);
+    for start in [MAIN_VDS_SECTOR, RESERVE_VDS_SECTOR] {
+        write_volume_descriptor_sequence(&mut head, start, options, partition_length, true);
+    }
+    let summary = ImageSummary {
+        sectors: capacity,
+        files: 0,
+        directories: 1,
+    };
+    write_integrity_sequence(&mut head, options, FIRST_UNIQUE_ID, &summary, partition_length);
+    write_anchor(sector_mut(&mut head, ANCHOR_SECTOR), ANCHOR_SECTOR, options.revision);
+    // the File Set Descriptor and its Terminating Descriptor are virtual blocks 0 and 1, and
+    // the root directory's File Entry, which the first append records, virtual block 2
+    write_file_set(&mut head, options, 1);
+    let mut logical_volume_identifier = [0; 128];
+    logical_volume_identifier.copy_from_slice(&head[MAIN_VDS_SECTOR as usize * BLOCK_SIZE + 3 * BLOCK_SIZE..][84..212]);
+    image.seek(SeekFrom::Start(0))?;
+    image.write_all(&head)?;
+
+    let mut volume = Volume {
+        revision: options.revision,
+        partition_start: PARTITION_START_SECTOR,
+        partition_length,
+        physical_reference: 0,
+        virtual_reference: 1,
+        logical_volume_identifier,
+        vat: (0..FILE_SET_BLOCKS).collect(),
+        vat_icb: None,
+        next_block: FILE_SET_BLOCKS,
+        entries: vec![Entry {
+            name: String::new(),
+            parent: 0,
+            children: Some(vec![]),
+            virtual_block: None,
+            unique_id: 0,
+            source: None,
+            changed: true,
+        }],
+        next_unique_id: FIRST_UNIQUE_ID,
+    };
+    volume.append(image, &nodes, options.recording_time)
+}
+
+/// Add the files of source to the image of an incrementally recorded volume, at the same
+/// paths, replacing the files that are already there
+pub fn append_to_vat_image<F: Read + Write + Seek>(
+    image: &mut F,
+    source: &Path,
+    recording_time: SystemTime,
+) -> io::Result<AppendSummary> {
+    let nodes = scan(source)?;
+    let mut volume = Volume::read(image)?;
+    volume.append(image, &nodes, recording_time)
+}
+
+impl Volume {
+    /// Read the volume through a UdfParser, which maps the virtual partition with the VAT
+    fn read<F: Read + Seek>(image: &mut F) -> io::Result<Self> {
+        let total_sectors = (image.seek(SeekFrom::End(0))? / BLOCK_SIZE as u64) as u32;
+        let mut parser = UdfParser::new(image).with_total_sectors(total_sectors);
+        let structures = parser.read_volume_structures().map_err(udf_error)?;
+        let lvd = &structures.logical_volume;
+        // the virtual partition and the Type 1 partition that records it
+        let Some((virtual_reference, partition_number)) =
+            structures.partition_maps.iter().enumerate().find_map(|(reference, map)| match map {
+                PartitionMap::Type2(map) if map.is_virtual() => Some((reference as u16, map.partition_number)),
+                _ => None,
+            })
+        else {
+            return Err(invalid_data(
+                "the volume has no virtual partition, so it was not recorded incrementally".to_string(),
+            ));
+        };
+        let physical_reference = structures
+            .partition_maps
+            .iter()
+            .position(|map| matches!(map, PartitionMap::Type1(map) if map.partition_number == partition_number))
+            .ok_or_else(|| invalid_data(format!("there is no Type 1 Partition Map of partition {}", partition_number)))?
+            as u16;
+        let partition = structures.partition_descriptors[&partition_number].clone();
+        let virtual_partition = &structures.partition_descriptors[&virtual_reference];
+
+        let partition_start = partition.partition_starting_location;
+        let vat_icb = total_sectors.checked_sub(partition_start + 1).ok_or_else(|| {
+            invalid_data(format!(
+                "the image ends before its partition, which starts at sector {}",
+                partition_start
+            ))
+        })?;
+        // UDF 2.2.11: the VAT's ICB is the last recorded sector, which an append records after
+        let mut reader = parser.cache();
+        let icb = ShortAllocationDescriptor {
+            extent_length_and_type: lvd.logical_block_size,
+            extent_location: vat_icb,
+        };
+        let vat_entries = read_file_entries(&mut reader, lvd, &partition, &icb).map_err(udf_error)?;
+        if vat_entries.first().map(|file_entry| file_entry.icb_tag.file_type) != Some(VAT_FILE_TYPE) {
+            return Err(invalid_data(format!(
+                "the last sector, {}, is not the File Entry of a Virtual Allocation Table of UDF 2.00 or later",
+                total_sectors - 1
+            )));
+        }
+        drop(reader);
+        let vat = parser.virtual_partitions[&virtual_reference].vat.clone();
+        debug!("Volume::read: {} VAT entries at block {}", vat.len(), vat_icb);
+
+        let fsds = parser
+            .read_fileset_descriptors_at(virtual_partition, structures.file_set_block(virtual_reference))
+            .map_err(udf_error)?;
+        let Some(fsd) = fsds.first() else {
+            return Err(invalid_data("the virtual partition has no File Set Descriptor".to_string()));
+        };
+        let mut volume = Volume {
+            revision: UdfRevision::from_descriptor_version(lvd.tag.descriptor_version),
+            partition_start,
+            partition_length: partition.partition_length,
+            physical_reference,
+            virtual_reference,
+            logical_volume_identifier: lvd.logical_volume_identifier.0,
+            vat,
+            vat_icb: Some(vat_icb),
+            next_block: vat_icb + 1,
+            entries: vec![],
+            next_unique_id: FIRST_UNIQUE_ID,
+        };
+        let mut walk = DirectoryWalk::new(DirectoryWalk::DEFAULT_MAX_DEPTH);
+        let root = fsd.root_directory_icb.clone();
+        volume.read_entry(&mut parser.cache(), &structures, &mut walk, String::new(), 0, &root, 0)?;
+        Ok(volume)
+    }
+
+    /// Read the entry at path whose File Entry is at icb, and for a directory its entries
+    #[allow(clippy::too_many_arguments)]
+    fn read_entry<B: BlockDevice>(
+        &mut self,
+        reader: &mut B,
+        structures: &VolumeStructures,
+        walk: &mut DirectoryWalk,
+        path: String,
+        parent: usize,
+        icb: &LongAd,
+        depth: usize,
+    ) -> io::Result<()> {
+        let LbAddr {
+            logical_block_number,
+            partition_reference_number,
+        } = icb.extent_location;
+        if partition_reference_number != self.virtual_reference {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "the File Entry of {:?} is not in the virtual partition, so it cannot be recorded again",
+                    path
+                ),
+            ));
+        }
+        let lvd = &structures.logical_volume;
+        let short_ad = ShortAllocationDescriptor {
+            extent_length_and_type: icb.extent_length_and_type,
+            extent_location: logical_block_number,
+        };
+        let file_entries = read_file_entries(
+            reader,
+            lvd,
+            &structures.partition_descriptors[&partition_reference_number],
+            &short_ad,
+        )
+        .map_err(udf_error)?;
+        let Some(file_entry) = file_entries.first() else {
+            return Err(invalid_data(format!("there is no File Entry of {:?}", path)));
+        };
+        let index = self.entries.len();
+        let is_directory = matches!(file_entry.icb_tag.file_type(), FileType::Directory);
+        let name = path.rsplit('/').next().unwrap_or_default().to_string();
+        self.entries.push(Entry {
+            name,
+            parent,
+            children: if is_directory { Some(vec![]) } else { None },
+            virtual_block: Some(logical_block_number),
+            unique_id: file_entry.unique_id,
+            source: None,
+            changed: false,
+        });
+        self.next_unique_id = self.next_unique_id.max(file_entry.unique_id.saturating_add(1));
+        if is_directory {
+            walk.enter(&path, icb, depth).map_err(udf_error)?;
+            let fids = read_directory_contents(
+                reader,
+                lvd,
+                &structures.partition_descriptors,
+                partition_reference_number,
+                &file_entries,
+            )
+            .map_err(udf_error)?;
+            for fid in fids {
+                let skipped = FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT
+                    | FileIdentifierDescriptor::FILE_CHARACTERISTIC_DELETED;
+                if fid.file_characteristics & skipped != 0 {
+                    continue;
+                }
+                let child = self.entries.len();
+                let name = fid.file_identifier.to_string();
+                let child_path = match path.as_str() {
+                    "" => name,
+                    path => format!("{}/{}", path, name),
+                };
+                self.read_entry(reader, structures, walk, child_path, index, &fid.icb, depth + 1)?;
+                self.entries[index].children.as_mut().expect("a directory").push(child);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the source nodes into the entries, marking what must be recorded again
+    fn merge(&mut self, nodes: &[Node]) -> io::Result<AppendSummary> {
+        let mut summary = AppendSummary::default();
+        let mut entry_of_node = vec![0; nodes.len()];
+        for (index, node) in nodes.iter().enumerate().skip(1) {
+            let parent = entry_of_node[node.parent];
+            let existing = (self.entries[parent].children.iter().flatten())
+                .copied()
+                .find(|&child| self.entries[child].name == node.name);
+            let entry = match existing {
+                Some(entry) => {
+                    if self.entries[entry].children.is_some() != node.children.is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "{:?} is a file in one of the image and the source and a directory in the other",
+                                node.path
+                            ),
+                        ));
+                    }
+                    if node.children.is_none() {
+                        // the new File Entry takes the place of the old one, so the directory is unchanged
+                        self.entries[entry].source = Some(index);
+                        self.entries[entry].changed = true;
+                        summary.files_replaced += 1;
+                    }
+                    entry
+                }
+                None => {
+                    let entry = self.entries.len();
+                    self.entries.push(Entry {
+                        name: node.name.clone(),
+                        parent,
+                        children: node.children.as_ref().map(|_| vec![]),
+                        virtual_block: None,
+                        unique_id: self.next_unique_id,
+                        source: if node.children.is_none() { Some(index) } else { None },
+                        changed: true,
+                    });
+                    self.next_unique_id += 1;
+                    self.entries[parent].children.as_mut().expect("a directory").push(entry);
+                    self.entries[parent].changed = true;
+                    match node.children {
+                        Some(_) => summary.directories_added += 1,
+                        None => summary.files_added += 1,
+                    }
+                    entry
+                }
+            };
+            entry_of_node[index] = entry;
+        }
+        Ok(summary)
+    }
+
+    fn append<F: Read + Write + Seek>(
+        &mut self,
+        image: &mut F,
+        nodes: &[Node],
+        recording_time: SystemTime,
+    ) -> io::Result<AppendSummary> {
+        let mut summary = self.merge(nodes)?;
+        for entry in &mut self.entries {
+            if entry.virtual_block.is_none() {
+                entry.virtual_block = Some(self.vat.len() as u32);
+                self.vat.push(u32::MAX);
+            }
+        }
+        debug_assert_eq!(self.entries[0].virtual_block, Some(FILE_SET_BLOCKS));
+
+        // the files' data, then the directories' identifiers and the File Entries, then the VAT
+        let mut next_block = self.next_block;
+        let mut data_blocks = vec![0; self.entries.len()];
+        for (index, entry) in self.entries.iter().enumerate() {
+            if let Some(source) = entry.source {
+                data_blocks[index] = next_block;
+                next_block += blocks(nodes[source].length);
+            }
+        }
+        let metadata_start = next_block;
+        let mut metadata = vec![];
+        let time = Timestamp::from_system_time(recording_time);
+        for (entry, &data_block) in self.entries.iter().zip(&data_blocks) {
+            if !entry.changed {
+                continue;
+            }
+            let (mut file_entry, extent) = match &entry.children {
+                Some(children) => {
+                    let icb = |entry: &Entry| {
+                        icb_long_ad(
+                            entry.virtual_block.expect("allocated"),
+                            self.virtual_reference,
+                            entry.unique_id,
+                        )
+                    };
+                    let parent = (
+                        FileIdentifierDescriptor::FILE_CHARACTERISTIC_DIRECTORY
+                            | FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT,
+                        "",
+                        icb(&self.entries[entry.parent]),
+                    );
+                    let entries = children.iter().map(|&child| {
+                        let child = &self.entries[child];
+                        let characteristics = match child.children {
+                            Some(_) => FileIdentifierDescriptor::FILE_CHARACTERISTIC_DIRECTORY,
+                            None => 0,
+                        };
+                        (characteristics, child.name.as_str(), icb(child))
+                    });
+                    let data = directory_data(self.revision, std::iter::once(parent).chain(entries), next_block);
+                    let extent = (next_block, data.len() as u64);
+                    next_block += blocks(data.len() as u64);
+                    metadata.extend_from_slice(&data);
+                    metadata.resize((next_block - metadata_start) as usize * BLOCK_SIZE, 0);
+                    let subdirectories = children
+                        .iter()
+                        .filter(|&&child| self.entries[child].children.is_some())
+                        .count();
+                    let mut file_entry = new_file_entry(self.revision, true, extent.1, entry.unique_id);
+                    file_entry.file_link_count += subdirectories as u16;
+                    file_entry.modification_time = time.clone();
+                    (file_entry, extent)
+                }
+                None => {
+                    let node = &nodes[entry.source.expect("a changed file has a source")];
+                    let mut file_entry = new_file_entry(self.revision, false, node.length, entry.unique_id);
+                    file_entry.modification_time = Timestamp::from_system_time(node.modified);
+                    (file_entry, (data_block, node.length))
+                }
+            };
+            file_entry.access_time = time.clone();
+            file_entry.attribute_time = time.clone();
+            let mut allocation_descriptors = vec![];
+            let (first_block, length) = extent;
+            let mut offset = 0;
+            while offset < length {
+                let extent_length = (length - offset).min(MAX_EXTENT_LENGTH);
+                let ad = LongAd {
+                    extent_length_and_type: extent_length as u32,
+                    extent_location: LbAddr {
+                        logical_block_number: first_block + blocks(offset),
+                        partition_reference_number: self.physical_reference,
+                    },
+                    ..Default::default()
+                };
+                let mut bytes = [0; LongAd::size()];
+                ad.write(&mut bytes);
+                allocation_descriptors.extend_from_slice(&bytes);
+                offset += extent_length;
+            }
+            file_entry.icb_tag.flags = AllocationDescriptorType::LONG as u16;
+            file_entry.length_of_allocation_descriptors = allocation_descriptors.len() as u32;
+            file_entry.allocation_descriptors = allocation_descriptors;
+
+            // the File Entry is addressed, and so tagged, by its virtual block
+            let virtual_block = entry.virtual_block.expect("allocated");
+            let mut block = vec![0u8; BLOCK_SIZE];
+            file_entry.write(&mut block[..file_entry.get_length()]);
+            DescriptorTag::finalize(&mut block[..file_entry.get_length()], virtual_block);
+            metadata.extend_from_slice(&block);
+            self.vat[virtual_block as usize] = next_block;
+            next_block += 1;
+        }
+
+        // UDF 2.2.11 Virtual Allocation Table, and its File Entry as the last sector
+        let files = self.entries.iter().filter(|entry| entry.children.is_none()).count() as u32;
+        let mut vat = vec![0u8; VAT_HEADER_LENGTH];
+        vat[0..2].copy_from_slice(&(VAT_HEADER_LENGTH as u16).to_le_bytes());
+        vat[4..132].copy_from_slice(&self.logical_volume_identifier);
+        vat[132..136].copy_from_slice(&self.vat_icb.unwrap_or(NO_PREVIOUS_VAT).to_le_bytes());
+        vat[136..140].copy_from_slice(&files.to_le_bytes());
+        vat[140..144].copy_from_slice(&(self.entries.len() as u32 - files).to_le_bytes());
+        for offset in [144, 146, 148] {
+            vat[offset..offset + 2].copy_from_slice(&self.revision.bcd().to_le_bytes());
+        }
+        vat.extend(self.vat.iter().flat_map(|entry| entry.to_le_bytes()));
+        let vat_block = next_block;
+        next_block += blocks(vat.len() as u64);
+        let vat_length = vat.len();
+        vat.resize((next_block - vat_block) as usize * BLOCK_SIZE, 0);
+        metadata.extend_from_slice(&vat);
+        let mut vat_entry = new_file_entry(self.revision, false, vat_length as u64, 0);
+        vat_entry.icb_tag.file_type = VAT_FILE_TYPE;
+        vat_entry.access_time = time.clone();
+        vat_entry.modification_time = time.clone();
+        vat_entry.attribute_time = time;
+        let mut ad = [0; 8];
+        ShortAllocationDescriptor {
+            extent_length_and_type: vat_length as u32,
+            extent_location: vat_block,
+        }
+        .write(&mut ad);
+        vat_entry.length_of_allocation_descriptors = ad.len() as u32;
+        vat_entry.allocation_descriptors = ad.to_vec();
+        let mut block = vec![0u8; BLOCK_SIZE];
+        vat_entry.write(&mut block[..vat_entry.get_length()]);
+        DescriptorTag::finalize(&mut block[..vat_entry.get_length()], next_block);
+        metadata.extend_from_slice(&block);
+        next_block += 1;
+
+        if next_block > self.partition_length {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "{} more sectors are needed but only {} are left on the disc",
+                    next_block - self.next_block,
+                    self.partition_length.saturating_sub(self.next_block)
+                ),
+            ));
+        }
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let Some(source) = entry.source else { continue };
+            let node = &nodes[source];
+            image.seek(SeekFrom::Start(
+                (self.partition_start + data_blocks[index]) as u64 * BLOCK_SIZE as u64,
+            ))?;
+            let copied = io::copy(&mut fs::File::open(&node.path)?.take(node.length), image)?;
+            if copied != node.length {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{:?} is {} bytes instead of {}", node.path, copied, node.length),
+                ));
+            }
+            image.write_all(&vec![
+                0;
+                blocks(node.length) as usize * BLOCK_SIZE - node.length as usize
+            ])?;
+        }
+        image.seek(SeekFrom::Start(
+            (self.partition_start + metadata_start) as u64 * BLOCK_SIZE as u64,
+        ))?;
+        image.write_all(&metadata)?;
+        image.flush()?;
+        debug!("append: recorded blocks {} to {}", self.next_block, next_block);
+        self.next_block = next_block;
+        self.vat_icb = Some(next_block - 1);
+        summary.sectors = self.partition_start + next_block;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::udf_parser::read_file_contents;
+
+    /// The data of the file whose File Entry is at block of the partition with reference
+    fn read_file(image: &mut Cursor<Vec<u8>>, reference: u16, block: u32) -> Vec<u8> {
+        let mut parser = UdfParser::new(image);
+        let structures = parser.read_volume_structures().unwrap();
+        let (lvd, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+        let mut reader = parser.cache();
+        let icb = ShortAllocationDescriptor {
+            extent_length_and_type: lvd.logical_block_size,
+            extent_location: block,
+        };
+        let file_entries = read_file_entries(&mut reader, lvd, &partitions[&reference], &icb).unwrap();
+        let mut data = read_file_contents(&mut reader, lvd, partitions, reference, &file_entries).unwrap();
+        data.truncate(file_entries[0].information_length as usize);
+        data
+    }
+
+    impl Volume {
+        fn entry(&self, path: &str) -> &Entry {
+            let mut index = 0;
+            for name in path.split('/') {
+                let children = self.entries[index].children.as_ref().unwrap();
+                index = *children
+                    .iter()
+                    .find(|&&child| self.entries[child].name == name)
+                    .unwrap();
+            }
+            &self.entries[index]
+        }
+
+        fn contents(&self, image: &mut Cursor<Vec<u8>>, path: &str) -> Vec<u8> {
+            read_file(image, self.virtual_reference, self.entry(path).virtual_block.unwrap())
+        }
+    }
+
+    #[test]
+    fn test_append_to_vat_image() {
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-vat-test-{}", std::process::id()));
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::create_dir_all(first.join("VIDEO_TS")).unwrap();
+        fs::write(first.join("VIDEO_TS/VIDEO_TS.IFO"), b"DVDVIDEO-VMG".repeat(200)).unwrap();
+        fs::write(first.join("README.TXT"), b"first session").unwrap();
+        fs::create_dir_all(second.join("VIDEO_TS")).unwrap();
+        fs::create_dir_all(second.join("EXTRAS")).unwrap();
+        fs::write(second.join("VIDEO_TS/VTS_01_1.VOB"), b"VOB".repeat(1000)).unwrap();
+        fs::write(second.join("EXTRAS/NOTES.TXT"), b"notes").unwrap();
+        fs::write(second.join("README.TXT"), b"second session").unwrap();
+
+        let options = ImageOptions {
+            volume_identifier: "FUNFANCY".to_string(),
+            revision: UdfRevision::Udf201,
+            recording_time: SystemTime::UNIX_EPOCH,
+            iso9660: false,
+        };
+        let mut image = Cursor::new(vec![]);
+        // room for two sessions but not a third
+        let summary = create_vat_image(&mut image, &first, &options, 290).unwrap();
+        assert_eq!((summary.files_added, summary.directories_added), (2, 1));
+        assert_eq!(summary.sectors as usize * BLOCK_SIZE, image.get_ref().len());
+        let first_length = image.get_ref().len();
+        // the files' data, the identifiers and File Entries of the root and VIDEO_TS, the files'
+        // File Entries, and the VAT and its File Entry
+        assert_eq!(
+            summary.sectors,
+            PARTITION_START_SECTOR + FILE_SET_BLOCKS + 3 + 4 + 2 + 2
+        );
+
+        let summary = append_to_vat_image(&mut image, &second, SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(
+            summary,
+            AppendSummary {
+                files_added: 2,
+                files_replaced: 1,
+                directories_added: 1,
+                sectors: (image.get_ref().len() / BLOCK_SIZE) as u32,
+            }
+        );
+        let result = append_to_vat_image(&mut image, &first, SystemTime::UNIX_EPOCH);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::StorageFull);
+        // nothing was written for the append that did not fit
+        assert_eq!(summary.sectors as usize * BLOCK_SIZE, image.get_ref().len());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let volume = Volume::read(&mut image).unwrap();
+        // the File Set Descriptor and its Terminating Descriptor, and the seven File Entries
+        assert_eq!(volume.vat.len(), FILE_SET_BLOCKS as usize + 7);
+        // the first session's VAT is the previous one
+        let vat = read_file(&mut image, 0, volume.vat_icb.unwrap());
+        let previous = u32::from_le_bytes(vat[132..136].try_into().unwrap());
+        assert_eq!(
+            previous as usize,
+            first_length / BLOCK_SIZE - 1 - PARTITION_START_SECTOR as usize
+        );
+        assert_eq!(u32::from_le_bytes(vat[136..140].try_into().unwrap()), 4);
+
+        assert_eq!(volume.contents(&mut image, "README.TXT"), b"second session");
+        assert_eq!(
+            volume.contents(&mut image, "VIDEO_TS/VIDEO_TS.IFO"),
+            b"DVDVIDEO-VMG".repeat(200)
+        );
+        assert_eq!(
+            volume.contents(&mut image, "VIDEO_TS/VTS_01_1.VOB"),
+            b"VOB".repeat(1000)
+        );
+        assert_eq!(volume.contents(&mut image, "EXTRAS/NOTES.TXT"), b"notes");
+        // a replaced file keeps its virtual block and unique ID
+        assert_eq!(volume.entry("README.TXT").unique_id, FIRST_UNIQUE_ID);
+        let names: Vec<&str> = (volume.entries[0].children.as_ref().unwrap())
+            .iter()
+            .map(|&child| volume.entries[child].name.as_str())
+            .collect();
+        assert_eq!(names, ["README.TXT", "VIDEO_TS", "EXTRAS"]);
+
+        // the other commands read the virtual partition through the VAT
+        let mut parser = UdfParser::new(&mut image);
+        let structures = parser.read_volume_structures().unwrap();
+        assert_eq!(parser.virtual_partitions[&1].vat, volume.vat);
+        assert_eq!(structures.file_set_partition_of(0), Some(1));
+        let virtual_partition = &structures.partition_descriptors[&1];
+        let fsds = parser.read_fileset_descriptors_at(virtual_partition, structures.file_set_block(1)).unwrap();
+        let root = &fsds[0].root_directory_icb;
+        assert_eq!({ root.extent_location.partition_reference_number }, 1);
+        let mut reader = parser.cache();
+        let icb = ShortAllocationDescriptor {
+            extent_length_and_type: root.extent_length_and_type,
+            extent_location: root.extent_location.logical_block_number,
+        };
+        let root_entries = read_file_entries(&mut reader, &structures.logical_volume, virtual_partition, &icb).unwrap();
+        let identifiers = read_directory_contents(
+            &mut reader,
+            &structures.logical_volume,
+            &structures.partition_descriptors,
+            1,
+            &root_entries,
+        )
+        .unwrap();
+        let names: Vec<String> = identifiers
+            .iter()
+            .filter(|fid| fid.file_characteristics & FileIdentifierDescriptor::FILE_CHARACTERISTIC_PARENT == 0)
+            .map(|fid| fid.file_identifier.to_string())
+            .collect();
+        assert_eq!(names, ["README.TXT", "VIDEO_TS", "EXTRAS"]);
+    }
+
+    #[test]
+    fn test_read_virtual_allocation_table() {
+        let mut vat_200 = vec![0u8; VAT_HEADER_LENGTH];
+        vat_200[0..2].copy_from_slice(&(VAT_HEADER_LENGTH as u16).to_le_bytes());
+        vat_200.extend_from_slice(&[0, 0, 0, 0, 7, 0, 0, 0]);
+        assert_eq!(read_virtual_allocation_table(VAT_FILE_TYPE, &vat_200).unwrap(), vec![0, 7]);
+        assert!(read_virtual_allocation_table(VAT_FILE_TYPE, &vat_200[..100]).is_err());
+
+        let mut vat_150 = vec![9, 0, 0, 0, 0xff, 0xff, 0xff, 0xff];
+        let mut identifier = [0u8; 32];
+        EntityID::new(VAT_150_IDENTIFIER, [0; 8]).write(&mut identifier);
+        vat_150.extend_from_slice(&identifier);
+        vat_150.extend_from_slice(&NO_PREVIOUS_VAT.to_le_bytes());
+        assert_eq!(
+            read_virtual_allocation_table(FileType::Unspecified as u8, &vat_150).unwrap(),
+            vec![9, u32::MAX]
+        );
+        // a file that is not a VAT
+        assert!(read_virtual_allocation_table(FileType::Unspecified as u8, &vat_150[..20]).is_err());
+        assert!(read_virtual_allocation_table(FileType::SequenceOfBytes as u8, &vat_150).is_err());
+    }
+}
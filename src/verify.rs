@@ -0,0 +1,308 @@
+//! `dvdromcopy verify`: read the disc again and compare each of its files sector by sector
+//! with an earlier copy in a directory. Sectors that the copy has as zeros, as those that could
+//! not be read are written with --on-read-error skip, are counted apart from other differences.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, Write},
+    path::Path,
+};
+
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::{
+    copier::{read_icb_entries, walk_file_sets, write_file_data},
+    dvdcss_sys::DVDCSS_BLOCK_SIZE,
+    manifest::VerifyOutcome,
+    path_filter::PathFilter,
+    udf_high_level::VolumeStructures,
+    udf_parser::{Result, UdfParser},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FileStatus {
+    Match,
+    /// some sectors differ: mismatched_sectors have other data and zero_filled_sectors are
+    /// zeros in the copy. first_difference is the offset of the first byte that differs, and
+    /// ranges are the bytes of the sectors that differ
+    Mismatch {
+        mismatched_sectors: u64,
+        zero_filled_sectors: u64,
+        first_difference: u64,
+        ranges: Vec<ByteRange>,
+    },
+    /// the copy is not as long as the file, so its contents are not compared
+    SizeMismatch { copy_size: u64 },
+    Missing,
+    /// the file could not be read from the disc or the copy
+    ReadError { error: String },
+}
+
+/// The bytes of a file from start up to but not including end
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileCheck {
+    pub path: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub status: FileStatus,
+}
+
+/// Per-file results of verifying a directory against the disc, suitable for serializing as JSON
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscVerifyReport {
+    pub outcome: VerifyOutcome,
+    pub matched: usize,
+    pub mismatched: usize,
+    pub missing: usize,
+    pub read_errors: usize,
+    /// the sectors of all files that have other data or are zeros in the copy
+    pub mismatched_sectors: u64,
+    pub zero_filled_sectors: u64,
+    pub files: Vec<FileCheck>,
+}
+impl DiscVerifyReport {
+    pub fn new(files: Vec<FileCheck>) -> Self {
+        let count = |f: fn(&FileStatus) -> bool| files.iter().filter(|c| f(&c.status)).count();
+        let matched = count(|s| matches!(s, FileStatus::Match));
+        let mismatched = count(|s| matches!(s, FileStatus::Mismatch { .. } | FileStatus::SizeMismatch { .. }));
+        let missing = count(|s| matches!(s, FileStatus::Missing));
+        let read_errors = count(|s| matches!(s, FileStatus::ReadError { .. }));
+        let (mut mismatched_sectors, mut zero_filled_sectors) = (0, 0);
+        for check in &files {
+            if let FileStatus::Mismatch { mismatched_sectors: m, zero_filled_sectors: z, .. } = check.status {
+                mismatched_sectors += m;
+                zero_filled_sectors += z;
+            }
+        }
+        let outcome = if read_errors > 0 {
+            VerifyOutcome::ReadErrors
+        } else if mismatched > 0 || missing > 0 {
+            VerifyOutcome::Mismatches
+        } else {
+            VerifyOutcome::Ok
+        };
+        Self {
+            outcome,
+            matched,
+            mismatched,
+            missing,
+            read_errors,
+            mismatched_sectors,
+            zero_filled_sectors,
+            files,
+        }
+    }
+}
+
+/// Compare every file of the disc that filter includes with the file at its path in dir
+pub fn verify_directory<R: Read + Seek>(
+    parser: &mut UdfParser<R>,
+    structures: &VolumeStructures,
+    dir: &Path,
+    max_depth: usize,
+    filter: &PathFilter,
+) -> Result<DiscVerifyReport> {
+    let (logical_volume, partitions) = (&structures.logical_volume, &structures.partition_descriptors);
+    let mut checks = vec![];
+    walk_file_sets(parser, structures, max_depth, filter, &mut |reader, path, icb_address, _| {
+        // path starts with "/" which would make join() discard dir
+        let path = path.trim_start_matches('/');
+        let (_, file_entries) = read_icb_entries(reader, logical_volume, partitions, icb_address)?;
+        let size = file_entries.first().map_or(0, |file_entry| file_entry.information_length);
+        debug!("verify_directory: comparing {:?}", path);
+        let status = match File::open(dir.join(path)).and_then(|file| Ok((file.metadata()?.len(), file))) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => FileStatus::Missing,
+            Err(e) => FileStatus::ReadError { error: e.to_string() },
+            Ok((copy_size, _)) if copy_size != size => FileStatus::SizeMismatch { copy_size },
+            Ok((_, copy)) => {
+                let mut comparing = ComparingWriter::new(copy);
+                match write_file_data(reader, logical_volume, partitions, path, icb_address, 0, &mut comparing)
+                    .and_then(|()| Ok(comparing.finish()?))
+                {
+                    Ok(status) => status,
+                    Err(e) => {
+                        warn!("Could not compare {}: {}", path, e);
+                        FileStatus::ReadError { error: e.to_string() }
+                    }
+                }
+            }
+        };
+        checks.push(FileCheck {
+            path: path.to_string(),
+            size,
+            status,
+        });
+        Ok(())
+    })?;
+    Ok(DiscVerifyReport::new(checks))
+}
+
+/// Compares what is written to it, sector by sector, with the same bytes of a copy
+struct ComparingWriter<C: Read> {
+    copy: BufReader<C>,
+    /// the offset in the file of disc_sector
+    position: u64,
+    /// the part of a sector written so far, and the same part of the copy
+    disc_sector: Vec<u8>,
+    copy_sector: Vec<u8>,
+    mismatched_sectors: u64,
+    zero_filled_sectors: u64,
+    first_difference: Option<u64>,
+    /// the sectors that differ, with adjacent ones merged
+    ranges: Vec<ByteRange>,
+}
+
+impl<C: Read> ComparingWriter<C> {
+    fn new(copy: C) -> Self {
+        Self {
+            copy: BufReader::with_capacity(1024 * 1024, copy),
+            position: 0,
+            disc_sector: Vec::with_capacity(DVDCSS_BLOCK_SIZE),
+            copy_sector: Vec::with_capacity(DVDCSS_BLOCK_SIZE),
+            mismatched_sectors: 0,
+            zero_filled_sectors: 0,
+            first_difference: None,
+            ranges: vec![],
+        }
+    }
+
+    fn compare_sector(&mut self) -> io::Result<()> {
+        self.copy_sector.resize(self.disc_sector.len(), 0);
+        self.copy.read_exact(&mut self.copy_sector)?;
+        if let Some(i) = self.disc_sector.iter().zip(&self.copy_sector).position(|(disc, copy)| disc != copy) {
+            self.first_difference.get_or_insert(self.position + i as u64);
+            if self.copy_sector.iter().all(|&b| b == 0) {
+                self.zero_filled_sectors += 1;
+            } else {
+                self.mismatched_sectors += 1;
+            }
+            let end = self.position + self.disc_sector.len() as u64;
+            match self.ranges.last_mut() {
+                Some(range) if range.end == self.position => range.end = end,
+                _ => self.ranges.push(ByteRange { start: self.position, end }),
+            }
+        }
+        self.position += self.disc_sector.len() as u64;
+        self.disc_sector.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<FileStatus> {
+        if !self.disc_sector.is_empty() {
+            self.compare_sector()?;
+        }
+        Ok(match self.first_difference {
+            None => FileStatus::Match,
+            Some(first_difference) => FileStatus::Mismatch {
+                mismatched_sectors: self.mismatched_sectors,
+                zero_filled_sectors: self.zero_filled_sectors,
+                first_difference,
+                ranges: self.ranges,
+            },
+        })
+    }
+}
+
+impl<C: Read> Write for ComparingWriter<C> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut rest = data;
+        while !rest.is_empty() {
+            let len = (DVDCSS_BLOCK_SIZE - self.disc_sector.len()).min(rest.len());
+            self.disc_sector.extend_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            if self.disc_sector.len() == DVDCSS_BLOCK_SIZE {
+                self.compare_sector()?;
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{build_image, FixtureFile, Quirks},
+        udf_parser::DirectoryWalk,
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_verify_directory() {
+        let vob: Vec<u8> = (0..5 * DVDCSS_BLOCK_SIZE + 100).map(|i| (i % 251) as u8 + 1).collect();
+        let files = [
+            FixtureFile {
+                path: "VIDEO_TS/VIDEO_TS.IFO",
+                contents: b"DVDVIDEO-VMG",
+            },
+            FixtureFile {
+                path: "VIDEO_TS/VTS_01_1.VOB",
+                contents: &vob,
+            },
+            FixtureFile {
+                path: "VIDEO_TS/VTS_01_0.BUP",
+                contents: b"DVDVIDEO-VTS",
+            },
+            FixtureFile {
+                path: "README.TXT",
+                contents: b"hello",
+            },
+        ];
+        let image = build_image(&files, "VERIFY", &Quirks::default()).unwrap();
+        let dir = std::env::temp_dir().join(format!("dvdromcopy-verify-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("VIDEO_TS")).unwrap();
+        std::fs::write(dir.join("VIDEO_TS/VIDEO_TS.IFO"), b"DVDVIDEO-VMG").unwrap();
+        let mut copy = vob.clone();
+        // a sector written as zeros and bytes that differ in the sector after it and in the
+        // last, partial sector
+        copy[DVDCSS_BLOCK_SIZE..2 * DVDCSS_BLOCK_SIZE].fill(0);
+        copy[2 * DVDCSS_BLOCK_SIZE + 50] ^= 0xff;
+        copy[5 * DVDCSS_BLOCK_SIZE + 50] ^= 0xff;
+        std::fs::write(dir.join("VIDEO_TS/VTS_01_1.VOB"), &copy).unwrap();
+        std::fs::write(dir.join("VIDEO_TS/VTS_01_0.BUP"), b"DVDVIDEO").unwrap();
+
+        let mut parser = UdfParser::new(Cursor::new(image));
+        let structures = parser.read_volume_structures().unwrap();
+        let max_depth = DirectoryWalk::DEFAULT_MAX_DEPTH;
+        let report = verify_directory(&mut parser, &structures, &dir, max_depth, &PathFilter::default()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let status = |path: &str| report.files.iter().find(|check| check.path == path).unwrap().status.clone();
+        assert_eq!(status("VIDEO_TS/VIDEO_TS.IFO"), FileStatus::Match);
+        assert_eq!(
+            status("VIDEO_TS/VTS_01_1.VOB"),
+            FileStatus::Mismatch {
+                mismatched_sectors: 2,
+                zero_filled_sectors: 1,
+                first_difference: DVDCSS_BLOCK_SIZE as u64,
+                ranges: vec![
+                    ByteRange {
+                        start: DVDCSS_BLOCK_SIZE as u64,
+                        end: 3 * DVDCSS_BLOCK_SIZE as u64,
+                    },
+                    ByteRange {
+                        start: 5 * DVDCSS_BLOCK_SIZE as u64,
+                        end: vob.len() as u64,
+                    },
+                ],
+            }
+        );
+        assert_eq!(status("VIDEO_TS/VTS_01_0.BUP"), FileStatus::SizeMismatch { copy_size: 8 });
+        assert_eq!(status("README.TXT"), FileStatus::Missing);
+        assert_eq!((report.matched, report.mismatched, report.missing), (1, 2, 1));
+        assert_eq!((report.mismatched_sectors, report.zero_filled_sectors), (2, 1));
+        assert_eq!(report.outcome, VerifyOutcome::Mismatches);
+    }
+}